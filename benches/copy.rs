@@ -0,0 +1,113 @@
+// Hashing a file through the copy routine split, reconstruct and verify share, which
+// reads the next block while the last one is hashed, against reading and hashing in
+// turn with a buffer of the same size; and a hashed split, which goes through it too.
+// The file is generated, BENCH_SIZE bytes of it, as in `BENCH_SIZE=8G cargo bench`,
+// 2 GiB when that isn't set. The gain is the time hashing and reading overlap, so it
+// shows on more than one core and on disks slower than the hash.
+
+use std::env;
+use std::fs::File;
+use std::hint::black_box;
+use std::io::{self, Read};
+use std::path::Path;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use reconstruct_large_file::manifest::{HashAlgorithm, hash_file};
+use reconstruct_large_file::size::parse_size;
+use reconstruct_large_file::{CancelToken, SplitOptions, pipeline, split_file};
+
+// Bytes that differ from block to block without being held anywhere whole.
+struct Generated {
+    left: u64,
+    state: u64,
+}
+
+impl Read for Generated {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.left as usize);
+        for byte in &mut buf[..len] {
+            self.state = self
+                .state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1);
+            *byte = (self.state >> 56) as u8;
+        }
+        self.left -= len as u64;
+        Ok(len)
+    }
+}
+
+fn bench_size() -> u64 {
+    match env::var("BENCH_SIZE") {
+        Ok(size) => parse_size(&size).expect("BENCH_SIZE is a size, such as 4G"),
+        Err(_) => 2 << 30,
+    }
+}
+
+fn generate(path: &Path, len: u64) {
+    let mut file = File::create(path).unwrap();
+    io::copy(
+        &mut Generated {
+            left: len,
+            state: 1,
+        },
+        &mut file,
+    )
+    .unwrap();
+}
+
+// What the copy routine replaced: read a buffer, hash it, read the next.
+fn hash_in_turn(path: &Path) -> String {
+    let mut hasher = HashAlgorithm::Sha256.hasher();
+    let mut file = File::open(path).unwrap();
+    let mut buffer = vec![0; pipeline::DEFAULT_BUFFER_SIZE];
+    loop {
+        match file.read(&mut buffer).unwrap() {
+            0 => return hasher.finish(),
+            read => hasher.update(&buffer[..read]),
+        }
+    }
+}
+
+fn copy(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    let len = bench_size();
+    generate(&input, len);
+
+    let mut group = c.benchmark_group("hash");
+    group.sample_size(10).throughput(Throughput::Bytes(len));
+    group.bench_function("in turn", |b| b.iter(|| black_box(hash_in_turn(&input))));
+    group.bench_function("overlapped", |b| {
+        b.iter(|| {
+            let hash = hash_file(
+                &input,
+                HashAlgorithm::Sha256,
+                &mut |_| {},
+                &CancelToken::new(),
+            );
+            black_box(hash.unwrap())
+        })
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("split");
+    group.sample_size(10).throughput(Throughput::Bytes(len));
+    group.bench_function("hashed", |b| {
+        b.iter(|| {
+            let chunks = dir.path().join("chunks");
+            let options = SplitOptions::builder(&input, &chunks)
+                .chunk_size(256 << 20)
+                .threads(1)
+                .hash(Some(HashAlgorithm::Sha256))
+                .build()
+                .unwrap();
+            split_file(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+            std::fs::remove_dir_all(&chunks).unwrap();
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, copy);
+criterion_main!(benches);
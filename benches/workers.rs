@@ -0,0 +1,120 @@
+// A compressed split and its reconstruction on one worker and on every core, on a
+// generated file of BENCH_SIZE bytes, 256 MiB when that isn't set, as gzip is slow.
+// Reading stays on one thread either way, so what more workers gain is the compression
+// done side by side; a regression in the pipeline shows as the two coming closer.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::thread;
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use reconstruct_large_file::manifest::Compression;
+use reconstruct_large_file::size::parse_size;
+use reconstruct_large_file::{
+    CancelToken, ReconstructOptions, SplitOptions, reconstruct, split_file,
+};
+
+// Bytes that differ from block to block without being held anywhere whole. Half of
+// every byte is left zero, so gzip has something to do beyond giving up.
+struct Generated {
+    left: u64,
+    state: u64,
+}
+
+impl Read for Generated {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.left as usize);
+        for byte in &mut buf[..len] {
+            self.state = self
+                .state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1);
+            *byte = (self.state >> 60) as u8;
+        }
+        self.left -= len as u64;
+        Ok(len)
+    }
+}
+
+fn bench_size() -> u64 {
+    match env::var("BENCH_SIZE") {
+        Ok(size) => parse_size(&size).expect("BENCH_SIZE is a size, such as 4G"),
+        Err(_) => 256 << 20,
+    }
+}
+
+fn generate(path: &Path, len: u64) {
+    let mut file = File::create(path).unwrap();
+    io::copy(
+        &mut Generated {
+            left: len,
+            state: 1,
+        },
+        &mut file,
+    )
+    .unwrap();
+}
+
+fn split(input: &Path, chunks: &Path, threads: usize) {
+    let options = SplitOptions::builder(input, chunks)
+        .chunk_size(8 << 20)
+        .threads(threads)
+        .compression(Compression::Gzip)
+        .build()
+        .unwrap();
+    split_file(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+}
+
+fn workers(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    let chunks = dir.path().join("chunks");
+    let len = bench_size();
+    generate(&input, len);
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    // The same count twice on a single core is still a comparison of the two runs
+    let counts = [1, cores.max(2)];
+
+    let mut group = c.benchmark_group("compressed split");
+    group.sample_size(10).throughput(Throughput::Bytes(len));
+    for threads in counts {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| {
+                    split(&input, &chunks, threads);
+                    fs::remove_dir_all(&chunks).unwrap();
+                })
+            },
+        );
+    }
+    group.finish();
+
+    split(&input, &chunks, cores);
+    let mut group = c.benchmark_group("compressed reconstruct");
+    group.sample_size(10).throughput(Throughput::Bytes(len));
+    for threads in counts {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| {
+                    let options = ReconstructOptions {
+                        output: Some("joined.bin".to_string()),
+                        threads,
+                        ..ReconstructOptions::new(&chunks)
+                    };
+                    let report = reconstruct(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+                    fs::remove_file(report.output).unwrap();
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, workers);
+criterion_main!(benches);
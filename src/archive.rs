@@ -0,0 +1,632 @@
+// Chunk sets inside an archive, zip or tar, read where they lie: whatever takes a chunk
+// directory takes an archive of one, whichever tool made it. Only the archive's index
+// is read up front, the zip's central directory or the tar's headers, and each entry is
+// streamed from its place in the archive when it is needed, so nothing is extracted and
+// no entry is ever held in memory whole. Zip entries may be stored or deflated, and are
+// checked against their CRC-32 as they are read; tars record no checksum of their own.
+// The set is looked for in the folder that holds `info.json`, or without one the folder
+// with the most chunks, so an archive of the chunk directory itself works as well as
+// one of its contents.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use log::debug;
+
+use crate::armor::ArmorDecoder;
+use crate::cancel::CancelToken;
+use crate::error::{PathContext, Result};
+use crate::event::Counting;
+use crate::gzip::{Crc32, GzDecoder};
+use crate::manifest::{ChunkHasher, Compression, HashAlgorithm, MANIFEST_NAME, Manifest};
+use crate::pipeline::{DEFAULT_BUFFER_SIZE, Io, copy_overlapped};
+use crate::store::{ChunkStore, chunk_name, is_random_name, is_shard_dir, unsharded};
+use crate::zst::ZstDecoder;
+use crate::{chunk_index, tar, zip};
+
+// Whether `path` is to be read as an archive of a chunk set: a file rather than the
+// directory that would hold one. What kind of archive it is, if any, `ArchiveStore::open`
+// finds out.
+pub fn is_archive(path: &Path) -> bool {
+    path.is_file()
+}
+
+// How a member's data is stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Method {
+    Stored,
+    Deflated,
+    Encrypted,
+    // Any other zip compression method, by its number
+    Unsupported(u16),
+}
+
+// One file in an archive, as its index has it.
+#[derive(Clone, Debug)]
+pub(crate) struct Member {
+    // Where its data starts
+    pub offset: u64,
+    // Its length once unpacked, and as it is stored
+    pub size: u64,
+    pub stored_size: u64,
+    pub crc: Option<u32>,
+    pub method: Method,
+}
+
+// A chunk set in a zip or tar archive, read-only. Chunks are found by name as in a
+// directory, or by the manifest's list when they have random names or are named for
+// another tool to join, and decoded as the manifest says they were stored.
+#[derive(Debug)]
+pub struct ArchiveStore {
+    path: PathBuf,
+    // The folder within the archive the set is in, empty for the top
+    root: String,
+    // The members in that folder, by their names within it, and in the archive's order
+    members: BTreeMap<String, Member>,
+    order: Vec<String>,
+    manifest: Option<Manifest>,
+    compression: Compression,
+    overrides: BTreeMap<usize, Compression>,
+    // The chunks present, by index
+    chunks: BTreeMap<usize, String>,
+}
+
+impl ArchiveStore {
+    // The store for the set in the archive at `path`, from its index. Chunks that can't
+    // be read in place, encrypted ones or ones compressed some other way, fail it here
+    // rather than partway through.
+    pub fn open(path: impl Into<PathBuf>, accept_modified: bool) -> Result<ArchiveStore> {
+        let path = path.into();
+        let mut file = File::open(&path).at(&path)?;
+        let mut start = Vec::with_capacity(512);
+        Read::take(&mut file, 512)
+            .read_to_end(&mut start)
+            .at(&path)?;
+        let listed = if start.starts_with(b"PK") {
+            zip::read_members(&mut file).at(&path)?
+        } else if tar::is_tar(&start) {
+            tar::read_members(&mut file).at(&path)?
+        } else if is_compressed(&start) {
+            return Err(invalid(
+                "the archive is compressed as a whole; only plain tar and zip archives can be \
+                 read in place, so decompress it first",
+            ))
+            .at(&path);
+        } else {
+            return Err(invalid("neither a zip nor a tar archive")).at(&path);
+        };
+        debug!("{} holds {} files", path.display(), listed.len());
+
+        let listed: Vec<(String, Member)> = listed
+            .into_iter()
+            .map(|(name, member)| (normalize(&name), member))
+            .collect();
+        let root = find_root(listed.iter().map(|(name, _)| name.as_str()));
+        if !root.is_empty() {
+            debug!("the set is in {} within the archive", root);
+        }
+        let mut store = ArchiveStore {
+            path,
+            root,
+            members: BTreeMap::new(),
+            order: Vec::new(),
+            manifest: None,
+            compression: Compression::None,
+            overrides: BTreeMap::new(),
+            chunks: BTreeMap::new(),
+        };
+        for (name, member) in listed {
+            let Some(name) = store.relative(&name) else {
+                continue;
+            };
+            // A later copy of a file replaces an earlier one, as extracting would
+            if store.members.insert(name.to_string(), member).is_none() {
+                store.order.push(name.to_string());
+            }
+        }
+
+        store.manifest = store.load_manifest(accept_modified)?;
+        if let Some(manifest) = &store.manifest {
+            store.compression = manifest.compression;
+            let listed = listed_by_name(manifest);
+            for (index, entry) in manifest.indexed() {
+                if let Some(overridden) = entry.compression
+                    && overridden != manifest.compression
+                {
+                    store.overrides.insert(index, overridden);
+                }
+                if listed && store.members.contains_key(entry.file()) {
+                    store.chunks.insert(index, entry.file().to_string());
+                }
+            }
+        }
+        let named = store.manifest.as_ref().is_none_or(|m| !listed_by_name(m));
+        if named {
+            // Only a manifest says a subdirectory's chunks are the set's
+            for name in store.order.iter().filter(|name| !name.contains('/')) {
+                if let Some(index) = chunk_index(name).and_then(|index| usize::try_from(index).ok())
+                {
+                    store.chunks.insert(index, name.clone());
+                }
+            }
+        }
+        for name in store.chunks.values() {
+            store.readable(name)?;
+        }
+        Ok(store)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The names of the files in the set's folder, in the archive's order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.order.iter().map(String::as_str)
+    }
+
+    // How chunk `index` is stored once out of the archive.
+    pub fn chunk_compression(&self, index: usize) -> Compression {
+        self.overrides
+            .get(&index)
+            .copied()
+            .unwrap_or(self.compression)
+    }
+
+    // Names that look like random chunk names but aren't any the manifest lists.
+    pub(crate) fn unexpected(&self) -> Vec<String> {
+        let Some(manifest) = self
+            .manifest
+            .as_ref()
+            .filter(|manifest| manifest.random_names)
+        else {
+            return Vec::new();
+        };
+        let mut unexpected: Vec<String> = self
+            .names()
+            .filter(|name| is_random_name(unsharded(name)))
+            .filter(|name| !manifest.chunks.iter().any(|entry| entry.name == *name))
+            .map(str::to_string)
+            .collect();
+        unexpected.sort();
+        unexpected
+    }
+
+    // `name` within the set's folder, None for anything outside it.
+    fn relative<'a>(&self, name: &'a str) -> Option<&'a str> {
+        let name = match self.root.is_empty() {
+            true => name,
+            false => name.strip_prefix(self.root.as_str())?.strip_prefix('/')?,
+        };
+        // A sharded set's chunks are a level down, in its shard subdirectories
+        let within = match name.split_once('/') {
+            Some((directory, file)) => is_shard_dir(directory) && !file.contains('/'),
+            None => true,
+        };
+        (!name.is_empty() && !name.ends_with('/') && within).then_some(name)
+    }
+
+    fn member_path(&self, name: &str) -> PathBuf {
+        self.path.join(&self.root).join(name)
+    }
+
+    // An error unless the member `name` is one this can read.
+    fn readable(&self, name: &str) -> Result<()> {
+        let path = self.member_path(name);
+        match self.members[name].method {
+            Method::Stored | Method::Deflated => Ok(()),
+            Method::Encrypted => Err(invalid(
+                "the archive is encrypted; extract it with its password first",
+            ))
+            .at(&path),
+            Method::Unsupported(method) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "compressed with zip method {}; only stored and deflated entries can be \
+                     read in place, so extract it first",
+                    method
+                ),
+            ))
+            .at(&path),
+        }
+    }
+
+    fn load_manifest(&self, accept_modified: bool) -> Result<Option<Manifest>> {
+        if !self.members.contains_key(MANIFEST_NAME) {
+            return Ok(None);
+        }
+        self.readable(MANIFEST_NAME)?;
+        let path = self.member_path(MANIFEST_NAME);
+        let mut data = String::new();
+        self.open_member(MANIFEST_NAME, Compression::None)?
+            .read_to_string(&mut data)
+            .at(&path)?;
+        Manifest::parse(data.as_bytes(), &path, accept_modified).map(Some)
+    }
+
+    // The member `name` as it would be extracted, with its length, for copying it out
+    // whole rather than reading it as a chunk.
+    pub(crate) fn open_file(&self, name: &str) -> Result<(EntryReader, u64)> {
+        let reader = self.open_member(name, Compression::None)?;
+        self.readable(name)?;
+        Ok((reader, self.members[name].size))
+    }
+
+    fn open_member(&self, name: &str, compression: Compression) -> Result<EntryReader> {
+        let path = self.member_path(name);
+        let Some(member) = self.members.get(name) else {
+            return Err(io::Error::from(io::ErrorKind::NotFound)).at(&path);
+        };
+        EntryReader::open(&self.path, member, compression).at(&path)
+    }
+
+    // Read chunk `index` through, hashing it with `algorithm` if given. False when it
+    // doesn't read back intact: a zip entry whose CRC-32 differs, which catches damage
+    // even in sets without hashes, one cut short, or a compressed chunk that no longer
+    // decodes. `copied` hears about each buffer read.
+    pub(crate) fn check_chunk(
+        &self,
+        index: usize,
+        algorithm: Option<HashAlgorithm>,
+        copied: &mut dyn FnMut(u64),
+        cancel: &CancelToken,
+    ) -> Result<(bool, Option<String>)> {
+        let path = self.chunk_path(index);
+        let mut reader = self.open_chunk(index)?;
+        let mut hasher = algorithm.map(HashAlgorithm::hasher);
+        let mut sink = Counting {
+            inner: io::sink(),
+            copied,
+            cancel,
+        };
+        match copy_overlapped(&Io::default(), &mut reader, &mut sink, hasher.as_mut()) {
+            Ok(_) => Ok((true, hasher.map(ChunkHasher::finish))),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                debug!("{} does not read back: {}", path.display(), e);
+                Ok((false, None))
+            }
+            Err(e) => Err(e).at(&path),
+        }
+    }
+}
+
+impl ChunkStore for ArchiveStore {
+    type Writer = io::Sink;
+    type Reader = EntryReader;
+
+    fn create_chunk(&mut self, index: usize) -> Result<io::Sink> {
+        Err(read_only()).at(&self.chunk_path(index))
+    }
+
+    fn open_chunk(&self, index: usize) -> Result<EntryReader> {
+        match self.chunks.get(&index) {
+            Some(name) => self.open_member(name, self.chunk_compression(index)),
+            None => Err(io::Error::from(io::ErrorKind::NotFound)).at(&self.chunk_path(index)),
+        }
+    }
+
+    // Compressed chunks have to be decoded to tell; callers with a manifest use the
+    // sizes it recorded instead.
+    fn chunk_len(&self, index: usize) -> Result<u64> {
+        let path = self.chunk_path(index);
+        let Some(name) = self.chunks.get(&index) else {
+            return Err(io::Error::from(io::ErrorKind::NotFound)).at(&path);
+        };
+        if self.chunk_compression(index).is_none() {
+            return Ok(self.members[name].size);
+        }
+        let mut reader = self.open_chunk(index)?;
+        io::copy(&mut reader, &mut io::sink()).at(&path)
+    }
+
+    fn remove_chunk(&mut self, index: usize) -> Result<()> {
+        Err(read_only()).at(&self.chunk_path(index))
+    }
+
+    fn list_chunks(&self) -> Result<Vec<usize>> {
+        Ok(self.chunks.keys().copied().collect())
+    }
+
+    fn read_info(&self) -> Result<Option<Manifest>> {
+        Ok(self.manifest.clone())
+    }
+
+    fn write_info(&mut self, _manifest: &Manifest) -> Result<()> {
+        Err(read_only()).at(&self.member_path(MANIFEST_NAME))
+    }
+
+    fn chunk_path(&self, index: usize) -> PathBuf {
+        match self.chunks.get(&index) {
+            Some(name) => self.member_path(name),
+            None => self.member_path(&chunk_name(index, self.chunk_compression(index))),
+        }
+    }
+
+    fn compression(&self) -> Compression {
+        self.compression
+    }
+}
+
+// A member's data, read from inside the archive and decoded: first out of the zip's
+// deflate if it is deflated, then out of the set's own codec if the chunk is
+// compressed. Seeking anywhere but in a plain stored member decodes up to the new
+// position, from the start when it is behind the current one.
+pub struct EntryReader {
+    archive: PathBuf,
+    member: Member,
+    compression: Compression,
+    inner: Inner,
+    position: u64,
+}
+
+enum Inner {
+    Stored(Checked<Bounded>),
+    Decoded(Box<dyn Read + Send>),
+}
+
+impl EntryReader {
+    pub(crate) fn open(
+        archive: &Path,
+        member: &Member,
+        compression: Compression,
+    ) -> io::Result<EntryReader> {
+        let mut file = File::open(archive)?;
+        let start = member.offset;
+        file.seek(SeekFrom::Start(start))?;
+        let data = Bounded {
+            file,
+            start,
+            len: member.stored_size,
+            position: 0,
+        };
+        let capacity = DEFAULT_BUFFER_SIZE.min(1 << 20);
+        let unpacked: Box<dyn Read + Send> = match member.method {
+            Method::Stored if compression.is_none() => {
+                return Ok(EntryReader {
+                    archive: archive.to_path_buf(),
+                    member: member.clone(),
+                    compression,
+                    inner: Inner::Stored(Checked::new(data, member)),
+                    position: 0,
+                });
+            }
+            Method::Stored => Box::new(Checked::new(data, member)),
+            Method::Deflated => {
+                let data = BufReader::with_capacity(capacity, data);
+                Box::new(Checked::new(GzDecoder::raw(data), member))
+            }
+            Method::Encrypted | Method::Unsupported(_) => {
+                return Err(invalid("the entry can't be read in place"));
+            }
+        };
+        let decoded: Box<dyn Read + Send> = match compression {
+            Compression::None => unpacked,
+            Compression::Gzip => {
+                Box::new(GzDecoder::new(BufReader::with_capacity(capacity, unpacked)))
+            }
+            Compression::Armor => Box::new(ArmorDecoder::new(BufReader::with_capacity(
+                capacity, unpacked,
+            ))),
+            Compression::Zstd { .. } => Box::new(ZstDecoder::new(BufReader::with_capacity(
+                capacity, unpacked,
+            ))?),
+        };
+        Ok(EntryReader {
+            archive: archive.to_path_buf(),
+            member: member.clone(),
+            compression,
+            inner: Inner::Decoded(decoded),
+            position: 0,
+        })
+    }
+
+    fn skip(&mut self, len: u64) -> io::Result<u64> {
+        io::copy(&mut Read::take(&mut *self, len), &mut io::sink())
+    }
+}
+
+impl Read for EntryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match &mut self.inner {
+            Inner::Stored(data) => data.read(buf)?,
+            Inner::Decoded(decoder) => decoder.read(buf)?,
+        };
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for EntryReader {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        if let Inner::Stored(data) = &mut self.inner {
+            // Only a read from start to end can be checked against the CRC
+            data.crc = None;
+            self.position = data.seek(to)?;
+            return Ok(self.position);
+        }
+        let target = match to {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+            // The end is only known once it has been decoded
+            SeekFrom::End(delta) => {
+                self.skip(u64::MAX)?;
+                self.position.checked_add_signed(delta)
+            }
+        };
+        let Some(target) = target else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative or overflowing position",
+            ));
+        };
+        if target < self.position {
+            *self = EntryReader::open(&self.archive, &self.member, self.compression)?;
+        }
+        // Past the end, as with a file, reads there return nothing
+        self.skip(target - self.position)?;
+        self.position = target;
+        Ok(target)
+    }
+}
+
+// A member's data as it lies in the archive; nothing past its end.
+struct Bounded {
+    file: File,
+    start: u64,
+    len: u64,
+    position: u64,
+}
+
+impl Read for Bounded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.len.saturating_sub(self.position);
+        let wanted = left.min(buf.len() as u64) as usize;
+        if wanted == 0 {
+            return Ok(0);
+        }
+        let read = self.file.read(&mut buf[..wanted])?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for Bounded {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        let position = match to {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        let Some(position) = position else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative or overflowing position",
+            ));
+        };
+        self.file
+            .seek(SeekFrom::Start(self.start + position.min(self.len)))?;
+        self.position = position;
+        Ok(position)
+    }
+}
+
+// A member's unpacked data, which at its end has to come to the size the archive
+// records and, for a zip, match its CRC-32.
+struct Checked<R> {
+    inner: R,
+    size: u64,
+    read: u64,
+    crc: Option<(Crc32, u32)>,
+}
+
+impl<R: Read> Checked<R> {
+    fn new(inner: R, member: &Member) -> Checked<R> {
+        Checked {
+            inner,
+            size: member.size,
+            read: 0,
+            crc: member.crc.map(|expected| (Crc32::new(), expected)),
+        }
+    }
+}
+
+impl<R: Read> Read for Checked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some((crc, _)) = &mut self.crc {
+            crc.update(&buf[..read]);
+        }
+        self.read += read as u64;
+        if self.read > self.size {
+            return Err(invalid("the entry is longer than the archive says"));
+        }
+        if read == 0 && !buf.is_empty() {
+            if self.read < self.size {
+                return Err(invalid("the entry is cut short"));
+            }
+            if let Some((crc, expected)) = self.crc.take()
+                && crc.finish() != expected
+            {
+                return Err(invalid("CRC mismatch"));
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl<R: Seek> Checked<R> {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        let position = self.inner.seek(to)?;
+        self.read = position.min(self.size);
+        Ok(position)
+    }
+}
+
+// Strip what only says where the archive was made: `./` and a leading `/`.
+fn normalize(name: &str) -> String {
+    let mut name = name.trim_start_matches('/');
+    while let Some(rest) = name.strip_prefix("./") {
+        name = rest.trim_start_matches('/');
+    }
+    name.to_string()
+}
+
+// The folder the set is in: the shallowest one holding an `info.json`, or without one,
+// the one holding the most chunks, the shallowest of those if several do.
+fn find_root<'a>(names: impl Iterator<Item = &'a str>) -> String {
+    let mut manifests = Vec::new();
+    let mut chunks: BTreeMap<&str, usize> = BTreeMap::new();
+    for name in names {
+        let (folder, file) = name.rsplit_once('/').unwrap_or(("", name));
+        if file == MANIFEST_NAME {
+            manifests.push(folder);
+        } else if chunk_index(file).is_some() {
+            *chunks.entry(folder).or_default() += 1;
+        }
+    }
+    let depth = |folder: &str| match folder.is_empty() {
+        true => 0,
+        false => folder.matches('/').count() + 1,
+    };
+    let root = match manifests
+        .into_iter()
+        .min_by_key(|&folder| (depth(folder), folder))
+    {
+        Some(folder) => folder,
+        None => chunks
+            .into_iter()
+            .min_by_key(|&(folder, count)| (usize::MAX - count, depth(folder), folder))
+            .map_or("", |(folder, _)| folder),
+    };
+    root.to_string()
+}
+
+// Magic numbers of gzip, bzip2, xz and zstd, which a tarball is often compressed with.
+fn is_compressed(start: &[u8]) -> bool {
+    [&b"\x1f\x8b"[..], b"BZh", b"\xfd7zXZ\0", b"\x28\xb5\x2f\xfd"]
+        .iter()
+        .any(|magic| start.starts_with(magic))
+}
+
+fn read_only() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "chunks in an archive can only be read",
+    )
+}
+
+pub(crate) fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Whether the chunks have to be found by the names the manifest gives their files, rather
+// than by the numbers in their names: random names, names for other tools, chunks in
+// shard subdirectories, or chunks sharing a file.
+fn listed_by_name(manifest: &Manifest) -> bool {
+    manifest.random_names
+        || manifest.compat.is_some()
+        || manifest.shards.is_some()
+        || manifest.shares_files()
+}
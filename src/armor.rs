@@ -0,0 +1,354 @@
+// Chunks as text, for channels that mangle binary: ticketing systems, email bodies,
+// serial consoles. A chunk is written as base64 in lines of 76 characters between a
+// header line and a footer line:
+//
+//     -----BEGIN CHUNK 4 OF 12-----
+//     …
+//     -----END CHUNK 4 OF 12: 5242880 BYTES, CRC32 1a2b3c4d-----
+//
+// The footer's length and CRC-32 are of the chunk's original bytes, and are checked
+// when it is decoded. Reading back takes whatever copying and pasting tends to do to
+// text: lines may have gained or lost whitespace at either end or had their endings
+// turned into CRLF, and anything before the header or after the footer is ignored.
+
+use std::io::{self, BufRead, Read, Write};
+
+use crate::gzip::Crc32;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+// Bytes per line, which make 76 characters
+const LINE_BYTES: usize = 57;
+// Most read of a line at once, so that one without breaks isn't held whole, and most
+// text gathered before it is written
+const PIECE: u64 = 64 * 1024;
+// Sextets by character, 255 for those that aren't base64
+const VALUES: [u8; 256] = {
+    let mut values = [255; 256];
+    let mut at = 0;
+    while at < 64 {
+        values[ALPHABET[at] as usize] = at as u8;
+        at += 1;
+    }
+    values
+};
+
+// Writes a chunk as armored text into `inner`; `finish` adds the footer.
+pub(crate) struct ArmorEncoder<W: Write> {
+    inner: W,
+    label: String,
+    // Bytes not yet making up a whole line, and lines not yet written
+    pending: Vec<u8>,
+    text: Vec<u8>,
+    crc: Crc32,
+    len: u64,
+}
+
+impl<W: Write> ArmorEncoder<W> {
+    // For chunk `index` of a set of `count`, if that is known.
+    pub fn new(mut inner: W, index: usize, count: Option<usize>) -> io::Result<ArmorEncoder<W>> {
+        let label = match count {
+            Some(count) => format!("CHUNK {} OF {}", index + 1, count),
+            None => format!("CHUNK {}", index + 1),
+        };
+        writeln!(inner, "-----BEGIN {}-----", label)?;
+        Ok(ArmorEncoder {
+            inner,
+            label,
+            pending: Vec::with_capacity(LINE_BYTES),
+            text: Vec::with_capacity(PIECE as usize),
+            crc: Crc32::new(),
+            len: 0,
+        })
+    }
+
+    fn end_line(&mut self) {
+        encode(&self.pending, &mut self.text);
+        self.text.push(b'\n');
+        self.pending.clear();
+    }
+
+    fn write_text(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.text)?;
+        self.text.clear();
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.pending.is_empty() {
+            self.end_line();
+        }
+        self.write_text()?;
+        writeln!(
+            self.inner,
+            "-----END {}: {} BYTES, CRC32 {:08x}-----",
+            self.label,
+            self.len,
+            self.crc.finish()
+        )?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ArmorEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() && self.text.len() < PIECE as usize {
+            let taken = rest.len().min(LINE_BYTES - self.pending.len());
+            self.pending.extend_from_slice(&rest[..taken]);
+            rest = &rest[taken..];
+            if self.pending.len() == LINE_BYTES {
+                self.end_line();
+            }
+        }
+        let taken = buf.len() - rest.len();
+        self.crc.update(&buf[..taken]);
+        self.len += taken as u64;
+        if self.text.len() >= PIECE as usize {
+            self.write_text()?;
+        }
+        Ok(taken)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_text()?;
+        self.inner.flush()
+    }
+}
+
+pub(crate) fn encode(data: &[u8], text: &mut Vec<u8>) {
+    for group in data.chunks(3) {
+        let bits = group.iter().enumerate().fold(0u32, |bits, (at, &b)| {
+            bits | (u32::from(b) << (16 - 8 * at))
+        });
+        for at in 0..4 {
+            text.push(match at <= group.len() {
+                true => ALPHABET[((bits >> (18 - 6 * at)) & 63) as usize],
+                false => b'=',
+            });
+        }
+    }
+}
+
+// The bytes `text` holds as base64, such as `encode` writes, if it is that and nothing
+// else.
+pub(crate) fn decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.as_bytes();
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut data = Vec::with_capacity(text.len() / 4 * 3);
+    for (at, group) in text.chunks(4).enumerate() {
+        let padding = group.iter().rev().take_while(|&&b| b == b'=').count();
+        if padding > 2 || (padding > 0 && at + 1 < text.len() / 4) {
+            return None;
+        }
+        let mut bits = 0u32;
+        for &b in &group[..4 - padding] {
+            let value = VALUES[usize::from(b)];
+            if value == 255 {
+                return None;
+            }
+            bits = bits << 6 | u32::from(value);
+        }
+        bits <<= 6 * padding;
+        data.extend_from_slice(&bits.to_be_bytes()[1..4 - padding]);
+    }
+    Some(data)
+}
+
+enum State {
+    Header,
+    Body,
+    Done,
+    // After an error, which every later read repeats rather than looking like the end
+    Failed,
+}
+
+// Reads an armored chunk back as its original bytes, checking its length and CRC-32
+// against the footer once it gets there.
+pub(crate) struct ArmorDecoder<R: BufRead> {
+    inner: R,
+    state: State,
+    piece: Vec<u8>,
+    // Whether the next piece read starts a line
+    line_start: bool,
+    // Sextets of a group of four not yet complete, and whether padding has ended the data
+    group: [u8; 4],
+    filled: usize,
+    padded: bool,
+    decoded: Vec<u8>,
+    at: usize,
+    crc: Crc32,
+    len: u64,
+}
+
+impl<R: BufRead> ArmorDecoder<R> {
+    pub fn new(inner: R) -> ArmorDecoder<R> {
+        ArmorDecoder {
+            inner,
+            state: State::Header,
+            piece: Vec::new(),
+            line_start: true,
+            group: [0; 4],
+            filled: 0,
+            padded: false,
+            decoded: Vec::new(),
+            at: 0,
+            crc: Crc32::new(),
+            len: 0,
+        }
+    }
+
+    // Read the next line into `piece`, or as much of it as `PIECE`; None at the end of
+    // the input. The flag says whether it starts a line.
+    fn next_piece(&mut self) -> io::Result<Option<bool>> {
+        self.piece.clear();
+        let read = (&mut self.inner)
+            .take(PIECE)
+            .read_until(b'\n', &mut self.piece)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        let starts = self.line_start;
+        self.line_start = self.piece.ends_with(b"\n");
+        Ok(Some(starts))
+    }
+
+    // Decode one piece of the body into `decoded`, or take the footer and check it.
+    fn decode_piece(&mut self, starts_line: bool) -> io::Result<()> {
+        if starts_line && self.piece.trim_ascii_start().starts_with(b"-----END ") {
+            return self.check_footer();
+        }
+        self.decoded.clear();
+        self.at = 0;
+        // Taken out for the loop, which needs the rest of `self`; an error leaves the
+        // decoder failed, so it needn't go back then
+        let piece = std::mem::take(&mut self.piece);
+        for &b in &piece {
+            if b.is_ascii_whitespace() {
+                continue;
+            }
+            if b == b'=' {
+                // Padding completes the group it is in; the data ends there
+                match self.filled {
+                    0 if self.padded => {}
+                    2 | 3 => {
+                        let bytes = self.filled - 1;
+                        self.push_group(bytes);
+                        self.padded = true;
+                    }
+                    _ => return Err(corrupt("misplaced base64 padding")),
+                }
+                continue;
+            }
+            if self.padded {
+                return Err(corrupt("base64 continues after its padding"));
+            }
+            let value = VALUES[usize::from(b)];
+            if value == 255 {
+                return Err(corrupt("a character that isn't base64"));
+            }
+            self.group[self.filled] = value;
+            self.filled += 1;
+            if self.filled == 4 {
+                self.push_group(3);
+            }
+        }
+        self.piece = piece;
+        self.crc.update(&self.decoded);
+        self.len += self.decoded.len() as u64;
+        Ok(())
+    }
+
+    fn push_group(&mut self, bytes: usize) {
+        let bits = self.group[..self.filled]
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (at, &v)| {
+                bits | (u32::from(v) << (18 - 6 * at))
+            });
+        for at in 0..bytes {
+            self.decoded.push((bits >> (16 - 8 * at)) as u8);
+        }
+        self.filled = 0;
+    }
+
+    fn check_footer(&mut self) -> io::Result<()> {
+        if self.filled != 0 {
+            return Err(corrupt("base64 ends partway through a group"));
+        }
+        let footer = String::from_utf8_lossy(&self.piece).into_owned();
+        let words: Vec<&str> = footer
+            .split(|c: char| c.is_whitespace() || c == ',' || c == ':' || c == '-')
+            .filter(|word| !word.is_empty())
+            .collect();
+        let after = |marker: &str, back: bool| {
+            let at = words
+                .iter()
+                .position(|word| word.eq_ignore_ascii_case(marker))?;
+            match back {
+                true => at.checked_sub(1).map(|at| words[at]),
+                false => words.get(at + 1).copied(),
+            }
+        };
+        let len = after("BYTES", true).and_then(|len| len.parse::<u64>().ok());
+        let crc = after("CRC32", false).and_then(|crc| u32::from_str_radix(crc, 16).ok());
+        let (Some(len), Some(crc)) = (len, crc) else {
+            return Err(corrupt("the armor's footer line is damaged"));
+        };
+        if len != self.len {
+            return Err(corrupt("length mismatch"));
+        }
+        if crc != self.crc.finish() {
+            return Err(corrupt("CRC mismatch"));
+        }
+        self.decoded.clear();
+        self.at = 0;
+        self.state = State::Done;
+        Ok(())
+    }
+
+    fn decode(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.at < self.decoded.len() {
+                let n = buf.len().min(self.decoded.len() - self.at);
+                buf[..n].copy_from_slice(&self.decoded[self.at..self.at + n]);
+                self.at += n;
+                return Ok(n);
+            }
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            match self.state {
+                State::Header => match self.next_piece()? {
+                    Some(true) if self.piece.trim_ascii_start().starts_with(b"-----BEGIN ") => {
+                        self.state = State::Body;
+                    }
+                    Some(_) => {}
+                    None => return Err(corrupt("no armor header line")),
+                },
+                State::Body => match self.next_piece()? {
+                    Some(starts_line) => self.decode_piece(starts_line)?,
+                    None => return Err(corrupt("the armor ends without its footer line")),
+                },
+                State::Done => return Ok(0),
+                State::Failed => return Err(corrupt("stream already failed")),
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for ArmorDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.decode(buf);
+        if result.is_err() {
+            self.state = State::Failed;
+        }
+        result
+    }
+}
+
+fn corrupt(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,247 @@
+// Whether what `verify` found lost can still be rebuilt, from the set's PAR2 files, its
+// parity or other copies of it, worked out the way `repair` and `heal` would go about
+// it but without writing anything.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::error::{Result, SplitterError};
+use crate::heal::{self, same_split};
+use crate::manifest::{MANIFEST_NAME, Manifest};
+use crate::par2::{Par2Damage, Par2Set};
+use crate::parity::{self, is_chunk_intact};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Recoverability {
+    pub recoverable: bool,
+    // Chunks missing or damaged, and the most the parity could rebuild were they spread
+    // over its stripes as evenly as can be
+    pub lost: Vec<String>,
+    pub tolerance: usize,
+    // Lost chunks one of the other copies `verify` was given has intact
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub in_copies: Vec<String>,
+    // Empty without parity
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stripes: Vec<StripeRecoverability>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub par2: Option<Par2Recoverability>,
+}
+
+// One stripe of the parity: its chunks lost, against the parity files it has left.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StripeRecoverability {
+    pub stripe: usize,
+    pub chunks: usize,
+    pub lost: Vec<String>,
+    // Of `lost`, how many another copy has intact
+    pub in_copies: usize,
+    pub parity_files: usize,
+    // Parity files missing or damaged themselves, which `repair` computes again
+    pub parity_lost: Vec<String>,
+    pub recoverable: bool,
+}
+
+impl StripeRecoverability {
+    // Lost chunks only the parity can bring back.
+    pub fn needed(&self) -> usize {
+        self.lost.len() - self.in_copies
+    }
+
+    pub fn parity_intact(&self) -> usize {
+        self.parity_files - self.parity_lost.len()
+    }
+}
+
+// What the `.par2` files of the directory found, and could do about it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Par2Recoverability {
+    pub damaged: Vec<String>,
+    pub lost_slices: usize,
+    pub recovery_slices: usize,
+}
+
+impl Par2Recoverability {
+    pub fn can_repair(&self) -> bool {
+        self.lost_slices <= self.recovery_slices
+    }
+}
+
+impl Recoverability {
+    // One line saying whether the set can be made whole and what to run for it, such as
+    // "recoverable: yes (3 of up to 4 losses), run `repair`".
+    pub fn conclusion(&self) -> String {
+        if !self.recoverable {
+            if let Some(stripe) = self.stripes.iter().find(|stripe| !stripe.recoverable) {
+                return format!(
+                    "NOT recoverable: stripe {} lost {} {} but parity covers {}",
+                    stripe.stripe,
+                    stripe.needed(),
+                    match stripe.needed() {
+                        1 => "chunk",
+                        _ => "chunks",
+                    },
+                    stripe.parity_intact()
+                );
+            }
+            let needed = self.lost.len() - self.in_copies.len();
+            return match &self.par2 {
+                Some(par2) if !par2.damaged.is_empty() => format!(
+                    "NOT recoverable: {} slices were lost but the PAR2 files have {} recovery slices",
+                    par2.lost_slices, par2.recovery_slices
+                ),
+                _ if needed == 1 => {
+                    "NOT recoverable: 1 chunk was lost with no redundancy to rebuild it from"
+                        .to_string()
+                }
+                _ => format!(
+                    "NOT recoverable: {} chunks were lost with no redundancy to rebuild them from",
+                    needed
+                ),
+            };
+        }
+        if let Some(par2) = &self.par2
+            && !par2.damaged.is_empty()
+            && par2.can_repair()
+        {
+            return format!(
+                "recoverable: yes ({} of up to {} lost PAR2 slices), run `repair`",
+                par2.lost_slices, par2.recovery_slices
+            );
+        }
+        let needed = self.lost.len() - self.in_copies.len();
+        let parity_lost = self.stripes.iter().any(|s| !s.parity_lost.is_empty());
+        let detail = match (self.in_copies.len(), needed) {
+            (0, 0) if parity_lost => "only parity files lost".to_string(),
+            (0, 0) => "nothing lost".to_string(),
+            (0, needed) => format!("{} of up to {} losses", needed, self.tolerance),
+            (copies, 0) => format!("{} lost, all intact in other copies", copies),
+            (copies, needed) => format!(
+                "{} from other copies, {} of up to {} losses",
+                copies, needed, self.tolerance
+            ),
+        };
+        let steps = match (self.in_copies.is_empty(), needed > 0 || parity_lost) {
+            (true, _) => "run `repair`",
+            (false, true) => "run `heal` and then `repair`",
+            (false, false) => "run `heal`",
+        };
+        format!("recoverable: yes ({}), {}", detail, steps)
+    }
+}
+
+// Work out whether `directory` can be made whole. `par2` is what its `.par2` files found;
+// they go first, as in `repair`, and when they can rebuild all of that, what they cover
+// counts as intact. Other copies in `copies` are checked for the chunks still lost, as
+// `heal` would, and must be of the same split. The parity then has to cover what is
+// left, stripe by stripe. Without a manifest only the PAR2 files can say. The copies'
+// manifests are refused for having been changed unless `accept_modified`.
+pub(crate) fn assess(
+    directory: &Path,
+    manifest: Option<&Manifest>,
+    par2: Option<(&Par2Set, &Par2Damage)>,
+    copies: &[PathBuf],
+    accept_modified: bool,
+    cancel: &CancelToken,
+) -> Result<Recoverability> {
+    let par2 = par2.map(|(set, damage)| Par2Recoverability {
+        damaged: set.damaged_names(damage),
+        lost_slices: damage.lost_slices(),
+        recovery_slices: set.recovery_slices(),
+    });
+    let par2_fixes: BTreeSet<String> = match &par2 {
+        Some(par2) if par2.can_repair() => par2.damaged.iter().cloned().collect(),
+        _ => BTreeSet::new(),
+    };
+    let mut report = Recoverability {
+        recoverable: true,
+        lost: Vec::new(),
+        tolerance: 0,
+        in_copies: Vec::new(),
+        stripes: Vec::new(),
+        par2,
+    };
+    let Some(manifest) = manifest else {
+        report.recoverable = par2_fixes.contains(MANIFEST_NAME);
+        return Ok(report);
+    };
+    let mut damaged = parity::damaged(directory, manifest, cancel)?;
+    damaged.retain(|&index| {
+        let entry = manifest.indexed().find(|&(i, _)| i == index);
+        entry.is_none_or(|(_, entry)| !par2_fixes.contains(&entry.name))
+    });
+    // One volume of a spanned split answers only for its own chunks
+    if let Some(span) = &manifest.span {
+        damaged.retain(|index| span.here().contains(index));
+    }
+    let names: Vec<(usize, String)> = manifest
+        .indexed()
+        .filter(|(index, _)| damaged.contains(index))
+        .map(|(index, entry)| (index, entry.name.clone()))
+        .collect();
+    report.lost = names.iter().map(|(_, name)| name.clone()).collect();
+
+    // Matched up by index, as `heal` does
+    let mut in_copies = BTreeSet::new();
+    for source in copies {
+        let other = heal::load(source, accept_modified)?;
+        if !same_split(manifest, &other) {
+            return Err(SplitterError::ManifestMismatch {
+                path: source.join(MANIFEST_NAME),
+            });
+        }
+        for (index, entry) in other.indexed() {
+            if damaged.contains(&index)
+                && !in_copies.contains(&index)
+                && is_chunk_intact(source, &other, entry, cancel)?
+            {
+                in_copies.insert(index);
+            }
+        }
+    }
+    report.in_copies = names
+        .iter()
+        .filter(|(index, _)| in_copies.contains(index))
+        .map(|(_, name)| name.clone())
+        .collect();
+
+    let Some(info) = &manifest.parity else {
+        report.recoverable = damaged.len() == in_copies.len();
+        return Ok(report);
+    };
+    let total = manifest.chunks.len();
+    for (stripe, indices) in parity::stripes(info, total, &damaged) {
+        let files = info.stripe_files(stripe);
+        let mut parity_lost = Vec::new();
+        for entry in files {
+            if !parity::is_intact(directory, manifest, entry, &mut |_| {}, cancel)? {
+                parity_lost.push(entry.name.clone());
+            }
+        }
+        let first = stripe * info.stripe_chunks.max(1);
+        let stripe = StripeRecoverability {
+            stripe,
+            chunks: info.stripe_chunks.max(1).min(total - first),
+            lost: names
+                .iter()
+                .filter(|(index, _)| indices.contains(index))
+                .map(|(_, name)| name.clone())
+                .collect(),
+            in_copies: indices.iter().filter(|i| in_copies.contains(i)).count(),
+            parity_files: files.len(),
+            parity_lost,
+            recoverable: true,
+        };
+        let recoverable = stripe.needed() <= stripe.parity_intact();
+        report.tolerance += stripe.parity_intact();
+        report.recoverable &= recoverable;
+        report.stripes.push(StripeRecoverability {
+            recoverable,
+            ..stripe
+        });
+    }
+    Ok(report)
+}
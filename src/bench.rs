@@ -0,0 +1,185 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Instant;
+
+use reconstruct_large_file::manifest::{Compression, HashAlgorithm, hash_file};
+use reconstruct_large_file::{
+    ReconstructOptions, SplitOptions, cache, pipeline, reconstruct, split_file,
+};
+
+use crate::{format_size, free_space, globals, reconstruct_options, split_options};
+
+pub struct BenchOptions {
+    pub directory: PathBuf,
+    pub size: u64,
+    pub chunk_size: u64,
+    pub threads: usize,
+    pub zeros: bool,
+    // Codec and level to split with; the split is then also timed on one thread
+    pub compress: Option<(Compression, u32)>,
+    pub keep: bool,
+}
+
+// Push synthetic data through the real split and reconstruct code inside a scratch
+// directory under `options.directory`, timing each stage. The scratch directory is
+// removed afterwards unless `keep` is set, also when a stage fails.
+pub fn run(options: &BenchOptions) -> io::Result<()> {
+    // The source, its chunks and the reconstruction all exist at the same time
+    let needed = options.size.saturating_mul(3);
+    if let Some(available) = free_space(&options.directory)
+        && available < needed
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::StorageFull,
+            format!(
+                "benchmark needs {} free but only {} is available",
+                format_size(needed),
+                format_size(available)
+            ),
+        ));
+    }
+
+    let scratch = options
+        .directory
+        .join(format!(".file_splitter-bench-{}", process::id()));
+    fs::create_dir(&scratch)?;
+    let result = run_stages(options, &scratch);
+    if options.keep {
+        println!("Benchmark data kept in {}", scratch.display());
+    } else {
+        let _ = fs::remove_dir_all(&scratch);
+    }
+    result
+}
+
+fn run_stages(options: &BenchOptions, scratch: &Path) -> io::Result<()> {
+    let source = scratch.join("source");
+    // Ctrl+C stops the stage at hand; the scratch directory is removed either way
+    let operation = crate::interrupt::start();
+    let cancel = &operation.token;
+    let chunks = scratch.join("chunks");
+
+    let started = Instant::now();
+    let expected = generate(
+        &source,
+        options.size,
+        options.zeros,
+        u64::from(process::id()),
+    )?;
+    report("Generate source", options.size, started);
+
+    // Every chunk is compressed, however well it does, so the codec is what gets timed
+    let builder = split_options(
+        SplitOptions::builder(&source, &chunks),
+        Some(options.threads as u64),
+    )
+    .chunk_size(options.chunk_size);
+    let builder = match options.compress {
+        Some((compression, level)) => builder
+            .compression(compression)
+            .compression_level(level)
+            .min_ratio(0.0),
+        None => builder,
+    };
+    if options.compress.is_some() && options.threads > 1 {
+        let serial = scratch.join("chunks-serial");
+        let split = SplitOptions {
+            destination: serial.clone(),
+            ..builder.clone().threads(1).build()?
+        };
+        let started = Instant::now();
+        split_file(&split, &mut |_| {}, cancel)?;
+        report("Split (1 thread)", options.size, started);
+        fs::remove_dir_all(&serial)?;
+    }
+
+    let started = Instant::now();
+    let split = builder.threads(options.threads).build()?;
+    split_file(&split, &mut |_| {}, cancel)?;
+    match options.compress {
+        Some(_) => report(
+            &match options.threads {
+                1 => "Split (1 thread)".to_string(),
+                threads => format!("Split ({} threads)", threads),
+            },
+            options.size,
+            started,
+        ),
+        None => report("Split (write)", options.size, started),
+    }
+
+    let started = Instant::now();
+    let rebuild = ReconstructOptions {
+        output: Some("reconstructed".to_string()),
+        threads: options.threads,
+        ..reconstruct_options(&chunks, Some(options.threads as u64))
+    };
+    reconstruct(&rebuild, &mut |_| {}, cancel)?;
+    report("Reconstruct (read)", options.size, started);
+
+    let started = Instant::now();
+    let actual = hash_file(
+        &chunks.join("reconstructed"),
+        HashAlgorithm::Sha256,
+        &mut |_| {},
+        cancel,
+    )?;
+    report("Verify (hash)", options.size, started);
+
+    if actual != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "reconstructed data does not match the source",
+        ));
+    }
+    println!("Reconstructed data matches the source.");
+    if globals().direct_io && cache::SUPPORTED {
+        println!("File data was dropped from the cache after each chunk (--direct-io).");
+    } else {
+        println!("Reads may have been served from the file cache; try --direct-io to compare.");
+    }
+    Ok(())
+}
+
+// Write `size` bytes of zeros or pseudo-random data to `path`, returning its SHA-256.
+// The random data only has to defeat compression and deduplication, so a xorshift
+// generator is plenty; the same `seed` gives the same data.
+pub(crate) fn generate(path: &Path, size: u64, zeros: bool, seed: u64) -> io::Result<String> {
+    let block_size = pipeline::DEFAULT_BUFFER_SIZE;
+    let mut output = BufWriter::with_capacity(block_size, File::create(path)?);
+    let mut hasher = HashAlgorithm::Sha256.hasher();
+    let mut block = vec![0u8; block_size];
+    // Never zero, which xorshift would stay at
+    let mut state: u64 = (0x9e37_79b9_7f4a_7c15 ^ seed).max(1);
+    let mut remaining = size;
+    while remaining > 0 {
+        if !zeros {
+            for word in block.chunks_mut(8) {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                word.copy_from_slice(&state.to_le_bytes()[..word.len()]);
+            }
+        }
+        let len = remaining.min(block_size as u64) as usize;
+        output.write_all(&block[..len])?;
+        hasher.update(&block[..len]);
+        remaining -= len as u64;
+    }
+    output.flush()?;
+    output.get_ref().sync_all()?;
+    Ok(hasher.finish())
+}
+
+fn report(stage: &str, bytes: u64, started: Instant) {
+    let elapsed = started.elapsed().as_secs_f64();
+    let rate = bytes as f64 / elapsed.max(1e-6);
+    println!(
+        "{:<20} {:>8.2} s  {:>12}/s",
+        stage,
+        elapsed,
+        format_size(rate as u64)
+    );
+}
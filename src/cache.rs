@@ -0,0 +1,57 @@
+use std::fs::File;
+use std::io::{self, Read};
+
+// `--direct-io`: keep the data we copy from pushing everything else out of the page
+// cache. Rather than O_DIRECT, with its alignment demands on every buffer, offset and
+// length, the kernel is told after each chunk that the cached pages won't be needed;
+// see `pipeline::Io::release`. Where there is no way to tell it, it has no effect.
+pub const SUPPORTED: bool = cfg!(target_os = "linux");
+
+// Drop the cached pages of `len` bytes at `offset` of `file` (0 meaning to the end).
+// Dirty pages aren't dropped, so data we `wrote` is flushed to disk first.
+pub fn release(file: &File, offset: u64, len: u64, wrote: bool) -> io::Result<()> {
+    if wrote {
+        file.sync_data()?;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+        // glibc's off_t is 32 bits on 32-bit targets, as on an armv7 NAS, where only the
+        // 64 variant reaches past 2 GiB; musl's is 64 bits everywhere
+        #[cfg(not(target_env = "gnu"))]
+        use libc::{off_t, posix_fadvise};
+        #[cfg(target_env = "gnu")]
+        use libc::{off64_t as off_t, posix_fadvise64 as posix_fadvise};
+        // SAFETY: advisory call on a descriptor we own; failure changes nothing.
+        unsafe {
+            posix_fadvise(
+                file.as_raw_fd(),
+                offset as off_t,
+                len as off_t,
+                libc::POSIX_FADV_DONTNEED,
+            );
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (offset, len);
+    Ok(())
+}
+
+// Reads `file` from `offset`, with `direct_io` releasing each buffer's worth from the
+// cache as soon as it has been read.
+pub(crate) struct Released {
+    pub file: File,
+    pub offset: u64,
+    pub direct_io: bool,
+}
+
+impl Read for Released {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.file.read(buf)?;
+        if read > 0 && self.direct_io {
+            release(&self.file, self.offset, read as u64, false)?;
+        }
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
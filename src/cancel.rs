@@ -0,0 +1,66 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::{Result, SplitterError};
+
+// Asks a running split, reconstruction or verification to stop. Clones share one flag,
+// so a clone can be handed to the operation while the original sits behind a cancel
+// button. The operation notices between buffers (between chunks for copies done by the
+// kernel or a memory map), cleans up and fails with `SplitterError::Cancelled`.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+    // The token this one was made from with `child`, whose cancellation it shares
+    parent: Option<Box<CancelToken>>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.is_cancelled())
+    }
+
+    // A token that is cancelled along with this one, but can also be cancelled on its
+    // own without this one noticing, to stop an operation for a reason of our own.
+    pub(crate) fn child(&self) -> CancelToken {
+        CancelToken {
+            flag: Arc::default(),
+            parent: Some(Box::new(self.clone())),
+        }
+    }
+
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(SplitterError::Cancelled);
+        }
+        Ok(())
+    }
+
+    // For code that can only fail with an `io::Error`; `PathContext::at` turns this
+    // back into `SplitterError::Cancelled`.
+    pub(crate) fn check_io(&self) -> io::Result<()> {
+        if self.is_cancelled() {
+            return Err(io::Error::other(SplitterError::Cancelled));
+        }
+        Ok(())
+    }
+}
+
+// Share a flag the caller already has, e.g. one set from a signal handler.
+impl From<Arc<AtomicBool>> for CancelToken {
+    fn from(flag: Arc<AtomicBool>) -> CancelToken {
+        CancelToken { flag, parent: None }
+    }
+}
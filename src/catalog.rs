@@ -0,0 +1,296 @@
+// `catalog`: one line of JSON for every chunk set under a directory, for a backup
+// catalog's ingestion job to read. The sets are found as `stats` finds them; one that
+// can't be read as a set is warned about on stderr and left out, so what is on stdout
+// is only ever entries.
+//
+// Every entry has every field, null where there is nothing to say, and `schema`, the
+// version of what they are. A new version only ever adds fields: none is removed,
+// renamed or given another meaning, so a parser written for one version reads every
+// later one as long as it passes over fields it doesn't know.
+//
+// An entry's id is the same for every set of the same file split into the same chunks,
+// wherever it is and whatever it is named: SHA-256 over the file's size and the SHA-256
+// of each chunk in turn. Those are what info.json records, for a set split with
+// hashes; a set without them is read through to hash its chunks, which takes as long
+// as verifying it. A set was created when info.json says, with `--record-times`, or
+// when the journal has the split or rechunk that wrote it; it was modified when its
+// newest file, info.json or a chunk, was written. With `--since`, only sets created or
+// modified at or after it are written.
+
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use reconstruct_large_file::manifest::{
+    Compression, HashAlgorithm, MANIFEST_NAME, hash_compressed,
+};
+use reconstruct_large_file::{CancelToken, ChunkSet, SetStats, SplitterError, stats};
+use serde::Serialize;
+
+use crate::journal::{self, Outcome, Record};
+use crate::logging::{parse_utc_time, utc_time};
+
+// Raised with each field added
+pub const SCHEMA_VERSION: u32 = 1;
+
+// What the catalog says of one set.
+#[derive(Serialize)]
+pub struct Entry {
+    pub schema: u32,
+    // As in sha256:9f86…
+    pub id: String,
+    // Of the file the set reconstructs into
+    pub name: String,
+    pub size: u64,
+    pub chunks: usize,
+    // What the chunk files take on disk
+    pub stored_size: u64,
+    // What info.json hashes the chunks with, if anything
+    pub hash: Option<HashAlgorithm>,
+    pub compression: Compression,
+    // Whether the chunks are gzipped or zstd-compressed; base64 armor is stored
+    // differently but no smaller
+    pub compressed: bool,
+    // This tool doesn't encrypt chunks, so always false; there for catalogs that ask
+    pub encrypted: bool,
+    // In UTC, as in 2024-03-01T12:34:56Z
+    pub created: Option<String>,
+    pub modified: Option<String>,
+    // The set's directory, made absolute
+    pub path: String,
+    // When the latest verify the journal has of the set started, and how it went
+    pub last_verified: Option<String>,
+    pub last_verify_outcome: Option<Outcome>,
+}
+
+// Write an entry for every set under `root` to stdout, a line each, leaving out those
+// neither created nor modified since `since`, in seconds since 1970. Returns how many
+// were written. Sets whose info.json was changed after it was sealed are left out
+// unless `accept_modified`.
+pub fn run(
+    root: &Path,
+    since: Option<u64>,
+    accept_modified: bool,
+    cancel: &CancelToken,
+) -> Result<usize, SplitterError> {
+    let report = stats(root, accept_modified, cancel)?;
+    for broken in &report.broken {
+        eprintln!(
+            "Warning: {} is left out: {}",
+            broken.directory.display(),
+            broken.reason
+        );
+    }
+    // An unreadable journal only leaves out what only it could say
+    let records = journal::read().unwrap_or_default();
+    let verified = journal::last_verified(&records);
+    let written = journal::last_written(&records);
+    let mut count = 0;
+    for set in &report.sets {
+        let path = journal::absolute(&set.directory);
+        let journal = Journal {
+            written: written.get(path.as_str()).copied(),
+            verified: verified.get(path.as_str()).copied(),
+        };
+        match entry(set, path, journal, since, accept_modified, cancel) {
+            Ok(Some(entry)) => {
+                if let Ok(line) = serde_json::to_string(&entry) {
+                    println!("{}", line);
+                    count += 1;
+                }
+            }
+            Ok(None) => {}
+            Err(SplitterError::Cancelled) => return Err(SplitterError::Cancelled),
+            Err(e) => eprintln!("Warning: {} is left out: {}", set.directory.display(), e),
+        }
+    }
+    Ok(count)
+}
+
+// What the journal has of one set.
+struct Journal<'a> {
+    written: Option<&'a Record>,
+    verified: Option<&'a Record>,
+}
+
+// The entry of `set`, whose directory is at `path`; None when it is older than `since`.
+fn entry(
+    set: &SetStats,
+    path: String,
+    journal: Journal,
+    since: Option<u64>,
+    accept_modified: bool,
+    cancel: &CancelToken,
+) -> Result<Option<Entry>, SplitterError> {
+    let directory = &set.directory;
+    let chunks = ChunkSet::open(directory, accept_modified)?;
+    let created = chunks
+        .manifest()
+        .and_then(|manifest| manifest.created)
+        .or_else(|| {
+            journal
+                .written
+                .and_then(|record| parse_utc_time(&record.timestamp))
+        });
+    let mut modified = None;
+    let files = chunks.iter().map(|chunk| chunk.path.clone());
+    for file in files.chain([directory.join(MANIFEST_NAME)]) {
+        let Ok(written) = fs::metadata(&file).and_then(|metadata| metadata.modified()) else {
+            continue;
+        };
+        let seconds = written
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        modified = modified.max(Some(seconds));
+    }
+    // A set with no time to go by can't be told to be older
+    if let Some(since) = since
+        && created.max(modified).is_some_and(|time| time < since)
+    {
+        return Ok(None);
+    }
+    Ok(Some(Entry {
+        schema: SCHEMA_VERSION,
+        id: content_id(&chunks, cancel)?,
+        name: set.original_filename.clone(),
+        size: set.original_size,
+        chunks: set.chunks,
+        stored_size: set.stored_size,
+        hash: set.hash,
+        compression: set.compression,
+        compressed: set.compression.shrinks(),
+        encrypted: false,
+        created: created.map(utc_time),
+        modified: modified.map(utc_time),
+        path,
+        last_verified: journal.verified.map(|record| record.timestamp.clone()),
+        last_verify_outcome: journal.verified.map(|record| record.outcome),
+    }))
+}
+
+// SHA-256 over the size of what `set` holds and the SHA-256 of each of its chunks.
+fn content_id(set: &ChunkSet, cancel: &CancelToken) -> Result<String, SplitterError> {
+    let mut id = HashAlgorithm::Sha256.hasher();
+    id.update(format!("catalog id 1\n{}\n", set.total_size()).as_bytes());
+    for chunk in set.iter() {
+        let hash = match (set.hash_algorithm(), &chunk.hash) {
+            (Some(HashAlgorithm::Sha256), Some(hash)) => hash.to_ascii_lowercase(),
+            _ => hash_compressed(
+                &chunk.path,
+                chunk.compression,
+                HashAlgorithm::Sha256,
+                &mut |_| {},
+                cancel,
+            )?,
+        };
+        id.update(format!("{} {}\n", chunk.len, hash).as_bytes());
+    }
+    Ok(format!("sha256:{}", id.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reconstruct_large_file::{SplitOptions, split_file};
+
+    fn split(input: &Path, destination: &Path, chunk_size: u64, hash: Option<HashAlgorithm>) {
+        let options = SplitOptions::builder(input, destination)
+            .chunk_size(chunk_size)
+            .min_chunk_size(0)
+            .hash(hash)
+            .build()
+            .unwrap();
+        split_file(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+    }
+
+    #[test]
+    fn sets_of_the_same_chunks_have_the_same_id_wherever_they_are() {
+        let temp = tempfile::tempdir().unwrap();
+        let input = temp.path().join("input.bin");
+        let data: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 251) as u8).collect();
+        fs::write(&input, data).unwrap();
+        let root = temp.path().join("root");
+        split(&input, &root.join("a"), 2048, None);
+        split(&input, &root.join("b"), 2048, Some(HashAlgorithm::Sha256));
+        split(&input, &root.join("c"), 1000, Some(HashAlgorithm::Sha256));
+
+        let cancel = CancelToken::new();
+        let report = stats(&root, false, &cancel).unwrap();
+        let entry_since = |set: &SetStats, since| {
+            let path = journal::absolute(&set.directory);
+            let journal = Journal {
+                written: None,
+                verified: None,
+            };
+            entry(set, path, journal, since, false, &cancel).unwrap()
+        };
+        let entries: Vec<Entry> = report
+            .sets
+            .iter()
+            .map(|set| entry_since(set, None).unwrap())
+            .collect();
+        let [a, b, c] = &entries[..] else {
+            panic!("{} sets", entries.len());
+        };
+        // Hashed by the split or only now, the same chunks come to the same id
+        assert!(a.id.starts_with("sha256:") && a.id.len() == 7 + 64);
+        assert_eq!(a.id, b.id);
+        assert_ne!(a.id, c.id);
+        let figures: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.name.as_str(),
+                    entry.size,
+                    entry.chunks,
+                    entry.stored_size,
+                )
+            })
+            .collect();
+        assert_eq!(
+            figures,
+            [
+                ("input.bin", 5000, 3, 5000),
+                ("input.bin", 5000, 3, 5000),
+                ("input.bin", 5000, 5, 5000),
+            ]
+        );
+        assert_eq!((a.hash, c.hash), (None, Some(HashAlgorithm::Sha256)));
+        assert!(!a.compressed && !a.encrypted && a.schema == SCHEMA_VERSION);
+        assert!(a.path.ends_with("a") && a.modified.is_some() && a.last_verified.is_none());
+
+        // Every field is there, null or not
+        let line = serde_json::to_value(a).unwrap();
+        let fields: Vec<&str> = line
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        let mut expected = [
+            "schema",
+            "id",
+            "name",
+            "size",
+            "chunks",
+            "stored_size",
+            "hash",
+            "compression",
+            "compressed",
+            "encrypted",
+            "created",
+            "modified",
+            "path",
+            "last_verified",
+            "last_verify_outcome",
+        ];
+        expected.sort();
+        assert_eq!(fields, expected);
+
+        // Left out when neither created nor modified since
+        let set = &report.sets[0];
+        assert!(entry_since(set, Some(0)).is_some());
+        assert!(entry_since(set, Some(u64::MAX)).is_none());
+    }
+}
@@ -0,0 +1,59 @@
+// What an operation would do to the files it touches, worked out before it touches any,
+// for the dry runs of reconstruct, repair, heal and rechunk. Each of those goes through
+// everything it would read first as it normally would, so a dry run fails where the
+// operation would, and lists the changes only once nothing is left in the way.
+
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Create,
+    // Written over, or replaced by a new file under the same name
+    Modify,
+    // Renamed to `to`
+    Move,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FileChange {
+    pub kind: ChangeKind,
+    pub path: PathBuf,
+    // What the file will take once written, or takes when deleted or moved; None when
+    // that isn't known until it is written, as for a compressed chunk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<PathBuf>,
+}
+
+impl FileChange {
+    // Writing `path`: creating it, or modifying the file already there.
+    pub fn write(path: PathBuf, size: Option<u64>) -> FileChange {
+        let kind = match fs::symlink_metadata(&path) {
+            Ok(_) => ChangeKind::Modify,
+            Err(_) => ChangeKind::Create,
+        };
+        FileChange {
+            kind,
+            path,
+            size,
+            to: None,
+        }
+    }
+
+    pub fn moved(path: PathBuf, to: PathBuf) -> FileChange {
+        let size = fs::symlink_metadata(&path)
+            .ok()
+            .map(|metadata| metadata.len());
+        FileChange {
+            kind: ChangeKind::Move,
+            path,
+            size,
+            to: Some(to),
+        }
+    }
+}
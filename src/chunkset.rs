@@ -0,0 +1,295 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::slice;
+
+use serde::{Deserialize, Serialize};
+
+use crate::archive::{ArchiveStore, is_archive};
+use crate::error::Result;
+use crate::longpath::{absolute_path, parent_dir};
+use crate::manifest::{Compression, HashAlgorithm, MANIFEST_NAME, Manifest};
+use crate::size::format_size;
+use crate::store::{ChunkStore, LocalDirStore, check_numbering, is_shard_dir};
+use crate::{chunk_index, is_set_file};
+
+// One chunk of a set and where its bytes belong in the original file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChunkInfo {
+    pub index: usize,
+    pub path: PathBuf,
+    pub offset: u64,
+    pub len: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    // How the chunk file is stored; `len` is always what it holds once decoded
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    pub compression: Compression,
+    // For an encrypted chunk, the SHA-256 of its file as stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stored_hash: Option<String>,
+    // Whether its file was there when the set was opened, and as long as recorded when
+    // stored as it is; see `ChunkSet::coverage`
+    #[serde(default = "present")]
+    pub present: bool,
+}
+
+fn present() -> bool {
+    true
+}
+
+// The chunks of a set in order, for tooling that wants to look at them without
+// reconstructing anything. The manifest's chunk list is used when there is one;
+// sets without one are scanned, and a gap in their numbering is an error since the
+// offsets after it would be guesses.
+#[derive(Clone, Debug)]
+pub struct ChunkSet {
+    manifest: Option<Manifest>,
+    chunks: Vec<ChunkInfo>,
+}
+
+impl ChunkSet {
+    // `directory` may be a zip or tar of the chunks. Its manifest is refused for having
+    // been changed after it was sealed unless `accept_modified`.
+    pub fn open(directory: &Path, accept_modified: bool) -> Result<ChunkSet> {
+        if is_archive(directory) {
+            return ChunkSet::from_store(&ArchiveStore::open(directory, accept_modified)?);
+        }
+        ChunkSet::from_store(&LocalDirStore::open(directory, accept_modified)?)
+    }
+
+    pub fn from_store(store: &impl ChunkStore) -> Result<ChunkSet> {
+        let manifest = store.read_info()?;
+        let mut sizes = Vec::new();
+        match &manifest {
+            Some(manifest) if !manifest.chunks.is_empty() => {
+                for (index, entry) in manifest.indexed() {
+                    let compression = manifest.compression_of(entry);
+                    let stored = (manifest.stored_as_is(entry), entry.stored_hash.clone());
+                    sizes.push((index, entry.size, entry.hash.clone(), compression, stored));
+                }
+            }
+            _ => {
+                let indices = store.list_chunks()?;
+                check_numbering(&indices)?;
+                for index in indices {
+                    let compression = store.compression();
+                    let stored = (compression.is_none(), None);
+                    sizes.push((index, store.chunk_len(index)?, None, compression, stored));
+                }
+            }
+        }
+
+        // Compressed and encrypted chunks aren't decoded to check their length, which
+        // takes reading them through
+        let found = store.list_chunks()?;
+        let mut chunks = Vec::with_capacity(sizes.len());
+        let mut offset = 0;
+        for (index, len, hash, compression, (as_is, stored_hash)) in sizes {
+            let present = found.binary_search(&index).is_ok()
+                && (!as_is || store.chunk_len(index).is_ok_and(|got| got == len));
+            chunks.push(ChunkInfo {
+                index,
+                path: store.chunk_path(index),
+                offset,
+                len,
+                hash,
+                compression,
+                stored_hash,
+                present,
+            });
+            offset += len;
+        }
+        Ok(ChunkSet { manifest, chunks })
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, ChunkInfo> {
+        self.chunks.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    // Size of the original file.
+    pub fn total_size(&self) -> u64 {
+        self.chunks
+            .last()
+            .map_or(0, |chunk| chunk.offset + chunk.len)
+    }
+
+    // None for sets without a manifest.
+    pub fn original_filename(&self) -> Option<&str> {
+        Some(&self.manifest.as_ref()?.original_filename)
+    }
+
+    // None for sets without a manifest; see `MANIFEST_VERSION`.
+    pub fn format_version(&self) -> Option<u32> {
+        Some(self.manifest.as_ref()?.version)
+    }
+
+    pub fn hash_algorithm(&self) -> Option<HashAlgorithm> {
+        self.manifest.as_ref()?.hash
+    }
+
+    // How the set as a whole is stored; see `ChunkInfo::compression` for single chunks.
+    pub fn compression(&self) -> Compression {
+        self.manifest
+            .as_ref()
+            .map_or(Compression::None, |manifest| manifest.compression)
+    }
+
+    pub fn manifest(&self) -> Option<&Manifest> {
+        self.manifest.as_ref()
+    }
+
+    // Which bytes of the original file the chunks present hold and which the missing
+    // ones would have, in offsets of the file as it was split, from its range's start
+    // for a set of only part of it.
+    pub fn coverage(&self) -> Coverage {
+        let base = self
+            .manifest
+            .as_ref()
+            .and_then(|manifest| manifest.range)
+            .map_or(0, |range| range.offset);
+        let mut ranges: Vec<ByteRange> = Vec::new();
+        for chunk in &self.chunks {
+            match ranges.last_mut() {
+                Some(last) if last.present == chunk.present => {
+                    last.len += chunk.len;
+                    last.last_chunk = chunk.index;
+                }
+                _ => ranges.push(ByteRange {
+                    offset: base + chunk.offset,
+                    len: chunk.len,
+                    present: chunk.present,
+                    first_chunk: chunk.index,
+                    last_chunk: chunk.index,
+                }),
+            }
+        }
+        Coverage {
+            total_size: self.total_size(),
+            ranges,
+        }
+    }
+}
+
+// The directory of the chunk set that the file at `path` looks to be a piece of, for a
+// split to be wary of: one of the files of a directory that opens as a set, such as a
+// chunk, its parity or info.json, or, where the set can't be opened, a file named like
+// a chunk beside an info.json. Anything else there, such as the file reconstructed from
+// the set, which goes beside its chunks, is not part of it. A chunk of a sharded set is
+// in a directory further down, so that is looked at too.
+pub fn containing_set(path: &Path) -> Option<PathBuf> {
+    if !fs::metadata(path).is_ok_and(|metadata| metadata.is_file()) {
+        return None;
+    }
+    let path = absolute_path(path);
+    let name = path.file_name()?.to_str()?;
+    let parent = parent_dir(&path)?;
+    let mut candidates = vec![(parent.clone(), name.to_string())];
+    if let Some(shard) = parent.file_name().and_then(|shard| shard.to_str())
+        && is_shard_dir(shard)
+        && let Some(above) = parent_dir(&parent)
+    {
+        candidates.push((above, format!("{}/{}", shard, name)));
+    }
+    candidates
+        .into_iter()
+        .find(|(directory, name)| is_piece_of(directory, name))
+        .map(|(directory, _)| directory)
+}
+
+// Whether `name`, in `directory` or a shard of it, is a piece of the set there.
+fn is_piece_of(directory: &Path, name: &str) -> bool {
+    match ChunkSet::open(directory, false) {
+        Ok(set) => {
+            !set.is_empty()
+                && (is_set_file(name) || set.iter().any(|chunk| chunk.path == directory.join(name)))
+        }
+        Err(_) => chunk_index(name).is_some() && directory.join(MANIFEST_NAME).is_file(),
+    }
+}
+
+// Bytes `offset` to `offset + len` of the original file, which chunks `first_chunk` to
+// `last_chunk` hold, or would if they were there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ByteRange {
+    pub offset: u64,
+    pub len: u64,
+    pub present: bool,
+    pub first_chunk: usize,
+    pub last_chunk: usize,
+}
+
+impl ByteRange {
+    pub fn end(&self) -> u64 {
+        self.offset + self.len
+    }
+}
+
+// Outcome of `ChunkSet::coverage`: the whole of the file in order, in ranges that are
+// there and gaps that aren't, one after the other.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Coverage {
+    pub total_size: u64,
+    pub ranges: Vec<ByteRange>,
+}
+
+impl Coverage {
+    pub fn gaps(&self) -> impl Iterator<Item = &ByteRange> {
+        self.ranges.iter().filter(|range| !range.present)
+    }
+
+    pub fn present_size(&self) -> u64 {
+        self.ranges
+            .iter()
+            .filter(|range| range.present)
+            .map(|range| range.len)
+            .sum()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.gaps().next().is_none()
+    }
+
+    // As in "93.7% present, 3 gaps, largest gap 15.0 MiB".
+    pub fn summary(&self) -> String {
+        let percent = match self.total_size {
+            0 => 100.0,
+            total => self.present_size() as f64 * 100.0 / total as f64,
+        };
+        // Not rounded up to 100% while anything is missing
+        let percent = match self.is_complete() {
+            true => format!("{:.0}%", percent),
+            false => format!("{:.1}%", (percent * 10.0).floor() / 10.0),
+        };
+        let gaps = self.gaps().count();
+        match self.gaps().map(|gap| gap.len).max() {
+            None => format!("{} present, no gaps", percent),
+            Some(largest) => format!(
+                "{} present, {} {}, largest gap {}",
+                percent,
+                gaps,
+                match gaps {
+                    1 => "gap",
+                    _ => "gaps",
+                },
+                format_size(largest)
+            ),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a ChunkSet {
+    type Item = &'a ChunkInfo;
+    type IntoIter = slice::Iter<'a, ChunkInfo>;
+
+    fn into_iter(self) -> slice::Iter<'a, ChunkInfo> {
+        self.chunks.iter()
+    }
+}
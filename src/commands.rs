@@ -0,0 +1,38 @@
+// A module for each command, with its arguments and what running it does; the menus
+// and the helpers several commands share stay in main.rs.
+
+pub(crate) mod bench;
+pub(crate) mod catalog;
+pub(crate) mod chunk_dirs;
+pub(crate) mod completions;
+pub(crate) mod coverage;
+pub(crate) mod doctor;
+pub(crate) mod empty_trash;
+pub(crate) mod explore;
+pub(crate) mod export_manifest;
+pub(crate) mod heal;
+pub(crate) mod history;
+pub(crate) mod import;
+pub(crate) mod keygen;
+pub(crate) mod mark;
+#[cfg(all(feature = "mount", unix))]
+pub(crate) mod mount;
+pub(crate) mod next;
+pub(crate) mod pack;
+pub(crate) mod profile;
+pub(crate) mod rechunk;
+pub(crate) mod reconstruct;
+pub(crate) mod rekey;
+pub(crate) mod repair;
+pub(crate) mod reseal;
+pub(crate) mod self_test;
+#[cfg(feature = "serve")]
+pub(crate) mod serve;
+pub(crate) mod split;
+pub(crate) mod stats;
+pub(crate) mod status;
+pub(crate) mod stress;
+pub(crate) mod undo_last;
+pub(crate) mod unpack;
+pub(crate) mod verify;
+pub(crate) mod watch;
@@ -0,0 +1,59 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::DEFAULT_CHUNK_SIZE;
+use reconstruct_large_file::manifest::Compression;
+use reconstruct_large_file::size::parse_size;
+
+use crate::{bench, parse_compression, path_arg, thread_count};
+
+#[derive(Args)]
+pub(crate) struct BenchArgs {
+    /// Directory to run the benchmark in
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+    /// Amount of data to push through, e.g. 500MiB or 2GiB
+    #[arg(long, value_parser = parse_size, default_value = "1GiB")]
+    pub(crate) size: u64,
+    /// Size of each chunk [default: 5MiB]
+    #[arg(short = 's', long, value_parser = parse_size)]
+    pub(crate) chunk_size: Option<u64>,
+    /// Number of threads splitting and reconstructing [default: up to 4]
+    #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) threads: Option<u64>,
+    /// Use zeros instead of random data
+    #[arg(long)]
+    pub(crate) zeros: bool,
+    /// Also time compressing the chunks, on one thread and on all of them
+    #[arg(long, value_name = "CODEC[:LEVEL]", value_parser = parse_compression)]
+    pub(crate) compress: Option<(Compression, u32)>,
+    /// Leave the generated data in place afterwards
+    #[arg(long)]
+    pub(crate) keep: bool,
+}
+
+pub(crate) fn run(args: BenchArgs) {
+    let BenchArgs {
+        directory,
+        size,
+        chunk_size,
+        threads,
+        zeros,
+        compress,
+        keep,
+    } = args;
+    let options = bench::BenchOptions {
+        directory,
+        size,
+        chunk_size: chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE),
+        threads: thread_count(threads),
+        zeros,
+        compress,
+        keep,
+    };
+    if let Err(e) = bench::run(&options) {
+        eprintln!("Benchmark failed: {}", e);
+        exit(1);
+    }
+}
@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::{Args, ValueEnum};
+
+use crate::{catalog, exit_code, globals, interrupt, logging, path_arg};
+
+#[derive(Args)]
+pub(crate) struct CatalogArgs {
+    /// Directory to look for chunk sets under
+    #[arg(value_parser = path_arg())]
+    pub(crate) root: PathBuf,
+    /// How to print the entries: jsonl, one JSON object per line
+    #[arg(long, value_enum, default_value_t = CatalogFormat::Jsonl)]
+    pub(crate) format: CatalogFormat,
+    /// Only the sets created or modified at or after this time in UTC, e.g.
+    /// 2024-03-01 or 2024-03-01T12:00:00Z, or in seconds since 1970
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    pub(crate) since: Option<u64>,
+}
+
+pub(crate) fn run(args: CatalogArgs) {
+    let CatalogArgs {
+        root,
+        format: CatalogFormat::Jsonl,
+        since,
+    } = args;
+    let operation = interrupt::start();
+    if let Err(e) = catalog::run(&root, since, globals().accept_modified, &operation.token) {
+        eprintln!("Error writing the catalog: {}", e);
+        exit(exit_code(&e));
+    }
+}
+
+// Below a single thread's buffers at their smallest nothing can be copied.
+// A time in UTC as RFC 3339, as in 2024-03-01T12:00:00Z, a date, or seconds since 1970.
+fn parse_time(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    input
+        .parse()
+        .ok()
+        .or_else(|| logging::parse_utc_time(input))
+        .ok_or_else(|| {
+            format!(
+                "'{}' is not a time such as 2024-03-01, 2024-03-01T12:00:00Z or 1709294400",
+                input
+            )
+        })
+}
+
+// For `catalog --format`, which only has the one so far.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum CatalogFormat {
+    Jsonl,
+}
@@ -0,0 +1,16 @@
+use clap::Args;
+
+use crate::completions;
+
+#[derive(Args)]
+pub(crate) struct ChunkDirsArgs {
+    #[arg(default_value = "", allow_hyphen_values = true)]
+    pub(crate) prefix: String,
+}
+
+pub(crate) fn run(args: ChunkDirsArgs) {
+    let ChunkDirsArgs { prefix } = args;
+    for directory in completions::chunk_dirs(&prefix) {
+        println!("{}", directory);
+    }
+}
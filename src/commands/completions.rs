@@ -0,0 +1,15 @@
+use clap::{Args, CommandFactory};
+
+use crate::{Cli, completions};
+
+#[derive(Args)]
+pub(crate) struct CompletionsArgs {
+    /// Shell to complete for
+    #[arg(value_enum)]
+    pub(crate) shell: completions::Shell,
+}
+
+pub(crate) fn run(args: CompletionsArgs) {
+    let CompletionsArgs { shell } = args;
+    print!("{}", completions::generate(shell, Cli::command()));
+}
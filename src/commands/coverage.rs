@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::size::format_size;
+use reconstruct_large_file::{ChunkSet, Coverage};
+
+use crate::style::{self, Color};
+use crate::{exit_code, globals, path_arg};
+
+#[derive(Args)]
+pub(crate) struct CoverageArgs {
+    /// Directory containing the chunks, or a zip or tar of them
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+    /// Print the ranges as JSON
+    #[arg(long)]
+    pub(crate) json: bool,
+}
+
+pub(crate) fn run(args: CoverageArgs) {
+    let CoverageArgs { directory, json } = args;
+    let set = ChunkSet::open(&directory, globals().accept_modified).unwrap_or_else(|e| {
+        eprintln!("Error reading the chunks: {}", e);
+        exit(exit_code(&e));
+    });
+    let coverage = set.coverage();
+    match json {
+        true => print_coverage_json(&coverage),
+        false => print_coverage(&coverage),
+    }
+    if !coverage.is_complete() {
+        exit(4);
+    }
+}
+
+// Each range on a line, from where to where in the file and which chunks hold it, then
+// how much of the file that comes to.
+fn print_coverage(coverage: &Coverage) {
+    let end = coverage.ranges.last().map_or(0, |range| range.end());
+    let width = end.to_string().len();
+    for range in &coverage.ranges {
+        let (what, color) = match range.present {
+            true => ("present", Color::Green),
+            false => ("missing", Color::Red),
+        };
+        let chunks = match range.first_chunk == range.last_chunk {
+            true => format!("chunk {}", range.first_chunk),
+            false => format!("chunks {}-{}", range.first_chunk, range.last_chunk),
+        };
+        println!(
+            "{}  {:>width$} - {:>width$}  {:>10}  {}",
+            style::paint(what, color),
+            range.offset,
+            range.end(),
+            format_size(range.len),
+            chunks,
+            width = width
+        );
+    }
+    println!("{}", coverage.summary());
+}
+
+fn print_coverage_json(coverage: &Coverage) {
+    let mut value = serde_json::to_value(coverage).unwrap_or_default();
+    value["present_size"] = serde_json::json!(coverage.present_size());
+    value["gaps"] = serde_json::json!(coverage.gaps().count());
+    value["largest_gap"] = serde_json::json!(coverage.gaps().map(|gap| gap.len).max());
+    value["summary"] = serde_json::json!(coverage.summary());
+    match serde_json::to_string_pretty(&value) {
+        Ok(text) => println!("{}", text),
+        Err(e) => {
+            eprintln!("Error writing the ranges: {}", e);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::Duration;
+
+use clap::Args;
+use reconstruct_large_file::size::format_size;
+use reconstruct_large_file::{DEFAULT_TIMESTAMP_TOLERANCE, Diagnosis, Severity, Status, diagnose};
+
+use crate::style::{self, Color};
+use crate::{exit_code, globals, interrupt, parse_duration, path_arg};
+
+#[derive(Args)]
+pub(crate) struct DoctorArgs {
+    /// Directory containing the chunks, or a zip or tar of them
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+    /// Print the diagnosis as JSON: each finding with a code that stays the same from
+    /// one version to the next, its severity, message and suggestion
+    #[arg(long)]
+    pub(crate) json: bool,
+    /// Also compare the chunks' modification times with those split --record-times
+    /// noted and with each other, and list the chunks that stand out, as one rewritten
+    /// since; only hints, which never change the exit status
+    #[arg(long)]
+    pub(crate) timestamps: bool,
+    /// How far apart two times can be and count as the same, for filesystems that keep
+    /// them coarsely, e.g. 2s on FAT or 1h for a set copied across time zones
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "timestamps")]
+    pub(crate) timestamp_tolerance: Option<Duration>,
+}
+
+pub(crate) fn run(args: DoctorArgs) {
+    let DoctorArgs {
+        directory,
+        json,
+        timestamps,
+        timestamp_tolerance,
+    } = args;
+    let operation = interrupt::start();
+    let tolerance = timestamp_tolerance.unwrap_or(DEFAULT_TIMESTAMP_TOLERANCE);
+    let timestamps = timestamps.then_some(tolerance);
+    match diagnose(
+        &directory,
+        timestamps,
+        globals().accept_modified,
+        &mut |_| {},
+        &operation.token,
+    ) {
+        Ok(diagnosis) => {
+            match json {
+                true => match serde_json::to_string_pretty(&diagnosis) {
+                    Ok(text) => println!("{}", text),
+                    Err(e) => {
+                        eprintln!("Error writing the diagnosis: {}", e);
+                        exit(1);
+                    }
+                },
+                false => print_diagnosis(&diagnosis),
+            }
+            if diagnosis.status == Status::Broken {
+                exit(4);
+            }
+        }
+        Err(e) => {
+            eprintln!("Error during diagnosis: {}", e);
+            exit(exit_code(&e));
+        }
+    }
+}
+
+// What `doctor` found: a line on the set as a whole, then each finding with what to do
+// about it.
+fn print_diagnosis(diagnosis: &Diagnosis) {
+    let (status, color) = match diagnosis.status {
+        Status::Healthy => ("no problems found", Color::Green),
+        Status::Degraded => ("usable, with warnings", Color::Yellow),
+        Status::Broken => ("broken", Color::Red),
+    };
+    let chunks = match diagnosis.expected_chunks {
+        Some(expected) => format!("{} of {} chunks", diagnosis.chunks, expected),
+        None => format!("{} chunks", diagnosis.chunks),
+    };
+    println!(
+        "{}: {}, {}, {}.",
+        diagnosis.directory.display(),
+        style::paint(status, color),
+        chunks,
+        format_size(diagnosis.total_size)
+    );
+    for finding in &diagnosis.findings {
+        let (label, color) = match finding.severity {
+            Severity::Error => ("error", Color::Red),
+            Severity::Warning => ("warning", Color::Yellow),
+            Severity::Note => ("note", Color::Blue),
+        };
+        println!();
+        let label = style::paint(&format!("{:<8}", label), color);
+        println!("{} {}.", label, finding.message);
+        if let Some(suggestion) = &finding.suggestion {
+            println!("{:<8} {}.", "", suggestion);
+        }
+    }
+}
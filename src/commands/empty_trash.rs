@@ -0,0 +1,73 @@
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::Duration;
+
+use clap::Args;
+use reconstruct_large_file::TRASH_DIR;
+use reconstruct_large_file::size::format_size;
+
+use crate::{parse_duration, path_arg, trash};
+
+#[derive(Args)]
+pub(crate) struct EmptyTrashArgs {
+    /// Directories with a .fsr-trash/ in them, or .fsr-trash/ directories themselves
+    #[arg(required = true, value_parser = path_arg())]
+    pub(crate) directories: Vec<PathBuf>,
+    /// Only remove what was moved there longer ago than this, e.g. 30d or 12h
+    #[arg(long, value_name = "AGE", value_parser = parse_duration)]
+    pub(crate) older_than: Option<Duration>,
+    /// Only list what would be removed
+    #[arg(long)]
+    pub(crate) dry_run: bool,
+}
+
+pub(crate) fn run(args: EmptyTrashArgs) {
+    let EmptyTrashArgs {
+        directories,
+        older_than,
+        dry_run,
+    } = args;
+    let mut failed = false;
+    for directory in &directories {
+        match trash::empty(directory, older_than, dry_run) {
+            Ok(emptied) => print_emptied(&emptied, dry_run),
+            Err(e) => {
+                eprintln!(
+                    "Cannot empty {} in {}: {}",
+                    TRASH_DIR,
+                    directory.display(),
+                    e
+                );
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        exit(1);
+    }
+}
+
+// Where the file a reconstruction replaced went.
+fn print_emptied(emptied: &trash::Emptied, dry_run: bool) {
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    for (path, size) in &emptied.removed {
+        println!("{} {} ({})", verb, path.display(), format_size(*size));
+    }
+    let total = emptied.removed.iter().map(|(_, size)| size).sum();
+    let kept = match emptied.kept {
+        0 => String::new(),
+        kept => format!("; kept {} moved there more recently", kept),
+    };
+    match emptied.removed.len() {
+        0 => println!("Nothing to remove in {}{}.", emptied.trash.display(), kept),
+        count => println!(
+            "{} {} {}, {}, from {}{}.",
+            verb,
+            count,
+            if count == 1 { "file" } else { "files" },
+            format_size(total),
+            emptied.trash.display(),
+            kept
+        ),
+    }
+}
@@ -0,0 +1,48 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+
+use crate::{exit_code, explore, globals, path_arg};
+
+#[derive(Args)]
+pub(crate) struct ExploreArgs {
+    /// Directory containing the chunks, or a zip or tar of them
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+    /// Number of the chunk, as in chunk117
+    pub(crate) index: usize,
+    /// Show it as a hex dump, however it looks
+    #[arg(long, conflicts_with = "text")]
+    pub(crate) hex: bool,
+    /// Show it as text, however it looks
+    #[arg(long)]
+    pub(crate) text: bool,
+    /// Write it straight to stdout, even on a terminal
+    #[arg(long)]
+    pub(crate) no_pager: bool,
+}
+
+pub(crate) fn run(args: ExploreArgs) {
+    let ExploreArgs {
+        directory,
+        index,
+        hex,
+        text,
+        no_pager,
+    } = args;
+    let explored =
+        explore::open(&directory, index, globals().accept_modified).unwrap_or_else(|e| {
+            eprintln!("Error exploring the chunk: {}", e);
+            exit(exit_code(&e));
+        });
+    let view = match (hex, text) {
+        (true, _) => explore::View::Hex,
+        (_, true) => explore::View::Text,
+        _ => explore::View::Auto,
+    };
+    if let Err(e) = explore::show(explored, view, !no_pager) {
+        eprintln!("Error exploring the chunk: {}", e);
+        exit(1);
+    }
+}
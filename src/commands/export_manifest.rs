@@ -0,0 +1,64 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::export_manifest;
+use reconstruct_large_file::size::format_size;
+
+use crate::{exit_code, globals, interrupt, path_arg, read_key};
+
+#[derive(Args)]
+pub(crate) struct ExportManifestArgs {
+    /// Directory containing the chunks and their info.json
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+    /// File to write, e.g. backup.fsrm
+    #[arg(short, long, value_name = "FILE", value_parser = path_arg())]
+    pub(crate) output: PathBuf,
+    /// File holding a secret key to sign the manifest with, which whoever checks it
+    /// needs too
+    #[arg(long, value_name = "FILE", value_parser = path_arg())]
+    pub(crate) key: Option<PathBuf>,
+}
+
+pub(crate) fn run(args: ExportManifestArgs) {
+    let ExportManifestArgs {
+        directory,
+        output,
+        key,
+    } = args;
+    let key = key.map(|path| read_key(&path));
+    let operation = interrupt::start();
+    match export_manifest(
+        &directory,
+        &output,
+        key.as_deref(),
+        globals().accept_modified,
+        &mut |_| {},
+        &operation.token,
+    ) {
+        Ok(report) => {
+            let signed = match report.signed {
+                true => ", signed",
+                false => "",
+            };
+            println!(
+                "Exported the {} chunks ({}) to {}{}.",
+                report.chunks,
+                format_size(report.total_size),
+                report.output.display(),
+                signed
+            );
+            if report.hashed > 0 {
+                println!(
+                    "info.json had no hashes for {} of them, so they were hashed now.",
+                    report.hashed
+                );
+            }
+        }
+        Err(e) => {
+            eprintln!("Error exporting the manifest: {}", e);
+            exit(exit_code(&e));
+        }
+    }
+}
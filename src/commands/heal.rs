@@ -0,0 +1,99 @@
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::{heal, plan_heal};
+
+use crate::progress::JsonProgress;
+use crate::{ProgressFormat, exit_code, finish_dry_run, globals, interrupt, path_arg, steal_lock};
+
+#[derive(Args)]
+pub(crate) struct HealArgs {
+    /// Directory containing the chunks
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+    /// Another copy of the same chunks, such as a mirror; give several to try in turn
+    #[arg(long = "from", value_name = "DIR", required = true, value_parser = path_arg())]
+    pub(crate) sources: Vec<PathBuf>,
+    /// Check every chunk and copy as for healing, then list what would be taken from
+    /// where, and the files that would be written with their sizes, without writing
+    #[arg(long)]
+    pub(crate) dry_run: bool,
+    /// Report progress on stderr, one JSON object per line
+    #[arg(long, value_enum)]
+    pub(crate) progress: Option<ProgressFormat>,
+}
+
+pub(crate) fn run(args: HealArgs) {
+    let HealArgs {
+        directory,
+        sources,
+        dry_run,
+        progress,
+    } = args;
+    if dry_run {
+        heal_dry_run(&directory, &sources, progress);
+        return;
+    }
+    let mut json = (progress == Some(ProgressFormat::Json)).then(|| JsonProgress::new(None));
+    steal_lock(&directory);
+    let operation = interrupt::start();
+    let mut update = |event| {
+        if let Some(json) = &mut json {
+            json.update(&event);
+        }
+    };
+    match heal(
+        &directory,
+        &sources,
+        globals().accept_modified,
+        &mut update,
+        &operation.token,
+    ) {
+        Ok(report) => {
+            for chunk in &report.healed {
+                println!("Healed {} from {}", chunk.name, chunk.source.display());
+            }
+            if !report.is_ok() {
+                eprintln!("No intact copy of {} was found.", report.damaged.join(", "));
+                exit(4);
+            }
+            if report.healed.is_empty() {
+                println!("Nothing to heal.");
+            }
+        }
+        Err(e) => {
+            if let Some(json) = &mut json {
+                json.fail(&e, exit_code(&e));
+            }
+            eprintln!("Error during healing: {}", e);
+            exit(exit_code(&e));
+        }
+    }
+}
+
+// `heal --dry-run`: which copy each lost chunk would come from, then the files written.
+fn heal_dry_run(directory: &Path, sources: &[PathBuf], progress: Option<ProgressFormat>) {
+    let operation = interrupt::start();
+    let mut healable = true;
+    let planned = plan_heal(
+        directory,
+        sources,
+        globals().accept_modified,
+        &operation.token,
+    )
+    .map(|plan| {
+        for chunk in &plan.chunks {
+            println!("Would heal {} from {}", chunk.name, chunk.source.display());
+        }
+        if !plan.damaged.is_empty() {
+            eprintln!("No intact copy of {} was found.", plan.damaged.join(", "));
+            healable = false;
+        }
+        plan.changes
+    });
+    finish_dry_run("heal", planned, progress);
+    if !healable {
+        exit(4);
+    }
+}
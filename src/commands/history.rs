@@ -0,0 +1,71 @@
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::size::format_size;
+
+use crate::journal;
+use crate::style::{self, Color};
+
+#[derive(Args)]
+pub(crate) struct HistoryArgs {
+    /// Show this many of the latest
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    pub(crate) limit: usize,
+    /// Print them as JSON, one object per line as the journal has them
+    #[arg(long)]
+    pub(crate) json: bool,
+}
+
+pub(crate) fn run(args: HistoryArgs) {
+    let HistoryArgs { limit, json } = args;
+    let records = journal::read().unwrap_or_else(|e| {
+        eprintln!("Cannot read the journal: {}", e);
+        exit(1);
+    });
+    let shown = &records[records.len().saturating_sub(limit)..];
+    match json {
+        true => {
+            for record in shown {
+                if let Ok(line) = serde_json::to_string(record) {
+                    println!("{}", line);
+                }
+            }
+        }
+        false => print_history(shown),
+    }
+}
+
+// The journal's records, oldest first, with what went wrong under those that failed.
+fn print_history(records: &[journal::Record]) {
+    if records.is_empty() {
+        match journal::path() {
+            Some(path) => println!("Nothing in the journal at {} yet.", path.display()),
+            None => println!("Nothing in the journal yet."),
+        }
+        return;
+    }
+    for record in records {
+        let (outcome, color) = match record.outcome {
+            journal::Outcome::Succeeded => ("succeeded", Color::Green),
+            journal::Outcome::Failed => ("failed", Color::Red),
+            journal::Outcome::Interrupted => ("interrupted", Color::Yellow),
+        };
+        let size = record.bytes.map(format_size).unwrap_or_default();
+        let target = match &record.destination {
+            Some(destination) => format!("{} -> {}", record.source, destination),
+            None => record.source.clone(),
+        };
+        println!(
+            "{}  {:<11} {} {:>10} {:>8.1} s  {}",
+            record.timestamp,
+            record.operation,
+            style::paint(&format!("{:<11}", outcome), color),
+            size,
+            record.duration_secs,
+            target
+        );
+        if let Some(error) = &record.error {
+            println!("{:>26}{}", "", error);
+        }
+    }
+}
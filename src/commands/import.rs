@@ -0,0 +1,121 @@
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::manifest::HashAlgorithm;
+use reconstruct_large_file::{detect_foreign, import, reconstruct_foreign};
+
+use crate::prompt::{self, confirm, text_prompt};
+use crate::{exit_code, globals, interrupt, path_arg, pick_foreign, print_foreign, thread_count};
+
+#[derive(Args)]
+pub(crate) struct ImportArgs {
+    /// Directory containing the pieces
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+    /// Name of the file the pieces were split from [default: worked out from their
+    /// names, or asked for]
+    #[arg(long)]
+    pub(crate) name: Option<String>,
+    /// Pieces to take when the directory holds several sets, by what their names share
+    /// before the number or letters, e.g. backup.7z.
+    #[arg(long)]
+    pub(crate) prefix: Option<String>,
+    /// Join the pieces into this file rather than writing an info.json
+    #[arg(short, long, value_name = "FILE", value_parser = path_arg())]
+    pub(crate) output: Option<PathBuf>,
+    /// Record a hash of every piece in info.json
+    #[arg(long, value_enum, conflicts_with = "output")]
+    pub(crate) hash: Option<HashAlgorithm>,
+}
+
+pub(crate) fn run(args: ImportArgs) {
+    let ImportArgs {
+        directory,
+        name,
+        prefix,
+        output,
+        hash,
+    } = args;
+    let sets = match detect_foreign(&directory) {
+        Ok(sets) => sets,
+        Err(e) => {
+            eprintln!("Cannot import {}: {}", directory.display(), e);
+            exit(exit_code(&e));
+        }
+    };
+    if sets.is_empty() {
+        eprintln!(
+            "No pieces split by another tool were found in {}.",
+            directory.display()
+        );
+        exit(1);
+    }
+    let ask = io::stdin().is_terminal();
+    let set = match pick_foreign(sets, prefix.as_deref(), ask) {
+        Ok(Some(set)) => set,
+        Ok(None) => exit(2),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    };
+    print_foreign(&set);
+    if !set.doubts.is_empty() && !prompt::assume_yes() {
+        if !ask {
+            eprintln!("Pass --yes to go ahead in this order anyway.");
+            exit(2);
+        }
+        if !confirm("Go ahead in this order?", false).unwrap_or(false) {
+            println!("Nothing imported.");
+            exit(1);
+        }
+    }
+    let operation = interrupt::start();
+    if let Some(output) = output {
+        match reconstruct_foreign(
+            &set,
+            &output,
+            globals().temp_dir.as_deref(),
+            thread_count(None),
+            &mut |_| {},
+            &operation.token,
+        ) {
+            Ok(report) => println!("Joined file saved as \"{}\".", report.output.display()),
+            Err(e) => {
+                eprintln!("Error joining the pieces: {}", e);
+                exit(exit_code(&e));
+            }
+        }
+        return;
+    }
+    let name = match name.or_else(|| set.original_filename.clone()) {
+        Some(name) => name,
+        None if ask => match text_prompt("Name of the file the pieces were split from", None) {
+            Ok(name) => name,
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        },
+        None => {
+            eprintln!(
+                "The names of the pieces don't say what the file was called; give it with --name."
+            );
+            exit(2);
+        }
+    };
+    match import(&set, &name, hash, &mut |_| {}, &operation.token) {
+        Ok(report) => println!(
+            "Wrote an info.json for the {} pieces of \"{}\"; {} is now a chunk set.",
+            report.chunks,
+            report.original_filename,
+            report.directory.display()
+        ),
+        Err(e) => {
+            eprintln!("Error during import: {}", e);
+            exit(exit_code(&e));
+        }
+    }
+}
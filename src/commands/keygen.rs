@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::ChunkKey;
+
+use crate::{exit_code, path_arg};
+
+#[derive(Args)]
+pub(crate) struct KeygenArgs {
+    /// File to write the key to, which mustn't exist yet
+    #[arg(long, value_name = "FILE", value_parser = path_arg())]
+    pub(crate) out: PathBuf,
+}
+
+pub(crate) fn run(args: KeygenArgs) {
+    let KeygenArgs { out } = args;
+    match ChunkKey::generate_file(&out) {
+        Ok(key) => println!(
+            "Wrote a new key to {}, fingerprint {}. Keep a copy of it somewhere safe: \
+             without it, nothing encrypted with it can be read.",
+            out.display(),
+            key.fingerprint()
+        ),
+        Err(e) => {
+            eprintln!("Error writing the key: {}", e);
+            exit(exit_code(&e));
+        }
+    }
+}
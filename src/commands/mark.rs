@@ -0,0 +1,38 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::{TransferState, mark};
+
+use crate::{exit_code, globals, path_arg};
+
+#[derive(Args)]
+pub(crate) struct MarkArgs {
+    /// Directory containing the chunks
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+    /// Chunks to mark, each by its index (from 0) or file name
+    #[arg(required = true)]
+    pub(crate) chunks: Vec<String>,
+    /// What they are now
+    #[arg(long, value_enum)]
+    pub(crate) state: TransferState,
+}
+
+pub(crate) fn run(args: MarkArgs) {
+    let MarkArgs {
+        directory,
+        chunks,
+        state,
+    } = args;
+    match mark(&directory, &chunks, state, globals().accept_modified) {
+        Ok(indices) => match indices.as_slice() {
+            [index] => println!("Marked chunk {} {}.", index, state.name()),
+            _ => println!("Marked {} chunks {}.", indices.len(), state.name()),
+        },
+        Err(e) => {
+            eprintln!("Error marking the chunks: {}", e);
+            exit(exit_code(&e));
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+
+use crate::{exit_code, globals, interrupt, mount, path_arg};
+
+#[derive(Args)]
+pub(crate) struct MountArgs {
+    /// Directory to look for chunk sets under, at any depth
+    #[arg(value_parser = path_arg())]
+    pub(crate) root: PathBuf,
+    /// Empty directory to mount them at
+    #[arg(value_parser = path_arg())]
+    pub(crate) mountpoint: PathBuf,
+}
+
+pub(crate) fn run(args: MountArgs) {
+    let MountArgs { root, mountpoint } = args;
+    for (directory, what) in [
+        (&root, "a directory"),
+        (&mountpoint, "a directory to mount at"),
+    ] {
+        if !directory.is_dir() {
+            eprintln!("{} is not {}.", directory.display(), what);
+            exit(2);
+        }
+    }
+    let options = mount::MountOptions {
+        root,
+        mountpoint,
+        accept_modified: globals().accept_modified,
+    };
+    let operation = interrupt::start();
+    if let Err(e) = mount::run(&options, &operation.token) {
+        eprintln!("Error during mounting: {}", e);
+        exit(exit_code(&e));
+    }
+}
@@ -0,0 +1,28 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::{TransferState, transfer_status};
+
+use crate::{exit_code, globals, path_arg};
+
+#[derive(Args)]
+pub(crate) struct NextArgs {
+    /// Directory containing the chunks
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+    /// How many to print
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) count: u64,
+}
+
+pub(crate) fn run(args: NextArgs) {
+    let NextArgs { directory, count } = args;
+    let status = transfer_status(&directory, globals().accept_modified).unwrap_or_else(|e| {
+        eprintln!("Error reading the transfer states: {}", e);
+        exit(exit_code(&e));
+    });
+    for chunk in status.in_state(TransferState::Pending).take(count as usize) {
+        println!("{}", chunk.path.display());
+    }
+}
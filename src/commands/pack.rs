@@ -0,0 +1,179 @@
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::size::{format_size, parse_size};
+use reconstruct_large_file::{
+    ChunkSet, DEFAULT_SELF_EXTRACTING_MAX, Script, default_output_name, pack, pack_into,
+    self_extracting, self_extracting_into,
+};
+
+use crate::{exit_code, globals, interrupt, path_arg, suffixed};
+
+#[derive(Args)]
+pub(crate) struct PackArgs {
+    /// Directory containing the chunks
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+    /// Tar to write, or - for standard output, e.g. to pipe it to ssh [default:
+    /// ./<directory name>.tar, or ./<file name>.sh or .cmd with --self-extracting]
+    #[arg(short, long, value_name = "FILE", value_parser = path_arg())]
+    pub(crate) output: Option<PathBuf>,
+    /// Write a shell script instead, holding the file itself as base64, that writes it
+    /// back into the current directory and checks its SHA-256 with nothing but sh,
+    /// tail, sed and base64 or openssl; it is about a third larger than the file
+    #[arg(long)]
+    pub(crate) self_extracting: bool,
+    /// With --self-extracting, write a .cmd for Windows, which decodes with certutil
+    #[arg(long, requires = "self_extracting")]
+    pub(crate) windows: bool,
+    /// With --self-extracting, the largest file to put in a script [default: 1 GiB]
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, requires = "self_extracting")]
+    pub(crate) max_size: Option<u64>,
+}
+
+pub(crate) fn run(args: PackArgs) {
+    let PackArgs {
+        directory,
+        output,
+        self_extracting,
+        windows,
+        max_size,
+    } = args;
+    if self_extracting {
+        let script = match windows {
+            true => Script::Cmd,
+            false => Script::Shell,
+        };
+        pack_self_extracting(
+            &directory,
+            output,
+            script,
+            max_size.unwrap_or(DEFAULT_SELF_EXTRACTING_MAX),
+        );
+        return;
+    }
+    let operation = interrupt::start();
+    let packed = match output {
+        Some(output) if output.as_os_str() == "-" => {
+            if io::stdout().is_terminal() {
+                eprintln!("Not writing a tar to the terminal; redirect or pipe it.");
+                exit(2);
+            }
+            let mut stdout = io::BufWriter::new(io::stdout().lock());
+            pack_into(
+                &directory,
+                &mut stdout,
+                Path::new("standard output"),
+                globals().accept_modified,
+                &mut |_| {},
+                &operation.token,
+            )
+        }
+        output => {
+            let output = output.unwrap_or_else(|| default_tar(&directory));
+            pack(
+                &directory,
+                &output,
+                globals().temp_dir.as_deref(),
+                globals().accept_modified,
+                &mut |_| {},
+                &operation.token,
+            )
+        }
+    };
+    match packed {
+        // Standard output may be the tar, so this goes to stderr
+        Ok(report) => eprintln!(
+            "Packed {} files ({}) into {}.",
+            report.files.len(),
+            format_size(report.size),
+            report.archive.display()
+        ),
+        Err(e) => {
+            eprintln!("Error during packing: {}", e);
+            exit(exit_code(&e));
+        }
+    }
+}
+
+// `pack --self-extracting`: the file the set in `directory` holds as a script, in
+// `output` or `./<file name>.sh` (or .cmd).
+fn pack_self_extracting(directory: &Path, output: Option<PathBuf>, script: Script, max_size: u64) {
+    let output =
+        output.unwrap_or_else(
+            || match default_output_name(directory, globals().accept_modified) {
+                Ok(name) => PathBuf::from(format!("./{}.{}", name, script.extension())),
+                Err(e) => {
+                    eprintln!("Error during packing: {}", e);
+                    exit(exit_code(&e));
+                }
+            },
+        );
+    let to_stdout = output.as_os_str() == "-";
+    if to_stdout && io::stdout().is_terminal() {
+        eprintln!("Not writing a script to the terminal; redirect it to a file.");
+        exit(2);
+    }
+    if let Ok(set) = ChunkSet::open(directory, globals().accept_modified)
+        && set.total_size() <= max_size
+    {
+        // Four characters for every three bytes, and a line break for every 64 of them
+        let size = set.total_size();
+        let text = size.div_ceil(3) * 4 * 65 / 64;
+        eprintln!(
+            "Warning: the script holds the file as base64, about a third larger than it: \
+             about {} for {}.",
+            format_size(text),
+            format_size(size)
+        );
+    }
+    let operation = interrupt::start();
+    let written = match to_stdout {
+        true => self_extracting_into(
+            directory,
+            &mut io::BufWriter::new(io::stdout().lock()),
+            Path::new("standard output"),
+            script,
+            max_size,
+            globals().accept_modified,
+            &mut |_| {},
+            &operation.token,
+        ),
+        false => self_extracting(
+            directory,
+            &output,
+            script,
+            max_size,
+            globals().accept_modified,
+            &mut |_| {},
+            &operation.token,
+        ),
+    };
+    match written {
+        // Standard output may be the script, so this goes to stderr
+        Ok(report) => eprintln!(
+            "Packed {} ({}) into {}, which writes it back when run{}.",
+            report.files[0],
+            format_size(report.size),
+            report.archive.display(),
+            match script {
+                Script::Shell => " with sh",
+                Script::Cmd => " on Windows",
+            }
+        ),
+        Err(e) => {
+            eprintln!("Error during packing: {}", e);
+            exit(exit_code(&e));
+        }
+    }
+}
+
+// Where `pack` writes when no output is given: `./<directory name>.tar`.
+fn default_tar(directory: &Path) -> PathBuf {
+    let canonical = fs::canonicalize(directory).ok();
+    let name = canonical.as_deref().and_then(Path::file_name);
+    Path::new(".").join(suffixed(name, "chunks", ".tar"))
+}
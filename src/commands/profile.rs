@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+use std::process::exit;
+
+use clap::{Args, ValueEnum};
+
+use crate::load_profile;
+use crate::profile::{self, Profile};
+
+#[derive(Args)]
+pub(crate) struct ProfileArgs {
+    #[arg(value_enum)]
+    pub(crate) action: ProfileAction,
+    /// The profile to show
+    #[arg(required_if_eq("action", "show"))]
+    pub(crate) name: Option<String>,
+}
+
+pub(crate) fn run(args: ProfileArgs) {
+    let ProfileArgs { action, name } = args;
+    match action {
+        ProfileAction::List => {
+            let profiles = profile::list().unwrap_or_else(|e| {
+                eprintln!("Cannot read the config file: {}", e);
+                exit(1);
+            });
+            print_profiles(&profiles);
+        }
+        ProfileAction::Show => print_profile(&load_profile(&name.unwrap_or_default())),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ProfileAction {
+    List,
+    Show,
+}
+
+// Every profile, with what it holds on one line.
+fn print_profiles(profiles: &BTreeMap<String, Profile>) {
+    if profiles.is_empty() {
+        let place = profile::config_path()
+            .map(|path| format!(" in {}", path.display()))
+            .unwrap_or_default();
+        println!(
+            "No profiles{} yet; the interactive split offers to save one.",
+            place
+        );
+        return;
+    }
+    let width = profiles
+        .keys()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    for (name, profile) in profiles {
+        let settings: Vec<String> = profile
+            .describe()
+            .into_iter()
+            .map(|(label, value)| format!("{} {}", label.to_lowercase(), value))
+            .collect();
+        println!("{:<width$}  {}", name, settings.join(", "), width = width);
+    }
+}
+
+fn print_profile(profile: &Profile) {
+    let settings = profile.describe();
+    if settings.is_empty() {
+        println!("The profile holds no settings; splits with it use the defaults.");
+    }
+    for (label, value) in settings {
+        println!("  {:<18}{}", format!("{}:", label), value);
+    }
+}
@@ -0,0 +1,141 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::manifest::{Compression, HashAlgorithm};
+use reconstruct_large_file::size::{format_size, parse_size};
+use reconstruct_large_file::{
+    ChunkSet, DEFAULT_MIN_CHUNK_SIZE, RechunkOptions, plan_rechunk, rechunk,
+};
+
+use crate::progress::{JsonProgress, Timing};
+use crate::{
+    ProgressFormat, exit_code, finish_dry_run, globals, interrupt, journal, long_window,
+    parse_compression, path_arg, steal_lock,
+};
+
+#[derive(Args)]
+pub(crate) struct RechunkArgs {
+    /// Directory containing the chunks, or a zip or tar of them
+    #[arg(value_parser = path_arg())]
+    pub(crate) source: PathBuf,
+    /// Directory for the new chunks, which must be empty or not exist yet
+    #[arg(value_parser = path_arg())]
+    pub(crate) dest: PathBuf,
+    /// Size of each new chunk, e.g. 500K, 25MB or 1GB
+    #[arg(short = 's', long, value_parser = parse_size)]
+    pub(crate) chunk_size: u64,
+    /// Take a --chunk-size below 4KiB, as small as one byte, however many files that
+    /// makes
+    #[arg(long)]
+    pub(crate) i_know_what_im_doing: bool,
+    /// Record a hash of every new chunk in info.json [default: the one the source has]
+    #[arg(long, value_enum)]
+    pub(crate) hash: Option<HashAlgorithm>,
+    /// Compress every new chunk: gzip or gzip:LEVEL (1-9, default 6), zstd or
+    /// zstd:LEVEL (1-22, default 3), or none [default: as the source's are]
+    #[arg(long, value_name = "CODEC[:LEVEL]", value_parser = parse_compression)]
+    pub(crate) compress: Option<(Compression, u32)>,
+    /// With --compress zstd, match over a 128 MiB window, as zstd --long
+    #[arg(long, requires = "compress")]
+    pub(crate) long: bool,
+    /// Write every new chunk as base64 text, chunk000.txt, …
+    #[arg(long, conflicts_with = "compress")]
+    pub(crate) armor: bool,
+    /// Verify the source and check the destination as for rechunking, then list the
+    /// files that would be written, with their sizes, without writing any
+    #[arg(long)]
+    pub(crate) dry_run: bool,
+    /// Report progress on stderr, one JSON object per line
+    #[arg(long, value_enum)]
+    pub(crate) progress: Option<ProgressFormat>,
+}
+
+pub(crate) fn run(args: RechunkArgs) {
+    let RechunkArgs {
+        source,
+        dest,
+        chunk_size,
+        i_know_what_im_doing,
+        hash,
+        compress,
+        long,
+        armor,
+        dry_run,
+        progress,
+    } = args;
+    let compress = long_window(compress, long);
+    let mut options = RechunkOptions {
+        min_chunk_size: match i_know_what_im_doing {
+            true => 0,
+            false => DEFAULT_MIN_CHUNK_SIZE,
+        },
+        hash,
+        temp_dir: globals().temp_dir.clone(),
+        accept_modified: globals().accept_modified,
+        ..RechunkOptions::new(&source, &dest, chunk_size)
+    };
+    match compress {
+        Some((compression, level)) => {
+            options.compression = Some(compression);
+            options.compression_level = Some(level);
+        }
+        None if armor => options.compression = Some(Compression::Armor),
+        None => {}
+    }
+    if dry_run {
+        let operation = interrupt::start();
+        let planned = plan_rechunk(&options, &operation.token).map(|plan| {
+            println!(
+                "Would rechunk {} of {} into {} chunks of {} in {}.",
+                format_size(plan.total_size),
+                source.display(),
+                plan.chunks,
+                format_size(chunk_size),
+                plan.destination.display()
+            );
+            plan.changes
+        });
+        finish_dry_run("rechunking", planned, progress);
+        return;
+    }
+    let mut timing = Timing::start();
+    let mut json = (progress == Some(ProgressFormat::Json)).then(|| {
+        let size = ChunkSet::open(&source, globals().accept_modified)
+            .ok()
+            .map(|set| set.total_size());
+        JsonProgress::new(size.map(|size| size.div_ceil(chunk_size).max(1)))
+    });
+    let operation = interrupt::start();
+    let started = journal::start();
+    steal_lock(&source);
+    let result = rechunk(
+        &options,
+        &mut |event| {
+            timing.record(&event);
+            if let Some(json) = &mut json {
+                json.update(&event);
+            }
+        },
+        &operation.token,
+    );
+    journal::rechunk(started, &options, &result);
+    match result {
+        Ok(report) => {
+            println!(
+                "Rechunked into {} chunks of {} in {}.",
+                report.chunks.len(),
+                format_size(chunk_size),
+                report.destination.display()
+            );
+            println!("{}", timing.summary());
+        }
+        Err(e) => {
+            if let Some(json) = &mut json {
+                json.fail(&e, exit_code(&e));
+            }
+            eprintln!("Error during rechunking: {}", e);
+            exit(exit_code(&e));
+        }
+    }
+}
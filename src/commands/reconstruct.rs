@@ -0,0 +1,370 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::{
+    Auth, ChunkSet, FetchOptions, FetchReport, Normalization, ProgressEvent, ReconstructOptions,
+    ReconstructReport, check_recovery, fetch, is_stream, plan_reconstruct,
+};
+
+#[cfg(feature = "sftp")]
+use crate::SftpArgs;
+use crate::history::History;
+use crate::progress::{FetchProgress, JsonProgress, Timing};
+use crate::{
+    HookArgs, HookCommand, ProgressFormat, RetryArgs, S3Args, exit_code, finish_dry_run, globals,
+    interrupt, is_remote, journal, note_pipe, offer_remap, output_name, parse_hook_command,
+    parse_mode, path_arg, print_trashed, reconstruct_options, set_key, steal_lock, trash,
+    warn_without_mmap,
+};
+
+#[derive(Args)]
+pub(crate) struct ReconstructArgs {
+    /// Directory containing the chunks, or a zip or tar of them, which the output goes
+    /// next to, or the s3://BUCKET/PREFIX or sftp://[USER@]HOST[:PORT]/PATH they were
+    /// written to, which the output comes down from into the current directory
+    #[arg(required_unless_present = "from_url", value_parser = path_arg())]
+    pub(crate) directory: Option<PathBuf>,
+    /// Download the chunks first from this http:// or https:// URL, such as the one
+    /// `serve` prints, into --cache; the file is then put together in the current
+    /// directory. https needs a build with the tls feature
+    #[arg(long, value_name = "URL", conflicts_with = "directory")]
+    pub(crate) from_url: Option<String>,
+    /// Directory the downloaded chunks are kept in until the file is whole, which an
+    /// interrupted download resumes from [default: ORIGINAL.chunks]
+    #[arg(long, value_name = "DIR", requires = "from_url", value_parser = path_arg())]
+    pub(crate) cache: Option<PathBuf>,
+    /// Leave the downloaded chunks in place afterwards
+    #[arg(long, requires = "from_url")]
+    pub(crate) keep_cache: bool,
+    /// Chunks downloaded at once for --from-url, and with the async feature from an
+    /// s3:// or sftp:// directory
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) connections: u64,
+    /// Further attempts at a chunk whose download fails or arrives damaged, at an S3
+    /// request, or at an SFTP chunk whose connection drops, after pauses of 1 s, 2 s,
+    /// 4 s, …, and at opening or reading a chunk file that fails in a way that can
+    /// pass (see --retry-on)
+    #[arg(long, default_value_t = 3)]
+    pub(crate) retries: u32,
+    #[command(flatten)]
+    pub(crate) retrying: RetryArgs,
+    /// Send this bearer token with every request
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        requires = "from_url",
+        conflicts_with = "user"
+    )]
+    pub(crate) bearer_token: Option<String>,
+    /// Send these credentials with every request, with basic authentication
+    #[arg(long, value_name = "USER:PASSWORD", requires = "from_url")]
+    pub(crate) user: Option<String>,
+    /// Send --bearer-token or --user over plain http to a host off the local network,
+    /// which is refused otherwise, as anyone on the way could read them
+    #[arg(long, requires = "from_url")]
+    pub(crate) insecure_auth: bool,
+    #[command(flatten)]
+    pub(crate) s3: S3Args,
+    #[cfg(feature = "sftp")]
+    #[command(flatten)]
+    pub(crate) sftp: SftpArgs,
+    /// Run this for every chunk info.json lists before reconstructing, to fetch it
+    /// into the directory, e.g. "rclone copyto remote:backup/{name} {path}". The
+    /// placeholders, the environment and the splitting into words are as for split's
+    /// --post-chunk-cmd, with FSR_HOOK=pre-chunk
+    #[arg(long, value_name = "COMMAND", value_parser = parse_hook_command, conflicts_with = "from_url")]
+    pub(crate) pre_chunk_cmd: Option<HookCommand>,
+    #[command(flatten)]
+    pub(crate) hook: HookArgs,
+    /// Name of the reconstructed file [default: the original file name]
+    #[arg(short, long)]
+    pub(crate) output: Option<String>,
+    /// Where the reconstructed file goes when the directory it would go in can't be
+    /// written to, as with chunks on a mounted ISO or a write-protected SD card
+    /// [default: the current directory]
+    #[arg(long, value_name = "DIR", value_parser = path_arg())]
+    pub(crate) fallback_dir: Option<PathBuf>,
+    /// Number of threads copying chunks [default: up to 4, depending on the CPU]
+    #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) threads: Option<u64>,
+    /// Write the output through a memory map (needs a build with the mmap feature)
+    #[arg(long)]
+    pub(crate) mmap: bool,
+    /// Don't reserve disk space for the whole output before copying
+    #[arg(long)]
+    pub(crate) sparse: bool,
+    /// Another directory of a file split with --span, to take the chunks DIRECTORY
+    /// doesn't have from; give every other one
+    #[arg(long = "volume", value_name = "DIR", conflicts_with = "from_url", value_parser = path_arg())]
+    pub(crate) volumes: Vec<PathBuf>,
+    /// When run as root: give the file back to the user and group info.json records
+    /// by number, rather than by name where this system knows the name
+    #[arg(long)]
+    pub(crate) numeric_owner: bool,
+    /// Put the recorded file name in this Unicode normalization form, such as nfc for
+    /// a name from macOS to match what Linux tools type, when --output isn't given
+    #[arg(long, value_enum, default_value_t = Normalization::Keep)]
+    pub(crate) normalize: Normalization,
+    /// Permissions for the reconstructed file, in octal, such as 640 (Unix only)
+    #[arg(long, value_name = "MODE", value_parser = parse_mode)]
+    pub(crate) chmod_files: Option<u32>,
+    /// Let no one else read the reconstructed file: --chmod-files 600
+    #[arg(long, conflicts_with = "chmod_files")]
+    pub(crate) private: bool,
+    /// Write over a file already at the output, rather than move it to the trash,
+    /// or into .fsr-trash/ beside it without a desktop, for undo-last to put back
+    #[arg(long)]
+    pub(crate) no_trash: bool,
+    /// Open encrypted chunks with the key in this file rather than asking for the
+    /// passphrase
+    #[arg(long, value_name = "FILE", value_parser = path_arg())]
+    pub(crate) key_file: Option<PathBuf>,
+    /// Use a --key-file that anyone on the system can read
+    #[arg(long, requires = "key_file")]
+    pub(crate) insecure_key_permissions: bool,
+    /// Open chunks encrypted to age recipients with the identities in this file, as
+    /// age-keygen writes it
+    #[arg(long, value_name = "FILE", value_parser = path_arg(), conflicts_with = "key_file")]
+    pub(crate) age_identity: Option<PathBuf>,
+    /// When the chunks info.json lists aren't there but files with their numbers and
+    /// sizes are under another prefix, as after renaming chunk* to part*, record
+    /// those names in info.json without asking
+    #[arg(long)]
+    pub(crate) auto_remap: bool,
+    /// Check everything as for reconstructing, then list the files that would be
+    /// written or moved, with their sizes, without touching any
+    #[arg(long, conflicts_with_all = ["from_url", "pre_chunk_cmd"])]
+    pub(crate) dry_run: bool,
+    /// Report progress on stderr, one JSON object per line
+    #[arg(long, value_enum)]
+    pub(crate) progress: Option<ProgressFormat>,
+}
+
+pub(crate) fn run(args: ReconstructArgs) {
+    let ReconstructArgs {
+        directory,
+        from_url,
+        cache,
+        keep_cache,
+        connections,
+        retries,
+        retrying,
+        bearer_token,
+        insecure_auth,
+        user,
+        s3,
+        #[cfg(feature = "sftp")]
+        sftp,
+        pre_chunk_cmd,
+        hook,
+        output,
+        fallback_dir,
+        threads,
+        mmap,
+        sparse,
+        volumes,
+        numeric_owner,
+        normalize,
+        chmod_files,
+        private,
+        no_trash,
+        key_file,
+        insecure_key_permissions,
+        age_identity,
+        auto_remap,
+        dry_run,
+        progress,
+    } = args;
+    warn_without_mmap(mmap);
+    let mut downloaded = None;
+    let directory = match (directory, from_url) {
+        (Some(directory), _) => directory,
+        (None, url) => {
+            let options = FetchOptions {
+                directory: cache,
+                connections: connections as usize,
+                retries,
+                auth: bearer_token.map(Auth::Bearer).or(user.map(Auth::Basic)),
+                insecure_auth,
+                accept_modified: globals().accept_modified,
+                ..FetchOptions::new(url.unwrap_or_default())
+            };
+            let report = fetch_chunks(&options, progress);
+            let directory = report.directory.clone();
+            downloaded = Some(report);
+            directory
+        }
+    };
+    let key = set_key(
+        &directory,
+        key_file.as_deref(),
+        age_identity.as_deref(),
+        insecure_key_permissions,
+    );
+    let key = key.unwrap_or_else(|e| {
+        eprintln!("Error during reconstruction: {}", e);
+        exit(exit_code(&e));
+    });
+    let options = ReconstructOptions {
+        output,
+        mmap,
+        sparse,
+        key,
+        s3: s3.options(connections, retries),
+        #[cfg(feature = "sftp")]
+        sftp: sftp.options(connections, retries),
+        pre_chunk_cmd: hook.hook(pre_chunk_cmd),
+        volumes,
+        numeric_owner,
+        normalize,
+        file_mode: if private { Some(0o600) } else { chmod_files },
+        fallback_dir,
+        retry: retrying.policy(retries),
+        ..reconstruct_options(&directory, threads)
+    };
+    if let Some(output) = &options.output
+        && is_stream(&directory.join(output))
+    {
+        note_pipe(&directory.join(output), "reads from");
+    }
+    if let Err(e) = offer_remap(&directory, auto_remap, dry_run) {
+        eprintln!("Error during reconstruction: {}", e);
+        exit(exit_code(&e));
+    }
+    if dry_run {
+        let operation = interrupt::start();
+        let planned = plan_reconstruct(&options, &operation.token).and_then(|plan| {
+            let recovered = check_recovery(&options, &operation.token)?;
+            if !recovered.is_empty() {
+                println!("Would rebuild from the parity: {}", recovered.join(", "));
+            }
+            trash::planned_changes(&plan, !no_trash)
+        });
+        finish_dry_run("reconstruction", planned, progress);
+        return;
+    }
+    let mut timing = Timing::start();
+    let mut json = (progress == Some(ProgressFormat::Json)).then(|| {
+        let chunks = ChunkSet::open(&directory, globals().accept_modified)
+            .ok()
+            .map(|set| set.len() as u64);
+        JsonProgress::new(chunks)
+    });
+    let operation = interrupt::start();
+    let started = journal::start();
+    steal_lock(&directory);
+    let (result, trashed) = trash::reconstruct_keeping(
+        &options,
+        !no_trash,
+        &mut |event| {
+            timing.record(&event);
+            if let Some(json) = &mut json {
+                json.update(&event);
+            }
+        },
+        &operation.token,
+    );
+    journal::reconstruct(started, &options, &result);
+    match result {
+        Ok(report) => {
+            print_trashed(trashed.as_ref());
+            match &downloaded {
+                Some(downloaded) => take_download(&report, downloaded, keep_cache),
+                None => {
+                    if !is_remote(&directory) {
+                        History::record_directory(&directory);
+                    }
+                    println!(
+                        "Reconstructed file saved as \"{}\".",
+                        output_name(&report, &directory)
+                    );
+                }
+            }
+            println!("{}", timing.summary());
+        }
+        Err(e) => {
+            if let Some(json) = &mut json {
+                json.fail(&e, exit_code(&e));
+            }
+            eprintln!("Error during reconstruction: {}", e);
+            exit(exit_code(&e));
+        }
+    }
+}
+
+// Download the chunks for `reconstruct --from-url`, exiting if that fails. What was
+// downloaded stays, for running the same command again to resume.
+fn fetch_chunks(options: &FetchOptions, progress: Option<ProgressFormat>) -> FetchReport {
+    let mut json = (progress == Some(ProgressFormat::Json)).then(|| JsonProgress::new(None));
+    let mut status = FetchProgress::new();
+    // Only then is there anything to resume
+    let mut started = false;
+    let operation = interrupt::start();
+    let result = fetch(
+        options,
+        &mut |event| {
+            started |= matches!(event, ProgressEvent::ChunkStarted { .. });
+            match &mut json {
+                Some(json) => json.update(&event),
+                None => status.update(&event),
+            }
+        },
+        &operation.token,
+    );
+    status.finish();
+    match result {
+        Ok(report) => {
+            if !report.cached.is_empty() {
+                println!(
+                    "{} of {} chunks were already in {}.",
+                    report.cached.len(),
+                    report.cached.len() + report.downloaded.len(),
+                    report.directory.display()
+                );
+            }
+            report
+        }
+        Err(e) => {
+            if let Some(json) = &mut json {
+                json.fail(&e, exit_code(&e));
+            }
+            eprintln!("Error downloading the chunks: {}", e);
+            if started {
+                eprintln!("Run the same command again to resume.");
+            }
+            exit(exit_code(&e));
+        }
+    }
+}
+
+// The file reconstructed from downloaded chunks is made inside their directory; move it
+// to the current one, and unless asked to keep them, remove the chunks if the download
+// put them there.
+fn take_download(report: &ReconstructReport, downloaded: &FetchReport, keep_cache: bool) {
+    let target = PathBuf::from(report.output.file_name().unwrap_or_default());
+    if let Err(e) = fs::rename(&report.output, &target) {
+        eprintln!(
+            "Cannot move the file out of {}: {}",
+            downloaded.directory.display(),
+            e
+        );
+        println!(
+            "Reconstructed file saved as \"{}\".",
+            report.output.display()
+        );
+        return;
+    }
+    println!("Reconstructed file saved as \"{}\".", target.display());
+    if downloaded.created
+        && !keep_cache
+        && let Err(e) = fs::remove_dir_all(&downloaded.directory)
+    {
+        eprintln!(
+            "Cannot remove the downloaded chunks in {}: {}",
+            downloaded.directory.display(),
+            e
+        );
+    }
+}
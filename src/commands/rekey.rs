@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::size::format_size;
+use reconstruct_large_file::{Manifest, RekeyOptions, SplitterError, rekey};
+
+use crate::progress::{JsonProgress, SplitProgress};
+use crate::{
+    ProgressFormat, chunk_files, exit_code, globals, interrupt, new_key, path_arg, set_key,
+    steal_lock,
+};
+
+#[derive(Args)]
+pub(crate) struct RekeyArgs {
+    /// Directory containing the encrypted chunks and their info.json
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+    /// Open the chunks with the key in this file rather than asking for the
+    /// passphrase
+    #[arg(long, value_name = "FILE", value_parser = path_arg())]
+    pub(crate) key_file: Option<PathBuf>,
+    /// Open chunks encrypted to age recipients with the identities in this file
+    #[arg(long, value_name = "FILE", value_parser = path_arg(), conflicts_with = "key_file")]
+    pub(crate) age_identity: Option<PathBuf>,
+    /// Encrypt the chunks under the key in this file (see keygen) [default: a new
+    /// passphrase, asked for twice]
+    #[arg(long, value_name = "FILE", value_parser = path_arg())]
+    pub(crate) new_key_file: Option<PathBuf>,
+    /// Encrypt the chunks to this age recipient (age1...); repeat it for several
+    #[arg(long, value_name = "RECIPIENT", conflicts_with = "new_key_file")]
+    pub(crate) new_age_recipient: Vec<String>,
+    /// Use a --key-file or --new-key-file that anyone on the system can read
+    #[arg(long)]
+    pub(crate) insecure_key_permissions: bool,
+    /// Only check that the current key opens every chunk, reading them all, without
+    /// asking for a new key or writing anything
+    #[arg(long)]
+    pub(crate) dry_run: bool,
+    /// Report progress on stderr, one JSON object per line
+    #[arg(long, value_enum)]
+    pub(crate) progress: Option<ProgressFormat>,
+}
+
+pub(crate) fn run(args: RekeyArgs) {
+    let RekeyArgs {
+        directory,
+        key_file,
+        age_identity,
+        new_key_file,
+        new_age_recipient,
+        insecure_key_permissions,
+        dry_run,
+        progress,
+    } = args;
+    let fail = |e: SplitterError| -> ! {
+        eprintln!("Error during rekeying: {}", e);
+        exit(exit_code(&e));
+    };
+    let old = set_key(
+        &directory,
+        key_file.as_deref(),
+        age_identity.as_deref(),
+        insecure_key_permissions,
+    );
+    let Some(old) = old.unwrap_or_else(|e| fail(e)) else {
+        fail(SplitterError::InvalidOption {
+            field: "directory",
+            reason: "holds a set that isn't encrypted, so has no key to change",
+        });
+    };
+    let current = Manifest::load(&directory, globals().accept_modified)
+        .ok()
+        .flatten()
+        .and_then(|manifest| manifest.encryption);
+    let (encryption, new) = match (dry_run, current) {
+        (true, Some(current)) => (current, old.clone()),
+        _ => new_key(
+            "rekeying",
+            new_key_file.as_deref(),
+            &new_age_recipient,
+            insecure_key_permissions,
+        ),
+    };
+    let options = RekeyOptions {
+        dry_run,
+        accept_modified: globals().accept_modified,
+        ..RekeyOptions::new(&directory, encryption)
+    };
+    let (total, count) = chunk_files(&directory);
+    let passes = if dry_run { 1 } else { 2 };
+    let mut status = SplitProgress::counted(total * passes, count * passes);
+    let mut json =
+        (progress == Some(ProgressFormat::Json)).then(|| JsonProgress::new(Some(count * passes)));
+    let operation = interrupt::start();
+    steal_lock(&directory);
+    let result = rekey(
+        &options,
+        &old,
+        &new,
+        &mut |event| match &mut json {
+            Some(json) => json.update(&event),
+            None => status.update(&event),
+        },
+        &operation.token,
+    );
+    if json.is_none() {
+        status.finish();
+    }
+    match result {
+        Ok(report) if report.dry_run => println!(
+            "The key opens all {} chunks ({}) in {}; nothing was written.",
+            report.chunks,
+            format_size(report.stored_size),
+            directory.display()
+        ),
+        Ok(report) => {
+            println!(
+                "Encrypted the {} chunks in {} again: {} read, {} written.",
+                report.chunks,
+                directory.display(),
+                format_size(report.stored_size),
+                format_size(report.rewritten_size)
+            );
+            match (
+                &report.encryption.fingerprint,
+                report.encryption.recipients.len(),
+            ) {
+                (Some(fingerprint), _) => {
+                    println!("info.json now records key fingerprint {}.", fingerprint)
+                }
+                (None, recipients) => println!(
+                    "info.json now records {} age {}.",
+                    recipients,
+                    if recipients == 1 {
+                        "recipient"
+                    } else {
+                        "recipients"
+                    }
+                ),
+            }
+        }
+        Err(e) => {
+            if let Some(json) = &mut json {
+                json.fail(&e, exit_code(&e));
+            }
+            fail(e);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::{SplitterError, repair};
+
+use crate::progress::JsonProgress;
+use crate::{
+    ProgressFormat, exit_code, globals, interrupt, journal, path_arg, print_changes, steal_lock,
+};
+
+#[derive(Args)]
+pub(crate) struct RepairArgs {
+    /// Directory containing the chunks
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+    /// Only list what would be rebuilt, and the files that would be written with their
+    /// sizes
+    #[arg(long)]
+    pub(crate) dry_run: bool,
+    /// Report progress on stderr, one JSON object per line
+    #[arg(long, value_enum)]
+    pub(crate) progress: Option<ProgressFormat>,
+}
+
+pub(crate) fn run(args: RepairArgs) {
+    let RepairArgs {
+        directory,
+        dry_run,
+        progress,
+    } = args;
+    let mut json = (progress == Some(ProgressFormat::Json)).then(|| JsonProgress::new(None));
+    if !dry_run {
+        steal_lock(&directory);
+    }
+    let operation = interrupt::start();
+    let started = journal::start();
+    let mut update = |event| {
+        if let Some(json) = &mut json {
+            json.update(&event);
+        }
+    };
+    let result = repair(
+        &directory,
+        dry_run,
+        globals().accept_modified,
+        &mut update,
+        &operation.token,
+    );
+    journal::repair(started, &directory, dry_run, &result);
+    if let (Some(json), Ok(report)) = (&mut json, &result)
+        && report.dry_run
+    {
+        json.planned(&report.changes);
+    }
+    match result {
+        Ok(report) if report.is_empty() => println!("Nothing to repair."),
+        Ok(report) => {
+            let (chunks, parity) = match report.dry_run {
+                true => ("Would rebuild", "Would compute again"),
+                false => ("Rebuilt", "Computed again"),
+            };
+            if !report.par2.is_empty() {
+                println!("{} from the PAR2 files: {}", chunks, report.par2.join(", "));
+            }
+            if !report.rebuilt.is_empty() {
+                println!("{} from the parity: {}", chunks, report.rebuilt.join(", "));
+            }
+            if !report.parity.is_empty() {
+                println!("{} from the chunks: {}", parity, report.parity.join(", "));
+            }
+            if report.dry_run {
+                print_changes(&report.changes);
+            }
+        }
+        Err(e) => {
+            if let Some(json) = &mut json {
+                json.fail(&e, exit_code(&e));
+            }
+            eprintln!("Error during repair: {}", e);
+            if let SplitterError::MissingChunks { .. } = e {
+                eprintln!("Those are more than the parity can rebuild.");
+            }
+            exit(exit_code(&e));
+        }
+    }
+}
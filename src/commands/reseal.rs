@@ -0,0 +1,38 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::manifest::Seal;
+use reconstruct_large_file::{MANIFEST_NAME, reseal};
+
+use crate::{exit_code, path_arg, steal_lock};
+
+#[derive(Args)]
+pub(crate) struct ResealArgs {
+    /// Directory containing the chunks and their info.json
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+}
+
+pub(crate) fn run(args: ResealArgs) {
+    let ResealArgs { directory } = args;
+    steal_lock(&directory);
+    match reseal(&directory) {
+        Ok(Seal::Intact) => println!(
+            "{} is unchanged since it was sealed.",
+            directory.join(MANIFEST_NAME).display()
+        ),
+        Ok(Seal::Broken) => println!(
+            "Resealed {} over the changes made to it.",
+            directory.join(MANIFEST_NAME).display()
+        ),
+        Ok(Seal::Absent) => println!(
+            "Sealed {}, which had no seal.",
+            directory.join(MANIFEST_NAME).display()
+        ),
+        Err(e) => {
+            eprintln!("Error during resealing: {}", e);
+            exit(exit_code(&e));
+        }
+    }
+}
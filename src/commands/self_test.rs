@@ -0,0 +1,52 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::size::parse_size;
+
+use crate::{interrupt, path_arg, selftest, thread_count};
+
+#[derive(Args)]
+pub(crate) struct SelfTestArgs {
+    /// Directory to run the test in [default: the system's temporary directory]
+    #[arg(long, value_parser = path_arg())]
+    pub(crate) dir: Option<PathBuf>,
+    /// Size of the generated file, e.g. 64MiB or 1GiB
+    #[arg(long, value_parser = parse_size, default_value = "256MiB")]
+    pub(crate) size: u64,
+    /// Seed of the generated data; the same seed gives the same file
+    #[arg(long, default_value_t = 1)]
+    pub(crate) seed: u64,
+    /// Number of threads splitting and reconstructing [default: up to 4]
+    #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) threads: Option<u64>,
+}
+
+pub(crate) fn run(args: SelfTestArgs) {
+    let SelfTestArgs {
+        dir,
+        size,
+        seed,
+        threads,
+    } = args;
+    let options = selftest::SelfTestOptions {
+        directory: dir.unwrap_or_else(env::temp_dir),
+        size,
+        seed,
+        threads: thread_count(threads),
+    };
+    let operation = interrupt::start();
+    match selftest::run(&options, &operation.token) {
+        Ok(true) => {}
+        Ok(false) => exit(1),
+        Err(_) if operation.token.is_cancelled() => {
+            eprintln!("Self-test interrupted.");
+            exit(interrupt::EXIT_CODE);
+        }
+        Err(e) => {
+            eprintln!("Self-test failed: {}", e);
+            exit(1);
+        }
+    }
+}
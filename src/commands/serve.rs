@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::ChunkSet;
+
+use crate::{exit_code, globals, interrupt, path_arg, serve};
+
+#[derive(Args)]
+pub(crate) struct ServeArgs {
+    /// Directory containing the chunks
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+    /// Address to listen on; the default is every address of this machine
+    #[arg(long, default_value = "0.0.0.0")]
+    pub(crate) address: std::net::IpAddr,
+    /// Port to listen on; 0 picks a free one
+    #[arg(short, long, default_value_t = 8080)]
+    pub(crate) port: u16,
+}
+
+pub(crate) fn run(args: ServeArgs) {
+    let ServeArgs {
+        directory,
+        address,
+        port,
+    } = args;
+    if !directory.is_dir() {
+        eprintln!("{} is not a directory of chunks.", directory.display());
+        exit(2);
+    }
+    let set = match ChunkSet::open(&directory, globals().accept_modified) {
+        Ok(set) => set,
+        Err(e) => {
+            eprintln!("Cannot serve {}: {}", directory.display(), e);
+            exit(exit_code(&e));
+        }
+    };
+    let options = serve::ServeOptions {
+        directory,
+        address,
+        port,
+    };
+    let operation = interrupt::start();
+    if let Err(e) = serve::run(&options, &set, &operation.token) {
+        eprintln!("Server failed: {}", e);
+        exit(1);
+    }
+}
@@ -0,0 +1,577 @@
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::manifest::{Compression, HashAlgorithm, MAX_PARITY_SHARDS, Parity};
+use reconstruct_large_file::size::parse_size;
+use reconstruct_large_file::{
+    ChunkHook, Compat, Container, DEFAULT_CHUNK_SIZE, DEFAULT_MAX_DIR_FILES,
+    DEFAULT_MIN_CHUNK_SIZE, DEFAULT_MIN_RATIO, DEFAULT_SPAN_MARGIN, MirrorFailure, ShardDirs, Span,
+    SplitOptions, ZIP_EXTENSION, absolute_path, containing_set, is_stream, split_file,
+};
+
+#[cfg(feature = "sftp")]
+use crate::SftpArgs;
+use crate::history::History;
+use crate::progress::{JsonProgress, Timing};
+use crate::prompt::{self, confirm};
+use crate::template::{Template, TemplateParser};
+use crate::{
+    HookArgs, HookCommand, ProgressFormat, RetryArgs, S3Args, default_savedir, exit_code,
+    interrupt, is_remote, journal, link_note, load_profile, long_window, nested_warning, new_key,
+    note_pipe, parse_compression, parse_hook_command, parse_mode, path_arg, print_input_changed,
+    print_volumes, shared_disk_warning, single_chunk_note, split_options, steal_lock, suffixed,
+    warn_without_mmap,
+};
+
+#[derive(Args)]
+pub(crate) struct SplitArgs {
+    /// File to split
+    #[arg(value_parser = path_arg())]
+    pub(crate) input: PathBuf,
+    /// Directory to save the chunks in, s3://BUCKET/PREFIX to upload them to, or
+    /// sftp://[USER@]HOST[:PORT]/PATH to write them to a directory on that server
+    /// (needs a build with the sftp feature), which can be a template with variables
+    /// such as /archive/{year}/{month}/{name}.{ext}.split (see --help) [default:
+    /// ./<file name>.chunks]
+    ///
+    /// Variables: {filename}, {name} and {stem}, the file's name without its last or
+    /// all extensions; {ext}, its last extension; {parent}, the name of the directory
+    /// it is in; {year}, {month}, {day}, {hour}, {minute} and {date} of the split, in
+    /// UTC; {size}, the first of 1KiB, 10KiB, 100KiB, 1MiB, … the file is smaller
+    /// than; {hash}, 8 hex digits of a SHA-256 of its first MiB. Write {{ and }} for
+    /// braces themselves.
+    #[arg(short, long, value_parser = TemplateParser)]
+    pub(crate) dest: Option<Template>,
+    /// Take whatever isn't given here from this profile in the config file, such as
+    /// one the interactive split saved; the destination is then a new directory in
+    /// the profile's (see the profile command)
+    #[arg(long, value_name = "NAME")]
+    pub(crate) profile: Option<String>,
+    /// Size of each chunk, e.g. 500K, 5MiB or 1GB [default: 5MiB]
+    #[arg(short = 's', long, value_parser = parse_size)]
+    pub(crate) chunk_size: Option<u64>,
+    /// Cut FILE into this many chunks of about the same size instead of giving a
+    /// --chunk-size (not for a pipe, whose size isn't known)
+    #[arg(long, value_name = "N", conflicts_with = "chunk_size")]
+    pub(crate) parts: Option<u64>,
+    /// Refuse a --chunk-size below this, which most likely had its unit left off
+    /// [default: 4KiB]
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub(crate) min_chunk_size: Option<u64>,
+    /// Take a --chunk-size as small as one byte, as for testing, however many files
+    /// that makes
+    #[arg(long, conflicts_with = "min_chunk_size")]
+    pub(crate) i_know_what_im_doing: bool,
+    /// Split only FILE from this byte on, e.g. 4096 or 2GiB, for a set that joins into
+    /// that extract of it, named for the range it is of
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub(crate) input_offset: Option<u64>,
+    /// Split only this much of FILE, e.g. 100MiB, from --input-offset or its start
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub(crate) input_length: Option<u64>,
+    /// Number of threads writing chunks [default: up to 4, depending on the CPU]
+    #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) threads: Option<u64>,
+    /// Record a hash of every chunk in info.json
+    #[arg(long, value_enum)]
+    pub(crate) hash: Option<HashAlgorithm>,
+    /// Keep one file for chunks with the same bytes, such as the runs of zeros in a
+    /// disk image, listing the others in info.json as stored in it (needs --hash and
+    /// a file to read; older versions of this tool can't reconstruct such a set)
+    #[arg(long, requires = "hash", conflicts_with_all = ["parity", "par2"])]
+    pub(crate) dedup: bool,
+    /// Read the file through a memory map (needs a build with the mmap feature)
+    #[arg(long)]
+    pub(crate) mmap: bool,
+    /// Leave the chunks written so far in place if the split fails or is interrupted
+    #[arg(long)]
+    pub(crate) keep_partial: bool,
+    /// Overwrite the chunks removed when the split fails or is interrupted with zeros
+    /// before removing them: a single pass, which does nothing on an SSD, a
+    /// copy-on-write file system or in a snapshot or backup
+    #[arg(long, conflicts_with = "keep_partial")]
+    pub(crate) shred: bool,
+    /// Flush every file written, and the directory, to disk before the split counts as
+    /// done, so a crash or power cut right after doesn't lose the set
+    #[arg(long)]
+    pub(crate) fsync: bool,
+    /// Split a file that is a piece of an existing split, such as one of its chunks,
+    /// without asking; it is otherwise refused, or asked about on a terminal
+    #[arg(long)]
+    pub(crate) allow_nested: bool,
+    /// Chunks held in memory at once when compressing on several threads, each the
+    /// chunk size; fewer to stay within --max-memory [default: twice the threads]
+    #[arg(long, value_name = "CHUNKS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) in_flight: Option<u64>,
+    /// Compress every chunk: gzip or gzip:LEVEL (1-9, default 6), zstd or zstd:LEVEL
+    /// (1-22, default 3), or none
+    #[arg(long, value_name = "CODEC[:LEVEL]", value_parser = parse_compression)]
+    pub(crate) compress: Option<(Compression, u32)>,
+    /// With --compress zstd, match over a 128 MiB window, as zstd --long, for files
+    /// with repeats far apart; reading the chunks back takes as much memory
+    #[arg(long, requires = "compress")]
+    pub(crate) long: bool,
+    /// Write every chunk as base64 text, chunk000.txt, …, for channels that only
+    /// carry text, such as email bodies or tickets; about a third larger
+    #[arg(long, conflicts_with = "compress")]
+    pub(crate) armor: bool,
+    /// Store chunks that compress by less than this ratio uncompressed; 0 compresses all
+    #[arg(long, value_name = "RATIO", default_value_t = DEFAULT_MIN_RATIO)]
+    pub(crate) min_ratio: f64,
+    /// Also write parity to rebuild lost or damaged chunks from: xor covers any one
+    /// chunk, rs:K any K chunks of each stripe of up to 256 chunks and parity files
+    #[arg(long, value_name = "xor|rs:K", value_parser = parse_parity)]
+    pub(crate) parity: Option<Parity>,
+    /// Also write every chunk, and info.json, to this directory, which must be empty
+    #[arg(long, value_name = "DIR", value_parser = path_arg())]
+    pub(crate) mirror: Option<PathBuf>,
+    /// What a failure to write the mirror does: warn and carry on without it, or abort
+    #[arg(long, value_enum, default_value_t = MirrorFailure::Warn, requires = "mirror")]
+    pub(crate) mirror_failure: MirrorFailure,
+    /// Also write PAR2 recovery files over the chunks and info.json, this percentage
+    /// of their size, for repair with any PAR2 client. It guards against the same
+    /// losses as --parity, which is quicker to write but spends a whole chunk on every
+    /// damaged one, so one of the two is usually enough
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u32).range(1..=100))]
+    pub(crate) par2: Option<u32>,
+    /// Give chunks random names, so only info.json knows their order (splits are then
+    /// not reproducible)
+    #[arg(long)]
+    pub(crate) random_names: bool,
+    /// Name chunks PREFIX000, PREFIX001, … rather than chunk000, …: a letter, then
+    /// letters, digits, - or _, not ending in a digit. Only info.json then knows the
+    /// chunks, so older versions of this tool can't reconstruct such a set
+    #[arg(long, value_name = "PREFIX", conflicts_with_all = ["random_names", "compat"])]
+    pub(crate) prefix: Option<String>,
+    /// Name chunks so they can be joined without this tool: split names them
+    /// FILE.partaa, FILE.partab, … for `cat FILE.part* > FILE`, hjsplit FILE.001,
+    /// FILE.002, … for HJSplit, 7-Zip or `cat FILE.* > FILE`. Chunks are stored
+    /// uncompressed
+    #[arg(long, value_enum, value_name = "split|hjsplit")]
+    pub(crate) compat: Option<Compat>,
+    /// Don't write info.json, leaving only the chunks; their names still give the
+    /// order when reconstructing
+    #[arg(long, requires = "compat")]
+    pub(crate) no_info: bool,
+    /// Also write JOIN.sh and JOIN.bat, which put the file back together with cat or
+    /// copy /b for someone without this tool; JOIN.sh checks the result's SHA-256
+    #[arg(long)]
+    pub(crate) join_scripts: bool,
+    /// Write the chunks and info.json into one zip archive rather than a directory, as
+    /// --dest or INPUT.fsrchunks.zip, for transfers that only take a single file
+    #[arg(long, value_enum, default_value_t = Container::Directory)]
+    pub(crate) container: Container,
+    #[command(flatten)]
+    pub(crate) s3: S3Args,
+    /// Chunks, or parts of large ones, uploaded at once to an s3:// destination, and
+    /// with the async feature chunks written at once to an sftp:// one
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) connections: u64,
+    /// Further attempts at an S3 request that fails, or at an SFTP chunk whose
+    /// connection drops, after pauses of 1 s, 2 s, 4 s, …, and at creating or writing
+    /// a chunk file that fails in a way that can pass (see --retry-on)
+    #[arg(long, default_value_t = 3)]
+    pub(crate) retries: u32,
+    #[command(flatten)]
+    pub(crate) retrying: RetryArgs,
+    #[cfg(feature = "sftp")]
+    #[command(flatten)]
+    pub(crate) sftp: SftpArgs,
+    /// Run this for every chunk as soon as it is written, e.g. "rclone copy {path}
+    /// remote:backup", with {path}, {name}, {index} (from 0) and {total} replaced. It
+    /// is split into words at spaces, with quotes around any that hold one, and run
+    /// without a shell. It also finds the chunk in FSR_CHUNK_PATH, FSR_CHUNK_NAME,
+    /// FSR_CHUNK_INDEX and FSR_CHUNK_TOTAL, and FSR_HOOK=post-chunk. The chunk must
+    /// still be there when it exits; info.json is written once every one has
+    #[arg(long, value_name = "COMMAND", value_parser = parse_hook_command)]
+    pub(crate) post_chunk_cmd: Option<HookCommand>,
+    #[command(flatten)]
+    pub(crate) hook: HookArgs,
+    /// Mark each chunk uploaded in transfer_state.json once the chunk command succeeds
+    /// for it, and failed once it has failed its last run, for status and next
+    #[arg(long, requires = "post_chunk_cmd")]
+    pub(crate) track_transfers: bool,
+    /// Once --dest has no room for another chunk, go on into this directory, such as
+    /// a second USB drive, and so on for as many as are given. Each gets as many
+    /// chunks as its free space holds, and an info.json saying which went where
+    #[arg(long, value_name = "DIR", value_parser = path_arg())]
+    pub(crate) span: Vec<PathBuf>,
+    /// With --span, one chunk per directory, as large as its free space holds, rather
+    /// than chunks of --chunk-size (FAT32 drives take no file of 4 GiB or more)
+    #[arg(long, requires = "span")]
+    pub(crate) fit: bool,
+    /// With --span, room to leave free in every directory [default: 16MiB]
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, requires = "span")]
+    pub(crate) span_margin: Option<u64>,
+    /// What to do when the split would put more than --max-dir-files chunks in one
+    /// directory, which many file systems grow slow with and FAT32 stops at 65,534:
+    /// warn, put them that many to a subdirectory (00/, 01/, …) recorded in info.json,
+    /// or refuse
+    #[arg(long, value_enum, default_value_t = ShardDirs::Warn)]
+    pub(crate) shard_dirs: ShardDirs,
+    /// Chunks to put in one directory before --shard-dirs applies
+    #[arg(long, value_name = "COUNT", default_value_t = DEFAULT_MAX_DIR_FILES as u64, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) max_dir_files: u64,
+    /// Fail, removing what was written, if the file changes while it is split,
+    /// rather than warn
+    #[arg(long)]
+    pub(crate) strict: bool,
+    /// Record the file's extended attributes in info.json, such as Finder tags on macOS
+    /// or user.* and security.* ones on Linux, for reconstruct to put back
+    #[arg(long)]
+    pub(crate) xattrs: bool,
+    /// Record in info.json when the split started and when it wrote each chunk, for
+    /// verify --timestamps to point out chunks changed since; info.json then differs
+    /// from one split of the same file to the next
+    #[arg(long)]
+    pub(crate) record_times: bool,
+    /// Permissions for the chunks, info.json and the other files written, in octal,
+    /// such as 640 for a directory shared with a group (Unix only)
+    #[arg(long, value_name = "MODE", value_parser = parse_mode)]
+    pub(crate) chmod_files: Option<u32>,
+    /// Permissions for the directory the chunks go in, in octal, such as 750 (Unix only)
+    #[arg(long, value_name = "MODE", value_parser = parse_mode)]
+    pub(crate) chmod_dirs: Option<u32>,
+    /// Let no one else read what is written: --chmod-files 600 --chmod-dirs 700
+    #[arg(long, conflicts_with_all = ["chmod_files", "chmod_dirs"])]
+    pub(crate) private: bool,
+    /// Encrypt every chunk with AES-256-GCM under a key from a passphrase, asked for
+    /// twice, which reconstructing then asks for; verify still checks the chunks
+    /// without it (needs a build with the encrypt feature)
+    #[arg(long, group = "encryption")]
+    pub(crate) encrypt: bool,
+    /// Encrypt every chunk as --encrypt does, under the key in this file (see keygen)
+    /// rather than one from a passphrase
+    #[arg(
+        long,
+        value_name = "FILE",
+        value_parser = path_arg(),
+        conflicts_with = "encrypt",
+        group = "encryption"
+    )]
+    pub(crate) key_file: Option<PathBuf>,
+    /// Use a --key-file that anyone on the system can read
+    #[arg(long, requires = "key_file")]
+    pub(crate) insecure_key_permissions: bool,
+    /// Encrypt every chunk to this age recipient (age1...), as a plain age file that
+    /// `age -d` decrypts alone; repeat it for several (needs a build with the age
+    /// feature)
+    #[arg(
+        long,
+        value_name = "RECIPIENT",
+        conflicts_with_all = ["encrypt", "key_file"],
+        group = "encryption"
+    )]
+    pub(crate) age_recipient: Vec<String>,
+    /// With the chunks encrypted, seal the file's name, its size, the chunks' hashes
+    /// and the rest of info.json under the same key, leaving only what verify and
+    /// asking for the key need
+    #[arg(long, requires = "encryption")]
+    pub(crate) encrypt_metadata: bool,
+    /// Report progress on stderr, one JSON object per line
+    #[arg(long, value_enum)]
+    pub(crate) progress: Option<ProgressFormat>,
+}
+
+pub(crate) fn run(args: SplitArgs) {
+    let SplitArgs {
+        input,
+        dest,
+        profile,
+        chunk_size,
+        parts,
+        min_chunk_size,
+        i_know_what_im_doing,
+        input_offset,
+        input_length,
+        threads,
+        hash,
+        dedup,
+        mmap,
+        keep_partial,
+        shred,
+        fsync,
+        allow_nested,
+        in_flight,
+        compress,
+        long,
+        armor,
+        min_ratio,
+        random_names,
+        prefix,
+        parity,
+        mirror,
+        mirror_failure,
+        par2,
+        compat,
+        no_info,
+        join_scripts,
+        container,
+        s3,
+        connections,
+        retries,
+        retrying,
+        #[cfg(feature = "sftp")]
+        sftp,
+        post_chunk_cmd,
+        hook,
+        track_transfers,
+        span,
+        fit,
+        span_margin,
+        shard_dirs,
+        max_dir_files,
+        strict,
+        xattrs,
+        record_times,
+        chmod_files,
+        chmod_dirs,
+        private,
+        encrypt,
+        key_file,
+        insecure_key_permissions,
+        age_recipient,
+        encrypt_metadata,
+        progress,
+    } = args;
+    warn_without_mmap(mmap);
+    let encrypt = encrypt || key_file.is_some();
+    if encrypt && !cfg!(feature = "encrypt") {
+        eprintln!("Error during splitting: encrypting needs a build with the encrypt feature");
+        exit(2);
+    }
+    if !age_recipient.is_empty() && !cfg!(feature = "age") {
+        eprintln!("Error during splitting: --age-recipient needs a build with the age feature");
+        exit(2);
+    }
+    let encrypt = encrypt || !age_recipient.is_empty();
+    if let Some(note) = link_note(&input) {
+        println!("{}", note);
+    }
+    let streamed = is_stream(&input);
+    let profile = profile.map(|name| load_profile(&name)).unwrap_or_default();
+    let chunk_size = chunk_size.or(profile.chunk_size);
+    let hash = hash.or(profile.hash);
+    let compat = compat.or(profile.compat);
+    let join_scripts = join_scripts || profile.join_scripts;
+    let compress = long_window(compress, long);
+    let (compress, armor) = match (compress, armor, profile.compression) {
+        (None, false, Some(Compression::Armor)) => (None, true),
+        (None, false, Some(compression)) => {
+            (Some((compression, profile.level().unwrap_or(0))), false)
+        }
+        (compress, armor, _) => (compress, armor),
+    };
+    let default = match container {
+        Container::Directory => default_savedir(&input),
+        Container::Zip => default_archive(&input),
+    };
+    let savedir = absolute_path(&match &dest {
+        Some(template) => expand_dest(template, &input),
+        None => profile.savedir(&default).unwrap_or(default),
+    });
+    let builder = split_options(SplitOptions::builder(&input, &savedir), threads);
+    let builder = match parts {
+        Some(parts) => builder.parts(Some(parts)),
+        None => builder.chunk_size(chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE)),
+    };
+    let options = builder
+        .min_chunk_size(match i_know_what_im_doing {
+            true => 0,
+            false => min_chunk_size.unwrap_or(DEFAULT_MIN_CHUNK_SIZE),
+        })
+        .hash(hash)
+        .dedup(dedup)
+        .mmap(mmap)
+        .keep_partial(keep_partial)
+        .shred(shred)
+        .fsync(fsync)
+        .allow_nested(allow_nested || split_nested(&input))
+        .min_ratio(min_ratio)
+        .random_names(random_names)
+        .prefix(prefix)
+        .parity(parity)
+        .mirror(mirror)
+        .mirror_failure(mirror_failure)
+        .par2(par2)
+        .compat(compat)
+        .no_manifest(no_info)
+        .join_scripts(join_scripts)
+        .container(container)
+        .s3(s3.options(connections, retries))
+        .retry(retrying.policy(retries))
+        .post_chunk_cmd(hook.hook(post_chunk_cmd).map(|hook| ChunkHook {
+            track_transfers,
+            ..hook
+        }))
+        .span((!span.is_empty()).then(|| Span {
+            volumes: span,
+            margin: span_margin.unwrap_or(DEFAULT_SPAN_MARGIN),
+            fit,
+        }))
+        .shard_dirs(shard_dirs)
+        .max_dir_files(max_dir_files as usize)
+        .strict(strict)
+        .xattrs(xattrs)
+        .record_times(record_times)
+        .file_mode(if private { Some(0o600) } else { chmod_files })
+        .dir_mode(if private { Some(0o700) } else { chmod_dirs })
+        .in_flight(in_flight.map_or(0, |n| n as usize))
+        .input_offset(input_offset.unwrap_or(0))
+        .input_length(input_length);
+    #[cfg(feature = "sftp")]
+    let options = options.sftp(sftp.options(connections, retries));
+    let options = match compress {
+        Some((compression, level)) => options.compression(compression).compression_level(level),
+        None if armor => options.compression(Compression::Armor),
+        None => options,
+    };
+    let options = match encrypt {
+        true => {
+            let (encryption, key) = new_key(
+                "splitting",
+                key_file.as_deref(),
+                &age_recipient,
+                insecure_key_permissions,
+            );
+            options
+                .encryption(Some(encryption), Some(key))
+                .encrypt_metadata(encrypt_metadata)
+        }
+        false => options,
+    };
+    let options = options.build().unwrap_or_else(|e| {
+        eprintln!("Error during splitting: {}", e);
+        exit(exit_code(&e));
+    });
+    // Of the range with --input-offset or --input-length
+    let size = options.input_len().unwrap_or_else(|e| {
+        eprintln!("Error during splitting: {}", e);
+        exit(exit_code(&e));
+    });
+    if streamed {
+        note_pipe(&input, "writes into");
+    } else if chunk_size.is_some()
+        && !fit
+        && let Some(size) = size
+        && let Some(note) = single_chunk_note(size, options.chunk_size)
+    {
+        eprintln!("Warning: {}", note);
+    }
+    if options.span.is_none()
+        && let Some(warning) = shared_disk_warning(&options)
+    {
+        eprintln!(
+            "Warning: {} Free some up, give --dest on another disk, or spread the \
+             chunks over several with --span.",
+            warning
+        );
+    }
+    if shred {
+        eprintln!(
+            "Warning: --shred overwrites what a failed split removes once with zeros. \
+             That doesn't reach the old contents on an SSD or flash drive, on a \
+             copy-on-write file system such as Btrfs, ZFS or APFS, or in a snapshot \
+             or backup; use full-disk encryption where those matter."
+        );
+    }
+    let mut timing = Timing::start();
+    let mut json = (progress == Some(ProgressFormat::Json)).then(|| {
+        let chunks = size.unwrap_or(0).div_ceil(options.chunk_size).max(1);
+        JsonProgress::new((!streamed).then_some(chunks))
+    });
+    let operation = interrupt::start();
+    let started = journal::start();
+    if dest.as_ref().is_some_and(Template::has_variables) {
+        println!("Splitting into {}", savedir.display());
+    }
+    steal_lock(&savedir);
+    let result = split_file(
+        &options,
+        &mut |event| {
+            timing.record(&event);
+            if let Some(json) = &mut json {
+                json.update(&event);
+            }
+        },
+        &operation.token,
+    );
+    journal::split(started, &options, &result);
+    match result {
+        Ok(report) => {
+            if !is_remote(&savedir) {
+                History::record_split(&input, &savedir);
+            }
+            println!("File split successfully.");
+            if let Some(span) = &report.span {
+                print_volumes(&span.volumes);
+            }
+            if report.input_changed {
+                print_input_changed();
+            }
+            println!("{}", timing.summary());
+        }
+        Err(e) => {
+            if let Some(json) = &mut json {
+                json.fail(&e, exit_code(&e));
+            }
+            eprintln!("Error during splitting: {}", e);
+            exit(exit_code(&e));
+        }
+    }
+}
+
+// Parse a parity scheme: `xor`, or `rs:K` for K Reed–Solomon parity files per stripe.
+fn parse_parity(input: &str) -> Result<Parity, String> {
+    let (name, shards) = match input.split_once(':') {
+        Some((name, shards)) => (name, Some(shards)),
+        None => (input, None),
+    };
+    match (name.trim().to_ascii_lowercase().as_str(), shards) {
+        ("xor", None) => Ok(Parity::Xor),
+        ("rs", Some(shards)) => shards
+            .trim()
+            .parse()
+            .ok()
+            .filter(|shards| (1..=MAX_PARITY_SHARDS).contains(shards))
+            .map(|shards| Parity::ReedSolomon { shards })
+            .ok_or_else(|| format!("rs takes from 1 to {} parity files", MAX_PARITY_SHARDS)),
+        ("rs", None) => Err("rs needs a count of parity files, as in rs:4".to_string()),
+        _ => Err(format!("unknown parity \"{}\"", input)),
+    }
+}
+
+// `--dest` for splitting `input`, or the end of the program when it can't be worked out.
+fn expand_dest(template: &Template, input: &Path) -> PathBuf {
+    template.expand(input).unwrap_or_else(|e| {
+        eprintln!("Error during splitting: the destination: {}", e);
+        exit(match e.kind() {
+            io::ErrorKind::InvalidInput => 2,
+            _ => 1,
+        });
+    })
+}
+
+fn default_archive(input_path: &Path) -> PathBuf {
+    let extension = format!(".{}", ZIP_EXTENSION);
+    Path::new(".").join(suffixed(input_path.file_name(), "output", &extension))
+}
+
+// Whether to split `input` although it is a piece of a chunk set, as asked when there
+// is a terminal to ask on; otherwise the split refuses it, saying why.
+fn split_nested(input: &Path) -> bool {
+    let Some(set) = containing_set(input) else {
+        return false;
+    };
+    if !(io::stdin().is_terminal() || prompt::assume_yes()) {
+        return false;
+    }
+    eprintln!("{}", nested_warning(input, &set));
+    if !confirm("Split it anyway?", false).unwrap_or(false) {
+        println!("Nothing split.");
+        exit(1);
+    }
+    true
+}
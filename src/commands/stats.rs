@@ -0,0 +1,188 @@
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use clap::{Args, ValueEnum};
+use reconstruct_large_file::size::format_size;
+use reconstruct_large_file::{SetStats, StatsReport, stats};
+
+use crate::style::{self, Color};
+use crate::{exit_code, globals, interrupt, journal, path_arg};
+
+#[derive(Args)]
+pub(crate) struct StatsArgs {
+    /// Directory to look for chunk sets under
+    #[arg(value_parser = path_arg())]
+    pub(crate) root: PathBuf,
+    /// Order the sets by: path, size, stored (on disk), ratio, chunks (most first) or
+    /// verified (longest ago first)
+    #[arg(long, value_enum, default_value_t = StatsOrder::Path)]
+    pub(crate) sort: StatsOrder,
+    /// Print the figures as JSON
+    #[arg(long)]
+    pub(crate) json: bool,
+}
+
+pub(crate) fn run(args: StatsArgs) {
+    let StatsArgs { root, sort, json } = args;
+    let operation = interrupt::start();
+    let mut report =
+        stats(&root, globals().accept_modified, &operation.token).unwrap_or_else(|e| {
+            eprintln!("Error gathering the figures: {}", e);
+            exit(exit_code(&e));
+        });
+    // An unreadable journal only leaves every set looking never verified
+    let records = journal::read().unwrap_or_default();
+    let verified = journal::last_verified(&records);
+    let last = |set: &SetStats| verified.get(journal::absolute(&set.directory).as_str());
+    let ratio = |set: &SetStats| set.ratio().unwrap_or(0.0);
+    match sort {
+        StatsOrder::Path => {}
+        StatsOrder::Size => report.sets.sort_by_key(|set| u64::MAX - set.original_size),
+        StatsOrder::Stored => report.sets.sort_by_key(|set| u64::MAX - set.disk_size),
+        StatsOrder::Ratio => report.sets.sort_by(|a, b| ratio(b).total_cmp(&ratio(a))),
+        StatsOrder::Chunks => report.sets.sort_by_key(|set| usize::MAX - set.chunks),
+        StatsOrder::Verified => report
+            .sets
+            .sort_by_key(|set| last(set).map(|record| record.timestamp.clone())),
+    }
+    let last_verified: Vec<Option<&journal::Record>> =
+        report.sets.iter().map(|set| last(set).copied()).collect();
+    match json {
+        true => print_stats_json(&report, &last_verified),
+        false => print_stats(&report, &last_verified),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum StatsOrder {
+    Path,
+    Size,
+    Stored,
+    Ratio,
+    Chunks,
+    Verified,
+}
+
+// A row for each set with the totals under them, then the directories that aren't sets
+// that could be read. `verified` is the latest verify of each set, in the same order.
+fn print_stats(report: &StatsReport, verified: &[Option<&journal::Record>]) {
+    if report.sets.is_empty() && report.broken.is_empty() && report.foreign.is_empty() {
+        println!("No chunk sets under {}.", report.root.display());
+        return;
+    }
+    let percent = |stored: u64, original: u64| match original {
+        0 => "-".to_string(),
+        _ => format!("{:.0}%", stored as f64 * 100.0 / original as f64),
+    };
+    if !report.sets.is_empty() {
+        println!(
+            "{:>10} {:>10} {:>6} {:>7} {:<7} {:<6} {:<22} SET",
+            "ORIGINAL", "ON DISK", "RATIO", "CHUNKS", "HASH", "CODEC", "LAST VERIFIED"
+        );
+    }
+    for (set, record) in report.sets.iter().zip(verified) {
+        let hash = set.hash.map(|hash| format!("{:?}", hash).to_lowercase());
+        let last = match record {
+            Some(record) => {
+                let (outcome, color) = match record.outcome {
+                    journal::Outcome::Succeeded => ("ok", Color::Green),
+                    journal::Outcome::Failed => ("failed", Color::Red),
+                    journal::Outcome::Interrupted => ("interrupted", Color::Yellow),
+                };
+                let date = record.timestamp.get(..10).unwrap_or(&record.timestamp);
+                style::paint(&format!("{:<22}", format!("{} {}", date, outcome)), color)
+            }
+            None => format!("{:<22}", "never"),
+        };
+        let directory = set
+            .directory
+            .strip_prefix(&report.root)
+            .unwrap_or(&set.directory);
+        let directory = match directory.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => directory,
+        };
+        println!(
+            "{:>10} {:>10} {:>6} {:>7} {:<7} {:<6} {} {} ({})",
+            format_size(set.original_size),
+            format_size(set.disk_size),
+            percent(set.stored_size, set.original_size),
+            set.chunks,
+            hash.as_deref().unwrap_or("-"),
+            set.compression.name(),
+            last,
+            directory.display(),
+            set.original_filename
+        );
+    }
+    if !report.sets.is_empty() {
+        let never = verified.iter().filter(|record| record.is_none()).count();
+        println!(
+            "{:>10} {:>10} {:>6} {:>7} in {} {}, {} never verified",
+            format_size(report.original_size()),
+            format_size(report.disk_size()),
+            percent(report.stored_size(), report.original_size()),
+            report.chunks(),
+            report.sets.len(),
+            match report.sets.len() {
+                1 => "set",
+                _ => "sets",
+            },
+            never
+        );
+    }
+    for (skipped, what) in [(&report.broken, "broken"), (&report.foreign, "foreign")] {
+        if skipped.is_empty() {
+            continue;
+        }
+        let label = match what {
+            "broken" => style::paint("broken", Color::Red),
+            _ => style::paint("foreign", Color::Yellow),
+        };
+        println!();
+        println!("{} {}:", skipped.len(), label);
+        for set in skipped {
+            println!("  {}: {}", set.directory.display(), set.reason);
+        }
+    }
+}
+
+// The report as JSON, each set with its compression ratio and its latest verify, and
+// the totals.
+fn print_stats_json(report: &StatsReport, verified: &[Option<&journal::Record>]) {
+    let sets: Vec<serde_json::Value> = report
+        .sets
+        .iter()
+        .zip(verified)
+        .map(|(set, record)| {
+            let mut value = serde_json::to_value(set).unwrap_or_default();
+            value["ratio"] = serde_json::json!(set.ratio());
+            value["last_verified"] = serde_json::json!(record.map(|r| &r.timestamp));
+            value["last_verify_outcome"] = serde_json::json!(record.map(|r| r.outcome));
+            value
+        })
+        .collect();
+    let value = serde_json::json!({
+        "root": report.root,
+        "sets": sets,
+        "broken": report.broken,
+        "foreign": report.foreign,
+        "totals": {
+            "sets": report.sets.len(),
+            "original_size": report.original_size(),
+            "stored_size": report.stored_size(),
+            "disk_size": report.disk_size(),
+            "chunks": report.chunks(),
+            "never_verified": verified.iter().filter(|record| record.is_none()).count(),
+            "broken": report.broken.len(),
+            "foreign": report.foreign.len(),
+        },
+    });
+    match serde_json::to_string_pretty(&value) {
+        Ok(text) => println!("{}", text),
+        Err(e) => {
+            eprintln!("Error writing the figures: {}", e);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::{TransferState, TransferStatus, transfer_status};
+
+use crate::style::{self, Color};
+use crate::{exit_code, globals, logging, path_arg};
+
+#[derive(Args)]
+pub(crate) struct StatusArgs {
+    /// Directory containing the chunks
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+    /// Print the state of every chunk as JSON
+    #[arg(long)]
+    pub(crate) json: bool,
+}
+
+pub(crate) fn run(args: StatusArgs) {
+    let StatusArgs { directory, json } = args;
+    let status = transfer_status(&directory, globals().accept_modified).unwrap_or_else(|e| {
+        eprintln!("Error reading the transfer states: {}", e);
+        exit(exit_code(&e));
+    });
+    match json {
+        true => match serde_json::to_string_pretty(&status) {
+            Ok(text) => println!("{}", text),
+            Err(e) => {
+                eprintln!("Error writing the transfer states: {}", e);
+                exit(1);
+            }
+        },
+        false => print_transfer_status(&status),
+    }
+}
+
+fn print_transfer_status(status: &TransferStatus) {
+    println!(
+        "{} of {} chunks uploaded, {} pending, {} failed.",
+        status.uploaded,
+        status.chunks.len(),
+        status.pending,
+        status.failed
+    );
+    for (state, color) in [
+        (TransferState::Failed, Color::Red),
+        (TransferState::Pending, Color::Yellow),
+    ] {
+        for chunk in status.in_state(state) {
+            let since = chunk
+                .changed
+                .map(|changed| format!("  since {}", logging::utc_time(changed)))
+                .unwrap_or_default();
+            println!(
+                "{}  {}{}",
+                style::paint(&format!("{:<7}", state.name()), color),
+                chunk.path.display(),
+                since
+            );
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::env;
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::Args;
+use reconstruct_large_file::size::parse_size;
+
+use crate::{interrupt, path_arg, stress, thread_count};
+
+#[derive(Args)]
+pub(crate) struct StressArgs {
+    /// Directory to run in [default: the system's temporary directory]
+    #[arg(long, value_parser = path_arg())]
+    pub(crate) dir: Option<PathBuf>,
+    /// How many files to try
+    #[arg(long, value_name = "N", default_value_t = 100)]
+    pub(crate) iterations: u64,
+    /// Seed of the first iteration; the next ones take the numbers after it [default:
+    /// drawn from the clock]
+    #[arg(long)]
+    pub(crate) seed: Option<u64>,
+    /// Largest file to try, e.g. 4GiB when the disk has room for three times that
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "64MiB")]
+    pub(crate) max_size: u64,
+    /// Most threads splitting and reconstructing; each iteration draws up to this many
+    /// [default: up to 4]
+    #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) threads: Option<u64>,
+}
+
+pub(crate) fn run(args: StressArgs) {
+    let StressArgs {
+        dir,
+        iterations,
+        seed,
+        max_size,
+        threads,
+    } = args;
+    let seed = seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    });
+    let options = stress::StressOptions {
+        directory: dir.unwrap_or_else(env::temp_dir),
+        iterations,
+        seed,
+        max_size,
+        threads: thread_count(threads),
+    };
+    let operation = interrupt::start();
+    match stress::run(&options, &operation.token) {
+        Ok(true) => {}
+        Ok(false) => exit(1),
+        Err(_) if operation.token.is_cancelled() => {
+            eprintln!("Stress test interrupted.");
+            exit(interrupt::EXIT_CODE);
+        }
+        Err(e) => {
+            eprintln!("Stress test failed: {}", e);
+            exit(1);
+        }
+    }
+}
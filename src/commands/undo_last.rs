@@ -0,0 +1,30 @@
+use std::process::exit;
+
+use crate::{journal, trash};
+
+pub(crate) fn run() {
+    if !journal::enabled() {
+        eprintln!("undo-last goes by the journal, which is turned off.");
+        exit(2);
+    }
+    match trash::undo_last() {
+        Ok(undone) => {
+            if let Some(displaced) = &undone.displaced {
+                println!(
+                    "Moved {} out of the way to {}.",
+                    displaced.original.display(),
+                    displaced.location()
+                );
+            }
+            println!(
+                "Put {} back from {}.",
+                undone.restored.original.display(),
+                undone.restored.location()
+            );
+        }
+        Err(e) => {
+            eprintln!("Nothing undone: {}", e);
+            exit(1);
+        }
+    }
+}
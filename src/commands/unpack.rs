@@ -0,0 +1,60 @@
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use clap::Args;
+use reconstruct_large_file::size::format_size;
+use reconstruct_large_file::unpack;
+
+use crate::{exit_code, globals, interrupt, path_arg};
+
+#[derive(Args)]
+pub(crate) struct UnpackArgs {
+    /// Tar or zip holding the chunks
+    #[arg(value_parser = path_arg())]
+    pub(crate) archive: PathBuf,
+    /// Directory to unpack into, which must be empty or not exist yet [default:
+    /// ./<archive name without .tar or .zip>]
+    #[arg(short, long, value_name = "DIR", value_parser = path_arg())]
+    pub(crate) dest: Option<PathBuf>,
+}
+
+pub(crate) fn run(args: UnpackArgs) {
+    let UnpackArgs { archive, dest } = args;
+    let Some(dest) = dest.or_else(|| default_unpacked(&archive)) else {
+        eprintln!(
+            "{} isn't named .tar or .zip; say where to unpack it with --dest.",
+            archive.display()
+        );
+        exit(2);
+    };
+    let operation = interrupt::start();
+    match unpack(
+        &archive,
+        &dest,
+        globals().accept_modified,
+        &mut |_| {},
+        &operation.token,
+    ) {
+        Ok(report) => println!(
+            "Unpacked {} files ({}) into {}.",
+            report.files.len(),
+            format_size(report.size),
+            report.directory.display()
+        ),
+        Err(e) => {
+            eprintln!("Error during unpacking: {}", e);
+            exit(exit_code(&e));
+        }
+    }
+}
+
+// Where `unpack` puts a set when no destination is given: the archive's name without
+// its extension, in the current directory.
+fn default_unpacked(archive: &Path) -> Option<PathBuf> {
+    let name = archive.file_name()?.to_str()?;
+    let stem = [".tar", ".zip"]
+        .iter()
+        .find_map(|extension| name.strip_suffix(extension))
+        .filter(|stem| !stem.is_empty())?;
+    Some(Path::new(".").join(stem))
+}
@@ -0,0 +1,314 @@
+use std::hash::{BuildHasher, RandomState};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::Duration;
+
+use clap::Args;
+use reconstruct_large_file::size::format_size;
+use reconstruct_large_file::{
+    ChunkSet, DEFAULT_TIMESTAMP_TOLERANCE, Manifest, SampleOptions, VerifyReport, verify,
+    verify_exported, verify_sample,
+};
+
+use crate::progress::JsonProgress;
+use crate::{
+    ProgressFormat, exit_code, globals, interrupt, journal, parse_duration, path_arg,
+    print_timestamps, read_key, steal_lock,
+};
+
+#[derive(Args)]
+pub(crate) struct VerifyArgs {
+    /// Directory containing the chunks, or a zip or tar of them
+    #[arg(value_parser = path_arg())]
+    pub(crate) directory: PathBuf,
+    /// Another copy of the same chunks, such as a mirror, that `heal` could take lost
+    /// chunks from; give several to count on them all
+    #[arg(long = "copy", value_name = "DIR", value_parser = path_arg())]
+    pub(crate) copies: Vec<PathBuf>,
+    /// Check the chunks against this exported manifest (see export-manifest) instead
+    /// of the directory's own info.json
+    #[arg(long, value_name = "FILE", conflicts_with = "copies", value_parser = path_arg())]
+    pub(crate) manifest: Option<PathBuf>,
+    /// File holding the key the exported manifest was signed with, to check its
+    /// signature
+    #[arg(long, value_name = "FILE", requires = "manifest", value_parser = path_arg())]
+    pub(crate) key: Option<PathBuf>,
+    /// Report progress on stderr, one JSON object per line
+    #[arg(long, value_enum)]
+    pub(crate) progress: Option<ProgressFormat>,
+    /// Also compare the chunks' modification times with those split --record-times
+    /// noted and with each other, and list the chunks that stand out, as one rewritten
+    /// since; only hints, which never change the exit status
+    #[arg(long)]
+    pub(crate) timestamps: bool,
+    /// How far apart two times can be and count as the same, for filesystems that keep
+    /// them coarsely, e.g. 2s on FAT or 1h for a set copied across time zones
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "timestamps")]
+    pub(crate) timestamp_tolerance: Option<Duration>,
+    /// Check only this share of the chunks, e.g. 5%, drawn at random; missing chunks
+    /// are still found, but parity, PAR2 and checksum files aren't checked, and the
+    /// result is a sample's, never counted as the set verified
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percent, group = "sampling", conflicts_with_all = ["copies", "manifest"])]
+    pub(crate) sample: Option<f64>,
+    /// Check only this many chunks, drawn at random, as for --sample
+    #[arg(long, value_name = "COUNT", value_parser = clap::value_parser!(u64).range(1..), group = "sampling", conflicts_with_all = ["copies", "manifest", "sample"])]
+    pub(crate) sample_chunks: Option<u64>,
+    /// Check chunks drawn at random, as for --sample, until this long has passed, e.g.
+    /// 10m; with --sample or --sample-chunks, stop there at the latest
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, group = "sampling", conflicts_with_all = ["copies", "manifest"])]
+    pub(crate) max_time: Option<Duration>,
+    /// Draw the sample with this seed, to check the same chunks again [default: a new
+    /// one, printed with the result]
+    #[arg(long, requires = "sampling")]
+    pub(crate) seed: Option<u64>,
+}
+
+pub(crate) fn run(args: VerifyArgs) {
+    let VerifyArgs {
+        directory,
+        copies,
+        manifest,
+        key,
+        progress,
+        timestamps,
+        timestamp_tolerance,
+        sample,
+        sample_chunks,
+        max_time,
+        seed,
+    } = args;
+    let tolerance = timestamp_tolerance.unwrap_or(DEFAULT_TIMESTAMP_TOLERANCE);
+    if sample.is_some() || sample_chunks.is_some() || max_time.is_some() {
+        let options = SampleOptions {
+            fraction: sample,
+            chunks: sample_chunks.map(|chunks| chunks as usize),
+            max_time,
+            // A fresh one, from std's randomly keyed hasher
+            seed: seed.unwrap_or_else(|| RandomState::new().hash_one(0u8)),
+            accept_modified: globals().accept_modified,
+        };
+        verify_sampled(&directory, &options, progress, timestamps, tolerance);
+        return;
+    }
+    let key = key.map(|path| read_key(&path));
+    let mut json = (progress == Some(ProgressFormat::Json)).then(|| {
+        let chunks = ChunkSet::open(&directory, globals().accept_modified)
+            .ok()
+            .map(|set| set.len() as u64);
+        JsonProgress::new(chunks)
+    });
+    let operation = interrupt::start();
+    let mut update = |event| {
+        if let Some(json) = &mut json {
+            json.update(&event);
+        }
+    };
+    steal_lock(&directory);
+    let started = journal::start();
+    let verified = match &manifest {
+        Some(manifest) => verify_exported(
+            &directory,
+            manifest,
+            key.as_deref(),
+            &mut update,
+            &operation.token,
+        ),
+        None => verify(
+            &directory,
+            &copies,
+            globals().accept_modified,
+            &mut update,
+            &operation.token,
+        ),
+    };
+    journal::verify(started, &directory, &verified);
+    match verified {
+        Ok(report) if report.is_ok() => {
+            let against = match report.checksum_files.is_empty() {
+                true => String::new(),
+                false => format!(
+                    " (also checked against {})",
+                    report.checksum_files.join(", ")
+                ),
+            };
+            println!(
+                "{}: {} chunks, {}, no problems found{}.",
+                directory.display(),
+                report.health.chunks,
+                format_size(report.health.total_size),
+                against
+            );
+            print_volume_note(&directory);
+            print_unchecksummed(&report);
+            if timestamps {
+                print_timestamps(&directory, tolerance);
+            }
+        }
+        Ok(report) => {
+            let health = &report.health;
+            if health.chunks == 0 {
+                println!("{}: no chunk files found.", directory.display());
+            }
+            print_volume_note(&directory);
+            if !health.missing.is_empty() {
+                println!("Missing chunks: {:?}", health.missing);
+            }
+            if !health.uneven.is_empty() {
+                println!("Unevenly sized chunks: {:?}", health.uneven);
+            }
+            if !health.unexpected.is_empty() {
+                println!("Files not in info.json: {}", health.unexpected.join(", "));
+            }
+            if !report.mismatched.is_empty() {
+                println!("Missing or damaged: {}", report.mismatched.join(", "));
+            }
+            if !report.checksum_missing.is_empty() {
+                println!(
+                    "Listed in {} but missing: {}",
+                    report.checksum_files.join(", "),
+                    report.checksum_missing.join(", ")
+                );
+            }
+            print_unchecksummed(&report);
+            if let Some(recoverability) = &report.recoverability {
+                println!("{}", recoverability.conclusion());
+            }
+            if timestamps {
+                print_timestamps(&directory, tolerance);
+            }
+            exit(4);
+        }
+        Err(e) => {
+            if let Some(json) = &mut json {
+                json.fail(&e, exit_code(&e));
+            }
+            eprintln!("Error during verification: {}", e);
+            exit(exit_code(&e));
+        }
+    }
+}
+
+// A share such as 5% or 0.5%; a bare number is a percentage too.
+fn parse_percent(input: &str) -> Result<f64, String> {
+    let number = input.trim().trim_end_matches('%').trim();
+    let percent: f64 = number
+        .parse()
+        .map_err(|_| format!("'{}' is not a percentage such as 5%", input))?;
+    if !(percent > 0.0 && percent <= 100.0) {
+        return Err("must be above 0% and at most 100%".to_string());
+    }
+    Ok(percent / 100.0)
+}
+
+// Chunks the checksum files another tool left don't cover, which is no problem when
+// the manifest has their hashes.
+fn print_unchecksummed(report: &VerifyReport) {
+    if !report.unchecksummed.is_empty() {
+        println!(
+            "No checksum in {} for: {}",
+            report.checksum_files.join(", "),
+            report.unchecksummed.join(", ")
+        );
+    }
+}
+
+// Which volume of a split across several directories `directory` is, if it is one.
+fn print_volume_note(directory: &Path) {
+    if let Ok(Some(manifest)) = Manifest::load(directory, globals().accept_modified)
+        && let Some(span) = &manifest.span
+    {
+        let here = span.here();
+        println!(
+            "Volume {} of {}, with chunks {} to {}; reconstructing needs the others too, given with --volume.",
+            span.volume + 1,
+            span.volumes.len(),
+            here.start,
+            here.end.saturating_sub(1)
+        );
+    }
+}
+
+// `verify --sample`, `--sample-chunks` or `--max-time`: check the chunks `options`
+// draws, and say plainly that it was only those.
+fn verify_sampled(
+    directory: &Path,
+    options: &SampleOptions,
+    progress: Option<ProgressFormat>,
+    timestamps: bool,
+    tolerance: Duration,
+) {
+    let mut json = (progress == Some(ProgressFormat::Json)).then(|| JsonProgress::new(None));
+    let operation = interrupt::start();
+    let mut update = |event| {
+        if let Some(json) = &mut json {
+            json.update(&event);
+        }
+    };
+    steal_lock(directory);
+    let started = journal::start();
+    let verified = verify_sample(directory, options, &mut update, &operation.token);
+    journal::verify_sample(started, directory, options, &verified);
+    let report = match verified {
+        Ok(report) => report,
+        Err(e) => {
+            if let Some(json) = &mut json {
+                json.fail(&e, exit_code(&e));
+            }
+            eprintln!("Error during verification: {}", e);
+            exit(exit_code(&e));
+        }
+    };
+    let Some(sample) = &report.sample else {
+        return;
+    };
+    let share = match sample.checkable {
+        0 => 0.0,
+        total => sample.checked.len() as f64 * 100.0 / total as f64,
+    };
+    let found = match report.is_ok() {
+        true => "no problems found in them".to_string(),
+        false => "problems found".to_string(),
+    };
+    println!(
+        "{}: sample of {} of {} chunks ({:.1}%), {}, {}.",
+        directory.display(),
+        sample.checked.len(),
+        sample.checkable,
+        share,
+        format_size(sample.bytes),
+        found
+    );
+    if sample.checkable == 0 && report.health.chunks > 0 {
+        println!("None of the chunks has a hash to check it by; split with --hash for some.");
+    }
+    if sample.timed_out {
+        println!("Stopped at the time limit before the rest of the sample.");
+    }
+    let health = &report.health;
+    if health.chunks == 0 {
+        println!("{}: no chunk files found.", directory.display());
+    }
+    if !health.missing.is_empty() {
+        println!("Missing chunks: {:?}", health.missing);
+    }
+    if !health.uneven.is_empty() {
+        println!("Unevenly sized chunks: {:?}", health.uneven);
+    }
+    if !health.unexpected.is_empty() {
+        println!("Files not in info.json: {}", health.unexpected.join(", "));
+    }
+    if !report.mismatched.is_empty() {
+        println!("Missing or damaged: {}", report.mismatched.join(", "));
+    }
+    println!(
+        "Only a sample: the chunks not drawn weren't checked. Check the same ones again with \
+         --seed {}, or run verify without sampling to check them all.",
+        sample.seed
+    );
+    if timestamps {
+        print_timestamps(directory, tolerance);
+    }
+    if !report.is_ok() {
+        exit(4);
+    }
+}
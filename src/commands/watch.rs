@@ -0,0 +1,136 @@
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::process::exit;
+use std::time::Duration;
+
+use clap::Args;
+use reconstruct_large_file::manifest::{Compression, HashAlgorithm};
+use reconstruct_large_file::size::parse_size;
+use reconstruct_large_file::{DEFAULT_CHUNK_SIZE, DEFAULT_MIN_CHUNK_SIZE, check_chunk_size};
+
+use crate::template::{Template, TemplateParser};
+use crate::{exit_code, interrupt, parse_compression, path_arg, thread_count, watch};
+
+#[derive(Args)]
+pub(crate) struct WatchArgs {
+    /// Directory to watch for files to split
+    #[arg(value_parser = path_arg())]
+    pub(crate) inbox: PathBuf,
+    /// Directory to put each file's chunks in, as <file name>.chunks or as --template
+    /// says
+    #[arg(value_parser = path_arg())]
+    pub(crate) dest_root: PathBuf,
+    /// Name each file's directory of chunks in DEST_ROOT by this template, with the
+    /// variables of split --dest, e.g. {year}/{month}/{name}.{ext}.split; a template
+    /// with the date in it names a file split again after a restart anew
+    #[arg(long, value_parser = TemplateParser)]
+    pub(crate) template: Option<Template>,
+    /// Size of each chunk [default: 5MiB]
+    #[arg(short = 's', long, value_parser = parse_size)]
+    pub(crate) chunk_size: Option<u64>,
+    /// Take a --chunk-size below 4KiB, as small as one byte, however many files that
+    /// makes
+    #[arg(long)]
+    pub(crate) i_know_what_im_doing: bool,
+    /// Number of threads writing chunks [default: up to 4, depending on the CPU]
+    #[arg(short, long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) threads: Option<u64>,
+    /// Record a hash of every chunk in info.json
+    #[arg(long, value_enum)]
+    pub(crate) hash: Option<HashAlgorithm>,
+    /// Compress every chunk: gzip or gzip:LEVEL (1-9, default 6), zstd or zstd:LEVEL
+    /// (1-22, default 3), or none
+    #[arg(long, value_name = "CODEC[:LEVEL]", value_parser = parse_compression)]
+    pub(crate) compress: Option<(Compression, u32)>,
+    /// Seconds a file's size and modification time must stay the same before it is
+    /// split, so files still being copied in are left until they are complete
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    pub(crate) quiet: u64,
+    /// Look through the directory every --interval rather than be told of changes by
+    /// the system, for a network share that isn't told of files copied in from other
+    /// machines
+    #[arg(long)]
+    pub(crate) poll: bool,
+    /// Seconds between looks through the directory, with --poll or where the system
+    /// can't tell of changes
+    #[arg(long, value_name = "SECONDS", default_value_t = 2, value_parser = clap::value_parser!(u64).range(1..))]
+    pub(crate) interval: u64,
+    /// Move each file into done/ in the watched directory once it is split; without
+    /// this, files whose chunks already exist are taken to be split and left alone
+    #[arg(long)]
+    pub(crate) done: bool,
+    /// Also split files in subdirectories, keeping their paths under DEST_ROOT
+    #[arg(short, long)]
+    pub(crate) recursive: bool,
+    /// What to do when a file's directory of chunks is already taken: ask, rename to
+    /// a numbered one, hash (one named for the file's path), skip, or fail, stopping
+    /// the watch [default: skip, or rename with --done]
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub(crate) on_collision: Option<watch::Collision>,
+}
+
+pub(crate) fn run(args: WatchArgs) {
+    let WatchArgs {
+        inbox,
+        dest_root,
+        template,
+        chunk_size,
+        i_know_what_im_doing,
+        threads,
+        hash,
+        compress,
+        quiet,
+        poll,
+        interval,
+        done,
+        recursive,
+        on_collision,
+    } = args;
+    if !inbox.is_dir() {
+        eprintln!("{} is not a directory to watch.", inbox.display());
+        exit(2);
+    }
+    if template.as_ref().is_some_and(Template::is_absolute) {
+        eprintln!("--template names directories in DEST_ROOT, so can't be absolute.");
+        exit(2);
+    }
+    let on_collision = on_collision.unwrap_or(match done {
+        true => watch::Collision::Rename,
+        false => watch::Collision::Skip,
+    });
+    if on_collision == watch::Collision::Ask && !io::stdin().is_terminal() {
+        eprintln!("--on-collision ask needs a terminal to ask on.");
+        exit(2);
+    }
+    let chunk_size = chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE);
+    let min_chunk_size = match i_know_what_im_doing {
+        true => 0,
+        false => DEFAULT_MIN_CHUNK_SIZE,
+    };
+    // Before watching, rather than failing every file that comes in
+    if let Err(e) = check_chunk_size(chunk_size, min_chunk_size) {
+        eprintln!("Watching failed: {}", e);
+        exit(exit_code(&e));
+    }
+    let options = watch::WatchOptions {
+        inbox,
+        dest_root,
+        template,
+        chunk_size,
+        min_chunk_size,
+        threads: thread_count(threads),
+        hash,
+        compress,
+        quiet: Duration::from_secs(quiet),
+        poll,
+        interval: Duration::from_secs(interval),
+        done,
+        recursive,
+        on_collision,
+    };
+    let operation = interrupt::start();
+    if let Err(e) = watch::run(&options, &operation.token) {
+        eprintln!("Watching failed: {}", e);
+        exit(1);
+    }
+}
@@ -0,0 +1,87 @@
+// Chunk names other tools understand, for sets sent to someone without this one.
+// `Split` names chunks the way `split -b SIZE FILE FILE.part` does, `FILE.partaa`,
+// `FILE.partab`, … so `cat FILE.part* > FILE` puts them back together. `Hjsplit`
+// names them the way HJSplit and 7-Zip do, `FILE.001`, `FILE.002`, … Either way the
+// names alone give the order, so reconstructing needs no manifest.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Compat {
+    Split,
+    Hjsplit,
+}
+
+impl Compat {
+    // The name of chunk `index` of the file `base`, in a set of `count` chunks.
+    pub fn chunk_name(self, base: &str, index: usize, count: usize) -> String {
+        match self {
+            Compat::Split => format!("{}.part{}", base, split_suffix(index as u64)),
+            // Shells sort `FILE.*` by name, so every number gets as many digits as the
+            // last one needs
+            Compat::Hjsplit => {
+                let width = count.to_string().len().max(3);
+                format!("{}.{:0width$}", base, index + 1, width = width)
+            }
+        }
+    }
+}
+
+// Suffixes as `split` makes them: two letters to begin with, `aa` to `yz`. Rather than
+// running out after `zz` it widens the suffix, keeping the names in order: `zaaa` to
+// `zyzz`, then `zzaaaa` and so on, each level one `z` and one letter longer.
+pub(crate) fn split_suffix(mut index: u64) -> String {
+    let mut level = 0;
+    while index >= level_size(level) {
+        index -= level_size(level);
+        level += 1;
+    }
+    let mut letters = vec![b'a'; level as usize + 2];
+    for letter in letters.iter_mut().rev() {
+        *letter = b'a' + (index % 26) as u8;
+        index /= 26;
+    }
+    "z".repeat(level as usize) + &String::from_utf8_lossy(&letters)
+}
+
+// Names at `level`, whose first letter after the `z`s runs from `a` to `y`.
+fn level_size(level: u32) -> u64 {
+    26u64.saturating_pow(level + 1).saturating_mul(25)
+}
+
+// The scheme, base name and index of a chunk `name` made by one of the `Compat` schemes.
+// Numbered chunks count from 1; they are taken by value, so `.010` and `.10` are both
+// the tenth whatever width another tool chose, though a single digit is too likely to
+// be something else.
+pub(crate) fn parse(name: &str) -> Option<(Compat, &str, u64)> {
+    let (base, extension) = name.rsplit_once('.')?;
+    if base.is_empty() {
+        return None;
+    }
+    if extension.len() >= 2 && extension.bytes().all(|b| b.is_ascii_digit()) {
+        let number: u64 = extension.parse().ok()?;
+        return Some((Compat::Hjsplit, base, number.checked_sub(1)?));
+    }
+    let index = split_index(extension.strip_prefix("part")?)?;
+    Some((Compat::Split, base, index))
+}
+
+// The index `split_suffix` made `suffix` from, if it is one.
+pub(crate) fn split_index(suffix: &str) -> Option<u64> {
+    if suffix.is_empty() || !suffix.bytes().all(|b| b.is_ascii_lowercase()) {
+        return None;
+    }
+    let level = suffix.bytes().take_while(|&b| b == b'z').count();
+    let letters = &suffix[level..];
+    if letters.len() != level + 2 {
+        return None;
+    }
+    let index = (0..level as u32).map(level_size).sum::<u64>();
+    let mut value = 0u64;
+    for b in letters.bytes() {
+        value = value.checked_mul(26)?.checked_add(u64::from(b - b'a'))?;
+    }
+    index.checked_add(value)
+}
@@ -0,0 +1,312 @@
+// `completions`: shell completion scripts, generated by clap_complete from the clap
+// definition of the command line, so they cover every command and option there is and
+// change with it. Values complete by what they are: paths as paths, the ones with a
+// fixed set of values, such as --hash, as those values. Arguments naming a directory of
+// chunks are completed at run time by the hidden `__chunk-dirs` command, which only
+// offers directories with an info.json in them, or leading to one: bash and zsh get a
+// function that asks it for those arguments and leaves the rest to the generated one,
+// fish gets it as the candidates for them, and the other shells complete them as paths.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use clap::{ArgAction, Command};
+pub use clap_complete::Shell;
+
+use reconstruct_large_file::MANIFEST_NAME;
+
+// The hidden command the scripts run to complete a directory of chunks
+pub const CHUNK_DIRS_COMMAND: &str = "__chunk-dirs";
+
+// Arguments that name a directory of chunks, by command and argument
+const CHUNK_DIRECTORIES: &[(&str, &str)] = &[
+    ("reconstruct", "directory"),
+    ("verify", "directory"),
+    ("verify", "copies"),
+    ("doctor", "directory"),
+    ("coverage", "directory"),
+    ("explore", "directory"),
+    ("repair", "directory"),
+    ("heal", "directory"),
+    ("heal", "sources"),
+    ("rechunk", "source"),
+    ("reseal", "directory"),
+    ("pack", "directory"),
+    ("export-manifest", "directory"),
+    ("serve", "directory"),
+];
+
+// Where a command takes a directory of chunks: the options, the positions among its
+// positional arguments, and the position from which every further one is one too, for
+// a last argument that can be given several times.
+struct ChunkArgs {
+    command: String,
+    // Every option of the command that takes a value, as they are skipped over
+    valued: Vec<String>,
+    options: Vec<String>,
+    positions: Vec<usize>,
+    rest: Option<usize>,
+}
+
+pub fn generate(shell: Shell, mut command: Command) -> String {
+    command.build();
+    let program = command.get_name().to_string();
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut command, &program, &mut script);
+    let mut script = String::from_utf8(script).expect("clap_complete writes UTF-8");
+    let chunk_args: Vec<ChunkArgs> = command.get_subcommands().filter_map(chunk_args).collect();
+    let ident = program.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    match shell {
+        Shell::Bash => script += &bash(&program, &ident, &chunk_args),
+        Shell::Zsh => script += &zsh(&program, &ident, &chunk_args),
+        Shell::Fish => script += &fish(&program, &chunk_args),
+        _ => {}
+    }
+    script
+}
+
+fn chunk_args(command: &Command) -> Option<ChunkArgs> {
+    let name = command.get_name();
+    let mut args = ChunkArgs {
+        command: name.to_string(),
+        valued: Vec::new(),
+        options: Vec::new(),
+        positions: Vec::new(),
+        rest: None,
+    };
+    let mut position = 0;
+    for arg in command.get_arguments() {
+        let chunks = CHUNK_DIRECTORIES.contains(&(name, arg.get_id().as_str()));
+        if arg.is_positional() {
+            if chunks && matches!(arg.get_action(), ArgAction::Append) {
+                args.rest = Some(position);
+            } else if chunks {
+                args.positions.push(position);
+            }
+            position += 1;
+            continue;
+        }
+        if !arg.get_action().takes_values() {
+            continue;
+        }
+        let longs = arg.get_long().into_iter().map(|long| format!("--{}", long));
+        let shorts = arg
+            .get_short()
+            .into_iter()
+            .map(|short| format!("-{}", short));
+        for flag in longs.chain(shorts) {
+            if chunks {
+                args.options.push(flag.clone());
+            }
+            args.valued.push(flag);
+        }
+    }
+    let any = !args.options.is_empty() || !args.positions.is_empty() || args.rest.is_some();
+    any.then_some(args)
+}
+
+// A function bash and zsh both run, which says whether the word being completed, after
+// the words it is given, names a directory of chunks.
+fn wants_chunks(ident: &str, chunk_args: &[ChunkArgs]) -> String {
+    let mut commands = String::new();
+    for args in chunk_args {
+        let positions: Vec<String> = args.positions.iter().map(usize::to_string).collect();
+        let _ = writeln!(
+            commands,
+            "                {}) command=$word; valued=' {} '; options=' {} '; \
+             positions=' {} '; rest={} ;;",
+            args.command,
+            args.valued.join(" "),
+            args.options.join(" "),
+            positions.join(" "),
+            args.rest.map(|rest| rest.to_string()).unwrap_or_default(),
+        );
+    }
+    r#"
+_IDENT_wants_chunks() {
+    local command= valued= options= positions= rest= count=0 skip= last= word
+    for word in "$@"; do
+        if [ -z "$command" ]; then
+            case "$word" in
+COMMANDS            esac
+            continue
+        fi
+        if [ -n "$skip" ]; then
+            skip=
+            continue
+        fi
+        case "$word" in
+            --*=*) ;;
+            -*) case "$valued" in *" $word "*) skip=1; last=$word ;; esac ;;
+            *) count=$((count + 1)) ;;
+        esac
+    done
+    [ -n "$command" ] || return 1
+    if [ -n "$skip" ]; then
+        case "$options" in *" $last "*) return 0 ;; esac
+        return 1
+    fi
+    case "$positions" in *" $count "*) return 0 ;; esac
+    [ -n "$rest" ] && [ "$count" -ge "$rest" ]
+}
+"#
+    .replace("IDENT", ident)
+    .replace("COMMANDS", &commands)
+}
+
+fn bash(program: &str, ident: &str, chunk_args: &[ChunkArgs]) -> String {
+    let mut script = wants_chunks(ident, chunk_args);
+    script += r#"
+_IDENT_chunks() {
+    local cur="${COMP_WORDS[COMP_CWORD]}"
+    if [[ $cur != -* ]] && _IDENT_wants_chunks "${COMP_WORDS[@]:1:COMP_CWORD-1}"; then
+        compopt -o filenames
+        local IFS=$'\n'
+        COMPREPLY=($(PROGRAM CHUNK_DIRS -- "$cur" 2>/dev/null))
+        return
+    fi
+    _IDENT "$@"
+}
+
+complete -F _IDENT_chunks -o nosort -o bashdefault -o default PROGRAM
+"#;
+    script
+        .replace("IDENT", ident)
+        .replace("PROGRAM", program)
+        .replace("CHUNK_DIRS", CHUNK_DIRS_COMMAND)
+}
+
+fn zsh(program: &str, ident: &str, chunk_args: &[ChunkArgs]) -> String {
+    let mut script = wants_chunks(ident, chunk_args);
+    script += r#"
+_IDENT_chunks() {
+    if [[ $PREFIX != -* ]] && _IDENT_wants_chunks "${(@)words[2,CURRENT-1]}"; then
+        local -a sets
+        sets=("${(@f)$(PROGRAM CHUNK_DIRS -- "$PREFIX" 2>/dev/null)}")
+        compadd -Q -f -- "${(@)sets:#}"
+        return
+    fi
+    _IDENT "$@"
+}
+
+compdef _IDENT_chunks PROGRAM
+"#;
+    script
+        .replace("IDENT", ident)
+        .replace("PROGRAM", program)
+        .replace("CHUNK_DIRS", CHUNK_DIRS_COMMAND)
+}
+
+// Fish adds these candidates to the paths the generated lines offer.
+fn fish(program: &str, chunk_args: &[ChunkArgs]) -> String {
+    let mut script = String::new();
+    let candidates = format!("({} {} -- (commandline -ct))", program, CHUNK_DIRS_COMMAND);
+    for args in chunk_args {
+        let condition = format!("__fish_seen_subcommand_from {}", args.command);
+        for option in &args.options {
+            let _ = writeln!(
+                script,
+                "complete -c {} -n \"{}\" -l {} -r -f -a \"{}\"",
+                program,
+                condition,
+                option.trim_start_matches('-'),
+                candidates
+            );
+        }
+        if !args.positions.is_empty() || args.rest.is_some() {
+            let _ = writeln!(
+                script,
+                "complete -c {} -n \"{}\" -f -a \"{}\"",
+                program, condition, candidates
+            );
+        }
+    }
+    script
+}
+
+// Directories for a chunk directory argument starting with `prefix`: those holding an
+// info.json, and those with one a level further down, to get to them through. When
+// there are none, every directory, so completion doesn't stop dead.
+pub fn chunk_dirs(prefix: &str) -> Vec<String> {
+    let (parent, start) = match prefix.rfind(['/', std::path::MAIN_SEPARATOR]) {
+        Some(at) => (&prefix[..=at], &prefix[at + 1..]),
+        None => ("", prefix),
+    };
+    let directory = if parent.is_empty() { "." } else { parent };
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+    let mut all = Vec::new();
+    let mut sets = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with(start) || (name.starts_with('.') && !start.starts_with('.')) {
+            continue;
+        }
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let candidate = format!("{}{}", parent, name);
+        if is_set(&path) || leads_to_set(&path) {
+            sets.push(candidate.clone());
+        }
+        all.push(candidate);
+    }
+    let mut found = if sets.is_empty() { all } else { sets };
+    found.sort();
+    found
+}
+
+fn is_set(directory: &Path) -> bool {
+    directory.join(MANIFEST_NAME).is_file()
+}
+
+fn leads_to_set(directory: &Path) -> bool {
+    fs::read_dir(directory).is_ok_and(|entries| {
+        entries.flatten().any(|entry| {
+            let path = entry.path();
+            path.is_dir() && is_set(&path)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+    use crate::Cli;
+
+    #[test]
+    fn every_chunk_directory_argument_is_one_of_the_command_line() {
+        let mut command = Cli::command();
+        command.build();
+        for (name, id) in CHUNK_DIRECTORIES {
+            let Some(sub) = command.find_subcommand(name) else {
+                // Commands behind features not built in
+                assert_eq!(*name, "serve");
+                continue;
+            };
+            assert!(
+                sub.get_arguments().any(|arg| arg.get_id() == *id),
+                "{} has no argument {}",
+                name,
+                id
+            );
+        }
+    }
+
+    #[test]
+    fn bash_asks_for_directories_of_chunks_where_they_go() {
+        let script = generate(Shell::Bash, Cli::command());
+        let verify = script
+            .lines()
+            .find(|line| line.trim_start().starts_with("verify) command=$word"))
+            .unwrap();
+        assert!(verify.contains("options=' --copy '"));
+        assert!(verify.contains("positions=' 0 '"));
+        assert!(script.contains("complete -F _reconstruct_large_file_chunks"));
+    }
+}
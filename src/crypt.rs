@@ -0,0 +1,1058 @@
+// Encrypted chunks, for `--encrypt`. Every chunk file of a set is sealed with AES-256-GCM
+// under one key, which comes from a passphrase through Argon2id with the parameters and
+// salt `Encryption` records in info.json. A chunk is sealed 64 KiB at a time with the
+// STREAM construction (Hoang, Reyhanitabar, Rogaway and Vizár, 2015), each segment under
+// the chunk's own random nonce prefix, a counter and a flag for the last one, so it can
+// be read a segment at a time with nothing handed on before that segment's tag checks
+// out, and a chunk cut short or spliced from segments of others fails as a changed one
+// does. The file is
+//
+//   fsr-aea\x01  8 bytes, the last of them the version of the format
+//   nonce        7 random bytes
+//   segments     each 64 KiB sealed followed by its 16-byte tag; the last shorter, as
+//                little as the tag alone
+//
+// Sizes and hashes in info.json stay those of the original bytes, as they are for
+// compressed chunks, and compression goes before encryption. Each chunk's entry also
+// records the SHA-256 of its file as stored, which `verify` checks without the key. The
+// nonce prefix being random, one key shouldn't seal more than some millions of chunks;
+// a passphrase gives every split a key of its own, through its salt.
+//
+// With `--age-recipient` each chunk is instead a plain age file (age-encryption.org/v1)
+// to those X25519 recipients, which info.json lists, so the chunks can be joined
+// without this tool by anyone holding one of their identities:
+//
+//   for f in chunk*; do age -d -i key.txt "$f"; done > original
+//
+// with `| gunzip` (or the like) after `done` for compressed chunks, compression going
+// before encryption there too. The identity's public key being among the recipients
+// listed stands for the fingerprint.
+//
+// The encrypt feature builds AES-256-GCM in and the age feature age; without them,
+// encrypted sets are still recognised, but reading or writing their chunks fails as
+// unsupported.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "encrypt")]
+use aes_gcm::aead::stream::{DecryptorBE32, EncryptorBE32};
+#[cfg(feature = "encrypt")]
+use aes_gcm::aead::{KeyInit, OsRng, rand_core::RngCore};
+#[cfg(feature = "encrypt")]
+use aes_gcm::{Aes256Gcm, Key};
+
+use crate::error::{PathContext, Result, SplitterError};
+
+const MAGIC: &[u8; 8] = b"fsr-aea\x01";
+const NONCE_LEN: usize = 7;
+const TAG_LEN: usize = 16;
+// What each segment holds before it is sealed; all but the last are this long
+const SEGMENT: usize = 64 << 10;
+pub const KEY_LEN: usize = 32;
+
+// How a set's chunks are encrypted, as info.json records it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Encryption {
+    pub cipher: Cipher,
+    // How the key comes from a passphrase; none for a key from a key file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kdf: Option<Kdf>,
+    // `ChunkKey::fingerprint` of the key, to tell another apart before any chunk is read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    // The age recipients every chunk is encrypted to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Cipher {
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    #[serde(rename = "age")]
+    Age,
+}
+
+// Argon2id as RFC 9106 has it, with the cost it was run at and the salt, in hex.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Kdf {
+    pub algorithm: KdfAlgorithm,
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    pub salt: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KdfAlgorithm {
+    Argon2id,
+}
+
+impl Encryption {
+    // AES-256-GCM under a key from a passphrase, through Argon2id at RFC 9106's second
+    // recommended cost, 64 MiB over 3 passes on 4 lanes, with a new random salt.
+    pub fn with_passphrase() -> io::Result<Encryption> {
+        Ok(Encryption {
+            cipher: Cipher::Aes256Gcm,
+            kdf: Some(Kdf {
+                algorithm: KdfAlgorithm::Argon2id,
+                memory_kib: 64 << 10,
+                iterations: 3,
+                parallelism: 4,
+                salt: hex(&random::<16>()?),
+            }),
+            fingerprint: None,
+            recipients: Vec::new(),
+        })
+    }
+
+    // AES-256-GCM under a key given as it is, as from a key file.
+    pub fn with_key_file() -> Encryption {
+        Encryption {
+            cipher: Cipher::Aes256Gcm,
+            kdf: None,
+            fingerprint: None,
+            recipients: Vec::new(),
+        }
+    }
+
+    // Every chunk a plain age file, to the recipients the key gives.
+    pub fn with_age() -> Encryption {
+        Encryption {
+            cipher: Cipher::Age,
+            ..Encryption::with_key_file()
+        }
+    }
+
+    // Why this build can't read or write chunks encrypted this way, if it can't.
+    pub(crate) fn unsupported(&self) -> Option<SplitterError> {
+        match self.cipher {
+            Cipher::Aes256Gcm if !cfg!(feature = "encrypt") => Some(no_encrypt()),
+            Cipher::Age if !cfg!(feature = "age") => Some(no_age()),
+            _ => None,
+        }
+    }
+}
+
+// The key a set is sealed with. It is wiped from memory when dropped, and never shown.
+#[derive(Clone)]
+pub struct ChunkKey(Secret);
+
+#[derive(Clone)]
+enum Secret {
+    Aes([u8; KEY_LEN]),
+    // Recipients to encrypt to when splitting, identities to decrypt with when joining,
+    // each as age writes them
+    Age {
+        recipients: Vec<String>,
+        identities: Vec<String>,
+    },
+}
+
+impl fmt::Debug for ChunkKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ChunkKey(..)")
+    }
+}
+
+impl Drop for ChunkKey {
+    fn drop(&mut self) {
+        let secret: &mut [u8] = match &mut self.0 {
+            Secret::Aes(bytes) => bytes,
+            Secret::Age { identities, .. } => {
+                for identity in identities {
+                    // SAFETY: zeroes are valid UTF-8.
+                    wipe(unsafe { identity.as_bytes_mut() });
+                }
+                return;
+            }
+        };
+        wipe(secret);
+    }
+}
+
+fn wipe(bytes: &mut [u8]) {
+    for byte in bytes {
+        // SAFETY: a plain write to a byte we own, volatile so it isn't left out.
+        unsafe { std::ptr::write_volatile(byte, 0) };
+    }
+}
+
+impl ChunkKey {
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> ChunkKey {
+        ChunkKey(Secret::Aes(bytes))
+    }
+
+    // A key that encrypts to the age recipients given, `age1...` as age-keygen prints
+    // them. It can't decrypt anything.
+    pub fn age_recipients(recipients: &[String]) -> Result<ChunkKey> {
+        if recipients.is_empty() {
+            return Err(SplitterError::InvalidOption {
+                field: "age_recipient",
+                reason: "needs at least one recipient",
+            });
+        }
+        #[cfg(feature = "age")]
+        for recipient in recipients {
+            recipient.parse::<age::x25519::Recipient>().map_err(|_| {
+                SplitterError::InvalidOption {
+                    field: "age_recipient",
+                    reason: "is not an age recipient, which starts with age1",
+                }
+            })?;
+        }
+        #[cfg(not(feature = "age"))]
+        return Err(no_age());
+        #[cfg(feature = "age")]
+        Ok(ChunkKey(Secret::Age {
+            recipients: recipients.to_vec(),
+            identities: Vec::new(),
+        }))
+    }
+
+    // The age identities in the file at `path`, as age-keygen writes it: one
+    // `AGE-SECRET-KEY-1...` to a line, with comments and blank lines between.
+    pub fn read_age_identity(path: &Path) -> Result<ChunkKey> {
+        let text = fs::read_to_string(path).at(path)?;
+        let identities: Vec<String> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_owned)
+            .collect();
+        let key = ChunkKey(Secret::Age {
+            recipients: Vec::new(),
+            identities,
+        });
+        if key.age_identities()?.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "holds no age identity, which starts with AGE-SECRET-KEY-1",
+            ))
+            .at(path);
+        }
+        Ok(key)
+    }
+
+    // The cipher this key is for.
+    pub fn cipher(&self) -> Cipher {
+        match &self.0 {
+            Secret::Aes(_) => Cipher::Aes256Gcm,
+            Secret::Age { .. } => Cipher::Age,
+        }
+    }
+
+    // What `encryption` records of this key: its fingerprint, or the age recipients.
+    pub fn record(&self, encryption: Encryption) -> Encryption {
+        match &self.0 {
+            Secret::Aes(_) => Encryption {
+                fingerprint: Some(self.fingerprint()),
+                ..encryption
+            },
+            Secret::Age { recipients, .. } => Encryption {
+                recipients: recipients.clone(),
+                ..encryption
+            },
+        }
+    }
+
+    // The key in the file at `path`, which holds its 32 bytes and nothing else. A file
+    // anyone on the system can read is refused unless `insecure_permissions`.
+    pub fn read_file(path: &Path, insecure_permissions: bool) -> Result<ChunkKey> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(path).at(path)?.permissions().mode();
+            if mode & 0o004 != 0 && !insecure_permissions {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "can be read by anyone; chmod 600 it, or allow that with --insecure-key-permissions",
+                ))
+                .at(path);
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = insecure_permissions;
+        let bytes = fs::read(path).at(path)?;
+        let bytes = <[u8; KEY_LEN]>::try_from(bytes).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "is not a key file, which holds exactly 32 bytes",
+            )
+        });
+        Ok(ChunkKey::from_bytes(bytes.at(path)?))
+    }
+
+    // Write a new random key to `path`, which mustn't exist yet, readable by its owner
+    // alone.
+    pub fn generate_file(path: &Path) -> Result<ChunkKey> {
+        let bytes = random().at(path)?;
+        let key = ChunkKey::from_bytes(bytes);
+        let mut options = File::options();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path).doing("creating", path)?;
+        file.write_all(&bytes)
+            .and_then(|()| file.sync_all())
+            .at(path)?;
+        Ok(key)
+    }
+
+    // A hash of the key that gives nothing of it away, which the set records to tell
+    // the wrong key from the right one. An age key's is that of its recipients.
+    pub fn fingerprint(&self) -> String {
+        let hasher = Sha256::new().chain_update(b"fsr-aea key fingerprint");
+        let digest = match &self.0 {
+            Secret::Aes(bytes) => hasher.chain_update(bytes),
+            Secret::Age { recipients, .. } => hasher.chain_update(recipients.join("\n")),
+        }
+        .finalize();
+        hex(&digest[..16])
+    }
+
+    // Fails with `WrongKey` when the set in `directory` that `encryption` describes was
+    // encrypted with another key, or another cipher. Sets that recorded no fingerprint
+    // only find out from their chunks; age sets by whether one of the identities is
+    // among their recipients.
+    pub fn check(&self, encryption: &Encryption, directory: &Path) -> Result<()> {
+        let wrong = || SplitterError::WrongKey {
+            path: directory.to_path_buf(),
+        };
+        match (&self.0, encryption.cipher) {
+            (Secret::Aes(_), Cipher::Aes256Gcm) => match &encryption.fingerprint {
+                Some(fingerprint) if *fingerprint != self.fingerprint() => Err(wrong()),
+                _ => Ok(()),
+            },
+            (Secret::Age { .. }, Cipher::Age) => {
+                let public = self.age_public_keys()?;
+                match public.iter().any(|key| encryption.recipients.contains(key)) {
+                    true => Ok(()),
+                    false => Err(wrong()),
+                }
+            }
+            _ => Err(wrong()),
+        }
+    }
+
+    #[cfg(feature = "age")]
+    fn age_identities(&self) -> Result<Vec<age::x25519::Identity>> {
+        let Secret::Age { identities, .. } = &self.0 else {
+            return Ok(Vec::new());
+        };
+        identities
+            .iter()
+            .map(|identity| {
+                identity.parse::<age::x25519::Identity>().map_err(|_| {
+                    SplitterError::InvalidOption {
+                        field: "age_identity",
+                        reason: "is not an age identity, which starts with AGE-SECRET-KEY-1",
+                    }
+                })
+            })
+            .collect()
+    }
+
+    #[cfg(not(feature = "age"))]
+    fn age_identities(&self) -> Result<Vec<()>> {
+        Err(no_age())
+    }
+
+    // The recipients this key's identities decrypt for.
+    fn age_public_keys(&self) -> Result<Vec<String>> {
+        #[cfg(feature = "age")]
+        return Ok(self
+            .age_identities()?
+            .iter()
+            .map(|identity| identity.to_public().to_string())
+            .collect());
+        #[cfg(not(feature = "age"))]
+        self.age_identities().map(|_| Vec::new())
+    }
+
+    // The key `passphrase` gives through `kdf`, as for the set `encryption` describes.
+    pub fn derive(passphrase: &str, encryption: &Encryption) -> Result<ChunkKey> {
+        let Some(kdf) = &encryption.kdf else {
+            return Err(SplitterError::InvalidOption {
+                field: "passphrase",
+                reason: "is no use for a set whose key wasn't made from one",
+            });
+        };
+        let invalid = || SplitterError::InvalidOption {
+            field: "kdf",
+            reason: "has a salt or cost that Argon2id can't take",
+        };
+        let salt = unhex(&kdf.salt).ok_or_else(invalid)?;
+        #[cfg(feature = "encrypt")]
+        {
+            use argon2::{Algorithm, Argon2, Params, Version};
+            let params = Params::new(
+                kdf.memory_kib,
+                kdf.iterations,
+                kdf.parallelism,
+                Some(KEY_LEN),
+            )
+            .map_err(|_| invalid())?;
+            let mut bytes = [0; KEY_LEN];
+            let hashed = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password_into(passphrase.as_bytes(), &salt, &mut bytes);
+            let key = ChunkKey::from_bytes(bytes);
+            wipe(&mut bytes);
+            hashed.map_err(|_| invalid())?;
+            Ok(key)
+        }
+        #[cfg(not(feature = "encrypt"))]
+        {
+            let _ = (passphrase, salt);
+            Err(no_encrypt())
+        }
+    }
+}
+
+// Why an encrypted set can't be read or written by this build.
+fn no_encrypt() -> SplitterError {
+    SplitterError::InvalidOption {
+        field: "encryption",
+        reason: "needs a build with the encrypt feature",
+    }
+}
+
+fn no_age() -> SplitterError {
+    SplitterError::InvalidOption {
+        field: "encryption",
+        reason: "needs a build with the age feature",
+    }
+}
+
+#[cfg(not(all(feature = "encrypt", feature = "age")))]
+fn unsupported(why: SplitterError) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, why)
+}
+
+// What a segment that fails its tag, or a chunk cut short, reads as: InvalidData, like a
+// compressed chunk that no longer decodes, with this inside for `PathContext` to turn
+// into `SplitterError::Undecryptable`.
+#[derive(Debug)]
+pub(crate) struct Unauthentic;
+
+impl fmt::Display for Unauthentic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("wrong passphrase or corrupted data")
+    }
+}
+
+impl std::error::Error for Unauthentic {}
+
+#[cfg(any(feature = "encrypt", feature = "age"))]
+fn unauthentic() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, Unauthentic)
+}
+
+// The size of the file `len` bytes are sealed into, which for an uncompressed chunk
+// tells a whole one from one cut short without the key.
+pub(crate) fn sealed_len(len: u64) -> u64 {
+    let segments = len / SEGMENT as u64 + 1;
+    (MAGIC.len() + NONCE_LEN) as u64 + len + segments * TAG_LEN as u64
+}
+
+// `N` bytes from the operating system's generator.
+#[cfg(feature = "encrypt")]
+fn random<const N: usize>() -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    OsRng
+        .try_fill_bytes(&mut bytes)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(bytes)
+}
+
+#[cfg(not(feature = "encrypt"))]
+fn random<const N: usize>() -> io::Result<[u8; N]> {
+    Err(unsupported(no_encrypt()))
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// `data` sealed under `key` as a chunk's bytes are, as for the metadata
+// `Manifest::conceal` keeps from info.json.
+pub(crate) fn seal(key: &ChunkKey, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut writer = SealedWriter::new(Vec::new(), Some(key))?;
+    writer.write_all(data)?;
+    writer.finish()
+}
+
+// What `seal` sealed, opened with the same key.
+pub(crate) fn open(key: &ChunkKey, sealed: &[u8]) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    SealedReader::new(sealed, Some(key))?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+// Seals everything written to it onto `inner` when given a key, and passes it through
+// untouched when not. `finish` seals the last segment; dropping it without that leaves
+// a chunk that fails as cut short. An age key hands it all to age's own stream instead.
+pub(crate) struct SealedWriter<W: Write> {
+    inner: Sink<W>,
+    #[cfg(feature = "encrypt")]
+    stream: Option<EncryptorBE32<Aes256Gcm>>,
+    sealing: bool,
+    buffer: Vec<u8>,
+}
+
+// Where a `SealedWriter` writes: to the file itself, or through age.
+enum Sink<W: Write> {
+    Direct(W),
+    #[cfg(feature = "age")]
+    Age(age::stream::StreamWriter<W>),
+}
+
+impl<W: Write> Sink<W> {
+    fn finish(self) -> io::Result<W> {
+        match self {
+            Sink::Direct(inner) => Ok(inner),
+            #[cfg(feature = "age")]
+            Sink::Age(stream) => stream.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Sink<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Direct(inner) => inner.write(buf),
+            #[cfg(feature = "age")]
+            Sink::Age(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Direct(inner) => inner.flush(),
+            #[cfg(feature = "age")]
+            Sink::Age(stream) => stream.flush(),
+        }
+    }
+}
+
+impl<W: Write> SealedWriter<W> {
+    pub fn new(mut inner: W, key: Option<&ChunkKey>) -> io::Result<SealedWriter<W>> {
+        let passing = |inner| SealedWriter {
+            inner,
+            #[cfg(feature = "encrypt")]
+            stream: None,
+            sealing: false,
+            buffer: Vec::new(),
+        };
+        let key = match key.map(|key| &key.0) {
+            None => return Ok(passing(Sink::Direct(inner))),
+            Some(Secret::Age { recipients, .. }) => {
+                return Ok(passing(age_sink(inner, recipients)?));
+            }
+            Some(Secret::Aes(key)) => key,
+        };
+        #[cfg(feature = "encrypt")]
+        {
+            let nonce = random::<NONCE_LEN>()?;
+            let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+            let stream = EncryptorBE32::from_aead(cipher, nonce.as_slice().into());
+            inner.write_all(MAGIC)?;
+            inner.write_all(&nonce)?;
+            Ok(SealedWriter {
+                inner: Sink::Direct(inner),
+                stream: Some(stream),
+                sealing: true,
+                buffer: Vec::with_capacity(SEGMENT + TAG_LEN),
+            })
+        }
+        #[cfg(not(feature = "encrypt"))]
+        {
+            let _ = (&mut inner, key);
+            Err(unsupported(no_encrypt()))
+        }
+    }
+
+    // Seal what is left as the last segment and hand back the writer.
+    pub fn finish(self) -> io::Result<W> {
+        #[cfg(feature = "encrypt")]
+        if let Some(stream) = self.stream {
+            let (mut inner, mut buffer) = (self.inner, self.buffer);
+            stream
+                .encrypt_last_in_place(&[], &mut buffer)
+                .map_err(|_| io::Error::other("cannot seal the last segment"))?;
+            inner.write_all(&buffer)?;
+            return inner.finish();
+        }
+        self.inner.finish()
+    }
+
+    #[cfg(feature = "encrypt")]
+    fn seal_segment(&mut self) -> io::Result<()> {
+        let Some(stream) = &mut self.stream else {
+            return Err(io::Error::other("the last segment is already sealed"));
+        };
+        stream
+            .encrypt_next_in_place(&[], &mut self.buffer)
+            .map_err(|_| io::Error::other("too many segments for one chunk"))?;
+        self.inner.write_all(&self.buffer)?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for SealedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.sealing {
+            return self.inner.write(buf);
+        }
+        let taken = buf.len().min(SEGMENT - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..taken]);
+        // A full segment is sealed straight away, so the last one is always shorter
+        // than the rest, if only the tag, which is how reading knows it for the last
+        #[cfg(feature = "encrypt")]
+        if self.buffer.len() == SEGMENT {
+            self.seal_segment()?;
+        }
+        Ok(taken)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "age")]
+fn age_sink<W: Write>(inner: W, recipients: &[String]) -> io::Result<Sink<W>> {
+    let recipients = recipients
+        .iter()
+        .map(|recipient| recipient.parse::<age::x25519::Recipient>())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let encryptor = age::Encryptor::with_recipients(
+        recipients
+            .iter()
+            .map(|recipient| recipient as &dyn age::Recipient),
+    )
+    .map_err(|e| io::Error::other(e.to_string()))?;
+    Ok(Sink::Age(encryptor.wrap_output(inner)?))
+}
+
+#[cfg(not(feature = "age"))]
+fn age_sink<W: Write>(inner: W, recipients: &[String]) -> io::Result<Sink<W>> {
+    let _ = (inner, recipients);
+    Err(unsupported(no_age()))
+}
+
+// Opens what a `SealedWriter` sealed with the same key, or passes `inner` through
+// untouched without one. Each segment is only handed on once its tag has checked out.
+pub(crate) struct SealedReader<R: Read> {
+    inner: Source<R>,
+    #[cfg(feature = "encrypt")]
+    stream: Option<DecryptorBE32<Aes256Gcm>>,
+    sealed: bool,
+    buffer: Vec<u8>,
+    // How much of `buffer` has been handed on
+    position: usize,
+}
+
+// What a `SealedReader` reads: the file itself, or what age decrypts of it.
+enum Source<R: Read> {
+    Direct(R),
+    #[cfg(feature = "age")]
+    Age(Box<age::stream::StreamReader<R>>),
+}
+
+impl<R: Read> Read for Source<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::Direct(inner) => inner.read(buf),
+            // age reads a chunk that fails its tag as InvalidData and one cut short as
+            // UnexpectedEof, both of which are `Unauthentic` here
+            #[cfg(feature = "age")]
+            Source::Age(stream) => stream.read(buf).map_err(|e| match e.kind() {
+                io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => unauthentic(),
+                _ => e,
+            }),
+        }
+    }
+}
+
+impl<R: Read> SealedReader<R> {
+    pub fn new(mut inner: R, key: Option<&ChunkKey>) -> io::Result<SealedReader<R>> {
+        let passing = |inner| SealedReader {
+            inner,
+            #[cfg(feature = "encrypt")]
+            stream: None,
+            sealed: false,
+            buffer: Vec::new(),
+            position: 0,
+        };
+        let (key, bytes) = match key {
+            None => return Ok(passing(Source::Direct(inner))),
+            Some(key @ ChunkKey(Secret::Age { .. })) => {
+                return Ok(passing(age_source(inner, key)?));
+            }
+            Some(key @ ChunkKey(Secret::Aes(bytes))) => (key, bytes),
+        };
+        #[cfg(feature = "encrypt")]
+        {
+            let _ = key;
+            let mut header = [0; MAGIC.len() + NONCE_LEN];
+            if fill(&mut inner, &mut header)? < header.len() || &header[..MAGIC.len()] != MAGIC {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not an encrypted chunk, or one this version can't read",
+                ));
+            }
+            let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(bytes));
+            let nonce = &header[MAGIC.len()..];
+            let stream = DecryptorBE32::from_aead(cipher, nonce.into());
+            Ok(SealedReader {
+                inner: Source::Direct(inner),
+                stream: Some(stream),
+                sealed: true,
+                buffer: Vec::with_capacity(SEGMENT + TAG_LEN),
+                position: 0,
+            })
+        }
+        #[cfg(not(feature = "encrypt"))]
+        {
+            let _ = (&mut inner, key, bytes);
+            Err(unsupported(no_encrypt()))
+        }
+    }
+
+    // Read and open the next segment into `buffer`, which stays empty after the last.
+    #[cfg(feature = "encrypt")]
+    fn open_segment(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        self.position = 0;
+        let Some(stream) = &mut self.stream else {
+            return Ok(());
+        };
+        self.buffer.resize(SEGMENT + TAG_LEN, 0);
+        let read = fill(&mut self.inner, &mut self.buffer)?;
+        self.buffer.truncate(read);
+        if read < TAG_LEN {
+            // Cut short, possibly right after a segment that wasn't the last
+            self.buffer.clear();
+            return Err(unauthentic());
+        }
+        let opened = match read == SEGMENT + TAG_LEN {
+            true => stream.decrypt_next_in_place(&[], &mut self.buffer),
+            false => {
+                let stream = self.stream.take().expect("checked above");
+                stream.decrypt_last_in_place(&[], &mut self.buffer)
+            }
+        };
+        if opened.is_err() {
+            self.buffer.clear();
+            return Err(unauthentic());
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for SealedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.sealed {
+            return self.inner.read(buf);
+        }
+        #[cfg(feature = "encrypt")]
+        while self.position == self.buffer.len() {
+            if self.stream.is_none() {
+                return Ok(0);
+            }
+            self.open_segment()?;
+        }
+        let len = buf.len().min(self.buffer.len() - self.position);
+        buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+// A header that isn't age's or isn't to any of the identities reads as `Unauthentic`,
+// as a chunk that fails its tag does.
+#[cfg(feature = "age")]
+fn age_source<R: Read>(inner: R, key: &ChunkKey) -> io::Result<Source<R>> {
+    let identities = key
+        .age_identities()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let decrypted = age::Decryptor::new(inner).and_then(|decryptor| {
+        decryptor.decrypt(
+            identities
+                .iter()
+                .map(|identity| identity as &dyn age::Identity),
+        )
+    });
+    match decrypted {
+        Ok(stream) => Ok(Source::Age(Box::new(stream))),
+        Err(age::DecryptError::Io(e)) if e.kind() != io::ErrorKind::UnexpectedEof => Err(e),
+        Err(_) => Err(unauthentic()),
+    }
+}
+
+#[cfg(not(feature = "age"))]
+fn age_source<R: Read>(inner: R, key: &ChunkKey) -> io::Result<Source<R>> {
+    let _ = (inner, key);
+    Err(unsupported(no_age()))
+}
+
+// Read until `buf` is full or `reader` ends, returning how much was read.
+#[cfg(feature = "encrypt")]
+fn fill(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(all(test, feature = "encrypt"))]
+mod tests {
+    use super::*;
+
+    fn seal(key: &ChunkKey, data: &[u8]) -> Vec<u8> {
+        let mut writer = SealedWriter::new(Vec::new(), Some(key)).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn open(key: &ChunkKey, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        SealedReader::new(sealed, Some(key))?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn unauthentic(result: io::Result<Vec<u8>>) -> bool {
+        result.is_err_and(|e| e.get_ref().is_some_and(|inner| inner.is::<Unauthentic>()))
+    }
+
+    #[test]
+    fn chunks_open_with_the_key_they_were_sealed_with() {
+        let key = ChunkKey::from_bytes([7; KEY_LEN]);
+        // Empty, within one segment, exactly one, and across several
+        for len in [0, 1, SEGMENT - 1, SEGMENT, SEGMENT + 1, 3 * SEGMENT + 17] {
+            let data: Vec<u8> = (0..len).map(|i| (i * 31 % 251) as u8).collect();
+            let sealed = seal(&key, &data);
+            let segments = len / SEGMENT + 1;
+            assert_eq!(sealed.len(), 15 + len + segments * TAG_LEN, "{} bytes", len);
+            assert_eq!(sealed.len() as u64, sealed_len(len as u64));
+            assert_eq!(open(&key, &sealed).unwrap(), data, "{} bytes", len);
+        }
+        // Every chunk has a nonce of its own
+        assert_ne!(seal(&key, b"same"), seal(&key, b"same"));
+    }
+
+    #[test]
+    fn a_wrong_key_or_any_change_fails_before_anything_is_read() {
+        let key = ChunkKey::from_bytes([7; KEY_LEN]);
+        let data = vec![42u8; 2 * SEGMENT + 5];
+        let sealed = seal(&key, &data);
+        assert!(unauthentic(open(
+            &ChunkKey::from_bytes([8; KEY_LEN]),
+            &sealed
+        )));
+        let mut flipped = sealed.clone();
+        flipped[20] ^= 1;
+        assert!(unauthentic(open(&key, &flipped)));
+        // Cut short, both within a segment and where one ends
+        assert!(unauthentic(open(&key, &sealed[..sealed.len() - 1])));
+        assert!(unauthentic(open(&key, &sealed[..15 + SEGMENT + TAG_LEN])));
+        // Nothing of a damaged segment is handed on
+        let mut reader = SealedReader::new(flipped.as_slice(), Some(&key)).unwrap();
+        let mut buf = [0u8; 100];
+        assert!(reader.read(&mut buf).is_err());
+        assert_eq!(buf, [0u8; 100]);
+    }
+
+    #[test]
+    fn passphrases_give_the_same_key_for_the_same_salt() {
+        let mut encryption = Encryption::with_passphrase().unwrap();
+        // Cheap enough for a debug build
+        let kdf = encryption.kdf.as_mut().unwrap();
+        (kdf.memory_kib, kdf.iterations, kdf.parallelism) = (64, 1, 1);
+        let key = ChunkKey::derive("correct horse", &encryption).unwrap();
+        let again = ChunkKey::derive("correct horse", &encryption).unwrap();
+        let other = ChunkKey::derive("battery staple", &encryption).unwrap();
+        assert_eq!(key.fingerprint(), again.fingerprint());
+        assert_ne!(key.fingerprint(), other.fingerprint());
+        // Every split gets a salt of its own
+        assert_ne!(Encryption::with_passphrase().unwrap(), encryption);
+    }
+
+    #[test]
+    fn key_files_hold_the_key_and_nothing_else() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("key.bin");
+        let key = ChunkKey::generate_file(&path).unwrap();
+        let read = ChunkKey::read_file(&path, false).unwrap();
+        assert_eq!(read.fingerprint(), key.fingerprint());
+        assert!(matches!(
+            ChunkKey::generate_file(&path),
+            Err(SplitterError::Io { source, .. }) if source.kind() == io::ErrorKind::AlreadyExists
+        ));
+        let other = ChunkKey::generate_file(&temp.path().join("other.bin")).unwrap();
+        assert_ne!(other.fingerprint(), key.fingerprint());
+
+        let encryption = Encryption {
+            fingerprint: Some(key.fingerprint()),
+            ..Encryption::with_key_file()
+        };
+        assert!(key.check(&encryption, temp.path()).is_ok());
+        assert!(matches!(
+            other.check(&encryption, temp.path()),
+            Err(SplitterError::WrongKey { .. })
+        ));
+
+        let short = temp.path().join("short.bin");
+        fs::write(&short, [1; KEY_LEN - 1]).unwrap();
+        // Left readable by anyone, which is refused first otherwise
+        assert!(matches!(
+            ChunkKey::read_file(&short, true),
+            Err(SplitterError::Io { path, source, .. })
+                if path == short && source.kind() == io::ErrorKind::InvalidData
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn key_files_anyone_can_read_are_refused_unless_allowed() {
+        use std::os::unix::fs::PermissionsExt;
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("key.bin");
+        ChunkKey::generate_file(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        let refused = ChunkKey::read_file(&path, false).unwrap_err();
+        assert!(
+            refused.to_string().contains("can be read by anyone"),
+            "{}",
+            refused
+        );
+        assert!(ChunkKey::read_file(&path, true).is_ok());
+    }
+}
+
+#[cfg(all(test, feature = "age"))]
+mod age_tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+
+    // A key to encrypt to a new identity with, and one holding that identity.
+    fn age_keys(temp: &Path, name: &str) -> (ChunkKey, ChunkKey) {
+        let identity = age::x25519::Identity::generate();
+        let public = identity.to_public().to_string();
+        let path = temp.join(name);
+        let file = format!(
+            "# public key: {}\n{}\n",
+            public,
+            identity.to_string().expose_secret()
+        );
+        fs::write(&path, file).unwrap();
+        (
+            ChunkKey::age_recipients(&[public]).unwrap(),
+            ChunkKey::read_age_identity(&path).unwrap(),
+        )
+    }
+
+    fn open(key: &ChunkKey, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        SealedReader::new(sealed, Some(key))?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn unauthentic(result: io::Result<Vec<u8>>) -> bool {
+        result.is_err_and(|e| e.get_ref().is_some_and(|inner| inner.is::<Unauthentic>()))
+    }
+
+    #[test]
+    fn age_chunks_are_age_files_to_the_recipients() {
+        let temp = tempfile::tempdir().unwrap();
+        let (recipients, identity) = age_keys(temp.path(), "key.txt");
+        let data: Vec<u8> = (0..200_000).map(|i| (i * 31 % 251) as u8).collect();
+        let mut writer = SealedWriter::new(Vec::new(), Some(&recipients)).unwrap();
+        writer.write_all(&data).unwrap();
+        let sealed = writer.finish().unwrap();
+        assert!(sealed.starts_with(b"age-encryption.org/v1\n"));
+        assert_eq!(open(&identity, &sealed).unwrap(), data);
+
+        // What age itself makes of it
+        let age_identity: age::x25519::Identity = match &identity.0 {
+            Secret::Age { identities, .. } => identities[0].parse().unwrap(),
+            Secret::Aes(_) => unreachable!(),
+        };
+        let mut decrypted = Vec::new();
+        age::Decryptor::new(sealed.as_slice())
+            .unwrap()
+            .decrypt(std::iter::once(&age_identity as &dyn age::Identity))
+            .unwrap()
+            .read_to_end(&mut decrypted)
+            .unwrap();
+        assert_eq!(decrypted, data);
+
+        // Another identity, a change or a chunk cut short all fail
+        let (_, other) = age_keys(temp.path(), "other.txt");
+        assert!(unauthentic(open(&other, &sealed)));
+        let mut flipped = sealed.clone();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 1;
+        assert!(unauthentic(open(&identity, &flipped)));
+        assert!(unauthentic(open(&identity, &sealed[..sealed.len() - 1])));
+    }
+
+    #[test]
+    fn identities_are_told_apart_by_the_recipients_recorded() {
+        let temp = tempfile::tempdir().unwrap();
+        let (recipients, identity) = age_keys(temp.path(), "key.txt");
+        let (_, other) = age_keys(temp.path(), "other.txt");
+        let encryption = recipients.record(Encryption::with_age());
+        assert!(identity.check(&encryption, temp.path()).is_ok());
+        for wrong in [other, ChunkKey::from_bytes([7; KEY_LEN])] {
+            assert!(matches!(
+                wrong.check(&encryption, temp.path()),
+                Err(SplitterError::WrongKey { .. })
+            ));
+        }
+        for recipients in [vec!["age1nope".to_string()], Vec::new()] {
+            assert!(matches!(
+                ChunkKey::age_recipients(&recipients),
+                Err(SplitterError::InvalidOption {
+                    field: "age_recipient",
+                    ..
+                })
+            ));
+        }
+        let empty = temp.path().join("empty.txt");
+        fs::write(&empty, "# nothing here\n").unwrap();
+        assert!(matches!(
+            ChunkKey::read_age_identity(&empty),
+            Err(SplitterError::Io { path, source, .. })
+                if path == empty && source.kind() == io::ErrorKind::InvalidData
+        ));
+    }
+}
@@ -0,0 +1,1098 @@
+// Working out what is wrong with a chunk directory, for `doctor`. Every check there is
+// runs at once, `verify`'s among them, and each problem found becomes one `Finding`
+// that says what it is and what to do about it, most pressing first. Where `verify`
+// and `reconstruct` stop at the first thing wrong with a bare error, this is for
+// telling the whole story: the metadata, how the chunks are named, which are missing,
+// short or damaged, what else is in the directory and whether an earlier
+// reconstruction was left there.
+//
+// The report is meant to be read by programs as well as people. Findings are told
+// apart by their `code` rather than their message, which may be reworded; codes are
+// never reused for something else, and `DIAGNOSIS_VERSION` goes up should a field
+// ever change its meaning.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::archive::{ArchiveStore, is_archive};
+use crate::cancel::CancelToken;
+use crate::chunkset::ChunkSet;
+use crate::compat::{self, Compat};
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::ProgressEvent;
+use crate::import::{Doubt, ForeignNaming, ForeignSet, detect_foreign};
+use crate::manifest::{Compression, MANIFEST_NAME, MANIFEST_VERSION, Manifest, SEAL_FIELD, Seal};
+use crate::natural::natural_cmp;
+use crate::scratch;
+use crate::size::format_size;
+use crate::store::ChunkStore;
+use crate::sums::sums_name;
+use crate::timestamps::{TimestampHint, TimestampReport, check_timestamps};
+use crate::transfer::TRANSFER_STATE_NAME;
+use crate::{
+    VerifyReport, chunk_health, chunk_index, default_output_name, is_set_file, numbered_index,
+    par2, parity, store, verify,
+};
+
+pub const DIAGNOSIS_VERSION: u32 = 1;
+
+// Files named in a finding's message; the rest are only counted, though all of them
+// are in its `files`
+const NAMED: usize = 5;
+
+// How much a finding matters, most first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    // The file can't be reconstructed as things are, or not as it was
+    Error,
+    // It can, but something is off that may matter
+    Warning,
+    // Worth knowing, though nothing is wrong
+    Note,
+}
+
+// What a finding is about, which stays the same from one version to the next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FindingCode {
+    // Neither chunks nor pieces another tool split a file into
+    NoChunks,
+    // Subdirectories that are chunk sets themselves
+    NestedSets,
+    MetadataCorrupt,
+    // Changed since it was sealed, as by an edit by hand
+    MetadataModified,
+    // From before manifests were sealed
+    MetadataUnsealed,
+    MetadataMissing,
+    // A manifest from a newer version than this one, which may record what it ignores
+    MetadataNewer,
+    // From before manifests recorded their version
+    MetadataUnversioned,
+    // Pieces `import` could take over
+    ForeignPieces,
+    // Two files with the same number, such as chunk7 and chunk007
+    DuplicateChunks,
+    MissingChunks,
+    // Shorter than the manifest records
+    TruncatedChunks,
+    // Longer than the manifest records
+    OversizedChunks,
+    // No longer what was recorded for them: their hash, their codec's checksum or that
+    // in a checksum file
+    DamagedChunks,
+    // Of a set without recorded sizes, chunks before the last that differ from the first
+    UnevenChunks,
+    // Files a checksum file lists that aren't there
+    ChecksumMissing,
+    ParityDamaged,
+    // With random names, files named like chunks the manifest doesn't list
+    UnlistedChunks,
+    // Nothing recorded to check the chunks' contents against
+    Unhashed,
+    // The hidden file a reconstruction writes until it is finished
+    InterruptedOutput,
+    // A file under the name the reconstruction would be given
+    OldOutput,
+    // Files that are nothing to do with the set
+    StrayFiles,
+    // With `--timestamps`, chunks modified when the others weren't, as one rewritten
+    // since the split
+    TimestampOutliers,
+    // With `--timestamps`, no times from the split to compare with: none were recorded,
+    // or a copy since didn't keep them
+    TimestampsUnrecorded,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Finding {
+    pub code: FindingCode,
+    pub severity: Severity,
+    pub message: String,
+    // What to do about it, when there is something
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<String>,
+    // The files it is about, by name
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+}
+
+// The worst of the findings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    // Nothing worse than notes
+    Healthy,
+    // Warnings, but no errors
+    Degraded,
+    Broken,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataState {
+    Present,
+    Missing,
+    Corrupt,
+    // Readable, but changed since it was sealed
+    Modified,
+}
+
+// How the chunks are named, and so how their order is known.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Naming {
+    // chunk000, chunk001, …
+    Numbered,
+    // In the order the manifest lists them, under random names or those another tool
+    // gave them before `import`
+    Listed,
+    // FILE.partaa, … as `split` names them, see `Compat`
+    Split,
+    // FILE.001, … as HJSplit names them
+    Hjsplit,
+    // Pieces another tool split a file into that aren't a set yet, see `import`
+    Foreign,
+}
+
+// Outcome of `diagnose`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Diagnosis {
+    // Of this report's layout, `DIAGNOSIS_VERSION`
+    pub version: u32,
+    pub directory: PathBuf,
+    pub status: Status,
+    pub metadata: MetadataState,
+    // The manifest's own version, when it could be read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata_version: Option<u32>,
+    // None when nothing here is named like a chunk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub naming: Option<Naming>,
+    // Chunks found, and how many the manifest lists when it does
+    pub chunks: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_chunks: Option<usize>,
+    pub total_size: u64,
+    // Whether the chunks' contents could be checked, by recorded hashes, PAR2 data or
+    // checksum files
+    pub hashed: bool,
+    // Most pressing first
+    pub findings: Vec<Finding>,
+}
+
+impl Diagnosis {
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    }
+}
+
+// A file in the directory, as found when it was listed.
+struct Entry {
+    name: String,
+    size: u64,
+    is_file: bool,
+}
+
+// Check everything about the chunk set in `directory` that can be checked without
+// reconstructing it, hashing the chunks as `verify` does. An error is for the checks
+// themselves failing, such as the directory not being readable; whatever is wrong with
+// the set is in the findings. `directory` may be a zip or tar of the chunks, of which
+// only the chunks and their metadata are looked at. With `timestamps`, the chunks' times
+// are checked too, to within that tolerance; see `check_timestamps`. A manifest changed
+// after it was sealed is a finding, and only gone by with `accept_modified`.
+pub fn diagnose(
+    directory: &Path,
+    timestamps: Option<Duration>,
+    accept_modified: bool,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Diagnosis> {
+    let archive = is_archive(directory);
+    let mut findings = Vec::new();
+    let entries = match archive {
+        true => Vec::new(),
+        false => list(directory)?,
+    };
+    let names: BTreeSet<&str> = entries
+        .iter()
+        .filter(|entry| entry.is_file)
+        .map(|entry| entry.name.as_str())
+        .collect();
+
+    let loaded = match archive {
+        // An archive's seal is checked as it is read, so only a broken one is found
+        true => ArchiveStore::open(directory, accept_modified).and_then(|archive| {
+            let manifest = archive.read_info()?;
+            Ok(manifest.map(|manifest| (manifest, Seal::Intact)))
+        }),
+        false => Manifest::load_sealed(directory),
+    };
+    let (metadata, manifest) = match loaded {
+        Ok(Some((manifest, Seal::Broken))) => {
+            findings.push(modified());
+            (MetadataState::Modified, Some(manifest))
+        }
+        Ok(Some((manifest, seal))) => {
+            if seal == Seal::Absent {
+                findings.push(finding(
+                    FindingCode::MetadataUnsealed,
+                    Severity::Note,
+                    format!(
+                        "{} has no {}, so an edit to it can't be told from damage",
+                        MANIFEST_NAME, SEAL_FIELD
+                    ),
+                    Some(format!("Run reseal {} to add one", directory.display())),
+                    vec![MANIFEST_NAME.to_string()],
+                ));
+            }
+            (MetadataState::Present, Some(manifest))
+        }
+        Err(SplitterError::MetadataModified { .. }) => {
+            findings.push(modified());
+            (MetadataState::Modified, None)
+        }
+        Ok(None) => (MetadataState::Missing, None),
+        Err(SplitterError::MetadataCorrupt { source, .. }) => {
+            let random = names.iter().any(|name| store::is_random_name(name));
+            let suggestion = match random {
+                true => {
+                    "The chunks have random names, whose order only info.json recorded: \
+                         restore it from another copy of the set"
+                }
+                false => {
+                    "Restore info.json from another copy of the set, or move it aside \
+                          to reconstruct the chunks in the order of their numbers, under a \
+                          name given with --output"
+                }
+            };
+            findings.push(finding(
+                FindingCode::MetadataCorrupt,
+                Severity::Error,
+                format!("{} can't be read: {}", MANIFEST_NAME, source),
+                Some(suggestion.to_string()),
+                vec![MANIFEST_NAME.to_string()],
+            ));
+            (MetadataState::Corrupt, None)
+        }
+        Err(e) => return Err(e),
+    };
+    if let Some(manifest) = &manifest {
+        if manifest.version > MANIFEST_VERSION {
+            findings.push(finding(
+                FindingCode::MetadataNewer,
+                Severity::Warning,
+                format!(
+                    "{} is version {}, written by a newer version of this tool than this one \
+                     (version {}); whatever it records that this one doesn't know of is ignored",
+                    MANIFEST_NAME, manifest.version, MANIFEST_VERSION
+                ),
+                Some("Use the version the set was split with, or a newer one".to_string()),
+                Vec::new(),
+            ));
+        } else if manifest.version == 0 {
+            findings.push(finding(
+                FindingCode::MetadataUnversioned,
+                Severity::Note,
+                format!(
+                    "{} is from before manifests recorded their version, so may list \
+                     nothing but the file's name",
+                    MANIFEST_NAME
+                ),
+                None,
+                Vec::new(),
+            ));
+        }
+    }
+
+    let health = chunk_health(directory, accept_modified)?;
+    let listed = manifest
+        .as_ref()
+        .filter(|manifest| !manifest.chunks.is_empty());
+    let naming = match &manifest {
+        Some(manifest) if manifest.random_names => Some(Naming::Listed),
+        Some(Manifest {
+            compat: Some(compat),
+            ..
+        }) => Some(compat_naming(*compat)),
+        _ if names.iter().any(|name| numbered_index(name).is_some()) => Some(Naming::Numbered),
+        _ => names
+            .iter()
+            .find_map(|name| compat::parse(name))
+            .map(|(compat, _, _)| compat_naming(compat))
+            .or(manifest.as_ref().map(|_| Naming::Numbered)),
+    };
+
+    // Pieces another tool made, which only matter without an info.json, and aren't our
+    // own chunks, which `detect_foreign` takes for `split -d`'s
+    let mut foreign = match metadata == MetadataState::Missing && !archive {
+        true => detect_foreign(directory)?,
+        false => Vec::new(),
+    };
+    foreign.retain(|set| {
+        !set.pieces
+            .iter()
+            .all(|piece| numbered_index(&piece.name).is_some())
+    });
+    let naming = match (naming, foreign.is_empty()) {
+        (None, false) => Some(Naming::Foreign),
+        (naming, _) => naming,
+    };
+    if let Some(set) = foreign.first() {
+        findings.push(foreign_finding(set, health.chunks > 0, foreign.len()));
+    } else if metadata == MetadataState::Missing && health.chunks > 0 {
+        findings.push(finding(
+            FindingCode::MetadataMissing,
+            Severity::Warning,
+            format!(
+                "There is no {}: the chunks can still be joined in the order of their \
+                 numbers, but nothing records the file's name, or their sizes to check them \
+                 against",
+                MANIFEST_NAME
+            ),
+            Some(
+                "Reconstruct with --output to name the file; if a split was interrupted with \
+                 --keep-partial, split the file again"
+                    .to_string(),
+            ),
+            Vec::new(),
+        ));
+    }
+
+    let found_any = health.chunks > 0 || !foreign.is_empty();
+    if !found_any && listed.is_none() && metadata != MetadataState::Corrupt {
+        let empty = manifest
+            .as_ref()
+            .is_some_and(|manifest| manifest.version > 0);
+        if !empty {
+            findings.push(finding(
+                FindingCode::NoChunks,
+                Severity::Error,
+                "Nothing here is named like a chunk, or like the pieces another tool splits \
+                 files into"
+                    .to_string(),
+                None,
+                Vec::new(),
+            ));
+        }
+        let nested: Vec<String> = entries
+            .iter()
+            .filter(|entry| !entry.is_file && is_chunk_directory(&directory.join(&entry.name)))
+            .map(|entry| entry.name.clone())
+            .collect();
+        if let Some(first) = nested.first() {
+            findings.push(finding(
+                FindingCode::NestedSets,
+                Severity::Note,
+                format!(
+                    "{} {}",
+                    describe(&nested, "subdirectory", "subdirectories"),
+                    match nested.len() {
+                        1 => "holds a chunk set of its own",
+                        _ => "hold chunk sets of their own",
+                    }
+                ),
+                Some(format!("Run doctor on {}", directory.join(first).display())),
+                nested,
+            ));
+        }
+    }
+
+    // Two files of one number, which `reconstruct` refuses to choose between
+    if naming != Some(Naming::Listed) {
+        let mut numbered: BTreeMap<u64, Vec<&str>> = BTreeMap::new();
+        for name in &names {
+            if let Some(index) = chunk_index(name) {
+                numbered.entry(index).or_default().push(name);
+            }
+        }
+        let duplicates: Vec<(u64, Vec<&str>)> = numbered
+            .into_iter()
+            .filter(|(_, names)| names.len() > 1)
+            .collect();
+        if !duplicates.is_empty() {
+            let pairs: Vec<String> = duplicates
+                .iter()
+                .take(NAMED)
+                .map(|(index, names)| match names.as_slice() {
+                    [first, second] => format!("{} and {} are both chunk {}", first, second, index),
+                    _ => format!("{} are all chunk {}", names.join(", "), index),
+                })
+                .collect();
+            findings.push(finding(
+                FindingCode::DuplicateChunks,
+                Severity::Error,
+                format!(
+                    "More than one file is numbered as the same chunk: {}",
+                    pairs.join("; ")
+                ),
+                Some(
+                    "Move aside whichever doesn't belong to the set; reconstruct won't guess"
+                        .to_string(),
+                ),
+                duplicates
+                    .iter()
+                    .flat_map(|(_, names)| names.iter().map(|name| name.to_string()))
+                    .collect(),
+            ));
+        }
+    }
+
+    // Missing chunks by the manifest's list, which unlike `chunk_health` also catches
+    // those missing after the last one still here
+    let set = match ChunkSet::open(directory, accept_modified) {
+        Ok(set) => Some(set),
+        Err(
+            SplitterError::MissingChunks { .. }
+            | SplitterError::NoChunks { .. }
+            | SplitterError::MetadataCorrupt { .. }
+            | SplitterError::MetadataModified { .. },
+        ) => None,
+        Err(e) => return Err(e),
+    };
+    let span = manifest
+        .as_ref()
+        .and_then(|manifest| manifest.span.as_ref());
+    let missing: Vec<u64> = match (&set, listed, archive) {
+        (Some(set), Some(_), false) => set
+            .iter()
+            .filter(|chunk| span.is_none_or(|span| span.here().contains(&chunk.index)))
+            .filter(|chunk| !chunk.path.exists())
+            .map(|chunk| chunk.index as u64)
+            .collect(),
+        _ => health.missing.clone(),
+    };
+    let name_of = |index: u64| match listed.and_then(|manifest| manifest.chunks.get(index as usize))
+    {
+        Some(entry) => entry.file().to_string(),
+        None => store::chunk_name(index as usize, Compression::None),
+    };
+    let mut missing_names: Vec<String> = missing.iter().map(|&index| name_of(index)).collect();
+    // A file other chunks are stored in as well is named once
+    let mut seen = BTreeSet::new();
+    missing_names.retain(|name| seen.insert(name.clone()));
+
+    // Sizes against those recorded, where the file's own size says what it holds
+    let mut truncated = Vec::new();
+    let mut oversized = Vec::new();
+    if let (Some(set), Some(_), false) = (&set, listed, archive) {
+        let mut seen = BTreeSet::new();
+        for chunk in set.iter().filter(|chunk| chunk.compression.is_none()) {
+            if !seen.insert(&chunk.path) {
+                continue;
+            }
+            let Ok(metadata) = fs::metadata(&chunk.path) else {
+                continue;
+            };
+            let name = chunk.path.file_name().unwrap_or_default();
+            let detail = (
+                name.to_string_lossy().into_owned(),
+                metadata.len(),
+                chunk.len,
+            );
+            if metadata.len() < chunk.len {
+                truncated.push(detail);
+            } else if metadata.len() > chunk.len {
+                oversized.push(detail);
+            }
+        }
+    }
+
+    // The contents, by whatever there is to check them against
+    let report = match metadata {
+        MetadataState::Corrupt | MetadataState::Modified => None,
+        _ => Some(verify(directory, &[], accept_modified, progress, cancel)?),
+    };
+    // Said once, with the first of the findings about chunks that are lost
+    let mut recovery = Some(recovery(report.as_ref(), manifest.as_ref()));
+    if !missing.is_empty() {
+        let total = listed.map_or(health.chunks + missing.len(), |manifest| {
+            span.map_or(manifest.chunks.len(), |span| span.here().len())
+        });
+        findings.push(finding(
+            FindingCode::MissingChunks,
+            Severity::Error,
+            format!(
+                "{} of {} chunks {} missing: {}",
+                missing.len(),
+                total,
+                match missing.len() {
+                    1 => "is",
+                    _ => "are",
+                },
+                named(&missing_names)
+            ),
+            recovery.take(),
+            missing_names.clone(),
+        ));
+    }
+    if !truncated.is_empty() {
+        findings.push(size_finding(
+            FindingCode::TruncatedChunks,
+            ("appears truncated", "appear truncated"),
+            &truncated,
+            recovery.take(),
+        ));
+    }
+    if !oversized.is_empty() {
+        findings.push(size_finding(
+            FindingCode::OversizedChunks,
+            (
+                "is larger than info.json records",
+                "are larger than info.json records",
+            ),
+            &oversized,
+            recovery.take(),
+        ));
+    }
+    if listed.is_none() && !health.uneven.is_empty() {
+        let uneven: Vec<String> = health.uneven.iter().map(|&index| name_of(index)).collect();
+        findings.push(finding(
+            FindingCode::UnevenChunks,
+            Severity::Warning,
+            format!(
+                "{} before the last {} in size from the first, which no split makes, so \
+                 may be truncated or from another split: {}",
+                describe(&uneven, "chunk", "chunks"),
+                match uneven.len() {
+                    1 => "differs",
+                    _ => "differ",
+                },
+                named(&uneven)
+            ),
+            None,
+            uneven,
+        ));
+    }
+
+    let mut hashed = false;
+    if let Some(report) = &report {
+        hashed = report.hashed || report.par2 || !report.checksum_files.is_empty();
+        let already: BTreeSet<&str> = missing_names
+            .iter()
+            .map(String::as_str)
+            .chain(truncated.iter().map(|(name, _, _)| name.as_str()))
+            .chain(oversized.iter().map(|(name, _, _)| name.as_str()))
+            .collect();
+        let (parity_lost, damaged): (Vec<String>, Vec<String>) = report
+            .mismatched
+            .iter()
+            .filter(|name| !already.contains(name.as_str()))
+            .filter(|name| archive || names.contains(name.as_str()) || is_redundancy(name))
+            .cloned()
+            .partition(|name| is_redundancy(name));
+        if !damaged.is_empty() {
+            findings.push(finding(
+                FindingCode::DamagedChunks,
+                Severity::Error,
+                format!(
+                    "{} no longer {} what was recorded for {}: {}",
+                    describe(&damaged, "chunk", "chunks"),
+                    match damaged.len() {
+                        1 => "matches",
+                        _ => "match",
+                    },
+                    match damaged.len() {
+                        1 => "it",
+                        _ => "them",
+                    },
+                    named(&damaged)
+                ),
+                recovery.take(),
+                damaged,
+            ));
+        }
+        if !report.checksum_missing.is_empty() {
+            findings.push(finding(
+                FindingCode::ChecksumMissing,
+                Severity::Error,
+                format!(
+                    "{} listed in {} {} missing: {}",
+                    describe(&report.checksum_missing, "file", "files"),
+                    report.checksum_files.join(", "),
+                    match report.checksum_missing.len() {
+                        1 => "is",
+                        _ => "are",
+                    },
+                    named(&report.checksum_missing)
+                ),
+                Some(
+                    "Copy them from wherever the rest came from; the set is incomplete without \
+                     them"
+                        .to_string(),
+                ),
+                report.checksum_missing.clone(),
+            ));
+        }
+        if !parity_lost.is_empty() {
+            findings.push(finding(
+                FindingCode::ParityDamaged,
+                Severity::Warning,
+                format!(
+                    "{} of the parity {} missing or damaged: {}",
+                    describe(&parity_lost, "file", "files"),
+                    match parity_lost.len() {
+                        1 => "is",
+                        _ => "are",
+                    },
+                    named(&parity_lost)
+                ),
+                Some("Run repair, which computes them again from the chunks".to_string()),
+                parity_lost,
+            ));
+        }
+        if !hashed && found_any {
+            findings.push(finding(
+                FindingCode::Unhashed,
+                Severity::Note,
+                "No hashes were recorded, so the chunks could only be checked by their number \
+                 and size, not by what they hold"
+                    .to_string(),
+                Some("Split with --hash to have their contents checked".to_string()),
+                Vec::new(),
+            ));
+        }
+    }
+    if !health.unexpected.is_empty() {
+        findings.push(finding(
+            FindingCode::UnlistedChunks,
+            Severity::Warning,
+            format!(
+                "{} named like chunks that {} doesn't list, perhaps from another split into \
+                 the same directory: {}",
+                describe(&health.unexpected, "file", "files"),
+                MANIFEST_NAME,
+                named(&health.unexpected)
+            ),
+            Some("Move them out; reconstruct leaves them out".to_string()),
+            health.unexpected.clone(),
+        ));
+    }
+
+    // What an earlier reconstruction left in the directory
+    let output = match (&manifest, archive) {
+        (_, true) => None,
+        (Some(manifest), _) => manifest.output_name().ok(),
+        (None, _) if metadata == MetadataState::Missing && health.chunks > 0 => {
+            default_output_name(directory, accept_modified).ok()
+        }
+        (None, _) => None,
+    };
+    let expected_size = match listed {
+        Some(manifest) => manifest.chunks.iter().map(|entry| entry.size).sum(),
+        None => health.total_size,
+    };
+    if let Some(output) = &output {
+        let parts: Vec<String> = names
+            .iter()
+            .filter(|name| scratch::is_temp_of(name, output))
+            .map(|name| name.to_string())
+            .collect();
+        if !parts.is_empty() {
+            findings.push(finding(
+                FindingCode::InterruptedOutput,
+                Severity::Warning,
+                format!(
+                    "{} {} left by a reconstruction that was interrupted",
+                    named(&parts),
+                    match parts.len() {
+                        1 => "was",
+                        _ => "were",
+                    }
+                ),
+                Some(match parts.len() {
+                    1 => "Remove it; reconstructing again starts over".to_string(),
+                    _ => "Remove them; reconstructing again starts over".to_string(),
+                }),
+                parts,
+            ));
+        }
+        if let Some(entry) = entries
+            .iter()
+            .find(|entry| entry.is_file && &entry.name == output)
+        {
+            let (severity, message, suggestion) = match entry.size == expected_size {
+                true => (
+                    Severity::Note,
+                    format!(
+                        "{} is here already, as large as the chunks add up to: the file looks \
+                         to have been reconstructed before",
+                        output
+                    ),
+                    "Reconstructing again replaces it; remove it, or the chunks if they are \
+                     no longer needed",
+                ),
+                false => (
+                    Severity::Warning,
+                    format!(
+                        "{} is here, {} where the chunks add up to {}: a reconstruction \
+                         that didn't finish, or another file by that name",
+                        output,
+                        format_size(entry.size),
+                        format_size(expected_size)
+                    ),
+                    "Reconstructing again replaces it; give --output to keep it",
+                ),
+            };
+            findings.push(finding(
+                FindingCode::OldOutput,
+                severity,
+                message,
+                Some(suggestion.to_string()),
+                vec![output.clone()],
+            ));
+        }
+    }
+
+    // What the chunks' times hint at, when asked, which is never more than a note
+    if let Some(tolerance) = timestamps
+        && !archive
+        && found_any
+    {
+        match check_timestamps(directory, tolerance, accept_modified) {
+            Ok(report) => findings.extend(timestamp_findings(&report)),
+            Err(e) => debug!("not checking the times in {}: {}", directory.display(), e),
+        }
+    }
+
+    // Whatever else is here
+    let pieces: BTreeSet<&str> = foreign
+        .iter()
+        .flat_map(|set| set.pieces.iter().map(|piece| piece.name.as_str()))
+        .collect();
+    let stray: Vec<String> = names
+        .iter()
+        .filter(|name| !name.starts_with('.'))
+        .filter(|name| !(is_set_file(name) || sums_name(name).is_some()))
+        .filter(|name| **name != TRANSFER_STATE_NAME)
+        .filter(|name| Some(**name) != output.as_deref())
+        .filter(|name| !pieces.contains(*name))
+        .filter(|name| {
+            listed
+                .is_none_or(|manifest| !manifest.chunks.iter().any(|entry| entry.file() == **name))
+        })
+        .map(|name| name.to_string())
+        .collect();
+    if !stray.is_empty() && found_any {
+        findings.push(finding(
+            FindingCode::StrayFiles,
+            Severity::Note,
+            format!(
+                "{} here {} no part of the set: {}",
+                describe(&stray, "file", "files"),
+                match stray.len() {
+                    1 => "is",
+                    _ => "are",
+                },
+                named(&stray)
+            ),
+            None,
+            stray,
+        ));
+    }
+
+    findings.sort_by_key(|finding| finding.severity);
+    let status = match findings.first().map(|finding| finding.severity) {
+        Some(Severity::Error) => Status::Broken,
+        Some(Severity::Warning) => Status::Degraded,
+        _ => Status::Healthy,
+    };
+    let chunks = match foreign.first() {
+        Some(set) if health.chunks == 0 => set.pieces.len(),
+        _ => health.chunks,
+    };
+    let total_size = match foreign.first() {
+        Some(set) if health.chunks == 0 => set.total_size(),
+        _ => health.total_size,
+    };
+    debug!(
+        "diagnosed {}: {} findings",
+        directory.display(),
+        findings.len()
+    );
+    Ok(Diagnosis {
+        version: DIAGNOSIS_VERSION,
+        directory: directory.to_path_buf(),
+        status,
+        metadata,
+        metadata_version: manifest.as_ref().map(|manifest| manifest.version),
+        naming,
+        chunks,
+        expected_chunks: listed.map(|manifest| manifest.chunks.len()),
+        total_size,
+        hashed,
+        findings,
+    })
+}
+
+fn timestamp_findings(report: &TimestampReport) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    if !report.kept {
+        let message = match report.recorded {
+            false => "No times were recorded when the set was split",
+            true => {
+                "Most chunks no longer have the times recorded when the set was split, as \
+                 after a copy that didn't keep them"
+            }
+        };
+        findings.push(finding(
+            FindingCode::TimestampsUnrecorded,
+            Severity::Note,
+            format!(
+                "{}, so only chunks modified long after the rest stand out",
+                message
+            ),
+            (!report.recorded)
+                .then(|| "Split with --record-times to have them recorded".to_string()),
+            Vec::new(),
+        ));
+    }
+    if !report.hints.is_empty() {
+        let files: Vec<String> = report.hints.iter().map(|hint| hint.name.clone()).collect();
+        let described: Vec<String> = report.hints.iter().map(TimestampHint::describe).collect();
+        findings.push(finding(
+            FindingCode::TimestampOutliers,
+            Severity::Note,
+            format!(
+                "{} stand out by when {} modified: {}",
+                describe(&files, "chunk", "chunks"),
+                match files.len() {
+                    1 => "it was",
+                    _ => "they were",
+                },
+                named(&described)
+            ),
+            Some(
+                "Times are only a hint, as copying tools change them; should these chunks not \
+                 verify, heal them from another copy"
+                    .to_string(),
+            ),
+            files,
+        ));
+    }
+    findings
+}
+
+fn list(directory: &Path) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(directory).at(directory)? {
+        let entry = entry.at(directory)?;
+        let path = entry.path();
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let metadata = fs::metadata(&path).at(&path)?;
+        entries.push(Entry {
+            name,
+            size: metadata.len(),
+            is_file: metadata.is_file(),
+        });
+    }
+    entries.sort_by(|a, b| natural_cmp(&a.name, &b.name));
+    Ok(entries)
+}
+
+fn finding(
+    code: FindingCode,
+    severity: Severity,
+    message: String,
+    suggestion: Option<String>,
+    files: Vec<String>,
+) -> Finding {
+    Finding {
+        code,
+        severity,
+        message,
+        suggestion,
+        files,
+    }
+}
+
+fn modified() -> Finding {
+    finding(
+        FindingCode::MetadataModified,
+        Severity::Error,
+        format!(
+            "{} has been modified or corrupted since it was written: it no longer matches \
+             its {}, so nothing it says can be relied on",
+            MANIFEST_NAME, SEAL_FIELD
+        ),
+        Some(
+            "If the changes were meant, run reseal on the directory; if not, restore \
+             info.json from another copy of the set. --accept-modified-metadata goes on \
+             with it as it is"
+                .to_string(),
+        ),
+        vec![MANIFEST_NAME.to_string()],
+    )
+}
+
+// Pieces another tool split a file into. Without chunks of ours alongside, nothing
+// but `import` makes anything of them.
+fn foreign_finding(set: &ForeignSet, chunks: bool, sets: usize) -> Finding {
+    let tool = match set.naming {
+        ForeignNaming::Numbered { first: 0 } => "split -d",
+        ForeignNaming::Numbered { .. } => "HJSplit or 7-Zip",
+        ForeignNaming::Lettered => "GNU split",
+    };
+    let mut message = format!(
+        "There is no {}, but {} pieces named {}… look to have been split by {}",
+        MANIFEST_NAME,
+        set.pieces.len(),
+        set.prefix,
+        tool
+    );
+    match sets {
+        1 => {}
+        2 => message.push_str(", and another set of pieces is here"),
+        _ => message.push_str(&format!(", and {} other sets of pieces are here", sets - 1)),
+    }
+    if !set.doubts.is_empty() {
+        let doubts: Vec<String> = set.doubts.iter().map(describe_doubt).collect();
+        message.push_str(&format!("; their order is in doubt: {}", doubts.join("; ")));
+    }
+    let severity = match (chunks, set.doubts.is_empty()) {
+        (true, true) => Severity::Note,
+        _ => Severity::Warning,
+    };
+    let suggestion = match sets {
+        1 => "Run import to make them a chunk set, or import --output FILE to join them",
+        _ => "Run import --prefix PREFIX for each set, or import --output FILE to join them",
+    };
+    Finding {
+        code: FindingCode::ForeignPieces,
+        severity,
+        message,
+        suggestion: Some(suggestion.to_string()),
+        files: set.pieces.iter().map(|piece| piece.name.clone()).collect(),
+    }
+}
+
+fn describe_doubt(doubt: &Doubt) -> String {
+    match doubt {
+        Doubt::MixedPadding { widths } => format!(
+            "numbers padded to {} digits",
+            widths
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(" and ")
+        ),
+        Doubt::SameNumber { names } => format!("{} share a number", names.join(", ")),
+        Doubt::Gaps { count, names } => format!("{} missing, such as {}", count, names.join(", ")),
+        Doubt::UnevenSizes { names } => format!("{} differ in size", named(names)),
+        Doubt::ListedMissing { names } => {
+            format!("{} listed in the checksums aren't here", named(names))
+        }
+    }
+}
+
+fn size_finding(
+    code: FindingCode,
+    (one, many): (&str, &str),
+    chunks: &[(String, u64, u64)],
+    recovery: Option<String>,
+) -> Finding {
+    let details: Vec<String> = chunks
+        .iter()
+        .take(NAMED)
+        .map(|(name, actual, recorded)| format!("{} ({} of {} bytes)", name, actual, recorded))
+        .collect();
+    let names: Vec<String> = chunks.iter().map(|(name, _, _)| name.clone()).collect();
+    let mut message = format!(
+        "{} {}: {}",
+        describe(&names, "chunk", "chunks"),
+        match names.len() {
+            1 => one,
+            _ => many,
+        },
+        details.join(", ")
+    );
+    if chunks.len() > NAMED {
+        message.push_str(&format!(" and {} more", chunks.len() - NAMED));
+    }
+    Finding {
+        code,
+        severity: Severity::Error,
+        message,
+        suggestion: recovery,
+        files: names,
+    }
+}
+
+// What can be done about chunks that are lost, as `verify` worked it out when it could.
+fn recovery(report: Option<&VerifyReport>, manifest: Option<&Manifest>) -> String {
+    if let Some(recoverability) = report.and_then(|report| report.recoverability.as_ref()) {
+        let conclusion = recoverability.conclusion();
+        return match recoverability.recoverable {
+            true => format!("The set is {}", conclusion),
+            false => format!(
+                "{}; copy them from another copy of the set with heal --from DIR, or split the \
+                 original again",
+                conclusion
+            ),
+        };
+    }
+    let redundancy = manifest.is_some_and(|manifest| manifest.parity.is_some())
+        || report.is_some_and(|report| report.par2);
+    match redundancy {
+        true => {
+            "Run repair, which rebuilds them from the parity if it covers this many".to_string()
+        }
+        false => "There is no parity to rebuild them from: copy them from another copy of the \
+                  set with heal --from DIR, or split the original again"
+            .to_string(),
+    }
+}
+
+fn compat_naming(compat: Compat) -> Naming {
+    match compat {
+        Compat::Split => Naming::Split,
+        Compat::Hjsplit => Naming::Hjsplit,
+    }
+}
+
+// Parity and PAR2 files, which the set can do without.
+fn is_redundancy(name: &str) -> bool {
+    parity::is_parity_name(name) || par2::is_par2_name(name)
+}
+
+fn is_chunk_directory(path: &Path) -> bool {
+    path.join(MANIFEST_NAME).is_file()
+        || fs::read_dir(path).is_ok_and(|entries| {
+            entries
+                .flatten()
+                .any(|entry| chunk_index(&entry.file_name().to_string_lossy()).is_some())
+        })
+}
+
+// "3 chunks", or "1 chunk".
+fn describe(names: &[String], one: &str, many: &str) -> String {
+    match names.len() {
+        1 => format!("1 {}", one),
+        count => format!("{} {}", count, many),
+    }
+}
+
+// The first few of `names`, and how many more there are.
+fn named(names: &[String]) -> String {
+    let mut text = names
+        .iter()
+        .take(NAMED)
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    if names.len() > NAMED {
+        text.push_str(&format!(" and {} more", names.len() - NAMED));
+    }
+    text
+}
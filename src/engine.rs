@@ -0,0 +1,510 @@
+// The async engine, in builds with the async feature: the chunks of a set on a server,
+// in S3, over SFTP or over HTTP for `fetch`, transferred several at once on tokio rather
+// than one after another. A transfer is the same blocking code the synchronous paths
+// run, on tokio's blocking pool; what the engine adds is keeping up to `in_flight` of
+// them under way, behind a semaphore, and trying a chunk that failed in a way that may
+// pass again from its start, after pauses of 1 s, 2 s, 4 s, … up to 30 s, as often as
+// `retries` allows. What is written and checked of each chunk, and the manifest, comes
+// from the functions `split_into` and `reconstruct_from` use, so a set comes out the
+// same whichever engine moved it. Local directories never come here.
+//
+// Progress events go back to the thread the engine was called from and are reported
+// there as they come. The first chunk that fails for good stops those not yet started;
+// those under way are waited for, and the failure is returned.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::panic;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use tokio::runtime::{Builder, Runtime};
+use tokio::sync::{Semaphore, mpsc};
+
+use crate::cancel::CancelToken;
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::{Counting, ProgressEvent};
+use crate::fetch::retryable;
+use crate::manifest::{ChunkEntry, ChunkHasher, HashAlgorithm};
+use crate::pipeline::{DEFAULT_BUFFER_SIZE, Io, copy_overlapped};
+use crate::reconstruct::OffsetWriter;
+use crate::retry::DEFAULT_DELAY;
+use crate::store::{ChunkStore, check_numbering, check_read_back, recorded_hashes, written_entry};
+
+// Longest wait before trying a chunk again
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// A `ChunkStore` whose chunks can be written from several threads at once.
+pub(crate) trait SharedStore: ChunkStore + Send + Sync + 'static {
+    // `create_chunk`, for any number of chunks at once.
+    fn create_shared(&self, index: usize) -> Result<Self::Writer>;
+
+    // `finish_chunk`, returning only once the chunk is whole on the server, so that a
+    // failure is the chunk's own and trying it again starts it over.
+    fn finish_shared(&self, index: usize, writer: Self::Writer) -> Result<()>;
+}
+
+pub(crate) struct Engine {
+    runtime: Runtime,
+    in_flight: usize,
+    retries: u32,
+    // The pause before the first retry of a chunk, doubled for each one after
+    delay: Duration,
+    cancel: CancelToken,
+}
+
+impl Engine {
+    // One to keep `in_flight` chunks under way, each tried `retries` more times.
+    pub(crate) fn new(in_flight: usize, retries: u32, cancel: &CancelToken) -> io::Result<Engine> {
+        let in_flight = in_flight.max(1);
+        let runtime = Builder::new_current_thread()
+            .enable_time()
+            .max_blocking_threads(in_flight)
+            .build()?;
+        Ok(Engine {
+            runtime,
+            in_flight,
+            retries,
+            delay: DEFAULT_DELAY,
+            cancel: cancel.clone(),
+        })
+    }
+
+    // `split_into` from the file at `input_path`, the `size` bytes of it from `start`,
+    // with each chunk read from its own offset, so that several go up at once.
+    pub(crate) fn split<S: SharedStore>(
+        &self,
+        store: &Arc<S>,
+        input_path: &Path,
+        (start, size): (u64, u64),
+        chunk_size: u64,
+        hash: Option<HashAlgorithm>,
+        progress: &mut dyn FnMut(ProgressEvent),
+    ) -> Result<Vec<ChunkEntry>> {
+        let count = usize::try_from(size.div_ceil(chunk_size)).map_err(|_| {
+            SplitterError::TooManyChunks {
+                count: size.div_ceil(chunk_size),
+            }
+        })?;
+        let (store, cancel) = (Arc::clone(store), self.cancel.clone());
+        let input_path = input_path.to_path_buf();
+        let transfer = move |index: usize, progress: &mut dyn FnMut(ProgressEvent)| {
+            let offset = index as u64 * chunk_size;
+            let len = chunk_size.min(size - offset);
+            let offset = start + offset;
+            let chunk_path = store.chunk_path(index);
+            progress(ProgressEvent::ChunkStarted { index, size: len });
+            let mut file = File::open(&input_path).doing("opening", &input_path)?;
+            file.seek(SeekFrom::Start(offset)).at(&input_path)?;
+            let mut input = BufReader::with_capacity(DEFAULT_BUFFER_SIZE, file.take(len));
+            let mut writer = store.create_shared(index)?;
+            let mut hasher = hash.map(HashAlgorithm::hasher);
+            let copied = copy_overlapped(
+                &Io::default(),
+                &mut input,
+                &mut Counting {
+                    inner: &mut writer,
+                    copied: &mut |delta| progress(ProgressEvent::BytesCopied { delta }),
+                    cancel: &cancel,
+                },
+                hasher.as_mut(),
+            )
+            .at(&chunk_path)?;
+            // Shorter than it was when the split started
+            if copied != len {
+                return Err(SplitterError::InputChanged {
+                    path: input_path.clone(),
+                });
+            }
+            store.finish_shared(index, writer)?;
+            Ok(written_entry(&*store, index, copied, hasher, progress))
+        };
+        self.run((0..count).collect(), transfer, progress)
+    }
+
+    // `reconstruct_from` into `output`, `output_path`, with each chunk written at its
+    // offset there, so that several come down at once. Returns the bytes written.
+    pub(crate) fn reconstruct<S: SharedStore>(
+        &self,
+        store: &Arc<S>,
+        output: &File,
+        output_path: &Path,
+        progress: &mut dyn FnMut(ProgressEvent),
+    ) -> Result<u64> {
+        let indices = store.list_chunks()?;
+        check_numbering(&indices)?;
+        let manifest = store.read_info().ok().flatten();
+        let (algorithm, hashes) = recorded_hashes(manifest.as_ref());
+        let mut placed = BTreeMap::new();
+        let mut offset = 0;
+        for &index in &indices {
+            let size = store.chunk_len(index)?;
+            placed.insert(index, (offset, size));
+            offset += size;
+        }
+        let output = output.try_clone().at(output_path)?;
+        let (store, cancel) = (Arc::clone(store), self.cancel.clone());
+        let transfer = move |index: usize, progress: &mut dyn FnMut(ProgressEvent)| {
+            let (offset, size) = placed[&index];
+            let chunk_path = store.chunk_path(index);
+            progress(ProgressEvent::ChunkStarted { index, size });
+            let mut reader = store.open_chunk(index)?;
+            let expected = hashes.get(&index).map(String::as_str);
+            let mut hasher = algorithm
+                .filter(|_| expected.is_some())
+                .map(HashAlgorithm::hasher);
+            // No further than the chunk's own place, which the next one starts after
+            let copied = copy_overlapped(
+                &Io::default(),
+                &mut (&mut reader).take(size),
+                &mut Counting {
+                    inner: OffsetWriter {
+                        file: &output,
+                        offset,
+                    },
+                    copied: &mut |delta| progress(ProgressEvent::BytesCopied { delta }),
+                    cancel: &cancel,
+                },
+                hasher.as_mut(),
+            )
+            .at(&chunk_path)?;
+            let actual = copied + io::copy(&mut reader, &mut io::sink()).at(&chunk_path)?;
+            let hash = hasher.map(ChunkHasher::finish);
+            check_read_back(&chunk_path, (actual, size), hash.as_deref(), expected)?;
+            progress(ProgressEvent::ChunkFinished { index, hash });
+            Ok(copied)
+        };
+        let copied = self.run(indices, transfer, progress)?;
+        Ok(copied.into_iter().sum())
+    }
+
+    // `transfer` every chunk of `chunks`, up to `in_flight` at once, reporting what each
+    // reports to `progress`. Returns what they returned, in the order of `chunks`.
+    pub(crate) fn run<T, F>(
+        &self,
+        chunks: Vec<usize>,
+        transfer: F,
+        progress: &mut dyn FnMut(ProgressEvent),
+    ) -> Result<Vec<T>>
+    where
+        T: Send + 'static,
+        F: Fn(usize, &mut dyn FnMut(ProgressEvent)) -> Result<T> + Send + Sync + 'static,
+    {
+        let transfer = Arc::new(transfer);
+        let permits = Arc::new(Semaphore::new(self.in_flight));
+        let failure: Arc<Mutex<Option<SplitterError>>> = Arc::default();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let done = self.runtime.block_on(async {
+            let tasks: Vec<_> = chunks
+                .into_iter()
+                .map(|index| {
+                    let (transfer, permits) = (Arc::clone(&transfer), Arc::clone(&permits));
+                    let (failure, sender) = (Arc::clone(&failure), sender.clone());
+                    let (retries, mut delay) = (self.retries, self.delay);
+                    let cancel = self.cancel.clone();
+                    tokio::spawn(async move {
+                        // Never closed, so only ever given
+                        let _permit = permits.acquire_owned().await.ok()?;
+                        let mut attempt = 0;
+                        loop {
+                            if failure.lock().unwrap().is_some() || cancel.is_cancelled() {
+                                return None;
+                            }
+                            let (transfer, events) = (Arc::clone(&transfer), sender.clone());
+                            let result = tokio::task::spawn_blocking(move || {
+                                transfer(index, &mut |event| {
+                                    let _ = events.send(event);
+                                })
+                            })
+                            .await
+                            .unwrap_or_else(|e| panic::resume_unwind(e.into_panic()));
+                            let error = match result {
+                                Ok(value) => return Some(value),
+                                Err(e) => e,
+                            };
+                            if attempt >= retries || !passing(&error) || cancel.is_cancelled() {
+                                failure.lock().unwrap().get_or_insert(error);
+                                return None;
+                            }
+                            attempt += 1;
+                            warn!(
+                                "{}; trying chunk {} again in {} s ({} of {})",
+                                error,
+                                index,
+                                delay.as_secs(),
+                                attempt,
+                                retries
+                            );
+                            pause(delay, &cancel).await;
+                            delay = (delay * 2).min(MAX_BACKOFF);
+                        }
+                    })
+                })
+                .collect();
+            drop(sender);
+            // Until the last transfer is done with its sender
+            while let Some(event) = receiver.recv().await {
+                progress(event);
+            }
+            let mut done = Vec::with_capacity(tasks.len());
+            for task in tasks {
+                done.push(
+                    task.await
+                        .unwrap_or_else(|e| panic::resume_unwind(e.into_panic())),
+                );
+            }
+            done
+        });
+        if let Some(error) = failure.lock().unwrap().take() {
+            return Err(error);
+        }
+        self.cancel.check()?;
+        Ok(done.into_iter().flatten().collect())
+    }
+}
+
+// Whether a chunk that failed with `error` is worth trying again: the network, the
+// server having trouble, or a chunk that came back damaged or short, as for `fetch`.
+fn passing(error: &SplitterError) -> bool {
+    match error {
+        SplitterError::Io { source, .. } => retryable(source),
+        SplitterError::ChangedSize { .. } => true,
+        _ => false,
+    }
+}
+
+// Wait `delay`, a little at a time so Ctrl+C isn't held up.
+async fn pause(delay: Duration, cancel: &CancelToken) {
+    let until = Instant::now() + delay;
+    while !cancel.is_cancelled() {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return;
+        }
+        tokio::time::sleep(left.min(Duration::from_millis(100))).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    use super::*;
+    use crate::manifest::Manifest;
+    use crate::store::{InMemoryStore, MemoryChunk, split_into};
+
+    // An `InMemoryStore` whose chunks drop their connection as many times as `failures`
+    // has for them, going up or coming down, and which counts how many are open at once.
+    #[derive(Default)]
+    struct Flaky {
+        inner: InMemoryStore,
+        failures: Mutex<BTreeMap<usize, u32>>,
+        open: Arc<AtomicUsize>,
+        most: Arc<AtomicUsize>,
+    }
+
+    impl Flaky {
+        fn failing(failures: impl IntoIterator<Item = (usize, u32)>) -> Flaky {
+            Flaky {
+                failures: Mutex::new(failures.into_iter().collect()),
+                ..Flaky::default()
+            }
+        }
+
+        fn fail(&self, index: usize) -> Result<()> {
+            match self.failures.lock().unwrap().get_mut(&index) {
+                Some(left) if *left > 0 => {
+                    *left -= 1;
+                    Err(io::Error::from(io::ErrorKind::ConnectionReset)).at(&self.chunk_path(index))
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+
+    // A chunk coming down, slowly enough for others to be under way alongside it.
+    struct Open {
+        inner: io::Cursor<Vec<u8>>,
+        open: Arc<AtomicUsize>,
+    }
+
+    impl Read for Open {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(Duration::from_millis(10));
+            self.inner.read(buf)
+        }
+    }
+
+    impl Drop for Open {
+        fn drop(&mut self) {
+            self.open.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    impl ChunkStore for Flaky {
+        type Writer = MemoryChunk;
+        type Reader = Open;
+
+        fn create_chunk(&mut self, index: usize) -> Result<MemoryChunk> {
+            self.inner.create_chunk(index)
+        }
+
+        fn open_chunk(&self, index: usize) -> Result<Open> {
+            self.fail(index)?;
+            let open = self.open.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most.fetch_max(open, Ordering::SeqCst);
+            Ok(Open {
+                inner: self.inner.open_chunk(index)?,
+                open: Arc::clone(&self.open),
+            })
+        }
+
+        fn chunk_len(&self, index: usize) -> Result<u64> {
+            self.inner.chunk_len(index)
+        }
+
+        fn remove_chunk(&mut self, index: usize) -> Result<()> {
+            self.inner.remove_chunk(index)
+        }
+
+        fn list_chunks(&self) -> Result<Vec<usize>> {
+            self.inner.list_chunks()
+        }
+
+        fn read_info(&self) -> Result<Option<Manifest>> {
+            self.inner.read_info()
+        }
+
+        fn write_info(&mut self, manifest: &Manifest) -> Result<()> {
+            self.inner.write_info(manifest)
+        }
+    }
+
+    impl SharedStore for Flaky {
+        fn create_shared(&self, index: usize) -> Result<MemoryChunk> {
+            self.fail(index)?;
+            // Clones share their chunks
+            self.inner.clone().create_chunk(index)
+        }
+
+        fn finish_shared(&self, _index: usize, _writer: MemoryChunk) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn engine(in_flight: usize, retries: u32) -> Engine {
+        let mut engine = Engine::new(in_flight, retries, &CancelToken::new()).unwrap();
+        engine.delay = Duration::ZERO;
+        engine
+    }
+
+    fn input(len: usize) -> (tempfile::NamedTempFile, Vec<u8>) {
+        let data: Vec<u8> = (0..len).map(|i| (i * 7 % 251) as u8).collect();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&data).unwrap();
+        (file, data)
+    }
+
+    #[test]
+    fn sets_moved_by_the_engine_are_those_moved_one_chunk_at_a_time() {
+        let (file, data) = input(10_500);
+        let hash = Some(HashAlgorithm::Sha256);
+        let mut one_at_a_time = InMemoryStore::new();
+        let expected = split_into(
+            &mut BufReader::new(File::open(file.path()).unwrap()),
+            file.path(),
+            &mut one_at_a_time,
+            1000,
+            hash,
+            &mut |_| {},
+            &CancelToken::new(),
+        )
+        .unwrap();
+
+        let store = Arc::new(Flaky::default());
+        let mut events = Vec::new();
+        let range = (0, data.len() as u64);
+        let chunks = engine(4, 0)
+            .split(&store, file.path(), range, 1000, hash, &mut |event| {
+                events.push(event)
+            })
+            .unwrap();
+        assert_eq!(chunks.len(), 11);
+        for (chunk, expected) in chunks.iter().zip(&expected) {
+            assert_eq!(
+                (&chunk.name, chunk.size, &chunk.hash),
+                (&expected.name, expected.size, &expected.hash)
+            );
+        }
+        let finished = events
+            .iter()
+            .filter(|event| matches!(event, ProgressEvent::ChunkFinished { .. }))
+            .count();
+        assert_eq!(finished, 11);
+
+        let output = tempfile::NamedTempFile::new().unwrap();
+        let copied = engine(4, 0)
+            .reconstruct(&store, output.as_file(), output.path(), &mut |_| {})
+            .unwrap();
+        assert_eq!(copied, data.len() as u64);
+        assert_eq!(fs::read(output.path()).unwrap(), data);
+        assert!(store.most.load(Ordering::SeqCst) > 1);
+    }
+
+    #[test]
+    fn no_more_chunks_are_under_way_than_allowed() {
+        let (file, _) = input(12_000);
+        let store = Arc::new(Flaky::default());
+        let range = (0, 12_000);
+        let engine = engine(3, 0);
+        engine
+            .split(&store, file.path(), range, 1000, None, &mut |_| {})
+            .unwrap();
+        let output = tempfile::NamedTempFile::new().unwrap();
+        engine
+            .reconstruct(&store, output.as_file(), output.path(), &mut |_| {})
+            .unwrap();
+        assert_eq!(store.most.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn a_chunk_that_fails_is_tried_again_from_its_start() {
+        let (file, data) = input(5000);
+        let store = Arc::new(Flaky::failing([(1, 2), (3, 1)]));
+        let range = (0, 5000);
+        engine(2, 2)
+            .split(&store, file.path(), range, 1000, None, &mut |_| {})
+            .unwrap();
+        *store.failures.lock().unwrap() = [(0, 1), (4, 2)].into_iter().collect();
+        let output = tempfile::NamedTempFile::new().unwrap();
+        engine(2, 2)
+            .reconstruct(&store, output.as_file(), output.path(), &mut |_| {})
+            .unwrap();
+        assert_eq!(fs::read(output.path()).unwrap(), data);
+    }
+
+    #[test]
+    fn a_chunk_out_of_retries_fails_the_transfer() {
+        let (file, _) = input(5000);
+        let store = Arc::new(Flaky::failing([(2, 3)]));
+        let range = (0, 5000);
+        let error = engine(1, 2)
+            .split(&store, file.path(), range, 1000, None, &mut |_| {})
+            .unwrap_err();
+        match error {
+            SplitterError::Io { path, source, .. } => {
+                assert_eq!(path, Path::new("chunk002"));
+                assert_eq!(source.kind(), io::ErrorKind::ConnectionReset);
+            }
+            error => panic!("{}", error),
+        }
+        // One at a time, so those after it never started, and it never got going
+        assert_eq!(store.inner.list_chunks().unwrap(), [0, 1]);
+    }
+}
@@ -0,0 +1,230 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::crypt;
+use crate::size::format_size;
+
+pub type Result<T> = std::result::Result<T, SplitterError>;
+
+// Everything the library can fail with. Variants name what went wrong so callers can
+// tell failures apart without matching on messages; I/O failures carry the path they
+// happened on.
+#[derive(Debug, Error)]
+pub enum SplitterError {
+    // An option that can't work, named as the field it was given in
+    #[error("invalid {field}: {reason}")]
+    InvalidOption {
+        field: &'static str,
+        reason: &'static str,
+    },
+    #[error("{} does not name a file", path.display())]
+    NotAFile { path: PathBuf },
+    // `target` is what the link says, relative to the link's directory or not
+    #[error("{} is a symbolic link to {}, which does not exist", path.display(), target.display())]
+    BrokenSymlink { path: PathBuf, target: PathBuf },
+    // A link where links aren't being followed
+    #[error("{} is a symbolic link to {}, and links are not being followed", path.display(), target.display())]
+    Symlink { path: PathBuf, target: PathBuf },
+    // A file to split that is a piece of the set in `set`, such as one of its chunks; see
+    // `SplitOptions::allow_nested`
+    #[error(
+        "{} looks like a piece of the existing split in {}; did you mean to reconstruct {} instead?",
+        path.display(),
+        set.display(),
+        set.display()
+    )]
+    NestedSplit { path: PathBuf, set: PathBuf },
+    #[error("{} is not empty", path.display())]
+    DestinationNotEmpty { path: PathBuf },
+    // A file where the destination, or a directory above it, would have to be
+    #[error("destination exists and is not a directory: {}", path.display())]
+    NotADirectory { path: PathBuf },
+    // Below `SplitOptions::min_chunk_size`, most likely a size given without its unit
+    #[error(
+        "a chunk size of {chunk_size} bytes is below the minimum of {}; give the size with its unit, as in {chunk_size}MiB, or lower the minimum to split that small on purpose",
+        format_size(*minimum)
+    )]
+    ChunkTooSmall { chunk_size: u64, minimum: u64 },
+    #[error("splitting into {count} chunks is more than this platform can track")]
+    TooManyChunks { count: u64 },
+    // More chunks than `SplitOptions::max_dir_files` for one directory, with
+    // `ShardDirs::Refuse`
+    #[error("splitting into {count} chunks would put more than {limit} files in {}", path.display())]
+    TooManyFiles {
+        path: PathBuf,
+        count: usize,
+        limit: usize,
+    },
+    #[error("chunks missing from the sequence: {indices:?}")]
+    MissingChunks { indices: Vec<u64> },
+    // Not a chunk in the directory, whose manifest, if any, doesn't say the file was empty
+    #[error("no chunk files found in {}", path.display())]
+    NoChunks { path: PathBuf },
+    // The chunks info.json lists aren't there, and files that could be them under other
+    // names can't be told to be them; see `remap`
+    #[error("can't tell which files in {} are the chunks info.json lists: {reason}", path.display())]
+    RemapDoubt { path: PathBuf, reason: String },
+    // Two files numbered the same, such as chunk7 and chunk007, of which either could be
+    // the one meant
+    #[error("{} and {} are both chunk {index}", first.display(), second.display())]
+    DuplicateChunk {
+        index: u64,
+        first: PathBuf,
+        second: PathBuf,
+    },
+    // A file numbered so far past the chunks there are that the gap before it would be
+    // more chunks missing than are worth listing, such as chunk99999999999 beside
+    // chunk000: a stray named like a chunk, more likely than the end of a set
+    #[error(
+        "chunk {index} is numbered far past the {chunks} chunks found; move it away if it \
+         is a stray file named like a chunk"
+    )]
+    StrayChunk { index: u64, chunks: usize },
+    #[error("{} changed size while it was being copied", path.display())]
+    ChangedSize { path: PathBuf },
+    // The input's size or modification time differed at the end of a split
+    #[error("{} changed while it was being split", path.display())]
+    InputChanged { path: PathBuf },
+    // A compressed chunk holding more or less than its manifest entry says
+    #[error("{} decompresses to {actual} bytes where {expected} were expected", path.display())]
+    DecodedSize {
+        path: PathBuf,
+        expected: u64,
+        actual: u64,
+    },
+    // An encrypted chunk that fails its tag: the key is not the set's, or the chunk was
+    // changed or cut short; see `crypt`
+    #[error("{}: wrong passphrase or corrupted data", path.display())]
+    Undecryptable { path: PathBuf },
+    // An encrypted set, read without its key
+    #[error("{} is encrypted; its passphrase or key file is needed to read it", path.display())]
+    Encrypted { path: PathBuf },
+    // A key other than the one an encrypted set records the fingerprint of
+    #[error("{}: wrong passphrase or key; it isn't the one the chunks were encrypted with", path.display())]
+    WrongKey { path: PathBuf },
+    #[error("{} is corrupt: {source}", path.display())]
+    MetadataCorrupt {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    // A manifest whose contents no longer match its seal, as after an edit by hand; see
+    // `manifest::SEAL_FIELD`
+    #[error(
+        "{} has been modified or corrupted since it was written (reseal it if the changes were meant)",
+        path.display()
+    )]
+    MetadataModified { path: PathBuf },
+    // A name in a manifest that would lead out of its directory, such as an absolute
+    // path or one with `..`; see `manifest::is_plain_name`
+    #[error("{} names a file outside its directory: {name:?}", path.display())]
+    UnsafeName { path: PathBuf, name: String },
+    // Another copy of a set, whose manifest says it was split from something else
+    #[error("{} does not describe the same split", path.display())]
+    ManifestMismatch { path: PathBuf },
+    // `action` says what was being done to `path` where the path alone leaves it open,
+    // as in "opening chunk /mnt/a/chunk117: Permission denied"
+    #[error("{}{}: {source}", before_path(action), path.display())]
+    Io {
+        path: PathBuf,
+        source: io::Error,
+        action: Option<&'static str>,
+    },
+    // Another split, reconstruction or the like is at work on `path`; see `lock`
+    #[error("{} is in use: {holder}", path.display())]
+    Locked { path: PathBuf, holder: String },
+    #[error("cancelled")]
+    Cancelled,
+}
+
+// An `Io` error's action as it reads ahead of the path.
+fn before_path(action: &Option<&str>) -> String {
+    action
+        .map(|action| format!("{} ", action))
+        .unwrap_or_default()
+}
+
+// For callers that only deal in `io::Error`, such as the benchmark.
+impl From<SplitterError> for io::Error {
+    fn from(error: SplitterError) -> io::Error {
+        let kind = match &error {
+            SplitterError::InvalidOption { .. }
+            | SplitterError::ChunkTooSmall { .. }
+            | SplitterError::NotAFile { .. }
+            | SplitterError::Symlink { .. }
+            | SplitterError::NestedSplit { .. }
+            | SplitterError::TooManyChunks { .. }
+            | SplitterError::TooManyFiles { .. } => io::ErrorKind::InvalidInput,
+            SplitterError::DestinationNotEmpty { .. } => io::ErrorKind::AlreadyExists,
+            SplitterError::NotADirectory { .. } => io::ErrorKind::NotADirectory,
+            SplitterError::MissingChunks { .. }
+            | SplitterError::NoChunks { .. }
+            | SplitterError::RemapDoubt { .. }
+            | SplitterError::BrokenSymlink { .. } => io::ErrorKind::NotFound,
+            SplitterError::ChangedSize { .. } => io::ErrorKind::UnexpectedEof,
+            SplitterError::InputChanged { .. } => io::ErrorKind::Other,
+            SplitterError::MetadataCorrupt { .. }
+            | SplitterError::MetadataModified { .. }
+            | SplitterError::UnsafeName { .. }
+            | SplitterError::ManifestMismatch { .. }
+            | SplitterError::DuplicateChunk { .. }
+            | SplitterError::StrayChunk { .. }
+            | SplitterError::DecodedSize { .. }
+            | SplitterError::Undecryptable { .. } => io::ErrorKind::InvalidData,
+            SplitterError::Encrypted { .. } | SplitterError::WrongKey { .. } => {
+                io::ErrorKind::PermissionDenied
+            }
+            SplitterError::Locked { .. } => io::ErrorKind::ResourceBusy,
+            SplitterError::Io { source, .. } => source.kind(),
+            SplitterError::Cancelled => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+// Attach the path an I/O operation was working on, as in `File::open(path).at(path)?`,
+// and with `doing` what it was doing there, as in `.doing("creating", path)`.
+pub(crate) trait PathContext<T> {
+    fn at(self, path: &Path) -> Result<T>;
+
+    fn doing(self, action: &'static str, path: &Path) -> Result<T>;
+}
+
+impl<T> PathContext<T> for io::Result<T> {
+    fn at(self, path: &Path) -> Result<T> {
+        self.map_err(|source| {
+            let cancelled = source
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<SplitterError>())
+                .is_some_and(|inner| matches!(inner, SplitterError::Cancelled));
+            if cancelled {
+                return SplitterError::Cancelled;
+            }
+            if source
+                .get_ref()
+                .is_some_and(|inner| inner.is::<crypt::Unauthentic>())
+            {
+                return SplitterError::Undecryptable {
+                    path: path.to_path_buf(),
+                };
+            }
+            SplitterError::Io {
+                path: path.to_path_buf(),
+                source,
+                action: None,
+            }
+        })
+    }
+
+    fn doing(self, action: &'static str, path: &Path) -> Result<T> {
+        self.at(path).map_err(|error| match error {
+            SplitterError::Io { path, source, .. } => SplitterError::Io {
+                path,
+                source,
+                action: Some(action),
+            },
+            error => error,
+        })
+    }
+}
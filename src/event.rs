@@ -0,0 +1,67 @@
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::{
+    ExportReport, FetchReport, HealReport, ImportReport, PackReport, ReconstructReport,
+    RekeyReport, RepairReport, SplitReport, VerifyReport,
+};
+
+// Something that happened during a split, reconstruction, verification, repair, heal,
+// fetch, pack, unpack, import, export or rekey, handed to the caller's callback as it
+// happens. When several threads are copying, chunks start and finish out of order.
+// Copies that go through a buffer report `BytesCopied` for every buffer; those the
+// kernel or a memory map does in one go report once per chunk.
+// As JSON an event is an object tagged with its `event` name, e.g.
+// `{"event":"chunk_finished","index":3,"hash":null}`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProgressEvent {
+    // `size` is what the chunk should end up holding; splitting input of unknown length
+    // (`split_into`) only knows the chunk size, which the last chunk may fall short of
+    ChunkStarted { index: usize, size: u64 },
+    BytesCopied { delta: u64 },
+    // `hash` is set when the chunk was hashed on the way
+    ChunkFinished { index: usize, hash: Option<String> },
+    Completed { report: Report },
+}
+
+// What a finished operation returns, also passed along with `Completed`. As JSON the
+// report's fields are tagged with the `operation` it came from.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum Report {
+    Split(SplitReport),
+    Reconstruct(ReconstructReport),
+    Verify(VerifyReport),
+    Repair(RepairReport),
+    Heal(HealReport),
+    Fetch(FetchReport),
+    Pack(PackReport),
+    Unpack(PackReport),
+    Import(ImportReport),
+    Export(ExportReport),
+    Rekey(RekeyReport),
+}
+
+// Tells `copied` about every write passed through to `inner`, so a copy through the
+// pipeline is reported buffer by buffer, and stops it there once `cancel` is set.
+pub(crate) struct Counting<'a, W> {
+    pub inner: W,
+    pub copied: &'a mut dyn FnMut(u64),
+    pub cancel: &'a CancelToken,
+}
+
+impl<W: Write> Write for Counting<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.cancel.check_io()?;
+        let written = self.inner.write(buf)?;
+        (self.copied)(written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
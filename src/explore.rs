@@ -0,0 +1,264 @@
+// `explore` and the interactive "Explore chunk…": one chunk of a set shown as the part
+// of the original file it is. A header says which chunk it is and which bytes of the
+// file it holds, in the file's own offsets, as the manifest has them; then comes what it
+// holds, decoded as reconstruct decodes it, as text when the start of it looks like text
+// and as a hex dump at the file's offsets otherwise.
+//
+// On a terminal it goes through $PAGER, or `less` when that isn't set, or failing both
+// a pager of our own that stops after every screen. The decoded bytes are only ever
+// piped to the pager, never written to a file on the way.
+
+use std::env;
+use std::io::{self, BufReader, IsTerminal, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use reconstruct_large_file::store::ChunkStore;
+use reconstruct_large_file::{
+    ArchiveStore, ChunkSet, LocalDirStore, Result, SplitterError, default_output_name, is_archive,
+};
+
+use crate::prompt;
+
+// How much of the start of a chunk is looked at to tell text from binary
+const SNIFF_LEN: u64 = 8 << 10;
+
+// How the contents are shown.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum View {
+    // As text if the start of the chunk looks like text, else as a hex dump
+    Auto,
+    Text,
+    Hex,
+}
+
+// A chunk opened for `show`: what to say about it, and its contents decoded.
+pub struct Explored {
+    pub header: String,
+    // Where its bytes start in the original file
+    offset: u64,
+    reader: Box<dyn Read + Send>,
+}
+
+// Chunk `index` of the set in `directory`, a zip or tar of one too, ready to be shown.
+// Its info.json is refused for having been changed unless `accept_modified`.
+pub fn open(directory: &Path, index: usize, accept_modified: bool) -> Result<Explored> {
+    let set = ChunkSet::open(directory, accept_modified)?;
+    let Some(chunk) = set.iter().find(|chunk| chunk.index == index) else {
+        return Err(SplitterError::InvalidOption {
+            field: "index",
+            reason: "is not the number of a chunk in the set",
+        });
+    };
+    if !chunk.present {
+        return Err(SplitterError::MissingChunks {
+            indices: vec![index as u64],
+        });
+    }
+    let base = set
+        .manifest()
+        .and_then(|manifest| manifest.range)
+        .map_or(0, |range| range.offset);
+    let offset = base + chunk.offset;
+    let name = match set.original_filename() {
+        Some(name) => name.to_string(),
+        None => default_output_name(directory, accept_modified)?,
+    };
+    let bytes = match chunk.len {
+        0 => "no bytes".to_string(),
+        len => format!("bytes {}..{}", grouped(offset), grouped(offset + len - 1)),
+    };
+    let header = format!("chunk {} of {} — {} of {}", index, set.len(), bytes, name);
+    let reader: Box<dyn Read + Send> = match is_archive(directory) {
+        true => Box::new(ArchiveStore::open(directory, accept_modified)?.open_chunk(index)?),
+        false => Box::new(LocalDirStore::open(directory, accept_modified)?.open_chunk(index)?),
+    };
+    Ok(Explored {
+        header,
+        offset,
+        reader,
+    })
+}
+
+// Write the header and contents of `explored` as `view` says, through a pager when
+// `page` is set and standard output is a terminal. A pager quit before the end isn't
+// an error.
+pub fn show(explored: Explored, view: View, page: bool) -> io::Result<()> {
+    let result = match page && io::stdout().is_terminal() {
+        true => paged(explored, view),
+        false => write_view(explored, view, &mut io::stdout().lock()),
+    };
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+fn paged(explored: Explored, view: View) -> io::Result<()> {
+    let (program, args) = match env::var("PAGER") {
+        Ok(pager) if !pager.trim().is_empty() => {
+            let mut words = pager.split_whitespace().map(str::to_string);
+            (words.next().unwrap_or_default(), words.collect())
+        }
+        _ => ("less".to_string(), Vec::new()),
+    };
+    let mut command = Command::new(&program);
+    command.args(&args).stdin(Stdio::piped());
+    // Out of the way for a chunk that fits on one screen, and leaving it on the screen
+    if env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(_) if io::stdin().is_terminal() => {
+            return write_view(explored, view, &mut ScreenPager::new());
+        }
+        Err(_) => return write_view(explored, view, &mut io::stdout().lock()),
+    };
+    let written = match child.stdin.take() {
+        Some(mut stdin) => write_view(explored, view, &mut stdin),
+        None => Ok(()),
+    };
+    child.wait()?;
+    written
+}
+
+fn write_view(explored: Explored, view: View, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", explored.header)?;
+    writeln!(out)?;
+    let mut reader = explored.reader;
+    let mut start = Vec::new();
+    if view == View::Auto {
+        (&mut reader).take(SNIFF_LEN).read_to_end(&mut start)?;
+    }
+    let text = match view {
+        View::Auto => looks_like_text(&start),
+        View::Text => true,
+        View::Hex => false,
+    };
+    let mut reader = start.as_slice().chain(reader);
+    match text {
+        true => io::copy(&mut reader, out).map(|_| ())?,
+        false => write_hex(&mut reader, explored.offset, out)?,
+    }
+    out.flush()
+}
+
+fn write_hex(reader: &mut dyn Read, offset: u64, out: &mut dyn Write) -> io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut offset = offset;
+    loop {
+        let mut line = Vec::with_capacity(16);
+        (&mut reader).take(16).read_to_end(&mut line)?;
+        if line.is_empty() {
+            return Ok(());
+        }
+        write_hex_dump(out, offset, &line)?;
+        offset += line.len() as u64;
+    }
+}
+
+// Classic hex dump layout: offset, 16 bytes in hex, then the printable ASCII.
+pub fn write_hex_dump(out: &mut dyn Write, offset: u64, bytes: &[u8]) -> io::Result<()> {
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        writeln!(
+            out,
+            "{:08x}  {:<48}  |{}|",
+            offset + (i * 16) as u64,
+            hex.join(" "),
+            ascii
+        )?;
+    }
+    Ok(())
+}
+
+// UTF-8, perhaps cut off part way through a character at the end, with no control
+// characters but tabs and line breaks, which would otherwise do things to the terminal.
+fn looks_like_text(start: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(start) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none() && start.len() - e.valid_up_to() < 4,
+    };
+    valid
+        && start
+            .iter()
+            .all(|&b| !b.is_ascii_control() || matches!(b, b'\t' | b'\n' | b'\r'))
+}
+
+// As in 613,416,960.
+fn grouped(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+// Standard output a screen at a time, asking before each next one, for when there is
+// no pager to run; long lines count for as many rows as they wrap into.
+struct ScreenPager {
+    out: io::Stdout,
+    rows: usize,
+    columns: usize,
+    row: usize,
+    column: usize,
+}
+
+impl ScreenPager {
+    fn new() -> ScreenPager {
+        let (columns, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+        ScreenPager {
+            out: io::stdout(),
+            // Leaving one for the question
+            rows: (rows as usize).saturating_sub(1).max(1),
+            columns: (columns as usize).max(1),
+            row: 0,
+            column: 0,
+        }
+    }
+}
+
+impl Write for ScreenPager {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for (i, &b) in buf.iter().enumerate() {
+            if self.row == self.rows {
+                self.out.flush()?;
+                if !prompt::yes_or_no("Show the next screen?", true)? {
+                    // Taken by `show` as the reader having gone, same as for a pager
+                    return Err(io::ErrorKind::BrokenPipe.into());
+                }
+                self.row = 0;
+            }
+            // A byte that starts a character, continuing bytes taking up no room
+            let starts = b & 0xc0 != 0x80;
+            if b == b'\n' || (starts && self.column == self.columns) {
+                self.row += 1;
+                self.column = 0;
+            }
+            if b != b'\n' && starts {
+                self.column += 1;
+            }
+            self.out.write_all(&buf[i..i + 1])?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
@@ -0,0 +1,348 @@
+// A set's chunk list exported on its own, to be sent ahead of the chunks and used to
+// check them when they arrive by whatever way, without trusting the info.json that
+// comes with them, or with none at all. An exported manifest (`.fsrm`) is one JSON
+// object, and its format is kept stable:
+//
+//   format             always "fsrm"
+//   version            1; readers refuse versions they don't know
+//   original_filename  the name the set joins into
+//   size               the original's size in bytes
+//   hash               the algorithm of the chunk hashes, "sha256"
+//   chunks             in order: `name`, `size` (original bytes), `hash` (lowercase
+//                      hex of the original bytes) and `compression` when the chunk is
+//                      stored compressed, as in info.json
+//   signature          optional: `algorithm` "hmac-sha256" and `value`, lowercase hex
+//
+// Every chunk has a hash: those info.json lacks are computed while exporting. The
+// signature is an HMAC keyed with a secret the sender and the receiver share, over the
+// document's compact JSON with `signature` left out, fields in the order above; it
+// shows the document came from someone holding the key and wasn't changed since.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::cancel::CancelToken;
+use crate::chunkset::ChunkSet;
+use crate::crypt::{hex, unhex};
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::{ProgressEvent, Report};
+use crate::manifest::{Compression, HashAlgorithm};
+use crate::s3::hmac;
+use crate::{ChunkHealth, VerifyReport, hash_chunk, is_archive};
+
+pub const EXPORT_FORMAT: &str = "fsrm";
+pub const EXPORT_VERSION: u32 = 1;
+pub const EXPORT_EXTENSION: &str = "fsrm";
+
+const SIGNATURE_ALGORITHM: &str = "hmac-sha256";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportedManifest {
+    pub format: String,
+    pub version: u32,
+    pub original_filename: String,
+    pub size: u64,
+    pub hash: HashAlgorithm,
+    pub chunks: Vec<ExportedChunk>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<Signature>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportedChunk {
+    pub name: String,
+    pub size: u64,
+    pub hash: String,
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    pub compression: Compression,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Signature {
+    pub algorithm: String,
+    // Lowercase hex
+    pub value: String,
+}
+
+impl ExportedManifest {
+    // Read the exported manifest at `path`, refusing a version this build doesn't know.
+    pub fn load(path: &Path) -> Result<ExportedManifest> {
+        let data = fs::read_to_string(path).at(path)?;
+        let exported: ExportedManifest =
+            serde_json::from_str(&data).map_err(|source| SplitterError::MetadataCorrupt {
+                path: path.to_path_buf(),
+                source,
+            })?;
+        if exported.format != EXPORT_FORMAT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "is not an exported manifest",
+            ))
+            .at(path);
+        }
+        if exported.version != EXPORT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "is version {} of the exported manifest format; this build reads version {}",
+                    exported.version, EXPORT_VERSION
+                ),
+            ))
+            .at(path);
+        }
+        if exported.chunks.iter().map(|chunk| chunk.size).sum::<u64>() != exported.size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "lists chunks that don't add up to its size",
+            ))
+            .at(path);
+        }
+        Ok(exported)
+    }
+
+    // The bytes the signature covers.
+    fn signed_bytes(&self) -> Vec<u8> {
+        let unsigned = ExportedManifest {
+            signature: None,
+            ..self.clone()
+        };
+        serde_json::to_vec(&unsigned).unwrap_or_default()
+    }
+
+    fn sign(&mut self, key: &[u8]) {
+        self.signature = Some(Signature {
+            algorithm: SIGNATURE_ALGORITHM.to_string(),
+            value: hex(&hmac(key, &self.signed_bytes())),
+        });
+    }
+
+    // Whether the signature is there and was made with `key`. It is compared in
+    // constant time, so that how long checking a forged one takes doesn't tell how much
+    // of it is right.
+    fn signed_with(&self, key: &[u8]) -> bool {
+        let Some(signature) = &self.signature else {
+            return false;
+        };
+        let Some(value) = unhex(&signature.value) else {
+            return false;
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes a key of any length");
+        mac.update(&self.signed_bytes());
+        signature.algorithm == SIGNATURE_ALGORITHM && mac.verify_slice(&value).is_ok()
+    }
+}
+
+// Outcome of `export_manifest`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportReport {
+    pub directory: PathBuf,
+    pub output: PathBuf,
+    pub chunks: usize,
+    pub total_size: u64,
+    // Chunks info.json had no hash for, hashed now
+    pub hashed: usize,
+    pub signed: bool,
+}
+
+// Export the chunk list of the set in `directory` to a new file at `output`, which
+// mustn't exist yet, signed with `key` if one is given. The set needs an info.json for
+// the original's name; chunks it has no hashes for are hashed, and must all be there
+// for that.
+pub fn export_manifest(
+    directory: &Path,
+    output: &Path,
+    key: Option<&[u8]>,
+    accept_modified: bool,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<ExportReport> {
+    let set = ChunkSet::open(directory, accept_modified)?;
+    let Some(original_filename) = set.original_filename() else {
+        return Err(SplitterError::InvalidOption {
+            field: "directory",
+            reason: "has no info.json to take the original's name from",
+        });
+    };
+    let recorded = set.hash_algorithm();
+    let mut chunks = Vec::with_capacity(set.len());
+    let mut hashed = 0;
+    for chunk in &set {
+        cancel.check()?;
+        let name = chunk.path.file_name().unwrap_or_default();
+        let name = name.to_string_lossy().into_owned();
+        let hash = match chunk.hash.clone().filter(|_| recorded.is_some()) {
+            Some(hash) => hash,
+            None => {
+                let index = chunk.index;
+                progress(ProgressEvent::ChunkStarted {
+                    index,
+                    size: chunk.len,
+                });
+                let mut copied = |delta| progress(ProgressEvent::BytesCopied { delta });
+                let hash = hash_chunk(
+                    &chunk.path,
+                    chunk.compression,
+                    HashAlgorithm::Sha256,
+                    &mut copied,
+                    cancel,
+                )?;
+                let Some(hash) = hash else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "is missing or damaged, so has no hash to export",
+                    ))
+                    .at(&chunk.path);
+                };
+                progress(ProgressEvent::ChunkFinished {
+                    index,
+                    hash: Some(hash.clone()),
+                });
+                hashed += 1;
+                hash
+            }
+        };
+        chunks.push(ExportedChunk {
+            name,
+            size: chunk.len,
+            hash,
+            compression: chunk.compression,
+        });
+    }
+    let mut exported = ExportedManifest {
+        format: EXPORT_FORMAT.to_string(),
+        version: EXPORT_VERSION,
+        original_filename: original_filename.to_string(),
+        size: set.total_size(),
+        hash: recorded.unwrap_or(HashAlgorithm::Sha256),
+        chunks,
+        signature: None,
+    };
+    if let Some(key) = key {
+        exported.sign(key);
+    }
+    let data = serde_json::to_vec_pretty(&exported)
+        .map_err(io::Error::other)
+        .at(output)?;
+    fs::File::create_new(output)
+        .and_then(|mut file| io::Write::write_all(&mut file, &data))
+        .at(output)?;
+    info!(
+        "exported the {} chunks of {} to {}",
+        exported.chunks.len(),
+        directory.display(),
+        output.display()
+    );
+
+    let report = ExportReport {
+        directory: directory.to_path_buf(),
+        output: output.to_path_buf(),
+        chunks: exported.chunks.len(),
+        total_size: exported.size,
+        hashed,
+        signed: key.is_some(),
+    };
+    progress(ProgressEvent::Completed {
+        report: Report::Export(report.clone()),
+    });
+    Ok(report)
+}
+
+// `verify` against the exported manifest at `manifest` rather than the directory's own
+// info.json, which isn't read at all. With `key` the signature is checked first and
+// must match; a signed manifest checked without one is only warned about. The report is
+// that of `verify`, with every listed chunk that is gone both in `health.missing`, by
+// its place in the list, and in `mismatched`. Nothing is said about recovering lost
+// chunks, since the parity is described only by the info.json.
+pub fn verify_exported(
+    directory: &Path,
+    manifest: &Path,
+    key: Option<&[u8]>,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<VerifyReport> {
+    if is_archive(directory) {
+        return Err(SplitterError::InvalidOption {
+            field: "directory",
+            reason: "is an archive; unpack it to check it against an exported manifest",
+        });
+    }
+    let exported = ExportedManifest::load(manifest)?;
+    match (key, &exported.signature) {
+        (Some(_), None) => {
+            return Err(SplitterError::InvalidOption {
+                field: "key",
+                reason: "given, but the exported manifest isn't signed",
+            });
+        }
+        (Some(key), Some(_)) if !exported.signed_with(key) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "has a signature that doesn't match the key; it was changed or signed with another",
+            ))
+            .at(manifest);
+        }
+        (None, Some(_)) => warn!(
+            "{} is signed, but without the key its signature isn't checked",
+            manifest.display()
+        ),
+        _ => {}
+    }
+
+    let mut health = ChunkHealth {
+        chunks: 0,
+        total_size: 0,
+        missing: Vec::new(),
+        uneven: Vec::new(),
+        unexpected: Vec::new(),
+    };
+    let mut mismatched = Vec::new();
+    for (index, chunk) in exported.chunks.iter().enumerate() {
+        cancel.check()?;
+        let path = directory.join(&chunk.name);
+        if !path.is_file() {
+            health.missing.push(index as u64);
+            mismatched.push(chunk.name.clone());
+            continue;
+        }
+        health.chunks += 1;
+        health.total_size += chunk.size;
+        progress(ProgressEvent::ChunkStarted {
+            index,
+            size: chunk.size,
+        });
+        let mut copied = |delta| progress(ProgressEvent::BytesCopied { delta });
+        let hash = hash_chunk(&path, chunk.compression, exported.hash, &mut copied, cancel)?;
+        if hash.as_ref() != Some(&chunk.hash) {
+            mismatched.push(chunk.name.clone());
+        }
+        progress(ProgressEvent::ChunkFinished { index, hash });
+    }
+    info!(
+        "verified {} against {}: {} mismatched",
+        directory.display(),
+        manifest.display(),
+        mismatched.len()
+    );
+
+    let report = VerifyReport {
+        health,
+        hashed: true,
+        mismatched,
+        par2: false,
+        recoverability: None,
+        checksum_files: Vec::new(),
+        checksum_missing: Vec::new(),
+        unchecksummed: Vec::new(),
+        sample: None,
+    };
+    progress(ProgressEvent::Completed {
+        report: Report::Verify(report.clone()),
+    });
+    Ok(report)
+}
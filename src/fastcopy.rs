@@ -0,0 +1,72 @@
+use std::fs::File;
+
+// Whether `copy_range` can do anything at all on this platform; elsewhere it always
+// copies nothing and callers might as well take their usual path.
+pub const SUPPORTED: bool = cfg!(target_os = "linux");
+
+// Copy `len` bytes from `input` at `in_offset` to `output` at `out_offset` without
+// passing the data through this process: first by sharing extents (reflink, on file
+// systems like Btrfs and XFS), then with copy_file_range. Returns how many bytes were
+// copied, which is short of `len` when the kernel can't (or won't, e.g. across
+// devices) do the rest; the caller copies the remainder itself.
+#[cfg(target_os = "linux")]
+pub fn copy_range(input: &File, in_offset: u64, output: &File, out_offset: u64, len: u64) -> u64 {
+    use std::os::fd::AsRawFd;
+
+    // Cloning needs block-aligned ranges, which the usual power-of-two chunk sizes are
+    const CLONE_ALIGNMENT: u64 = 4096;
+    if in_offset.is_multiple_of(CLONE_ALIGNMENT)
+        && out_offset.is_multiple_of(CLONE_ALIGNMENT)
+        && len > 0
+    {
+        let range = libc::file_clone_range {
+            src_fd: input.as_raw_fd() as i64,
+            src_offset: in_offset,
+            src_length: len,
+            dest_offset: out_offset,
+        };
+        // SAFETY: both descriptors are open for the duration of the call and `range`
+        // outlives it.
+        if unsafe { libc::ioctl(output.as_raw_fd(), libc::FICLONERANGE, &range) } == 0 {
+            // Cloning doesn't extend the destination past a partial last block
+            if output.metadata().is_ok_and(|m| m.len() >= out_offset + len) {
+                return len;
+            }
+        }
+    }
+
+    let mut off_in = in_offset as i64;
+    let mut off_out = out_offset as i64;
+    let mut copied = 0;
+    while copied < len {
+        let wanted = (len - copied).min(1 << 30) as usize;
+        // SAFETY: as above; the offset pointers refer to locals that outlive the call.
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_copy_file_range,
+                input.as_raw_fd(),
+                &mut off_in as *mut i64,
+                output.as_raw_fd(),
+                &mut off_out as *mut i64,
+                wanted,
+                0u32,
+            )
+        };
+        if result <= 0 {
+            break;
+        }
+        copied += result as u64;
+    }
+    copied
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn copy_range(
+    _input: &File,
+    _in_offset: u64,
+    _output: &File,
+    _out_offset: u64,
+    _len: u64,
+) -> u64 {
+    0
+}
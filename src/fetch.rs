@@ -0,0 +1,515 @@
+// Downloading a chunk set over HTTP, such as one `serve` shares, into a local directory
+// it can be reconstructed from. The directory doubles as a cache: chunks only arrive in
+// it under their own names once they have checked out, so running the fetch again after
+// an interrupted one takes up where it left off, and a chunk cut off mid-download is
+// continued from its `.part` file when the server honours ranges.
+
+#[cfg(feature = "async")]
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+#[cfg(not(feature = "async"))]
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+#[cfg(not(feature = "async"))]
+use std::sync::mpsc;
+#[cfg(not(feature = "async"))]
+use std::thread;
+#[cfg(not(feature = "async"))]
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+#[cfg(feature = "async")]
+use crate::engine::Engine;
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::{Counting, ProgressEvent, Report};
+use crate::http::{self, Auth, Url};
+use crate::manifest::{ChunkEntry, MANIFEST_NAME, Manifest};
+use crate::parity::is_chunk_intact;
+use crate::pipeline::{Io, copy_overlapped};
+use crate::store::restore_shard_dir;
+
+// Left in a directory `fetch` made, naming the URL, so that running it again to resume
+// knows the directory is still its own
+const MARKER: &str = ".fetched-from";
+// Longest wait between two attempts at a chunk
+#[cfg(not(feature = "async"))]
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug)]
+pub struct FetchOptions {
+    // Where the set is shared: the directory holding its info.json, or info.json itself
+    pub url: String,
+    // Where the chunks go [default: ORIGINAL.chunks in the current directory]
+    pub directory: Option<PathBuf>,
+    // Chunks downloaded at once
+    pub connections: usize,
+    // Further attempts at a chunk after the first fails
+    pub retries: u32,
+    pub auth: Option<Auth>,
+    // Send `auth` over plain http to a host off the local network, where anyone on the
+    // way can read it
+    pub insecure_auth: bool,
+    // Go on with an info.json changed after it was sealed, with a warning
+    pub accept_modified: bool,
+}
+
+impl FetchOptions {
+    // Four connections, three retries, no credentials.
+    pub fn new(url: impl Into<String>) -> FetchOptions {
+        FetchOptions {
+            url: url.into(),
+            directory: None,
+            connections: 4,
+            retries: 3,
+            auth: None,
+            insecure_auth: false,
+            accept_modified: false,
+        }
+    }
+}
+
+// Outcome of `fetch`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FetchReport {
+    pub directory: PathBuf,
+    // Whether `fetch` made the directory, this time or in an interrupted run before, so
+    // it is the caller's to remove once done with the chunks
+    pub created: bool,
+    pub downloaded: Vec<String>,
+    // Chunks the directory already held intact
+    pub cached: Vec<String>,
+    // Bytes that came over the network
+    pub bytes: u64,
+}
+
+// Download the set at `options.url` into `options.directory`. The set's info.json goes
+// first, and there has to be one listing the chunks, which `serve` always provides. A
+// directory that already has an info.json must have the same one, and chunks in it
+// that check out are kept. Each chunk is checked as it arrives, by its hash if the
+// set has them, its size, and for compressed chunks by decoding it; one that fails
+// that, or whose download fails, is tried again after a pause that doubles every
+// time. Missing on the server, or refused, it fails the fetch straight away. Events
+// are those of a reconstruction, `ChunkStarted` with the chunk's decoded size, for
+// the chunks downloaded; those already there are only in the report.
+pub fn fetch(
+    options: &FetchOptions,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<FetchReport> {
+    if options.connections == 0 {
+        return Err(SplitterError::InvalidOption {
+            field: "connections",
+            reason: "must be at least 1",
+        });
+    }
+    let base = base_url(&options.url)?;
+    if options.auth.is_some() && !base.is_secure() {
+        if !base.is_local() && !options.insecure_auth {
+            return Err(SplitterError::InvalidOption {
+                field: "url",
+                reason: "is http:// to a host off the local network, which would send the \
+                         credentials unencrypted; use https",
+            });
+        }
+        warn!(
+            "sending credentials to {} unencrypted, over plain http",
+            base.host()
+        );
+    }
+    let manifest_url = base.join(MANIFEST_NAME);
+    let manifest_path = PathBuf::from(manifest_url.to_string());
+    info!("fetching {}", manifest_url);
+    let text = download_text(&manifest_url, options.auth.as_ref()).at(&manifest_path)?;
+    let manifest = Manifest::parse(&text, &manifest_path, options.accept_modified)?;
+    if manifest.chunks.is_empty() {
+        return Err(SplitterError::InvalidOption {
+            field: "url",
+            reason: "its info.json lists no chunks",
+        });
+    }
+    let directory = options
+        .directory
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(format!("{}.chunks", manifest.original_filename)));
+    let marker = directory.join(MARKER);
+    if !directory.exists() {
+        fs::create_dir_all(&directory).at(&directory)?;
+        fs::write(&marker, format!("{}\n", base)).at(&marker)?;
+    }
+    let created = marker.is_file();
+    let local = directory.join(MANIFEST_NAME);
+    match fs::read(&local) {
+        Ok(existing) if !same_manifest(&existing, &text) => {
+            return Err(SplitterError::ManifestMismatch { path: local });
+        }
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => fs::write(&local, &text).at(&local)?,
+        Err(e) => return Err(e).at(&local),
+    }
+
+    let mut report = FetchReport {
+        directory: directory.clone(),
+        created,
+        downloaded: Vec::new(),
+        cached: Vec::new(),
+        bytes: 0,
+    };
+    let mut wanted: Vec<(usize, &ChunkEntry)> = Vec::new();
+    for (index, entry) in manifest.indexed() {
+        cancel.check()?;
+        match is_chunk_intact(&directory, &manifest, entry, cancel)? {
+            true => report.cached.push(entry.name.clone()),
+            // A file other chunks are stored in as well comes down once
+            false if wanted.iter().any(|(_, other)| other.file() == entry.file()) => {}
+            false => wanted.push((index, entry)),
+        }
+    }
+    info!(
+        "{} of {} chunks to download, {} already in {}",
+        wanted.len(),
+        manifest.chunks.len(),
+        report.cached.len(),
+        directory.display()
+    );
+
+    let job = Job {
+        base,
+        directory,
+        manifest: manifest.clone(),
+        options: options.clone(),
+        cancel: cancel.clone(),
+    };
+    let mut record = |event: ProgressEvent| {
+        match &event {
+            ProgressEvent::BytesCopied { delta } => report.bytes += delta,
+            ProgressEvent::ChunkFinished { index, .. } => {
+                let entry = wanted.iter().find(|(i, _)| i == index);
+                report.downloaded.extend(entry.map(|(_, e)| e.name.clone()));
+            }
+            _ => {}
+        }
+        progress(event);
+    };
+    #[cfg(feature = "async")]
+    download_async(job, &wanted, &mut record)?;
+    #[cfg(not(feature = "async"))]
+    download_threads(&job, &wanted, &mut record)?;
+
+    progress(ProgressEvent::Completed {
+        report: Report::Fetch(report.clone()),
+    });
+    Ok(report)
+}
+
+// Download the chunks of `wanted`, `options.connections` at once on threads of their
+// own, each retried by `Job::chunk`.
+#[cfg(not(feature = "async"))]
+fn download_threads(
+    job: &Job,
+    wanted: &[(usize, &ChunkEntry)],
+    progress: &mut dyn FnMut(ProgressEvent),
+) -> Result<()> {
+    let cancel = &job.cancel;
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel::<Result<ProgressEvent>>();
+    thread::scope(|scope| {
+        for _ in 0..job.options.connections.min(wanted.len()) {
+            let sender = sender.clone();
+            let (next, failed) = (&next, &failed);
+            scope.spawn(move || {
+                while !failed.load(Ordering::Relaxed) && !cancel.is_cancelled() {
+                    let Some(&(index, entry)) = wanted.get(next.fetch_add(1, Ordering::Relaxed))
+                    else {
+                        return;
+                    };
+                    let size = entry.size;
+                    let _ = sender.send(Ok(ProgressEvent::ChunkStarted { index, size }));
+                    let mut copied = |delta| {
+                        let _ = sender.send(Ok(ProgressEvent::BytesCopied { delta }));
+                    };
+                    let result = job.chunk(entry, &mut copied);
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    let finished = ProgressEvent::ChunkFinished {
+                        index,
+                        hash: entry.hash.clone(),
+                    };
+                    let _ = sender.send(result.map(|_| finished));
+                }
+            });
+        }
+        drop(sender);
+
+        let mut error = None;
+        for result in receiver {
+            match result {
+                Ok(event) => progress(event),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        error.map_or_else(|| cancel.check(), Err)
+    })
+}
+
+// Download the chunks of `wanted` through the async engine, `options.connections` at
+// once, each tried `options.retries` more times.
+#[cfg(feature = "async")]
+fn download_async(
+    job: Job,
+    wanted: &[(usize, &ChunkEntry)],
+    progress: &mut dyn FnMut(ProgressEvent),
+) -> Result<()> {
+    let engine =
+        Engine::new(job.options.connections, job.options.retries, &job.cancel).at(&job.directory)?;
+    let entries: BTreeMap<usize, ChunkEntry> = wanted
+        .iter()
+        .map(|&(index, entry)| (index, entry.clone()))
+        .collect();
+    let indices = entries.keys().copied().collect();
+    let transfer = move |index: usize, progress: &mut dyn FnMut(ProgressEvent)| {
+        let entry = &entries[&index];
+        let size = entry.size;
+        progress(ProgressEvent::ChunkStarted { index, size });
+        job.attempt(entry, &mut |delta| {
+            progress(ProgressEvent::BytesCopied { delta })
+        })?;
+        progress(ProgressEvent::ChunkFinished {
+            index,
+            hash: entry.hash.clone(),
+        });
+        Ok(())
+    };
+    engine.run(indices, transfer, progress).map(drop)
+}
+
+// The URL the set's files are under, for a URL given as either that directory or its
+// info.json.
+fn base_url(text: &str) -> Result<Url> {
+    let text = text.strip_suffix(MANIFEST_NAME).unwrap_or(text);
+    Url::parse(text).map_err(|e| {
+        let reason = match e.kind() {
+            io::ErrorKind::Unsupported => "is https, which needs a build with the tls feature",
+            _ => "must be an http:// or https:// URL",
+        };
+        SplitterError::InvalidOption {
+            field: "url",
+            reason,
+        }
+    })
+}
+
+// Whether two info.json files say the same, however they are laid out.
+fn same_manifest(existing: &[u8], fetched: &[u8]) -> bool {
+    let parse = |text| serde_json::from_slice::<serde_json::Value>(text).ok();
+    parse(existing).is_some_and(|existing| Some(existing) == parse(fetched))
+}
+
+fn download_text(url: &Url, auth: Option<&Auth>) -> io::Result<Vec<u8>> {
+    let mut response = http::get(url, auth, 0)?;
+    check_status(response.status)?;
+    let mut text = Vec::new();
+    io::copy(&mut response, &mut text)?;
+    Ok(text)
+}
+
+// Statuses worth trying again come back as errors of their own kind, `Interrupted`;
+// the rest that aren't success are final.
+fn check_status(status: u16) -> io::Result<()> {
+    let (kind, message) = match status {
+        200..=299 => return Ok(()),
+        401 => (
+            io::ErrorKind::PermissionDenied,
+            "the server wants credentials",
+        ),
+        403 => (io::ErrorKind::PermissionDenied, "the server refused access"),
+        404 | 410 => (io::ErrorKind::NotFound, "not on the server"),
+        408 | 429 | 500..=599 => (io::ErrorKind::Interrupted, "the server failed"),
+        _ => (io::ErrorKind::Other, "unexpected response"),
+    };
+    Err(io::Error::new(kind, format!("{} ({})", message, status)))
+}
+
+// What every chunk's download needs.
+struct Job {
+    base: Url,
+    directory: PathBuf,
+    manifest: Manifest,
+    options: FetchOptions,
+    cancel: CancelToken,
+}
+
+impl Job {
+    // `attempt` at `entry` until it checks out, pausing between attempts.
+    #[cfg(not(feature = "async"))]
+    fn chunk(&self, entry: &ChunkEntry, copied: &mut dyn FnMut(u64)) -> Result<()> {
+        let url = self.base.join(entry.file());
+        let mut backoff = Duration::from_secs(1);
+        let mut attempt = 0;
+        loop {
+            let failure = match self.attempt(entry, copied) {
+                Ok(()) => return Ok(()),
+                Err(SplitterError::Io { source, .. })
+                    if retryable(&source) && !self.cancel.is_cancelled() =>
+                {
+                    source
+                }
+                Err(e) => return Err(e),
+            };
+            if attempt >= self.options.retries {
+                return Err(failure).at(Path::new(&url.to_string()));
+            }
+            attempt += 1;
+            warn!(
+                "{}: {}; trying again in {} s ({} of {})",
+                url,
+                failure,
+                backoff.as_secs(),
+                attempt,
+                self.options.retries
+            );
+            self.pause(backoff)?;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    // Download `entry` under a temporary name and, if it checks out, give it its own.
+    fn attempt(&self, entry: &ChunkEntry, copied: &mut dyn FnMut(u64)) -> Result<()> {
+        let url = self.base.join(entry.file());
+        let url_path = PathBuf::from(url.to_string());
+        let part = ChunkEntry {
+            name: format!("{}.part", entry.file()),
+            same_as: None,
+            ..entry.clone()
+        };
+        let directory = self.directory.as_path();
+        let part_path = directory.join(&part.name);
+        restore_shard_dir(directory, &part.name)?;
+        match self.download(&url, &part_path, copied) {
+            Ok(()) if is_chunk_intact(directory, &self.manifest, &part, &self.cancel)? => {
+                let path = directory.join(entry.file());
+                fs::rename(&part_path, &path).at(&path)?;
+                debug!("{} arrived intact", url);
+                Ok(())
+            }
+            Ok(()) => {
+                fs::remove_file(&part_path).at(&part_path)?;
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "damaged on the way",
+                ))
+                .at(&url_path)
+            }
+            Err(e) => Err(e).at(&url_path),
+        }
+    }
+
+    // Download `url` into `part_path`, continuing what is there if the server allows.
+    fn download(&self, url: &Url, part_path: &Path, copied: &mut dyn FnMut(u64)) -> io::Result<()> {
+        let have = fs::metadata(part_path).map_or(0, |metadata| metadata.len());
+        let mut response = http::get(url, self.options.auth.as_ref(), have)?;
+        if response.status == 416 {
+            // The part is as long as the file or longer, so it can't be continued
+            fs::remove_file(part_path)?;
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "the partial download is no use",
+            ));
+        }
+        check_status(response.status)?;
+        let file = match (response.status, response.range_start()) {
+            (206, Some(start)) if start == have => {
+                debug!("continuing {} from byte {}", url, have);
+                OpenOptions::new().append(true).open(part_path)?
+            }
+            (206, _) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    "the server sent the wrong part",
+                ));
+            }
+            // Whole, with or without a range asked for
+            _ => File::create(part_path)?,
+        };
+        let mut sink = Counting {
+            inner: file,
+            copied,
+            cancel: &self.cancel,
+        };
+        copy_overlapped(&Io::default(), &mut response, &mut sink, None)?;
+        sink.inner.flush()?;
+        sink.inner.sync_all()
+    }
+
+    // Wait `pause`, a little at a time so Ctrl+C isn't held up.
+    #[cfg(not(feature = "async"))]
+    fn pause(&self, pause: Duration) -> Result<()> {
+        let until = Instant::now() + pause;
+        while Instant::now() < until {
+            self.cancel.check()?;
+            thread::sleep(Duration::from_millis(100));
+        }
+        self.cancel.check()
+    }
+}
+
+// Failures that another attempt may get past: the network, the server having trouble,
+// or a damaged download. A missing file, refused access or a cancellation won't be.
+pub(crate) fn retryable(error: &io::Error) -> bool {
+    let cancelled = error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<SplitterError>())
+        .is_some_and(|inner| matches!(inner, SplitterError::Cancelled));
+    !cancelled
+        && !matches!(
+            error.kind(),
+            io::ErrorKind::NotFound
+                | io::ErrorKind::PermissionDenied
+                | io::ErrorKind::InvalidInput
+                | io::ErrorKind::Unsupported
+                | io::ErrorKind::Other
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fetch_with(url: &str) -> Result<FetchReport> {
+        let directory = tempfile::tempdir().unwrap();
+        let options = FetchOptions {
+            directory: Some(directory.path().join("set")),
+            retries: 0,
+            auth: Some(Auth::Bearer("secret".to_string())),
+            ..FetchOptions::new(url)
+        };
+        fetch(&options, &mut |_| {}, &CancelToken::new())
+    }
+
+    #[test]
+    fn credentials_only_go_over_plain_http_on_the_local_network() {
+        // 192.0.2.0/24 is set aside for documentation, so nothing answers there
+        let refused = fetch_with("http://192.0.2.1:9/set/").unwrap_err();
+        assert!(
+            matches!(refused, SplitterError::InvalidOption { field: "url", .. }),
+            "{}",
+            refused
+        );
+        // Locally they are sent, and the fetch fails only because nothing listens there
+        for url in ["http://127.0.0.1:9/set/", "http://[::1]:9/"] {
+            let error = fetch_with(url).unwrap_err();
+            assert!(
+                !matches!(error, SplitterError::InvalidOption { .. }),
+                "{}",
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,200 @@
+// Arithmetic in GF(2^16), the field PAR2 computes its recovery slices in, reduced by
+// x^16 + x^12 + x^3 + x + 1 as the specification has it. Data is taken as 16-bit
+// little-endian words. The tables are too big to build at compile time, so the first
+// use builds them.
+
+use std::sync::OnceLock;
+
+const POLYNOMIAL: u32 = 0x1100b;
+
+// The multiplicative group's order; every nonzero value is a power of 2 below it
+pub(crate) const ORDER: u32 = 65535;
+
+struct Tables {
+    exp: Vec<u16>,
+    log: Vec<u16>,
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        let mut exp = vec![0; ORDER as usize];
+        let mut log = vec![0; ORDER as usize + 1];
+        let mut x: u32 = 1;
+        for (i, value) in exp.iter_mut().enumerate() {
+            *value = x as u16;
+            log[x as usize] = i as u16;
+            x <<= 1;
+            if x & 0x10000 != 0 {
+                x ^= POLYNOMIAL;
+            }
+        }
+        Tables { exp, log }
+    })
+}
+
+// 2 to the power `n`.
+pub(crate) fn exp(n: u32) -> u16 {
+    tables().exp[(n % ORDER) as usize]
+}
+
+pub(crate) fn mul(a: u16, b: u16) -> u16 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    let tables = tables();
+    let sum = u32::from(tables.log[a as usize]) + u32::from(tables.log[b as usize]);
+    tables.exp[(sum % ORDER) as usize]
+}
+
+pub(crate) fn pow(a: u16, n: u32) -> u16 {
+    if a == 0 {
+        return u16::from(n == 0);
+    }
+    let log = u64::from(tables().log[a as usize]);
+    exp((log * u64::from(n) % u64::from(ORDER)) as u32)
+}
+
+// Zero has no inverse; callers never ask for it.
+pub(crate) fn inv(a: u16) -> u16 {
+    debug_assert!(a != 0);
+    exp(ORDER - u32::from(tables().log[a as usize]))
+}
+
+// `target += factor * data`, word by word. Both are whole words long.
+pub(crate) fn mul_add(factor: u16, data: &[u8], target: &mut [u8]) {
+    if factor == 0 {
+        return;
+    }
+    // A word's product is that of its low byte plus that of its high byte
+    let mut low = [0u16; 256];
+    let mut high = [0u16; 256];
+    for byte in 0..256 {
+        low[byte] = mul(factor, byte as u16);
+        high[byte] = mul(factor, (byte as u16) << 8);
+    }
+    for (word, other) in target.chunks_exact_mut(2).zip(data.chunks_exact(2)) {
+        let product = low[other[0] as usize] ^ high[other[1] as usize];
+        word[0] ^= product as u8;
+        word[1] ^= (product >> 8) as u8;
+    }
+}
+
+// The inverse of a square matrix, by Gauss–Jordan elimination; None when it is singular.
+pub(crate) fn invert(matrix: &[Vec<u16>]) -> Option<Vec<Vec<u16>>> {
+    let n = matrix.len();
+    let mut left = matrix.to_vec();
+    let mut right: Vec<Vec<u16>> = (0..n)
+        .map(|row| (0..n).map(|column| u16::from(row == column)).collect())
+        .collect();
+    for column in 0..n {
+        let pivot = (column..n).find(|&row| left[row][column] != 0)?;
+        left.swap(column, pivot);
+        right.swap(column, pivot);
+        let scale = inv(left[column][column]);
+        for value in left[column].iter_mut().chain(right[column].iter_mut()) {
+            *value = mul(*value, scale);
+        }
+        for row in 0..n {
+            let factor = left[row][column];
+            if row == column || factor == 0 {
+                continue;
+            }
+            for i in 0..n {
+                left[row][i] ^= mul(factor, left[column][i]);
+                right[row][i] ^= mul(factor, right[column][i]);
+            }
+        }
+    }
+    Some(right)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Multiplication the long way: shift and add, reducing as it goes.
+    fn slow_mul(mut a: u16, mut b: u16) -> u16 {
+        let mut product = 0;
+        while b != 0 {
+            if b & 1 != 0 {
+                product ^= a;
+            }
+            b >>= 1;
+            let carry = a & 0x8000 != 0;
+            a <<= 1;
+            if carry {
+                a ^= POLYNOMIAL as u16;
+            }
+        }
+        product
+    }
+
+    #[test]
+    fn powers_of_two_match_the_specification() {
+        // The first constants PAR2 gives recovery slices: 2^n for n coprime with 65535
+        let constants = [2, 4, 16, 128, 256, 2048, 8192, 16384, 4107, 32856, 17132];
+        let exponents = (1..).filter(|n| [3, 5, 17, 257].iter().all(|p| n % p != 0));
+        for (n, constant) in exponents.zip(constants) {
+            assert_eq!(exp(n), constant, "2^{}", n);
+        }
+        assert_eq!(exp(0), 1);
+        assert_eq!(exp(ORDER), 1);
+        assert_eq!(exp(15), 0x8000);
+    }
+
+    #[test]
+    fn products_match_the_long_way() {
+        let values = [0, 1, 2, 3, 0x8000, 0x100b, 0x1234, 0xabcd, 0xfffe, 0xffff];
+        for a in values {
+            for b in values {
+                assert_eq!(mul(a, b), slow_mul(a, b), "{:#x} * {:#x}", a, b);
+            }
+        }
+        assert_eq!(mul(0x8000, 2), 0x100b);
+        assert_eq!(mul(0x1234, 0x5678), slow_mul(0x1234, 0x5678));
+    }
+
+    #[test]
+    fn inverses_and_powers() {
+        assert_eq!(inv(1), 1);
+        assert_eq!(inv(2), 0x8805);
+        for a in (1..=u16::MAX).step_by(251) {
+            assert_eq!(mul(a, inv(a)), 1, "{:#x}", a);
+            assert_eq!(pow(a, ORDER), 1, "{:#x}", a);
+            assert_eq!(pow(a, 3), mul(a, mul(a, a)), "{:#x}", a);
+        }
+        assert_eq!(pow(0, 0), 1);
+        assert_eq!(pow(0, 5), 0);
+    }
+
+    #[test]
+    fn mul_add_takes_little_endian_words() {
+        let data = [0x34, 0x12, 0x00, 0x80];
+        let mut target = [0xff, 0x00, 0x01, 0x00];
+        mul_add(2, &data, &mut target);
+        let first = 0x00ff ^ slow_mul(0x1234, 2);
+        let second = 0x0001 ^ 0x100b;
+        assert_eq!(
+            target,
+            [first as u8, (first >> 8) as u8, second as u8, 0x10]
+        );
+        mul_add(0, &data, &mut target);
+        assert_eq!(target[2..], [0x0a, 0x10]);
+    }
+
+    #[test]
+    fn inverting_a_matrix() {
+        let matrix = vec![vec![1, 1, 1], vec![2, 4, 16], vec![4, 16, 256]];
+        let inverse = invert(&matrix).unwrap();
+        for (row, values) in matrix.iter().enumerate() {
+            for column in 0..3 {
+                let sum = (values.iter().zip(&inverse)).fold(0, |sum, (&value, inverse)| {
+                    sum ^ mul(value, inverse[column])
+                });
+                assert_eq!(sum, u16::from(row == column));
+            }
+        }
+        assert!(invert(&[vec![1, 2], vec![2, 4]]).is_none());
+    }
+}
@@ -0,0 +1,178 @@
+// Gzip (RFC 1952) around DEFLATE (RFC 1951), through flate2: a streaming encoder with
+// levels 1 to 9, and a streaming decoder for any valid stream, including ones other
+// tools wrote, and for the bare DEFLATE inside zip archives.
+//
+// The header every stream we write has no name and a zero timestamp, so the same input
+// at the same level always compresses to the same bytes, which `splits_are_reproducible`
+// relies on. Damaged data of any sort reads as InvalidData, as a damaged chunk should.
+
+use std::io::{self, BufRead, Read, Write};
+
+use flate2::bufread::{DeflateDecoder, MultiGzDecoder};
+use flate2::{Compression, GzBuilder};
+
+// The CRC-32 gzip and zip use.
+#[derive(Clone, Default)]
+pub(crate) struct Crc32(crc32fast::Hasher);
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.0.clone().finalize()
+    }
+}
+
+// Compresses everything written to it into a gzip stream on `inner`. `finish` writes
+// the end of the stream; dropping the encoder without it leaves a truncated stream.
+pub(crate) struct GzEncoder<W: Write>(flate2::write::GzEncoder<W>);
+
+impl<W: Write> GzEncoder<W> {
+    // Level 0, which flate2 takes as storing without compression, is level 1 here.
+    pub fn new(inner: W, level: u32) -> GzEncoder<W> {
+        let level = Compression::new(level.clamp(1, 9));
+        GzEncoder(GzBuilder::new().mtime(0).write(inner, level))
+    }
+
+    // End the stream and hand back the writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.0.finish()
+    }
+}
+
+impl<W: Write> Write for GzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+// Decompresses a gzip stream from `inner`, several members one after the other if
+// that is what it holds, checking each member's CRC and length. `raw` reads bare
+// DEFLATE instead, as zip entries hold, and stops after its last block; nothing there
+// to check it against, so that is up to the caller.
+pub(crate) enum GzDecoder<R: BufRead> {
+    Gzip(MultiGzDecoder<R>),
+    Raw(DeflateDecoder<R>),
+}
+
+impl<R: BufRead> GzDecoder<R> {
+    pub fn new(inner: R) -> GzDecoder<R> {
+        GzDecoder::Gzip(MultiGzDecoder::new(inner))
+    }
+
+    pub fn raw(inner: R) -> GzDecoder<R> {
+        GzDecoder::Raw(DeflateDecoder::new(inner))
+    }
+}
+
+impl<R: BufRead> Read for GzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match self {
+            GzDecoder::Gzip(decoder) => decoder.read(buf),
+            GzDecoder::Raw(decoder) => decoder.read(buf),
+        };
+        // flate2 reports a bad header, block or trailer as invalid input, and a stream
+        // cut short as an early end
+        read.map_err(|e| match e.kind() {
+            io::ErrorKind::InvalidInput | io::ErrorKind::UnexpectedEof => io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("corrupt gzip data: {}", e),
+            ),
+            _ => e,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compress(data: &[u8], level: u32) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), level);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        GzDecoder::new(data).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    fn sample() -> Vec<u8> {
+        (0..20_000u32)
+            .flat_map(|i| format!("line {} of {}\n", i % 977, i / 3).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_at_every_level() {
+        let data = sample();
+        for level in 0..=9 {
+            let compressed = compress(&data, level);
+            assert!(compressed.len() < data.len() / 4, "level {}", level);
+            assert_eq!(decompress(&compressed).unwrap(), data, "level {}", level);
+        }
+        assert_eq!(decompress(&compress(b"", 6)).unwrap(), b"");
+    }
+
+    #[test]
+    fn the_same_input_compresses_to_the_same_bytes() {
+        let data = sample();
+        let compressed = compress(&data, 6);
+        assert_eq!(compressed, compress(&data, 6));
+        // No name, no timestamp, OS unknown
+        assert_eq!(&compressed[..4], [0x1f, 0x8b, 8, 0]);
+        assert_eq!(&compressed[4..8], [0; 4]);
+        assert_eq!(compressed[9], 255);
+    }
+
+    #[test]
+    fn reads_every_member_of_a_stream() {
+        let mut joined = compress(b"first, ", 1);
+        joined.extend(compress(b"then second", 9));
+        assert_eq!(decompress(&joined).unwrap(), b"first, then second");
+    }
+
+    #[test]
+    fn reads_bare_deflate() {
+        let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"inside a zip").unwrap();
+        let deflated = encoder.finish().unwrap();
+        let mut out = Vec::new();
+        GzDecoder::raw(&deflated[..]).read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"inside a zip");
+    }
+
+    #[test]
+    fn damage_reads_as_invalid_data() {
+        let compressed = compress(&sample(), 6);
+        let mut flipped = compressed.clone();
+        let end = flipped.len() - 6;
+        flipped[end] ^= 0x40;
+        let truncated = &compressed[..compressed.len() / 2];
+        for damaged in [&flipped[..], truncated, b"not gzip at all"] {
+            let error = decompress(damaged).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", error);
+        }
+    }
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        let mut crc = Crc32::new();
+        crc.update(b"12345");
+        crc.update(b"6789");
+        assert_eq!(crc.finish(), 0xcbf4_3926);
+        assert_eq!(Crc32::new().finish(), 0);
+    }
+}
@@ -0,0 +1,344 @@
+// Fixing a chunk set in place from other copies of it, such as a mirror written
+// alongside it, when each copy has lost different chunks but together they are whole.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::changes::FileChange;
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::{ProgressEvent, Report};
+use crate::lock;
+use crate::manifest::{ChunkEntry, Compression, MANIFEST_NAME, Manifest};
+use crate::parity::{self, is_chunk_intact};
+use crate::pipeline::{Io, copy_overlapped};
+use crate::store::{ChunkReader, ChunkStore, LocalDirStore, restore_shard_dir, temp_name};
+
+// Outcome of `heal`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HealReport {
+    pub directory: PathBuf,
+    // Chunks that were missing or damaged, each with the copy it was taken from
+    pub healed: Vec<HealedChunk>,
+    // Chunks that are still missing or damaged, having no intact copy anywhere
+    pub damaged: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HealedChunk {
+    pub name: String,
+    pub source: PathBuf,
+}
+
+impl HealReport {
+    pub fn is_ok(&self) -> bool {
+        self.damaged.is_empty()
+    }
+}
+
+// What `heal` would do, from `plan_heal`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HealPlan {
+    pub directory: PathBuf,
+    // Chunks that are missing or damaged, each with the first copy it is intact in
+    pub chunks: Vec<HealedChunk>,
+    // Chunks that are missing or damaged with no intact copy anywhere
+    pub damaged: Vec<String>,
+    // The chunk files that would be written, with their sizes
+    pub changes: Vec<FileChange>,
+}
+
+// A set's manifest, with every copy to heal it from and the copies' chunks by index,
+// as sets with random names have different names in every copy.
+struct Copies<'a> {
+    manifest: Manifest,
+    copies: Vec<(&'a Path, Manifest)>,
+}
+
+impl Copies<'_> {
+    fn indexed(&self) -> Vec<BTreeMap<usize, &ChunkEntry>> {
+        self.copies
+            .iter()
+            .map(|(_, other)| other.indexed().collect())
+            .collect()
+    }
+}
+
+// Replace the missing and damaged chunks of `directory` with intact ones from `sources`,
+// other copies of the same split, trying them in order for each chunk. A copy is used
+// when it checks out the way `verify` would check it, and is written under a temporary
+// name and checked again before being renamed over the damaged chunk. Stored another
+// way in the source than in `directory` (compressed or not), it is converted on the
+// way. A source whose manifest describes a different split stops everything before
+// anything is written. Afterwards `directory` is checked again, and whatever is still
+// damaged is reported. Parity files are left to `repair`, which computes them again.
+// Manifests changed after they were sealed are refused unless `accept_modified`.
+pub fn heal(
+    directory: &Path,
+    sources: &[PathBuf],
+    accept_modified: bool,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<HealReport> {
+    let _lock = lock::acquire(directory, "heal")?;
+    let set = open_copies(directory, sources, accept_modified)?;
+    let manifest = &set.manifest;
+    let indexed = set.indexed();
+    let mut report = HealReport {
+        directory: directory.to_path_buf(),
+        healed: Vec::new(),
+        damaged: Vec::new(),
+    };
+    for (index, entry, first) in survey(directory, &set, &indexed, cancel)? {
+        progress(ProgressEvent::ChunkStarted {
+            index,
+            size: entry.size,
+        });
+        let mut healed = None;
+        // From the copy the chunk was found intact in, which needs no second look
+        let tried = set.copies.iter().zip(&indexed).enumerate();
+        for (number, ((source, other), entries)) in tried.skip(first.unwrap_or(usize::MAX)) {
+            let Some(&found) = entries.get(&index) else {
+                continue;
+            };
+            if Some(number) != first && !is_chunk_intact(source, other, found, cancel)? {
+                debug!("{} is damaged in {} too", found.name, source.display());
+                continue;
+            }
+            if replace_chunk(
+                directory,
+                manifest,
+                (index, entry),
+                (source, other, found),
+                cancel,
+            )? {
+                healed = Some(source);
+                break;
+            }
+        }
+        if let Some(source) = healed {
+            info!("healed {} from {}", entry.name, source.display());
+            report.healed.push(HealedChunk {
+                name: entry.name.clone(),
+                source: source.to_path_buf(),
+            });
+        }
+        progress(ProgressEvent::ChunkFinished { index, hash: None });
+    }
+
+    let damaged = parity::damaged(directory, manifest, cancel)?;
+    report.damaged = manifest
+        .indexed()
+        .filter(|(index, _)| damaged.contains(index))
+        .map(|(_, entry)| entry.name.clone())
+        .collect();
+    progress(ProgressEvent::Completed {
+        report: Report::Heal(report.clone()),
+    });
+    Ok(report)
+}
+
+// What `heal` would do with the same arguments, checking everything it would check
+// before writing anything, and writing nothing.
+pub fn plan_heal(
+    directory: &Path,
+    sources: &[PathBuf],
+    accept_modified: bool,
+    cancel: &CancelToken,
+) -> Result<HealPlan> {
+    let _lock = lock::acquire(directory, "heal")?;
+    let set = open_copies(directory, sources, accept_modified)?;
+    let manifest = &set.manifest;
+    let indexed = set.indexed();
+    let mut plan = HealPlan {
+        directory: directory.to_path_buf(),
+        chunks: Vec::new(),
+        damaged: Vec::new(),
+        changes: Vec::new(),
+    };
+    for (index, entry, first) in survey(directory, &set, &indexed, cancel)? {
+        let Some(number) = first else {
+            plan.damaged.push(entry.name.clone());
+            continue;
+        };
+        let (source, other) = &set.copies[number];
+        let found = indexed[number][&index];
+        // Copied as it is when stored the same way, else converted
+        let compression = manifest.compression_of(entry);
+        let size = match compression == other.compression_of(found) {
+            true => fs::metadata(source.join(found.file()))
+                .ok()
+                .map(|m| m.len()),
+            false => Some(entry.size).filter(|_| compression.is_none()),
+        };
+        plan.changes
+            .push(FileChange::write(directory.join(entry.file()), size));
+        plan.chunks.push(HealedChunk {
+            name: entry.name.clone(),
+            source: source.to_path_buf(),
+        });
+    }
+    Ok(plan)
+}
+
+// The manifests of `directory` and of each of `sources`, which must all describe the
+// same split.
+fn open_copies<'a>(
+    directory: &Path,
+    sources: &'a [PathBuf],
+    accept_modified: bool,
+) -> Result<Copies<'a>> {
+    let manifest = load(directory, accept_modified)?;
+    let mut copies = Vec::with_capacity(sources.len());
+    for source in sources {
+        if fs::canonicalize(source).ok() == fs::canonicalize(directory).ok() {
+            return Err(SplitterError::InvalidOption {
+                field: "sources",
+                reason: "must not include the directory being healed",
+            });
+        }
+        let other = load(source, accept_modified)?;
+        if !same_split(&manifest, &other) {
+            return Err(SplitterError::ManifestMismatch {
+                path: source.join(MANIFEST_NAME),
+            });
+        }
+        copies.push((source.as_path(), other));
+    }
+    Ok(Copies { manifest, copies })
+}
+
+// Every missing or damaged chunk of `directory`, with the number of the first copy it
+// is intact in, if any.
+fn survey<'a>(
+    directory: &Path,
+    set: &'a Copies,
+    indexed: &[BTreeMap<usize, &ChunkEntry>],
+    cancel: &CancelToken,
+) -> Result<Vec<(usize, &'a ChunkEntry, Option<usize>)>> {
+    info!("checking the chunks of {}", directory.display());
+    let damaged = parity::damaged(directory, &set.manifest, cancel)?;
+    let mut found = Vec::new();
+    for (index, entry) in set.manifest.indexed() {
+        if !damaged.contains(&index) {
+            continue;
+        }
+        let mut first = None;
+        for (number, ((source, other), entries)) in set.copies.iter().zip(indexed).enumerate() {
+            let Some(&copy) = entries.get(&index) else {
+                continue;
+            };
+            if is_chunk_intact(source, other, copy, cancel)? {
+                first = Some(number);
+                break;
+            }
+            debug!("{} is damaged in {} too", copy.name, source.display());
+        }
+        found.push((index, entry, first));
+    }
+    Ok(found)
+}
+
+// The manifest of `directory`, which must have one.
+pub(crate) fn load(directory: &Path, accept_modified: bool) -> Result<Manifest> {
+    match Manifest::load(directory, accept_modified)? {
+        Some(manifest) => Ok(manifest),
+        None => {
+            let path = directory.join(MANIFEST_NAME);
+            Err(io::Error::from(io::ErrorKind::NotFound)).at(&path)
+        }
+    }
+}
+
+// Whether `other` lists the same chunks of the same file as `manifest`, however each
+// copy stores or names them. Hashes only count where both copies have them.
+pub(crate) fn same_split(manifest: &Manifest, other: &Manifest) -> bool {
+    manifest.original_filename == other.original_filename
+        && manifest.chunks.len() == other.chunks.len()
+        && manifest.indexed().zip(other.indexed()).all(
+            |((index, entry), (other_index, other_entry))| {
+                let hashes = match (manifest.hash == other.hash, &entry.hash, &other_entry.hash) {
+                    (true, Some(hash), Some(other_hash)) => hash == other_hash,
+                    _ => true,
+                };
+                index == other_index && entry.size == other_entry.size && hashes
+            },
+        )
+}
+
+// Put the intact chunk `found` of `source` in place of `entry`, by way of a temporary
+// file. False, with nothing changed, when the copy doesn't check out once written.
+fn replace_chunk(
+    directory: &Path,
+    manifest: &Manifest,
+    (index, entry): (usize, &ChunkEntry),
+    (source, other, found): (&Path, &Manifest, &ChunkEntry),
+    cancel: &CancelToken,
+) -> Result<bool> {
+    let temp_name = temp_name(&entry.name, "heal");
+    restore_shard_dir(directory, &entry.name)?;
+    let temp = directory.join(&temp_name);
+    let from = source.join(found.file());
+    let (compression, from_compression) =
+        (manifest.compression_of(entry), other.compression_of(found));
+    let copied = match compression == from_compression {
+        true => fs::copy(&from, &temp).map(|_| ()).at(&temp),
+        false => {
+            let store = LocalDirStore::new(directory)
+                .compressed(compression, compression.default_level())
+                .counted(manifest.chunks.len());
+            convert(&from, from_compression, &store, index, &temp_name)
+        }
+    };
+    // Checked as the chunk it is about to become
+    let checked = copied.and_then(|()| {
+        let temp_entry = ChunkEntry {
+            name: temp_name,
+            compression: Some(compression),
+            same_as: None,
+            ..entry.clone()
+        };
+        is_chunk_intact(directory, manifest, &temp_entry, cancel)
+    });
+    match checked {
+        Ok(true) => {
+            let path = directory.join(entry.file());
+            fs::rename(&temp, &path).at(&path)?;
+            Ok(true)
+        }
+        Ok(false) => {
+            let _ = fs::remove_file(&temp);
+            debug!(
+                "the copy of {} from {} does not check out",
+                entry.name,
+                source.display()
+            );
+            Ok(false)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+// Decode the chunk at `from` and store it in `store` as `name`, for chunk `index`, the
+// way the store keeps its chunks.
+fn convert(
+    from: &Path,
+    from_compression: Compression,
+    store: &LocalDirStore,
+    index: usize,
+    name: &str,
+) -> Result<()> {
+    let mut reader = ChunkReader::open(from, from_compression).at(from)?;
+    let mut writer = store.create_named(index, name, store.compression())?;
+    copy_overlapped(&Io::default(), &mut reader, &mut writer, None)
+        .at(&store.directory().join(name))?;
+    store.finish(writer).map(|_| ())
+}
@@ -0,0 +1,91 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+const MAX_ENTRIES: usize = 10;
+
+static WRITE_ENABLED: AtomicBool = AtomicBool::new(true);
+
+// Recently used locations, persisted between runs. Anything wrong with the state file
+// (missing, unreadable, corrupted) just means starting with an empty history.
+#[derive(Default, Serialize, Deserialize)]
+pub struct History {
+    #[serde(default)]
+    pub sources: Vec<PathBuf>,
+    #[serde(default)]
+    pub destinations: Vec<PathBuf>,
+    #[serde(default)]
+    pub directories: Vec<PathBuf>,
+}
+
+// `--no-history` turns off writing; existing history is still read.
+pub fn init(no_history: bool) {
+    WRITE_ENABLED.store(!no_history, Ordering::Relaxed);
+}
+
+// Where this tool keeps what it remembers between runs, the journal too.
+pub fn state_dir() -> Option<PathBuf> {
+    let state_dir = env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| crate::home_dir().map(|home| home.join(".local").join("state")))?;
+    Some(state_dir.join("file_splitter"))
+}
+
+fn state_file() -> Option<PathBuf> {
+    Some(state_dir()?.join("recent.json"))
+}
+
+impl History {
+    pub fn load() -> History {
+        state_file()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) {
+        if !WRITE_ENABLED.load(Ordering::Relaxed) {
+            return;
+        }
+        let Some(path) = state_file() else {
+            return;
+        };
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Ok(data) = serde_json::to_string_pretty(self) {
+            let _ = fs::write(path, data);
+        }
+    }
+
+    pub fn record_split(source: &Path, destination: &Path) {
+        let mut history = History::load();
+        remember(&mut history.sources, source);
+        remember(&mut history.destinations, destination);
+        history.save();
+    }
+
+    pub fn record_directory(directory: &Path) {
+        let mut history = History::load();
+        remember(&mut history.directories, directory);
+        history.save();
+    }
+}
+
+// Move `path` (made absolute so it still works from another directory) to the front.
+fn remember(list: &mut Vec<PathBuf>, path: &Path) {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    // The state file is JSON, which can't hold a path that isn't UTF-8; one such entry
+    // would keep the rest from being saved
+    if path.to_str().is_none() {
+        return;
+    }
+    list.retain(|existing| *existing != path);
+    list.insert(0, path);
+    list.truncate(MAX_ENTRIES);
+}
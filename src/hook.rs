@@ -0,0 +1,431 @@
+// Commands run for every chunk: after a split writes it, for the chunk to be copied
+// elsewhere as soon as it is complete, or before a reconstruction, for it to be
+// fetched. A hook is a program and its arguments, run without a shell, so nothing in a
+// chunk's path is ever read as a command. Each hook also finds the chunk in its
+// environment:
+//
+//   FSR_HOOK          post-chunk or pre-chunk
+//   FSR_CHUNK_PATH    the chunk file, as the split or reconstruction names it
+//   FSR_CHUNK_NAME    its file name alone
+//   FSR_CHUNK_INDEX   its place in the set, from 0
+//   FSR_CHUNK_TOTAL   how many chunks the set has, or 0 when splitting from a pipe,
+//                     where that isn't known until it ends
+//
+// A hook that exits unsuccessfully is run again up to `retries` times, and then fails
+// the operation with the last lines it printed; the hooks still running are killed.
+// When transfers are tracked, every chunk whose hook succeeds is marked uploaded in
+// transfer_state.json, and one whose hook has failed its last run, failed.
+
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::io::{self, BufRead, BufReader, Read};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::error::{PathContext, Result, SplitterError};
+use crate::s3::pause;
+use crate::transfer::{self, TransferState};
+
+// Replaced in every word of a command with the chunk's path, file name, index and the
+// set's size
+const PLACEHOLDERS: [&str; 4] = ["{path}", "{name}", "{index}", "{total}"];
+// Lines of what a hook printed kept for error messages
+const OUTPUT_LINES: usize = 20;
+// Longest wait before running a hook again
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// How often a running hook is checked on, for Ctrl+C
+const POLL: Duration = Duration::from_millis(50);
+
+// A command run for every chunk, see the module comment.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChunkHook {
+    // The program and its arguments, with `{path}`, `{name}`, `{index}` and `{total}`
+    // replaced in each; nothing else in them is interpreted
+    pub command: Vec<String>,
+    // Run the one word of `command` with `sh -c` (`cmd /C` on Windows) instead. The
+    // chunk is then only in the environment: placeholders would put a path where the
+    // shell reads commands, so they are refused
+    #[serde(default)]
+    pub shell: bool,
+    // Hooks running at once
+    pub jobs: usize,
+    // Further runs of a hook that fails, after pauses of 1 s, 2 s, 4 s, …
+    #[serde(default)]
+    pub retries: u32,
+    // Record in transfer_state.json, for a post-chunk hook, which chunks it has uploaded
+    // and which it failed to; see `transfer`
+    #[serde(default)]
+    pub track_transfers: bool,
+}
+
+impl ChunkHook {
+    // One at a time, failing on the first unsuccessful exit.
+    pub fn new(command: Vec<String>) -> ChunkHook {
+        ChunkHook {
+            command,
+            shell: false,
+            jobs: 1,
+            retries: 0,
+            track_transfers: false,
+        }
+    }
+
+    // The checks for a hook given as `field`.
+    pub(crate) fn validate(&self, field: &'static str) -> Result<()> {
+        if self
+            .command
+            .first()
+            .is_none_or(|program| program.is_empty())
+        {
+            return Err(SplitterError::InvalidOption {
+                field,
+                reason: "needs a program to run",
+            });
+        }
+        if self.jobs == 0 {
+            return Err(SplitterError::InvalidOption {
+                field,
+                reason: "needs at least 1 job",
+            });
+        }
+        if self.shell && self.command.len() > 1 {
+            return Err(SplitterError::InvalidOption {
+                field,
+                reason: "takes a shell command as a single string",
+            });
+        }
+        let placeholders = self.command.iter().any(|word| {
+            PLACEHOLDERS
+                .iter()
+                .any(|placeholder| word.contains(placeholder))
+        });
+        if self.shell && placeholders {
+            return Err(SplitterError::InvalidOption {
+                field,
+                reason: "run by a shell takes the chunk from the FSR_CHUNK_* variables, not placeholders",
+            });
+        }
+        Ok(())
+    }
+}
+
+// When a hook runs, for FSR_HOOK and messages.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Phase {
+    PostChunk,
+    PreChunk,
+}
+
+impl Phase {
+    fn name(self) -> &'static str {
+        match self {
+            Phase::PostChunk => "post-chunk",
+            Phase::PreChunk => "pre-chunk",
+        }
+    }
+}
+
+// Runs a hook for each chunk handed to `run`, on `jobs` threads, while the operation
+// goes on. The operation runs with `token`, which a failing hook cancels so that it
+// stops early; `finish` then puts the hook's failure in place of the cancellation.
+// Transfers are tracked in `transfers`, the set's directory, when given.
+pub(crate) struct Hooks {
+    sender: Option<mpsc::Sender<(usize, PathBuf)>>,
+    workers: Vec<JoinHandle<()>>,
+    failure: Arc<Mutex<Option<SplitterError>>>,
+    stop: CancelToken,
+}
+
+impl Hooks {
+    pub(crate) fn start(
+        hook: &ChunkHook,
+        phase: Phase,
+        total: usize,
+        transfers: Option<&Path>,
+        cancel: &CancelToken,
+    ) -> Hooks {
+        let stop = cancel.child();
+        let failure = Arc::new(Mutex::new(None));
+        let (sender, receiver) = mpsc::channel::<(usize, PathBuf)>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..hook.jobs)
+            .map(|_| {
+                let (hook, receiver) = (hook.clone(), Arc::clone(&receiver));
+                let (failure, stop) = (Arc::clone(&failure), stop.clone());
+                let transfers = transfers.map(Path::to_path_buf);
+                thread::spawn(move || {
+                    loop {
+                        // Not in a `while let`, which would hold the lock for the whole body
+                        let next = receiver.lock().unwrap().recv();
+                        let Ok((index, path)) = next else {
+                            return;
+                        };
+                        // What is queued after a failure is only taken off the queue
+                        if stop.is_cancelled() {
+                            continue;
+                        }
+                        let ran = run(&hook, phase, index, total, &path, &stop);
+                        let ran = match (&transfers, ran) {
+                            (Some(directory), Ok(())) => {
+                                transfer::mark_indices(directory, &[index], TransferState::Uploaded)
+                            }
+                            (Some(directory), Err(e)) if !matches!(e, SplitterError::Cancelled) => {
+                                // The hook's failure is the one to report
+                                let _ = transfer::mark_indices(
+                                    directory,
+                                    &[index],
+                                    TransferState::Failed,
+                                );
+                                Err(e)
+                            }
+                            (_, ran) => ran,
+                        };
+                        match ran {
+                            Ok(()) | Err(SplitterError::Cancelled) => {}
+                            Err(e) => {
+                                failure.lock().unwrap().get_or_insert(e);
+                                stop.cancel();
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        Hooks {
+            sender: Some(sender),
+            workers,
+            failure,
+            stop,
+        }
+    }
+
+    // What the operation should run with, so a failing hook stops it.
+    pub(crate) fn token(&self) -> &CancelToken {
+        &self.stop
+    }
+
+    // Queue the hook for chunk `index` at `path`.
+    pub(crate) fn run(&self, index: usize, path: PathBuf) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send((index, path));
+        }
+    }
+
+    // Wait for the hooks queued so far, once the operation has come to `result`. When
+    // that is a failure the hooks are not waited for but stopped; when it was stopped by
+    // a hook, the hook's failure is returned instead, and when the hooks were cancelled,
+    // the cancellation.
+    pub(crate) fn finish<T>(mut self, result: Result<T>) -> Result<T> {
+        if result.is_err() {
+            self.stop.cancel();
+        }
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        let failure = self.failure.lock().unwrap().take();
+        match (result, failure) {
+            (Ok(_) | Err(SplitterError::Cancelled), Some(e)) => Err(e),
+            // Cancelled by the caller while waiting for the hooks
+            (Ok(_), None) if self.stop.is_cancelled() => Err(SplitterError::Cancelled),
+            (result, _) => result,
+        }
+    }
+}
+
+// Hooks left behind by an operation that returned early are stopped.
+impl Drop for Hooks {
+    fn drop(&mut self) {
+        self.stop.cancel();
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+// Run the hook for one chunk until it succeeds or has no runs left.
+fn run(
+    hook: &ChunkHook,
+    phase: Phase,
+    index: usize,
+    total: usize,
+    path: &Path,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let mut backoff = Duration::from_secs(1);
+    let mut attempt = 0;
+    loop {
+        let (status, output) = run_once(hook, phase, index, total, path, cancel).at(path)?;
+        if status.success() {
+            return Ok(());
+        }
+        if attempt == hook.retries {
+            let mut message = format!("{} command failed ({})", phase.name(), status);
+            if !output.is_empty() {
+                message.push_str(", after printing:");
+                for line in &output {
+                    message.push_str("\n  ");
+                    message.push_str(line);
+                }
+            }
+            return Err(io::Error::other(message)).at(path);
+        }
+        attempt += 1;
+        warn!(
+            "{}: {} command failed ({}); running it again in {} s ({} of {})",
+            path.display(),
+            phase.name(),
+            status,
+            backoff.as_secs(),
+            attempt,
+            hook.retries
+        );
+        pause(backoff, cancel).at(path)?;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+// Run the hook for one chunk, with what it printed on stdout and stderr, in the order
+// it arrived, and killing it when `cancel` is set.
+fn run_once(
+    hook: &ChunkHook,
+    phase: Phase,
+    index: usize,
+    total: usize,
+    path: &Path,
+    cancel: &CancelToken,
+) -> io::Result<(ExitStatus, Vec<String>)> {
+    cancel.check_io()?;
+    let mut command = command(hook, index, total, path);
+    command
+        .env("FSR_HOOK", phase.name())
+        .env("FSR_CHUNK_PATH", path)
+        .env("FSR_CHUNK_NAME", path.file_name().unwrap_or_default())
+        .env("FSR_CHUNK_INDEX", index.to_string())
+        .env("FSR_CHUNK_TOTAL", total.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    debug!(
+        "running the {} command for {}",
+        phase.name(),
+        path.display()
+    );
+    let mut child = command.spawn().map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "cannot run the {} command {}: {}",
+                phase.name(),
+                hook.command[0],
+                e
+            ),
+        )
+    })?;
+    let output = Arc::new(Mutex::new(VecDeque::new()));
+    let readers = [
+        child.stdout.take().map(|pipe| collect(pipe, &output)),
+        child.stderr.take().map(|pipe| collect(pipe, &output)),
+    ];
+    // What a killed hook started may hold on to its pipes, so its output isn't waited for
+    let status = wait(&mut child, cancel)?;
+    for reader in readers.into_iter().flatten() {
+        let _ = reader.join();
+    }
+    let output = mem::take(&mut *output.lock().unwrap());
+    Ok((status, Vec::from(output)))
+}
+
+// The program with its arguments filled in, or the shell with the command.
+fn command(hook: &ChunkHook, index: usize, total: usize, path: &Path) -> Command {
+    if hook.shell {
+        let (shell, flag) = if cfg!(windows) {
+            ("cmd", "/C")
+        } else {
+            ("sh", "-c")
+        };
+        let mut command = Command::new(shell);
+        command.arg(flag).arg(&hook.command[0]);
+        return command;
+    }
+    let mut words = hook
+        .command
+        .iter()
+        .map(|word| substitute(word, index, total, path));
+    let mut command = Command::new(words.next().unwrap_or_default());
+    command.args(words);
+    command
+}
+
+// `word` with the placeholders replaced. Any other brace is left as it is.
+fn substitute(word: &str, index: usize, total: usize, path: &Path) -> OsString {
+    let mut filled = OsString::new();
+    let mut rest = word;
+    while let Some(start) = rest.find('{') {
+        filled.push(&rest[..start]);
+        let tail = &rest[start..];
+        if let Some(after) = tail.strip_prefix("{path}") {
+            filled.push(path);
+            rest = after;
+        } else if let Some(after) = tail.strip_prefix("{name}") {
+            filled.push(path.file_name().unwrap_or_default());
+            rest = after;
+        } else if let Some(after) = tail.strip_prefix("{index}") {
+            filled.push(index.to_string());
+            rest = after;
+        } else if let Some(after) = tail.strip_prefix("{total}") {
+            filled.push(total.to_string());
+            rest = after;
+        } else {
+            filled.push("{");
+            rest = &tail[1..];
+        }
+    }
+    filled.push(rest);
+    filled
+}
+
+// Keep the last lines read from `pipe` in `lines`, logging them all.
+fn collect(
+    pipe: impl Read + Send + 'static,
+    lines: &Arc<Mutex<VecDeque<String>>>,
+) -> JoinHandle<()> {
+    let lines = Arc::clone(lines);
+    thread::spawn(move || {
+        let mut pipe = BufReader::new(pipe);
+        let mut line = Vec::new();
+        while matches!(pipe.read_until(b'\n', &mut line), Ok(1..)) {
+            let text = String::from_utf8_lossy(&line).trim_end().to_string();
+            line.clear();
+            debug!("hook: {}", text);
+            let mut lines = lines.lock().unwrap();
+            if lines.len() == OUTPUT_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(text);
+        }
+    })
+}
+
+fn wait(child: &mut Child, cancel: &CancelToken) -> io::Result<ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if cancel.is_cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::other(SplitterError::Cancelled));
+        }
+        thread::sleep(POLL);
+    }
+}
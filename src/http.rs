@@ -0,0 +1,379 @@
+// The HTTP client `fetch` and the S3 store go through: ureq, over TLS with rustls for
+// https URLs in builds with the tls feature, with this module's own URLs, credentials
+// and errors around it. GET with an optional byte range and credentials, following
+// redirects here rather than in ureq so credentials never follow one to another host,
+// or any request with a body for `send`. Connections are kept and reused, and a server
+// that goes quiet for `READ_TIMEOUT` in the middle of a response is given up on.
+
+use std::error::Error as _;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::armor;
+
+const MAX_REDIRECTS: usize = 8;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+// How long the server may go quiet mid-response before the request is given up on
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Credentials sent with every request to the host the URL names; a redirect elsewhere
+// goes without them.
+#[derive(Clone, Debug)]
+pub enum Auth {
+    Bearer(String),
+    // `user:password`
+    Basic(String),
+}
+
+impl Auth {
+    fn header(&self) -> String {
+        match self {
+            Auth::Bearer(token) => format!("Bearer {}", token),
+            Auth::Basic(credentials) => {
+                let mut encoded = Vec::new();
+                armor::encode(credentials.as_bytes(), &mut encoded);
+                format!("Basic {}", String::from_utf8_lossy(&encoded))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Url {
+    secure: bool,
+    host: String,
+    port: u16,
+    // From the leading slash, with any query
+    path: String,
+}
+
+impl Url {
+    pub fn parse(text: &str) -> io::Result<Url> {
+        let Some((scheme, rest)) = text.split_once("://") else {
+            return Err(invalid_url("not a URL"));
+        };
+        let secure = match scheme.to_ascii_lowercase().as_str() {
+            "http" => false,
+            "https" if cfg!(feature = "tls") => true,
+            "https" => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "https needs a build with the tls feature",
+                ));
+            }
+            _ => return Err(invalid_url("only http and https URLs are supported")),
+        };
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(at) => (&rest[..at], &rest[at..]),
+            None => (rest, "/"),
+        };
+        // Credentials in the URL itself aren't taken; they would be logged with it
+        if authority.contains('@') {
+            return Err(invalid_url(
+                "give credentials with --user or --bearer-token",
+            ));
+        }
+        let default_port = match secure {
+            true => 443,
+            false => 80,
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                let port = port.parse().map_err(|_| invalid_url("bad port"))?;
+                (host, port)
+            }
+            _ => (authority, default_port),
+        };
+        if host.is_empty() {
+            return Err(invalid_url("no host"));
+        }
+        let path = match path.starts_with('/') {
+            true => path.to_string(),
+            false => format!("/{}", path),
+        };
+        Ok(Url {
+            secure,
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+
+    // The file `name` next to this URL's path, taken as a directory.
+    pub fn join(&self, name: &str) -> Url {
+        let directory = self.path.split('?').next().unwrap_or_default();
+        let directory = directory.trim_end_matches('/');
+        Url {
+            path: format!("{}/{}", directory, encode(name)),
+            ..self.clone()
+        }
+    }
+
+    // Where a redirect's Location points, which may be relative to this URL.
+    fn resolve(&self, location: &str) -> io::Result<Url> {
+        if location.contains("://") {
+            return Url::parse(location);
+        }
+        if let Some(rest) = location.strip_prefix("//") {
+            return Url::parse(&format!("{}://{}", self.scheme(), rest));
+        }
+        let path = match location.starts_with('/') {
+            true => location.to_string(),
+            false => {
+                let directory = self.path.split('?').next().unwrap_or_default();
+                let directory = &directory[..directory.rfind('/').map_or(0, |at| at + 1)];
+                format!("{}{}", directory, location)
+            }
+        };
+        Ok(Url {
+            path,
+            ..self.clone()
+        })
+    }
+
+    // The same server with another path, which may carry a query.
+    pub fn with_path(&self, path: String) -> Url {
+        Url {
+            path,
+            ..self.clone()
+        }
+    }
+
+    // The host, with the port unless it is the scheme's own, as the Host header gives
+    // it.
+    pub fn authority(&self) -> String {
+        match (self.secure, self.port) {
+            (false, 80) | (true, 443) => self.host.clone(),
+            (_, port) => format!("{}:{}", self.host, port),
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // Whether requests to it go over TLS.
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    // Whether every address the host has is on this machine or a private network, as a
+    // MinIO beside it or in a container is.
+    pub fn is_local(&self) -> bool {
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        let Ok(addresses) = (host, self.port).to_socket_addrs() else {
+            return false;
+        };
+        let mut any = false;
+        for address in addresses {
+            any = true;
+            let local = match address.ip() {
+                IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+                // Unique local addresses are fc00::/7, link-local ones fe80::/10
+                IpAddr::V6(ip) => {
+                    ip.is_loopback()
+                        || (ip.segments()[0] & 0xfe00) == 0xfc00
+                        || (ip.segments()[0] & 0xffc0) == 0xfe80
+                        || ip
+                            .to_ipv4_mapped()
+                            .is_some_and(|ip| ip.is_loopback() || ip.is_private())
+                }
+            };
+            if !local {
+                return false;
+            }
+        }
+        any
+    }
+
+    fn scheme(&self) -> &'static str {
+        match self.secure {
+            true => "https",
+            false => "http",
+        }
+    }
+
+    fn same_host(&self, other: &Url) -> bool {
+        self.secure == other.secure
+            && self.host.eq_ignore_ascii_case(&other.host)
+            && self.port == other.port
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}://{}{}", self.scheme(), self.authority(), self.path)
+    }
+}
+
+// Everything in a file name but unreserved characters percent-encoded.
+pub(crate) fn encode(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for &b in name.as_bytes() {
+        match b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            true => encoded.push(char::from(b)),
+            false => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+pub(crate) struct Response {
+    pub status: u16,
+    headers: Vec<(String, String)>,
+    body: Box<dyn Read + Send + Sync>,
+}
+
+impl Response {
+    fn new(response: ureq::Response) -> Response {
+        let headers = response
+            .headers_names()
+            .into_iter()
+            .filter_map(|name| {
+                let value = response.header(&name)?.to_string();
+                Some((name, value))
+            })
+            .collect();
+        Response {
+            status: response.status(),
+            headers,
+            body: response.into_reader(),
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Where a 206 response's part starts, from its Content-Range.
+    pub fn range_start(&self) -> Option<u64> {
+        let range = self
+            .header("Content-Range")?
+            .trim()
+            .strip_prefix("bytes ")?;
+        range.split('-').next()?.trim().parse().ok()
+    }
+}
+
+impl Read for Response {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body.read(buf)
+    }
+}
+
+// GET `url`, from byte `from` on when that isn't 0, following redirects; `auth` goes
+// only to the host `url` names. Whatever the status, the response is returned as it
+// came, for the caller to judge, except that a redirect without a Location fails.
+pub(crate) fn get(url: &Url, auth: Option<&Auth>, from: u64) -> io::Result<Response> {
+    let mut target = url.clone();
+    for _ in 0..=MAX_REDIRECTS {
+        let auth = auth.filter(|_| target.same_host(url));
+        let response = request(&target, auth, from)?;
+        if !matches!(response.status, 301 | 302 | 303 | 307 | 308) {
+            return Ok(response);
+        }
+        let Some(location) = response.header("Location") else {
+            return Err(invalid_response("a redirect without a Location"));
+        };
+        target = target.resolve(location.trim())?;
+        log::debug!("redirected to {}", target);
+    }
+    Err(invalid_response("too many redirects"))
+}
+
+fn request(url: &Url, auth: Option<&Auth>, from: u64) -> io::Result<Response> {
+    let mut headers = Vec::new();
+    if from > 0 {
+        headers.push(("Range".to_string(), format!("bytes={}-", from)));
+    }
+    if let Some(auth) = auth {
+        headers.push(("Authorization".to_string(), auth.header()));
+    }
+    send("GET", url, &headers, &[])
+}
+
+// One request, `method` on `url` with `headers` and `body`, without following redirects.
+// Host, User-Agent and Accept-Encoding are always sent, and Content-Length for anything
+// with a body.
+pub(crate) fn send(
+    method: &str,
+    url: &Url,
+    headers: &[(String, String)],
+    body: &[u8],
+) -> io::Result<Response> {
+    let mut request = agent()
+        .request(method, &url.to_string())
+        .set("Accept-Encoding", "identity");
+    for (name, value) in headers {
+        request = request.set(name, value);
+    }
+    let sent = match (method, body.is_empty()) {
+        ("GET" | "HEAD" | "DELETE", true) => request.call(),
+        _ => request.send_bytes(body),
+    };
+    match sent {
+        Ok(response) => Ok(Response::new(response)),
+        // Whatever the status, the caller judges it
+        Err(ureq::Error::Status(_, response)) => Ok(Response::new(response)),
+        Err(ureq::Error::Transport(transport)) => Err(transport_error(transport)),
+    }
+}
+
+// The one agent every request goes through, so connections are kept between them.
+fn agent() -> &'static ureq::Agent {
+    static AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+    AGENT.get_or_init(|| {
+        ureq::AgentBuilder::new()
+            .timeout_connect(CONNECT_TIMEOUT)
+            .timeout_read(READ_TIMEOUT)
+            .timeout_write(READ_TIMEOUT)
+            .redirects(0)
+            .user_agent(concat!(
+                "reconstruct_large_file/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .build()
+    })
+}
+
+// A failure to get a response at all, as the kind of I/O error `fetch::retryable` goes
+// by: one that went wrong on the connection keeps the error it had.
+fn transport_error(transport: ureq::Transport) -> io::Error {
+    let kind = match transport.kind() {
+        ureq::ErrorKind::InvalidUrl | ureq::ErrorKind::UnknownScheme => io::ErrorKind::InvalidInput,
+        ureq::ErrorKind::BadStatus | ureq::ErrorKind::BadHeader => io::ErrorKind::InvalidData,
+        ureq::ErrorKind::ConnectionFailed => io::ErrorKind::ConnectionRefused,
+        ureq::ErrorKind::Io => transport
+            .source()
+            .and_then(|source| source.downcast_ref::<io::Error>())
+            .map_or(io::ErrorKind::ConnectionAborted, io::Error::kind),
+        // Lookups that fail may well work next time
+        _ => io::ErrorKind::ConnectionAborted,
+    };
+    // Its Display repeats the URL, which the caller's path context already gives
+    let message = match (transport.message(), transport.source()) {
+        (Some(message), Some(source)) => format!("{}: {}", message, source),
+        (Some(message), None) => message.to_string(),
+        (None, Some(source)) => source.to_string(),
+        (None, None) => transport.kind().to_string(),
+    };
+    io::Error::new(kind, message)
+}
+
+fn invalid_url(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, reason.to_string())
+}
+
+fn invalid_response(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
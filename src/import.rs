@@ -0,0 +1,537 @@
+// Taking over the pieces another tool split a file into, which come without an
+// info.json: `FILE.001`, `FILE.002`, … from HJSplit or 7-Zip (`FILE.7z.001` for an
+// archive in volumes), `xaa`, `xab`, … or `FILE.partaa`, … from GNU split, and `x00`,
+// `x01`, … from `split -d`. Pieces are found by what their names end in, digits or
+// split's letter suffixes (see `Compat`), and grouped by what comes before, so one
+// directory may hold several sets. Numbers are taken by value, counting from 0 when
+// there is a piece 0 and from 1 otherwise. Whatever leaves the order in doubt is noted
+// rather than settled here, for the caller to put to the user: numbers padded to
+// different widths, two pieces with the same number, gaps in the numbering, and pieces
+// before the last that differ in size, which no splitter makes. The set then either
+// becomes one of ours, with an info.json listing the pieces in order under their own
+// names as a set with random names would, or is joined straight away. Checksum files
+// left with the pieces (see `sums`) are used either way: every piece they list is
+// checked against them first, and their SHA-256 hashes go into the info.json.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::compat;
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::{ProgressEvent, Report};
+use crate::is_stream;
+use crate::manifest::{
+    ChunkEntry, Compression, HashAlgorithm, MANIFEST_NAME, MANIFEST_VERSION, Manifest, hash_file,
+};
+use crate::reconstruct::{self, Copying, ReconstructReport};
+use crate::sums::{self, SumAlgorithm, SumEntry, Sums};
+
+// Missing pieces named in a `Doubt::Gaps`; the rest are only counted
+const GAPS_NAMED: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForeignNaming {
+    // Numbers counting from `first`, 1 for HJSplit and 7-Zip, 0 for `split -d`
+    Numbered { first: u64 },
+    // `split`'s letters: `aa` to `yz`, then `zaaa` and so on
+    Lettered,
+}
+
+// Pieces another tool split a file into, as `detect_foreign` found them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ForeignSet {
+    pub directory: PathBuf,
+    // What the names have in common before their numbers or letters, such as `FILE.`
+    pub prefix: String,
+    pub naming: ForeignNaming,
+    // The file the pieces were split from, when their names give it away
+    pub original_filename: Option<String>,
+    // In the order they go together
+    pub pieces: Vec<ForeignPiece>,
+    // Empty when the order is certain
+    pub doubts: Vec<Doubt>,
+    // None without checksum files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksums: Option<ForeignChecksums>,
+}
+
+// The checksum files found with a set.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ForeignChecksums {
+    pub files: Vec<String>,
+    // Pieces they don't list, which go unchecked
+    pub unlisted: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ForeignPiece {
+    pub name: String,
+    pub size: u64,
+}
+
+// Why the order of a `ForeignSet` may not be the one the pieces were split in.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "doubt", rename_all = "snake_case")]
+pub enum Doubt {
+    // Numbers padded to different widths, like `FILE.01` next to `FILE.002`
+    MixedPadding { widths: Vec<usize> },
+    // Pieces with the same number, put in order by name
+    SameNumber { names: Vec<String> },
+    // Numbers with no piece, the first few of them by the name the piece would have
+    Gaps { count: u64, names: Vec<String> },
+    // Pieces before the last that differ in size from the first, or a last one larger
+    UnevenSizes { names: Vec<String> },
+    // Pieces the checksum files list that aren't there
+    ListedMissing { names: Vec<String> },
+}
+
+impl ForeignSet {
+    pub fn total_size(&self) -> u64 {
+        self.pieces.iter().map(|piece| piece.size).sum()
+    }
+}
+
+// Outcome of `import`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub directory: PathBuf,
+    pub original_filename: String,
+    pub chunks: usize,
+    pub total_size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<HashAlgorithm>,
+}
+
+// The sets of pieces in `directory`, the one with the most pieces first. It takes at
+// least two files named alike to make a set. A directory that already has an info.json
+// is a set of ours and is refused.
+pub fn detect_foreign(directory: &Path) -> Result<Vec<ForeignSet>> {
+    if directory.join(MANIFEST_NAME).exists() {
+        return Err(SplitterError::InvalidOption {
+            field: "directory",
+            reason: "already has an info.json, so is a chunk set already",
+        });
+    }
+    let sums = sums::load(directory)?;
+    let mut files = BTreeMap::new();
+    for entry in fs::read_dir(directory).at(directory)? {
+        let entry = entry.at(directory)?;
+        let path = entry.path();
+        if !entry.file_type().at(&path)?.is_file() {
+            continue;
+        }
+        if let Ok(name) = entry.file_name().into_string() {
+            let size = entry.metadata().at(&path)?.len();
+            files.insert(name, size);
+        }
+    }
+
+    // Each way of reading each name as a piece, by the prefix it would have. A name
+    // like `xzaaa` reads as `xza` + `aa` and as `x` + `zaaa`; its neighbours decide.
+    let mut readings: BTreeMap<(String, bool), Vec<(u64, &str)>> = BTreeMap::new();
+    for name in files.keys() {
+        for (prefix, lettered, number) in read_name(name) {
+            readings
+                .entry((prefix.to_string(), lettered))
+                .or_default()
+                .push((number, name));
+        }
+    }
+    let mut groups: Vec<_> = readings
+        .into_iter()
+        .filter(|(_, members)| members.len() >= 2)
+        .collect();
+    groups.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then_with(|| a.0.cmp(&b.0)));
+
+    // A name goes to the largest set that can have it
+    let mut taken = BTreeSet::new();
+    let mut sets = Vec::new();
+    for ((prefix, lettered), members) in groups {
+        let members: Vec<(u64, &str)> = members
+            .into_iter()
+            .filter(|(_, name)| !taken.contains(name))
+            .collect();
+        if members.len() < 2 {
+            continue;
+        }
+        taken.extend(members.iter().map(|&(_, name)| name));
+        debug!(
+            "{} pieces named {}… in {}",
+            members.len(),
+            prefix,
+            directory.display()
+        );
+        let mut set = foreign_set(directory, prefix, lettered, members, &files);
+        if let Some(sums) = &sums {
+            add_checksums(&mut set, lettered, sums);
+        }
+        sets.push(set);
+    }
+    Ok(sets)
+}
+
+// Write an info.json into the set's directory listing its pieces in order, under the
+// name `original_filename`, so it can be verified, reconstructed and so on like any
+// other set. With `hash` every piece is read through to record its hash. The pieces
+// themselves are left as they are.
+pub fn import(
+    set: &ForeignSet,
+    original_filename: &str,
+    hash: Option<HashAlgorithm>,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<ImportReport> {
+    if original_filename.is_empty() || original_filename.contains(['/', '\\']) {
+        return Err(SplitterError::InvalidOption {
+            field: "original_filename",
+            reason: "must be a file name, not a path",
+        });
+    }
+    let directory = set.directory.as_path();
+    if directory.join(MANIFEST_NAME).exists() {
+        return Err(SplitterError::InvalidOption {
+            field: "directory",
+            reason: "already has an info.json, so is a chunk set already",
+        });
+    }
+    info!(
+        "importing the {} pieces of {} in {}",
+        set.pieces.len(),
+        original_filename,
+        directory.display()
+    );
+    let sums = sums::load(directory)?;
+    let mut chunks = Vec::with_capacity(set.pieces.len());
+    for (index, piece) in set.pieces.iter().enumerate() {
+        cancel.check()?;
+        let path = directory.join(&piece.name);
+        if fs::metadata(&path).at(&path)?.len() != piece.size {
+            return Err(SplitterError::ChangedSize { path });
+        }
+        progress(ProgressEvent::ChunkStarted {
+            index,
+            size: piece.size,
+        });
+        let listed = sums.as_ref().and_then(|sums| sums.entries.get(&piece.name));
+        let mut hashed = match listed {
+            Some(entry) => check_piece(&path, entry, progress, cancel)?,
+            None => None,
+        };
+        if hashed.is_none()
+            && let Some(algorithm) = hash
+        {
+            hashed = Some(hash_file(
+                &path,
+                algorithm,
+                &mut |delta| progress(ProgressEvent::BytesCopied { delta }),
+                cancel,
+            )?);
+        }
+        progress(ProgressEvent::ChunkFinished {
+            index,
+            hash: hashed.clone(),
+        });
+        chunks.push(ChunkEntry {
+            name: piece.name.clone(),
+            size: piece.size,
+            hash: hashed,
+            stored_hash: None,
+            compression: None,
+            same_as: None,
+            shared: None,
+            mtime: None,
+        });
+    }
+    // SHA-256 hashes from the checksum files are recorded even when not asked for
+    let hash = hash.or_else(|| {
+        chunks
+            .iter()
+            .any(|chunk| chunk.hash.is_some())
+            .then_some(HashAlgorithm::Sha256)
+    });
+    let even = !set
+        .doubts
+        .iter()
+        .any(|doubt| matches!(doubt, Doubt::UnevenSizes { .. }));
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        original_filename: original_filename.to_string(),
+        name_form: None,
+        link_name: None,
+        range: None,
+        xattrs: BTreeMap::new(),
+        owner: None,
+        created: None,
+        chunk_size: set
+            .pieces
+            .first()
+            .filter(|_| even && set.pieces.len() > 1)
+            .map(|piece| piece.size),
+        hash,
+        compression: Compression::None,
+        encryption: None,
+        metadata: None,
+        random_names: true,
+        compat: None,
+        shards: None,
+        parity: None,
+        span: None,
+        chunks,
+    };
+    manifest.save(directory)?;
+
+    let report = ImportReport {
+        directory: directory.to_path_buf(),
+        original_filename: original_filename.to_string(),
+        chunks: set.pieces.len(),
+        total_size: set.total_size(),
+        hash,
+    };
+    progress(ProgressEvent::Completed {
+        report: Report::Import(report.clone()),
+    });
+    Ok(report)
+}
+
+// Join the pieces of `set` into `output_path` in their order, as `reconstruct_chunks`
+// would, gaps and all; nothing is written into the set's directory but the output. The
+// pieces the checksum files list are checked first, and the output too when they list
+// it, as they often list the original along with its pieces; an output that doesn't
+// match is removed again. Until complete the output is a temporary file in `temp_dir`,
+// or beside it when None; see `scratch`.
+pub fn reconstruct_foreign(
+    set: &ForeignSet,
+    output_path: &Path,
+    temp_dir: Option<&Path>,
+    threads: usize,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<ReconstructReport> {
+    let sums = sums::load(&set.directory)?;
+    if let Some(sums) = &sums {
+        for piece in &set.pieces {
+            if let Some(entry) = sums.entries.get(&piece.name) {
+                check_piece(&set.directory.join(&piece.name), entry, progress, cancel)?;
+            }
+        }
+    }
+    let chunk_files: Vec<PathBuf> = set
+        .pieces
+        .iter()
+        .map(|piece| set.directory.join(&piece.name))
+        .collect();
+    let report = reconstruct::assemble(
+        &chunk_files,
+        output_path,
+        &Copying {
+            temp_dir: temp_dir.map(Path::to_path_buf),
+            ..Copying::new(threads)
+        },
+        progress,
+        cancel,
+    )?;
+    // What went into a pipe can't be read back
+    let whole = output_path
+        .file_name()
+        .filter(|_| !is_stream(output_path))
+        .and_then(|name| sums.as_ref()?.entries.get(name.to_str()?));
+    if let Some(entry) = whole
+        && let Err(e) = check_piece(output_path, entry, progress, cancel)
+    {
+        let _ = fs::remove_file(output_path);
+        return Err(e);
+    }
+    progress(ProgressEvent::Completed {
+        report: Report::Reconstruct(report.clone()),
+    });
+    Ok(report)
+}
+
+// Check the piece at `path` against its checksum, an error unless it matches. Its
+// SHA-256 hash, when that is what the checksum is, comes back to be recorded.
+fn check_piece(
+    path: &Path,
+    entry: &SumEntry,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Option<String>> {
+    let mut copied = |delta| progress(ProgressEvent::BytesCopied { delta });
+    let (intact, hash) = match entry.algorithm {
+        SumAlgorithm::Sha256 => {
+            let hash = hash_file(path, HashAlgorithm::Sha256, &mut copied, cancel)?;
+            (hash == entry.hash, Some(hash))
+        }
+        _ => (sums::check(path, entry, &mut copied, cancel)?, None),
+    };
+    if !intact {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("does not match its checksum in {}", entry.source),
+        ))
+        .at(path);
+    }
+    Ok(hash)
+}
+
+// Note which pieces the checksum files leave out, and which they list that are gone.
+fn add_checksums(set: &mut ForeignSet, lettered: bool, sums: &Sums) {
+    let unlisted = set
+        .pieces
+        .iter()
+        .filter(|piece| !sums.entries.contains_key(&piece.name))
+        .map(|piece| piece.name.clone())
+        .collect();
+    let missing: Vec<String> = sums
+        .entries
+        .keys()
+        .filter(|name| !set.pieces.iter().any(|piece| &piece.name == *name))
+        .filter(|name| {
+            read_name(name)
+                .iter()
+                .any(|&(prefix, l, _)| prefix == set.prefix && l == lettered)
+        })
+        .cloned()
+        .collect();
+    if !missing.is_empty() {
+        set.doubts.push(Doubt::ListedMissing { names: missing });
+    }
+    set.checksums = Some(ForeignChecksums {
+        files: sums.files.clone(),
+        unlisted,
+    });
+}
+
+// Every way `name` could be a piece: its prefix, whether it is lettered, and its number.
+// Numbers need two digits at least, as one is too likely to be something else.
+fn read_name(name: &str) -> Vec<(&str, bool, u64)> {
+    let mut readings = Vec::new();
+    let digits = name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    if digits >= 2
+        && digits < name.len()
+        && let Ok(number) = name[name.len() - digits..].parse()
+    {
+        readings.push((&name[..name.len() - digits], false, number));
+    }
+    let letters = name.len()
+        - name
+            .trim_end_matches(|c: char| c.is_ascii_lowercase())
+            .len();
+    for len in (2..=letters.min(name.len() - 1)).step_by(2) {
+        let (prefix, suffix) = name.split_at(name.len() - len);
+        if let Some(index) = compat::split_index(suffix) {
+            readings.push((prefix, true, index));
+        }
+    }
+    readings
+}
+
+fn foreign_set(
+    directory: &Path,
+    prefix: String,
+    lettered: bool,
+    mut members: Vec<(u64, &str)>,
+    files: &BTreeMap<String, u64>,
+) -> ForeignSet {
+    members.sort();
+    let first = match lettered || members[0].0 == 0 {
+        true => 0,
+        false => 1,
+    };
+    let naming = match lettered {
+        true => ForeignNaming::Lettered,
+        false => ForeignNaming::Numbered { first },
+    };
+    let pieces: Vec<ForeignPiece> = members
+        .iter()
+        .map(|&(_, name)| ForeignPiece {
+            name: name.to_string(),
+            size: files[name],
+        })
+        .collect();
+
+    let mut doubts = Vec::new();
+    let widths: BTreeSet<usize> = members
+        .iter()
+        .map(|(_, name)| name.len() - prefix.len())
+        .collect();
+    if !lettered {
+        // Widths that come from padding, rather than from numbers growing a digit
+        let padded: BTreeSet<usize> = members
+            .iter()
+            .map(|(_, name)| &name[prefix.len()..])
+            .filter(|digits| digits.starts_with('0'))
+            .map(str::len)
+            .collect();
+        let narrowest = widths.first().copied().unwrap_or(0);
+        if padded.len() > 1 || padded.first().is_some_and(|&width| narrowest < width) {
+            doubts.push(Doubt::MixedPadding {
+                widths: widths.iter().copied().collect(),
+            });
+        }
+    }
+    let mut named: BTreeMap<u64, usize> = BTreeMap::new();
+    for &(number, _) in &members {
+        *named.entry(number).or_default() += 1;
+    }
+    let same: Vec<String> = members
+        .iter()
+        .filter(|(number, _)| named[number] > 1)
+        .map(|(_, name)| name.to_string())
+        .collect();
+    if !same.is_empty() {
+        doubts.push(Doubt::SameNumber { names: same });
+    }
+    let last = members[members.len() - 1].0;
+    let count = (last - first + 1) - named.len() as u64;
+    if count > 0 {
+        let width = widths.first().copied().unwrap_or(0);
+        let names = (first..last)
+            .filter(|number| !named.contains_key(number))
+            .take(GAPS_NAMED)
+            .map(|number| match lettered {
+                true => format!("{}{}", prefix, compat::split_suffix(number)),
+                false => format!("{}{:0width$}", prefix, number, width = width),
+            })
+            .collect();
+        doubts.push(Doubt::Gaps { count, names });
+    }
+    let size = pieces[0].size;
+    let (last_piece, before) = pieces.split_last().unwrap();
+    let mut uneven: Vec<String> = before
+        .iter()
+        .filter(|piece| piece.size != size)
+        .map(|piece| piece.name.clone())
+        .collect();
+    if last_piece.size > size {
+        uneven.push(last_piece.name.clone());
+    }
+    if !uneven.is_empty() {
+        doubts.push(Doubt::UnevenSizes { names: uneven });
+    }
+
+    ForeignSet {
+        directory: directory.to_path_buf(),
+        original_filename: original_name(&prefix),
+        prefix,
+        naming,
+        pieces,
+        doubts,
+        checksums: None,
+    }
+}
+
+// `FILE` from the prefix `FILE.` or `FILE.part`; a bare prefix such as split's `x`
+// gives nothing away.
+fn original_name(prefix: &str) -> Option<String> {
+    let name = prefix
+        .strip_suffix(".part")
+        .or_else(|| prefix.strip_suffix('.'))?;
+    (!name.is_empty()).then(|| name.to_string())
+}
@@ -0,0 +1,71 @@
+// Ctrl+C while a split or reconstruction runs cancels it through its `CancelToken`,
+// so the operation stops between buffers and cleans up after itself. Anywhere else,
+// and for a second Ctrl+C that the operation hasn't reacted to yet, the program ends
+// the way it always has.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+
+use reconstruct_large_file::CancelToken;
+
+static CANCEL: LazyLock<Arc<AtomicBool>> = LazyLock::new(Arc::default);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+// Exit status after an interrupt, what a shell reports for SIGINT.
+pub const EXIT_CODE: i32 = 130;
+
+pub fn install() {
+    LazyLock::force(&CANCEL);
+    #[cfg(unix)]
+    // SAFETY: the handler only touches atomics and async-signal-safe calls.
+    unsafe {
+        libc::signal(libc::SIGINT, on_sigint as *const () as libc::sighandler_t);
+    }
+    #[cfg(windows)]
+    // SAFETY: the handler only touches atomics.
+    unsafe {
+        windows_sys::Win32::System::Console::SetConsoleCtrlHandler(Some(on_ctrl), 1);
+    }
+}
+
+// Marks an operation as running until dropped. `token` is what to hand it.
+pub struct Operation {
+    pub token: CancelToken,
+}
+
+pub fn start() -> Operation {
+    CANCEL.store(false, Ordering::Relaxed);
+    RUNNING.store(true, Ordering::Relaxed);
+    Operation {
+        token: CancelToken::from(Arc::clone(&CANCEL)),
+    }
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        RUNNING.store(false, Ordering::Relaxed);
+    }
+}
+
+// True when the handler took the interrupt for the running operation.
+fn take() -> bool {
+    RUNNING.load(Ordering::Relaxed) && !CANCEL.swap(true, Ordering::Relaxed)
+}
+
+#[cfg(unix)]
+extern "C" fn on_sigint(_: libc::c_int) {
+    if !take() {
+        // SAFETY: both are async-signal-safe; the default action ends the process.
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+            libc::raise(libc::SIGINT);
+        }
+    }
+}
+
+// Returning false passes the event on to the default handler, which exits.
+#[cfg(windows)]
+unsafe extern "system" fn on_ctrl(kind: u32) -> windows_sys::core::BOOL {
+    use windows_sys::Win32::System::Console::{CTRL_BREAK_EVENT, CTRL_C_EVENT};
+    ((kind == CTRL_C_EVENT || kind == CTRL_BREAK_EVENT) && take()).into()
+}
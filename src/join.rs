@@ -0,0 +1,153 @@
+// `JOIN.sh` and `JOIN.bat`, written next to the chunks so that someone without this
+// tool can put the file back together with what their system already has: `cat` on
+// anything POSIX, checking the result with `sha256sum` or `shasum` when it has one,
+// and `copy /b` on Windows. Both name every chunk in order, so they work whatever the
+// chunks are called, and are run from the chunk directory wherever they are started.
+
+use std::fs;
+use std::path::Path;
+
+use crate::error::{PathContext, Result};
+use crate::manifest::Manifest;
+
+pub(crate) const SCRIPT_NAMES: [&str; 2] = ["JOIN.sh", "JOIN.bat"];
+
+// Length of the chunk names one `copy /b` takes, well short of cmd's 8191 characters
+// a line
+const BATCH_LINE: usize = 4000;
+
+pub(crate) fn is_script_name(name: &str) -> bool {
+    SCRIPT_NAMES.contains(&name)
+}
+
+// Whether `name` can go into `JOIN.bat`. cmd has no way to quote a `"`, and Windows
+// allows neither it nor control characters in file names, so such a set couldn't be
+// joined there anyway.
+pub(crate) fn batch_safe(name: &str) -> bool {
+    !name.chars().any(|c| c == '"' || c.is_control())
+}
+
+// Write both scripts into `directory` for the chunks of `manifest`, in order. `sha256`
+// is the whole file's, for `JOIN.sh` to check the result against.
+pub(crate) fn write(directory: &Path, manifest: &Manifest, sha256: &str) -> Result<()> {
+    let mut chunks: Vec<(usize, &str)> = manifest
+        .indexed()
+        .map(|(index, entry)| (index, entry.file()))
+        .collect();
+    chunks.sort_unstable();
+    let names: Vec<&str> = chunks.into_iter().map(|(_, name)| name).collect();
+    let output = manifest.output_name()?;
+    let output = output.as_str();
+
+    let path = directory.join(SCRIPT_NAMES[0]);
+    fs::write(&path, shell_script(&names, output, sha256)).at(&path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).at(&path)?;
+    }
+    let path = directory.join(SCRIPT_NAMES[1]);
+    fs::write(&path, batch_script(&names, output)).at(&path)
+}
+
+fn shell_script(names: &[&str], output: &str, sha256: &str) -> String {
+    let mut script = String::from("#!/bin/sh\n");
+    script += "# Puts the chunks in this directory back together into the original file.\n";
+    script += "set -e\ncd \"$(dirname \"$0\")\"\n";
+    script += &format!("output={}\n", shell_quote(output));
+    script += &format!("expected={}\n", sha256);
+    // The chunks are the script's arguments, which no limit on command lines applies to
+    // since `set` is built in
+    script += "set --";
+    for name in names {
+        script += &format!(" \\\n    {}", shell_quote(name));
+    }
+    script += "\n";
+    script += "for chunk do\n";
+    script += "    [ -f \"$chunk\" ] || { echo \"missing chunk: $chunk\" >&2; exit 1; }\n";
+    script += "done\n";
+    script += "echo \"joining into $output\"\n";
+    script += "for chunk do cat \"$chunk\"; done > \"$output\"\n";
+    script += &shell_check("a chunk is damaged");
+    script
+}
+
+// The end of a POSIX script that has written "$output": checking it against the SHA-256
+// in "$expected" with `sha256sum` or `shasum` when there is one, and failing with
+// `damaged` when it doesn't match.
+pub(crate) fn shell_check(damaged: &str) -> String {
+    let mut script = String::from("if command -v sha256sum >/dev/null 2>&1; then\n");
+    script += "    actual=$(sha256sum < \"$output\")\n";
+    script += "elif command -v shasum >/dev/null 2>&1; then\n";
+    script += "    actual=$(shasum -a 256 < \"$output\")\n";
+    script += "else\n";
+    script += "    echo \"done; there is no sha256sum to check the result with\"\n";
+    script += "    exit 0\n";
+    script += "fi\n";
+    script += "if [ \"${actual%% *}\" = \"$expected\" ]; then\n";
+    script += "    echo \"done; the SHA-256 matches\"\n";
+    script += "else\n";
+    script += &format!(
+        "    echo \"the SHA-256 of $output does not match: {}\" >&2\n",
+        damaged
+    );
+    script += "    exit 1\n";
+    script += "fi\n";
+    script
+}
+
+// In single quotes nothing is special but the quote itself, which is closed, escaped
+// and opened again.
+pub(crate) fn shell_quote(name: &str) -> String {
+    format!("'{}'", name.replace('\'', "'\\''"))
+}
+
+// The first group is copied into the output and each further one appended to it, as
+// `copy /b` does when the destination is also the first source. Lines end in CRLF.
+fn batch_script(names: &[&str], output: &str) -> String {
+    let output = batch_quote(output);
+    let mut lines = vec![
+        "@echo off".to_string(),
+        // So that names outside ASCII read as the UTF-8 they are written in
+        "chcp 65001 >nul".to_string(),
+        "rem Puts the chunks in this directory back together into the original file.".to_string(),
+        "setlocal DisableDelayedExpansion".to_string(),
+        "cd /d \"%~dp0\"".to_string(),
+    ];
+    for name in names {
+        lines.push(format!(
+            "if not exist {0} (echo missing chunk: {0}& pause& exit /b 1)",
+            batch_quote(name)
+        ));
+    }
+    lines.push(format!("echo joining into {}", output));
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut length = BATCH_LINE;
+    for name in names {
+        let name = batch_quote(name);
+        if length + name.len() > BATCH_LINE {
+            groups.push(match groups.is_empty() {
+                true => Vec::new(),
+                false => vec![output.clone()],
+            });
+            length = 0;
+        }
+        length += name.len();
+        groups.last_mut().unwrap().push(name);
+    }
+    for sources in groups {
+        lines.push(format!(
+            "copy /y /b {} {} >nul || (echo joining failed& pause& exit /b 1)",
+            sources.join(" + "),
+            output
+        ));
+    }
+    lines.push("echo done".to_string());
+    lines.push("pause".to_string());
+    lines.join("\r\n") + "\r\n"
+}
+
+// Quoted, everything but `%` is taken as it is; `batch_safe` has ruled out `"`.
+pub(crate) fn batch_quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('%', "%%"))
+}
@@ -0,0 +1,369 @@
+// The journal: for every split, reconstruction, rechunk, verify and repair once it is
+// over, and every file a reconstruction moved to the trash or `undo-last` put back, one
+// line of JSON appended to journal.jsonl in the state directory saying what
+// was done to what, with which options, how much and how long it took and how it went,
+// for `history` to show. The file is only ever appended to, each record a whole line in
+// one write to a file opened for appending, so runs at the same time can't interleave
+// theirs. Nothing about the journal fails an operation: a record that can't be written
+// is warned about and dropped. `--no-journal`, or FILE_SPLITTER_NO_JOURNAL set to
+// anything but the empty string, turns it off.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use reconstruct_large_file::{
+    RechunkOptions, ReconstructOptions, ReconstructReport, RepairReport, SampleOptions,
+    SplitOptions, SplitReport, SplitterError, VerifyReport, is_s3_url, is_sftp_url,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::history::state_dir;
+use crate::logging::timestamp;
+use crate::trash;
+
+pub const DISABLE_VARIABLE: &str = "FILE_SPLITTER_NO_JOURNAL";
+const JOURNAL_NAME: &str = "journal.jsonl";
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+pub fn init(no_journal: bool) {
+    let disabled =
+        no_journal || env::var_os(DISABLE_VARIABLE).is_some_and(|value| !value.is_empty());
+    ENABLED.store(!disabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn path() -> Option<PathBuf> {
+    Some(state_dir()?.join(JOURNAL_NAME))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Succeeded,
+    // Failed, or for verify found the set damaged
+    Failed,
+    Interrupted,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Record {
+    // When the operation started, in UTC
+    pub timestamp: String,
+    pub operation: String,
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    // Everything else it was given, as the library's options have it
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub options: Value,
+    // Of the original file, split, reconstructed or checked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<u64>,
+    pub duration_secs: f64,
+    pub outcome: Outcome,
+    // What went wrong, when it didn't succeed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// An operation under way, to be recorded once it is over.
+pub struct Started {
+    timestamp: String,
+    started: Instant,
+}
+
+pub fn start() -> Started {
+    Started {
+        timestamp: timestamp(),
+        started: Instant::now(),
+    }
+}
+
+impl Started {
+    fn record(self, operation: &str, source: &Path, destination: Option<&Path>) -> Record {
+        let seconds = self.started.elapsed().as_secs_f64();
+        Record {
+            timestamp: self.timestamp,
+            operation: operation.to_string(),
+            source: absolute(source),
+            destination: destination.map(absolute),
+            options: Value::Null,
+            bytes: None,
+            chunks: None,
+            duration_secs: (seconds * 1000.0).round() / 1000.0,
+            outcome: Outcome::Succeeded,
+            error: None,
+        }
+    }
+}
+
+pub fn split(
+    started: Started,
+    options: &SplitOptions,
+    result: &Result<SplitReport, SplitterError>,
+) {
+    let mut record = started.record("split", &options.input, Some(&options.destination));
+    record.options = options_of(options, &["input", "destination"]);
+    match result {
+        Ok(report) => {
+            record.bytes = Some(report.total_size);
+            record.chunks = Some(report.chunks.len() as u64);
+        }
+        Err(e) => failed(&mut record, e),
+    }
+    finish(&record);
+}
+
+pub fn rechunk(
+    started: Started,
+    options: &RechunkOptions,
+    result: &Result<SplitReport, SplitterError>,
+) {
+    let mut record = started.record("rechunk", &options.source, Some(&options.destination));
+    record.options = options_of(options, &["source", "destination"]);
+    match result {
+        Ok(report) => {
+            record.bytes = Some(report.total_size);
+            record.chunks = Some(report.chunks.len() as u64);
+        }
+        Err(e) => failed(&mut record, e),
+    }
+    finish(&record);
+}
+
+pub fn reconstruct(
+    started: Started,
+    options: &ReconstructOptions,
+    result: &Result<ReconstructReport, SplitterError>,
+) {
+    let output = match result {
+        Ok(report) => Some(report.output.clone()),
+        Err(_) => options
+            .output
+            .as_ref()
+            .map(|output| options.directory.join(output)),
+    };
+    let mut record = started.record("reconstruct", &options.directory, output.as_deref());
+    record.options = options_of(options, &["directory", "output"]);
+    match result {
+        Ok(report) => {
+            record.bytes = Some(report.total_size);
+            record.chunks = Some(report.chunks as u64);
+        }
+        Err(e) => failed(&mut record, e),
+    }
+    finish(&record);
+}
+
+pub fn verify(started: Started, directory: &Path, result: &Result<VerifyReport, SplitterError>) {
+    let mut record = started.record("verify", directory, None);
+    match result {
+        Ok(report) => {
+            record.bytes = Some(report.health.total_size);
+            record.chunks = Some(report.health.chunks as u64);
+            if !report.is_ok() {
+                record.outcome = Outcome::Failed;
+                record.error = Some(problems(report));
+            }
+        }
+        Err(e) => failed(&mut record, e),
+    }
+    finish(&record);
+}
+
+// Recorded as "verify-sample", so a sample never counts as the set verified.
+pub fn verify_sample(
+    started: Started,
+    directory: &Path,
+    options: &SampleOptions,
+    result: &Result<VerifyReport, SplitterError>,
+) {
+    let mut record = started.record("verify-sample", directory, None);
+    record.options = options_of(options, &[]);
+    match result {
+        Ok(report) => {
+            if let Some(sample) = &report.sample {
+                record.bytes = Some(sample.bytes);
+                record.chunks = Some(sample.checked.len() as u64);
+            }
+            if !report.is_ok() {
+                record.outcome = Outcome::Failed;
+                record.error = Some(problems(report));
+            }
+        }
+        Err(e) => failed(&mut record, e),
+    }
+    finish(&record);
+}
+
+pub fn repair(
+    started: Started,
+    directory: &Path,
+    dry_run: bool,
+    result: &Result<RepairReport, SplitterError>,
+) {
+    let mut record = started.record("repair", directory, None);
+    record.options = serde_json::json!({ "dry_run": dry_run });
+    match result {
+        Ok(report) => {
+            let rebuilt = report.rebuilt.len() + report.par2.len() + report.parity.len();
+            record.chunks = Some(rebuilt as u64);
+        }
+        Err(e) => failed(&mut record, e),
+    }
+    finish(&record);
+}
+
+// A file a reconstruction replaced, moved from `original` to `trashed`; see `trash`.
+pub fn trash(original: &Path, trashed: &Path) {
+    let record = start().record("trash", original, Some(trashed));
+    append(&record);
+}
+
+// A file `undo-last` put back from the trash.
+pub fn restore(trashed: &Path, original: &Path) {
+    let record = start().record("restore", trashed, Some(original));
+    append(&record);
+}
+
+fn failed(record: &mut Record, error: &SplitterError) {
+    record.outcome = match error {
+        SplitterError::Cancelled => Outcome::Interrupted,
+        _ => Outcome::Failed,
+    };
+    record.error = Some(error.to_string());
+}
+
+// What verify found wrong, in a few words.
+fn problems(report: &VerifyReport) -> String {
+    let health = &report.health;
+    let mut problems = Vec::new();
+    if health.chunks == 0 {
+        problems.push("no chunk files found".to_string());
+    }
+    for (count, what) in [
+        (health.missing.len(), "missing"),
+        (health.uneven.len(), "unevenly sized"),
+        (health.unexpected.len(), "not in info.json"),
+        (report.mismatched.len(), "missing or damaged"),
+        (
+            report.checksum_missing.len(),
+            "listed in a checksum file but missing",
+        ),
+    ] {
+        if count > 0 {
+            problems.push(format!("{} {}", count, what));
+        }
+    }
+    problems.join(", ")
+}
+
+// `options` as JSON, without the fields the record has places of its own for.
+fn options_of(options: &impl Serialize, without: &[&str]) -> Value {
+    let mut value = serde_json::to_value(options).unwrap_or(Value::Null);
+    if let Value::Object(fields) = &mut value {
+        for field in without {
+            fields.remove(*field);
+        }
+    }
+    value
+}
+
+// Local paths made absolute, so the journal says which file was meant whatever
+// directory the run was in; URLs as they are.
+pub fn absolute(path: &Path) -> String {
+    if is_s3_url(path) || is_sftp_url(path) || trash::on_desktop(path) {
+        return path.to_string_lossy().into_owned();
+    }
+    let absolute = fs::canonicalize(path)
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf());
+    absolute.to_string_lossy().into_owned()
+}
+
+// The end of every operation, where `--notify` says so as well.
+fn finish(record: &Record) {
+    crate::notify::finished(record);
+    append(record);
+}
+
+fn append(record: &Record) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let Some(path) = path() else {
+        return;
+    };
+    if let Err(e) = write_line(&path, record) {
+        eprintln!(
+            "Warning: could not add to the journal {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+fn write_line(path: &Path, record: &Record) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut line = serde_json::to_string(record).map_err(io::Error::other)?;
+    line.push('\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())
+}
+
+// The latest verify in `records` of each directory, by the path the journal has it
+// under, as `absolute` gives it.
+pub fn last_verified(records: &[Record]) -> BTreeMap<&str, &Record> {
+    let mut latest = BTreeMap::new();
+    for record in records.iter().filter(|record| record.operation == "verify") {
+        latest.insert(record.source.as_str(), record);
+    }
+    latest
+}
+
+// The latest split or rechunk that wrote each directory and succeeded, by the path the
+// journal has it under.
+pub fn last_written(records: &[Record]) -> BTreeMap<&str, &Record> {
+    let mut latest = BTreeMap::new();
+    for record in records.iter().filter(|record| {
+        matches!(record.operation.as_str(), "split" | "rechunk")
+            && record.outcome == Outcome::Succeeded
+    }) {
+        if let Some(destination) = &record.destination {
+            latest.insert(destination.as_str(), record);
+        }
+    }
+    latest
+}
+
+// Every record in the journal, oldest first; none when there is no journal yet. Lines
+// that don't read as a record, such as one cut short when the disk filled up, are
+// passed over.
+pub fn read() -> io::Result<Vec<Record>> {
+    let Some(path) = path() else {
+        return Ok(Vec::new());
+    };
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(String::from_utf8_lossy(&data)
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
@@ -0,0 +1,1199 @@
+// Splitting large files into chunks and putting them back together, for embedding as
+// well as for the `reconstruct_large_file` binary, which is a thin UI over this. Nothing
+// in here prints or exits: failures come back as errors and progress is reported
+// through the callbacks as `ProgressEvent`s.
+
+mod archive;
+mod armor;
+mod assess;
+pub mod cache;
+mod cancel;
+mod changes;
+mod chunkset;
+mod compat;
+mod crypt;
+mod doctor;
+#[cfg(feature = "async")]
+mod engine;
+mod error;
+mod event;
+mod export;
+mod fastcopy;
+mod fetch;
+mod gf65536;
+mod gzip;
+mod heal;
+mod hook;
+mod http;
+mod import;
+mod join;
+pub mod lock;
+mod longpath;
+pub mod manifest;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod modes;
+mod natural;
+mod owner;
+mod pack;
+mod par2;
+mod parity;
+pub mod pipeline;
+mod reader;
+mod rechunk;
+mod reconstruct;
+mod rekey;
+mod remap;
+mod repair;
+pub mod retry;
+mod s3;
+mod sample;
+pub mod scratch;
+mod selfextract;
+#[cfg(feature = "sftp")]
+mod sftp;
+mod shred;
+pub mod size;
+mod span;
+mod split;
+mod stats;
+pub mod store;
+mod sums;
+pub mod symlinks;
+mod tar;
+mod timestamps;
+mod transfer;
+mod unicode;
+mod writer;
+mod xattrs;
+mod zip;
+mod zst;
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::error::PathContext;
+use crate::symlinks::SymlinkPolicy;
+
+pub use archive::{ArchiveStore, EntryReader, is_archive};
+pub use assess::{Par2Recoverability, Recoverability, StripeRecoverability};
+pub use cancel::CancelToken;
+pub use changes::{ChangeKind, FileChange};
+pub use chunkset::{ByteRange, ChunkInfo, ChunkSet, Coverage, containing_set};
+pub use compat::Compat;
+pub use crypt::{ChunkKey, Cipher, Encryption, KEY_LEN, Kdf, KdfAlgorithm};
+pub use doctor::{
+    DIAGNOSIS_VERSION, Diagnosis, Finding, FindingCode, MetadataState, Naming, Severity, Status,
+    diagnose,
+};
+pub use error::{Result, SplitterError};
+pub use event::{ProgressEvent, Report};
+pub use export::{
+    EXPORT_EXTENSION, EXPORT_FORMAT, EXPORT_VERSION, ExportReport, ExportedChunk, ExportedManifest,
+    Signature, export_manifest, verify_exported,
+};
+pub use fetch::{FetchOptions, FetchReport, fetch};
+pub use heal::{HealPlan, HealReport, HealedChunk, heal, plan_heal};
+pub use hook::ChunkHook;
+pub use http::Auth;
+pub use import::{
+    Doubt, ForeignChecksums, ForeignNaming, ForeignPiece, ForeignSet, ImportReport, detect_foreign,
+    import, reconstruct_foreign,
+};
+pub use longpath::{absolute_path, display_path, extended_path, parent_dir};
+pub use manifest::{
+    ChunkEntry, Compression, HashAlgorithm, InputRange, MANIFEST_NAME, MANIFEST_VERSION,
+    MAX_PARITY_SHARDS, Manifest, Owner, Parity, ParityEntry, ParityInfo, SEAL_FIELD, Seal,
+    SpanInfo, VolumeEntry,
+};
+pub use modes::MAX_MODE;
+pub use natural::natural_cmp;
+pub use pack::{PackReport, pack, pack_into, unpack};
+pub use par2::Par2Report;
+pub use reader::ChunkedReader;
+pub use rechunk::{RechunkOptions, RechunkPlan, plan_rechunk, rechunk};
+pub use reconstruct::{
+    ReconstructOptions, ReconstructPlan, ReconstructReport, check_recovery, plan_reconstruct,
+    reconstruct, reconstruct_chunks,
+};
+pub use rekey::{RekeyOptions, RekeyReport, rekey};
+pub use remap::{Remap, apply_remap, find_remap};
+pub use repair::{RepairReport, repair};
+pub use s3::{S3_SCHEME, S3Options, S3Store, is_s3_url};
+pub use sample::{SampleOptions, Sampled, verify_sample};
+pub use selfextract::{DEFAULT_SELF_EXTRACTING_MAX, Script, self_extracting, self_extracting_into};
+#[cfg(feature = "sftp")]
+pub use sftp::{SftpOptions, SftpStore};
+pub use span::{
+    DEFAULT_SPAN_MARGIN, PlannedVolume, Span, SpanPlan, free_space, plan_span, same_file_system,
+};
+pub use split::{
+    Container, DEFAULT_MAX_DIR_FILES, DEFAULT_MIN_CHUNK_SIZE, MirrorFailure, MirrorReport,
+    ShardDirs, SplitOptions, SplitOptionsBuilder, SplitReport, check_chunk_size, check_destination,
+    split_file,
+};
+pub use stats::{SetStats, SkippedSet, StatsReport, stats};
+pub use store::{
+    ChunkStore, InMemoryStore, LocalDirStore, reconstruct_from, split_into, split_reader,
+};
+pub use timestamps::{
+    DEFAULT_TIMESTAMP_TOLERANCE, TimestampHint, TimestampKind, TimestampReport, check_timestamps,
+};
+pub use transfer::{
+    ChunkStatus, ChunkTransfer, TRANSFER_STATE_NAME, TransferState, TransferStatus, mark,
+    transfer_status,
+};
+pub use unicode::Normalization;
+pub use writer::ChunkedWriter;
+pub use zip::{ZIP_EXTENSION, ZipStore};
+
+pub const DEFAULT_CHUNK_SIZE: u64 = 5 * 1024 * 1024; // 5MiB
+// Below this, compressing a chunk saves too little to be worth decoding it again
+pub const DEFAULT_MIN_RATIO: f64 = 1.05;
+pub const SFTP_SCHEME: &str = "sftp://";
+// Where files a reconstruction replaces go, beside them, without a desktop trash
+pub const TRASH_DIR: &str = ".fsr-trash";
+
+// Whether `path` is an `sftp://` URL rather than a local path. Builds without the sftp
+// feature refuse these rather than take them for a directory named `sftp:`.
+pub fn is_sftp_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with(SFTP_SCHEME))
+}
+
+// What a directory browser needs to know about the directory it is showing. One scan
+// yields both, so a browser and the reconstruction it starts don't each read the
+// directory again.
+pub struct Listing {
+    pub subdirectories: Vec<PathBuf>,
+    // In the order they are concatenated
+    pub chunk_files: Vec<PathBuf>,
+    // Symbolic links named like chunks, left out of `chunk_files` unless they are followed;
+    // see `symlinks`
+    pub skipped_links: Vec<PathBuf>,
+}
+
+// Chunks of a set split with random names, into shard subdirectories, or with chunks
+// stored in another's file, are the ones its manifest lists, in its order, whatever else
+// the directory holds. Its shard subdirectories aren't listed as subdirectories. Links
+// named like chunks are taken or skipped as `policy` says. A manifest changed after it
+// was sealed is only gone by with `accept_modified`.
+pub fn list_directory(
+    directory: &Path,
+    policy: SymlinkPolicy,
+    accept_modified: bool,
+) -> Result<Listing> {
+    let mut listing = Listing {
+        subdirectories: Vec::new(),
+        chunk_files: Vec::new(),
+        skipped_links: Vec::new(),
+    };
+    let manifest = Manifest::load(directory, accept_modified).ok().flatten();
+    let shard_dirs: BTreeSet<String> = manifest
+        .iter()
+        .flat_map(|manifest| manifest.shard_dirs())
+        .map(str::to_string)
+        .collect();
+    let listed = match manifest {
+        Some(manifest)
+            if manifest.random_names || manifest.shards.is_some() || manifest.shares_files() =>
+        {
+            Some(manifest.chunks)
+        }
+        _ => None,
+    };
+    for entry in fs::read_dir(directory).at(directory)? {
+        let entry = entry.at(directory)?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let chunk = listed.is_none() && chunk_index(&name).is_some();
+        if path.is_dir() && shard_dirs.contains(&name) {
+            continue;
+        } else if path.is_dir() {
+            listing.subdirectories.push(path);
+        } else if chunk && !symlinks::takes_chunk(&path, entry.file_type().at(&path)?, policy)? {
+            listing.skipped_links.push(path);
+        } else if chunk {
+            listing.chunk_files.push(path);
+        } else if listed.is_none() {
+            debug!("ignoring {}", path.display());
+        }
+    }
+    match listed {
+        Some(chunks) => {
+            listing.chunk_files = chunks
+                .iter()
+                .map(|entry| directory.join(entry.file()))
+                .filter(|path| path.is_file())
+                .collect();
+        }
+        // By number rather than by name, which would put chunk1000 before chunk101
+        None => listing.chunk_files.sort_by_cached_key(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            (chunk_index(&name), name.into_owned())
+        }),
+    }
+    Ok(listing)
+}
+
+// The output name recorded when the file was split, if any. Chunks named for another
+// tool to join carry it in their names. `directory` may be a zip or tar of the chunks.
+// A manifest changed after it was sealed fails it unless `accept_modified`.
+pub fn default_output_name(directory: &Path, accept_modified: bool) -> Result<String> {
+    let archive = match is_archive(directory) {
+        true => Some(ArchiveStore::open(directory, accept_modified)?),
+        false => None,
+    };
+    let loaded = match &archive {
+        Some(archive) => archive.read_info(),
+        None => Manifest::load(directory, accept_modified),
+    };
+    match loaded {
+        Ok(Some(manifest)) => manifest.output_name(),
+        Ok(None) => {
+            let mut names = Vec::new();
+            match &archive {
+                Some(archive) => names.extend(archive.names().map(str::to_string)),
+                None => {
+                    for entry in fs::read_dir(directory).at(directory)? {
+                        let name = entry.at(directory)?.file_name();
+                        names.push(name.to_string_lossy().into_owned());
+                    }
+                }
+            }
+            let mut bases: Vec<&str> = names
+                .iter()
+                .filter_map(|name| Some(compat::parse(name)?.1))
+                .collect();
+            bases.sort();
+            Ok(bases
+                .into_iter()
+                .next()
+                .unwrap_or("reconstructed_file")
+                .to_string())
+        }
+        Err(e @ SplitterError::MetadataCorrupt { .. }) => {
+            warn!("{}; naming the output reconstructed_file", e);
+            Ok("reconstructed_file".to_string())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// Index of a chunk file named like `chunk007`, or `chunk007.gz` when compressed, if
+// `name` is one, or named the way another tool would (see `Compat`). So is the name
+// the manifest of a sharded set gives it, as in `00/chunk007`.
+pub(crate) fn chunk_index(name: &str) -> Option<u64> {
+    let name = store::unsharded(name);
+    numbered_index(name).or_else(|| compat::parse(name).map(|(_, _, index)| index))
+}
+
+// The most chunks a set can be missing below the highest index found before that index
+// is taken for a stray file named like a chunk rather than counted up to.
+const MAX_MISSING: u64 = 1 << 20;
+
+// The indices below the highest of `present` that aren't among them, worked out from the
+// gaps between those there are rather than by counting up to the highest. More than
+// `MAX_MISSING` of them is a `StrayChunk`, the index after the gap that goes past it.
+pub(crate) fn missing_below(present: impl IntoIterator<Item = u64>) -> Result<Vec<u64>> {
+    let mut present: Vec<u64> = present.into_iter().collect();
+    present.sort_unstable();
+    present.dedup();
+    let mut missing = Vec::new();
+    let mut next = 0;
+    for &index in &present {
+        if index - next > MAX_MISSING - missing.len() as u64 {
+            return Err(SplitterError::StrayChunk {
+                index,
+                chunks: present.len(),
+            });
+        }
+        missing.extend(next..index);
+        next = index + 1;
+    }
+    Ok(missing)
+}
+
+// Whether `name` is one of the files a split writes: the manifest, a chunk under any of
+// its names, parity, PAR2 volumes or a join script.
+pub(crate) fn is_set_file(name: &str) -> bool {
+    name == MANIFEST_NAME
+        || chunk_index(name).is_some()
+        || store::is_random_name(name)
+        || parity::is_parity_name(name)
+        || par2::is_par2_name(name)
+        || join::is_script_name(name)
+}
+
+// Whether `directory` is on a file system that doesn't tell names apart by case, as
+// macOS's and Windows' usually don't: found by looking one of its files up with the
+// case of its name swapped, or, with nothing in it to look up, as is usual for the
+// platform.
+pub(crate) fn case_insensitive(directory: &Path) -> bool {
+    let directory = match directory.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => directory,
+    };
+    let names: BTreeSet<String> = fs::read_dir(directory)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect();
+    for name in &names {
+        let swapped: String = name
+            .chars()
+            .map(|c| match c.is_ascii_lowercase() {
+                true => c.to_ascii_uppercase(),
+                false => c.to_ascii_lowercase(),
+            })
+            .collect();
+        if swapped != *name && !names.contains(&swapped) {
+            return fs::symlink_metadata(directory.join(swapped)).is_ok();
+        }
+    }
+    cfg!(any(windows, target_os = "macos"))
+}
+
+// Whether `a` and `b` name the same file where case is or isn't ignored. Where it is,
+// so is the normalization of accented letters: macOS's file systems take "é" composed
+// and decomposed for one name, and should Windows' keep them apart, that only errs on
+// the side of leaving a set's files alone.
+pub(crate) fn same_name(a: &str, b: &str, ignore_case: bool) -> bool {
+    a == b || ignore_case && unicode::nfd(&a.to_lowercase()) == unicode::nfd(&b.to_lowercase())
+}
+
+// Whether this is the first time a warning or note about `path` is given, for those an
+// operation may come to more than once and should only give once. Only the latest few
+// are remembered, so that a process that goes on running, as `watch` and `serve` do,
+// neither keeps every path it has seen nor stays quiet about a set it comes back to.
+pub(crate) fn first_time(path: &Path) -> bool {
+    static TOLD: Mutex<VecDeque<PathBuf>> = Mutex::new(VecDeque::new());
+    let mut told = TOLD.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    remember(&mut told, path)
+}
+
+// Add `path` to the latest paths `told`, if it isn't one already, forgetting the
+// earliest when there are too many. Returns whether it was added.
+fn remember(told: &mut VecDeque<PathBuf>, path: &Path) -> bool {
+    const REMEMBERED: usize = 32;
+    if told.iter().any(|seen| seen == path) {
+        return false;
+    }
+    if told.len() == REMEMBERED {
+        told.pop_front();
+    }
+    told.push_back(path.to_path_buf());
+    true
+}
+
+// Whether `path` is a named pipe, a character device or the like rather than a file:
+// something read or written front to back, whose size isn't known up front, and whose
+// opening waits until the other end is opened too.
+pub fn is_stream(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| {
+        let kind = metadata.file_type();
+        !kind.is_file() && !kind.is_dir()
+    })
+}
+
+pub(crate) fn numbered_index(name: &str) -> Option<u64> {
+    prefixed_index(name, "chunk")
+}
+
+// `numbered_index` for a chunk named with `prefix` rather than `chunk`.
+pub(crate) fn prefixed_index(name: &str, prefix: &str) -> Option<u64> {
+    let digits = name.strip_prefix(prefix)?;
+    let digits = match digits.split_once('.') {
+        Some((digits, extension)) => {
+            Compression::from_extension(extension)?;
+            digits
+        }
+        None => digits,
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+// Summary of the chunk files found in a directory. For compressed sets the sizes are
+// those the manifest recorded, since the files' own say nothing about the original.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChunkHealth {
+    pub chunks: usize,
+    pub total_size: u64,
+    // Indices info.json lists that no file holds, or without it, those absent from the
+    // otherwise contiguous chunk000..chunkN sequence
+    pub missing: Vec<u64>,
+    // Chunks other than the last whose size differs from the first chunk
+    pub uneven: Vec<u64>,
+    // With random names: files named like chunks that the manifest doesn't list
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unexpected: Vec<String>,
+}
+
+impl ChunkHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.chunks > 0
+            && self.missing.is_empty()
+            && self.uneven.is_empty()
+            && self.unexpected.is_empty()
+    }
+}
+
+// A set split with random names, or into shard subdirectories, is checked against its
+// manifest: every chunk it lists must be there, and nothing else named like one. So is
+// a volume of a spanned set, for the chunks on it. `directory` may be a zip or tar of
+// the chunks. A manifest changed after it was sealed is only gone by with
+// `accept_modified`.
+pub fn chunk_health(directory: &Path, accept_modified: bool) -> Result<ChunkHealth> {
+    if is_archive(directory) {
+        return archive_health(&ArchiveStore::open(directory, accept_modified)?);
+    }
+    let manifest = Manifest::load(directory, accept_modified).ok().flatten();
+    // Chunks in shard subdirectories are found by the names listed for them too
+    let random = manifest
+        .as_ref()
+        .is_some_and(|manifest| manifest.random_names || manifest.shards.is_some());
+    // Chunks the manifest lists as compressed or as age files, by index, with their
+    // original size, and those only encrypted, whose files are a known size larger. A
+    // manifest with its metadata concealed lists the sizes of the files themselves.
+    let mut recorded = BTreeMap::new();
+    let mut sealed = BTreeMap::new();
+    let mut listed = BTreeMap::new();
+    if let Some(manifest) = &manifest {
+        let age = manifest
+            .encryption
+            .as_ref()
+            .is_some_and(|encryption| encryption.cipher == Cipher::Age);
+        for (index, entry) in manifest.indexed() {
+            let index = index as u64;
+            if manifest.concealed() {
+                // Listed at the size of its file, which is measured below
+            } else if !manifest.compression_of(entry).is_none() || age {
+                recorded.insert(index, entry.size);
+            } else if !manifest.stored_as_is(entry) {
+                sealed.insert(index, entry.size);
+            }
+            if random {
+                listed.insert(entry.name.as_str(), index);
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(directory).at(directory)? {
+        let entry = entry.at(directory)?;
+        files.push((
+            entry.file_name().to_string_lossy().into_owned(),
+            entry.path(),
+        ));
+    }
+    for shard in manifest.iter().flat_map(|manifest| manifest.shard_dirs()) {
+        let path = directory.join(shard);
+        for entry in fs::read_dir(&path).into_iter().flatten() {
+            let entry = entry.at(&path)?;
+            let name = format!("{}/{}", shard, entry.file_name().to_string_lossy());
+            files.push((name, entry.path()));
+        }
+    }
+    let mut sizes = BTreeMap::new();
+    let mut unexpected = Vec::new();
+    for (name, path) in files {
+        let index = match random {
+            true => listed.get(name.as_str()).copied(),
+            false => chunk_index(&name),
+        };
+        if let Some(index) = index {
+            let size = match recorded.get(&index) {
+                Some(&size) => size,
+                None => fs::symlink_metadata(&path).at(&path)?.len(),
+            };
+            let size = match sealed.get(&index) {
+                Some(&original) if size == crypt::sealed_len(original) => original,
+                _ => size,
+            };
+            sizes.insert(index, size);
+        } else if random
+            && (store::is_random_name(store::unsharded(&name))
+                || name.contains('/') && chunk_index(&name).is_some())
+        {
+            unexpected.push(name);
+        }
+    }
+    unexpected.sort_by(|a, b| natural_cmp(a, b));
+    // Chunks stored in another's file are there when it is
+    if let Some(manifest) = &manifest {
+        let indices: BTreeMap<&str, u64> = manifest
+            .indexed()
+            .map(|(index, entry)| (entry.name.as_str(), index as u64))
+            .collect();
+        for (index, entry) in manifest.indexed() {
+            if let Some(file) = &entry.same_as
+                && indices
+                    .get(file.as_str())
+                    .is_some_and(|index| sizes.contains_key(index))
+            {
+                sizes.insert(index as u64, entry.size);
+            }
+        }
+    }
+
+    let last = sizes.keys().next_back().copied();
+    let span = manifest
+        .as_ref()
+        .and_then(|manifest| manifest.span.as_ref());
+    let missing = match (&manifest, span, last) {
+        // Counted against the manifest, so chunks gone from the end are missing too
+        (Some(manifest), _, _) if random || span.is_none() && !manifest.chunks.is_empty() => {
+            let listed = manifest.chunks.len() as u64;
+            (0..listed).filter(|i| !sizes.contains_key(i)).collect()
+        }
+        // A volume of a spanned set only holds its own chunks
+        (_, Some(span), _) => span
+            .here()
+            .map(|index| index as u64)
+            .filter(|i| !sizes.contains_key(i))
+            .collect(),
+        (_, _, Some(_)) => missing_below(sizes.keys().copied())?,
+        (_, _, None) => Vec::new(),
+    };
+    let expected = sizes.values().next().copied();
+    let uneven = match (&manifest, span) {
+        // Whose chunks are as large as planned, which needn't be evenly, or with
+        // concealed metadata as large as their files were written
+        (Some(manifest), _) if span.is_some() || manifest.concealed() => {
+            let listed: BTreeMap<u64, u64> = manifest
+                .indexed()
+                .map(|(index, entry)| (index as u64, entry.size))
+                .collect();
+            sizes
+                .iter()
+                .filter(|&(index, size)| listed.get(index).is_some_and(|listed| listed != size))
+                .map(|(index, _)| *index)
+                .collect()
+        }
+        _ => sizes
+            .iter()
+            .filter(|&(index, size)| Some(*index) != last && Some(*size) != expected)
+            .map(|(index, _)| *index)
+            .collect(),
+    };
+
+    Ok(ChunkHealth {
+        chunks: sizes.len(),
+        total_size: sizes.values().sum(),
+        missing,
+        uneven,
+        unexpected,
+    })
+}
+
+// `chunk_health` for the chunks in an archive. With a manifest, every chunk it lists
+// must be there, and compressed ones count with the size it recorded.
+fn archive_health(archive: &ArchiveStore) -> Result<ChunkHealth> {
+    let manifest = archive.read_info()?;
+    let mut recorded = BTreeMap::new();
+    if let Some(manifest) = &manifest {
+        for (index, entry) in manifest.indexed() {
+            if !manifest.compression_of(entry).is_none() {
+                recorded.insert(index, entry.size);
+            }
+        }
+    }
+    let mut sizes = BTreeMap::new();
+    for index in archive.list_chunks()? {
+        let size = match recorded.get(&index) {
+            Some(&size) => size,
+            None => archive.chunk_len(index)?,
+        };
+        sizes.insert(index as u64, size);
+    }
+    let last = sizes.keys().next_back().copied();
+    let missing = match (&manifest, last) {
+        (Some(manifest), _) if !manifest.chunks.is_empty() => manifest
+            .indexed()
+            .map(|(index, _)| index as u64)
+            .filter(|index| !sizes.contains_key(index))
+            .collect(),
+        (_, Some(_)) => missing_below(sizes.keys().copied())?,
+        (_, None) => Vec::new(),
+    };
+    let expected = sizes.values().next().copied();
+    let uneven = sizes
+        .iter()
+        .filter(|&(index, size)| Some(*index) != last && Some(*size) != expected)
+        .map(|(index, _)| *index)
+        .collect();
+    Ok(ChunkHealth {
+        chunks: sizes.len(),
+        total_size: sizes.values().sum(),
+        missing,
+        uneven,
+        unexpected: archive.unexpected(),
+    })
+}
+
+// Hash of what the chunk at `path` holds once decoded, or None when it is gone or no
+// longer decodes, either of which means it changed.
+pub(crate) fn hash_chunk(
+    path: &Path,
+    compression: Compression,
+    algorithm: HashAlgorithm,
+    copied: &mut dyn FnMut(u64),
+    cancel: &CancelToken,
+) -> Result<Option<String>> {
+    let hashed = match compression {
+        Compression::None => manifest::hash_file(path, algorithm, copied, cancel),
+        compression => manifest::hash_compressed(path, compression, algorithm, copied, cancel),
+    };
+    match hashed {
+        Ok(actual) => {
+            debug!("{} hashes to {}", path.display(), actual);
+            Ok(Some(actual))
+        }
+        Err(SplitterError::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => {
+            debug!("{} is missing", path.display());
+            Ok(None)
+        }
+        // A compressed chunk that no longer decodes has changed as surely as one that
+        // hashes differently
+        Err(SplitterError::Io { source, .. }) if source.kind() == io::ErrorKind::InvalidData => {
+            debug!("{} does not decode: {}", path.display(), source);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// Whether the compressed chunk at `path` still decodes, which checks it against the
+// codec's own checksum: gzip's CRC, or the one in an armored chunk's footer. False too
+// when it is gone.
+pub(crate) fn decodes(
+    path: &Path,
+    compression: Compression,
+    copied: &mut dyn FnMut(u64),
+    cancel: &CancelToken,
+) -> Result<bool> {
+    let mut sink = event::Counting {
+        inner: io::sink(),
+        copied,
+        cancel,
+    };
+    let decoded = store::ChunkReader::open(path, compression).and_then(|mut reader| {
+        pipeline::copy_overlapped(&pipeline::Io::default(), &mut reader, &mut sink, None)
+    });
+    match decoded {
+        Ok(_) => Ok(true),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::InvalidData
+            ) =>
+        {
+            debug!("{} does not decode: {}", path.display(), e);
+            Ok(false)
+        }
+        Err(e) => Err(e).at(path),
+    }
+}
+
+// Outcome of `verify`: the shape of the chunk set, and which chunks no longer hash to
+// what the manifest recorded for them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VerifyReport {
+    pub health: ChunkHealth,
+    // Whether the manifest recorded hashes at all; without them only the shape is checked
+    pub hashed: bool,
+    // Chunks listed in the manifest that are gone or whose contents changed, and parity
+    // files likewise, along with any other file the PAR2 data covers
+    pub mismatched: Vec<String>,
+    // Whether `.par2` files were found and the files they cover checked against them
+    #[serde(default)]
+    pub par2: bool,
+    // Whether what was found lost can be rebuilt; None when nothing was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recoverability: Option<Box<Recoverability>>,
+    // Checksum files another tool left, such as SHA256SUMS, whose entries were checked
+    // as well; files that don't match them are in `mismatched`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checksum_files: Vec<String>,
+    // Files they list that aren't there
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checksum_missing: Vec<String>,
+    // Chunks none of them lists, which nothing but the manifest checks
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unchecksummed: Vec<String>,
+    // With `verify_sample`: the chunks checked, which are all that were
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample: Option<Box<Sampled>>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.health.is_healthy() && self.mismatched.is_empty() && self.checksum_missing.is_empty()
+    }
+}
+
+// Seal the manifest in `directory` again over what it holds now, after edits to it that
+// were meant, returning the seal it had. One still intact is left as it is. Only a
+// local directory can be resealed; an archive would have to be written anew.
+pub fn reseal(directory: &Path) -> Result<Seal> {
+    if is_archive(directory) || is_s3_url(directory) || is_sftp_url(directory) {
+        return Err(SplitterError::InvalidOption {
+            field: "directory",
+            reason: "must be a local directory; unpack an archive to reseal it",
+        });
+    }
+    let _lock = lock::acquire(directory, "reseal")?;
+    let Some((manifest, seal)) = Manifest::load_sealed(directory)? else {
+        let message = format!("there is no {} to reseal", MANIFEST_NAME);
+        return Err(io::Error::new(io::ErrorKind::NotFound, message)).at(directory);
+    };
+    if seal != Seal::Intact {
+        info!(
+            "sealing {} over its contents",
+            directory.join(MANIFEST_NAME).display()
+        );
+        manifest.save(directory)?;
+    }
+    Ok(seal)
+}
+
+// Check the chunks in `directory` without reconstructing anything, hashing each one
+// when the manifest has hashes to compare with. Parity files are checked the same way,
+// or only by their size without hashes, and `.par2` files are used to check whatever
+// they cover. When anything is lost, the report says whether the PAR2 files, the parity
+// and the other copies of the set in `copies` can still rebuild it. A volume of a set
+// split across several directories is checked for the chunks it should hold itself.
+// `progress` hears about every chunk hashed; `cancel` stops the hashing between buffers.
+// A manifest changed after it was sealed fails it unless `accept_modified`.
+pub fn verify(
+    directory: &Path,
+    copies: &[PathBuf],
+    accept_modified: bool,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<VerifyReport> {
+    if is_archive(directory) {
+        return verify_archive(directory, accept_modified, progress, cancel);
+    }
+    let _lock = lock::acquire(directory, "verify")?;
+    verify_locked(directory, copies, accept_modified, progress, cancel)
+}
+
+// `verify` of a directory whose lock the caller holds.
+pub(crate) fn verify_locked(
+    directory: &Path,
+    copies: &[PathBuf],
+    accept_modified: bool,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<VerifyReport> {
+    let health = chunk_health(directory, accept_modified)?;
+    let mut report = VerifyReport {
+        health,
+        hashed: false,
+        mismatched: Vec::new(),
+        par2: false,
+        recoverability: None,
+        checksum_files: Vec::new(),
+        checksum_missing: Vec::new(),
+        unchecksummed: Vec::new(),
+        sample: None,
+    };
+    // Gaps are already in `health`, and only sets with a manifest have hashes to check
+    let set = match ChunkSet::open(directory, accept_modified) {
+        Ok(set) => Some(set),
+        Err(SplitterError::MissingChunks { .. }) => None,
+        Err(e) => return Err(e),
+    };
+    let algorithm = set.as_ref().and_then(ChunkSet::hash_algorithm);
+    report.hashed = algorithm.is_some();
+    // Of a spanned set, only the chunks of this volume, and any gathered here
+    let span = set
+        .as_ref()
+        .and_then(ChunkSet::manifest)
+        .and_then(|manifest| manifest.span.clone());
+    for chunk in set.iter().flatten() {
+        if let Some(span) = &span
+            && !span.here().contains(&chunk.index)
+            && !chunk.path.exists()
+        {
+            continue;
+        }
+        if checkable(chunk, algorithm) {
+            check_chunk(directory, chunk, algorithm, &mut report, progress, cancel)?;
+        }
+    }
+    // The parity is only any use if it is intact itself
+    let manifest = Manifest::load(directory, accept_modified).ok().flatten();
+    if let Some(manifest) = &manifest
+        && let Some(info) = &manifest.parity
+    {
+        for entry in &info.files {
+            let mut copied = |delta| progress(ProgressEvent::BytesCopied { delta });
+            if !parity::is_intact(directory, manifest, entry, &mut copied, cancel)? {
+                debug!("{} is missing or damaged", entry.name);
+                report.mismatched.push(entry.name.clone());
+            }
+        }
+    }
+    // PAR2 data checks every file it covers by MD5, hashed set or not
+    let par2 = match par2::load(directory)? {
+        Some(set) => {
+            report.par2 = true;
+            let damage = par2::check(&set, directory, cancel)?;
+            for name in set.damaged_names(&damage) {
+                if !report.mismatched.contains(&name) {
+                    report.mismatched.push(name);
+                }
+            }
+            Some((set, damage))
+        }
+        None => None,
+    };
+    // So do checksum files another tool left
+    check_sums(
+        directory,
+        set.as_ref(),
+        manifest.as_ref(),
+        &mut report,
+        progress,
+        cancel,
+    )?;
+    let lost = !report.mismatched.is_empty() || !report.health.missing.is_empty();
+    if lost && (manifest.is_some() || par2.is_some()) {
+        let par2 = par2.as_ref().map(|(set, damage)| (set, damage));
+        let assessed = assess::assess(
+            directory,
+            manifest.as_ref(),
+            par2,
+            copies,
+            accept_modified,
+            cancel,
+        )?;
+        info!("{}", assessed.conclusion());
+        report.recoverability = Some(Box::new(assessed));
+    }
+    info!(
+        "verified {}: {} mismatched",
+        directory.display(),
+        report.mismatched.len()
+    );
+    progress(ProgressEvent::Completed {
+        report: Report::Verify(report.clone()),
+    });
+    Ok(report)
+}
+
+// Whether `check_chunk` has anything to check `chunk` against: a hash, for compressed
+// and armored chunks the checksum they carry of their own, or for encrypted ones the
+// hash of the file as stored.
+pub(crate) fn checkable(chunk: &ChunkInfo, algorithm: Option<HashAlgorithm>) -> bool {
+    (chunk.hash.is_some() && algorithm.is_some())
+        || !chunk.compression.is_none()
+        || chunk.stored_hash.is_some()
+}
+
+// Hash or decode `chunk` of the set in `directory`, adding its name there to
+// `report.mismatched` when it isn't intact. An encrypted chunk is checked as stored,
+// which needs no key: a file that hashes as it did when it was written opens as it did
+// then.
+pub(crate) fn check_chunk(
+    directory: &Path,
+    chunk: &ChunkInfo,
+    algorithm: Option<HashAlgorithm>,
+    report: &mut VerifyReport,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<()> {
+    let expected = chunk.hash.as_ref().filter(|_| algorithm.is_some());
+    let index = chunk.index;
+    progress(ProgressEvent::ChunkStarted {
+        index,
+        size: chunk.len,
+    });
+    // As info.json names it, with the shard subdirectory of a sharded set; a chunk in
+    // another volume of a spanned set by its file name
+    let relative = match chunk.path.strip_prefix(directory) {
+        Ok(relative) => relative,
+        Err(_) => Path::new(chunk.path.file_name().unwrap_or_default()),
+    };
+    let name: Vec<_> = relative
+        .iter()
+        .map(|component| component.to_string_lossy())
+        .collect();
+    let name = name.join("/");
+    let mut copied = |delta| progress(ProgressEvent::BytesCopied { delta });
+    let (intact, hash) = match (expected, algorithm) {
+        _ if chunk.stored_hash.is_some() => {
+            let hash = hash_chunk(
+                &chunk.path,
+                Compression::None,
+                HashAlgorithm::Sha256,
+                &mut copied,
+                cancel,
+            )?;
+            (hash == chunk.stored_hash, None)
+        }
+        (Some(expected), Some(algorithm)) => {
+            let hash = hash_chunk(
+                &chunk.path,
+                chunk.compression,
+                algorithm,
+                &mut copied,
+                cancel,
+            )?;
+            (hash.as_ref() == Some(expected), hash)
+        }
+        _ => {
+            let decodes = decodes(&chunk.path, chunk.compression, &mut copied, cancel)?;
+            (decodes, None)
+        }
+    };
+    // Once for a file other chunks are stored in as well
+    if !intact && !report.mismatched.contains(&name) {
+        report.mismatched.push(name);
+    }
+    progress(ProgressEvent::ChunkFinished { index, hash });
+    Ok(())
+}
+
+// Check every file the checksum files in `directory` list against them but the
+// original file itself, whose hash is often given alongside those of its pieces, noting
+// those they list that are gone and the chunks they have nothing for. For pieces
+// another tool split with no info.json, they are the only check there is, and the
+// pieces are found by their names as `import` finds them.
+fn check_sums(
+    directory: &Path,
+    set: Option<&ChunkSet>,
+    manifest: Option<&Manifest>,
+    report: &mut VerifyReport,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<()> {
+    let Some(sums) = sums::load(directory)? else {
+        return Ok(());
+    };
+    let foreign = match manifest.is_none() && !directory.join(MANIFEST_NAME).exists() {
+        true => detect_foreign(directory)?.into_iter().next(),
+        false => None,
+    };
+    let foreign = foreign.as_ref();
+    // Pieces named like none of ours are the set's chunks; those that are, such as
+    // `FILE.001`, were counted as chunks already
+    let pieces = foreign.filter(|_| report.health.chunks == 0);
+    if let Some(pieces) = pieces {
+        report.health.chunks = pieces.pieces.len();
+        report.health.total_size = pieces.total_size();
+    }
+    info!(
+        "checking {} against {}",
+        directory.display(),
+        sums.files.join(", ")
+    );
+    report.checksum_files = sums.files.clone();
+    let original = match manifest {
+        Some(manifest) => Some(manifest.original_filename.as_str()),
+        None => foreign.and_then(|set| set.original_filename.as_deref()),
+    };
+    for (name, entry) in &sums.entries {
+        if Some(name.as_str()) == original {
+            debug!(
+                "{} is listed in {}, but isn't here to check",
+                name, entry.source
+            );
+            continue;
+        }
+        let path = directory.join(name);
+        if !path.is_file() {
+            // Of a foreign set only what is named like one of its pieces is missed
+            let missed = foreign.is_none_or(|set| {
+                set.doubts.iter().any(
+                    |doubt| matches!(doubt, Doubt::ListedMissing { names } if names.contains(name)),
+                )
+            });
+            if missed {
+                report.checksum_missing.push(name.clone());
+            }
+            continue;
+        }
+        let mut copied = |delta| progress(ProgressEvent::BytesCopied { delta });
+        if !sums::check(&path, entry, &mut copied, cancel)? && !report.mismatched.contains(name) {
+            report.mismatched.push(name.clone());
+        }
+    }
+    let chunks = set.iter().flat_map(|set| set.iter()).map(|chunk| {
+        let name = chunk.path.file_name().unwrap_or_default();
+        name.to_string_lossy().into_owned()
+    });
+    let pieces = pieces.iter().flat_map(|set| &set.pieces);
+    for name in chunks.chain(pieces.map(|piece| piece.name.clone())) {
+        if sums.entries.contains_key(&name) {
+            report.hashed = true;
+        } else {
+            report.unchecksummed.push(name);
+        }
+    }
+    Ok(())
+}
+
+// `verify` for a zip or tar of the chunks. Every chunk is read through as reconstructing
+// would, which checks a zip's against the CRC-32 it records, and against any hash the
+// manifest has. Parity and PAR2 files in an archive aren't used, so nothing there can
+// rebuild what is lost, and that is all the report can say about it.
+fn verify_archive(
+    path: &Path,
+    accept_modified: bool,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<VerifyReport> {
+    let archive = ArchiveStore::open(path, accept_modified)?;
+    let manifest = archive.read_info()?;
+    let mut report = VerifyReport {
+        health: archive_health(&archive)?,
+        hashed: manifest
+            .as_ref()
+            .is_some_and(|manifest| manifest.hash.is_some()),
+        mismatched: Vec::new(),
+        par2: false,
+        recoverability: None,
+        checksum_files: Vec::new(),
+        checksum_missing: Vec::new(),
+        unchecksummed: Vec::new(),
+        sample: None,
+    };
+    let algorithm = manifest.as_ref().and_then(|manifest| manifest.hash);
+    for index in archive.list_chunks()? {
+        let expected = manifest.as_ref().and_then(|manifest| {
+            let (_, entry) = manifest.indexed().find(|&(i, _)| i == index)?;
+            entry.hash.clone()
+        });
+        progress(ProgressEvent::ChunkStarted {
+            index,
+            size: archive.chunk_len(index)?,
+        });
+        let mut copied = |delta| progress(ProgressEvent::BytesCopied { delta });
+        let (intact, hash) = archive.check_chunk(index, algorithm, &mut copied, cancel)?;
+        if !intact || (expected.is_some() && hash != expected) {
+            let name = archive.chunk_path(index);
+            let name = name.file_name().unwrap_or_default().to_string_lossy();
+            if !report.mismatched.iter().any(|other| *other == name) {
+                report.mismatched.push(name.into_owned());
+            }
+        }
+        progress(ProgressEvent::ChunkFinished { index, hash });
+    }
+    if !report.mismatched.is_empty() || !report.health.missing.is_empty() {
+        // Missing chunks by the name the manifest gave them, if it lists them
+        let mut lost: Vec<String> = report
+            .health
+            .missing
+            .iter()
+            .map(|&index| {
+                let listed = manifest.as_ref().and_then(|manifest| {
+                    let (_, entry) = manifest.indexed().find(|&(i, _)| i as u64 == index)?;
+                    Some(entry.file().to_string())
+                });
+                listed.unwrap_or_else(|| {
+                    let path = archive.chunk_path(index as usize);
+                    path.file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+            })
+            .collect();
+        lost.extend(report.mismatched.iter().cloned());
+        // A file other chunks are stored in as well is lost once
+        let mut seen = BTreeSet::new();
+        lost.retain(|name| seen.insert(name.clone()));
+        let assessed = Recoverability {
+            recoverable: false,
+            lost,
+            tolerance: 0,
+            in_copies: Vec::new(),
+            stripes: Vec::new(),
+            par2: None,
+        };
+        info!("{}", assessed.conclusion());
+        report.recoverability = Some(Box::new(assessed));
+    }
+    info!(
+        "verified {}: {} mismatched",
+        path.display(),
+        report.mismatched.len()
+    );
+    progress(ProgressEvent::Completed {
+        report: Report::Verify(report.clone()),
+    });
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_latest_paths_told_of_are_remembered() {
+        let path = |number| PathBuf::from(format!("set{}/info.json", number));
+        let mut told = VecDeque::new();
+        assert!(remember(&mut told, &path(0)));
+        assert!(!remember(&mut told, &path(0)));
+        for number in 1..32 {
+            assert!(remember(&mut told, &path(number)));
+        }
+        assert!(!remember(&mut told, &path(0)));
+        // One more and the earliest is forgotten, to be told of again
+        assert!(remember(&mut told, &path(32)));
+        assert_eq!(told.len(), 32);
+        assert!(remember(&mut told, &path(0)));
+        assert!(!remember(&mut told, &path(32)));
+    }
+
+    #[test]
+    fn names_match_by_case_and_normalization_only_where_case_is_ignored() {
+        let cases = [
+            ("info.json", "info.json", true, true),
+            ("Info.JSON", "info.json", false, true),
+            // "é" composed, and as "e" with a combining accent
+            ("caf\u{e9}.zip", "cafe\u{301}.zip", false, true),
+            ("CAF\u{c9}.zip", "cafe\u{301}.zip", false, true),
+            ("chunk000", "chunk001", false, false),
+            ("caf\u{e9}.zip", "cafe.zip", false, false),
+        ];
+        for (a, b, sensitive, insensitive) in cases {
+            assert_eq!(same_name(a, b, false), sensitive, "{} {}", a, b);
+            assert_eq!(same_name(b, a, false), sensitive, "{} {}", b, a);
+            assert_eq!(same_name(a, b, true), insensitive, "{} {}", a, b);
+            assert_eq!(same_name(b, a, true), insensitive, "{} {}", b, a);
+        }
+    }
+
+    // Probed on the file system itself, as found by looking a file up by another case
+    #[cfg(any(target_os = "macos", windows))]
+    #[test]
+    fn the_usual_file_systems_of_macos_and_windows_ignore_case() {
+        let temp = tempfile::tempdir().unwrap();
+        assert!(case_insensitive(temp.path()));
+        fs::write(temp.path().join("Info.json"), "{}").unwrap();
+        assert!(case_insensitive(temp.path()));
+        assert!(fs::symlink_metadata(temp.path().join("INFO.JSON")).is_ok());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn linux_file_systems_tell_case_apart() {
+        let temp = tempfile::tempdir().unwrap();
+        fs::write(temp.path().join("Info.json"), "{}").unwrap();
+        assert!(!case_insensitive(temp.path()));
+    }
+}
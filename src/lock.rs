@@ -0,0 +1,262 @@
+// The lock on a chunk directory: `.fsr.lock`, made in it at the start of a split,
+// reconstruction, verify, repair or heal of it and removed at the end, saying which
+// process on which host is doing what since when. Another of these on the same
+// directory meanwhile refuses to start, with `SplitterError::Locked` naming the holder,
+// rather than read chunks being written or write chunks being read. It is only
+// advisory: nothing else is kept out. A lock left behind by a process that died is
+// stale once that process is known to be gone, or once it is older than the most a lock
+// is honored for, DEFAULT_MAX_AGE unless the operation says otherwise; breaking one is
+// up to the front end, as `--steal-lock` does. A directory that can't be written to,
+// such as on a read-only disc, is read without one, as nothing can be written into it
+// meanwhile either.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{PathContext, Result, SplitterError};
+
+pub const LOCK_NAME: &str = ".fsr.lock";
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+// What a lock file holds.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Holder {
+    pub pid: u32,
+    pub host: String,
+    // "split", "reconstruct" and so on
+    pub operation: String,
+    // When it was taken, in seconds since the Unix epoch
+    pub started: u64,
+}
+
+impl Holder {
+    fn ours(operation: &str) -> Holder {
+        Holder {
+            pid: process::id(),
+            host: hostname(),
+            operation: operation.to_string(),
+            started: now(),
+        }
+    }
+
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(now().saturating_sub(self.started))
+    }
+
+    // Why the lock can be broken, if it can: its process is gone, or it is older than
+    // `max_age`, the most a lock is honored for. A process on another host can't be
+    // looked for.
+    pub fn stale(&self, max_age: Duration) -> Option<String> {
+        if self.host == hostname() && !alive(self.pid) {
+            return Some(format!("process {} is no longer running", self.pid));
+        }
+        (self.age() > max_age).then(|| format!("it is older than {}", describe_age(max_age)))
+    }
+
+    // As in "split by process 4242 on nas, started 3 minutes ago".
+    pub fn describe(&self) -> String {
+        format!(
+            "{} by process {} on {}, started {} ago",
+            self.operation,
+            self.pid,
+            self.host,
+            describe_age(self.age())
+        )
+    }
+
+    fn is_ours(&self) -> bool {
+        self.pid == process::id() && self.host == hostname()
+    }
+}
+
+// Held until dropped, when the lock file is removed again.
+#[derive(Debug)]
+pub struct DirLock {
+    // None when the directory couldn't be written to, so there is no lock to remove
+    path: Option<PathBuf>,
+}
+
+impl Drop for DirLock {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            release_at(path);
+        }
+    }
+}
+
+// Lock `directory` for `operation`, or fail with `Locked` when something already has.
+pub fn acquire(directory: &Path, operation: &str) -> Result<DirLock> {
+    acquire_with_max_age(directory, operation, DEFAULT_MAX_AGE)
+}
+
+// `acquire`, where a lock older than `max_age` is said to be stale.
+pub(crate) fn acquire_with_max_age(
+    directory: &Path,
+    operation: &str,
+    max_age: Duration,
+) -> Result<DirLock> {
+    let path = directory.join(LOCK_NAME);
+    let holder = Holder::ours(operation);
+    let data = serde_json::to_vec(&holder)
+        .map_err(io::Error::other)
+        .at(&path)?;
+    let created = OpenOptions::new().write(true).create_new(true).open(&path);
+    match created {
+        Ok(mut file) => {
+            file.write_all(&data).at(&path)?;
+            debug!("locked {} for {}", directory.display(), operation);
+            Ok(DirLock { path: Some(path) })
+        }
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(SplitterError::Locked {
+            path: directory.to_path_buf(),
+            holder: match holder_at(&path) {
+                Some(holder) => match holder.stale(max_age) {
+                    Some(reason) => format!("{}, but {}", holder.describe(), reason),
+                    None => holder.describe(),
+                },
+                // Being written this moment, or not one of ours
+                None => "something else".to_string(),
+            },
+        }),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem
+            ) =>
+        {
+            debug!(
+                "cannot lock {}: {}; going on without",
+                directory.display(),
+                e
+            );
+            Ok(DirLock { path: None })
+        }
+        // Nothing to read there, which the operation says better than a lock error would
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+            ) =>
+        {
+            Ok(DirLock { path: None })
+        }
+        Err(e) => Err(e).doing("locking", directory),
+    }
+}
+
+// Who holds the lock on `directory`, if anyone does and says.
+pub fn holder(directory: &Path) -> Option<Holder> {
+    holder_at(&directory.join(LOCK_NAME))
+}
+
+fn holder_at(path: &Path) -> Option<Holder> {
+    let mut data = Vec::new();
+    fs::File::open(path).ok()?.read_to_end(&mut data).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+// Remove the lock on `directory` whoever holds it.
+pub fn break_lock(directory: &Path) -> io::Result<()> {
+    match fs::remove_file(directory.join(LOCK_NAME)) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+// Remove the lock on `directory` if this process holds it, as before removing a
+// directory made for a split that failed.
+pub(crate) fn release_own(directory: &Path) {
+    release_at(&directory.join(LOCK_NAME));
+}
+
+fn release_at(path: &Path) {
+    if holder_at(path).is_some_and(|holder| holder.is_ours()) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// In the largest whole unit, as in "3 minutes" or "2 days".
+pub(crate) fn describe_age(age: Duration) -> String {
+    let seconds = age.as_secs();
+    let (count, unit) = match seconds {
+        0..60 => (seconds, "second"),
+        60..3_600 => (seconds / 60, "minute"),
+        3_600..86_400 => (seconds / 3_600, "hour"),
+        _ => (seconds / 86_400, "day"),
+    };
+    match count {
+        1 => format!("1 {}", unit),
+        _ => format!("{} {}s", count, unit),
+    }
+}
+
+fn hostname() -> String {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+        // SAFETY: `buf` is writable for its whole length, which is what is passed.
+        if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } == 0 {
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            return String::from_utf8_lossy(&buf[..len]).into_owned();
+        }
+        String::new()
+    }
+    #[cfg(not(unix))]
+    {
+        std::env::var("COMPUTERNAME").unwrap_or_default()
+    }
+}
+
+// Whether process `pid` on this host is still running; when that can't be told,
+// taken to be.
+fn alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        let Ok(pid) = libc::pid_t::try_from(pid) else {
+            return true;
+        };
+        // SAFETY: signal 0 only checks that the process exists and may be signalled.
+        if unsafe { libc::kill(pid, 0) } == 0 {
+            return true;
+        }
+        // Running as someone else
+        io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::Foundation::{CloseHandle, STILL_ACTIVE};
+        use windows_sys::Win32::System::Threading::{
+            GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+        };
+        // SAFETY: the handle is only used while open, and closed once done with.
+        unsafe {
+            let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+            if handle.is_null() {
+                // Gone, or not ours to look at
+                return io::Error::last_os_error().raw_os_error() == Some(5);
+            }
+            let mut code = 0;
+            let ok = GetExitCodeProcess(handle, &mut code);
+            CloseHandle(handle);
+            ok == 0 || code == STILL_ACTIVE as u32
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = pid;
+        true
+    }
+}
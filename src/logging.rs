@@ -0,0 +1,188 @@
+// The library logs through the `log` facade; this is what the binary plugs in. Lines
+// go to stderr at the level chosen with -v (warnings only by default), and with
+// `--log-file` also to that file with a timestamp, at info level or more. A progress
+// line being redrawn on the terminal is cleared first so the two don't run together,
+// and the full-screen interface turns the terminal side off altogether.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::style;
+
+static CONSOLE: AtomicBool = AtomicBool::new(true);
+
+struct Logger {
+    console: LevelFilter,
+    file: Option<(LevelFilter, Mutex<File>)>,
+    terminal: bool,
+}
+
+pub fn init(verbosity: u8, log_file: Option<&Path>) -> io::Result<()> {
+    let console = match verbosity {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    let file = match log_file {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            Some((console.max(LevelFilter::Info), Mutex::new(file)))
+        }
+        None => None,
+    };
+    let max = file
+        .as_ref()
+        .map_or(console, |(level, _)| console.max(*level));
+    let logger = Logger {
+        console,
+        file,
+        terminal: io::stderr().is_terminal() && !style::dumb_terminal(),
+    };
+    log::set_logger(Box::leak(Box::new(logger))).map_err(|e| io::Error::other(e.to_string()))?;
+    log::set_max_level(max);
+    Ok(())
+}
+
+// Whether log lines may go to the terminal at all.
+pub fn set_console(enabled: bool) {
+    CONSOLE.store(enabled, Ordering::Relaxed);
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.console
+            || self
+                .file
+                .as_ref()
+                .is_some_and(|(level, _)| metadata.level() <= *level)
+    }
+
+    fn log(&self, record: &Record) {
+        // The trash crate warns of every trash it looks for and doesn't find, as before
+        // the first file ever goes in; only worth a debug line
+        let level = match record.target().starts_with("trash::") {
+            true => record.level().max(Level::Debug),
+            false => record.level(),
+        };
+        if level <= self.console && CONSOLE.load(Ordering::Relaxed) {
+            let clear = if self.terminal { "\r\x1b[2K" } else { "" };
+            eprintln!("{}{}: {}", clear, label(level), record.args());
+        }
+        if let Some((max, file)) = &self.file
+            && level <= *max
+        {
+            let line = format!(
+                "{} {:<5} {}: {}\n",
+                timestamp(),
+                level,
+                record.target(),
+                record.args()
+            );
+            let _ = file.lock().unwrap().write_all(line.as_bytes());
+        }
+    }
+
+    fn flush(&self) {
+        if let Some((_, file)) = &self.file {
+            let _ = file.lock().unwrap().flush();
+        }
+    }
+}
+
+fn label(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warn => "warning",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
+
+// The current time in UTC as RFC 3339, e.g. 2024-03-01T12:34:56.789Z.
+pub fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = now.as_secs();
+    let (days, time) = (seconds / 86_400, seconds % 86_400);
+    let (year, month, day) = civil_date(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60,
+        now.subsec_millis()
+    )
+}
+
+// `seconds` since 1970 in UTC as RFC 3339 to the second, e.g. 2024-03-01T12:34:56Z.
+pub fn utc_time(seconds: u64) -> String {
+    let (days, time) = (seconds / 86_400, seconds % 86_400);
+    let (year, month, day) = civil_date(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60
+    )
+}
+
+// Seconds since 1970 of a time in UTC as `utc_time` and `timestamp` write it, any
+// fraction of a second dropped, or of midnight starting a date such as 2024-03-01.
+pub fn parse_utc_time(text: &str) -> Option<u64> {
+    let (date, time) = text.split_once('T').unwrap_or((text, "00:00:00Z"));
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let time = time.strip_suffix('Z')?;
+    let time = time.split_once('.').map_or(time, |(whole, _)| whole);
+    let mut time = time.splitn(3, ':').map(|part| part.parse::<u64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    Some(days * 86_400 + hour * 3_600 + minute * 60 + second)
+}
+
+// Days since 1970-01-01 to a civil date, as (year, month, day), by Howard Hinnant's
+// algorithm.
+pub fn civil_date(days: u64) -> (i64, i64, i64) {
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// The other way: days since 1970-01-01 of a civil date, negative before it.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
@@ -0,0 +1,244 @@
+// Paths past Windows' 260-character limit, and on network shares. The standard library
+// already opens and creates long paths itself, but calls made straight to Windows, such
+// as the free space check, need the extended-length form: `\\?\C:\...`, or
+// `\\?\UNC\server\share\...` for a share. That form is only for handing to the OS. It
+// turns up in the other direction too, from `canonicalize` or a deep current directory,
+// and is shown without the prefix, the way the user would type it. Other platforms
+// have nothing of the sort, and everything here returns the path as it was.
+//
+// Paths given on the command line or at a prompt are made absolute where they are taken,
+// by `absolute_path`, so the rest of the program, the journal and the messages all have
+// the one form of each.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::{is_s3_url, is_sftp_url};
+
+// Below this, plain paths work everywhere; it leaves room for an 8.3 file name in a
+// directory, as CreateDirectoryW wants.
+const MAX_PLAIN: usize = 248;
+
+const VERBATIM: &str = r"\\?\";
+const VERBATIM_UNC: &str = r"\\?\UNC\";
+
+// `path` in extended-length form when it is too long for the plain one. It is made
+// absolute first, which also resolves `.` and `..`, as Windows takes every byte of an
+// extended-length path literally.
+pub fn extended_path(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    if !cfg!(windows) || text.starts_with(VERBATIM) || text.len() < MAX_PLAIN && path.is_absolute()
+    {
+        return path.to_path_buf();
+    }
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_path_buf();
+    };
+    let text = absolute.to_string_lossy();
+    if text.len() < MAX_PLAIN {
+        return absolute;
+    }
+    // `\\.\` device paths are left alone
+    if let Some(share) = text
+        .strip_prefix(r"\\")
+        .filter(|rest| !rest.starts_with('.'))
+    {
+        return PathBuf::from(format!("{}{}", VERBATIM_UNC, share));
+    }
+    PathBuf::from(format!("{}{}", VERBATIM, text))
+}
+
+// `path` as it is shown: an extended-length one without its prefix, so `\\?\C:\x` reads
+// `C:\x` and `\\?\UNC\nas\share` reads `\\nas\share`.
+pub fn display_path(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    if !cfg!(windows) {
+        return path.to_path_buf();
+    }
+    if let Some(share) = text.strip_prefix(VERBATIM_UNC) {
+        return PathBuf::from(format!(r"\\{}", share));
+    }
+    match text.strip_prefix(VERBATIM) {
+        // Only a drive path reads the same without the prefix
+        Some(rest) if rest.as_bytes().get(1) == Some(&b':') => PathBuf::from(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+// `path` as it is kept once given: absolute, so a script that changes directory part way
+// through still reaches the same place, with every directory above the last name
+// resolved as `canonicalize` resolves it, and `.` and `..` taken out. The last name is
+// left as it was given, so a symbolic link stays one for the options that ask what to do
+// with one. Whatever doesn't exist yet, as a destination directory often doesn't, is
+// worked out lexically from the nearest directory above it that does. URLs and the empty
+// path are left alone, and a path that can't be made absolute is returned as it was.
+pub fn absolute_path(path: &Path) -> PathBuf {
+    if path.as_os_str().is_empty() || is_s3_url(path) || is_sftp_url(path) {
+        return path.to_path_buf();
+    }
+    let Ok(absolute) = std::path::absolute(path) else {
+        return path.to_path_buf();
+    };
+    let mut components: Vec<Component> = absolute.components().collect();
+    let last = match components.last() {
+        Some(Component::Normal(name)) => {
+            let name = *name;
+            components.pop();
+            Some(name)
+        }
+        _ => None,
+    };
+    // The longest start of it that exists, resolved, and what comes after that
+    let mut existing = components.len();
+    let mut resolved = loop {
+        if existing == 0 {
+            break PathBuf::new();
+        }
+        let start: PathBuf = components[..existing].iter().collect();
+        if let Ok(start) = fs::canonicalize(&start) {
+            break start;
+        }
+        existing -= 1;
+    };
+    for component in &components[existing..] {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            component => resolved.push(component),
+        }
+    }
+    if let Some(last) = last {
+        resolved.push(last);
+    }
+    display_path(&resolved)
+}
+
+// The directory above `path`, for browsing up. A share's root, `\\nas\share`, has none:
+// `\\nas` alone is no directory. An extended-length path goes up in its plain form,
+// which is what gets shown.
+pub fn parent_dir(path: &Path) -> Option<PathBuf> {
+    let path = display_path(path);
+    // `Path::parent` of `\\nas\share`, without a trailing separator, is empty rather
+    // than None
+    let mut components = path.components();
+    if matches!(components.next(), Some(Component::Prefix(_)))
+        && components.all(|component| component == Component::RootDir)
+    {
+        return None;
+    }
+    let parent = path.parent()?;
+    (!parent.as_os_str().is_empty()).then(|| parent.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A temporary directory with `a/b` in it, by its resolved name.
+    fn tree() -> (tempfile::TempDir, PathBuf) {
+        let temp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(temp.path().join("a").join("b")).unwrap();
+        let root = fs::canonicalize(temp.path()).unwrap();
+        (temp, display_path(&root))
+    }
+
+    #[test]
+    fn dots_are_taken_out() {
+        let (_temp, root) = tree();
+        let given = root.join("a").join(".").join("b").join("..").join("c");
+        assert_eq!(absolute_path(&given), root.join("a").join("c"));
+        let given = root.join(".").join("a").join("b").join(".");
+        assert_eq!(absolute_path(&given), root.join("a").join("b"));
+    }
+
+    #[test]
+    fn dots_are_taken_out_of_what_doesnt_exist_yet() {
+        let (_temp, root) = tree();
+        let given = root
+            .join("new")
+            .join("..")
+            .join("newer")
+            .join(".")
+            .join("x");
+        assert_eq!(absolute_path(&given), root.join("newer").join("x"));
+        let given = root.join("a").join("new").join("..").join("..").join("y");
+        assert_eq!(absolute_path(&given), root.join("y"));
+    }
+
+    #[test]
+    fn trailing_separators_are_dropped() {
+        let (_temp, root) = tree();
+        let mut given = root.join("a").join("b").into_os_string();
+        given.push(std::path::MAIN_SEPARATOR_STR);
+        given.push(std::path::MAIN_SEPARATOR_STR);
+        assert_eq!(absolute_path(Path::new(&given)), root.join("a").join("b"));
+    }
+
+    #[test]
+    fn relative_paths_resolve_against_the_current_directory() {
+        let current = display_path(&fs::canonicalize(".").unwrap());
+        assert_eq!(absolute_path(Path::new(".")), current);
+        assert_eq!(
+            absolute_path(Path::new("not-here")),
+            current.join("not-here")
+        );
+        let parent = current.parent().unwrap();
+        assert_eq!(absolute_path(Path::new("..")), parent);
+    }
+
+    #[test]
+    fn urls_and_the_empty_path_are_left_alone() {
+        for given in ["", "s3://bucket/a/../b", "sftp://host/a/./b"] {
+            assert_eq!(absolute_path(Path::new(given)), Path::new(given));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_last_name_stays_a_link() {
+        let (_temp, root) = tree();
+        std::os::unix::fs::symlink(root.join("a"), root.join("link")).unwrap();
+        assert_eq!(absolute_path(&root.join("link")), root.join("link"));
+        // A link above the last name is resolved
+        let given = root.join("link").join("b");
+        assert_eq!(absolute_path(&given), root.join("a").join("b"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dot_dot_stops_at_the_root() {
+        assert_eq!(absolute_path(Path::new("/../..")), Path::new("/"));
+        assert_eq!(absolute_path(Path::new("/../tmp/./")), Path::new("/tmp"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn drive_relative_paths_resolve_on_their_drive() {
+        let current = display_path(&fs::canonicalize(".").unwrap());
+        let Some(std::path::Component::Prefix(prefix)) = current.components().next() else {
+            panic!("{} has no drive", current.display());
+        };
+        let drive = prefix.as_os_str().to_string_lossy().into_owned();
+        // `C:name` is in the current directory of drive C:, which is this one
+        let given = format!("{}not-here", drive);
+        assert_eq!(absolute_path(Path::new(&given)), current.join("not-here"));
+        // `\name` is at the root of the current drive
+        let root = PathBuf::from(format!("{}\\", drive));
+        assert_eq!(
+            absolute_path(Path::new(r"\not-here")),
+            root.join("not-here")
+        );
+        let given = format!(r"{}\not-here\..\.\other\", drive);
+        assert_eq!(absolute_path(Path::new(&given)), root.join("other"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn forward_slashes_and_dots_on_windows() {
+        let (_temp, root) = tree();
+        let given = format!("{}/a/./b/../c/", root.display());
+        assert_eq!(absolute_path(Path::new(&given)), root.join("a").join("c"));
+    }
+}
@@ -1,203 +1,3198 @@
-use std::collections::BTreeMap;
+mod bench;
+mod catalog;
+mod commands;
+mod completions;
+mod explore;
+mod history;
+mod interrupt;
+mod journal;
+mod logging;
+#[cfg(all(feature = "mount", unix))]
+mod mount;
+mod notify;
+mod outcome;
+mod profile;
+mod progress;
+mod prompt;
+mod selftest;
+#[cfg(feature = "serve")]
+mod serve;
+mod stress;
+mod style;
+mod template;
+mod trash;
+mod tui;
+mod watch;
+
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
+use std::ffi::{OsStr, OsString};
 use std::fs::{self, File};
-use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::io::{self, IsTerminal, Read};
 use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Once, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use clap::builder::{PathBufValueParser, TypedValueParser};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use walkdir::WalkDir;
+
+use history::History;
+use profile::Profile;
+use progress::{JsonProgress, SplitProgress, Timing};
+use prompt::{MenuEntry, confirm, list_prompt, menu_prompt, path_prompt, preference, text_prompt};
+#[cfg(feature = "sftp")]
+use reconstruct_large_file::SftpOptions;
+use reconstruct_large_file::lock;
+use reconstruct_large_file::manifest::{Compression, HashAlgorithm, hash_file};
+use reconstruct_large_file::retry::{RetryPolicy, Transient};
+use reconstruct_large_file::size::{format_size, parse_size};
+use reconstruct_large_file::store;
+use reconstruct_large_file::symlinks::SymlinkPolicy;
+use reconstruct_large_file::{
+    CancelToken, ChangeKind, ChunkHook, ChunkKey, ChunkSet, Cipher, Compat, DEFAULT_CHUNK_SIZE,
+    Doubt, Encryption, FileChange, ForeignNaming, ForeignSet, MANIFEST_NAME, MAX_MODE, Manifest,
+    PlannedVolume, ReconstructOptions, ReconstructPlan, ReconstructReport, S3Options, Span,
+    SplitOptions, SplitOptionsBuilder, SplitterError, absolute_path, apply_remap, cache,
+    check_destination, check_timestamps, chunk_health, containing_set, default_output_name,
+    detect_foreign, display_path, find_remap, free_space, import, is_s3_url, is_sftp_url,
+    is_stream, list_directory, natural_cmp, parent_dir, pipeline, plan_reconstruct, plan_span,
+    reconstruct_foreign, same_file_system, split_file,
+};
+use template::Template;
+
+// A few threads keep a fast disk busy; more mostly add memory use.
+fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get().min(4))
+}
+
+// The global flags on how splits and reconstructions copy, lock and treat links,
+// which go into the options of each. Set once by `main`.
+#[derive(Default)]
+struct Globals {
+    // None to try 1MiB and the default and go on with the faster
+    buffer_size: Option<usize>,
+    max_memory: Option<u64>,
+    direct_io: bool,
+    lock_max_age: Option<Duration>,
+    symlinks: SymlinkPolicy,
+    // Where outputs are written until complete, beside them when None
+    temp_dir: Option<PathBuf>,
+    accept_modified: bool,
+}
+
+static GLOBALS: OnceLock<Globals> = OnceLock::new();
+
+fn globals() -> &'static Globals {
+    GLOBALS.get_or_init(Globals::default)
+}
+
+impl Globals {
+    // The buffer size for `threads` threads copying, within `--max-memory`.
+    fn buffer_size(&self, threads: usize) -> usize {
+        let size = self.buffer_size.unwrap_or(pipeline::DEFAULT_BUFFER_SIZE);
+        pipeline::fit(threads, size, self.max_memory).1
+    }
+
+    fn lock_max_age(&self) -> Duration {
+        self.lock_max_age.unwrap_or(lock::DEFAULT_MAX_AGE)
+    }
+}
+
+// A split on the threads `thread_count` gives for `threads`, with the global flags.
+fn split_options(builder: SplitOptionsBuilder, threads: Option<u64>) -> SplitOptionsBuilder {
+    let globals = globals();
+    let threads = thread_count(threads);
+    builder
+        .threads(threads)
+        .buffer_size(globals.buffer_size(threads))
+        .adaptive_buffers(globals.buffer_size.is_none())
+        .max_memory(globals.max_memory)
+        .direct_io(globals.direct_io)
+        .lock_max_age(globals.lock_max_age())
+        .symlinks(globals.symlinks)
+}
+
+// As `split_options`, for reconstructing `directory`.
+fn reconstruct_options(directory: &Path, threads: Option<u64>) -> ReconstructOptions {
+    let globals = globals();
+    let threads = thread_count(threads);
+    ReconstructOptions {
+        threads,
+        buffer_size: globals.buffer_size(threads),
+        adaptive_buffers: globals.buffer_size.is_none(),
+        max_memory: globals.max_memory,
+        direct_io: globals.direct_io,
+        lock_max_age: globals.lock_max_age(),
+        symlinks: globals.symlinks,
+        temp_dir: globals.temp_dir.clone(),
+        accept_modified: globals.accept_modified,
+        ..ReconstructOptions::new(directory)
+    }
+}
+
+// A key for a new encrypted set, or one `operation` encrypts again, to the age
+// recipients given, from the key file at `key_file`, or else from a passphrase asked for
+// twice, and how it is derived from that again. Exits when there is none to be had.
+fn new_key(
+    operation: &str,
+    key_file: Option<&Path>,
+    age_recipients: &[String],
+    insecure: bool,
+) -> (Encryption, ChunkKey) {
+    let fail = |e: SplitterError| -> ! {
+        eprintln!("Error during {}: {}", operation, e);
+        exit(exit_code(&e));
+    };
+    if !age_recipients.is_empty() {
+        let key = ChunkKey::age_recipients(age_recipients).unwrap_or_else(|e| fail(e));
+        return (Encryption::with_age(), key);
+    }
+    if let Some(path) = key_file {
+        let key = ChunkKey::read_file(path, insecure).unwrap_or_else(|e| fail(e));
+        return (Encryption::with_key_file(), key);
+    }
+    let derived = prompt::new_passphrase("Passphrase")
+        .and_then(|passphrase| Ok((passphrase, Encryption::with_passphrase()?)));
+    let (passphrase, encryption) = derived.unwrap_or_else(|e| {
+        eprintln!("Error during {}: {}", operation, e);
+        exit(1);
+    });
+    let key = ChunkKey::derive(&passphrase, &encryption).unwrap_or_else(|e| fail(e));
+    (encryption, key)
+}
+
+// The key the chunks in `directory` open with, when its info.json says they are
+// encrypted: the one in `key_file` or the identities in `age_identity`, or else from
+// the passphrase, asked for then, or for a set encrypted under a key file or to age
+// recipients, from the file asked for.
+fn set_key(
+    directory: &Path,
+    key_file: Option<&Path>,
+    age_identity: Option<&Path>,
+    insecure: bool,
+) -> Result<Option<ChunkKey>, SplitterError> {
+    let manifest = match directory.is_dir() {
+        true => Manifest::load(directory, globals().accept_modified)?,
+        false => None,
+    };
+    let Some(encryption) = manifest.and_then(|manifest| manifest.encryption) else {
+        return Ok(None);
+    };
+    let asking = |source| SplitterError::Io {
+        path: directory.to_path_buf(),
+        source,
+        action: Some("asking for the key to"),
+    };
+    let age = encryption.cipher == Cipher::Age;
+    let key = match (key_file, age_identity, &encryption.kdf) {
+        (_, Some(path), _) => ChunkKey::read_age_identity(path)?,
+        (Some(path), None, _) => ChunkKey::read_file(path, insecure)?,
+        (None, None, _) if age => {
+            let path = path_prompt("age identity file", None).map_err(asking)?;
+            ChunkKey::read_age_identity(&path)?
+        }
+        (None, None, Some(_)) => {
+            let passphrase = prompt::passphrase_prompt("Passphrase").map_err(asking)?;
+            ChunkKey::derive(&passphrase, &encryption)?
+        }
+        (None, None, None) => {
+            let path = path_prompt("Key file", None).map_err(asking)?;
+            ChunkKey::read_file(&path, insecure)?
+        }
+    };
+    key.check(&encryption, directory)?;
+    Ok(Some(key))
+}
+
+// The size of the chunk files info.json lists in `directory`, and how many there are,
+// for a status line over them; nothing when they can't be found.
+fn chunk_files(directory: &Path) -> (u64, u64) {
+    let Ok(Some(manifest)) = Manifest::load(directory, globals().accept_modified) else {
+        return (0, 0);
+    };
+    let files = manifest
+        .chunks
+        .iter()
+        .filter(|entry| entry.same_as.is_none());
+    files.fold((0, 0), |(total, count), entry| {
+        let size = fs::metadata(directory.join(&entry.name)).map_or(0, |m| m.len());
+        (total + size, count + 1)
+    })
+}
+
+// What the browsers show for a set whose details are sealed under its key.
+pub(crate) const CONCEALED: &str = "encrypted metadata — passphrase required for details";
+
+// Whether the set in `directory` keeps its details sealed under its key, as split with
+// --encrypt-metadata.
+pub(crate) fn concealed(directory: &Path) -> bool {
+    Manifest::load(directory, globals().accept_modified)
+        .ok()
+        .flatten()
+        .is_some_and(|manifest| manifest.concealed())
+}
 
-fn list_prompt(prompt: &str, options: &BTreeMap<String, &str>) -> String {
+// The name the set in `directory` joins into by default, from its details opened with
+// `key` when they are sealed under it.
+fn recorded_name(directory: &Path, key: Option<&ChunkKey>) -> Result<String, SplitterError> {
+    let accept_modified = globals().accept_modified;
+    match Manifest::load_revealed(directory, accept_modified, key)? {
+        Some(manifest) if key.is_some() => manifest.output_name(),
+        _ => default_output_name(directory, accept_modified),
+    }
+}
+
+// The threads to copy with for `--threads` (the default without it), fewer with
+// `--max-memory` when their buffers wouldn't fit in it. What was changed to fit is said
+// once.
+fn thread_count(threads: Option<u64>) -> usize {
+    static TOLD: Once = Once::new();
+    let globals = globals();
+    let wanted = threads.map_or_else(default_threads, |t| t as usize);
+    let size = globals.buffer_size.unwrap_or(pipeline::DEFAULT_BUFFER_SIZE);
+    let (threads, buffer_size) = pipeline::fit(wanted, size, globals.max_memory);
+    if (threads, buffer_size) != (wanted, size) {
+        TOLD.call_once(|| {
+            eprintln!(
+                "Using {} {} with {} buffers to stay within --max-memory {}.",
+                threads,
+                match threads {
+                    1 => "thread",
+                    _ => "threads",
+                },
+                format_size(buffer_size as u64),
+                format_size(globals.max_memory.unwrap_or(0))
+            )
+        });
+    }
+    threads
+}
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Split large files into chunks and reconstruct them",
+    long_about = "Split large files into chunks and reconstruct them.\n\n\
+                  Run without a subcommand to use the interactive menus.\n\n\
+                  Exit status: 0 on success, 1 for I/O failures, 2 for usage errors, \
+                  3 when the destination is not empty or not a directory, or the chunk directory is in use by \
+                  another run, 4 when chunks are missing, numbered twice, damaged \
+                  (for verify) or lost beyond what the parity can rebuild, \
+                  5 when info.json is corrupt, describes a different split or names files \
+                  outside its directory, 6 when a file \
+                  changed size mid-copy (or at all, for split --strict) or a chunk \
+                  decompressed to the wrong size or failed to decrypt, \
+                  7 when the menus have no input to read answers from or it runs out, \
+                  130 when interrupted with Ctrl+C. \
+                  The menus exit with the worst status of anything run from them that failed, \
+                  whether left by Exit or otherwise, and 0 only when nothing did: \
+                  4, 5 and 6 count over 1, then 3, 2, 7 and last 130."
+)]
+struct Cli {
+    /// Disable colored output (also honors the NO_COLOR environment variable)
+    #[arg(long, global = true)]
+    no_color: bool,
+    /// Don't record recently used files and directories
+    #[arg(long, global = true)]
+    no_history: bool,
+    /// Don't add what was done to the journal `history` shows (also honors the
+    /// FILE_SPLITTER_NO_JOURNAL environment variable)
+    #[arg(long, global = true)]
+    no_journal: bool,
+    /// Say when a split, reconstruction, verify or repair is over, with a desktop
+    /// notification or else a terminal bell (also `"notify": true` in config.json)
+    #[arg(long, global = true)]
+    notify: bool,
+    /// Keep the data a split or reconstruction copies out of the operating system's
+    /// file cache
+    #[arg(long, global = true)]
+    direct_io: bool,
+    /// Size of the buffers a split or reconstruction copies with, e.g. 256K or 16MiB
+    /// [default: 1MiB or 4MiB, whichever copies faster at the start of the run]
+    #[arg(long, global = true, value_parser = parse_buffer_size)]
+    buffer_size: Option<usize>,
+    /// Keep the copy buffers of all threads within this much memory, e.g. 64M on a
+    /// machine with little RAM, using fewer threads and then smaller buffers to fit
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_max_memory)]
+    max_memory: Option<u64>,
+    /// Log more of what is going on to stderr: -v for milestones, -vv for every file
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Also append timestamped log lines to this file
+    #[arg(long, global = true, value_parser = path_arg())]
+    log_file: Option<PathBuf>,
+    /// Follow symbolic links named like chunks in a chunk directory, which are skipped
+    /// by default, as well as a file to split that is one
+    #[arg(long, global = true, overrides_with = "no_follow_symlinks")]
+    follow_symlinks: bool,
+    /// Follow no symbolic links: refuse a file to split that is one, and skip those
+    /// named like chunks
+    #[arg(long, global = true, overrides_with = "follow_symlinks")]
+    no_follow_symlinks: bool,
+    /// Offer to break the lock on the chunk directory that a run which died or hung
+    /// left behind (.fsr.lock), once its process is gone or it is older than
+    /// --lock-max-age
+    #[arg(long, global = true)]
+    steal_lock: bool,
+    /// How long a lock on a chunk directory is honored for, e.g. 90m or 12h, for a run
+    /// on another host that can't be looked for [default: 24h]
+    #[arg(long, global = true, value_name = "DURATION", value_parser = parse_duration)]
+    lock_max_age: Option<Duration>,
+    /// Go on with an info.json that was changed after it was written, as by an edit by
+    /// hand, rather than refuse it; `reseal` makes such changes stick
+    #[arg(long, global = true)]
+    accept_modified_metadata: bool,
+    /// Answer yes to every question that only asks whether to go ahead, and take the
+    /// default of those asking how; anything that needs typing in is still asked for,
+    /// and is an error without a terminal
+    #[arg(short, long, global = true)]
+    yes: bool,
+    /// Write the file a reconstruction builds, the tar pack writes and each chunk
+    /// rechunk writes into this directory until it is complete, such as on a drive with
+    /// more room than the output's [default: beside the output] (also "temp_dir" in
+    /// config.json)
+    #[arg(long, global = true, value_name = "DIR", value_parser = path_arg())]
+    temp_dir: Option<PathBuf>,
+    /// Use the full-screen terminal interface instead of the prompts
+    #[arg(long)]
+    tui: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Split a file into a directory of chunks
+    Split(commands::split::SplitArgs),
+    /// Reconstruct a file from a directory of chunks
+    Reconstruct(commands::reconstruct::ReconstructArgs),
+    /// Put back the file the last reconstruction moved to the trash
+    #[command(
+        long_about = "Put back the file the last reconstruction moved to the trash.\n\n\
+        A reconstruction moves a file already at the output to the desktop's trash, or \
+        without a desktop, as over ssh, into .fsr-trash/ beside it, unless given \
+        --no-trash, and the journal records the move (see history). This puts back the \
+        latest file moved that hasn't been put back yet. Whatever is there now, as the \
+        reconstructed file is, goes to the trash in its place, so running this again \
+        undoes it. On macOS a file in the Trash is put back from the Finder instead. \
+        Nothing is removed from the desktop's trash; empty it once it isn't wanted. \
+        empty-trash removes what is in .fsr-trash/."
+    )]
+    UndoLast,
+    /// Remove the files reconstructions moved into .fsr-trash/ beside their output
+    #[command(
+        long_about = "Remove the files reconstructions moved into .fsr-trash/ beside their \
+        output.\n\n\
+        Without a desktop trash, as over ssh, a reconstruction moves a file already at \
+        its output into .fsr-trash/ in the same directory, for undo-last to put back, and \
+        nothing else ever removes it. This removes everything in the .fsr-trash/ of each \
+        directory given, or with --older-than only what was moved there longer ago than \
+        that, going by the journal, or where it has no record of a file, by when the \
+        file's entry last changed (on Windows such a file is kept). The .fsr-trash/ \
+        directory goes too once empty. A file removed can no longer be put back."
+    )]
+    EmptyTrash(commands::empty_trash::EmptyTrashArgs),
+    /// Check a directory's chunks, and say whether whatever is lost can be rebuilt
+    Verify(commands::verify::VerifyArgs),
+    /// Work out what is wrong with a chunk directory, and say what to do about it
+    #[command(
+        long_about = "Work out what is wrong with a chunk directory, and say what to do \
+        about it.\n\n\
+        Checks the metadata, how the chunks are named, which are missing, truncated or \
+        damaged, and what else is in the directory, such as pieces another tool split a file \
+        into or an earlier reconstruction, and lists what it found, most pressing first, each \
+        with what to run next. Exits with 4 when the set can't be reconstructed as it is."
+    )]
+    Doctor(commands::doctor::DoctorArgs),
+    /// Show what one chunk holds, with which bytes of the original file those are, through
+    /// $PAGER on a terminal
+    #[command(
+        long_about = "Show what one chunk holds, with which bytes of the original file \
+        those are, through $PAGER on a terminal.\n\n\
+        The chunk is decoded as reconstruct would decode it, compressed or armored, and \
+        shown as text when the start of it looks like text, or else as a hex dump at the \
+        offsets of the original file. Without $PAGER, less is run, or failing that the \
+        chunk is shown a screen at a time. Nothing decoded is written to a file."
+    )]
+    Explore(commands::explore::ExploreArgs),
+    /// List which byte ranges of the original file the chunks there hold and which are
+    /// missing
+    #[command(
+        long_about = "List which byte ranges of the original file the chunks there hold \
+        and which are missing.\n\n\
+        Goes by the sizes info.json records and which chunk files are there, as long as \
+        recorded when stored as they are, without reading them through; verify checks what \
+        they hold. The ranges are in offsets of the original file, so the gaps can be \
+        fetched again from wherever it came from. Exits with 4 when anything is missing."
+    )]
+    Coverage(commands::coverage::CoverageArgs),
+    /// Record where chunks stand in a transfer done by something else, such as a script
+    /// uploading them, in transfer_state.json beside them
+    #[command(
+        long_about = "Record where chunks stand in a transfer done by something else, such \
+        as a script uploading them one by one, in transfer_state.json beside them.\n\n\
+        Each chunk is pending until marked otherwise, and is marked with the time. The \
+        states are never put in info.json, which a transfer leaves as it is, and several \
+        uploaders can mark chunks of the same set at once. status shows where the chunks \
+        stand and next which are still pending; split --track-transfers marks them as its \
+        chunk command uploads them."
+    )]
+    Mark(commands::mark::MarkArgs),
+    /// Show how many chunks of a directory are uploaded, pending and failed, from
+    /// transfer_state.json, and which are left
+    Status(commands::status::StatusArgs),
+    /// Print the paths of the next chunks still pending in transfer_state.json, one per
+    /// line, and nothing once none are
+    Next(commands::next::NextArgs),
+    /// Rebuild missing or damaged chunks and parity files of a directory from its parity
+    /// and any .par2 files in it
+    Repair(commands::repair::RepairArgs),
+    /// Replace missing or damaged chunks of a directory with intact ones from other copies
+    Heal(commands::heal::HealArgs),
+    /// Split a directory's chunks again into chunks of another size, without
+    /// reconstructing the file on disk; the directory is verified first
+    Rechunk(commands::rechunk::RechunkArgs),
+    /// Seal a directory's info.json again after changes to it that were meant, so they
+    /// are no longer refused as an accidental edit or damage
+    Reseal(commands::reseal::ResealArgs),
+    /// Pack a directory's chunks into one tar, the same bytes every time, for moving them
+    /// as a single file; the other commands read the tar as they would the directory
+    Pack(commands::pack::PackArgs),
+    /// Unpack the chunks in a tar made by `pack`, or any tar or zip of a chunk directory
+    Unpack(commands::unpack::UnpackArgs),
+    /// Take over the pieces another tool split a file into (GNU split, HJSplit, 7-Zip
+    /// volumes), writing an info.json that makes them a chunk set, or join them now
+    Import(commands::import::ImportArgs),
+    /// Write a directory's chunk list with sizes and hashes to one small file, to send
+    /// ahead and check the chunks against with verify --manifest when they arrive
+    ExportManifest(commands::export_manifest::ExportManifestArgs),
+    /// Write a new random key for split --key-file to a file only you can read, which
+    /// reconstructing the chunks then needs (needs a build with the encrypt feature)
+    Keygen(commands::keygen::KeygenArgs),
+    /// Encrypt a set's chunks again under a new passphrase, key file or age recipients,
+    /// one chunk at a time, once the current key is found to open every one
+    Rekey(commands::rekey::RekeyArgs),
+    /// Show the splits, reconstructions, rechunks, verifies and repairs done, from the
+    /// journal
+    History(commands::history::HistoryArgs),
+    /// Total up every chunk set under a directory: sizes, chunks, hashes and when each
+    /// was last verified, from the journal
+    Stats(commands::stats::StatsArgs),
+    /// Print one line of JSON for every chunk set under a directory, for a backup
+    /// catalog to ingest: an id from its content, its file, size, chunks, hashing and
+    /// storage, when it was created and modified, and its last verify from the journal
+    Catalog(commands::catalog::CatalogArgs),
+    /// List the split profiles in the config file, or show what one holds
+    Profile(commands::profile::ProfileArgs),
+    /// Split every file that turns up in a directory, each into a directory of chunks of
+    /// its own, until Ctrl+C
+    Watch(commands::watch::WatchArgs),
+    /// Measure split, reconstruct and verify throughput on a directory's storage
+    Bench(commands::bench::BenchArgs),
+    /// Check this build on a file system: split a generated file with several
+    /// combinations of options, reconstruct each and compare it with the original
+    SelfTest(commands::self_test::SelfTestArgs),
+    /// Split, verify and reconstruct generated files again and again, with sizes and
+    /// options drawn at random, for leaving running against a disk suspected of losing
+    /// data
+    #[command(
+        long_about = "Split, verify and reconstruct generated files again and again, with \
+        sizes and options drawn at random, for leaving running against a disk suspected of \
+        losing data.\n\n\
+        Each iteration draws a size, from an empty file, one of exactly a chunk or a byte \
+        either side of one up to --max-size, and a combination of chunk size, threads, \
+        hashing, deduplication, compression, parity and shard directories, then compares \
+        the reconstruction with the original byte for byte. An iteration that fails is \
+        left in its directory under --dir, with its options in case.json, and printed \
+        with the --seed that draws it again on its own; the run goes on with the next. \
+        Exits with 1 when any iteration failed."
+    )]
+    Stress(commands::stress::StressArgs),
+    /// Serve a directory's chunks over HTTP, read-only, for fetching them from another
+    /// machine
+    #[cfg(feature = "serve")]
+    Serve(commands::serve::ServeArgs),
+    /// Show every chunk set under a directory as a file at a mount point, read-only,
+    /// read from the chunks as programs read it, until Ctrl+C (needs a build with the
+    /// mount feature, on Linux or macOS)
+    #[cfg(all(feature = "mount", unix))]
+    Mount(commands::mount::MountArgs),
+    /// Print a completion script for bash, zsh, fish, PowerShell or Elvish
+    ///
+    /// For bash, add `source <(reconstruct_large_file completions bash)` to ~/.bashrc;
+    /// the others are loaded the same way, or saved where the shell looks for them.
+    Completions(commands::completions::CompletionsArgs),
+    /// Directories of chunks starting with PREFIX, one per line, for the completion
+    /// scripts
+    #[command(name = completions::CHUNK_DIRS_COMMAND, hide = true)]
+    ChunkDirs(commands::chunk_dirs::ChunkDirsArgs),
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+// Home directory of another user, looked up in /etc/passwd where it exists and
+// otherwise guessed as a sibling of our own home directory.
+fn user_home_dir(user: &str) -> Option<PathBuf> {
+    if let Ok(passwd) = fs::read_to_string("/etc/passwd") {
+        for line in passwd.lines() {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() >= 6 && fields[0] == user {
+                return Some(PathBuf::from(fields[5]));
+            }
+        }
+    }
+    let home = home_dir()?;
+    let candidate = home.parent()?.join(user);
+    candidate.is_dir().then_some(candidate)
+}
+
+// Turn a path as typed (or dragged) into the terminal into a usable path: trims
+// surrounding whitespace including `\r`, strips matching quotes, undoes shell-style
+// backslash escapes on Unix and expands a leading `~` or `~user`.
+fn normalize_path_input(input: &str) -> PathBuf {
+    let trimmed = input.trim();
+    let unquoted = [('"', '"'), ('\'', '\'')]
+        .iter()
+        .find_map(|&(open, close)| {
+            trimmed
+                .strip_prefix(open)
+                .and_then(|rest| rest.strip_suffix(close))
+        });
+
+    let path = match unquoted {
+        Some(inner) => inner.to_string(),
+        None if cfg!(windows) => trimmed.to_string(),
+        None => {
+            let mut unescaped = String::with_capacity(trimmed.len());
+            let mut chars = trimmed.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => unescaped.extend(chars.next()),
+                    _ => unescaped.push(c),
+                }
+            }
+            unescaped
+        }
+    };
+
+    expand_home(&path)
+}
+
+// Expand a leading `~` or `~user`. Relative paths are left as they are and therefore
+// resolve against the current working directory.
+fn expand_home(path: &str) -> PathBuf {
+    let Some(rest) = path.strip_prefix('~') else {
+        return PathBuf::from(path);
+    };
+    let split = rest.find(['/', '\\']).unwrap_or(rest.len());
+    let (user, remainder) = rest.split_at(split);
+    let remainder = remainder.trim_start_matches(['/', '\\']);
+    let home = if user.is_empty() {
+        home_dir()
+    } else {
+        user_home_dir(user)
+    };
+    match home {
+        Some(home) if remainder.is_empty() => home,
+        Some(home) => home.join(remainder),
+        None => PathBuf::from(path),
+    }
+}
+
+// `label` for a menu entry, numbered when another entry already has it, as two names
+// that aren't UTF-8 can read the same once shown with replacement characters. What is
+// returned is added to `taken`.
+fn unique_label(taken: &mut BTreeSet<String>, label: String) -> String {
+    let label = match taken.contains(&label) {
+        false => label,
+        true => (2..)
+            .map(|n| format!("{} ({})", label, n))
+            .find(|numbered| !taken.contains(numbered))
+            .unwrap(),
+    };
+    taken.insert(label.clone());
+    label
+}
+
+// Let the user pick a file by navigating from `directory`. Returns None if they go back.
+fn pick_file(mut directory: PathBuf) -> io::Result<Option<PathBuf>> {
+    let mut previous: Option<PathBuf> = None;
     loop {
-        println!("{}", prompt);
-        for (i, (option, option_type)) in options.iter().enumerate() {
-            match *option_type {
-                "directory" => println!("{}. {}", i + 1, option),
-                "chunk" => println!("\x1b[92m{}. {}\x1b[0m", i + 1, option),
-                "action" => println!("\x1b[94m{}. {}\x1b[0m", i + 1, option),
-                "exit" => println!("\x1b[91m{}. {}\x1b[0m", i + 1, option),
-                _ => println!("{}. {}", i + 1, option),
+        // None is Back
+        let mut entries = Vec::new();
+        let mut labels = BTreeSet::new();
+        match fs::read_dir(&directory) {
+            Ok(listing) => {
+                for entry in listing.flatten() {
+                    let path = entry.path();
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    let (label, kind) = if path.is_dir() {
+                        (format!("{}/", name), "directory")
+                    } else if path.is_file() {
+                        (name, "file")
+                    } else {
+                        continue;
+                    };
+                    entries.push(MenuEntry {
+                        label: unique_label(&mut labels, label),
+                        kind,
+                        choice: Some(path),
+                    });
+                }
+            }
+            Err(e) => {
+                println!("cannot open {}: {}", directory.display(), e);
+                match previous.take() {
+                    Some(fallback) => {
+                        directory = fallback;
+                        continue;
+                    }
+                    None => return Ok(None),
+                }
+            }
+        }
+        if let Some(parent) = parent_dir(&directory) {
+            entries.push(MenuEntry {
+                label: "../".to_string(),
+                kind: "directory",
+                choice: Some(parent),
+            });
+        }
+        entries.push(MenuEntry {
+            label: "Back".to_string(),
+            kind: "back",
+            choice: None,
+        });
+
+        println!("\n>>>\t{}", directory.display());
+        match menu_prompt("Select the file to split:", entries)? {
+            None => return Ok(None),
+            Some(path) if path.is_dir() => {
+                previous = Some(std::mem::replace(&mut directory, path));
             }
+            Some(path) => return Ok(Some(path)),
+        }
+    }
+}
+
+// Copying keeps two buffers per thread in flight, so a buffer size that doesn't fit
+// in memory twice over is refused rather than left to fail mid-operation.
+fn parse_buffer_size(input: &str) -> Result<usize, String> {
+    let size = parse_size(input)?;
+    if size == 0 {
+        return Err("buffer size must be greater than zero".to_string());
+    }
+    let size = usize::try_from(size).map_err(|_| "buffer size is too large".to_string())?;
+    if let Some(available) = available_memory()
+        && size as u64 * 2 > available
+    {
+        return Err(format!(
+            "buffer size is larger than the available memory ({})",
+            format_size(available)
+        ));
+    }
+    Ok(size)
+}
+
+// A number of seconds, or of milliseconds, minutes, hours or days, e.g. 2s, 1.5s, 500ms
+// or 30d; a bare number is seconds.
+fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("'{}' is not a duration such as 2s or 500ms", input))?;
+    let seconds = match unit.trim() {
+        "" | "s" => number,
+        "ms" => number / 1000.0,
+        "m" | "min" => number * 60.0,
+        "h" => number * 3600.0,
+        "d" => number * 86400.0,
+        unit => return Err(format!("unknown unit '{}'; use ms, s, m, h or d", unit)),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("'{}' is out of range", input))
+}
+
+fn parse_max_memory(input: &str) -> Result<u64, String> {
+    let size = parse_size(input)?;
+    let least = (pipeline::BUFFERS_PER_THREAD * pipeline::MIN_BUFFER_SIZE) as u64;
+    if size < least {
+        return Err(format!(
+            "must be at least {}, what one thread copying needs",
+            format_size(least)
+        ));
+    }
+    Ok(size)
+}
+
+// Memory the system could hand out right now, where that is easy to find out.
+fn available_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+const SIZE_SCAN_LIMIT: usize = 1000;
+const ENTRY_COUNT_LIMIT: usize = 10_000;
+
+// Paths as clap takes them, made absolute there, so nothing after depends on the
+// current directory staying put; `-`, for standard input or output, is left as it is.
+fn path_arg() -> impl TypedValueParser<Value = PathBuf> {
+    PathBufValueParser::new().map(|path| match path.as_os_str() == "-" {
+        true => path,
+        false => absolute_path(&path),
+    })
+}
+
+// Parse a mode in octal, such as `640`, `0640` or `0o640`.
+fn parse_mode(input: &str) -> Result<u32, String> {
+    let digits = input.strip_prefix("0o").unwrap_or(input);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= MAX_MODE && !digits.starts_with('+') => Ok(mode),
+        _ => Err(format!(
+            "'{}' is not an octal mode such as 640 or 0750, of at most 7777",
+            input
+        )),
+    }
+}
+
+// Parse a codec with an optional level, such as `gzip`, `gzip:9` or `zstd:19`, or
+// `none`.
+fn parse_compression(input: &str) -> Result<(Compression, u32), String> {
+    let (name, level) = match input.split_once(':') {
+        Some((name, level)) => (name, Some(level)),
+        None => (input, None),
+    };
+    let compression = match name.trim().to_ascii_lowercase().as_str() {
+        "none" if level.is_none() => return Ok((Compression::None, 0)),
+        "gzip" | "gz" => Compression::Gzip,
+        "zstd" | "zst" if cfg!(feature = "zstd") => Compression::ZSTD,
+        "zstd" | "zst" => return Err("zstd needs a build with the zstd feature".to_string()),
+        _ => return Err(format!("unknown compression \"{}\"", name)),
+    };
+    let level = match level {
+        Some(level) => level
+            .trim()
+            .parse()
+            .ok()
+            .filter(|level| compression.levels().contains(level))
+            .ok_or_else(|| {
+                let levels = compression.levels();
+                format!(
+                    "{} levels run from {} to {}",
+                    name,
+                    levels.start(),
+                    levels.end()
+                )
+            })?,
+        None => compression.default_level(),
+    };
+    Ok((compression.at_level(level), level))
+}
+
+// `compress` with zstd's long window when `long`, which only zstd has.
+fn long_window(compress: Option<(Compression, u32)>, long: bool) -> Option<(Compression, u32)> {
+    match compress {
+        Some((Compression::Zstd { level, .. }, at)) if long => {
+            Some((Compression::Zstd { level, long }, at))
+        }
+        Some(_) if long => {
+            eprintln!("error: --long is only for --compress zstd");
+            exit(2);
+        }
+        compress => compress,
+    }
+}
+
+// Dot-directories, and on Windows anything with the hidden attribute.
+fn is_hidden(path: &Path) -> bool {
+    if path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+    {
+        return true;
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        if let Ok(metadata) = fs::metadata(path) {
+            return metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0;
+        }
+    }
+    false
+}
+
+// Summarize the immediate contents of a directory for the browser. The scan is
+// bounded so that huge directories (or slow network mounts) don't stall the menu.
+fn describe_directory(path: &Path) -> String {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return "unreadable".to_string(),
+    };
+
+    let mut count = 0;
+    let mut size = 0u64;
+    for entry in entries.flatten() {
+        count += 1;
+        if count > ENTRY_COUNT_LIMIT {
+            return format!("{}+ entries, …", ENTRY_COUNT_LIMIT);
+        }
+        if count <= SIZE_SCAN_LIMIT
+            && let Ok(metadata) = entry.metadata()
+            && metadata.is_file()
+        {
+            size += metadata.len();
+        }
+    }
+
+    let noun = if count == 1 { "entry" } else { "entries" };
+    if count > SIZE_SCAN_LIMIT {
+        format!("{} {}, …", count, noun)
+    } else {
+        format!("{} {}, {}", count, noun, format_size(size))
+    }
+}
+
+// Why `path` can't be split, if it can't. It has to be a file, or something read front to
+// back such as a named pipe, and have a name of its own for info.json to record.
+fn input_problem(path: &Path) -> Option<String> {
+    let shown = path.display();
+    let link = fs::read_link(path).ok();
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Some(match link {
+                Some(target) => format!(
+                    "{} is a symbolic link to {}, which does not exist.",
+                    shown,
+                    target.display()
+                ),
+                None => format!("{} does not exist.", shown),
+            });
+        }
+        Err(e) => return Some(format!("Cannot read {}: {}", shown, e)),
+    };
+    if let Some(target) = &link
+        && globals().symlinks == SymlinkPolicy::NoFollow
+    {
+        return Some(format!(
+            "{} is a symbolic link to {}; leave out --no-follow-symlinks to split that.",
+            shown,
+            target.display()
+        ));
+    }
+    if metadata.is_dir() {
+        return Some(format!("{} is a directory, not a file.", shown));
+    }
+    // Opening a named pipe would wait for a writer, so only files are tried
+    if metadata.is_file()
+        && let Err(e) = File::open(path)
+    {
+        return Some(match e.kind() {
+            io::ErrorKind::PermissionDenied => format!("No permission to read {}.", shown),
+            _ => format!("Cannot read {}: {}", shown, e),
+        });
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if metadata.file_type().is_socket() {
+            return Some(format!(
+                "{} is a socket, which can't be read like a file.",
+                shown
+            ));
+        }
+    }
+    if path.file_name().is_none() {
+        return Some(format!("{} has no file name component.", shown));
+    }
+    None
+}
+
+// Said before splitting a symbolic link, since the set is named for the file it leads
+// to. A link that leads nowhere, or isn't to be followed, is left for the split to
+// refuse.
+fn link_note(path: &Path) -> Option<String> {
+    if globals().symlinks == SymlinkPolicy::NoFollow || !path.exists() {
+        return None;
+    }
+    let target = fs::read_link(path).ok()?;
+    Some(format!(
+        "{} is a symbolic link; splitting {}, which it points to.",
+        path.display(),
+        target.display()
+    ))
+}
+
+// Where chunks are saved when no destination is given: `./<file name>.chunks`.
+fn default_savedir(input_path: &Path) -> PathBuf {
+    Path::new(".").join(suffixed(input_path.file_name(), "output", ".chunks"))
+}
+
+// `name`, or `fallback` without one, with `suffix` added, keeping the bytes of a name
+// that isn't UTF-8 as they are.
+fn suffixed(name: Option<&OsStr>, fallback: &str, suffix: &str) -> OsString {
+    let mut name = name.unwrap_or(OsStr::new(fallback)).to_os_string();
+    name.push(suffix);
+    name
+}
+
+fn main() {
+    let cli = Cli::parse();
+    style::init(cli.no_color);
+    prompt::init(cli.yes);
+    history::init(cli.no_history);
+    journal::init(cli.no_journal);
+    notify::init(cli.notify || profile::notify());
+    let temp_dir = cli.temp_dir.or_else(profile::temp_dir);
+    if let Some(temp_dir) = &temp_dir
+        && !temp_dir.is_dir()
+    {
+        eprintln!(
+            "Error: the temporary directory {} is not a directory",
+            temp_dir.display()
+        );
+        exit(2);
+    }
+    let _ = GLOBALS.set(Globals {
+        buffer_size: cli.buffer_size,
+        max_memory: cli.max_memory,
+        direct_io: cli.direct_io,
+        lock_max_age: cli.lock_max_age,
+        symlinks: match (cli.follow_symlinks, cli.no_follow_symlinks) {
+            (true, _) => SymlinkPolicy::Follow,
+            (_, true) => SymlinkPolicy::NoFollow,
+            _ => SymlinkPolicy::Resolve,
+        },
+        temp_dir,
+        accept_modified: cli.accept_modified_metadata,
+    });
+    STEAL_LOCK.store(cli.steal_lock, atomic::Ordering::Relaxed);
+    interrupt::install();
+    if let Err(e) = logging::init(cli.verbose, cli.log_file.as_deref()) {
+        eprintln!("Cannot open the log file: {}", e);
+        exit(1);
+    }
+    if cli.direct_io && !cache::SUPPORTED {
+        eprintln!("--direct-io is not supported on this platform and has no effect.");
+    }
+    match cli.command {
+        Some(command) => run_command(command),
+        None if cli.tui => {
+            if let Err(e) = tui::run(starting_directory()) {
+                eprintln!("Error starting the terminal interface: {}", e);
+                exit(1);
+            }
+        }
+        None => {
+            if stdin_is_empty() {
+                eprintln!(
+                    "The menus need a terminal or answers piped in, and standard input has \
+                     neither. Give a command instead, such as `split FILE` or \
+                     `reconstruct DIRECTORY`; see --help."
+                );
+                exit(NO_INPUT_EXIT_CODE);
+            }
+            interactive()
+        }
+    }
+}
+
+// Exit status when the menus have nothing to read answers from, from the start or once
+// the input runs out.
+const NO_INPUT_EXIT_CODE: i32 = 7;
+
+// Whether standard input can't answer a prompt at all: closed, the null device or an
+// empty file. A pipe may yet bring answers, so only what is known to be empty counts.
+fn stdin_is_empty() -> bool {
+    let stdin = io::stdin();
+    if stdin.is_terminal() {
+        return false;
+    }
+    #[cfg(unix)]
+    let file = {
+        use std::os::fd::AsFd;
+        stdin.as_fd().try_clone_to_owned().map(File::from)
+    };
+    #[cfg(windows)]
+    let file = {
+        use std::os::windows::io::AsHandle;
+        stdin.as_handle().try_clone_to_owned().map(File::from)
+    };
+    #[cfg(not(any(unix, windows)))]
+    let file: io::Result<File> = Err(io::ErrorKind::Unsupported.into());
+    let metadata = match file.and_then(|file| file.metadata()) {
+        Ok(metadata) => metadata,
+        Err(e) => return e.kind() != io::ErrorKind::Unsupported,
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        if metadata.file_type().is_char_device() {
+            return true;
+        }
+    }
+    metadata.is_file() && metadata.len() == 0
+}
+
+// Where an s3:// destination or directory is; what isn't given is looked for where the
+// AWS tools look.
+#[derive(Args)]
+struct S3Args {
+    /// S3 service of an s3:// URL as an https:// URL, or http:// for one on this machine
+    /// or the local network such as a MinIO (https needs a build with the tls feature)
+    /// [default: AWS_ENDPOINT_URL_S3, AWS_ENDPOINT_URL or the profile's, else AWS]
+    #[arg(long, value_name = "URL")]
+    endpoint: Option<String>,
+    /// S3 region [default: AWS_REGION, AWS_DEFAULT_REGION or the profile's, else us-east-1]
+    #[arg(long)]
+    region: Option<String>,
+    /// Profile in ~/.aws/credentials and ~/.aws/config to take the S3 credentials and
+    /// settings from, over any in the environment [default: AWS_PROFILE or default]
+    #[arg(long, value_name = "NAME")]
+    aws_profile: Option<String>,
+}
+
+impl S3Args {
+    fn options(self, connections: u64, retries: u32) -> S3Options {
+        S3Options {
+            endpoint: self.endpoint,
+            region: self.region,
+            profile: self.aws_profile,
+            connections: connections as usize,
+            retries,
+        }
+    }
+}
+
+// How an sftp:// destination or directory is reached, on top of the keys and
+// known_hosts in ~/.ssh.
+#[cfg(feature = "sftp")]
+#[derive(Args)]
+struct SftpArgs {
+    /// Reads or writes of 32 KiB kept in flight to an sftp:// server, which a slow round
+    /// trip needs more of
+    #[arg(long, value_name = "REQUESTS", default_value_t = 64, value_parser = clap::value_parser!(u64).range(1..))]
+    window: u64,
+    /// Connect to an sftp:// server even if its key isn't in known_hosts or differs from
+    /// the one there; anyone in between can then read and change the chunks
+    #[arg(long)]
+    insecure_skip_hostkey: bool,
+}
+
+#[cfg(feature = "sftp")]
+impl SftpArgs {
+    fn options(self, connections: u64, retries: u32) -> SftpOptions {
+        SftpOptions {
+            window: self.window as usize,
+            connections: connections as usize,
+            retries,
+            insecure_skip_hostkey: self.insecure_skip_hostkey,
+            // Passwords and passphrases are typed at a terminal, never piped in
+            ask: match io::stdin().is_terminal() {
+                true => Some(prompt::passphrase_prompt),
+                false => None,
+            },
+        }
+    }
+}
+
+// How chunk files that fail now and then are retried, with --retries.
+#[derive(Args)]
+struct RetryArgs {
+    /// Pause before retrying a chunk file, e.g. 2s or 500ms, doubling for each further
+    /// attempt up to 30 s
+    #[arg(long, value_name = "DURATION", default_value = "1s", value_parser = parse_duration)]
+    retry_delay: Duration,
+    /// Also retry chunk files on these errors, besides interrupted, timed out and would
+    /// block, e.g. eio for a network mount that returns it now and then; a missing file
+    /// or refused access is never retried
+    #[arg(long, value_enum, value_name = "ERRORS", value_delimiter = ',')]
+    retry_on: Vec<Transient>,
+}
+
+impl RetryArgs {
+    fn policy(self, retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            retries,
+            delay: self.retry_delay,
+            also: self.retry_on,
+        }
+    }
+}
+
+// How --post-chunk-cmd or --pre-chunk-cmd is run.
+#[derive(Args)]
+struct HookArgs {
+    /// Chunk commands run at once
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    hook_jobs: u64,
+    /// Further runs of a chunk command that exits unsuccessfully, after pauses of 1 s,
+    /// 2 s, 4 s, …, before it fails the operation with what it printed
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    hook_retries: u32,
+    /// Run the chunk command with sh -c (cmd /C on Windows) instead. It then finds the
+    /// chunk only in the environment, as placeholders would let a file name run commands
+    #[arg(long)]
+    hook_shell: bool,
+}
+
+impl HookArgs {
+    fn hook(self, command: Option<HookCommand>) -> Option<ChunkHook> {
+        let command = command?;
+        Some(ChunkHook {
+            command: match self.hook_shell {
+                true => vec![command.text],
+                false => command.words,
+            },
+            shell: self.hook_shell,
+            jobs: self.hook_jobs as usize,
+            retries: self.hook_retries,
+            track_transfers: false,
+        })
+    }
+}
+
+// A chunk command as given, and split into words for running without a shell.
+#[derive(Clone)]
+struct HookCommand {
+    text: String,
+    words: Vec<String>,
+}
+
+// Split at whitespace outside quotes. Single and double quotes work alike, taking
+// everything up to the closing one as it is; there are no escapes, so Windows paths
+// need none.
+fn parse_hook_command(input: &str) -> Result<HookCommand, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in input.chars() {
+        match quote {
+            Some(open) if c == open => quote = None,
+            Some(_) => word.get_or_insert_default().push(c),
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            None if c.is_whitespace() => words.extend(word.take()),
+            None => word.get_or_insert_default().push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err("a quote is not closed".to_string());
+    }
+    words.extend(word);
+    if words.is_empty() {
+        return Err("no command given".to_string());
+    }
+    Ok(HookCommand {
+        text: input.to_string(),
+        words,
+    })
+}
+
+// The profile `split --profile` names, or the exit it can't be found for.
+fn load_profile(name: &str) -> Profile {
+    match profile::load(name) {
+        Ok(Some(profile)) => profile,
+        Ok(None) => {
+            eprintln!("There is no profile named \"{}\"; see profile list.", name);
+            exit(2);
+        }
+        Err(e) => {
+            eprintln!("Cannot read the config file: {}", e);
+            exit(1);
+        }
+    }
+}
+
+// Whether `path` is on a server rather than here, and so not for the history.
+static STEAL_LOCK: AtomicBool = AtomicBool::new(false);
+
+// With --steal-lock, break a stale lock on `directory` before working on it, once the
+// user says so; one still held is left for the operation to refuse.
+fn steal_lock(directory: &Path) {
+    if !STEAL_LOCK.load(atomic::Ordering::Relaxed) {
+        return;
+    }
+    let Some(holder) = lock::holder(directory) else {
+        return;
+    };
+    let Some(reason) = holder.stale(globals().lock_max_age()) else {
+        eprintln!(
+            "{} is in use: {}, which isn't stale; leaving the lock.",
+            directory.display(),
+            holder.describe()
+        );
+        return;
+    };
+    println!(
+        "{} is locked: {}, but {}.",
+        directory.display(),
+        holder.describe(),
+        reason
+    );
+    if confirm("Break the lock?", false).unwrap_or(false)
+        && let Err(e) = lock::break_lock(directory)
+    {
+        eprintln!("Cannot break the lock: {}", e);
+    }
+}
+
+fn nested_warning(input: &Path, set: &Path) -> String {
+    format!(
+        "Warning: {} looks like a piece of the existing split in {}; did you mean to \
+         reconstruct {} instead?",
+        input.display(),
+        set.display(),
+        set.display()
+    )
+}
+
+// Before reconstructing from `directory`: when the chunks info.json lists aren't there
+// but are under another prefix, record the names they have in info.json, with `auto` or
+// once the user agrees on a terminal, or only say what would be recorded with
+// `dry_run`. Without either, the reconstruction goes on to fail as it would have, after
+// saying how to go on. Files that can't be told to be the chunks are an error.
+fn offer_remap(directory: &Path, auto: bool, dry_run: bool) -> Result<(), SplitterError> {
+    if is_remote(directory) || !directory.is_dir() {
+        return Ok(());
+    }
+    let operation = interrupt::start();
+    let remap = match find_remap(directory, globals().accept_modified, &operation.token) {
+        Ok(Some(remap)) => remap,
+        Ok(None) => return Ok(()),
+        Err(e @ (SplitterError::RemapDoubt { .. } | SplitterError::Cancelled)) => return Err(e),
+        // Left for the reconstruction to report as it does
+        Err(_) => return Ok(()),
+    };
+    let (first, last) = (&remap.renamed[0], &remap.renamed[remap.renamed.len() - 1]);
+    let names = |first: &str, last: &str| match first == last {
+        true => first.to_string(),
+        false => format!("{} … {}", first, last),
+    };
+    println!(
+        "The {} chunks missing from {}, {}, are there as {}, with the {} {} lists.",
+        remap.renamed.len(),
+        directory.display(),
+        names(&first.0, &last.0),
+        names(&first.1, &last.1),
+        if remap.hashed {
+            "sizes and hashes"
+        } else {
+            "sizes"
+        },
+        MANIFEST_NAME
+    );
+    if dry_run {
+        println!("Would record their names in {}.", MANIFEST_NAME);
+        return Ok(());
+    }
+    if !auto {
+        if !(io::stdin().is_terminal() || prompt::assume_yes()) {
+            println!(
+                "Give --auto-remap to record their names in {}.",
+                MANIFEST_NAME
+            );
+            return Ok(());
+        }
+        let question = format!("Record their names in {} and go on?", MANIFEST_NAME);
+        if !confirm(&question, false).unwrap_or(false) {
+            return Ok(());
+        }
+    }
+    apply_remap(&remap, globals().accept_modified)?;
+    println!(
+        "Recorded {} names starting {} in {}.",
+        remap.renamed.len(),
+        remap.to,
+        MANIFEST_NAME
+    );
+    Ok(())
+}
+
+fn is_remote(path: &Path) -> bool {
+    is_s3_url(path) || is_sftp_url(path)
+}
+
+// For `--progress`. Which lines on stderr are progress is obvious from the leading
+// `{`; anything else there is a log line or a warning.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ProgressFormat {
+    Json,
+}
+
+fn run_command(command: Command) {
+    match command {
+        Command::Split(args) => commands::split::run(args),
+        Command::Reconstruct(args) => commands::reconstruct::run(args),
+        Command::UndoLast => commands::undo_last::run(),
+        Command::EmptyTrash(args) => commands::empty_trash::run(args),
+        Command::Verify(args) => commands::verify::run(args),
+        Command::Doctor(args) => commands::doctor::run(args),
+        Command::Explore(args) => commands::explore::run(args),
+        Command::Coverage(args) => commands::coverage::run(args),
+        Command::Mark(args) => commands::mark::run(args),
+        Command::Status(args) => commands::status::run(args),
+        Command::Next(args) => commands::next::run(args),
+        Command::Repair(args) => commands::repair::run(args),
+        Command::Heal(args) => commands::heal::run(args),
+        Command::Rechunk(args) => commands::rechunk::run(args),
+        Command::Reseal(args) => commands::reseal::run(args),
+        Command::Pack(args) => commands::pack::run(args),
+        Command::Unpack(args) => commands::unpack::run(args),
+        Command::Import(args) => commands::import::run(args),
+        Command::ExportManifest(args) => commands::export_manifest::run(args),
+        Command::Keygen(args) => commands::keygen::run(args),
+        Command::Rekey(args) => commands::rekey::run(args),
+        Command::History(args) => commands::history::run(args),
+        Command::Stats(args) => commands::stats::run(args),
+        Command::Catalog(args) => commands::catalog::run(args),
+        Command::Profile(args) => commands::profile::run(args),
+        Command::Watch(args) => commands::watch::run(args),
+        Command::Bench(args) => commands::bench::run(args),
+        Command::SelfTest(args) => commands::self_test::run(args),
+        Command::Stress(args) => commands::stress::run(args),
+        #[cfg(feature = "serve")]
+        Command::Serve(args) => commands::serve::run(args),
+        #[cfg(all(feature = "mount", unix))]
+        Command::Mount(args) => commands::mount::run(args),
+        Command::Completions(args) => commands::completions::run(args),
+        Command::ChunkDirs(args) => commands::chunk_dirs::run(args),
+    }
+}
+
+// The set to import out of those found, of which there is at least one: the only one,
+// the one whose names start with `prefix`, or with `ask`, the one picked. None, having
+// said why, when it takes a --prefix to tell.
+fn pick_foreign(
+    mut sets: Vec<ForeignSet>,
+    prefix: Option<&str>,
+    ask: bool,
+) -> io::Result<Option<ForeignSet>> {
+    let prefixes: Vec<&str> = sets.iter().map(|set| set.prefix.as_str()).collect();
+    if let Some(prefix) = prefix {
+        let Some(found) = prefixes.iter().position(|&p| p == prefix) else {
+            eprintln!(
+                "No pieces are named {}…; there are {}.",
+                prefix,
+                prefixes
+                    .iter()
+                    .map(|p| format!("{}…", p))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            return Ok(None);
+        };
+        return Ok(Some(sets.swap_remove(found)));
+    }
+    if sets.len() == 1 {
+        return Ok(sets.pop());
+    }
+    if !ask {
+        eprintln!(
+            "There are several sets of pieces here: {}. Say which with --prefix.",
+            prefixes
+                .iter()
+                .map(|p| format!("{}…", p))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        return Ok(None);
+    }
+    let mut options = BTreeMap::new();
+    for set in &sets {
+        let label = format!(
+            "{}…  ({} pieces, {})",
+            set.prefix,
+            set.pieces.len(),
+            format_size(set.total_size())
+        );
+        options.insert(label, "set");
+    }
+    let choice = list_prompt("Which pieces?", &options)?;
+    let found = sets
+        .iter()
+        .position(|set| choice.starts_with(&format!("{}…", set.prefix)))
+        .unwrap_or(0);
+    Ok(Some(sets.swap_remove(found)))
+}
+
+// The key in the file at `path` for signing exported manifests, exiting if it can't be
+// read. A trailing newline, as an editor or `echo` leaves, isn't part of it.
+fn read_key(path: &Path) -> Vec<u8> {
+    match fs::read(path) {
+        Ok(mut key) => {
+            while key.last().is_some_and(|&b| b == b'\n' || b == b'\r') {
+                key.pop();
+            }
+            if key.is_empty() {
+                eprintln!("{} is empty; a key can't be.", path.display());
+                exit(2);
+            }
+            key
+        }
+        Err(e) => {
+            eprintln!("Can't read the key in {}: {}", path.display(), e);
+            exit(1);
+        }
+    }
+}
+
+// Opening a named pipe blocks until the other end is opened too, which would otherwise
+// look like a hang.
+fn note_pipe(path: &Path, peer: &str) {
+    eprintln!(
+        "{} is a named pipe: waiting until something {} it (press Ctrl+C to give up).",
+        path.display(),
+        peer
+    );
+}
+
+// Pieces shown in full when the order is in doubt; longer lists are cut short
+const ORDER_SHOWN: usize = 20;
+
+// What was found, and everything that puts its order in doubt.
+fn print_foreign(set: &ForeignSet) {
+    let (Some(first), Some(last)) = (set.pieces.first(), set.pieces.last()) else {
+        return;
+    };
+    let naming = match set.naming {
+        ForeignNaming::Numbered { first: 0 } => "numbered from 0, as split -d does",
+        ForeignNaming::Numbered { .. } => "numbered from 1, as HJSplit and 7-Zip do",
+        ForeignNaming::Lettered => "lettered, as split does",
+    };
+    println!(
+        "Found {} pieces ({}), {}: {} to {}.",
+        set.pieces.len(),
+        format_size(set.total_size()),
+        naming,
+        first.name,
+        last.name
+    );
+    if let Some(checksums) = &set.checksums {
+        println!(
+            "They are checked against {} first.",
+            checksums.files.join(", ")
+        );
+        if !checksums.unlisted.is_empty() {
+            println!(
+                "These have no checksum there and go unchecked: {}",
+                checksums.unlisted.join(", ")
+            );
+        }
+    }
+    if set.doubts.is_empty() {
+        return;
+    }
+    println!("Their order is in doubt:");
+    for doubt in &set.doubts {
+        match doubt {
+            Doubt::MixedPadding { widths } => {
+                let widths: Vec<String> = widths.iter().map(usize::to_string).collect();
+                println!(
+                    "  the numbers are padded to different widths ({} digits), so they are put in order by value",
+                    widths.join(", ")
+                );
+            }
+            Doubt::SameNumber { names } => println!(
+                "  these have the same number, and are put in order by name: {}",
+                names.join(", ")
+            ),
+            Doubt::Gaps { count, names } => {
+                let more = match *count as usize > names.len() {
+                    true => ", …",
+                    false => "",
+                };
+                println!(
+                    "  {} missing from the numbering: {}{}",
+                    match count {
+                        1 => "a piece is".to_string(),
+                        _ => format!("{} pieces are", count),
+                    },
+                    names.join(", "),
+                    more
+                );
+            }
+            Doubt::UnevenSizes { names } => println!(
+                "  these differ in size from the first, as no pieces of one split do: {}",
+                names.join(", ")
+            ),
+            Doubt::ListedMissing { names } => println!(
+                "  the checksum files list pieces that aren't here: {}",
+                names.join(", ")
+            ),
+        }
+    }
+    let mut order: Vec<&str> = set
+        .pieces
+        .iter()
+        .take(ORDER_SHOWN)
+        .map(|piece| piece.name.as_str())
+        .collect();
+    if set.pieces.len() > ORDER_SHOWN {
+        order.push("…");
+    }
+    println!("They would go together in this order: {}", order.join(", "));
+}
+
+// Exit status for a failed split or reconstruction, as listed in `--help`. Usage
+// errors (2) are clap's.
+fn exit_code(error: &SplitterError) -> i32 {
+    match error {
+        SplitterError::Cancelled => interrupt::EXIT_CODE,
+        SplitterError::DestinationNotEmpty { .. }
+        | SplitterError::NotADirectory { .. }
+        | SplitterError::Locked { .. } => 3,
+        SplitterError::MissingChunks { .. }
+        | SplitterError::NoChunks { .. }
+        | SplitterError::RemapDoubt { .. }
+        | SplitterError::DuplicateChunk { .. }
+        | SplitterError::StrayChunk { .. } => 4,
+        SplitterError::MetadataCorrupt { .. }
+        | SplitterError::MetadataModified { .. }
+        | SplitterError::UnsafeName { .. }
+        | SplitterError::ManifestMismatch { .. } => 5,
+        SplitterError::ChangedSize { .. }
+        | SplitterError::InputChanged { .. }
+        | SplitterError::DecodedSize { .. }
+        | SplitterError::Undecryptable { .. } => 6,
+        SplitterError::InvalidOption { .. }
+        | SplitterError::ChunkTooSmall { .. }
+        | SplitterError::NotAFile { .. }
+        | SplitterError::Symlink { .. }
+        | SplitterError::NestedSplit { .. }
+        | SplitterError::Encrypted { .. }
+        | SplitterError::WrongKey { .. } => 2,
+        SplitterError::TooManyChunks { .. }
+        | SplitterError::TooManyFiles { .. }
+        | SplitterError::BrokenSymlink { .. }
+        | SplitterError::Io { .. } => 1,
+    }
+}
+
+// The name the reconstructed file was saved under, as the user would have typed it, or
+// where it is when that isn't in `directory`, as when it couldn't be written to.
+fn output_name(report: &ReconstructReport, directory: &Path) -> String {
+    match report.output.parent() == Some(directory) {
+        true => report
+            .output
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+        false => report.output.display().to_string(),
+    }
+}
+
+fn warn_without_mmap(mmap: bool) {
+    if mmap && !cfg!(feature = "mmap") {
+        eprintln!("Built without memory-map support, using normal file I/O.");
+    }
+}
+
+// State carried across operations within one interactive session.
+struct Session {
+    directory: PathBuf,
+    show_details: bool,
+    show_hidden: bool,
+    // Multi-select mode of the browser and the chunk directories picked so far
+    selecting: bool,
+    selected: BTreeSet<PathBuf>,
+}
+
+// Answers accepted by the free-form prompts to return to the main menu.
+const BACK_ANSWER: &str = "back";
+const CANCEL_ANSWER: &str = "cancel";
+
+// Whether `answer` to a free-form prompt is one of the ways to return to the main menu.
+fn backs_out(answer: impl AsRef<OsStr>) -> bool {
+    answer.as_ref().to_str().is_some_and(|answer| {
+        answer.eq_ignore_ascii_case(BACK_ANSWER) || answer.eq_ignore_ascii_case(CANCEL_ANSWER)
+    })
+}
+
+// The directory the menus and the terminal interface start browsing from: the current
+// one, without which (it was removed, or can't be read) there is nowhere to start.
+fn starting_directory() -> PathBuf {
+    match env::current_dir() {
+        Ok(directory) => display_path(&directory),
+        Err(e) => {
+            eprintln!("Cannot read the current directory: {}", e);
+            exit(1);
+        }
+    }
+}
+
+// What an entry of the main menu does.
+enum Main {
+    Reconstruct,
+    Split,
+    Import,
+    Exit,
+}
+
+fn interactive() {
+    let mut session = Session {
+        directory: starting_directory(),
+        show_details: true,
+        show_hidden: false,
+        selecting: false,
+        selected: BTreeSet::new(),
+    };
+
+    loop {
+        let entries = [
+            ("Reconstruct file", "action", Main::Reconstruct),
+            ("Split file", "action", Main::Split),
+            ("Import foreign chunk set", "action", Main::Import),
+            ("Exit", "exit", Main::Exit),
+        ]
+        .into_iter()
+        .map(|(label, kind, choice)| MenuEntry {
+            label: label.to_string(),
+            kind,
+            choice,
+        })
+        .collect();
+        let result =
+            menu_prompt("Reconstruct, split or import:", entries).and_then(|choice| match choice {
+                Main::Reconstruct => reconstruct_menu(&mut session),
+                Main::Split => split_menu(),
+                Main::Import => import_menu(),
+                Main::Exit => outcome::leave(),
+            });
+        // Prompts only fail when there is no usable input left
+        if let Err(e) = result {
+            eprintln!("\n{}, exiting.", e);
+            match e.kind() {
+                io::ErrorKind::UnexpectedEof => outcome::exit_with(NO_INPUT_EXIT_CODE),
+                _ => outcome::exit_with(1),
+            }
+        }
+        println!();
+    }
+}
+
+// What an entry of `reconstruct_menu` does.
+enum Browse {
+    Directory(PathBuf),
+    Reconstruct,
+    ToggleSelecting,
+    Recent,
+    Find,
+    Batch,
+    ClearSelection,
+    ToggleDetails,
+    Reveal,
+    Preview,
+    Explore,
+    ToggleHidden,
+    Back,
+    Exit,
+}
+
+// Browse for a chunk directory and reconstruct it. The browsed directory is kept in
+// the session so the next reconstruction starts where this one left off.
+fn reconstruct_menu(session: &mut Session) -> io::Result<()> {
+    // Where to return to if the directory we navigate into turns out to be unreadable
+    let mut previous: Option<PathBuf> = None;
+    loop {
+        let directory = &mut session.directory;
+        let show_details = session.show_details;
+        let listing = match list_directory(directory, globals().symlinks, globals().accept_modified)
+        {
+            Ok(listing) => listing,
+            Err(e) => {
+                println!("cannot open {}: {}", directory.display(), e);
+                match previous.take().or_else(|| parent_dir(directory)) {
+                    Some(fallback) => {
+                        *directory = fallback;
+                        continue;
+                    }
+                    None => return Ok(()),
+                }
+            }
+        };
+        // Entries are told apart by what they do, not by their labels, which a
+        // directory can share with an action
+        let mut entries = Vec::new();
+        let mut names = BTreeSet::new();
+        let mut hidden = 0;
+        for path in &listing.subdirectories {
+            if !session.show_hidden && is_hidden(path) {
+                hidden += 1;
+                continue;
+            }
+            if let Some(name) = path.file_name() {
+                let mut label = unique_label(&mut names, name.to_string_lossy().into_owned());
+                if session.selecting && is_chunk_set(path) {
+                    let mark = if session.selected.contains(path) {
+                        "[x]"
+                    } else {
+                        "[ ]"
+                    };
+                    label = format!("{} {}", label, mark);
+                }
+                if show_details {
+                    label = format!("{}  ({})", label, describe_directory(path));
+                }
+                entries.push(MenuEntry {
+                    label,
+                    kind: "directory",
+                    choice: Browse::Directory(path.clone()),
+                });
+            }
+        }
+        let mut action = |label: &str, kind, choice| {
+            entries.push(MenuEntry {
+                label: label.to_string(),
+                kind,
+                choice,
+            })
+        };
+        action("Reconstruct", "action", Browse::Reconstruct);
+        let selection_label = if session.selecting {
+            "Stop selecting"
+        } else {
+            "Select multiple…"
+        };
+        action(selection_label, "action", Browse::ToggleSelecting);
+        let recent = History::load().directories;
+        if !recent.is_empty() {
+            action("Recent locations", "action", Browse::Recent);
+        }
+        action("Find chunk sets…", "action", Browse::Find);
+        if !session.selected.is_empty() {
+            let batch_label = format!("Reconstruct selected ({})", session.selected.len());
+            action(&batch_label, "action", Browse::Batch);
+            action("Clear selection", "action", Browse::ClearSelection);
+        }
+        let toggle_label = if show_details {
+            "Hide directory details"
+        } else {
+            "Show directory details"
+        };
+        action(toggle_label, "action", Browse::ToggleDetails);
+        let sealed = concealed(directory);
+        if sealed {
+            action(
+                "Enter the passphrase for details…",
+                "action",
+                Browse::Reveal,
+            );
+        }
+        if !listing.chunk_files.is_empty() {
+            action("Preview chunk…", "action", Browse::Preview);
+            action("Explore chunk…", "action", Browse::Explore);
+        }
+        let hidden_label = if session.show_hidden {
+            "Hide hidden directories"
+        } else {
+            "Show hidden directories"
+        };
+        action(hidden_label, "action", Browse::ToggleHidden);
+        action("Back", "back", Browse::Back);
+        action("Exit", "exit", Browse::Exit);
+        if session.show_hidden {
+            println!("\n>>>\t{}  (showing hidden)", directory.display());
+        } else if hidden > 0 {
+            println!("\n>>>\t{}  ({} hidden)", directory.display(), hidden);
+        } else {
+            println!("\n>>>\t{}", directory.display());
+        }
+        if !listing.chunk_files.is_empty() {
+            println!(
+                "\tFound {} chunk files in this directory.",
+                listing.chunk_files.len()
+            );
+        } else {
+            println!("\tNo chunk files found in this directory.");
+        }
+        if sealed {
+            println!("\t{}.", CONCEALED);
+        }
+        if !listing.skipped_links.is_empty() {
+            println!(
+                "\tSkipping {} chunk files that are symbolic links (see --follow-symlinks).",
+                listing.skipped_links.len()
+            );
         }
-        print!("Enter the number of your choice: ");
-        io::stdout().flush().unwrap();
-        let mut response = String::new();
-        io::stdin().read_line(&mut response).unwrap();
-        if let Ok(index) = response.trim().parse::<usize>() {
-            if index > 0 && index <= options.len() {
-                return options.keys().nth(index - 1).unwrap().clone();
+        match menu_prompt("", entries)? {
+            Browse::Reconstruct => {
+                // A set whose details are sealed under its key only gives its name
+                // once that is had
+                let concealed = match concealed(directory) {
+                    true => match set_key(directory, None, None, false) {
+                        Ok(key) => key,
+                        Err(e) => {
+                            println!("Error during reconstruction: {}", e);
+                            outcome::failed(exit_code(&e));
+                            return Ok(());
+                        }
+                    },
+                    false => None,
+                };
+                let (name, default) = match recorded_name(directory, concealed.as_ref()) {
+                    Ok(default) => (text_prompt("Output name", Some(&default))?, default),
+                    Err(e) => {
+                        println!("Error during reconstruction: {}", e);
+                        outcome::failed(exit_code(&e));
+                        return Ok(());
+                    }
+                };
+                if is_stream(&directory.join(&name)) {
+                    note_pipe(&directory.join(&name), "reads from");
+                }
+                if let Err(e) = offer_remap(directory, false, false) {
+                    println!("Error during reconstruction: {}", e);
+                    outcome::failed(exit_code(&e));
+                    return Ok(());
+                }
+                let key = match concealed {
+                    Some(key) => Ok(Some(key)),
+                    None => set_key(directory, None, None, false),
+                };
+                let key = match key {
+                    Ok(key) => key,
+                    Err(e) => {
+                        println!("Error during reconstruction: {}", e);
+                        outcome::failed(exit_code(&e));
+                        return Ok(());
+                    }
+                };
+                let operation = interrupt::start();
+                let options = ReconstructOptions {
+                    // The recorded name, unless another was given, so that one that is
+                    // the name of a file of the set's goes in the directory above
+                    output: (name != default).then(|| name.clone()),
+                    key,
+                    ..reconstruct_options(directory, None)
+                };
+                let plan = match plan_reconstruct(&options, &operation.token) {
+                    Ok(plan) => plan,
+                    Err(e) => {
+                        println!("Error during reconstruction: {}", e);
+                        outcome::failed(exit_code(&e));
+                        return Ok(());
+                    }
+                };
+                print_reconstruct_plan(&plan);
+                if !confirm("Proceed?", !plan.overwrites)? {
+                    return Ok(());
+                }
+                let mut timing = Timing::start();
+                let started = journal::start();
+                let (result, trashed) = trash::reconstruct_keeping(
+                    &options,
+                    true,
+                    &mut |event| timing.record(&event),
+                    &operation.token,
+                );
+                journal::reconstruct(started, &options, &result);
+                match result {
+                    Ok(report) => {
+                        print_trashed(trashed.as_ref());
+                        History::record_directory(directory);
+                        println!(
+                            "Reconstructed file saved as \"{}\".",
+                            output_name(&report, directory)
+                        );
+                        println!("{}", timing.summary());
+                    }
+                    Err(e) => {
+                        println!("Error during reconstruction: {}", e);
+                        outcome::failed(exit_code(&e));
+                    }
+                }
+                return Ok(());
+            }
+            Browse::Back => return Ok(()),
+            Browse::Exit => outcome::leave(),
+            Browse::ToggleDetails => session.show_details = !show_details,
+            Browse::Reveal => {
+                let revealed = set_key(directory, None, None, false).and_then(|key| {
+                    Manifest::load_revealed(directory, globals().accept_modified, key.as_ref())
+                });
+                match revealed {
+                    Ok(Some(manifest)) => print_revealed(&manifest),
+                    Ok(None) => {}
+                    Err(e) => {
+                        println!("Error reading info.json: {}", e);
+                        outcome::failed(exit_code(&e));
+                    }
+                }
+            }
+            Browse::ToggleHidden => session.show_hidden = !session.show_hidden,
+            Browse::ToggleSelecting => session.selecting = !session.selecting,
+            Browse::ClearSelection => session.selected.clear(),
+            Browse::Preview => preview_chunk(directory, &listing.chunk_files)?,
+            Browse::Explore => explore_chunk(directory)?,
+            Browse::Recent => {
+                if let Some(recent) = pick_recent(&recent)? {
+                    previous = Some(std::mem::replace(directory, recent));
+                }
+            }
+            Browse::Find => {
+                if let Some(found) = find_menu()? {
+                    previous = Some(std::mem::replace(directory, found));
+                }
+            }
+            Browse::Batch => {
+                let selected = std::mem::take(&mut session.selected);
+                session.selecting = false;
+                reconstruct_batch(&selected);
+                return Ok(());
+            }
+            Browse::Directory(path) => {
+                if session.selecting && is_chunk_set(&path) {
+                    if !session.selected.remove(&path) {
+                        session.selected.insert(path);
+                    }
+                } else {
+                    previous = Some(std::mem::replace(directory, path));
+                }
             }
         }
-        println!("Invalid choice. Please select a valid number from the list.");
     }
 }
 
-fn reconstruct_file(directory: &Path) -> io::Result<String> {
-    let info_path = directory.join("info.json");
-    let name = if info_path.exists() {
-        let data = fs::read_to_string(&info_path)?;
-        serde_json::from_str::<serde_json::Value>(&data)
-            .ok()
-            .and_then(|v| {
-                v.get("original_filename")
-                    .and_then(|n| n.as_str().map(|s| s.to_string()))
-            })
-            .unwrap_or_else(|| "reconstructed_file".to_string())
-    } else {
-        "reconstructed_file".to_string()
+const PREVIEW_WINDOW: u64 = 256;
+
+// Pick one of the `chunk_files` of `directory` and page through it as a hex dump.
+fn preview_chunk(directory: &Path, chunk_files: &[PathBuf]) -> io::Result<()> {
+    let mut entries = Vec::new();
+    let mut labels = BTreeSet::new();
+    for path in chunk_files {
+        if let Some(name) = path.file_name() {
+            let label = unique_label(&mut labels, name.to_string_lossy().into_owned());
+            entries.push(MenuEntry {
+                label: label.clone(),
+                kind: "chunk",
+                choice: Some((label, path.clone())),
+            });
+        }
+    }
+    entries.push(MenuEntry {
+        label: "Back".to_string(),
+        kind: "back",
+        choice: None,
+    });
+    let Some((choice, path)) = menu_prompt("Chunk to preview:", entries)? else {
+        return Ok(());
+    };
+
+    let mut file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("cannot open {}: {}", path.display(), e);
+            return Ok(());
+        }
     };
+    let size = file.metadata()?.len();
+    println!("{}: {} ({} bytes)", choice, format_size(size), size);
+    if let Ok(Some(manifest)) = Manifest::load(directory, globals().accept_modified)
+        && let Some(algorithm) = manifest.hash
+        && let Some(expected) = manifest
+            .chunks
+            .iter()
+            .find(|chunk| chunk.name == choice)
+            .and_then(|chunk| chunk.hash.as_ref())
+    {
+        let operation = interrupt::start();
+        match hash_file(&path, algorithm, &mut |_| {}, &operation.token) {
+            Ok(actual) if actual == *expected => println!("Hash matches the recorded one."),
+            Ok(_) => println!("Hash does NOT match the recorded one!"),
+            Err(e) => println!("cannot hash {}: {}", path.display(), e),
+        }
+    }
+    let mut offset = 0;
+    loop {
+        let mut window = Vec::new();
+        (&mut file).take(PREVIEW_WINDOW).read_to_end(&mut window)?;
+        explore::write_hex_dump(&mut io::stdout().lock(), offset, &window)?;
+        offset += window.len() as u64;
+        if offset >= size || window.is_empty() {
+            println!("(end of {})", choice);
+            return Ok(());
+        }
+        if !prompt::yes_or_no("Show the next 256 bytes?", true)? {
+            return Ok(());
+        }
+    }
+}
 
-    let output_path = directory.join(&name);
-    let mut output_file = BufWriter::new(File::create(&output_path)?);
+// Ask which chunk of the set in `directory` to explore, and page through it as the
+// part of the original file it is.
+fn explore_chunk(directory: &Path) -> io::Result<()> {
+    let count = match ChunkSet::open(directory, globals().accept_modified) {
+        Ok(set) => set.len(),
+        Err(e) => {
+            println!("Error reading the chunks: {}", e);
+            return Ok(());
+        }
+    };
+    let prompt = format!("Chunk to explore (0-{})", count.saturating_sub(1));
+    let answer = text_prompt(&prompt, Some("0"))?;
+    if backs_out(&answer) {
+        return Ok(());
+    }
+    let Ok(index) = answer.trim().parse() else {
+        println!("{} is not a chunk number.", answer.trim());
+        return Ok(());
+    };
+    match explore::open(directory, index, globals().accept_modified) {
+        Ok(explored) => explore::show(explored, explore::View::Auto, true),
+        Err(e) => {
+            println!("Error exploring the chunk: {}", e);
+            Ok(())
+        }
+    }
+}
 
-    // Collect and sort chunk files
-    let mut chunk_files: Vec<_> = fs::read_dir(directory)?
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_name().to_string_lossy().starts_with("chunk"))
-        .map(|e| e.path())
+fn pick_recent(recent: &[PathBuf]) -> io::Result<Option<PathBuf>> {
+    let mut entries: Vec<_> = recent
+        .iter()
+        .filter(|path| path.is_dir())
+        .map(|path| MenuEntry {
+            label: path.display().to_string(),
+            kind: "directory",
+            choice: Some(path.clone()),
+        })
         .collect();
+    entries.push(MenuEntry {
+        label: "Back".to_string(),
+        kind: "back",
+        choice: None,
+    });
+    menu_prompt("Recent locations:", entries)
+}
 
-    chunk_files.sort();
+// How far below the root, and through how many entries, "Find chunk sets…" looks
+// before it stops, so a search from / or of a slow mount still comes back.
+const FIND_DEPTH: usize = 8;
+const FIND_ENTRY_LIMIT: usize = 100_000;
+
+// Search a tree for chunk sets and pick one to go to.
+fn find_menu() -> io::Result<Option<PathBuf>> {
+    let here = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let root = path_prompt("Search under (Tab completes)", Some(&here))?;
+    if backs_out(&root) {
+        return Ok(None);
+    }
+    let root = absolute_path(&root);
+    println!("Searching {}… (Ctrl+C stops)", root.display());
+    let operation = interrupt::start();
+    let (found, complete) = find_sets(&root, &operation.token);
+    drop(operation);
+    if !complete {
+        println!(
+            "Stopped before looking everywhere; showing the {} found so far.",
+            found.len()
+        );
+    }
+    if found.is_empty() {
+        println!("No chunk sets found under {}.", root.display());
+        return Ok(None);
+    }
+    let mut entries = Vec::new();
+    let mut labels = BTreeSet::new();
+    for directory in found {
+        let shown = directory.strip_prefix(&root).unwrap_or(&directory);
+        let shown = match shown.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => shown,
+        };
+        let label = unique_label(
+            &mut labels,
+            format!("{}  ({})", shown.display(), describe_set(&directory)),
+        );
+        entries.push(MenuEntry {
+            label,
+            kind: "directory",
+            choice: Some(directory),
+        });
+    }
+    entries.push(MenuEntry {
+        label: "Back".to_string(),
+        kind: "back",
+        choice: None,
+    });
+    menu_prompt("Chunk sets found:", entries)
+}
 
-    // Concatenate all chunks
-    for chunk_path in chunk_files {
-        let mut chunk_file = BufReader::new(File::open(&chunk_path)?);
-        io::copy(&mut chunk_file, &mut output_file)?;
+// Every directory with an info.json from `root` down, nearest first, and whether the
+// whole tree was looked through: not when `cancel` was set, by Ctrl+C, or FIND_DEPTH or
+// FIND_ENTRY_LIMIT was reached. Directories that can't be read are passed over.
+// Symbolic links are followed, to sets kept elsewhere, and walkdir stops at one that
+// leads back up the tree.
+fn find_sets(root: &Path, cancel: &CancelToken) -> (Vec<PathBuf>, bool) {
+    let mut found = Vec::new();
+    let mut complete = true;
+    // The info.json of a set FIND_DEPTH levels down is a level further
+    let walk = WalkDir::new(root)
+        .follow_links(true)
+        .max_depth(FIND_DEPTH + 1)
+        .sort_by(|a, b| {
+            natural_cmp(
+                &a.file_name().to_string_lossy(),
+                &b.file_name().to_string_lossy(),
+            )
+        });
+    for (visited, entry) in walk.into_iter().enumerate() {
+        if cancel.is_cancelled() || visited > FIND_ENTRY_LIMIT {
+            complete = false;
+            break;
+        }
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if entry.file_name() == MANIFEST_NAME
+            && let Some(directory) = entry.path().parent()
+        {
+            found.push(directory.to_path_buf());
+        } else if entry.depth() > FIND_DEPTH && entry.file_type().is_dir() {
+            complete = false;
+        }
     }
+    // Walked depth first, and stably sorted so the nearest come first
+    found.sort_by_key(|directory| directory.components().count());
+    (found, complete)
+}
+
+// What a set's details sealed under its key say, once opened.
+fn print_revealed(manifest: &Manifest) {
+    println!("\tOriginal file: {}", manifest.original_filename);
+    let size = manifest.chunks.iter().map(|entry| entry.size).sum();
+    println!(
+        "\t{} chunks, {} in all",
+        manifest.chunks.len(),
+        format_size(size)
+    );
+}
+
+// The set in `directory` at a glance: what it joins into, how large, and whether every
+// chunk is there.
+fn describe_set(directory: &Path) -> String {
+    let name = match concealed(directory) {
+        true => CONCEALED.to_string(),
+        false => default_output_name(directory, globals().accept_modified)
+            .unwrap_or_else(|_| "?".to_string()),
+    };
+    let health = match chunk_health(directory, globals().accept_modified) {
+        Ok(health) => health,
+        Err(e) => return format!("{}, unreadable: {}", name, e),
+    };
+    let state = match health.missing.len() {
+        _ if health.chunks == 0 => "no chunks".to_string(),
+        0 if health.is_healthy() => "complete".to_string(),
+        0 => "chunks look wrong".to_string(),
+        1 => "1 chunk missing".to_string(),
+        missing => format!("{} chunks missing", missing),
+    };
+    format!("{}, {}, {}", name, format_size(health.total_size), state)
+}
 
-    Ok(name)
+fn is_chunk_set(directory: &Path) -> bool {
+    directory.join(MANIFEST_NAME).is_file()
+        || chunk_health(directory, globals().accept_modified).is_ok_and(|health| health.chunks > 0)
 }
 
-fn split_file(input_path: &Path, savedir: &Path) -> io::Result<()> {
-    const CHUNK_SIZE: usize = 5 * 1024 * 1024; // 5MB
+// Reconstruct several chunk directories one after the other, each under its recorded
+// original name, then report how they all went.
+fn reconstruct_batch(directories: &BTreeSet<PathBuf>) {
+    let total = directories.len();
+    let mut failures = Vec::new();
+    let mut succeeded = 0;
+    for (i, directory) in directories.iter().enumerate() {
+        println!("[{}/{}] {}", i + 1, total, directory.display());
+        let options = reconstruct_options(directory, None);
+        let operation = interrupt::start();
+        let started = journal::start();
+        let (result, trashed) =
+            trash::reconstruct_keeping(&options, true, &mut |_| {}, &operation.token);
+        journal::reconstruct(started, &options, &result);
+        match result {
+            Ok(report) => {
+                if let Some(trashed) = &trashed {
+                    println!(
+                        "\tThe file that was there is now in {}.",
+                        trashed.location()
+                    );
+                }
+                History::record_directory(directory);
+                println!(
+                    "\tReconstructed file saved as \"{}\".",
+                    output_name(&report, directory)
+                );
+                succeeded += 1;
+            }
+            // Ctrl+C stops the whole batch, not just the directory at hand
+            Err(SplitterError::Cancelled) => {
+                println!("\tCancelled.");
+                outcome::failed(interrupt::EXIT_CODE);
+                break;
+            }
+            Err(e) => {
+                println!("\tError during reconstruction: {}", e);
+                outcome::failed(exit_code(&e));
+                failures.push((directory, e));
+            }
+        }
+    }
 
-    // Create directory if it doesn't exist
-    if !savedir.exists() {
-        fs::create_dir_all(savedir)?;
+    println!("\nReconstructed {} of {} chunk sets.", succeeded, total);
+    for (directory, e) in &failures {
+        println!("  failed: {}: {}", directory.display(), e);
     }
+}
 
-    // Check if directory is empty
-    if fs::read_dir(savedir)?.next().is_some() {
-        return Err(io::Error::new(
-            io::ErrorKind::AlreadyExists,
-            "Directory is not empty",
-        ));
+// Take over the pieces another tool left in a directory, asking about everything the
+// `import` command takes as options.
+fn import_menu() -> io::Result<()> {
+    println!(
+        "(Enter \"{}\" or \"{}\" at any prompt to return to the main menu.)",
+        BACK_ANSWER, CANCEL_ANSWER
+    );
+    let current = display_path(&env::current_dir().unwrap_or_else(|_| PathBuf::from(".")));
+    let directory = path_prompt("Directory with the pieces (Tab completes)", Some(&current))?;
+    if backs_out(&directory) {
+        return Ok(());
+    }
+    let directory = absolute_path(&directory);
+    let sets = match detect_foreign(&directory) {
+        Ok(sets) => sets,
+        Err(e) => {
+            println!("Cannot import {}: {}", directory.display(), e);
+            outcome::failed(exit_code(&e));
+            return Ok(());
+        }
+    };
+    if sets.is_empty() {
+        println!("No pieces split by another tool were found there.");
+        return Ok(());
+    }
+    let Some(set) = pick_foreign(sets, None, true)? else {
+        return Ok(());
+    };
+    print_foreign(&set);
+    if !set.doubts.is_empty() && !confirm("Go ahead in this order?", false)? {
+        return Ok(());
     }
 
-    // Save original filename info
-    let info = serde_json::json!({
-        "original_filename": input_path.file_name().unwrap().to_string_lossy()
-    });
-    fs::write(
-        savedir.join("info.json"),
-        serde_json::to_string(&info).unwrap(),
+    let name = text_prompt(
+        "Name of the file the pieces were split from",
+        set.original_filename.as_deref(),
     )?;
+    if backs_out(&name) || name.is_empty() {
+        return Ok(());
+    }
+    let mut options = BTreeMap::new();
+    options.insert(
+        "Write an info.json, making them a chunk set".to_string(),
+        "info",
+    );
+    options.insert("Join them into the file now".to_string(), "join");
+    let join = list_prompt("What now?", &options)? == "Join them into the file now";
+    let operation = interrupt::start();
+    if join {
+        let output = path_prompt("Save the file as", Some(&directory.join(&name)))?;
+        if backs_out(&output) {
+            return Ok(());
+        }
+        match reconstruct_foreign(
+            &set,
+            &absolute_path(&output),
+            globals().temp_dir.as_deref(),
+            thread_count(None),
+            &mut |_| {},
+            &operation.token,
+        ) {
+            Ok(report) => println!("Joined file saved as \"{}\".", report.output.display()),
+            Err(e) => {
+                println!("Error joining the pieces: {}", e);
+                outcome::failed(exit_code(&e));
+            }
+        }
+        return Ok(());
+    }
+    let hash = preference("Record a hash of every piece, for verify?", true)?;
+    let hash = hash.then_some(HashAlgorithm::Sha256);
+    match import(&set, &name, hash, &mut |_| {}, &operation.token) {
+        Ok(report) => {
+            History::record_directory(&report.directory);
+            println!(
+                "Wrote an info.json for the {} pieces of \"{}\"; {} is now a chunk set.",
+                report.chunks,
+                report.original_filename,
+                report.directory.display()
+            );
+        }
+        Err(e) => {
+            println!("Error during import: {}", e);
+            outcome::failed(exit_code(&e));
+        }
+    }
+    Ok(())
+}
+
+fn split_menu() -> io::Result<()> {
+    println!(
+        "(Enter \"{}\" or \"{}\" at any prompt to return to the main menu.)",
+        BACK_ANSWER, CANCEL_ANSWER
+    );
 
-    // Split file into chunks
-    let mut input_file = BufReader::new(File::open(input_path)?);
-    let mut buffer = vec![0u8; CHUNK_SIZE];
-    let mut chunk_index = 0;
+    // Answers from a previous round, offered as defaults if the user declines the
+    // summary; the first round starts from the most recently used locations.
+    let history = History::load();
+    let mut previous_input = history.sources.into_iter().find(|path| path.is_file());
+    let recent_root = history
+        .destinations
+        .first()
+        .and_then(|dest| dest.parent())
+        .map(Path::to_path_buf);
+    let mut previous_dest: Option<PathBuf> = None;
+    let mut size_answer = format_size(DEFAULT_CHUNK_SIZE);
 
     loop {
-        let bytes_read = input_file.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
+        let mut input_path =
+            path_prompt("File to split (Tab completes)", previous_input.as_deref())?;
+        if backs_out(&input_path) {
+            return Ok(());
+        }
+        input_path = absolute_path(&input_path);
+
+        if input_path.is_dir() {
+            match pick_file(input_path.clone())? {
+                Some(path) => input_path = path,
+                None => return Ok(()),
+            }
+        }
+
+        if let Some(problem) = input_problem(&input_path) {
+            println!("{}", problem);
+            continue;
+        }
+        if let Some(note) = link_note(&input_path) {
+            println!("{}", note);
+        }
+        let nested = containing_set(&input_path);
+        if let Some(set) = &nested {
+            println!("{}", nested_warning(&input_path, set));
+            if !confirm("Split it anyway?", false)? {
+                continue;
+            }
+        }
+        let streamed = is_stream(&input_path);
+        // Only to show the plan; a file that can't be looked at is split without one
+        let input_size = fs::metadata(&input_path)
+            .ok()
+            .filter(|_| !streamed)
+            .map(|metadata| metadata.len());
+        if let Some(size) = input_size {
+            println!("Size: {}", format_size(size));
         }
 
-        let chunk_name = format!("chunk{:03}", chunk_index);
-        let chunk_path = savedir.join(&chunk_name);
-        let mut chunk_file = BufWriter::new(File::create(&chunk_path)?);
-        chunk_file.write_all(&buffer[..bytes_read])?;
+        let default_dest = match (previous_dest, &recent_root) {
+            (Some(dest), _) if previous_input.as_ref() == Some(&input_path) => dest,
+            (_, Some(root)) => root.join(default_savedir(&input_path).file_name().unwrap()),
+            _ => default_savedir(&input_path),
+        };
+        let savedir = loop {
+            let savedir = path_prompt("Save chunks to", Some(&default_dest))?;
+            if backs_out(&savedir) {
+                return Ok(());
+            }
+            let template = match Template::parse(savedir.as_os_str()) {
+                Ok(template) => template,
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            };
+            let savedir = match template.expand(&input_path) {
+                Ok(savedir) => absolute_path(&savedir),
+                Err(e) => {
+                    println!("{}", e);
+                    continue;
+                }
+            };
+            if template.has_variables() {
+                println!("That is {}", savedir.display());
+            }
+            match check_destination(&savedir) {
+                Ok(()) => break savedir,
+                Err(e) => println!("{}", e),
+            }
+        };
+
+        let compat = match naming_prompt(&input_path)? {
+            Some(compat) => compat,
+            None => return Ok(()),
+        };
+        // Chunks named for other tools are likely going to someone without this one
+        let join_scripts = compat.is_some()
+            && preference("Add JOIN.sh and JOIN.bat to put them back together?", true)?;
+        // Spreading the chunks over drives plans by the input's size, in this tool's names
+        let span = match compat.is_none()
+            && !streamed
+            && preference(
+                "Spread the chunks over several drives, filling each in turn?",
+                false,
+            )? {
+            true => Some(Span {
+                fit: preference(
+                    "Make it one chunk per drive, as large as it has room for?",
+                    false,
+                )?,
+                ..Span::new(Vec::new())
+            }),
+            false => None,
+        };
+        let fit = span.as_ref().is_some_and(|span| span.fit);
+
+        let options = loop {
+            // Sized by the drives instead
+            let answer = match fit {
+                true => size_answer.clone(),
+                false => text_prompt("Chunk size", Some(&size_answer))?,
+            };
+            if backs_out(&answer) {
+                return Ok(());
+            }
+            let options = parse_size(&answer).and_then(|size| {
+                split_options(SplitOptions::builder(&input_path, &savedir), None)
+                    .chunk_size(size)
+                    .compat(compat)
+                    .join_scripts(join_scripts)
+                    .span(span.clone())
+                    .allow_nested(nested.is_some())
+                    .build()
+                    .map_err(|e| e.to_string())
+            });
+            match options {
+                Ok(options) => {
+                    if let Some(size) = input_size.filter(|_| !fit) {
+                        println!(
+                            "{}",
+                            chunk_plan(&input_path, size, options.chunk_size, compat)
+                        );
+                        // The size offered at first needs no second thought
+                        if options.chunk_size != DEFAULT_CHUNK_SIZE
+                            && let Some(note) = single_chunk_note(size, options.chunk_size)
+                        {
+                            println!("Warning: {}", note);
+                            if !confirm("Split it into a single chunk anyway?", true)? {
+                                continue;
+                            }
+                        }
+                    }
+                    size_answer = answer;
+                    break options;
+                }
+                Err(e) => println!("{}", e),
+            }
+        };
+        let options = match options.span {
+            Some(_) => match span_prompt(options)? {
+                Some(options) => options,
+                None => return Ok(()),
+            },
+            None => options,
+        };
+        let chunk_size = options.chunk_size;
+
+        print_split_summary(&input_path, &savedir, chunk_size, compat);
+        let proceed = room_confirmed(&options)? && confirm("Proceed?", true)?;
+        previous_input = Some(input_path.clone());
+        previous_dest = Some(savedir.clone());
+        if !proceed {
+            continue;
+        }
 
-        chunk_index += 1;
+        if !menu_split(&options) || options.span.is_some() {
+            return Ok(());
+        }
+        // A split across drives is planned for the one file, so isn't repeated
+        let root = fs::canonicalize(&savedir)
+            .ok()
+            .and_then(|savedir| savedir.parent().map(Path::to_path_buf));
+        let compression = (!options.compression.is_none()).then_some(options.compression);
+        let settings = Profile {
+            destination_root: root,
+            chunk_size: Some(chunk_size),
+            hash: options.hash,
+            compression,
+            compression_level: compression.map(|_| options.compression_level),
+            compat,
+            join_scripts,
+        };
+        return repeat_menu(&settings);
     }
+}
 
-    Ok(())
+// Whether to go ahead with a split in the menu that would fill up the disk the file is
+// on, asked when it would; a split across drives plans its own room.
+fn room_confirmed(options: &SplitOptions) -> io::Result<bool> {
+    match shared_disk_warning(options) {
+        Some(warning) if options.span.is_none() => {
+            println!(
+                "Warning: {} Free some up, or choose a destination on another disk.",
+                warning
+            );
+            confirm("Split onto this disk anyway?", false)
+        }
+        _ => Ok(true),
+    }
 }
 
-fn main() {
-    let mut directory = env::current_dir().unwrap();
-    let mut options = BTreeMap::new();
-    options.insert("Reconstruct file".to_string(), "action");
-    options.insert("Split file".to_string(), "action");
-    options.insert("Exit".to_string(), "exit");
-    let choice = list_prompt("Reconstruct or split file:", &options);
-
-    match choice.as_str() {
-        "Reconstruct file" => loop {
-            let mut dir_options = BTreeMap::new();
-            if let Ok(entries) = fs::read_dir(&directory) {
-                for entry in entries.flatten() {
-                    let path = entry.path();
-                    if path.is_dir() {
-                        if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                            dir_options.insert(name.to_string(), "directory");
-                        }
+// Run a split chosen in the menu, saying how it went. Whether it succeeded.
+fn menu_split(options: &SplitOptions) -> bool {
+    let (input_path, savedir) = (&options.input, &options.destination);
+    let streamed = is_stream(input_path);
+    if streamed {
+        note_pipe(input_path, "writes into");
+    }
+    let total = fs::metadata(input_path)
+        .ok()
+        .filter(|_| !streamed)
+        .map(|metadata| metadata.len());
+    let mut status = SplitProgress::new(total, options.chunk_size);
+    let mut timing = Timing::start();
+    let operation = interrupt::start();
+    let started = journal::start();
+    let result = split_file(
+        options,
+        &mut |event| {
+            status.update(&event);
+            timing.record(&event);
+        },
+        &operation.token,
+    );
+    journal::split(started, options, &result);
+    status.finish();
+    match result {
+        Ok(report) => {
+            History::record_split(input_path, savedir);
+            println!("File split successfully.");
+            if let Some(span) = &report.span {
+                print_volumes(&span.volumes);
+            }
+            if report.input_changed {
+                print_input_changed();
+            }
+            println!("{}", timing.summary());
+            true
+        }
+        Err(e) => {
+            println!("Error during splitting: {}", e);
+            outcome::failed(exit_code(&e));
+            false
+        }
+    }
+}
+
+// After a split in the menu: split more files with its settings, each only asked for
+// and put in a new directory next to the first one's, or save the settings as a
+// profile for `split --profile`.
+fn repeat_menu(settings: &Profile) -> io::Result<()> {
+    const REPEAT: &str = "Split another file with these settings";
+    const SAVE: &str = "Save these settings as a profile";
+    let mut saved = false;
+    loop {
+        let mut options = BTreeMap::new();
+        options.insert(REPEAT.to_string(), "action");
+        if !saved {
+            options.insert(SAVE.to_string(), "action");
+        }
+        options.insert("Back".to_string(), "back");
+        match list_prompt("\nWhat next?", &options)?.as_str() {
+            SAVE => {
+                let name = text_prompt("Profile name", None)?;
+                if backs_out(&name) {
+                    return Ok(());
+                }
+                if name.is_empty() {
+                    continue;
+                }
+                match profile::save(&name, settings) {
+                    Ok(path) => {
+                        println!(
+                            "Saved in {}; split --profile {} FILE splits the same way.",
+                            path.display(),
+                            name
+                        );
+                        saved = true;
                     }
+                    Err(e) => println!("Cannot save the profile: {}", e),
                 }
             }
-            dir_options.insert("Reconstruct".to_string(), "action");
-            dir_options.insert("Exit".to_string(), "exit");
-            println!("\n>>>\t{}", directory.display());
-            let chunk_files: Vec<_> = fs::read_dir(&directory)
-                .unwrap()
-                .filter_map(|e| e.ok())
-                .filter(|e| e.file_name().to_string_lossy().starts_with("chunk"))
-                .collect();
-            if !chunk_files.is_empty() {
-                println!(
-                    "\tFound {} chunk files in this directory.",
-                    chunk_files.len()
+            REPEAT => {
+                let input_path = path_prompt("File to split (Tab completes)", None)?;
+                if backs_out(&input_path) {
+                    return Ok(());
+                }
+                let input_path = absolute_path(&input_path);
+                if let Some(problem) = input_problem(&input_path) {
+                    println!("{}", problem);
+                    continue;
+                }
+                let savedir = absolute_path(
+                    &settings
+                        .savedir(&default_savedir(&input_path))
+                        .unwrap_or_else(|| default_savedir(&input_path)),
                 );
-            } else {
-                println!("\tNo chunk files found in this directory.");
-            }
-            let choice = list_prompt("", &dir_options);
-            match choice.as_str() {
-                "Reconstruct" => {
-                    match reconstruct_file(&directory) {
-                        Ok(name) => {
-                            println!("Reconstructed file saved as \"{}\".", name);
-                        }
-                        Err(e) => {
-                            println!("Error during reconstruction: {}", e);
-                        }
+                let options = split_options(settings.builder(&input_path, &savedir), None).build();
+                let options = match options {
+                    Ok(options) => options,
+                    Err(e) => {
+                        println!("{}", e);
+                        continue;
                     }
-                    break;
+                };
+                if let Ok(metadata) = fs::metadata(&input_path)
+                    && !is_stream(&input_path)
+                {
+                    let (size, chunk_size) = (metadata.len(), options.chunk_size);
+                    println!(
+                        "{}",
+                        chunk_plan(&input_path, size, chunk_size, options.compat)
+                    );
                 }
-                "Exit" => exit(0),
-                other => {
-                    directory = directory.join(other);
+                print_split_summary(&input_path, &savedir, options.chunk_size, options.compat);
+                if !room_confirmed(&options)? {
+                    continue;
+                }
+                if !menu_split(&options) {
+                    return Ok(());
                 }
             }
-        },
-        "Split file" => {
-            print!("Enter the path to the file to split\n>>> ");
-            io::stdout().flush().unwrap();
-            let mut input_path = String::new();
-            io::stdin().read_line(&mut input_path).unwrap();
-            let input_path = input_path.trim();
-
-            if !Path::new(input_path).is_file() {
-                println!("File does not exist.");
-                exit(1);
+            _ => return Ok(()),
+        }
+    }
+}
+
+// Plan the split in `options` across drives, asking for one more for as long as those
+// so far have no room for all of it. None to return to the main menu.
+fn span_prompt(mut options: SplitOptions) -> io::Result<Option<SplitOptions>> {
+    loop {
+        let plan = match plan_span(&options) {
+            Ok(plan) => plan,
+            Err(e) => {
+                println!("{}.", e);
+                return Ok(None);
+            }
+        };
+        println!("\nRoom on the drives so far:");
+        print_volumes(&plan.volumes);
+        if plan.unplaced == 0 {
+            return Ok(Some(options));
+        }
+        println!(
+            "That leaves {} of {} with nowhere to go.",
+            format_size(plan.unplaced),
+            format_size(plan.total_size)
+        );
+        let next = path_prompt(
+            "Insert or choose the next drive, and give the directory for chunks on it (Tab completes)",
+            None,
+        )?;
+        if backs_out(&next) {
+            return Ok(None);
+        }
+        if let Some(span) = &mut options.span {
+            span.volumes.push(absolute_path(&next));
+        }
+        if let Err(e) = options.validate() {
+            println!("{}.", e);
+            if let Some(span) = &mut options.span {
+                span.volumes.pop();
             }
+        }
+    }
+}
 
-            print!("Give a directory to save the chunks\n>>> ");
-            io::stdout().flush().unwrap();
-            let mut savedir = String::new();
-            io::stdin().read_line(&mut savedir).unwrap();
-            let savedir = savedir.trim();
+// What the chunks' modification times hint at, for `verify --timestamps`. Not being
+// able to tell is only said, as nothing hangs on it.
+fn print_timestamps(directory: &Path, tolerance: Duration) {
+    let report = match check_timestamps(directory, tolerance, globals().accept_modified) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Timestamps not checked: {}", e);
+            return;
+        }
+    };
+    if !report.recorded {
+        println!(
+            "Hint: no times were recorded when the set was split (see split --record-times), \
+             so only chunks modified long after the rest stand out."
+        );
+    } else if !report.kept {
+        println!(
+            "Hint: most chunks no longer have the times recorded when the set was split, as \
+             after a copy that didn't keep them, so only chunks modified long after the rest \
+             stand out."
+        );
+    }
+    for hint in &report.hints {
+        println!("Hint: {}.", hint.describe());
+    }
+}
 
-            match split_file(Path::new(input_path), Path::new(savedir)) {
-                Ok(_) => {
-                    println!("File split successfully.");
-                }
-                Err(e) => {
-                    println!("Error during splitting: {}", e);
-                    exit(1);
+// After a split whose input was written to while it was read, so what the chunks hold
+// may be no single version of it.
+fn print_input_changed() {
+    eprintln!(
+        "Warning: the file changed while it was being split, so the chunks hold each part \
+         as it was when read. Split it again once it stops changing, or use --strict to \
+         fail instead."
+    );
+}
+
+// Which chunks each volume of a split across several directories gets, or got.
+fn print_volumes(volumes: &[PlannedVolume]) {
+    for (number, volume) in volumes.iter().enumerate() {
+        let chunks = match volume.count {
+            0 => "no room for a chunk".to_string(),
+            1 => format!("chunk {}", volume.first),
+            count => format!("chunks {} to {}", volume.first, volume.first + count - 1),
+        };
+        println!(
+            "  {:<17}{}: {} ({}; {} was free)",
+            format!("Volume {}:", number + 1),
+            volume.directory.display(),
+            chunks,
+            format_size(volume.size),
+            format_size(volume.free)
+        );
+    }
+}
+
+// How to name the chunks: this tool's own names, or ones a recipient without it can
+// join. None to return to the main menu.
+fn naming_prompt(input_path: &Path) -> io::Result<Option<Option<Compat>>> {
+    let base = input_path.file_name().unwrap_or_default().to_string_lossy();
+    let choices = [
+        ("chunk000, chunk001, … (for this tool)".to_string(), None),
+        (
+            format!("{}.001, {}.002, … (for HJSplit, 7-Zip or cat)", base, base),
+            Some(Compat::Hjsplit),
+        ),
+        (
+            format!("{}.partaa, {}.partab, … (for cat)", base, base),
+            Some(Compat::Split),
+        ),
+    ];
+    let mut options = BTreeMap::new();
+    for (label, _) in &choices {
+        options.insert(label.clone(), "action");
+    }
+    options.insert("Back".to_string(), "back");
+    let choice = list_prompt("Chunk names:", &options)?;
+    Ok(choices
+        .into_iter()
+        .find(|(label, _)| *label == choice)
+        .map(|(_, compat)| compat))
+}
+
+// How a file of `size` bytes splits into chunks of `chunk_size`, as shown once the size
+// is chosen: how many there are, how large the last is and what they are called.
+fn chunk_plan(input_path: &Path, size: u64, chunk_size: u64, compat: Option<Compat>) -> String {
+    let count = size.div_ceil(chunk_size);
+    if count == 0 {
+        return "The file is empty, so there are no chunks to write.".to_string();
+    }
+    let (first, last) = chunk_names(input_path, count, compat);
+    let remainder = size - (count - 1) * chunk_size;
+    match (count, remainder == chunk_size) {
+        (1, _) => format!("1 chunk of {}: {}", format_size(size), first),
+        (_, true) => format!(
+            "{} chunks of {}: {} to {}",
+            count,
+            format_size(chunk_size),
+            first,
+            last
+        ),
+        _ => format!(
+            "{} chunks: {} of {} and a last one of {}, {} to {}",
+            count,
+            count - 1,
+            format_size(chunk_size),
+            format_size(remainder),
+            first,
+            last
+        ),
+    }
+}
+
+// A file smaller than the chunk size it is given goes into one chunk, which more often
+// than not means the size was meant in other units.
+fn single_chunk_note(size: u64, chunk_size: u64) -> Option<String> {
+    (size > 0 && size < chunk_size).then(|| {
+        format!(
+            "the file ({}) is smaller than one chunk ({}); a single chunk will be created. \
+             Did you mean a smaller size?",
+            format_size(size),
+            format_size(chunk_size)
+        )
+    })
+}
+
+// Splitting onto the disk the file is on, with less free than the chunks take, fills it
+// up partway through, as the original is still there until it is deleted. What to say
+// then; None when there is room, or no telling.
+fn shared_disk_warning(options: &SplitOptions) -> Option<String> {
+    let (input_path, savedir) = (options.input.as_path(), options.destination.as_path());
+    if is_stream(input_path) || is_remote(savedir) {
+        return None;
+    }
+    let size = options.input_len().ok()??;
+    let existing = savedir.ancestors().find(|dir| dir.exists());
+    let free = free_space(existing.unwrap_or(Path::new(".")))?;
+    if free >= size || same_file_system(input_path, savedir) != Some(true) {
+        return None;
+    }
+    Some(format!(
+        "{} is on the same disk as {}, which has {} free, and the chunks take {}. The \
+         original stays there alongside them until you delete it, so the disk will fill \
+         up partway through.",
+        savedir.display(),
+        input_path.display(),
+        format_size(free),
+        format_size(size)
+    ))
+}
+
+// The names of the first and last of `count` chunks of `input_path`.
+fn chunk_names(input_path: &Path, count: u64, compat: Option<Compat>) -> (String, String) {
+    // More than a 32-bit target can count is refused by the split itself
+    let count = usize::try_from(count).unwrap_or(usize::MAX);
+    let last = count.max(1) - 1;
+    match compat {
+        Some(compat) => {
+            let base = input_path.file_name().unwrap_or_default().to_string_lossy();
+            (
+                compat.chunk_name(&base, 0, count),
+                compat.chunk_name(&base, last, count),
+            )
+        }
+        None => (
+            store::chunk_name(0, Compression::None),
+            store::chunk_name(last, Compression::None),
+        ),
+    }
+}
+
+fn print_trashed(trashed: Option<&trash::Trashed>) {
+    if let Some(trashed) = trashed {
+        println!(
+            "The file that was there is now in {}; undo-last puts it back.",
+            trashed.location()
+        );
+    }
+}
+
+fn print_reconstruct_plan(plan: &ReconstructPlan) {
+    let name = plan.output.file_name().unwrap_or(plan.output.as_os_str());
+    let full_path = std::path::absolute(&plan.output).unwrap_or_else(|_| plan.output.clone());
+    println!("\nAbout to reconstruct:");
+    println!("  Output:          {}", name.to_string_lossy());
+    println!("  Written to:      {}", full_path.display());
+    println!("  Size:            {}", format_size(plan.total_size));
+    println!("  Chunks read:     {}", plan.chunks);
+    match plan.verifies {
+        true => println!("  Hashes:          checked against info.json"),
+        false => println!("  Hashes:          not checked; verify the set first for that"),
+    }
+    if plan.overwrites {
+        println!("  Overwrites:      yes, the file already there goes to the trash");
+    }
+}
+
+// The files a dry run found would be written or moved, one to a line.
+fn print_changes(changes: &[FileChange]) {
+    if changes.is_empty() {
+        println!("No files would change.");
+        return;
+    }
+    println!("Files that would change:");
+    for change in changes {
+        let kind = match change.kind {
+            ChangeKind::Create => "create",
+            ChangeKind::Modify => "modify",
+            ChangeKind::Move => "move",
+        };
+        let to = match &change.to {
+            Some(to) => format!(" to {}", to.display()),
+            None => String::new(),
+        };
+        let size = match change.size {
+            Some(size) => format_size(size),
+            None => "size not known until written".to_string(),
+        };
+        println!("  {:<6}  {}{} ({})", kind, change.path.display(), to, size);
+    }
+    println!("Nothing was changed: this was a dry run.");
+}
+
+// Print what the dry run of `operation` found, or fail as the operation itself would.
+fn finish_dry_run(
+    operation: &str,
+    planned: Result<Vec<FileChange>, SplitterError>,
+    progress: Option<ProgressFormat>,
+) {
+    let mut json = (progress == Some(ProgressFormat::Json)).then(|| JsonProgress::new(None));
+    match planned {
+        Ok(changes) => {
+            if let Some(json) = &mut json {
+                json.planned(&changes);
+            }
+            print_changes(&changes);
+        }
+        Err(e) => {
+            if let Some(json) = &mut json {
+                json.fail(&e, exit_code(&e));
+            }
+            eprintln!("Error during the {} dry run: {}", operation, e);
+            exit(exit_code(&e));
+        }
+    }
+}
+
+fn print_split_summary(input_path: &Path, savedir: &Path, chunk_size: u64, compat: Option<Compat>) {
+    println!("\nAbout to split:");
+    println!("  Source:          {}", input_path.display());
+    if is_stream(input_path) {
+        println!("  Size:            unknown (a pipe, split as it arrives)");
+        println!("  Destination:     {}", savedir.display());
+        println!("  Chunk size:      {}", format_size(chunk_size));
+        return;
+    }
+    match fs::metadata(input_path) {
+        Ok(metadata) => {
+            let size = metadata.len();
+            println!("  Size:            {}", format_size(size));
+            println!("  Destination:     {}", savedir.display());
+            println!("  Chunk size:      {}", format_size(chunk_size));
+            let count = size.div_ceil(chunk_size);
+            println!("  Chunks:          {}", count);
+            if count > 0 {
+                match chunk_names(input_path, count, compat) {
+                    (first, _) if count == 1 => println!("  Named:           {}", first),
+                    (first, last) => println!("  Named:           {} to {}", first, last),
                 }
             }
+            println!("  Disk usage:      {} (approx.)", format_size(size));
+        }
+        Err(e) => {
+            println!("  Size:            unknown ({})", e);
+            println!("  Destination:     {}", savedir.display());
+            println!("  Chunk size:      {}", format_size(chunk_size));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_paths_lose_their_quotes_and_surrounding_whitespace() {
+        for typed in [
+            "'/data/big file.iso'",
+            "\"/data/big file.iso\"",
+            "  /data/big file.iso \r\n",
+            "\t\"/data/big file.iso\"\r",
+        ] {
+            assert_eq!(
+                normalize_path_input(typed),
+                Path::new("/data/big file.iso"),
+                "{:?}",
+                typed
+            );
+        }
+        // Only a matching pair is taken off
+        assert_eq!(normalize_path_input("'it\"s'"), Path::new("it\"s"));
+        assert_eq!(normalize_path_input("\"half"), Path::new("\"half"));
+    }
+
+    #[test]
+    fn a_trailing_slash_names_the_same_directory() {
+        assert_eq!(normalize_path_input("/data/sets/"), Path::new("/data/sets"));
+        assert_eq!(
+            normalize_path_input("'/data/sets/' "),
+            Path::new("/data/sets")
+        );
+    }
+
+    #[test]
+    fn a_leading_tilde_is_the_home_directory() {
+        let Some(home) = home_dir() else {
+            return;
+        };
+        assert_eq!(normalize_path_input("~"), home);
+        assert_eq!(normalize_path_input(" ~/ "), home);
+        assert_eq!(
+            normalize_path_input("'~/Downloads/big.iso'"),
+            home.join("Downloads/big.iso")
+        );
+        // Not in the middle of a path
+        assert_eq!(normalize_path_input("a/~/b"), Path::new("a/~/b"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn another_users_tilde_is_their_home_directory() {
+        assert_eq!(normalize_path_input("~root/x"), Path::new("/root/x"));
+        // Left as it is when there is no such user
+        let unknown = "~no-such-user-here/x";
+        assert_eq!(normalize_path_input(unknown), Path::new(unknown));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn escapes_from_a_dragged_file_are_undone_on_unix() {
+        assert_eq!(
+            normalize_path_input("/Users/me/My\\ Files/big\\ \\(1\\).iso\n"),
+            Path::new("/Users/me/My Files/big (1).iso")
+        );
+        // Quoted paths are taken as they are
+        assert_eq!(normalize_path_input("'a\\ b'"), Path::new("a\\ b"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn backslashes_are_separators_on_windows() {
+        assert_eq!(
+            normalize_path_input("\"C:\\Users\\me\\big file.iso\"\r\n"),
+            Path::new("C:\\Users\\me\\big file.iso")
+        );
+        if let Some(home) = home_dir() {
+            assert_eq!(
+                normalize_path_input("~\\Downloads\\"),
+                home.join("Downloads")
+            );
+        }
+    }
+
+    #[test]
+    fn sets_are_found_nearest_first_through_a_loop_of_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let mut deep = root.to_path_buf();
+        for level in 1..=FIND_DEPTH {
+            deep.push(level.to_string());
+        }
+        for set in [root.join("a/b/set"), root.join("near"), deep.clone()] {
+            fs::create_dir_all(&set).unwrap();
+            fs::write(set.join(MANIFEST_NAME), "{}").unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root, root.join("a/up")).unwrap();
+
+        let (found, complete) = find_sets(root, &CancelToken::new());
+        assert_eq!(
+            found,
+            [root.join("near"), root.join("a/b/set"), deep.clone()]
+        );
+        assert!(complete);
+
+        fs::create_dir(deep.join("further")).unwrap();
+        let (found, complete) = find_sets(root, &CancelToken::new());
+        assert_eq!(found.len(), 3);
+        assert!(!complete);
+    }
+
+    // The browser lists a directory through list_directory, and goes back where it came
+    // from when that fails, so what matters is that it fails rather than panics, saying
+    // which directory it couldn't open.
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_and_vanished_directories_are_errors_naming_them() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Permissions don't stop root
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let locked = dir.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        let result = list_directory(&locked, SymlinkPolicy::default(), false);
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        match result {
+            Err(SplitterError::Io { path, source, .. }) => {
+                assert_eq!(path, locked);
+                assert_eq!(source.kind(), io::ErrorKind::PermissionDenied);
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("a directory of mode 000 was listed"),
+        }
+
+        let gone = dir.path().join("gone");
+        fs::create_dir(&gone).unwrap();
+        fs::remove_dir(&gone).unwrap();
+        match list_directory(&gone, SymlinkPolicy::default(), false) {
+            Err(SplitterError::Io { path, source, .. }) => {
+                assert_eq!(path, gone);
+                assert_eq!(source.kind(), io::ErrorKind::NotFound);
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("a removed directory was listed"),
+        }
+    }
+
+    // Names that aren't UTF-8 are listed by their real paths, and two that read the same
+    // with replacement characters still get a label each.
+    #[cfg(unix)]
+    #[test]
+    fn names_that_are_not_utf8_are_listed_apart() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let names = [OsStr::from_bytes(b"old\xff"), OsStr::from_bytes(b"old\xfe")];
+        for name in names {
+            if fs::create_dir(dir.path().join(name)).is_err() {
+                return;
+            }
         }
-        "Exit" => exit(0),
-        _ => {}
+        let listing = list_directory(dir.path(), SymlinkPolicy::default(), false).unwrap();
+        let mut listed: Vec<_> = listing
+            .subdirectories
+            .iter()
+            .map(|path| path.file_name().unwrap())
+            .collect();
+        listed.sort();
+        assert_eq!(listed, [names[1], names[0]]);
+
+        let mut taken = BTreeSet::new();
+        let labels: Vec<String> = names
+            .iter()
+            .map(|name| unique_label(&mut taken, name.to_string_lossy().into_owned()))
+            .collect();
+        assert_eq!(labels, ["old\u{fffd}", "old\u{fffd} (2)"]);
+        assert_eq!(
+            default_savedir(&dir.path().join(names[0])),
+            Path::new(".").join(OsStr::from_bytes(b"old\xff.chunks"))
+        );
     }
 }
@@ -0,0 +1,913 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Write};
+use std::ops::{Range, RangeInclusive};
+use std::path::{Component, Path, PathBuf};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::cancel::CancelToken;
+use crate::compat::Compat;
+use crate::crypt::{self, ChunkKey, Encryption};
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::Counting;
+use crate::pipeline::{Io, copy_overlapped};
+use crate::scratch::Staged;
+use crate::store::{ChunkReader, is_shard_dir};
+use crate::unicode::Normalization;
+use crate::{chunk_index, first_time};
+
+pub const MANIFEST_NAME: &str = "info.json";
+
+// Written into every new manifest. Manifests from before versions were recorded read
+// as version 0.
+pub const MANIFEST_VERSION: u32 = 1;
+
+// The field of info.json holding its seal: the SHA-256, in hex, of every other field,
+// written out with the keys of every object in order and no whitespace. It is written
+// with every manifest and checked whenever one is read, so that an edit by hand, or
+// damage, is told apart from damage to the chunks. `reseal` seals a manifest again
+// after edits that were meant.
+pub const SEAL_FIELD: &str = "meta_checksum";
+
+// How a manifest's contents compare with its seal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Seal {
+    Intact,
+    // Changed since it was sealed
+    Broken,
+    // Written before manifests were sealed
+    Absent,
+}
+
+// Contents of `info.json`. Older chunk directories only have `original_filename`, so
+// everything else is optional when reading.
+//
+// Splits are reproducible: the same input split with the same chunk size and hash
+// gives byte-identical directories, whatever the thread count or copy path. That
+// holds as long as nothing here records when, where or how a split ran. Fields are
+// written in declaration order, there are no timestamps unless `--record-times` asks
+// for them, and the input appears only by its file name, never its path. Options
+// whose output can't be the same twice, such as encryption with random nonces or an
+// encoder that isn't deterministic, should say so where they are offered; the test
+// `splits_are_reproducible` holds the rest to it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub version: u32,
+    pub original_filename: String,
+    // The normalization form `original_filename` is in, if only one; see `unicode`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_form: Option<Normalization>,
+    // The file split was a symbolic link by this name to the one `original_filename`
+    // names
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_name: Option<String>,
+    // Only this part of the file was split, so the chunks join into an extract of it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<InputRange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<HashAlgorithm>,
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    pub compression: Compression,
+    // Every chunk is encrypted, after any compression, with a key this says how to get;
+    // see `crypt`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<Encryption>,
+    // With `--encrypt-metadata`, the whole manifest sealed under the chunks' key, in hex,
+    // in place of everything here that finding and checking the chunks doesn't need;
+    // see `conceal`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<String>,
+    // Chunks have random names rather than numbered ones, numbered ones with a prefix
+    // other than `chunk`, or the names another tool gave them (see `import`), so their
+    // order is only recorded here, as their position in `chunks`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub random_names: bool,
+    // Chunks are named for another tool to join, see `Compat`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compat: Option<Compat>,
+    // Chunks are kept this many to a subdirectory, `00/`, `01/` and so on, which their
+    // names in `chunks` include; see `ShardDirs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shards: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parity: Option<ParityInfo>,
+    // The set is spread over several directories, and this copy is one volume's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<SpanInfo>,
+    // The input's extended attributes by name, values in base64, with `--xattrs`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattrs: BTreeMap<String, String>,
+    // Who owned the input, on Unix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Owner>,
+    // When the split started, in seconds since the Unix epoch, with `--record-times`; see
+    // `timestamps`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+    // Sizes and hashes are those of the original bytes, however the chunks are stored
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<ChunkEntry>,
+}
+
+// The bytes of the input a split took, from `offset` on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputRange {
+    pub offset: u64,
+    pub length: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChunkEntry {
+    pub name: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    // The SHA-256 of the chunk's file as stored, for an encrypted set, which checks it
+    // without the key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stored_hash: Option<String>,
+    // Set when this chunk is stored differently from the rest of the set, as after it
+    // was rewritten; see `Manifest::compression_of`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    // The chunk has no file of its own: its bytes are the same as those of the chunk
+    // this names, whose file holds them for both; see `SplitOptions::dedup`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub same_as: Option<String>,
+    // How many other chunks are stored in this one's file by `same_as`, which is then
+    // not to be removed or replaced as though only this chunk needed it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared: Option<usize>,
+    // When the split last wrote the chunk's file, in seconds since the Unix epoch, with
+    // `--record-times`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<u64>,
+}
+
+impl ChunkEntry {
+    // The name of the file holding the chunk's bytes: its own, or with `same_as` the
+    // other chunk's.
+    pub fn file(&self) -> &str {
+        self.same_as.as_deref().unwrap_or(&self.name)
+    }
+}
+
+// The parity files written next to the chunks. Chunks are taken in stripes of
+// `stripe_chunks` consecutive ones, chunk `i` belonging to stripe `i / stripe_chunks`,
+// and each stripe has `scheme.shards()` parity files of its own, listed in `files`
+// stripe after stripe. Every parity file is `size` bytes long, the length of the
+// longest chunk, shorter chunks counting as padded with zeros.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ParityInfo {
+    #[serde(flatten)]
+    pub scheme: Parity,
+    pub stripe_chunks: usize,
+    pub size: u64,
+    pub files: Vec<ParityEntry>,
+}
+
+impl ParityInfo {
+    // The stripe chunk `index` belongs to, and its place within it.
+    pub fn stripe_of(&self, index: usize) -> (usize, usize) {
+        let chunks = self.stripe_chunks.max(1);
+        (index / chunks, index % chunks)
+    }
+
+    // The parity files of `stripe`, fewer than `scheme.shards()` only if the list was
+    // cut short.
+    pub fn stripe_files(&self, stripe: usize) -> &[ParityEntry] {
+        let shards = self.scheme.shards();
+        let start = (stripe * shards).min(self.files.len());
+        &self.files[start..(start + shards).min(self.files.len())]
+    }
+}
+
+// One parity file; `hash` is of the file as stored.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ParityEntry {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+// The owner and group of a file split on Unix, by number and, where the system could
+// name them, by name, which is what carries over to another system; see `owner`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+// Where a split spread over several directories put its chunks, in the info.json each
+// of them has; see `Span`. Volumes are numbered from 0 in the order they were filled,
+// each holding `count` consecutive chunks from `first` on, and `volume` is the one this
+// copy is on. Which directories they were is left out: drives are mounted somewhere
+// else by the time the set is put back together.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpanInfo {
+    pub volume: usize,
+    pub volumes: Vec<VolumeEntry>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeEntry {
+    pub first: usize,
+    pub count: usize,
+}
+
+impl VolumeEntry {
+    pub fn chunks(self) -> Range<usize> {
+        self.first..self.first + self.count
+    }
+}
+
+impl SpanInfo {
+    // The chunks this copy's volume holds.
+    pub fn here(&self) -> Range<usize> {
+        self.volumes
+            .get(self.volume)
+            .map_or(0..0, |volume| volume.chunks())
+    }
+
+    // The volume chunk `index` is on.
+    pub fn volume_of(&self, index: usize) -> Option<usize> {
+        self.volumes
+            .iter()
+            .position(|volume| volume.chunks().contains(&index))
+    }
+}
+
+impl Manifest {
+    // Each entry with the index of its chunk: the number in its name, or with random
+    // names (or a name without one) its position in the list. A chunk in a shard
+    // subdirectory goes by the number in its file's name.
+    pub fn indexed(&self) -> impl Iterator<Item = (usize, &ChunkEntry)> {
+        self.chunks.iter().enumerate().map(|(position, entry)| {
+            let index = match self.random_names {
+                true => None,
+                false => chunk_index(&entry.name).and_then(|index| usize::try_from(index).ok()),
+            };
+            (index.unwrap_or(position), entry)
+        })
+    }
+
+    // What a reconstruction is named by default: `original_filename`, or for an extract
+    // of it that with the range the extract is of, as in `disk.img.4096-1048576`. Fails
+    // with `UnsafeName` when that isn't a plain name, as for a manifest put together by
+    // hand rather than read, which `parse` would have checked.
+    pub fn output_name(&self) -> Result<String> {
+        if self.concealed() {
+            return Err(SplitterError::Encrypted {
+                path: PathBuf::from(MANIFEST_NAME),
+            });
+        }
+        if !is_plain_name(&self.original_filename) {
+            return Err(SplitterError::UnsafeName {
+                path: PathBuf::from(MANIFEST_NAME),
+                name: self.original_filename.clone(),
+            });
+        }
+        Ok(match self.range {
+            Some(range) => format!(
+                "{}.{}-{}",
+                self.original_filename, range.offset, range.length
+            ),
+            None => self.original_filename.clone(),
+        })
+    }
+
+    // Whether any chunk is stored in another's file, so that chunks have to be found by
+    // the names of their files rather than their numbers.
+    pub fn shares_files(&self) -> bool {
+        self.chunks.iter().any(|entry| entry.same_as.is_some())
+    }
+
+    // The shard subdirectories the chunks are in, with `shards`.
+    pub fn shard_dirs(&self) -> BTreeSet<&str> {
+        self.chunks
+            .iter()
+            .filter_map(|entry| Some(entry.name.split_once('/')?.0))
+            .filter(|directory| is_shard_dir(directory))
+            .collect()
+    }
+
+    // How `entry`'s chunk is stored: its own codec if it names one, the set's otherwise.
+    pub fn compression_of(&self, entry: &ChunkEntry) -> Compression {
+        entry.compression.unwrap_or(self.compression)
+    }
+
+    // Whether `entry`'s file holds the chunk's bytes as they are, neither compressed nor
+    // encrypted, so that its length is the chunk's.
+    pub fn stored_as_is(&self, entry: &ChunkEntry) -> bool {
+        self.compression_of(entry).is_none() && self.encryption.is_none()
+    }
+
+    // Whether all but what finding and checking the chunks takes is sealed in
+    // `metadata`, for the key to `reveal`.
+    pub fn concealed(&self) -> bool {
+        self.metadata.is_some()
+    }
+
+    // This manifest as info.json holds it with `--encrypt-metadata`: all of it sealed
+    // under `key` in `metadata`, and beside that only the chunks' names, how they are
+    // stored and the hashes of their files, and what getting the key again takes. A
+    // chunk's size is then that of its file in `directory`, which gives away nothing
+    // the file doesn't.
+    pub fn conceal(&self, key: &ChunkKey, directory: &Path) -> Result<Manifest> {
+        let path = directory.join(MANIFEST_NAME);
+        let data = serde_json::to_vec(self)
+            .map_err(io::Error::other)
+            .at(&path)?;
+        let sealed = crypt::seal(key, &data).at(&path)?;
+        let mut chunks = Vec::with_capacity(self.chunks.len());
+        for entry in &self.chunks {
+            let file = directory.join(entry.file());
+            chunks.push(ChunkEntry {
+                name: entry.name.clone(),
+                size: fs::metadata(&file).at(&file)?.len(),
+                hash: None,
+                stored_hash: entry.stored_hash.clone(),
+                compression: entry.compression,
+                same_as: entry.same_as.clone(),
+                shared: entry.shared,
+                mtime: None,
+            });
+        }
+        Ok(Manifest {
+            version: self.version,
+            original_filename: String::new(),
+            name_form: None,
+            link_name: None,
+            range: None,
+            chunk_size: None,
+            hash: None,
+            compression: self.compression,
+            encryption: self.encryption.clone(),
+            metadata: Some(crypt::hex(&sealed)),
+            random_names: self.random_names,
+            compat: None,
+            shards: self.shards,
+            parity: None,
+            span: None,
+            xattrs: BTreeMap::new(),
+            owner: None,
+            created: None,
+            chunks,
+        })
+    }
+
+    // The manifest `conceal` sealed in this one, read from `path`, opened with `key`;
+    // one with nothing concealed as it is. Another key fails with `WrongKey` as for
+    // the chunks, and a blob that doesn't open with `Undecryptable`.
+    pub fn reveal(self, key: &ChunkKey, path: &Path) -> Result<Manifest> {
+        let Some(metadata) = &self.metadata else {
+            return Ok(self);
+        };
+        if let Some(encryption) = &self.encryption {
+            key.check(encryption, path.parent().unwrap_or(Path::new(".")))?;
+        }
+        let corrupt = |source| SplitterError::MetadataCorrupt {
+            path: path.to_path_buf(),
+            source,
+        };
+        let sealed = crypt::unhex(metadata)
+            .ok_or_else(|| corrupt(serde::de::Error::custom("metadata is not hex")))?;
+        let data = crypt::open(key, &sealed).at(path)?;
+        let manifest: Manifest = serde_json::from_slice(&data).map_err(corrupt)?;
+        manifest.check_names(path)?;
+        Ok(manifest)
+    }
+
+    // `load`, opening concealed metadata with `key`, without which that fails with
+    // `Encrypted`.
+    pub fn load_revealed(
+        directory: &Path,
+        accept_modified: bool,
+        key: Option<&ChunkKey>,
+    ) -> Result<Option<Manifest>> {
+        let path = directory.join(MANIFEST_NAME);
+        match (Manifest::load(directory, accept_modified)?, key) {
+            (Some(manifest), Some(key)) => manifest.reveal(key, &path).map(Some),
+            (Some(manifest), None) if manifest.concealed() => Err(SplitterError::Encrypted {
+                path: directory.to_path_buf(),
+            }),
+            (manifest, _) => Ok(manifest),
+        }
+    }
+
+    // None when the directory has no manifest; an error when it has one we can't read,
+    // or one that was changed after it was sealed unless `accept_modified`.
+    pub fn load(directory: &Path, accept_modified: bool) -> Result<Option<Manifest>> {
+        let path = directory.join(MANIFEST_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read(&path).at(&path)?;
+        Manifest::parse(&data, &path, accept_modified).map(Some)
+    }
+
+    // `load` that leaves what to make of the seal to the caller.
+    pub fn load_sealed(directory: &Path) -> Result<Option<(Manifest, Seal)>> {
+        let path = directory.join(MANIFEST_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read(&path).at(&path)?;
+        Manifest::parse_sealed(&data, &path).map(Some)
+    }
+
+    // The manifest in `data`, read from `path`, failing with `MetadataModified` if its
+    // seal doesn't match unless `accept_modified`, as `--accept-modified-metadata` asks.
+    pub fn parse(data: &[u8], path: &Path, accept_modified: bool) -> Result<Manifest> {
+        let (manifest, seal) = Manifest::parse_sealed(data, path)?;
+        match seal {
+            Seal::Intact => {}
+            Seal::Broken if !accept_modified => {
+                return Err(SplitterError::MetadataModified {
+                    path: path.to_path_buf(),
+                });
+            }
+            _ if !first_time(path) => {}
+            Seal::Absent => info!(
+                "{} has no {}, so changes to it can't be told from damage; reseal adds one",
+                path.display(),
+                SEAL_FIELD
+            ),
+            Seal::Broken => warn!(
+                "{} has been modified since it was sealed; going on with it as it is",
+                path.display()
+            ),
+        }
+        Ok(manifest)
+    }
+
+    pub fn parse_sealed(data: &[u8], path: &Path) -> Result<(Manifest, Seal)> {
+        let corrupt = |source| SplitterError::MetadataCorrupt {
+            path: path.to_path_buf(),
+            source,
+        };
+        let mut value: Value = serde_json::from_slice(data).map_err(corrupt)?;
+        let recorded = value
+            .as_object_mut()
+            .and_then(|fields| fields.remove(SEAL_FIELD));
+        let seal = match recorded {
+            None => Seal::Absent,
+            Some(Value::String(recorded)) if recorded == seal_of(&value) => Seal::Intact,
+            Some(_) => Seal::Broken,
+        };
+        let manifest: Manifest = serde_json::from_value(value).map_err(corrupt)?;
+        manifest.check_names(path)?;
+        Ok((manifest, seal))
+    }
+
+    // Fails with `UnsafeName` for a name in the manifest that would lead out of the
+    // directory it is joined to: anything but a single plain component, or for a chunk
+    // one in a shard subdirectory. Manifests can come from anywhere, as from a server
+    // or an archive, so this is checked before any name is used.
+    fn check_names(&self, path: &Path) -> Result<()> {
+        let unsafe_name = |name: &str| SplitterError::UnsafeName {
+            path: path.to_path_buf(),
+            name: name.to_string(),
+        };
+        // Concealed metadata leaves no name to check until it is revealed
+        let original = Some(&self.original_filename).filter(|_| !self.concealed());
+        let names = [original, self.link_name.as_ref()];
+        if let Some(name) = names
+            .into_iter()
+            .flatten()
+            .find(|name| !is_plain_name(name))
+        {
+            return Err(unsafe_name(name));
+        }
+        let chunk_names = self
+            .chunks
+            .iter()
+            .flat_map(|entry| std::iter::once(&entry.name).chain(entry.same_as.as_ref()));
+        let parity_names = self.parity.iter().flat_map(|parity| &parity.files);
+        match chunk_names
+            .chain(parity_names.map(|entry| &entry.name))
+            .find(|name| !is_chunk_name(name))
+        {
+            Some(name) => Err(unsafe_name(name)),
+            None => Ok(()),
+        }
+    }
+
+    // What info.json holds for this manifest: its fields in declaration order, then
+    // the seal over them.
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        let meta_checksum = seal_of(&serde_json::to_value(self)?);
+        serde_json::to_string(&Sealed {
+            manifest: self,
+            meta_checksum,
+        })
+    }
+
+    pub fn save(&self, directory: &Path) -> Result<()> {
+        let path = directory.join(MANIFEST_NAME);
+        let data = self.to_json().map_err(io::Error::other).at(&path)?;
+        fs::write(&path, data).doing("writing", &path)
+    }
+
+    // `save` through a temporary file flushed to disk and renamed over info.json, so
+    // that whatever happens the directory holds the old manifest or the new one whole.
+    pub fn replace(&self, directory: &Path) -> Result<()> {
+        let path = directory.join(MANIFEST_NAME);
+        let data = self.to_json().map_err(io::Error::other).at(&path)?;
+        let (mut file, staged) = Staged::create(&path, None)?;
+        file.write_all(data.as_bytes())
+            .and_then(|()| file.sync_all())
+            .doing("writing", &path)?;
+        drop(file);
+        staged.persist(&path)
+    }
+}
+
+// Whether `name` is a single plain path component on any platform: not empty, `.` or
+// `..`, with no separator of either kind and no drive letter, so that joined to a
+// directory it stays in it.
+pub fn is_plain_name(name: &str) -> bool {
+    let drive =
+        name.len() >= 2 && name.as_bytes()[0].is_ascii_alphabetic() && name.as_bytes()[1] == b':';
+    !drive
+        && !name.contains(['/', '\\', '\0'])
+        && matches!(
+            Path::new(name).components().collect::<Vec<_>>()[..],
+            [Component::Normal(_)]
+        )
+}
+
+// `is_plain_name` for a chunk or parity file, which may be in a shard subdirectory.
+fn is_chunk_name(name: &str) -> bool {
+    match name.split_once('/') {
+        Some((directory, file)) => is_shard_dir(directory) && is_plain_name(file),
+        None => is_plain_name(name),
+    }
+}
+
+#[derive(Serialize)]
+struct Sealed<'a> {
+    #[serde(flatten)]
+    manifest: &'a Manifest,
+    meta_checksum: String,
+}
+
+fn seal_of(value: &Value) -> String {
+    let mut canonical = String::new();
+    write_canonical(value, &mut canonical);
+    let mut hasher = HashAlgorithm::Sha256.hasher();
+    hasher.update(canonical.as_bytes());
+    hasher.finish()
+}
+
+// `value` as JSON with the keys of every object in order and no whitespace, the same
+// however the file it came from was laid out.
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Object(fields) => {
+            let mut keys: Vec<&String> = fields.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::from(key.as_str()).to_string());
+                out.push(':');
+                write_canonical(&fields[key], out);
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        other => out.push_str(&other.to_string()),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Sha256,
+}
+
+// Redundancy written next to the chunks so damaged ones can be rebuilt. `Xor` is one
+// extra file over the whole set that covers the loss of any single chunk.
+// `ReedSolomon` writes `shards` files per stripe and covers the loss of as many chunks
+// of that stripe; stripes hold as many chunks as the code allows, up to 256 chunks and
+// parity files together, with the reed-solomon-erasure crate's code; see `parity`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "scheme", rename_all = "lowercase")]
+pub enum Parity {
+    Xor,
+    #[serde(rename = "rs")]
+    ReedSolomon {
+        shards: usize,
+    },
+}
+
+// Parity files per stripe `ReedSolomon` accepts.
+pub const MAX_PARITY_SHARDS: usize = 128;
+
+impl Parity {
+    // Parity files per stripe.
+    pub fn shards(self) -> usize {
+        match self {
+            Parity::Xor => 1,
+            Parity::ReedSolomon { shards } => shards,
+        }
+    }
+
+    // Chunks per stripe for a set of `chunks`. Stripes are made as even as they can be,
+    // so a set just over the limit isn't left with a stripe of one.
+    pub fn stripe_chunks(self, chunks: usize) -> usize {
+        let most = match self {
+            Parity::Xor => return chunks.max(1),
+            Parity::ReedSolomon { shards } => 256 - shards.min(255),
+        };
+        let stripes = chunks.div_ceil(most).max(1);
+        chunks.div_ceil(stripes).max(1)
+    }
+}
+
+// How chunk contents are stored. Compressed chunks carry the codec's extension, as in
+// `chunk000.gz`, but reading them goes by what the manifest says. `Armor` doesn't
+// compress at all: it stores chunks as base64 text, see `armor`. `Zstd` keeps its level
+// and window with it, as `{"zstd": {"level": 3, "long": false}}`; see `zst`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Armor,
+    Zstd {
+        level: u32,
+        // Long-distance matching over a 128 MiB window, as `zstd --long`
+        #[serde(default)]
+        long: bool,
+    },
+}
+
+impl Compression {
+    // Zstandard at its default level, with the normal window.
+    pub const ZSTD: Compression = Compression::Zstd {
+        level: 3,
+        long: false,
+    };
+
+    pub fn is_none(&self) -> bool {
+        *self == Compression::None
+    }
+
+    // The codec's name, as `--compress` takes it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Armor => "armor",
+            Compression::Zstd { .. } => "zstd",
+        }
+    }
+
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gz"),
+            Compression::Armor => Some("txt"),
+            Compression::Zstd { .. } => Some("zst"),
+        }
+    }
+
+    pub(crate) fn from_extension(extension: &str) -> Option<Compression> {
+        match extension {
+            "gz" => Some(Compression::Gzip),
+            "txt" => Some(Compression::Armor),
+            "zst" => Some(Compression::ZSTD),
+            _ => None,
+        }
+    }
+
+    // Whether the codec makes chunks smaller, so that those it hardly does are better
+    // stored as they are.
+    pub fn shrinks(self) -> bool {
+        matches!(self, Compression::Gzip | Compression::Zstd { .. })
+    }
+
+    // Levels the codec accepts, higher meaning smaller and slower.
+    pub fn levels(self) -> RangeInclusive<u32> {
+        match self {
+            Compression::None | Compression::Armor => 0..=0,
+            Compression::Gzip => 1..=9,
+            Compression::Zstd { .. } => 1..=22,
+        }
+    }
+
+    // The level a split at no level in particular compresses at; zstd's own.
+    pub fn default_level(self) -> u32 {
+        match self {
+            Compression::None | Compression::Armor => 0,
+            Compression::Gzip => 6,
+            Compression::Zstd { level, .. } => level,
+        }
+    }
+
+    // The same codec at `level`, for a level given apart from it.
+    pub fn at_level(self, level: u32) -> Compression {
+        match self {
+            Compression::Zstd { long, .. } => Compression::Zstd { level, long },
+            other => other,
+        }
+    }
+}
+
+impl HashAlgorithm {
+    pub fn hasher(self) -> ChunkHasher {
+        match self {
+            HashAlgorithm::Sha256 => ChunkHasher::Sha256(Sha256::new()),
+        }
+    }
+}
+
+pub enum ChunkHasher {
+    Sha256(Sha256),
+}
+
+impl ChunkHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            ChunkHasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    // Lowercase hex, the form `sha256sum` prints.
+    pub fn finish(self) -> String {
+        let digest = match self {
+            ChunkHasher::Sha256(hasher) => hasher.finalize().to_vec(),
+        };
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+// Hash a whole file, e.g. a chunk being checked against its recorded hash. `copied`
+// hears about each buffer read; `cancel` stops it between buffers.
+pub fn hash_file(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    copied: &mut dyn FnMut(u64),
+    cancel: &CancelToken,
+) -> Result<String> {
+    let mut hasher = algorithm.hasher();
+    let mut file = fs::File::open(path).at(path)?;
+    let mut sink = Counting {
+        inner: io::sink(),
+        copied,
+        cancel,
+    };
+    copy_overlapped(&Io::default(), &mut file, &mut sink, Some(&mut hasher)).at(path)?;
+    Ok(hasher.finish())
+}
+
+// `hash_file` for a compressed chunk: the hash of what it decodes to.
+pub fn hash_compressed(
+    path: &Path,
+    compression: Compression,
+    algorithm: HashAlgorithm,
+    copied: &mut dyn FnMut(u64),
+    cancel: &CancelToken,
+) -> Result<String> {
+    let mut hasher = algorithm.hasher();
+    let mut reader = ChunkReader::open(path, compression).at(path)?;
+    let mut sink = Counting {
+        inner: io::sink(),
+        copied,
+        cancel,
+    };
+    copy_overlapped(&Io::default(), &mut reader, &mut sink, Some(&mut hasher)).at(path)?;
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Result<Manifest> {
+        Manifest::parse_sealed(json.as_bytes(), Path::new("info.json")).map(|(m, _)| m)
+    }
+
+    fn with_name(name: &str) -> String {
+        serde_json::json!({ "original_filename": name }).to_string()
+    }
+
+    fn with_chunk(name: &str) -> String {
+        serde_json::json!({
+            "original_filename": "file.bin",
+            "chunks": [{ "name": name, "size": 1 }]
+        })
+        .to_string()
+    }
+
+    fn refused(json: &str, name: &str) {
+        match parse(json) {
+            Err(SplitterError::UnsafeName { name: refused, .. }) => assert_eq!(refused, name),
+            other => panic!("{} was let through: {:?}", name, other.map(|m| m.chunks)),
+        }
+    }
+
+    #[test]
+    fn original_filename_has_to_be_a_plain_name() {
+        for name in [
+            "/tmp/rv/pwned_out",
+            "../escape.bin",
+            "..",
+            ".",
+            "",
+            "dir/file",
+            "dir\\file",
+            "C:file",
+            "C:\\file",
+            "\\\\server\\share",
+        ] {
+            refused(&with_name(name), name);
+        }
+        assert_eq!(
+            parse(&with_name("disk..img")).unwrap().original_filename,
+            "disk..img"
+        );
+    }
+
+    #[test]
+    fn chunk_names_stay_in_their_directory() {
+        for name in [
+            "../pwned_chunk",
+            "/tmp/chunk000",
+            "sub/chunk000",
+            "00/../chunk000",
+            "00/sub/chunk000",
+            "..\\chunk000",
+        ] {
+            refused(&with_chunk(name), name);
+        }
+        assert!(parse(&with_chunk("chunk000")).is_ok());
+        // In a shard subdirectory
+        assert!(parse(&with_chunk("00/chunk000")).is_ok());
+    }
+
+    #[test]
+    fn names_other_files_go_by_are_checked_too() {
+        let same_as = serde_json::json!({
+            "original_filename": "file.bin",
+            "chunks": [{ "name": "chunk0", "size": 1, "same_as": "../elsewhere" }]
+        });
+        refused(&same_as.to_string(), "../elsewhere");
+        let link = serde_json::json!({ "original_filename": "file.bin", "link_name": "/tmp/link" });
+        refused(&link.to_string(), "/tmp/link");
+        let parity = serde_json::json!({
+            "original_filename": "file.bin",
+            "parity": {
+                "scheme": "xor",
+                "stripe_chunks": 1,
+                "size": 1,
+                "files": [{ "name": "../parity0" }]
+            }
+        });
+        refused(&parity.to_string(), "../parity0");
+    }
+
+    #[test]
+    fn a_broken_seal_is_only_gone_by_when_asked() {
+        let path = Path::new("broken/info.json");
+        let json = serde_json::json!({ "original_filename": "file.bin", SEAL_FIELD: "00" });
+        let data = json.to_string();
+        assert!(matches!(
+            Manifest::parse(data.as_bytes(), path, false),
+            Err(SplitterError::MetadataModified { .. })
+        ));
+        let manifest = Manifest::parse(data.as_bytes(), path, true).unwrap();
+        assert_eq!(manifest.original_filename, "file.bin");
+        // Each read asks for itself; accepting one doesn't accept the next
+        assert!(matches!(
+            Manifest::parse(data.as_bytes(), path, false),
+            Err(SplitterError::MetadataModified { .. })
+        ));
+    }
+}
@@ -0,0 +1,387 @@
+// Memory-mapped versions of split and reconstruct, used with `--mmap` when built with
+// the `mmap` feature. Both return None when mapping isn't possible (empty files,
+// address space too small, file systems that refuse) so the caller can go on with
+// buffered I/O.
+//
+// A mapped file that another process truncates makes touching the pages that are gone
+// fault with SIGBUS, which would end the process. While split reads its input through
+// a map, a handler for it puts a page of zeros where the faulting page was and marks
+// the map, and the split fails with `ChangedSize` once the chunk being read is done.
+// Pages the kernel copies from rather than this process fail the write instead, and
+// a file found shorter than its map after that fails the same way. Windows doesn't let a mapped file be truncated at all.
+// Reconstruction only maps the temporary file it writes, which nothing else knows of.
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+#[cfg(unix)]
+use self::guard::Guarded;
+
+use memmap2::{Mmap, MmapMut};
+
+use crate::cancel::CancelToken;
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::ProgressEvent;
+use crate::manifest::{ChunkEntry, Compression, HashAlgorithm};
+use crate::scratch;
+use crate::store::{ChunkStore, LocalDirStore, chunk_name, log_written};
+
+pub fn split(
+    input_path: &Path,
+    store: &LocalDirStore,
+    chunk_size: u64,
+    hash: Option<HashAlgorithm>,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Option<Vec<ChunkEntry>>> {
+    let input_file = File::open(input_path).doing("opening", input_path)?;
+    if input_file.metadata().at(input_path)?.len() == 0 {
+        return Ok(None);
+    }
+    // SAFETY: the mapping is only read; see the module comment for truncation.
+    let Ok(map) = (unsafe { Mmap::map(&input_file) }) else {
+        return Ok(None);
+    };
+    let Ok(chunk_size) = usize::try_from(chunk_size) else {
+        return Ok(None);
+    };
+    // Unguarded, a truncation would end the process
+    #[cfg(unix)]
+    let Some(guarded) = Guarded::new(&map) else {
+        return Ok(None);
+    };
+
+    let mut chunks = Vec::new();
+    for (index, data) in map.chunks(chunk_size).enumerate() {
+        cancel.check()?;
+        let size = data.len() as u64;
+        progress(ProgressEvent::ChunkStarted { index, size });
+        let written = write_chunk(store, index, data, hash);
+        // Pages gone fault where the chunk is hashed, and fail the write (EFAULT)
+        // where the kernel copies them
+        #[cfg(unix)]
+        let faulted = guarded.faulted();
+        #[cfg(not(unix))]
+        let faulted = false;
+        if faulted || written.is_err() && shorter(input_path, map.len()) {
+            return Err(SplitterError::ChangedSize {
+                path: input_path.to_path_buf(),
+            });
+        }
+        let entry = written?;
+        progress(ProgressEvent::BytesCopied { delta: size });
+        progress(ProgressEvent::ChunkFinished {
+            index,
+            hash: entry.hash.clone(),
+        });
+        chunks.push(entry);
+    }
+    Ok(Some(chunks))
+}
+
+// Whether the file at `path` is now shorter than the `len` bytes mapped of it.
+fn shorter(path: &Path, len: usize) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.len() < len as u64)
+}
+
+// Map the preallocated output and have `threads` workers read chunks straight into
+// their slice of it. Returns Some(()) once `output_path` is in place.
+pub fn reconstruct(
+    chunk_files: &[PathBuf],
+    output_path: &Path,
+    temp_dir: Option<&Path>,
+    threads: usize,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Option<()>> {
+    let mut sizes = Vec::with_capacity(chunk_files.len());
+    for chunk_path in chunk_files {
+        sizes.push(fs::metadata(chunk_path).at(chunk_path)?.len());
+    }
+    let total: u64 = sizes.iter().sum();
+    if total == 0 || usize::try_from(total).is_err() {
+        return Ok(None);
+    }
+
+    let (output_file, temp_path) = scratch::create(output_path, temp_dir)?;
+    let result = output_file.set_len(total).at(&temp_path).and_then(|_| {
+        // SAFETY: the file was just created by us and nothing else writes to it.
+        match unsafe { MmapMut::map_mut(&output_file) } {
+            Ok(map) => fill(map, chunk_files, &sizes, threads, progress, cancel)
+                .and_then(|map| map.flush().at(&temp_path))
+                .map(Some),
+            Err(_) => Ok(None),
+        }
+    });
+    drop(output_file);
+    match result {
+        Ok(Some(())) => scratch::persist(&temp_path, output_path).map(Some),
+        Ok(None) => {
+            let _ = fs::remove_file(&temp_path);
+            Ok(None)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(e)
+        }
+    }
+}
+
+fn fill(
+    mut map: MmapMut,
+    chunk_files: &[PathBuf],
+    sizes: &[u64],
+    threads: usize,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<MmapMut> {
+    // Carve the mapping into one disjoint slice per chunk
+    let mut jobs = Vec::with_capacity(chunk_files.len());
+    let mut rest: &mut [u8] = &mut map;
+    for (index, (chunk_path, &size)) in chunk_files.iter().zip(sizes).enumerate() {
+        let (slice, tail) = rest.split_at_mut(size as usize);
+        jobs.push((index, chunk_path.as_path(), slice));
+        rest = tail;
+    }
+    let jobs = Mutex::new(jobs);
+    let (sender, receiver) = std::sync::mpsc::channel::<Result<ProgressEvent>>();
+
+    let result = thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            let sender = sender.clone();
+            let jobs = &jobs;
+            scope.spawn(move || {
+                while !cancel.is_cancelled() {
+                    let Some((index, chunk_path, slice)) = jobs.lock().unwrap().pop() else {
+                        return;
+                    };
+                    let size = slice.len() as u64;
+                    let _ = sender.send(Ok(ProgressEvent::ChunkStarted { index, size }));
+                    let result = fill_slice(chunk_path, slice);
+                    let failed = result.is_err();
+                    let _ = sender.send(result.map(|delta| ProgressEvent::BytesCopied { delta }));
+                    if !failed {
+                        let _ = sender.send(Ok(ProgressEvent::ChunkFinished { index, hash: None }));
+                    }
+                    if failed {
+                        jobs.lock().unwrap().clear();
+                        return;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let mut error = None;
+        for result in receiver {
+            match result {
+                Ok(event) => progress(event),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        error.map_or_else(|| cancel.check(), Err)
+    });
+    result?;
+    Ok(map)
+}
+
+// Read one chunk into its slice, which must be exactly filled: a chunk that changed
+// size since the layout was worked out is an error rather than a gap or overlap.
+fn fill_slice(chunk_path: &Path, slice: &mut [u8]) -> Result<u64> {
+    let changed_size = || SplitterError::ChangedSize {
+        path: chunk_path.to_path_buf(),
+    };
+    let mut chunk_file = File::open(chunk_path).doing("opening chunk", chunk_path)?;
+    match chunk_file.read_exact(slice) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(changed_size()),
+        result => result.at(chunk_path)?,
+    }
+    if chunk_file.read(&mut [0u8]).at(chunk_path)? != 0 {
+        return Err(changed_size());
+    }
+    Ok(slice.len() as u64)
+}
+
+fn write_chunk(
+    store: &LocalDirStore,
+    index: usize,
+    data: &[u8],
+    hash: Option<HashAlgorithm>,
+) -> Result<ChunkEntry> {
+    let name = store.sharded_name(index, &chunk_name(index, Compression::None));
+    let chunk_path = store.chunk_path(index);
+    // Hashed first, so pages gone fault before anything of them is written
+    let hash = hash.map(|algorithm| {
+        let mut hasher = algorithm.hasher();
+        hasher.update(data);
+        hasher.finish()
+    });
+    fs::write(&chunk_path, data).at(&chunk_path)?;
+    log_written(&chunk_path, data.len() as u64, hash.as_deref());
+    Ok(ChunkEntry {
+        name,
+        size: data.len() as u64,
+        hash,
+        stored_hash: None,
+        compression: None,
+        same_as: None,
+        shared: None,
+        mtime: None,
+    })
+}
+
+// The SIGBUS handler, and the maps it looks after.
+#[cfg(unix)]
+mod guard {
+    use std::sync::Once;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use libc::{c_int, c_void, siginfo_t};
+
+    // Maps read at once, each in a slot of its own; with all of them taken, a split
+    // goes on with buffered I/O
+    const SLOTS: usize = 16;
+
+    static STARTS: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(0) }; SLOTS];
+    static ENDS: [AtomicUsize; SLOTS] = [const { AtomicUsize::new(0) }; SLOTS];
+    static FAULTED: [AtomicBool; SLOTS] = [const { AtomicBool::new(false) }; SLOTS];
+    static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+    // The handler there was before, for faults that aren't in any of the maps
+    static PREVIOUS: AtomicUsize = AtomicUsize::new(libc::SIG_DFL);
+    static PREVIOUS_INFO: AtomicBool = AtomicBool::new(false);
+    static INSTALL: Once = Once::new();
+    static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+    // A map looked after until dropped, which must be before it is unmapped.
+    pub(super) struct Guarded {
+        slot: usize,
+    }
+
+    impl Guarded {
+        // None when the handler can't be installed or every slot is taken.
+        pub(super) fn new(map: &[u8]) -> Option<Guarded> {
+            INSTALL.call_once(install);
+            if !INSTALLED.load(Ordering::SeqCst) {
+                return None;
+            }
+            let start = map.as_ptr() as usize;
+            for slot in 0..SLOTS {
+                if STARTS[slot]
+                    .compare_exchange(0, start, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    FAULTED[slot].store(false, Ordering::SeqCst);
+                    ENDS[slot].store(start + map.len(), Ordering::SeqCst);
+                    return Some(Guarded { slot });
+                }
+            }
+            None
+        }
+
+        // Whether a page of the map was found gone, and zeros read in its place.
+        pub(super) fn faulted(&self) -> bool {
+            FAULTED[self.slot].load(Ordering::SeqCst)
+        }
+    }
+
+    impl Drop for Guarded {
+        fn drop(&mut self) {
+            ENDS[self.slot].store(0, Ordering::SeqCst);
+            STARTS[self.slot].store(0, Ordering::SeqCst);
+        }
+    }
+
+    fn install() {
+        // SAFETY: sysconf only reads a setting.
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        let Ok(page_size) = usize::try_from(page_size) else {
+            return;
+        };
+        PAGE_SIZE.store(page_size, Ordering::SeqCst);
+        // SAFETY: both structs are plain data, zeroed as sigaction expects them unset,
+        // and the handler only touches atomics and async-signal-safe calls.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_sigbus as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            if libc::sigaction(libc::SIGBUS, &action, &mut previous) != 0 {
+                return;
+            }
+            PREVIOUS.store(previous.sa_sigaction, Ordering::SeqCst);
+            PREVIOUS_INFO.store(previous.sa_flags & libc::SA_SIGINFO != 0, Ordering::SeqCst);
+        }
+        INSTALLED.store(true, Ordering::SeqCst);
+    }
+
+    extern "C" fn on_sigbus(signal: c_int, info: *mut siginfo_t, context: *mut c_void) {
+        // SAFETY: the kernel passes a valid siginfo_t for a handler with SA_SIGINFO.
+        let address = unsafe { fault_address(info) };
+        let page_size = PAGE_SIZE.load(Ordering::SeqCst);
+        for slot in 0..SLOTS {
+            let start = STARTS[slot].load(Ordering::SeqCst);
+            let end = ENDS[slot].load(Ordering::SeqCst);
+            if start == 0 || !(start..end).contains(&address) {
+                continue;
+            }
+            let page = address & !(page_size - 1);
+            // SAFETY: the page is in a map that is still there, as its slot is taken,
+            // and only ever read; mmap is a plain system call.
+            let mapped = unsafe {
+                libc::mmap(
+                    page as *mut c_void,
+                    page_size,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED,
+                    -1,
+                    0,
+                )
+            };
+            if mapped != libc::MAP_FAILED {
+                FAULTED[slot].store(true, Ordering::SeqCst);
+                return;
+            }
+        }
+        // Not one of ours: as the handler before would have had it
+        let previous = PREVIOUS.load(Ordering::SeqCst);
+        // SAFETY: `previous` is what sigaction returned as the handler before, called
+        // as the kind of handler its flags say it is.
+        unsafe {
+            if previous == libc::SIG_DFL || previous == libc::SIG_IGN {
+                // Faulting again on return, the default action ends the process
+                libc::signal(libc::SIGBUS, libc::SIG_DFL);
+            } else if PREVIOUS_INFO.load(Ordering::SeqCst) {
+                let handler: extern "C" fn(c_int, *mut siginfo_t, *mut c_void) =
+                    std::mem::transmute(previous);
+                handler(signal, info, context);
+            } else {
+                let handler: extern "C" fn(c_int) = std::mem::transmute(previous);
+                handler(signal);
+            }
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    unsafe fn fault_address(info: *mut siginfo_t) -> usize {
+        match info.is_null() {
+            true => 0,
+            // SAFETY: as the caller says, `info` is valid.
+            false => unsafe { (*info).si_addr() as usize },
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    unsafe fn fault_address(info: *mut siginfo_t) -> usize {
+        match info.is_null() {
+            true => 0,
+            // SAFETY: as the caller says, `info` is valid.
+            false => unsafe { (*info).si_addr as usize },
+        }
+    }
+}
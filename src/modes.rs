@@ -0,0 +1,92 @@
+// Permissions of what a split writes and of a reconstructed file, set with
+// `SplitOptions::file_mode` and `dir_mode` and `ReconstructOptions::file_mode` rather
+// than left to the umask: group-readable for a shared directory, or 0600 and 0700 for
+// content no one else should see. They are set on the file after it is written, so a
+// file that was already there, such as an output being overwritten, gets them too.
+// Only Unix has such modes; elsewhere they are left alone.
+
+use std::fs;
+use std::path::Path;
+
+use log::debug;
+
+use crate::error::{PathContext, Result};
+use crate::store::is_shard_dir;
+
+// The permission bits, with setuid, setgid and sticky
+pub const MAX_MODE: u32 = 0o7777;
+
+// Give `path` `mode`, if one is asked for.
+pub(crate) fn set(path: &Path, mode: Option<u32>) -> Result<()> {
+    let Some(mode) = mode else {
+        return Ok(());
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        debug!("setting the mode of {} to {:o}", path.display(), mode);
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+            .doing("setting the permissions of", path)
+    }
+    #[cfg(not(unix))]
+    {
+        debug!(
+            "leaving the permissions of {} as they are rather than {:o}; modes are only set on Unix",
+            path.display(),
+            mode
+        );
+        Ok(())
+    }
+}
+
+// A destination directory the split is about to write into. Until the split is done it
+// is kept writable and searchable by its owner, whatever `mode` says, so the chunks can
+// go in; `finish` gives it `mode` itself.
+pub(crate) fn prepare(directory: &Path, mode: Option<u32>) -> Result<()> {
+    set(directory, mode.map(|mode| mode | 0o700))
+}
+
+// Give every file in `directory`, all of them written by the split as it started out
+// empty, `file_mode`, and the directory `dir_mode`, as for the shard subdirectories in
+// it and their files. Files written executable, the join scripts, stay executable by
+// whoever may read them.
+pub(crate) fn finish(
+    directory: &Path,
+    file_mode: Option<u32>,
+    dir_mode: Option<u32>,
+) -> Result<()> {
+    if file_mode.is_none() && dir_mode.is_none() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(directory).at(directory)? {
+        let entry = entry.at(directory)?;
+        let kind = entry.file_type().at(directory)?;
+        if kind.is_dir() && is_shard_dir(&entry.file_name().to_string_lossy()) {
+            finish(&entry.path(), file_mode, dir_mode)?;
+        }
+        if let Some(mode) = file_mode
+            && kind.is_file()
+        {
+            let path = entry.path();
+            let mode = match executable(&path) {
+                true => mode | (mode & 0o444) >> 2,
+                false => mode,
+            };
+            set(&path, Some(mode))?;
+        }
+    }
+    set(directory, dir_mode)
+}
+
+fn executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        false
+    }
+}
@@ -0,0 +1,462 @@
+// `mount`: the chunk sets under a directory as a read-only file system through FUSE,
+// each set one file by the name it reconstructs into, read from its chunks through
+// `ChunkedReader` as something reads it, so nothing is joined on disk. The tree under
+// the root is kept: the set in `root/backups/disk-img/` shows as `backups/disk.img`, and
+// directories with no set anywhere under them are left out. Two sets in one directory
+// that reconstruct into the same name are told apart by their directories' names. A
+// file's size is the original's, and its time when the split started, which info.json
+// has with --record-times, or else when the newest of the set's files was written.
+//
+// The sets are found once, when mounting. Every open file has a reader of its own, and
+// reads are handed to a few threads, so programs reading different files, or the same
+// one, don't wait on each other beyond what the disk makes them. Encrypted sets, which
+// take a key to read, are left out. Ctrl+C unmounts and ends the command, as does
+// unmounting from outside with umount or fusermount -u.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
+    ReplyOpen, Request,
+};
+use log::warn;
+use reconstruct_large_file::size::format_size;
+use reconstruct_large_file::store::LocalDirStore;
+use reconstruct_large_file::{
+    CancelToken, ChunkSet, ChunkedReader, MANIFEST_NAME, SplitterError, stats,
+};
+
+// How long the kernel may keep what it was told of a file or directory; nothing here
+// changes while mounted
+const TTL: Duration = Duration::from_secs(60);
+// Threads reading for the kernel
+const READERS: usize = 4;
+// How often the command looks for Ctrl+C, or for the file system unmounted from outside
+const POLL: Duration = Duration::from_millis(100);
+const ROOT: u64 = 1;
+
+pub struct MountOptions {
+    pub root: PathBuf,
+    pub mountpoint: PathBuf,
+    pub accept_modified: bool,
+}
+
+// What an inode is: a directory of the tree under the root, or a set's file.
+enum Node {
+    Directory {
+        parent: u64,
+        // By name, with their inodes
+        children: BTreeMap<OsString, u64>,
+    },
+    Set {
+        directory: PathBuf,
+        size: u64,
+        mtime: SystemTime,
+    },
+}
+
+struct SetsFs {
+    // Inode `n` is `nodes[n - 1]`
+    nodes: Vec<Node>,
+    accept_modified: bool,
+    // The reader of every open file, by its handle
+    open: HashMap<u64, Arc<Mutex<ChunkedReader<LocalDirStore>>>>,
+    next_handle: u64,
+    jobs: mpsc::Sender<Job>,
+    // Whoever mounted it owns everything in it
+    uid: u32,
+    gid: u32,
+    mounted: SystemTime,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// Find the sets under `options.root` and mount them at `options.mountpoint` until
+// `cancel` is set or something else unmounts them.
+pub fn run(options: &MountOptions, cancel: &CancelToken) -> Result<(), SplitterError> {
+    let (root, mountpoint) = (options.root.as_path(), options.mountpoint.as_path());
+    let filesystem = SetsFs::new(root, options.accept_modified, cancel)?;
+    let sets = filesystem.sets();
+    if sets == 0 {
+        return Err(SplitterError::NoChunks {
+            path: root.to_path_buf(),
+        });
+    }
+    let mount_options = [
+        MountOption::RO,
+        MountOption::NoExec,
+        MountOption::FSName(root.display().to_string()),
+        MountOption::Subtype("chunks".to_string()),
+    ];
+    let mounting = |source| SplitterError::Io {
+        path: mountpoint.to_path_buf(),
+        source,
+        action: Some("mounting at"),
+    };
+    let session = fuser::spawn_mount2(filesystem, mountpoint, &mount_options).map_err(mounting)?;
+    println!(
+        "Mounted {} {} from {} at {}, read-only. Press Ctrl+C to unmount.",
+        sets,
+        if sets == 1 { "set" } else { "sets" },
+        root.display(),
+        mountpoint.display()
+    );
+    while !cancel.is_cancelled() && !session.guard.is_finished() {
+        thread::sleep(POLL);
+    }
+    if cancel.is_cancelled() {
+        println!("Unmounting {}…", mountpoint.display());
+    }
+    session.join();
+    Ok(())
+}
+
+impl SetsFs {
+    fn new(
+        root: &Path,
+        accept_modified: bool,
+        cancel: &CancelToken,
+    ) -> Result<SetsFs, SplitterError> {
+        let report = stats(root, accept_modified, cancel)?;
+        for broken in &report.broken {
+            eprintln!(
+                "Warning: {} is left out: {}",
+                broken.directory.display(),
+                broken.reason
+            );
+        }
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..READERS {
+            let receiver = Arc::clone(&receiver);
+            thread::spawn(move || {
+                // Ends once the file system, and with it the sender, is gone
+                while let Ok(job) = receiver.lock().unwrap().recv() {
+                    job();
+                }
+            });
+        }
+        // SAFETY: neither call can fail or touches memory.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let mut filesystem = SetsFs {
+            nodes: vec![Node::Directory {
+                parent: ROOT,
+                children: BTreeMap::new(),
+            }],
+            accept_modified,
+            open: HashMap::new(),
+            next_handle: 1,
+            jobs,
+            uid,
+            gid,
+            mounted: SystemTime::now(),
+        };
+        for found in &report.sets {
+            let directory = &found.directory;
+            let set = match ChunkSet::open(directory, accept_modified) {
+                Ok(set) => set,
+                Err(e) => {
+                    eprintln!("Warning: {} is left out: {}", directory.display(), e);
+                    continue;
+                }
+            };
+            if set
+                .manifest()
+                .is_some_and(|manifest| manifest.encryption.is_some())
+            {
+                let why = "its chunks are encrypted";
+                eprintln!("Warning: {} is left out: {}", directory.display(), why);
+                continue;
+            }
+            let node = Node::Set {
+                directory: directory.clone(),
+                size: set.total_size(),
+                mtime: written(&set, directory),
+            };
+            let relative = directory.strip_prefix(root).unwrap_or(Path::new(""));
+            filesystem.add(relative, &found.original_filename, node);
+        }
+        Ok(filesystem)
+    }
+
+    fn sets(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|node| matches!(node, Node::Set { .. }))
+            .count()
+    }
+
+    // Put the set in `relative`, a directory under the root, into the tree as a file
+    // named `name` in the directory above it.
+    fn add(&mut self, relative: &Path, name: &str, node: Node) {
+        let mut parent = ROOT;
+        for component in relative.parent().into_iter().flat_map(Path::iter) {
+            parent = match self.children(parent).get(component) {
+                Some(&ino) if matches!(self.node(ino), Some(Node::Directory { .. })) => ino,
+                _ => self.insert(
+                    parent,
+                    component,
+                    Node::Directory {
+                        parent,
+                        children: BTreeMap::new(),
+                    },
+                ),
+            };
+        }
+        let taken = |name: &OsStr| self.children(parent).contains_key(name);
+        let own = relative
+            .file_name()
+            .filter(|own| !taken(own) && !own.is_empty());
+        let name = match OsString::from(name) {
+            name if !taken(&name) => name,
+            _ if own.is_some() => own.unwrap_or_default().to_os_string(),
+            _ => (2..)
+                .map(|n| OsString::from(format!("{} ({})", name, n)))
+                .find(|numbered| !taken(numbered))
+                .unwrap_or_default(),
+        };
+        self.insert(parent, &name, node);
+    }
+
+    fn insert(&mut self, parent: u64, name: &OsStr, node: Node) -> u64 {
+        self.nodes.push(node);
+        let ino = self.nodes.len() as u64;
+        if let Some(Node::Directory { children, .. }) = self.nodes.get_mut(parent as usize - 1) {
+            children.insert(name.to_os_string(), ino);
+        }
+        ino
+    }
+
+    fn node(&self, ino: u64) -> Option<&Node> {
+        self.nodes.get((ino as usize).checked_sub(1)?)
+    }
+
+    fn children(&self, ino: u64) -> &BTreeMap<OsString, u64> {
+        static NONE: BTreeMap<OsString, u64> = BTreeMap::new();
+        match self.node(ino) {
+            Some(Node::Directory { children, .. }) => children,
+            _ => &NONE,
+        }
+    }
+
+    fn attr(&self, ino: u64) -> Option<FileAttr> {
+        let (kind, size, mtime, perm, nlink) = match self.node(ino)? {
+            Node::Directory { .. } => (FileType::Directory, 0, self.mounted, 0o555, 2),
+            Node::Set { size, mtime, .. } => (FileType::RegularFile, *size, *mtime, 0o444, 1),
+        };
+        Some(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: mtime,
+            mtime,
+            ctime: mtime,
+            crtime: mtime,
+            kind,
+            perm,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 4096,
+            flags: 0,
+        })
+    }
+}
+
+// When the split of `set` in `directory` started, if info.json says, or else when its
+// newest file was written.
+fn written(set: &ChunkSet, directory: &Path) -> SystemTime {
+    if let Some(created) = set.manifest().and_then(|manifest| manifest.created) {
+        return UNIX_EPOCH + Duration::from_secs(created);
+    }
+    let files = set.iter().map(|chunk| chunk.path.clone());
+    files
+        .chain([directory.join(MANIFEST_NAME)])
+        .filter_map(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+        .max()
+        .unwrap_or(UNIX_EPOCH)
+}
+
+// Up to `size` bytes of `reader` from `offset`, fewer only at the end of the file.
+fn read_at(
+    reader: &mut ChunkedReader<LocalDirStore>,
+    offset: u64,
+    size: usize,
+) -> io::Result<Vec<u8>> {
+    let size = size.min(reader.len().saturating_sub(offset) as usize);
+    let mut data = vec![0; size];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+impl Filesystem for SetsFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self
+            .children(parent)
+            .get(name)
+            .and_then(|&ino| self.attr(ino))
+        {
+            Some(attr) => reply.entry(&TTL, &attr, 0),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        let Some(Node::Set { directory, .. }) = self.node(ino) else {
+            return reply.error(libc::EISDIR);
+        };
+        if flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return reply.error(libc::EROFS);
+        }
+        match ChunkedReader::open(directory, self.accept_modified) {
+            Ok(reader) => {
+                let handle = self.next_handle;
+                self.next_handle += 1;
+                self.open.insert(handle, Arc::new(Mutex::new(reader)));
+                reply.opened(handle, 0);
+            }
+            Err(e) => {
+                warn!("cannot open {}: {}", directory.display(), e);
+                reply.error(libc::EIO);
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Some(reader) = self.open.get(&fh).cloned() else {
+            return reply.error(libc::EBADF);
+        };
+        let job = move || {
+            let mut reader = reader.lock().unwrap();
+            match read_at(&mut reader, offset.max(0) as u64, size as usize) {
+                Ok(data) => reply.data(&data),
+                Err(e) => {
+                    warn!("reading {} at {}: {}", format_size(size as u64), offset, e);
+                    reply.error(libc::EIO);
+                }
+            }
+        };
+        if let Err(mpsc::SendError(job)) = self.jobs.send(Box::new(job)) {
+            job();
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request<'_>,
+        _ino: u64,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        self.open.remove(&fh);
+        reply.ok();
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let Some(Node::Directory { parent, children }) = self.node(ino) else {
+            return reply.error(libc::ENOTDIR);
+        };
+        let dots = [(ino, OsStr::new(".")), (*parent, OsStr::new(".."))];
+        let entries = dots.into_iter().chain(
+            children
+                .iter()
+                .map(|(name, &child)| (child, name.as_os_str())),
+        );
+        for (next, (child, name)) in entries.enumerate().skip(offset.max(0) as usize) {
+            let kind = match self.node(child) {
+                Some(Node::Set { .. }) => FileType::RegularFile,
+                _ => FileType::Directory,
+            };
+            // Full once this is true
+            if reply.add(child, next as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reconstruct_large_file::{SplitOptions, split_file};
+
+    fn split(input: &Path, destination: &Path, chunk_size: u64) {
+        let options = SplitOptions::builder(input, destination)
+            .chunk_size(chunk_size)
+            .min_chunk_size(0)
+            .build()
+            .unwrap();
+        split_file(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+    }
+
+    #[test]
+    fn sets_show_as_their_files_in_the_tree_under_the_root() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().join("root");
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let input = temp.path().join("disk.img");
+        fs::write(&input, &data).unwrap();
+        split(&input, &root.join("backups/disk-a"), 3000);
+        split(&input, &root.join("backups/disk-b"), 4096);
+        fs::create_dir_all(root.join("empty/nothing")).unwrap();
+
+        let filesystem = SetsFs::new(&root, false, &CancelToken::new()).unwrap();
+        assert_eq!(filesystem.sets(), 2);
+        let top: Vec<_> = filesystem.children(ROOT).keys().collect();
+        assert_eq!(top, ["backups"]);
+        let backups = filesystem.children(ROOT)[OsStr::new("backups")];
+        let names: Vec<_> = filesystem.children(backups).keys().collect();
+        // The second set by the name of its directory
+        assert_eq!(names, ["disk-b", "disk.img"]);
+
+        let ino = filesystem.children(backups)[OsStr::new("disk.img")];
+        let attr = filesystem.attr(ino).unwrap();
+        assert_eq!((attr.size, attr.kind), (10_000, FileType::RegularFile));
+        let Some(Node::Set { directory, .. }) = filesystem.node(ino) else {
+            panic!("not a set");
+        };
+        let mut reader = ChunkedReader::open(directory, false).unwrap();
+        // Across a chunk boundary, and cut short at the end
+        assert_eq!(read_at(&mut reader, 2990, 20).unwrap(), &data[2990..3010]);
+        assert_eq!(read_at(&mut reader, 9990, 100).unwrap(), &data[9990..]);
+        assert!(read_at(&mut reader, 20_000, 10).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+// Names in the order people read them, for directory listings: runs of digits compare
+// by their value, so part2 comes before part10, and everything else compares without
+// regard to case, so Backups sorts next to backups. Only ASCII digits count as digits;
+// a digit of another script compares as the character it is. Case is folded with
+// Unicode's lowercase mapping, so Ärger sorts with ärger as well.
+//
+// Names that only differ in case or leading zeros, such as part01 and part1, are still
+// put in a fixed order, by comparing them as they are, so sorting is the same every
+// time and only exactly equal names compare equal.
+
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::str::Chars;
+
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut left = a.chars().peekable();
+    let mut right = b.chars().peekable();
+    loop {
+        match (left.peek().copied(), right.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(l), Some(r)) if l.is_ascii_digit() && r.is_ascii_digit() => {
+                let l = digit_run(&mut left);
+                let r = digit_run(&mut right);
+                let (l, r) = (l.trim_start_matches('0'), r.trim_start_matches('0'));
+                // As many digits as anyone likes, without parsing them into a number
+                let order = l.len().cmp(&r.len()).then_with(|| l.cmp(r));
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+            (Some(l), Some(r)) => {
+                let order = l.to_lowercase().cmp(r.to_lowercase());
+                if order != Ordering::Equal {
+                    return order;
+                }
+                left.next();
+                right.next();
+            }
+        }
+    }
+}
+
+fn digit_run(chars: &mut Peekable<Chars>) -> String {
+    let mut run = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        run.push(c);
+    }
+    run
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        names.sort_by(|a, b| natural_cmp(a, b));
+        names
+    }
+
+    #[test]
+    fn digit_runs_compare_by_value() {
+        assert_eq!(
+            sorted(&["part10", "part2", "part1", "part20", "part9"]),
+            ["part1", "part2", "part9", "part10", "part20"]
+        );
+        assert_eq!(natural_cmp("chunk999", "chunk1000"), Ordering::Less);
+    }
+
+    #[test]
+    fn mixed_digits_and_letters() {
+        assert_eq!(
+            sorted(&["a10b2", "a2b10", "a2b2", "a10b1", "a2"]),
+            ["a2", "a2b2", "a2b10", "a10b1", "a10b2"]
+        );
+        assert_eq!(
+            sorted(&["v1.10.0", "v1.9.2", "v1.9.10", "v1.2"]),
+            ["v1.2", "v1.9.2", "v1.9.10", "v1.10.0"]
+        );
+        // A name that runs out first comes first
+        assert_eq!(natural_cmp("disk", "disk1"), Ordering::Less);
+        assert_eq!(natural_cmp("7", "a"), Ordering::Less);
+    }
+
+    #[test]
+    fn leading_zeros_dont_change_the_value() {
+        assert_eq!(
+            sorted(&["chunk010", "chunk9", "chunk0011", "chunk001"]),
+            ["chunk001", "chunk9", "chunk010", "chunk0011"]
+        );
+        // Equal in value, then in a fixed order by the names as they are
+        assert_eq!(natural_cmp("part01", "part1"), Ordering::Less);
+        assert_eq!(natural_cmp("part1", "part01"), Ordering::Greater);
+        assert_eq!(natural_cmp("x0", "x00"), Ordering::Less);
+    }
+
+    #[test]
+    fn numbers_longer_than_any_integer() {
+        let huge = "n123456789012345678901234567890";
+        let huger = "n123456789012345678901234567891";
+        assert_eq!(natural_cmp(huge, huger), Ordering::Less);
+        assert_eq!(
+            natural_cmp("n99999999999999999999999", huge),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn case_is_ignored_but_still_ordered() {
+        assert_eq!(
+            sorted(&["backups", "Zip", "apple", "Backups", "Apple"]),
+            ["Apple", "apple", "Backups", "backups", "Zip"]
+        );
+        assert_eq!(natural_cmp("README", "readme"), Ordering::Less);
+    }
+
+    #[test]
+    fn unicode_names() {
+        // Case folded beyond ASCII
+        assert_eq!(
+            sorted(&["ärger", "Ärger", "Zebra"]),
+            ["Zebra", "Ärger", "ärger"]
+        );
+        assert_eq!(natural_cmp("Ölfass2", "ölfass10"), Ordering::Less);
+        assert_eq!(
+            sorted(&["ファイル10", "ファイル2", "ファイル1"]),
+            ["ファイル1", "ファイル2", "ファイル10"]
+        );
+        // Digits of other scripts are characters rather than numbers
+        assert_eq!(natural_cmp("x٣", "x2"), Ordering::Greater);
+        assert_eq!(natural_cmp("é2", "é10"), Ordering::Less);
+    }
+
+    #[test]
+    fn only_equal_names_compare_equal() {
+        assert_eq!(natural_cmp("", ""), Ordering::Equal);
+        assert_eq!(natural_cmp("part7", "part7"), Ordering::Equal);
+        assert_eq!(natural_cmp("", "a"), Ordering::Less);
+        let names = ["a1", "A1", "a01", "a001", "A01", "a1b", "a"];
+        for a in names {
+            for b in names {
+                assert_eq!(natural_cmp(a, b) == Ordering::Equal, a == b, "{} {}", a, b);
+                assert_eq!(
+                    natural_cmp(a, b),
+                    natural_cmp(b, a).reverse(),
+                    "{} {}",
+                    a,
+                    b
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// Telling whoever started a long split, reconstruction, verify or repair that it is
+// over, for `--notify` or `"notify": true` in the config file: what was done, to what,
+// how it went and how long it took. Built with the `notify` feature, that is a desktop
+// notification, through notify-rust. Without the feature, or when there is no
+// notification service or it takes too long to answer, it is a terminal bell instead.
+// Nothing about a notification fails anything or changes an exit status.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::journal::{Outcome, Record};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn init(notify: bool) {
+    ENABLED.store(notify, Ordering::Relaxed);
+}
+
+// Say that the operation `record` is for is over, when asked to.
+pub fn finished(record: &Record) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let outcome = match record.outcome {
+        Outcome::Succeeded => "finished",
+        Outcome::Failed => "failed",
+        Outcome::Interrupted => "was interrupted",
+    };
+    let mut operation = record.operation.clone();
+    if let Some(first) = operation.get_mut(..1) {
+        first.make_ascii_uppercase();
+    }
+    let summary = format!("{} {}", operation, outcome);
+    let name = record
+        .source
+        .rsplit(['/', '\\'])
+        .find(|part| !part.is_empty())
+        .unwrap_or(&record.source);
+    let mut body = format!("{} after {}", name, describe_duration(record.duration_secs));
+    if let Some(error) = &record.error {
+        body.push_str(": ");
+        body.push_str(error);
+    }
+    if !desktop::show(&summary, &body) {
+        bell();
+    }
+}
+
+fn bell() {
+    let mut stderr = io::stderr();
+    if stderr.is_terminal() {
+        let _ = stderr.write_all(b"\x07");
+        let _ = stderr.flush();
+    }
+}
+
+// As in "42 s", "3 min 5 s" or "2 h 10 min".
+fn describe_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    match seconds {
+        0..60 => format!("{} s", seconds),
+        60..3_600 => format!("{} min {} s", seconds / 60, seconds % 60),
+        _ => format!("{} h {} min", seconds / 3_600, seconds / 60 % 60),
+    }
+}
+
+#[cfg(feature = "notify")]
+mod desktop {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use notify_rust::Notification;
+
+    // How long the notification service gets before the bell is rung instead, as a
+    // session bus that isn't answering can keep a call waiting a long while.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // Whether the notification was shown. One still being sent when the time is up is
+    // left to finish or not as the process exits.
+    pub fn show(summary: &str, body: &str) -> bool {
+        let (sent, shown) = mpsc::channel();
+        let mut notification = Notification::new();
+        notification
+            .appname("reconstruct_large_file")
+            .summary(summary)
+            .body(body);
+        thread::spawn(move || {
+            let _ = sent.send(notification.show().is_ok());
+        });
+        shown.recv_timeout(TIMEOUT).unwrap_or(false)
+    }
+}
+
+#[cfg(not(feature = "notify"))]
+mod desktop {
+    pub fn show(_summary: &str, _body: &str) -> bool {
+        false
+    }
+}
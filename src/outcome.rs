@@ -0,0 +1,45 @@
+// How the interactive menus went, for the exit status they leave with: a script driving
+// them can't read what was printed, but it can check that. Every operation run from the
+// menus that fails is noted with the status the command for it would have exited with,
+// and the menus exit with the worst of those however they are left, by Exit, Ctrl+C at a
+// prompt or the answers running out; 0 only when nothing failed.
+//
+// Worst is by what it says about the files: chunks missing or damaged (4), info.json not
+// matching them (5) and a size that changed (6) come first, then I/O failures (1), a
+// destination in the way (3) and a bad answer (2), then the answers running out (7), and
+// last an interruption (130), which was the user's own doing.
+
+use std::process::exit;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+static WORST: AtomicI32 = AtomicI32::new(0);
+
+// From least to most severe
+const RANKING: &[i32] = &[0, 130, 7, 2, 3, 1, 6, 5, 4];
+
+// Note that an operation failed with `code`.
+pub fn failed(code: i32) {
+    let _ = WORST.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |worst| {
+        (rank(code) > rank(worst)).then_some(code)
+    });
+}
+
+// End the program with the worst status noted.
+pub fn leave() -> ! {
+    exit(WORST.load(Ordering::Relaxed))
+}
+
+// End the program for a reason that is itself a failure, such as an interrupt, with the
+// worst status noted counting that too.
+pub fn exit_with(code: i32) -> ! {
+    failed(code);
+    leave()
+}
+
+fn rank(code: i32) -> usize {
+    // Anything not listed counts as the worst
+    RANKING
+        .iter()
+        .position(|&ranked| ranked == code)
+        .unwrap_or(RANKING.len())
+}
@@ -0,0 +1,157 @@
+// Who owns a file split on Unix, recorded in its info.json and given back to the file
+// reconstructed from it where that is allowed, usually only to root. As tar does, the
+// names win over the numbers when this system knows them, since the same account can
+// have another number here, unless `ReconstructOptions::numeric_owner` says otherwise.
+// Not being allowed is no failure: the file stays with whoever reconstructed it, which
+// is only logged.
+
+use std::path::Path;
+
+use log::debug;
+
+use crate::manifest::Owner;
+
+// The owner of the file at `path`; none for anything but a file, or off Unix.
+pub(crate) fn of(path: &Path) -> Option<Owner> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = std::fs::metadata(path).ok().filter(|m| m.is_file())?;
+        let (uid, gid) = (metadata.uid(), metadata.gid());
+        Some(Owner {
+            uid,
+            gid,
+            user: sys::user_name(uid),
+            group: sys::group_name(gid),
+        })
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+// Give `path` to `owner`, if allowed.
+pub(crate) fn restore(path: &Path, owner: &Owner, numeric: bool) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let (uid, gid) = match numeric {
+            true => (owner.uid, owner.gid),
+            false => (
+                owner
+                    .user
+                    .as_deref()
+                    .and_then(sys::uid_of)
+                    .unwrap_or(owner.uid),
+                owner
+                    .group
+                    .as_deref()
+                    .and_then(sys::gid_of)
+                    .unwrap_or(owner.gid),
+            ),
+        };
+        let current = std::fs::metadata(path).map(|m| (m.uid(), m.gid()));
+        if current.is_ok_and(|current| current == (uid, gid)) {
+            return;
+        }
+        match std::os::unix::fs::chown(path, Some(uid), Some(gid)) {
+            Ok(()) => debug!("gave {} to {}:{}", path.display(), uid, gid),
+            Err(e) => debug!(
+                "leaving {} with its owner rather than {}:{}: {}",
+                path.display(),
+                uid,
+                gid,
+                e
+            ),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, owner, numeric);
+        debug!("owners are only restored on Unix");
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::{CStr, CString};
+    use std::mem::MaybeUninit;
+    use std::ptr;
+
+    use libc::{c_char, c_int};
+
+    pub fn user_name(uid: u32) -> Option<String> {
+        lookup(
+            // SAFETY: `lookup` passes an entry and a buffer of `len` bytes to fill.
+            |entry, buffer, len, found| unsafe { libc::getpwuid_r(uid, entry, buffer, len, found) },
+            // SAFETY: `pw_name` is NUL-terminated, in the buffer.
+            |entry: &libc::passwd| name(unsafe { CStr::from_ptr(entry.pw_name) }),
+        )
+    }
+
+    pub fn group_name(gid: u32) -> Option<String> {
+        lookup(
+            // SAFETY: as for `user_name`.
+            |entry, buffer, len, found| unsafe { libc::getgrgid_r(gid, entry, buffer, len, found) },
+            // SAFETY: as for `user_name`.
+            |entry: &libc::group| name(unsafe { CStr::from_ptr(entry.gr_name) }),
+        )
+    }
+
+    pub fn uid_of(user: &str) -> Option<u32> {
+        let user = CString::new(user).ok()?;
+        lookup(
+            // SAFETY: as for `user_name`, and `user` is NUL-terminated.
+            |entry, buffer, len, found| unsafe {
+                libc::getpwnam_r(user.as_ptr(), entry, buffer, len, found)
+            },
+            |entry: &libc::passwd| Some(entry.pw_uid),
+        )
+    }
+
+    pub fn gid_of(group: &str) -> Option<u32> {
+        let group = CString::new(group).ok()?;
+        lookup(
+            // SAFETY: as for `uid_of`.
+            |entry, buffer, len, found| unsafe {
+                libc::getgrnam_r(group.as_ptr(), entry, buffer, len, found)
+            },
+            |entry: &libc::group| Some(entry.gr_gid),
+        )
+    }
+
+    fn name(name: &CStr) -> Option<String> {
+        name.to_str().ok().map(str::to_string)
+    }
+
+    // One of the reentrant lookups of the user and group databases, with a buffer for
+    // the strings of the entry it finds, grown for as long as it is too small. What
+    // `take` keeps of the entry has to be had before the buffer goes.
+    fn lookup<T, R>(
+        mut call: impl FnMut(*mut T, *mut c_char, usize, *mut *mut T) -> c_int,
+        take: impl FnOnce(&T) -> Option<R>,
+    ) -> Option<R> {
+        let mut buffer: Vec<c_char> = vec![0; 1024];
+        loop {
+            let mut entry = MaybeUninit::<T>::uninit();
+            let mut found = ptr::null_mut();
+            let result = call(
+                entry.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            );
+            if result == libc::ERANGE && buffer.len() < 1 << 20 {
+                buffer.resize(buffer.len() * 2, 0);
+                continue;
+            }
+            if result != 0 || found.is_null() {
+                return None;
+            }
+            // SAFETY: `found` points at `entry`, filled in.
+            return take(unsafe { &*found });
+        }
+    }
+}
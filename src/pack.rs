@@ -0,0 +1,281 @@
+// Packing a chunk set into a single tar, to move it around as one file, and unpacking it
+// again. Packing the same set gives the same tar wherever and whenever it is done:
+// members go in byte order of their names, each with mode 0644, no owner and a 1970
+// date, so packing, unpacking and packing again gives back the same bytes. The tar is
+// written front to back with nothing sought or patched afterwards, so it can go down a
+// pipe as well as into a file. Reconstructing, verifying and the rest read a packed set
+// in place like any other archive (see `ArchiveStore`), and unpacking goes through the
+// same code, so it takes a zip or a tar made by other tools too.
+
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::archive::ArchiveStore;
+use crate::cancel::CancelToken;
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::{Counting, ProgressEvent, Report};
+use crate::lock;
+use crate::manifest::Manifest;
+use crate::pipeline::{Io, copy_overlapped};
+use crate::scratch::Staged;
+use crate::split::{prepare_destination, remove_partial};
+use crate::store::{ChunkStore, restore_shard_dir};
+use crate::{is_set_file, tar};
+
+// Outcome of `pack` and `unpack`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PackReport {
+    pub directory: PathBuf,
+    // The tar, or whatever `pack_into` was told to call what it wrote to
+    pub archive: PathBuf,
+    // The files packed or unpacked, in the tar's order
+    pub files: Vec<String>,
+    // Their bytes, not counting the tar's headers
+    pub size: u64,
+}
+
+// Pack the set in `directory` into a new tar at `archive`, which mustn't exist yet. The
+// tar is written to a temporary file, see `scratch`, in `temp_dir` or else beside it,
+// and only put in place once it is complete; an error or cancellation before then
+// removes it. A manifest changed after it was sealed is refused unless `accept_modified`.
+pub fn pack(
+    directory: &Path,
+    archive: &Path,
+    temp_dir: Option<&Path>,
+    accept_modified: bool,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<PackReport> {
+    if fs::symlink_metadata(archive).is_ok() {
+        return Err(io::Error::from(io::ErrorKind::AlreadyExists)).at(archive);
+    }
+    let (file, staged) = Staged::create(archive, temp_dir)?;
+    let mut output = io::BufWriter::new(file);
+    let report = pack_into(
+        directory,
+        &mut output,
+        archive,
+        accept_modified,
+        progress,
+        cancel,
+    )?;
+    let file = output
+        .into_inner()
+        .map_err(|e| e.into_error())
+        .at(archive)?;
+    file.sync_all().at(archive)?;
+    drop(file);
+    staged.persist(archive)?;
+    Ok(report)
+}
+
+// Pack the set in `directory` as a tar written to `output`, e.g. standard output; `name`
+// is what errors writing it and the report call it. Only the files a split writes are
+// packed (the manifest, the chunks it lists or that are named like ours, parity, PAR2
+// volumes and join scripts), never anything else kept alongside them or in
+// subdirectories other than the set's shard subdirectories.
+pub fn pack_into(
+    directory: &Path,
+    output: &mut dyn Write,
+    name: &Path,
+    accept_modified: bool,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<PackReport> {
+    let manifest = Manifest::load(directory, accept_modified)?;
+    let mut folders = vec![String::new()];
+    if let Some(manifest) = &manifest {
+        folders.extend(
+            manifest
+                .shard_dirs()
+                .into_iter()
+                .map(|shard| format!("{}/", shard)),
+        );
+    }
+    let listed = listed_names(manifest);
+    let mut files = Vec::new();
+    for folder in &folders {
+        let path = directory.join(folder);
+        let entries = match fs::read_dir(&path) {
+            // A shard subdirectory gone with all its chunks
+            Err(e) if !folder.is_empty() && e.kind() == io::ErrorKind::NotFound => continue,
+            entries => entries.at(&path)?,
+        };
+        for entry in entries {
+            let entry = entry.at(&path)?;
+            let Ok(file_name) = entry.file_name().into_string() else {
+                continue;
+            };
+            let file_name = format!("{}{}", folder, file_name);
+            if (is_set_file(&file_name) || listed.contains(&file_name))
+                && entry.file_type().at(&entry.path())?.is_file()
+            {
+                files.push(file_name);
+            }
+        }
+    }
+    if files.is_empty() {
+        return Err(SplitterError::InvalidOption {
+            field: "directory",
+            reason: "holds no chunk set to pack",
+        });
+    }
+    // Bytewise, so the order is the same on every platform and locale
+    files.sort();
+    info!(
+        "packing {} files from {} into {}",
+        files.len(),
+        directory.display(),
+        name.display()
+    );
+
+    let mut size = 0;
+    for (index, file_name) in files.iter().enumerate() {
+        cancel.check()?;
+        let path = directory.join(file_name);
+        let file = File::open(&path).at(&path)?;
+        let len = file.metadata().at(&path)?.len();
+        debug!("packing {} ({} bytes)", path.display(), len);
+        progress(ProgressEvent::ChunkStarted { index, size: len });
+        output
+            .write_all(&tar::file_header(file_name, len))
+            .at(name)?;
+        let mut copied = |delta| progress(ProgressEvent::BytesCopied { delta });
+        let mut counting = Counting {
+            inner: &mut *output,
+            copied: &mut copied,
+            cancel,
+        };
+        // A file that grows while it is packed would spill into the next header
+        let written =
+            copy_overlapped(&Io::default(), &mut file.take(len), &mut counting, None).at(&path)?;
+        if written != len {
+            return Err(SplitterError::ChangedSize { path });
+        }
+        output.write_all(&[0; 512][..tar::padding(len)]).at(name)?;
+        size += len;
+        progress(ProgressEvent::ChunkFinished { index, hash: None });
+    }
+    output.write_all(&tar::END).at(name)?;
+    output.flush().at(name)?;
+
+    let report = PackReport {
+        directory: directory.to_path_buf(),
+        archive: name.to_path_buf(),
+        files,
+        size,
+    };
+    progress(ProgressEvent::Completed {
+        report: Report::Pack(report.clone()),
+    });
+    Ok(report)
+}
+
+// Unpack the set in the tar or zip at `archive` into `destination`, which must be empty
+// or not exist yet, as for a split. Anything in the archive besides the set's files is
+// left in it. On error or cancellation the files unpacked so far are removed again.
+// A manifest changed after it was sealed is refused unless `accept_modified`.
+pub fn unpack(
+    archive: &Path,
+    destination: &Path,
+    accept_modified: bool,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<PackReport> {
+    let store = ArchiveStore::open(archive, accept_modified)?;
+    let listed = listed_names(store.read_info()?);
+    let files: Vec<String> = store
+        .names()
+        .filter(|name| is_set_file(name) || listed.contains(*name))
+        .map(str::to_string)
+        .collect();
+    if files.is_empty() {
+        return Err(SplitterError::InvalidOption {
+            field: "archive",
+            reason: "holds no chunk set to unpack",
+        });
+    }
+    let (created, _lock) = prepare_destination(destination, "unpack", lock::DEFAULT_MAX_AGE)?;
+    info!(
+        "unpacking {} files from {} into {}",
+        files.len(),
+        archive.display(),
+        destination.display()
+    );
+    let unpacked = extract(&store, &files, destination, progress, cancel);
+    match unpacked {
+        Ok(size) => {
+            let report = PackReport {
+                directory: destination.to_path_buf(),
+                archive: archive.to_path_buf(),
+                files,
+                size,
+            };
+            progress(ProgressEvent::Completed {
+                report: Report::Unpack(report.clone()),
+            });
+            Ok(report)
+        }
+        Err(e) => {
+            remove_partial(destination, created, false, None);
+            Err(e)
+        }
+    }
+}
+
+// The chunk names a manifest lists, which for an imported set (see `import`) may be
+// named like nothing a split writes.
+fn listed_names(manifest: Option<Manifest>) -> BTreeSet<String> {
+    manifest
+        .map(|manifest| {
+            manifest
+                .chunks
+                .into_iter()
+                .map(|entry| entry.name)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn extract(
+    store: &ArchiveStore,
+    files: &[String],
+    destination: &Path,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<u64> {
+    let mut size = 0;
+    for (index, file_name) in files.iter().enumerate() {
+        cancel.check()?;
+        let (mut reader, len) = store.open_file(file_name)?;
+        let path = destination.join(file_name);
+        restore_shard_dir(destination, file_name)?;
+        debug!("unpacking {} ({} bytes)", path.display(), len);
+        progress(ProgressEvent::ChunkStarted { index, size: len });
+        let mut file = File::create_new(&path).at(&path)?;
+        let mut copied = |delta| progress(ProgressEvent::BytesCopied { delta });
+        let mut counting = Counting {
+            inner: &mut file,
+            copied: &mut copied,
+            cancel,
+        };
+        let written =
+            copy_overlapped(&Io::default(), &mut reader, &mut counting, None).at(&path)?;
+        if written != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the archive ends in the middle of this file",
+            ))
+            .at(&path);
+        }
+        file.sync_all().at(&path)?;
+        size += len;
+        progress(ProgressEvent::ChunkFinished { index, hash: None });
+    }
+    Ok(size)
+}
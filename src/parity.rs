@@ -0,0 +1,654 @@
+// Parity over a chunk set, as described by `ParityInfo`. Within a stripe, parity file
+// `row` holds the sum over the stripe's chunks of each chunk's original bytes, padded
+// with zeros to the length of the longest, times the factor `Code` has for the row and
+// the chunk's place in the stripe, all in GF(2^8). For `ReedSolomon` the factors are
+// the parity rows of the reed-solomon-erasure crate's code for as many chunks as the
+// stripe holds, so any choice of as many parity files as there are lost chunks in a
+// stripe gives a system that can be solved for them, and the crate solves it; `Xor`
+// has a single row of ones. Computing the parity and rebuilding chunks both read the
+// chunks one after the other, decoding compressed ones, and hold one chunk's worth of
+// data in memory for each parity file involved, where the crate's own encoding and
+// reconstruction would want every chunk of a stripe in memory at once.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use log::{debug, info, warn};
+use reed_solomon_erasure::galois_8::{self, ReedSolomon};
+
+use crate::cancel::CancelToken;
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::Counting;
+use crate::manifest::{
+    ChunkEntry, ChunkHasher, Compression, Manifest, Parity, ParityEntry, ParityInfo,
+};
+use crate::pipeline::{DEFAULT_BUFFER_SIZE, Io, copy_overlapped};
+use crate::store::{ChunkReader, log_written};
+use crate::{decodes, hash_chunk};
+
+// Names of parity files, numbered across all stripes: `parity000`, `parity001`, …
+pub(crate) fn parity_name(number: usize) -> String {
+    format!("parity{:03}", number)
+}
+
+pub(crate) fn is_parity_name(name: &str) -> bool {
+    name.strip_prefix("parity")
+        .is_some_and(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+}
+
+// Compute the parity of the chunks `manifest` lists and write it next to them, hashed
+// when the manifest hashes its chunks.
+pub(crate) fn write(
+    directory: &Path,
+    manifest: &Manifest,
+    scheme: Parity,
+    cancel: &CancelToken,
+) -> Result<ParityInfo> {
+    let shards = scheme.shards();
+    let stripe_chunks = scheme.stripe_chunks(manifest.chunks.len());
+    let size = manifest.chunks.iter().map(|entry| entry.size).max();
+    let mut info = ParityInfo {
+        scheme,
+        stripe_chunks,
+        size: size.unwrap_or(0),
+        files: Vec::new(),
+    };
+    let stripes = manifest.chunks.len().div_ceil(stripe_chunks);
+    info!(
+        "computing {} parity files for each of {} stripes of up to {} chunks",
+        shards, stripes, stripe_chunks
+    );
+    let rows: Vec<usize> = (0..shards).collect();
+    for stripe in 0..stripes {
+        let Some(code) = Code::of(&info, manifest.chunks.len(), stripe) else {
+            return Err(too_wide());
+        };
+        let sums = stripe_sums(directory, manifest, &info, &code, stripe, &rows, cancel)?;
+        for data in &sums {
+            let name = parity_name(info.files.len());
+            let hash = save(&directory.join(&name), data, manifest)?;
+            info.files.push(ParityEntry { name, hash });
+        }
+    }
+    Ok(info)
+}
+
+// Compute the parity files at `numbers` in the manifest's list again from the chunks,
+// which have to be intact by now, and put each in place of the old one once it is
+// written in full. Returns their new hashes, in the same order.
+pub(crate) fn rewrite(
+    directory: &Path,
+    manifest: &Manifest,
+    numbers: &[usize],
+    cancel: &CancelToken,
+) -> Result<Vec<Option<String>>> {
+    let Some(info) = &manifest.parity else {
+        return Ok(Vec::new());
+    };
+    let shards = info.scheme.shards();
+    let mut hashes = Vec::with_capacity(numbers.len());
+    for &number in numbers {
+        let (stripe, row) = (number / shards, number % shards);
+        debug!("recomputing parity row {} of stripe {}", row, stripe);
+        let Some(code) = Code::of(info, manifest.chunks.len(), stripe) else {
+            return Err(too_wide());
+        };
+        let sums = stripe_sums(directory, manifest, info, &code, stripe, &[row], cancel)?;
+        let name = &info.files[number].name;
+        let path = directory.join(name);
+        let temp = directory.join(format!(".{}.repair", name));
+        let hash = match save(&temp, &sums[0], manifest) {
+            Ok(hash) => hash,
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                return Err(e);
+            }
+        };
+        fs::rename(&temp, &path).at(&path)?;
+        hashes.push(hash);
+    }
+    Ok(hashes)
+}
+
+// The parity rows `rows` of stripe `stripe`, computed from its chunks.
+fn stripe_sums(
+    directory: &Path,
+    manifest: &Manifest,
+    info: &ParityInfo,
+    code: &Code,
+    stripe: usize,
+    rows: &[usize],
+    cancel: &CancelToken,
+) -> Result<Vec<Vec<u8>>> {
+    let mut sums = Vec::with_capacity(rows.len());
+    for _ in rows {
+        sums.push(buffer(info.size).at(directory)?);
+    }
+    for (index, entry) in manifest.indexed() {
+        let (within, column) = info.stripe_of(index);
+        if within != stripe {
+            continue;
+        }
+        let factors: Vec<u8> = rows.iter().map(|&row| code.factor(row, column)).collect();
+        let chunk_path = directory.join(entry.file());
+        let compression = manifest.compression_of(entry);
+        accumulate(
+            &mut sums,
+            &factors,
+            &chunk_path,
+            compression,
+            entry.size,
+            cancel,
+        )?;
+    }
+    Ok(sums)
+}
+
+// Write one parity file, hashed when the manifest hashes its chunks.
+fn save(path: &Path, data: &[u8], manifest: &Manifest) -> Result<Option<String>> {
+    fs::write(path, data).at(path)?;
+    let hash = manifest.hash.map(|algorithm| {
+        let mut hasher = algorithm.hasher();
+        hasher.update(data);
+        hasher.finish()
+    });
+    log_written(path, data.len() as u64, hash.as_deref());
+    Ok(hash)
+}
+
+// Indices of the chunks `manifest` lists that are missing from `directory` or no longer
+// hold what it recorded: the wrong size, or with hashes, the wrong hash. Without hashes
+// a chunk that changed but kept its size goes unnoticed.
+pub(crate) fn damaged(
+    directory: &Path,
+    manifest: &Manifest,
+    cancel: &CancelToken,
+) -> Result<Vec<usize>> {
+    let mut damaged = Vec::new();
+    for (index, entry) in manifest.indexed() {
+        if !is_chunk_intact(directory, manifest, entry, cancel)? {
+            debug!(
+                "{} is missing or damaged",
+                directory.join(entry.file()).display()
+            );
+            damaged.push(index);
+        }
+    }
+    Ok(damaged)
+}
+
+// Whether the chunk `entry` is in `directory` as `manifest` recorded it, as far as
+// `damaged` can tell: by its hash if it has one, and a compressed or armored chunk
+// otherwise by whether it still decodes.
+pub(crate) fn is_chunk_intact(
+    directory: &Path,
+    manifest: &Manifest,
+    entry: &ChunkEntry,
+    cancel: &CancelToken,
+) -> Result<bool> {
+    let path = directory.join(entry.file());
+    let compression = manifest.compression_of(entry);
+    match fs::metadata(&path) {
+        Ok(metadata) if !metadata.is_file() => Ok(false),
+        Ok(metadata) if compression.is_none() && metadata.len() != entry.size => Ok(false),
+        Ok(_) => match (manifest.hash, &entry.hash) {
+            (Some(algorithm), Some(expected)) => {
+                let actual = hash_chunk(&path, compression, algorithm, &mut |_| {}, cancel)?;
+                Ok(actual.as_ref() == Some(expected))
+            }
+            _ if !compression.is_none() => decodes(&path, compression, &mut |_| {}, cancel),
+            _ => Ok(true),
+        },
+        Err(_) => Ok(false),
+    }
+}
+
+// Whether parity file `entry` is there with the size, and if recorded the hash, it was
+// written with.
+pub(crate) fn is_intact(
+    directory: &Path,
+    manifest: &Manifest,
+    entry: &ParityEntry,
+    copied: &mut dyn FnMut(u64),
+    cancel: &CancelToken,
+) -> Result<bool> {
+    let Some(info) = &manifest.parity else {
+        return Ok(false);
+    };
+    let path = directory.join(&entry.name);
+    match fs::metadata(&path) {
+        Ok(metadata) if metadata.is_file() && metadata.len() == info.size => {}
+        _ => return Ok(false),
+    }
+    let (Some(algorithm), Some(expected)) = (manifest.hash, &entry.hash) else {
+        return Ok(true);
+    };
+    let actual = hash_chunk(&path, Compression::None, algorithm, copied, cancel)?;
+    Ok(actual.as_ref() == Some(expected))
+}
+
+// The chunks of `damaged` by the stripe they belong to, every stripe of a set of
+// `chunks` listed whether it lost any or not.
+pub(crate) fn stripes(
+    info: &ParityInfo,
+    chunks: usize,
+    damaged: &[usize],
+) -> BTreeMap<usize, Vec<usize>> {
+    let count = chunks.div_ceil(info.stripe_chunks.max(1));
+    let mut stripes: BTreeMap<usize, Vec<usize>> = (0..count).map(|s| (s, Vec::new())).collect();
+    for &index in damaged {
+        let (stripe, _) = info.stripe_of(index);
+        stripes.entry(stripe).or_default().push(index);
+    }
+    stripes
+}
+
+// Which parity files of a stripe its lost chunks are to be rebuilt from, as many as
+// there are of them.
+pub(crate) struct Plan {
+    stripe: usize,
+    indices: Vec<usize>,
+    rows: Vec<usize>,
+}
+
+// Work out how to rebuild every chunk of `damaged` from the parity and the rest of its
+// stripe. When a stripe hasn't enough intact parity files, its damaged chunks are
+// reported missing.
+pub(crate) fn plan(
+    directory: &Path,
+    manifest: &Manifest,
+    damaged: &[usize],
+    cancel: &CancelToken,
+) -> Result<Vec<Plan>> {
+    let Some(info) = &manifest.parity else {
+        return Err(missing(damaged));
+    };
+    let stripes = stripes(info, manifest.chunks.len(), damaged);
+    let mut plans = Vec::new();
+    let mut lost = Vec::new();
+    for (stripe, indices) in stripes.into_iter().filter(|(_, lost)| !lost.is_empty()) {
+        let mut rows = Vec::with_capacity(indices.len());
+        for (row, entry) in info.stripe_files(stripe).iter().enumerate() {
+            if rows.len() == indices.len() {
+                break;
+            }
+            if is_intact(directory, manifest, entry, &mut |_| {}, cancel)? {
+                rows.push(row);
+            } else {
+                warn!("{} is missing or damaged as well", entry.name);
+            }
+        }
+        if rows.len() < indices.len() {
+            lost.extend(indices);
+        } else {
+            plans.push(Plan {
+                stripe,
+                indices,
+                rows,
+            });
+        }
+    }
+    if !lost.is_empty() {
+        return Err(missing(&lost));
+    }
+    Ok(plans)
+}
+
+// Rebuild the chunks `plans` cover, writing each one's original bytes uncompressed to
+// `output(entry)`. A rebuilt chunk that doesn't come out as recorded (padding that
+// isn't zero, the wrong hash) means the parity doesn't match the chunks it was
+// computed over, and is reported missing.
+pub(crate) fn rebuild(
+    directory: &Path,
+    manifest: &Manifest,
+    plans: &[Plan],
+    output: &dyn Fn(&ChunkEntry) -> PathBuf,
+    cancel: &CancelToken,
+) -> Result<()> {
+    for plan in plans {
+        debug!(
+            "rebuilding chunks {:?} of stripe {} from parity rows {:?}",
+            plan.indices, plan.stripe, plan.rows
+        );
+        rebuild_stripe(directory, manifest, plan, output, cancel)?;
+    }
+    Ok(())
+}
+
+// Within a stripe, the parity files of `rows` less what the surviving chunks put in them
+// leaves the lost chunks' share of each: a square system with those chunks as unknowns.
+fn rebuild_stripe(
+    directory: &Path,
+    manifest: &Manifest,
+    plan: &Plan,
+    output: &dyn Fn(&ChunkEntry) -> PathBuf,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let (stripe, indices, rows) = (plan.stripe, plan.indices.as_slice(), plan.rows.as_slice());
+    let Some(info) = &manifest.parity else {
+        return Err(missing(indices));
+    };
+    let Some(code) = Code::of(info, manifest.chunks.len(), stripe) else {
+        return Err(missing(indices));
+    };
+    let files = info.stripe_files(stripe);
+    let mut sums = Vec::with_capacity(rows.len());
+    for &row in rows {
+        let path = directory.join(&files[row].name);
+        sums.push(fs::read(&path).at(&path)?);
+    }
+    let mut targets = Vec::with_capacity(indices.len());
+    for (index, entry) in manifest.indexed() {
+        let (within, column) = info.stripe_of(index);
+        if within != stripe {
+            continue;
+        }
+        if indices.contains(&index) {
+            targets.push((column, entry));
+            continue;
+        }
+        let factors: Vec<u8> = rows.iter().map(|&row| code.factor(row, column)).collect();
+        let chunk_path = directory.join(entry.file());
+        let compression = manifest.compression_of(entry);
+        accumulate(
+            &mut sums,
+            &factors,
+            &chunk_path,
+            compression,
+            entry.size,
+            cancel,
+        )?;
+    }
+
+    let lost: Vec<usize> = targets.iter().map(|&(column, _)| column).collect();
+    let Some(solution) = code.solve(rows, &lost) else {
+        return Err(missing(indices));
+    };
+    for (weights, &(_, entry)) in solution.iter().zip(&targets) {
+        let path = output(entry);
+        match solve_chunk(&sums, weights, entry, manifest, &path, cancel) {
+            Ok(true) => {}
+            Ok(false) => {
+                let _ = fs::remove_file(&path);
+                warn!(
+                    "{} does not come out as recorded from the parity",
+                    entry.name
+                );
+                return Err(missing(indices));
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&path);
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Write the lost chunk that `weights` picks out of `sums` to `path`, a block at a time.
+// False when it doesn't come out as its manifest entry says it should.
+fn solve_chunk(
+    sums: &[Vec<u8>],
+    weights: &[u8],
+    entry: &ChunkEntry,
+    manifest: &Manifest,
+    path: &Path,
+    cancel: &CancelToken,
+) -> Result<bool> {
+    let len = sums.first().map_or(0, Vec::len);
+    let size = usize::try_from(entry.size).unwrap_or(usize::MAX);
+    if size > len {
+        return Ok(false);
+    }
+    let mut hasher = manifest.hash.map(|algorithm| algorithm.hasher());
+    let mut file = BufWriter::new(File::create(path).at(path)?);
+    let mut block = vec![0; DEFAULT_BUFFER_SIZE.min(len.max(1))];
+    let mut start = 0;
+    while start < len {
+        cancel.check()?;
+        let end = (start + block.len()).min(len);
+        let block = &mut block[..end - start];
+        block.fill(0);
+        for (sum, &weight) in sums.iter().zip(weights) {
+            galois_8::mul_slice_xor(weight, &sum[start..end], block);
+        }
+        // Past the chunk's own length it was padding, which has to come out as zeros
+        let keep = size.clamp(start, end) - start;
+        if block[keep..].iter().any(|&b| b != 0) {
+            return Ok(false);
+        }
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&block[..keep]);
+        }
+        file.write_all(&block[..keep]).at(path)?;
+        start = end;
+    }
+    file.flush().at(path)?;
+    let hash = hasher.map(ChunkHasher::finish);
+    Ok(entry.hash.is_none() || hash == entry.hash)
+}
+
+// The factors of one stripe's chunks in its parity files: `factors[row][column]`.
+// Sets with a single XOR file don't go through the crate, so their one stripe isn't
+// limited by the size of the field.
+struct Code {
+    codec: Option<ReedSolomon>,
+    factors: Vec<Vec<u8>>,
+}
+
+impl Code {
+    // The code of `stripe` in a set of `chunks`; None if the stripe holds more chunks
+    // and parity files than GF(2^8) has room for.
+    fn of(info: &ParityInfo, chunks: usize, stripe: usize) -> Option<Code> {
+        let start = stripe * info.stripe_chunks;
+        let columns = chunks.saturating_sub(start).min(info.stripe_chunks).max(1);
+        let shards = info.scheme.shards();
+        if info.scheme == Parity::Xor {
+            return Some(Code {
+                codec: None,
+                factors: vec![vec![1; columns]],
+            });
+        }
+        let codec = ReedSolomon::new(columns, shards).ok()?;
+        // Encoding the identity, chunk `column` a one at `column` and zeros elsewhere,
+        // leaves each parity row's factors in its parity file
+        let identity: Vec<Vec<u8>> = (0..columns)
+            .map(|column| (0..columns).map(|i| u8::from(i == column)).collect())
+            .collect();
+        let mut factors = vec![vec![0; columns]; shards];
+        codec.encode_sep(&identity, &mut factors).ok()?;
+        Some(Code {
+            codec: Some(codec),
+            factors,
+        })
+    }
+
+    fn factor(&self, row: usize, column: usize) -> u8 {
+        self.factors[row][column]
+    }
+
+    // How to weigh what is left of parity rows `rows`, once the surviving chunks are
+    // taken out, to get each of the chunks at `lost`: `weights[lost][row]`. None when
+    // those rows can't be solved for them.
+    fn solve(&self, rows: &[usize], lost: &[usize]) -> Option<Vec<Vec<u8>>> {
+        let Some(codec) = &self.codec else {
+            return (rows.len() == 1 && lost.len() == 1).then(|| vec![vec![1]]);
+        };
+        // With the surviving chunks all zeros, what is left of each row is the row
+        // itself, so reconstructing from row `i` set to a one at `i` gives every lost
+        // chunk's weight of it
+        let columns = codec.data_shard_count();
+        let mut shards: Vec<Option<Vec<u8>>> = (0..codec.total_shard_count())
+            .map(|shard| (shard < columns).then(|| vec![0; rows.len()]))
+            .collect();
+        for &column in lost {
+            *shards.get_mut(column)? = None;
+        }
+        for (i, &row) in rows.iter().enumerate() {
+            let unit = (0..rows.len()).map(|j| u8::from(i == j)).collect();
+            *shards.get_mut(columns + row)? = Some(unit);
+        }
+        codec.reconstruct_data(&mut shards).ok()?;
+        lost.iter().map(|&column| shards[column].take()).collect()
+    }
+}
+
+fn too_wide() -> SplitterError {
+    SplitterError::InvalidOption {
+        field: "parity",
+        reason: "has more chunks and parity files to a stripe than the code allows",
+    }
+}
+
+fn missing(indices: &[usize]) -> SplitterError {
+    SplitterError::MissingChunks {
+        indices: indices.iter().map(|&index| index as u64).collect(),
+    }
+}
+
+// A zeroed buffer the length of the longest chunk.
+fn buffer(size: u64) -> io::Result<Vec<u8>> {
+    let size = usize::try_from(size).map_err(|_| io::Error::from(io::ErrorKind::OutOfMemory))?;
+    Ok(vec![0; size])
+}
+
+// Add the original bytes of one chunk, times each of `factors`, to the start of the
+// matching one of `sums`.
+fn accumulate(
+    sums: &mut [Vec<u8>],
+    factors: &[u8],
+    path: &Path,
+    compression: Compression,
+    size: u64,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let mut reader = ChunkReader::open(path, compression).at(path)?;
+    let mut writer = Counting {
+        inner: SumWriter {
+            sums,
+            factors,
+            position: 0,
+        },
+        copied: &mut |_| {},
+        cancel,
+    };
+    let copied = copy_overlapped(&Io::default(), &mut reader, &mut writer, None).at(path)?;
+    if copied != size {
+        return Err(SplitterError::ChangedSize {
+            path: path.to_path_buf(),
+        });
+    }
+    Ok(())
+}
+
+// Adds everything written to it, times a factor each, into buffers, from their start.
+struct SumWriter<'a> {
+    sums: &'a mut [Vec<u8>],
+    factors: &'a [u8],
+    position: usize,
+}
+
+impl Write for SumWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = self.position + buf.len();
+        for (sum, &factor) in self.sums.iter_mut().zip(self.factors) {
+            let Some(target) = sum.get_mut(self.position..end) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "chunk is longer than the parity",
+                ));
+            };
+            galois_8::mul_slice_xor(factor, buf, target);
+        }
+        self.position = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(scheme: Parity, chunks: usize) -> ParityInfo {
+        ParityInfo {
+            scheme,
+            stripe_chunks: scheme.stripe_chunks(chunks),
+            size: 0,
+            files: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn any_rows_solve_for_as_many_lost_chunks() {
+        let columns = 6;
+        let code = Code::of(
+            &info(Parity::ReedSolomon { shards: 3 }, columns),
+            columns,
+            0,
+        )
+        .unwrap();
+        let data: Vec<u8> = (0..columns as u8)
+            .map(|i| i.wrapping_mul(97) ^ 0x5a)
+            .collect();
+        let parity: Vec<u8> = (0..3)
+            .map(|row| {
+                (0..columns).fold(0, |sum, column| {
+                    sum ^ galois_8::mul(code.factor(row, column), data[column])
+                })
+            })
+            .collect();
+        for (lost, rows) in [
+            (vec![0], vec![2]),
+            (vec![1, 4], vec![0, 2]),
+            (vec![0, 3, 5], vec![0, 1, 2]),
+            (vec![5, 2], vec![1, 0]),
+        ] {
+            // What is left of each row once the surviving chunks are taken out
+            let left: Vec<u8> = rows
+                .iter()
+                .map(|&row| {
+                    (0..columns)
+                        .filter(|column| !lost.contains(column))
+                        .fold(parity[row], |sum, column| {
+                            sum ^ galois_8::mul(code.factor(row, column), data[column])
+                        })
+                })
+                .collect();
+            let weights = code.solve(&rows, &lost).unwrap();
+            for (&column, weights) in lost.iter().zip(&weights) {
+                let solved = weights
+                    .iter()
+                    .zip(&left)
+                    .fold(0, |sum, (&weight, &left)| sum ^ galois_8::mul(weight, left));
+                assert_eq!(solved, data[column], "{:?} from {:?}", lost, rows);
+            }
+        }
+    }
+
+    #[test]
+    fn xor_is_one_row_of_ones_over_any_number_of_chunks() {
+        let code = Code::of(&info(Parity::Xor, 1000), 1000, 0).unwrap();
+        assert!((0..1000).all(|column| code.factor(0, column) == 1));
+        assert_eq!(code.solve(&[0], &[417]), Some(vec![vec![1]]));
+    }
+
+    #[test]
+    fn the_last_stripe_is_coded_for_the_chunks_it_has() {
+        let scheme = Parity::ReedSolomon { shards: 4 };
+        let info = info(scheme, 300);
+        assert_eq!(info.stripe_chunks, 150);
+        let last = Code::of(&info, 290, 1).unwrap();
+        assert_eq!(last.factors[0].len(), 140);
+        let too_wide = ParityInfo {
+            stripe_chunks: 253,
+            ..info
+        };
+        assert!(Code::of(&too_wide, 253, 0).is_none());
+    }
+}
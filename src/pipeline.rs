@@ -0,0 +1,285 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+use log::info;
+
+use crate::cache::{self, Released};
+use crate::manifest::ChunkHasher;
+use crate::size::format_size;
+
+// Two buffers are enough for one to be filled while the other is drained
+const BUFFERS: usize = 2;
+
+pub const DEFAULT_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+// Adaptive sizing, unless `--buffer-size` says what size to use: how much suits a disk
+// or share varies too much for one size to do for all, so the first bytes a run copies
+// are copied at SMALL_BUFFER_SIZE and at `buffer_size` in turn, TRIAL_BYTES at a time
+// and TRIAL_ROUNDS times each, and whichever went faster is used for the rest of it.
+// When the sizes change goes by bytes copied, never by time, and only how much is read
+// and written at once changes, never what, so output is the same whichever is picked.
+// Buffers are never larger than `buffer_size`, which `fit` and `memory` go by.
+const SMALL_BUFFER_SIZE: usize = 1 << 20;
+const TRIAL_BYTES: u64 = 32 << 20;
+const TRIAL_ROUNDS: u64 = 2;
+
+// How one split or reconstruction copies, from its options: with buffers of
+// `buffer_size`, or with `adaptive` whichever of that and SMALL_BUFFER_SIZE turns out
+// faster, and with `direct_io` the data it copies dropped from the page cache, see
+// `cache`. Its threads share it, and so the trial.
+#[derive(Debug)]
+pub(crate) struct Io {
+    buffer_size: usize,
+    adaptive: bool,
+    direct_io: bool,
+    // Bytes copied during the trial, across all threads, and at each size with the
+    // nanoseconds that took
+    tried: AtomicU64,
+    tried_bytes: [AtomicU64; 2],
+    tried_nanos: [AtomicU64; 2],
+    // The size settled on, index into `candidates`, or usize::MAX while trying
+    settled: AtomicUsize,
+}
+
+impl Default for Io {
+    fn default() -> Io {
+        Io::new(DEFAULT_BUFFER_SIZE, false, false)
+    }
+}
+
+impl Io {
+    pub fn new(buffer_size: usize, adaptive: bool, direct_io: bool) -> Io {
+        Io {
+            buffer_size,
+            adaptive,
+            direct_io,
+            tried: AtomicU64::new(0),
+            tried_bytes: [AtomicU64::new(0), AtomicU64::new(0)],
+            tried_nanos: [AtomicU64::new(0), AtomicU64::new(0)],
+            settled: AtomicUsize::new(usize::MAX),
+        }
+    }
+
+    // Size of each I/O buffer, independent of the chunk size.
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    // `cache::release`, with `direct_io`.
+    pub fn release(&self, file: &File, offset: u64, len: u64, wrote: bool) -> io::Result<()> {
+        match self.direct_io {
+            true => cache::release(file, offset, len, wrote),
+            false => Ok(()),
+        }
+    }
+
+    // `file` read from `offset`, released from the cache as it goes with `direct_io`.
+    pub fn released(&self, file: File, offset: u64) -> Released {
+        Released {
+            file,
+            offset,
+            direct_io: self.direct_io,
+        }
+    }
+
+    fn candidates(&self) -> [usize; 2] {
+        [SMALL_BUFFER_SIZE.min(self.buffer_size), self.buffer_size]
+    }
+
+    // The size to read the next buffer with, and which candidate that is while they are
+    // being tried.
+    fn next_size(&self) -> (usize, Option<usize>) {
+        let candidates = self.candidates();
+        if !self.adaptive || candidates[0] == candidates[1] {
+            return (self.buffer_size, None);
+        }
+        if let Some(settled) = self.settled() {
+            return (candidates[settled], None);
+        }
+        let round = self.tried.load(Ordering::Relaxed) / TRIAL_BYTES;
+        if round >= 2 * TRIAL_ROUNDS {
+            return (candidates[self.settle()], None);
+        }
+        let which = (round % 2) as usize;
+        (candidates[which], Some(which))
+    }
+
+    fn settled(&self) -> Option<usize> {
+        Some(self.settled.load(Ordering::Relaxed)).filter(|&settled| settled < 2)
+    }
+
+    fn record(&self, which: usize, bytes: u64, nanos: u64) {
+        self.tried_bytes[which].fetch_add(bytes, Ordering::Relaxed);
+        self.tried_nanos[which].fetch_add(nanos, Ordering::Relaxed);
+        self.tried.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    // Bytes per second at candidate `which` during the trial.
+    fn rate(&self, which: usize) -> u64 {
+        let bytes = self.tried_bytes[which].load(Ordering::Relaxed) as u128;
+        let nanos = self.tried_nanos[which].load(Ordering::Relaxed).max(1) as u128;
+        u64::try_from(bytes * 1_000_000_000 / nanos).unwrap_or(u64::MAX)
+    }
+
+    // Pick the faster candidate, once, for every thread.
+    fn settle(&self) -> usize {
+        let faster = match self.rate(0) >= self.rate(1) {
+            true => 0,
+            false => 1,
+        };
+        let settled =
+            self.settled
+                .compare_exchange(usize::MAX, faster, Ordering::Relaxed, Ordering::Relaxed);
+        match settled {
+            Ok(_) => {
+                let (sizes, other) = (self.candidates(), 1 - faster);
+                info!(
+                    "copying with {} buffers from now on: {}/s with them against {}/s with {}",
+                    format_size(sizes[faster] as u64),
+                    format_size(self.rate(faster)),
+                    format_size(self.rate(other)),
+                    format_size(sizes[other] as u64)
+                );
+                faster
+            }
+            Err(settled) => settled,
+        }
+    }
+
+    // With adaptive sizing, the buffer size settled on and the one it was faster than,
+    // for the report once the run is over. Settled then if both were tried but the run
+    // ended before all the rounds; None if it ended before both were.
+    pub fn chosen(&self) -> Option<(usize, usize)> {
+        if !self.adaptive {
+            return None;
+        }
+        let tried = |which: usize| self.tried_bytes[which].load(Ordering::Relaxed) > 0;
+        let settled = match self.settled() {
+            Some(settled) => settled,
+            None if tried(0) && tried(1) => self.settle(),
+            None => return None,
+        };
+        let candidates = self.candidates();
+        Some((candidates[settled], candidates[1 - settled]))
+    }
+}
+
+// What a thread copying holds in buffers: the BUFFERS `copy_overlapped` passes back and
+// forth, and one more for the buffered reader or writer around it
+pub const BUFFERS_PER_THREAD: usize = BUFFERS + 1;
+
+// Buffers are not made smaller than this to fit `--max-memory`; a multiple of the block
+// size direct I/O wants
+pub const MIN_BUFFER_SIZE: usize = 64 << 10;
+
+// What `threads` threads copying at once with buffers of `buffer_size` hold in them.
+pub fn memory(threads: usize, buffer_size: usize) -> u64 {
+    (threads * BUFFERS_PER_THREAD) as u64 * buffer_size as u64
+}
+
+// The threads and buffer size to copy with, for `threads` wanted with buffers of
+// `buffer_size`, that stay within `max_memory`, a cap on the buffers held at once across
+// all threads for machines with little RAM: fewer threads first, then for a single
+// thread smaller buffers, down to MIN_BUFFER_SIZE. As they are without a cap.
+pub fn fit(threads: usize, buffer_size: usize, max_memory: Option<u64>) -> (usize, usize) {
+    let Some(max) = max_memory else {
+        return (threads, buffer_size);
+    };
+    match usize::try_from(max / memory(1, buffer_size)).unwrap_or(usize::MAX) {
+        0 => {
+            let smaller = (max / BUFFERS_PER_THREAD as u64) as usize;
+            let smaller = smaller / MIN_BUFFER_SIZE * MIN_BUFFER_SIZE;
+            (1, smaller.max(MIN_BUFFER_SIZE))
+        }
+        fitting => (threads.min(fitting), buffer_size),
+    }
+}
+
+// Copy everything `reader` yields into `writer`, feeding `hasher` on the way. A helper
+// thread reads the next block while this one hashes and writes the previous, so the
+// disk doesn't sit idle during hashing. The same BUFFERS blocks are passed back and
+// forth, keeping memory use fixed. Each block is timed from the last one's end while
+// buffer sizes are being tried.
+pub(crate) fn copy_overlapped<R: Read + Send, W: Write>(
+    io: &Io,
+    reader: &mut R,
+    writer: &mut W,
+    mut hasher: Option<&mut ChunkHasher>,
+) -> io::Result<u64> {
+    type Block = (Vec<u8>, usize, Option<usize>);
+    let (full_sender, full_receiver) = mpsc::sync_channel::<io::Result<Block>>(BUFFERS);
+    let (free_sender, free_receiver) = mpsc::sync_channel::<Vec<u8>>(BUFFERS);
+    // Room for either size while they are tried
+    let capacity = match io.next_size() {
+        (size, None) => size,
+        (_, Some(_)) => io.buffer_size,
+    };
+    for _ in 0..BUFFERS {
+        let _ = free_sender.send(vec![0; capacity]);
+    }
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            // Runs until the input is exhausted or the writing side goes away
+            for mut buffer in free_receiver {
+                let (size, trying) = io.next_size();
+                let size = size.min(buffer.len());
+                let result = read_full(reader, &mut buffer[..size]);
+                let done = !matches!(result, Ok(read) if read > 0);
+                if full_sender
+                    .send(result.map(|read| (buffer, read, trying)))
+                    .is_err()
+                    || done
+                {
+                    return;
+                }
+            }
+        });
+
+        let mut copied = 0;
+        let mut since = Instant::now();
+        let result = loop {
+            match full_receiver.recv() {
+                Ok(Ok((_, 0, _))) | Err(_) => break Ok(copied),
+                Ok(Ok((buffer, read, trying))) => {
+                    if let Some(hasher) = hasher.as_deref_mut() {
+                        hasher.update(&buffer[..read]);
+                    }
+                    if let Err(e) = writer.write_all(&buffer[..read]) {
+                        break Err(e);
+                    }
+                    copied += read as u64;
+                    let _ = free_sender.send(buffer);
+                    if let Some(which) = trying {
+                        let nanos = since.elapsed().as_nanos();
+                        io.record(which, read as u64, u64::try_from(nanos).unwrap_or(u64::MAX));
+                    }
+                    since = Instant::now();
+                }
+                Ok(Err(e)) => break Err(e),
+            }
+        };
+        // Let the reader finish before the scope waits for it
+        drop(free_sender);
+        drop(full_receiver);
+        result
+    })
+}
+
+// Fill as much of `buffer` as the reader allows; less than full means end of input.
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
@@ -0,0 +1,198 @@
+// Named split settings kept in config.json in the config directory, for splitting the
+// same kind of file the same way every time: `split --profile NAME` takes what the
+// profile holds for whatever isn't given on the command line, the interactive split can
+// save its answers as one, and `profile list` and `profile show` say what there is.
+// Unlike the history, a config file that can't be read is an error, as a profile asked
+// for that silently went missing would split with the wrong settings. It also holds
+// `"notify": true`, to have `--notify` without giving it, and `"temp_dir"`, for a
+// `--temp-dir` given once.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use reconstruct_large_file::manifest::{Compression, HashAlgorithm};
+use reconstruct_large_file::size::format_size;
+use reconstruct_large_file::{Compat, SplitOptions, SplitOptionsBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+const CONFIG_NAME: &str = "config.json";
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    // Each split gets a directory of its own in here, named for the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination_root: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<HashAlgorithm>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    // The codec's default when not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_level: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compat: Option<Compat>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub join_scripts: bool,
+}
+
+impl Profile {
+    // Where to split into in `destination_root`: named as `default`, what a split
+    // without a destination writes to, or with a number added when that is taken.
+    pub fn savedir(&self, default: &Path) -> Option<PathBuf> {
+        let root = self.destination_root.as_ref()?;
+        let savedir = root.join(default.file_name()?);
+        Some(match savedir.exists() {
+            true => crate::watch::unused(&savedir),
+            false => savedir,
+        })
+    }
+
+    // A split of `input` into `savedir` with everything the profile holds.
+    pub fn builder(&self, input: &Path, savedir: &Path) -> SplitOptionsBuilder {
+        let mut builder = SplitOptions::builder(input, savedir)
+            .hash(self.hash)
+            .compat(self.compat)
+            .join_scripts(self.join_scripts);
+        if let Some(chunk_size) = self.chunk_size {
+            builder = builder.chunk_size(chunk_size);
+        }
+        if let Some(compression) = self.compression {
+            builder = builder
+                .compression(compression)
+                .compression_level(self.level().unwrap_or(0));
+        }
+        builder
+    }
+
+    pub fn level(&self) -> Option<u32> {
+        let compression = self.compression?;
+        Some(
+            self.compression_level
+                .unwrap_or_else(|| compression.default_level()),
+        )
+    }
+
+    // What the profile holds, a line for each setting, for `profile show`.
+    pub fn describe(&self) -> Vec<(&'static str, String)> {
+        let mut lines = Vec::new();
+        if let Some(root) = &self.destination_root {
+            lines.push(("Destination root", root.display().to_string()));
+        }
+        if let Some(chunk_size) = self.chunk_size {
+            lines.push(("Chunk size", format_size(chunk_size)));
+        }
+        if let Some(hash) = self.hash {
+            lines.push(("Hash", format!("{:?}", hash).to_lowercase()));
+        }
+        if let Some(compression) = self.compression {
+            let level = self.level().unwrap_or(0);
+            lines.push((
+                "Compression",
+                match compression {
+                    _ if *compression.levels().end() == 0 => compression.name().to_string(),
+                    Compression::Zstd { long: true, .. } => {
+                        format!("{}:{}, long window", compression.name(), level)
+                    }
+                    _ => format!("{}:{}", compression.name(), level),
+                },
+            ));
+        }
+        if let Some(compat) = self.compat {
+            lines.push(("Chunk names", format!("{:?}", compat).to_lowercase()));
+        }
+        if self.join_scripts {
+            lines.push(("Join scripts", "yes".to_string()));
+        }
+        lines
+    }
+}
+
+// The config file, keeping whatever else it holds when a profile is saved.
+#[derive(Default, Serialize, Deserialize)]
+struct Config {
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    notify: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    temp_dir: Option<PathBuf>,
+    #[serde(flatten)]
+    other: Map<String, Value>,
+}
+
+// Where this tool looks for settings given once rather than on every run.
+pub fn config_dir() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| crate::home_dir().map(|home| home.join(".config")))?;
+    Some(config_dir.join("file_splitter"))
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    Some(config_dir()?.join(CONFIG_NAME))
+}
+
+fn load_config() -> io::Result<Config> {
+    let Some(path) = config_path() else {
+        return Ok(Config::default());
+    };
+    let data = match fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(e),
+    };
+    serde_json::from_str(&data).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a valid config file: {}", path.display(), e),
+        )
+    })
+}
+
+// Whether the config file asks for `--notify`; not when it can't be read, as going
+// without a notification is no reason to fail.
+pub fn notify() -> bool {
+    load_config().is_ok_and(|config| config.notify)
+}
+
+// The directory the config file names for temporary files, if any; none when it can't
+// be read, as writing them beside the output instead still works.
+pub fn temp_dir() -> Option<PathBuf> {
+    load_config().ok()?.temp_dir
+}
+
+// Every profile, by name; none without a config file.
+pub fn list() -> io::Result<BTreeMap<String, Profile>> {
+    Ok(load_config()?.profiles)
+}
+
+pub fn load(name: &str) -> io::Result<Option<Profile>> {
+    Ok(list()?.remove(name))
+}
+
+// Save `profile` as `name`, replacing any by that name.
+pub fn save(name: &str, profile: &Profile) -> io::Result<PathBuf> {
+    let path = config_path().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "no home directory for the config file",
+        )
+    })?;
+    let mut config = load_config()?;
+    config.profiles.insert(name.to_string(), profile.clone());
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut data = serde_json::to_string_pretty(&config).map_err(io::Error::other)?;
+    data.push('\n');
+    fs::write(&path, data)?;
+    Ok(path)
+}
@@ -0,0 +1,480 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use reconstruct_large_file::size::format_size;
+use reconstruct_large_file::{
+    Compression, FileChange, MirrorReport, Par2Report, ProgressEvent, Report, SplitterError,
+};
+use serde_json::{Value, json};
+
+use crate::style;
+
+// How often the status line is redrawn on a terminal.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+// Without a terminal there is no line to redraw, so print one every this many chunks.
+const LOG_EVERY_CHUNKS: u64 = 10;
+// Byte counts in `--progress json` are merged into at most one line per this long.
+const JSON_INTERVAL: Duration = Duration::from_millis(100);
+
+// Status line for a running split, fed with the library's progress events. `total`
+// is None when the size of the source isn't known up front, in which case
+// only bytes and rate are shown.
+pub struct SplitProgress {
+    total: Option<u64>,
+    chunk_size: u64,
+    // How many chunks there are to go through, when known rather than estimated
+    count: Option<u64>,
+    terminal: bool,
+    chunks: u64,
+    bytes: u64,
+    started: Instant,
+    last_draw: Instant,
+    bytes_at_last_draw: u64,
+    rate: f64,
+}
+
+impl SplitProgress {
+    pub fn new(total: Option<u64>, chunk_size: u64) -> Self {
+        let now = Instant::now();
+        SplitProgress {
+            total,
+            chunk_size,
+            count: None,
+            terminal: style::status_line(),
+            chunks: 0,
+            bytes: 0,
+            started: now,
+            last_draw: now,
+            bytes_at_last_draw: 0,
+            rate: 0.0,
+        }
+    }
+
+    // A status line for going through `count` chunk files of `total` bytes between them.
+    pub fn counted(total: u64, count: u64) -> Self {
+        SplitProgress {
+            count: Some(count),
+            ..SplitProgress::new(Some(total), total.div_ceil(count.max(1)).max(1))
+        }
+    }
+
+    // On a terminal the line follows the bytes as they are copied; a log gets a line
+    // per LOG_EVERY_CHUNKS finished chunks.
+    pub fn update(&mut self, event: &ProgressEvent) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_draw);
+        match *event {
+            ProgressEvent::BytesCopied { delta } => {
+                self.bytes += delta;
+                if !self.terminal || elapsed < REFRESH_INTERVAL {
+                    return;
+                }
+            }
+            ProgressEvent::ChunkFinished { .. } => {
+                self.chunks += 1;
+                if self.terminal || !self.chunks.is_multiple_of(LOG_EVERY_CHUNKS) {
+                    return;
+                }
+            }
+            _ => return,
+        }
+        self.rate = (self.bytes - self.bytes_at_last_draw) as f64 / elapsed.as_secs_f64().max(1e-6);
+        self.last_draw = now;
+        self.bytes_at_last_draw = self.bytes;
+        self.draw();
+    }
+
+    // Draw the final state and move off the status line.
+    pub fn finish(&mut self) {
+        if self.chunks == 0 {
+            return;
+        }
+        let elapsed = self.started.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.rate = self.bytes as f64 / elapsed;
+        }
+        if self.terminal {
+            self.draw();
+            println!();
+        } else if !self.chunks.is_multiple_of(LOG_EVERY_CHUNKS) {
+            self.draw();
+        }
+    }
+
+    fn draw(&self) {
+        let rate = format!("{}/s", format_size(self.rate as u64));
+        let line = match self.total {
+            Some(total) => {
+                let estimated = self
+                    .count
+                    .unwrap_or_else(|| total.div_ceil(self.chunk_size).max(1));
+                let percent = if total == 0 {
+                    100.0
+                } else {
+                    self.bytes as f64 * 100.0 / total as f64
+                };
+                format!(
+                    "Chunk {}/{}  {} of {}  {:.0}%  {}",
+                    self.chunks,
+                    estimated,
+                    format_size(self.bytes),
+                    format_size(total),
+                    percent,
+                    rate
+                )
+            }
+            None => format!(
+                "Chunk {}  {}  {}",
+                self.chunks,
+                format_size(self.bytes),
+                rate
+            ),
+        };
+        if self.terminal {
+            style::draw_status(&line, false);
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+// Status for `reconstruct --from-url` while the chunks download: a line for every chunk
+// that arrives, with how fast it came, under a status line redrawn on a terminal with
+// the chunks and bytes so far and the rate over the last moment. Chunks download
+// several at once, so each one's rate is its size over the time since it started.
+pub struct FetchProgress {
+    terminal: bool,
+    started_chunks: BTreeMap<usize, (Instant, u64)>,
+    chunks: u64,
+    bytes: u64,
+    started: Instant,
+    last_draw: Instant,
+    bytes_at_last_draw: u64,
+    rate: f64,
+}
+
+impl FetchProgress {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        FetchProgress {
+            terminal: style::status_line(),
+            started_chunks: BTreeMap::new(),
+            chunks: 0,
+            bytes: 0,
+            started: now,
+            last_draw: now,
+            bytes_at_last_draw: 0,
+            rate: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, event: &ProgressEvent) {
+        let now = Instant::now();
+        match *event {
+            ProgressEvent::ChunkStarted { index, size } => {
+                self.started_chunks.insert(index, (now, size));
+            }
+            ProgressEvent::BytesCopied { delta } => {
+                self.bytes += delta;
+                let elapsed = now.duration_since(self.last_draw);
+                if self.terminal && elapsed >= REFRESH_INTERVAL {
+                    self.rate = (self.bytes - self.bytes_at_last_draw) as f64
+                        / elapsed.as_secs_f64().max(1e-6);
+                    self.last_draw = now;
+                    self.bytes_at_last_draw = self.bytes;
+                    self.draw();
+                }
+            }
+            ProgressEvent::ChunkFinished { index, .. } => {
+                self.chunks += 1;
+                let Some((started, size)) = self.started_chunks.remove(&index) else {
+                    return;
+                };
+                let elapsed = now.duration_since(started).as_secs_f64();
+                let line = format!(
+                    "Chunk {} arrived: {} in {:.2} s ({}/s)",
+                    index + 1,
+                    format_size(size),
+                    elapsed,
+                    format_size((size as f64 / elapsed.max(1e-6)) as u64)
+                );
+                match self.terminal {
+                    true => {
+                        style::draw_status(&line, true);
+                        self.draw();
+                    }
+                    false => println!("{}", line),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Move off the status line, after a summary of the whole download.
+    pub fn finish(&mut self) {
+        if self.chunks == 0 {
+            return;
+        }
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let line = format!(
+            "Downloaded {} chunks, {}, in {:.2} s ({}/s)",
+            self.chunks,
+            format_size(self.bytes),
+            elapsed,
+            format_size((self.bytes as f64 / elapsed.max(1e-6)) as u64)
+        );
+        match self.terminal {
+            true => style::draw_status(&line, true),
+            false => println!("{}", line),
+        }
+    }
+
+    fn draw(&self) {
+        let line = format!(
+            "Downloading: {} chunks done  {}  {}/s",
+            self.chunks,
+            format_size(self.bytes),
+            format_size(self.rate as u64)
+        );
+        style::draw_status(&line, false);
+    }
+}
+
+// `--progress json`: one object per line on stderr for every library event, as
+// `ProgressEvent` serializes itself, plus the bytes copied so far as `bytes`, the
+// milliseconds since the start as `elapsed_ms` and, when known up front, the number
+// of chunks as `total`. A failed operation ends with a `failed` event instead of
+// `completed`.
+pub struct JsonProgress {
+    total: Option<u64>,
+    started: Instant,
+    bytes: u64,
+    // Copied since the last `bytes_copied` line
+    pending: u64,
+    last_bytes: Instant,
+}
+
+impl JsonProgress {
+    pub fn new(total: Option<u64>) -> Self {
+        let now = Instant::now();
+        JsonProgress {
+            total,
+            started: now,
+            bytes: 0,
+            pending: 0,
+            last_bytes: now,
+        }
+    }
+
+    pub fn update(&mut self, event: &ProgressEvent) {
+        if let ProgressEvent::BytesCopied { delta } = *event {
+            self.bytes += delta;
+            self.pending += delta;
+            if self.last_bytes.elapsed() >= JSON_INTERVAL {
+                self.flush_bytes();
+            }
+            return;
+        }
+        // Bytes held back are counted before whatever comes next
+        self.flush_bytes();
+        self.emit(serde_json::to_value(event).unwrap_or_default());
+    }
+
+    // What a dry run found would be done, in place of anything being done.
+    pub fn planned(&mut self, changes: &[FileChange]) {
+        self.emit(json!({
+            "event": "planned",
+            "changes": changes,
+        }));
+    }
+
+    pub fn fail(&mut self, error: &SplitterError, exit_code: i32) {
+        self.flush_bytes();
+        self.emit(json!({
+            "event": "failed",
+            "error": error.to_string(),
+            "exit_code": exit_code,
+        }));
+    }
+
+    fn flush_bytes(&mut self) {
+        if self.pending == 0 {
+            return;
+        }
+        let event = ProgressEvent::BytesCopied {
+            delta: self.pending,
+        };
+        self.pending = 0;
+        self.last_bytes = Instant::now();
+        self.emit(serde_json::to_value(&event).unwrap_or_default());
+    }
+
+    fn emit(&self, mut line: Value) {
+        if let Some(fields) = line.as_object_mut() {
+            if let Some(total) = self.total {
+                fields.insert("total".to_string(), total.into());
+            }
+            fields.insert("bytes".to_string(), self.bytes.into());
+            let elapsed = self.started.elapsed().as_millis() as u64;
+            fields.insert("elapsed_ms".to_string(), elapsed.into());
+        }
+        let _ = writeln!(io::stderr().lock(), "{}", line);
+    }
+}
+
+// Wall time, bytes and chunk count of one operation, for the summary printed after it.
+// A compressed split also reports how much smaller the chunks came out, and how many
+// were stored raw for not compressing well, and an armored one how much text it made.
+// Chunks stored in another's file, parity written, and chunks rebuilt from it, are
+// mentioned too, as are PAR2 recovery slices and whether a mirror came out complete.
+pub struct Timing {
+    started: Instant,
+    bytes: u64,
+    chunks: u64,
+    stored: Option<(u64, usize)>,
+    armored: bool,
+    parity: Option<(usize, u64)>,
+    mirror: Option<MirrorReport>,
+    par2: Option<Par2Report>,
+    recovered: Vec<String>,
+    // Chunks stored in another's file, and the room that saved
+    deduplicated: Option<(usize, u64)>,
+    // The buffer size adaptive sizing settled on, and the one it was faster than
+    adapted: Option<(usize, usize)>,
+}
+
+impl Timing {
+    pub fn start() -> Self {
+        Timing {
+            started: Instant::now(),
+            bytes: 0,
+            chunks: 0,
+            stored: None,
+            armored: false,
+            parity: None,
+            mirror: None,
+            par2: None,
+            recovered: Vec::new(),
+            deduplicated: None,
+            adapted: None,
+        }
+    }
+
+    // Called with every progress event of the operation.
+    pub fn record(&mut self, event: &ProgressEvent) {
+        match event {
+            ProgressEvent::BytesCopied { delta } => self.bytes += delta,
+            ProgressEvent::ChunkFinished { .. } => self.chunks += 1,
+            ProgressEvent::Completed {
+                report: Report::Split(report),
+            } => {
+                if !report.compression.is_none() {
+                    let raw = report
+                        .chunks
+                        .iter()
+                        .filter(|chunk| chunk.compression.unwrap_or(report.compression).is_none())
+                        .count();
+                    self.stored = Some((report.stored_size, raw));
+                    self.armored = report.compression == Compression::Armor;
+                }
+                self.parity = report
+                    .parity
+                    .as_ref()
+                    .map(|parity| (parity.files.len(), parity.size));
+                self.mirror = report.mirror.clone();
+                self.par2 = report.par2.clone();
+                let shared = report.chunks.iter().filter(|c| c.same_as.is_some()).count();
+                self.deduplicated = (shared > 0).then_some((shared, report.deduplicated_size));
+                self.adapted = report.adapted_buffers;
+            }
+            ProgressEvent::Completed {
+                report: Report::Reconstruct(report),
+            } => {
+                self.recovered = report.recovered.clone();
+                self.adapted = report.adapted_buffers;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = self.bytes as f64 / elapsed.max(1e-6);
+        let mut summary = format!(
+            "{} in {} {} took {:.2} s ({}/s).",
+            format_size(self.bytes),
+            self.chunks,
+            match self.chunks {
+                1 => "chunk",
+                _ => "chunks",
+            },
+            elapsed,
+            format_size(rate as u64)
+        );
+        if let Some((stored, _)) = self.stored
+            && self.armored
+        {
+            summary += &format!(" Armored as {} of text.", format_size(stored));
+        } else if let Some((stored, raw)) = self.stored {
+            let ratio = self.bytes as f64 / stored.max(1) as f64;
+            summary += &format!(" Compressed to {} ({:.2}:1).", format_size(stored), ratio);
+            if raw > 0 {
+                summary += &format!(
+                    " {} of {} chunks didn't compress well and were stored as they are.",
+                    raw, self.chunks
+                );
+            }
+        }
+        if let Some((shared, saved)) = self.deduplicated {
+            summary += &format!(
+                " {} {} the same as an earlier one and stored in its file, saving {}.",
+                shared,
+                match shared {
+                    1 => "chunk was",
+                    _ => "chunks were",
+                },
+                format_size(saved)
+            );
+        }
+        if let Some((files, size)) = self.parity {
+            summary += &match files {
+                1 => format!(" Wrote a parity file of {}.", format_size(size)),
+                _ => format!(
+                    " Wrote {} parity files of {} each.",
+                    files,
+                    format_size(size)
+                ),
+            };
+        }
+        if let Some(par2) = &self.par2 {
+            summary += &format!(
+                " Wrote {} PAR2 recovery slices of {} for {} slices.",
+                par2.recovery_slices,
+                format_size(par2.slice_size),
+                par2.slices
+            );
+        }
+        if let Some(mirror) = &self.mirror {
+            summary += &match &mirror.failure {
+                None => format!(" Mirrored to {}.", mirror.directory.display()),
+                Some(failure) => format!(
+                    " The mirror in {} is incomplete; writing it failed at {}.",
+                    mirror.directory.display(),
+                    failure
+                ),
+            };
+        }
+        if !self.recovered.is_empty() {
+            summary += &format!(" Rebuilt {} from the parity.", self.recovered.join(", "));
+        }
+        if let Some((chosen, other)) = self.adapted {
+            summary += &format!(
+                " Copied with {} buffers, which were faster here than {}.",
+                format_size(chosen as u64),
+                format_size(other as u64)
+            );
+        }
+        summary
+    }
+}
@@ -0,0 +1,470 @@
+// The prompts of the interactive menus and of the commands that ask before going on.
+//
+// With --yes, every question that only asks whether to go ahead (overwriting a file,
+// breaking a stale lock, splitting onto a disk that looks full, joining pieces whose
+// order is in doubt, Proceed?) is answered yes without reading anything, and those
+// that ask how to do something (hash the pieces? add join scripts?) take their
+// default. Lists to choose from are still read, as they are what the menus are made
+// of, as is whether to show the next screen of something paged through, which either
+// answer would be wrong for. So is anything that needs typing in, such as a file name,
+// which --yes can't answer: without a terminal to ask on, that is an error, and in a
+// debug build a panic, as the command should have taken it as an argument.
+
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::terminal::{self, ClearType};
+use crossterm::{cursor, queue};
+use rustyline::completion::FilenameCompleter;
+use rustyline::error::ReadlineError;
+use rustyline::{Completer, Editor, Helper, Highlighter, Hinter, Validator};
+
+use crate::style::{self, Color, paint};
+use crate::{interrupt, outcome};
+use crate::{natural_cmp, normalize_path_input};
+
+// How many unusable answers in a row a prompt accepts before giving up, so a script
+// feeding garbage can't keep the program spinning forever.
+const MAX_ATTEMPTS: usize = 10;
+
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+// Answer the questions that are only confirmations, for --yes.
+pub fn init(yes: bool) {
+    ASSUME_YES.store(yes, Ordering::Relaxed);
+}
+
+pub fn assume_yes() -> bool {
+    ASSUME_YES.load(Ordering::Relaxed)
+}
+
+// Fails when what is to be asked can only be typed in and --yes was given with no
+// terminal to type it on.
+fn needs_answer(prompt: &str) -> io::Result<()> {
+    if !assume_yes() || io::stdin().is_terminal() {
+        return Ok(());
+    }
+    if cfg!(debug_assertions) {
+        panic!("\"{}\" was asked for with --yes and no terminal", prompt);
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("\"{}\" needs an answer, which --yes can't give", prompt),
+    ))
+}
+
+fn stream_closed() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "input stream closed")
+}
+
+fn too_many_attempts() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "too many invalid answers")
+}
+
+// Read one line from stdin. End of input is an error rather than an empty answer;
+// a line that isn't valid UTF-8 comes back as None so callers can ask again.
+fn read_answer() -> io::Result<Option<String>> {
+    io::stdout().flush()?;
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line) {
+        Ok(0) => Err(stream_closed()),
+        Ok(_) => Ok(Some(line)),
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// Let the user choose one of `options` (label -> kind). Uses an arrow-key selector on
+// a terminal and falls back to typing the number of the entry otherwise, so scripts
+// that pipe answers into the program keep working.
+pub fn list_prompt(prompt: &str, options: &BTreeMap<String, &str>) -> io::Result<String> {
+    let entries = options
+        .iter()
+        .map(|(label, kind)| MenuEntry {
+            label: label.clone(),
+            kind,
+            choice: label.clone(),
+        })
+        .collect();
+    menu_prompt(prompt, entries)
+}
+
+// One entry of a menu: what it shows, the kind of entry it is, which colours it and
+// makes Esc pick the "back" one, and what choosing it gives.
+pub struct MenuEntry<'a, T> {
+    pub label: String,
+    pub kind: &'a str,
+    pub choice: T,
+}
+
+// Let the user choose one of `entries`, as `list_prompt` does, and return its choice.
+// Entries are told apart by where they are rather than by their labels, so a
+// directory named like an action is still the directory, and two that read the same
+// are both shown.
+pub fn menu_prompt<T>(prompt: &str, entries: Vec<MenuEntry<T>>) -> io::Result<T> {
+    let mut entries = sort_listed(entries);
+    let shown: Vec<(&str, &str)> = entries
+        .iter()
+        .map(|entry| (entry.label.as_str(), entry.kind))
+        .collect();
+    let chosen = if io::stdin().is_terminal()
+        && io::stdout().is_terminal()
+        && let Some(chosen) = arrow_prompt(prompt, &shown)
+    {
+        chosen
+    } else {
+        numeric_prompt(prompt, &shown)?
+    };
+    Ok(entries.swap_remove(chosen).choice)
+}
+
+// The directories, files and chunks of a menu in natural order, labels that tie
+// keeping theirs, among the places they had; actions stay where they were put.
+fn sort_listed<T>(entries: Vec<MenuEntry<T>>) -> Vec<MenuEntry<T>> {
+    let listed = |kind: &str| matches!(kind, "directory" | "file" | "chunk");
+    let mut slots: Vec<Option<MenuEntry<T>>> = entries.into_iter().map(Some).collect();
+    let places: Vec<usize> = (0..slots.len())
+        .filter(|&i| slots[i].as_ref().is_some_and(|entry| listed(entry.kind)))
+        .collect();
+    let mut sorted: Vec<MenuEntry<T>> = places.iter().filter_map(|&i| slots[i].take()).collect();
+    sorted.sort_by(|a, b| natural_cmp(&a.label, &b.label));
+    for (&i, entry) in places.iter().zip(sorted) {
+        slots[i] = Some(entry);
+    }
+    slots.into_iter().flatten().collect()
+}
+
+fn styled(label: &str, kind: &str) -> String {
+    let color = match kind {
+        "chunk" => Some(Color::Green),
+        "action" => Some(Color::Blue),
+        "back" => Some(Color::Yellow),
+        "exit" => Some(Color::Red),
+        _ => None,
+    };
+    match color {
+        Some(color) => paint(label, color),
+        None => label.to_string(),
+    }
+}
+
+fn numeric_prompt(prompt: &str, entries: &[(&str, &str)]) -> io::Result<usize> {
+    for _ in 0..MAX_ATTEMPTS {
+        println!("{}", prompt);
+        for (i, (option, option_type)) in entries.iter().enumerate() {
+            println!("{}", styled(&format!("{}. {}", i + 1, option), option_type));
+        }
+        print!("Enter the number of your choice: ");
+        let response = read_answer()?.unwrap_or_default();
+        if let Ok(index) = response.trim().parse::<usize>()
+            && index > 0
+            && index <= entries.len()
+        {
+            return Ok(index - 1);
+        }
+        println!("Invalid choice. Please select a valid number from the list.");
+    }
+    Err(too_many_attempts())
+}
+
+enum Selection {
+    Chosen(usize),
+    Interrupted,
+}
+
+// Returns None when the terminal can't be put into raw mode, in which case the caller
+// falls back to the numeric prompt.
+fn arrow_prompt(prompt: &str, entries: &[(&str, &str)]) -> Option<usize> {
+    terminal::enable_raw_mode().ok()?;
+    let result = run_selector(prompt, entries);
+    let _ = terminal::disable_raw_mode();
+    println!();
+
+    match result {
+        Ok(Selection::Chosen(index)) => Some(index),
+        Ok(Selection::Interrupted) => outcome::exit_with(interrupt::EXIT_CODE),
+        Err(_) => None,
+    }
+}
+
+fn run_selector(prompt: &str, entries: &[(&str, &str)]) -> io::Result<Selection> {
+    // Esc picks the menu's "back" entry when it has one
+    let back = entries.iter().position(|(_, kind)| *kind == "back");
+    let mut stdout = io::stdout();
+    let mut selected = 0;
+    let mut top = 0;
+    let mut drawn = 0;
+
+    loop {
+        // Some terminals (and bare ptys) report a zero size
+        let rows = match terminal::size() {
+            Ok((_, rows)) if rows > 0 => rows,
+            _ => 24,
+        };
+        let height = (rows as usize).saturating_sub(3).max(1).min(entries.len());
+        if selected < top {
+            top = selected;
+        } else if selected >= top + height {
+            top = selected + 1 - height;
+        }
+
+        if drawn > 0 {
+            queue!(stdout, cursor::MoveToPreviousLine(drawn))?;
+        }
+        queue!(stdout, terminal::Clear(ClearType::FromCursorDown))?;
+        drawn = 0;
+        if !prompt.is_empty() {
+            queue!(stdout, Print(prompt), Print("\r\n"))?;
+            drawn += 1;
+        }
+        for (i, (label, kind)) in entries.iter().enumerate().skip(top).take(height) {
+            let line = if i == selected {
+                style::inverse(&styled(&format!("> {}", label), kind))
+            } else {
+                styled(&format!("  {}", label), kind)
+            };
+            queue!(stdout, Print(line), Print("\r\n"))?;
+            drawn += 1;
+        }
+        queue!(
+            stdout,
+            Print("↑/↓ or j/k to move, Enter to select, Esc to go back")
+        )?;
+        stdout.flush()?;
+
+        let Event::Key(KeyEvent {
+            code,
+            modifiers,
+            kind: KeyEventKind::Press,
+            ..
+        }) = event::read()?
+        else {
+            continue;
+        };
+        match code {
+            KeyCode::Up | KeyCode::Char('k') => selected = selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => selected = (selected + 1).min(entries.len() - 1),
+            KeyCode::PageUp => selected = selected.saturating_sub(height),
+            KeyCode::PageDown => selected = (selected + height).min(entries.len() - 1),
+            KeyCode::Home => selected = 0,
+            KeyCode::End => selected = entries.len() - 1,
+            KeyCode::Enter => return Ok(Selection::Chosen(selected)),
+            KeyCode::Esc => {
+                if let Some(back) = back {
+                    return Ok(Selection::Chosen(back));
+                }
+            }
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(Selection::Interrupted);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Helper, Completer, Highlighter, Hinter, Validator)]
+struct PathHelper {
+    #[rustyline(Completer)]
+    completer: FilenameCompleter,
+}
+
+// Read a line of free-form input. An empty answer selects `default` when one is given.
+pub fn text_prompt(prompt: &str, default: Option<&str>) -> io::Result<String> {
+    needs_answer(prompt)?;
+    for _ in 0..MAX_ATTEMPTS {
+        match default {
+            Some(default) => print!("{} [{}]: ", prompt, default),
+            None => print!("{}: ", prompt),
+        }
+        let Some(line) = read_answer()? else {
+            println!("Input is not valid UTF-8.");
+            continue;
+        };
+        let answer = line.trim();
+        return Ok(match default {
+            Some(default) if answer.is_empty() => default.to_string(),
+            _ => answer.to_string(),
+        });
+    }
+    Err(too_many_attempts())
+}
+
+// Read a passphrase without showing it. Off a terminal it is read as a line like any
+// other answer, for scripts that pipe it in; an empty one is asked for again.
+pub fn passphrase_prompt(prompt: &str) -> io::Result<String> {
+    needs_answer(prompt)?;
+    for _ in 0..MAX_ATTEMPTS {
+        print!("{}: ", prompt);
+        let line = match io::stdin().is_terminal() {
+            true => Some(read_hidden()?),
+            false => read_answer()?,
+        };
+        let Some(line) = line else {
+            println!("Input is not valid UTF-8.");
+            continue;
+        };
+        let passphrase = line.trim_end_matches(['\r', '\n']);
+        if passphrase.is_empty() {
+            println!("The passphrase can't be empty.");
+            continue;
+        }
+        return Ok(passphrase.to_string());
+    }
+    Err(too_many_attempts())
+}
+
+// A new passphrase, typed twice, as a slip nobody can see would leave what it
+// encrypts unreadable.
+pub fn new_passphrase(prompt: &str) -> io::Result<String> {
+    for _ in 0..MAX_ATTEMPTS {
+        let passphrase = passphrase_prompt(prompt)?;
+        if passphrase_prompt("Again, to confirm")? == passphrase {
+            return Ok(passphrase);
+        }
+        println!("The passphrases don't match.");
+    }
+    Err(too_many_attempts())
+}
+
+// A line typed with the terminal in raw mode, so that nothing is echoed.
+fn read_hidden() -> io::Result<String> {
+    io::stdout().flush()?;
+    terminal::enable_raw_mode()?;
+    let result = hidden_line();
+    let _ = terminal::disable_raw_mode();
+    println!();
+    match result? {
+        Some(line) => Ok(line),
+        None => outcome::exit_with(interrupt::EXIT_CODE),
+    }
+}
+
+// None for Ctrl+C, which raw mode turns into a key like any other.
+fn hidden_line() -> io::Result<Option<String>> {
+    let mut line = String::new();
+    loop {
+        let Event::Key(KeyEvent {
+            code,
+            modifiers,
+            kind: KeyEventKind::Press,
+            ..
+        }) = event::read()?
+        else {
+            continue;
+        };
+        let control = modifiers.contains(KeyModifiers::CONTROL);
+        match code {
+            KeyCode::Enter => return Ok(Some(line)),
+            KeyCode::Backspace => {
+                line.pop();
+            }
+            KeyCode::Char('c') if control => return Ok(None),
+            KeyCode::Char('d') if control && line.is_empty() => return Err(stream_closed()),
+            KeyCode::Char(c) if !control => line.push(c),
+            _ => {}
+        }
+    }
+}
+
+// Read a path from the user with Tab-completion of file names. Falls back to a plain
+// line read when the line editor can't be set up (e.g. unsupported terminal). An
+// empty answer selects `default` when one is given.
+pub fn path_prompt(prompt: &str, default: Option<&Path>) -> io::Result<PathBuf> {
+    needs_answer(prompt)?;
+    let prompt = match default {
+        Some(default) => format!("{} [{}]: ", prompt, default.display()),
+        None => format!("{}: ", prompt),
+    };
+    let line = match Editor::new() {
+        Ok(mut editor) => {
+            editor.set_helper(Some(PathHelper {
+                completer: FilenameCompleter::new(),
+            }));
+            match editor.readline(&prompt) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => outcome::exit_with(interrupt::EXIT_CODE),
+                Err(ReadlineError::Eof) => return Err(stream_closed()),
+                Err(ReadlineError::Io(e)) => return Err(e),
+                Err(e) => return Err(io::Error::other(e)),
+            }
+        }
+        Err(_) => text_prompt(prompt.trim_end().trim_end_matches(':'), None)?,
+    };
+    Ok(match default {
+        Some(default) if line.trim().is_empty() => default.to_path_buf(),
+        _ => normalize_path_input(&line),
+    })
+}
+
+// Ask whether to go ahead; an empty answer picks `default`, and --yes answers yes.
+pub fn confirm(question: &str, default: bool) -> io::Result<bool> {
+    if assume_yes() {
+        return Ok(assumed(question, default, true));
+    }
+    yes_or_no(question, default)
+}
+
+// Ask a yes/no question about how to do something rather than whether to; an empty
+// answer, or --yes, picks `default`.
+pub fn preference(question: &str, default: bool) -> io::Result<bool> {
+    if assume_yes() {
+        return Ok(assumed(question, default, default));
+    }
+    yes_or_no(question, default)
+}
+
+// Show `question` answered with `answer` by --yes.
+fn assumed(question: &str, default: bool, answer: bool) -> bool {
+    let hint = if default { "Y/n" } else { "y/N" };
+    let shown = if answer { "y" } else { "n" };
+    println!("{} [{}] {} (--yes)", question, hint, shown);
+    answer
+}
+
+// Ask a yes/no question that --yes leaves alone; an empty answer picks `default`.
+pub fn yes_or_no(question: &str, default: bool) -> io::Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    for _ in 0..MAX_ATTEMPTS {
+        print!("{} [{}] ", question, hint);
+        let answer = read_answer()?.unwrap_or_default();
+        match answer.trim().to_ascii_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("Please answer y or n."),
+        }
+    }
+    Err(too_many_attempts())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_listed_entries_are_sorted() {
+        let entries = ["chunk10", "Reconstruct", "chunk9", "Back", "Another action"]
+            .into_iter()
+            .map(|label| MenuEntry {
+                label: label.to_string(),
+                kind: if label.starts_with("chunk") {
+                    "directory"
+                } else {
+                    "action"
+                },
+                choice: label,
+            });
+        let sorted: Vec<&str> = sort_listed(entries.collect())
+            .into_iter()
+            .map(|entry| entry.choice)
+            .collect();
+        assert_eq!(
+            sorted,
+            ["chunk9", "Reconstruct", "chunk10", "Back", "Another action"]
+        );
+    }
+}
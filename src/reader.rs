@@ -0,0 +1,361 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use crate::chunkset::ChunkSet;
+use crate::error::{Result, SplitterError};
+use crate::store::{ChunkStore, LocalDirStore};
+
+// How many chunk handles stay open between reads.
+const OPEN_HANDLES: usize = 4;
+
+// Reads a chunk set as if it were the original file, without reconstructing it. The
+// layout is the set's `ChunkSet`. Chunks are opened when a read first reaches them, and the few
+// used most recently are kept open. A chunk that turns out shorter than the layout
+// says fails the read rather than shifting everything after it.
+pub struct ChunkedReader<S: ChunkStore> {
+    store: S,
+    // (chunk index, offset in the original file, length) in order
+    layout: Vec<(usize, u64, u64)>,
+    len: u64,
+    position: u64,
+    // (chunk index, handle, its position within the chunk), most recently used last
+    open: Vec<(usize, S::Reader, u64)>,
+}
+
+impl ChunkedReader<LocalDirStore> {
+    pub fn open(
+        directory: impl Into<PathBuf>,
+        accept_modified: bool,
+    ) -> Result<ChunkedReader<LocalDirStore>> {
+        ChunkedReader::new(LocalDirStore::open(directory, accept_modified)?)
+    }
+}
+
+impl<S: ChunkStore> ChunkedReader<S>
+where
+    S::Reader: Seek,
+{
+    pub fn new(store: S) -> Result<ChunkedReader<S>> {
+        let set = ChunkSet::from_store(&store)?;
+        // Empty chunks hold nothing to read and would only get in the way of the lookup
+        let layout = set
+            .iter()
+            .filter(|chunk| chunk.len > 0)
+            .map(|chunk| (chunk.index, chunk.offset, chunk.len))
+            .collect();
+        Ok(ChunkedReader {
+            store,
+            layout,
+            len: set.total_size(),
+            position: 0,
+            open: Vec::new(),
+        })
+    }
+
+    // Length of the original file.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn into_store(self) -> S {
+        self.store
+    }
+
+    // The handle for chunk `index`, positioned at `offset` within it.
+    fn handle(&mut self, index: usize, offset: u64) -> io::Result<&mut (usize, S::Reader, u64)> {
+        match self.open.iter().position(|(open, ..)| *open == index) {
+            Some(found) => {
+                let entry = self.open.remove(found);
+                self.open.push(entry);
+            }
+            None => {
+                if self.open.len() == OPEN_HANDLES {
+                    self.open.remove(0);
+                }
+                let reader = self.store.open_chunk(index)?;
+                self.open.push((index, reader, 0));
+            }
+        }
+        let entry = self.open.last_mut().unwrap();
+        if entry.2 != offset {
+            entry.1.seek(SeekFrom::Start(offset))?;
+            entry.2 = offset;
+        }
+        Ok(entry)
+    }
+}
+
+impl<S: ChunkStore> Read for ChunkedReader<S>
+where
+    S::Reader: Seek,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.len {
+            return Ok(0);
+        }
+        // The chunk holding `position`: the last one starting at or before it
+        let slot = self
+            .layout
+            .partition_point(|&(_, offset, _)| offset <= self.position)
+            - 1;
+        let (index, offset, size) = self.layout[slot];
+        let within = self.position - offset;
+        let wanted = (size - within).min(buf.len() as u64) as usize;
+        let path = self.store.chunk_path(index);
+        let (_, reader, at) = self.handle(index, within)?;
+        let read = reader.read(&mut buf[..wanted])?;
+        if read == 0 {
+            return Err(SplitterError::ChangedSize { path }.into());
+        }
+        *at += read as u64;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+// Seeking past the end is allowed; reads there return nothing, as with a file.
+impl<S: ChunkStore> Seek for ChunkedReader<S>
+where
+    S::Reader: Seek,
+{
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        let position = match to {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        let Some(position) = position else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative or overflowing position",
+            ));
+        };
+        self.position = position;
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::manifest::Manifest;
+    use crate::{CancelToken, ReconstructOptions, SplitOptions, reconstruct, split_file};
+
+    const GIB: u64 = 1 << 30;
+
+    // The byte at `offset` of the virtual file, which depends on the high bits too, so
+    // an offset cut to 32 bits reads the wrong byte.
+    fn byte_at(offset: u64) -> u8 {
+        (offset.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 56) as u8
+    }
+
+    // Chunks as large as anyone likes, none of them stored: each reads as `byte_at` of
+    // where it is in the file.
+    struct Virtual {
+        sizes: Vec<u64>,
+    }
+
+    struct Pattern {
+        start: u64,
+        len: u64,
+        position: u64,
+    }
+
+    impl Read for Pattern {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let left = self.len.saturating_sub(self.position);
+            let len = (buf.len() as u64).min(left) as usize;
+            for (i, byte) in buf[..len].iter_mut().enumerate() {
+                *byte = byte_at(self.start + self.position + i as u64);
+            }
+            self.position += len as u64;
+            Ok(len)
+        }
+    }
+
+    impl Seek for Pattern {
+        fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+            let SeekFrom::Start(position) = to else {
+                unreachable!("the reader only seeks from the start of a chunk");
+            };
+            self.position = position;
+            Ok(position)
+        }
+    }
+
+    impl ChunkStore for Virtual {
+        type Writer = io::Sink;
+        type Reader = Pattern;
+
+        fn create_chunk(&mut self, _: usize) -> Result<io::Sink> {
+            unreachable!()
+        }
+
+        fn open_chunk(&self, index: usize) -> Result<Pattern> {
+            Ok(Pattern {
+                start: self.sizes[..index].iter().sum(),
+                len: self.sizes[index],
+                position: 0,
+            })
+        }
+
+        fn chunk_len(&self, index: usize) -> Result<u64> {
+            Ok(self.sizes[index])
+        }
+
+        fn remove_chunk(&mut self, _: usize) -> Result<()> {
+            unreachable!()
+        }
+
+        fn list_chunks(&self) -> Result<Vec<usize>> {
+            Ok((0..self.sizes.len()).collect())
+        }
+
+        fn read_info(&self) -> Result<Option<Manifest>> {
+            Ok(None)
+        }
+
+        fn write_info(&mut self, _: &Manifest) -> Result<()> {
+            unreachable!()
+        }
+    }
+
+    fn read_at(reader: &mut ChunkedReader<Virtual>, offset: u64, len: usize) -> Vec<u8> {
+        reader.seek(SeekFrom::Start(offset)).unwrap();
+        let mut buffer = vec![0; len];
+        reader.read_exact(&mut buffer).unwrap();
+        buffer
+    }
+
+    fn expected(offset: u64, len: usize) -> Vec<u8> {
+        (0..len as u64).map(|i| byte_at(offset + i)).collect()
+    }
+
+    #[test]
+    fn reads_past_4_gib_at_the_right_offsets() {
+        let sizes = vec![3 * GIB, 3 * GIB, 1, 0, 2 * GIB + 3];
+        let mut reader = ChunkedReader::new(Virtual { sizes }).unwrap();
+        assert_eq!(reader.len(), 8 * GIB + 4);
+        for offset in [
+            0,
+            // Across the first boundary, then past 4 GiB and 2^32 exactly
+            3 * GIB - 4,
+            4 * GIB - 1,
+            1 << 32,
+            5 * GIB + 12_345,
+            // The 1-byte chunk, the empty one after it, and the last
+            6 * GIB - 2,
+            6 * GIB,
+            8 * GIB - 5,
+        ] {
+            assert_eq!(
+                read_at(&mut reader, offset, 8),
+                expected(offset, 8),
+                "{}",
+                offset
+            );
+        }
+    }
+
+    #[test]
+    fn seeks_from_the_end_of_a_large_file() {
+        let mut reader = ChunkedReader::new(Virtual {
+            sizes: vec![5 * GIB, 5 * GIB],
+        })
+        .unwrap();
+        assert_eq!(reader.seek(SeekFrom::End(-3)).unwrap(), 10 * GIB - 3);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, expected(10 * GIB - 3, 3));
+        assert_eq!(
+            reader.seek(SeekFrom::Current(-(6 * GIB as i64))).unwrap(),
+            4 * GIB
+        );
+        assert!(reader.seek(SeekFrom::Current(-(5 * GIB as i64))).is_err());
+    }
+
+    // Where `offset` lands in a file of `len` bytes, a little past the end included.
+    fn within(offset: u64, len: u64) -> u64 {
+        offset % (len + 16)
+    }
+
+    proptest! {
+        // Chunks of up to 3 GiB, empty ones among them, read anywhere for any length,
+        // past the end too: reading on to `len` gives the file's bytes there, and a single
+        // read at least one of them unless there are none.
+        #[test]
+        fn reads_anywhere_give_the_bytes_there(
+            sizes in vec(prop_oneof![Just(0), 1..=3 * GIB], 1..6),
+            offset in any::<u64>(),
+            len in 0usize..4096,
+        ) {
+            let mut reader = ChunkedReader::new(Virtual { sizes }).unwrap();
+            let total = reader.len();
+            let offset = within(offset, total);
+            let there = total.saturating_sub(offset).min(len as u64) as usize;
+
+            reader.seek(SeekFrom::Start(offset)).unwrap();
+            let mut once = vec![0; len];
+            let read = reader.read(&mut once).unwrap();
+            prop_assert_eq!(read == 0, there == 0);
+            prop_assert_eq!(&once[..read], &expected(offset, read)[..]);
+
+            reader.seek(SeekFrom::Start(offset)).unwrap();
+            let mut all = Vec::new();
+            (&mut reader).take(len as u64).read_to_end(&mut all).unwrap();
+            prop_assert_eq!(all, expected(offset, there));
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        // A set split for real, read through the reader at offsets one after another, with
+        // the reconstructed file read at the same ones.
+        #[test]
+        fn a_split_set_reads_as_its_reconstruction(
+            len in 0usize..20_000,
+            chunk_size in 1u64..5000,
+            reads in vec((any::<u64>(), 0usize..6000), 1..8),
+        ) {
+            let dir = tempfile::tempdir().unwrap();
+            let input = dir.path().join("input.bin");
+            let chunks = dir.path().join("chunks");
+            let data: Vec<u8> = (0..len as u64).map(byte_at).collect();
+            fs::write(&input, data).unwrap();
+            let options = SplitOptions::builder(&input, &chunks)
+                .chunk_size(chunk_size)
+                .min_chunk_size(0)
+                .build()
+                .unwrap();
+            split_file(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+            let options = ReconstructOptions {
+                output: Some(dir.path().join("joined.bin").display().to_string()),
+                ..ReconstructOptions::new(&chunks)
+            };
+            let joined = reconstruct(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+
+            let mut reader = ChunkedReader::open(&chunks, false).unwrap();
+            let mut file = File::open(joined.output).unwrap();
+            prop_assert_eq!(reader.len(), file.metadata().unwrap().len());
+            for (offset, len) in reads {
+                let offset = within(offset, reader.len());
+                let (mut got, mut want) = (Vec::new(), Vec::new());
+                reader.seek(SeekFrom::Start(offset)).unwrap();
+                (&mut reader).take(len as u64).read_to_end(&mut got).unwrap();
+                file.seek(SeekFrom::Start(offset)).unwrap();
+                (&mut file).take(len as u64).read_to_end(&mut want).unwrap();
+                prop_assert_eq!(got, want, "{} bytes at {}", len, offset);
+            }
+        }
+    }
+}
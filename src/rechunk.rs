@@ -0,0 +1,359 @@
+// Cutting a chunk set again into chunks of another size, for a different transfer limit
+// once the original file is gone: the chunks of the source are read in order, as
+// `ChunkedReader` reads them, and split afresh into the destination, so the whole file
+// is never on disk. The source is verified first, so a damaged one is caught before
+// anything is written rather than half way through. The new manifest keeps what the old
+// one says about the original, its name, range, owner and extended attributes, with the
+// chunk size, hashes and compression of the new chunks. `plan_rechunk` goes as far as
+// the verification and a look at the destination, and lists what would be written.
+
+use std::io::{self, BufReader, Seek};
+use std::path::{Path, PathBuf};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::archive::{ArchiveStore, is_archive};
+use crate::cancel::CancelToken;
+use crate::changes::FileChange;
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::{ProgressEvent, Report};
+use crate::lock::{self, DirLock};
+use crate::manifest::{Compression, HashAlgorithm, MANIFEST_NAME, MANIFEST_VERSION, Manifest};
+use crate::pipeline::DEFAULT_BUFFER_SIZE;
+use crate::reader::ChunkedReader;
+use crate::split::{
+    DEFAULT_MIN_CHUNK_SIZE, SplitReport, check_chunk_size, check_destination, check_empty,
+    chunk_count, prepare_destination, remove_partial,
+};
+use crate::store::{ChunkStore, LocalDirStore, split_into, stream_manifest};
+use crate::{VerifyReport, default_output_name, is_s3_url, is_sftp_url, verify, verify_locked};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RechunkOptions {
+    // A local directory of chunks, or a zip or tar of them
+    pub source: PathBuf,
+    // Created if it doesn't exist; must be empty if it does
+    pub destination: PathBuf,
+    pub chunk_size: u64,
+    // The smallest `chunk_size` taken, as for a split
+    #[serde(default = "default_min_chunk_size")]
+    pub min_chunk_size: u64,
+    // For the new chunks; the source's when not given, none if it had none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<HashAlgorithm>,
+    // How the new chunks are stored; as the source's are when not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    // The codec's default when not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_level: Option<u32>,
+    // Where the new chunks are written until complete, see `scratch`; beside them when
+    // not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temp_dir: Option<PathBuf>,
+    // Go on with a source whose info.json was changed after it was sealed
+    #[serde(default)]
+    pub accept_modified: bool,
+}
+
+fn default_min_chunk_size() -> u64 {
+    DEFAULT_MIN_CHUNK_SIZE
+}
+
+impl RechunkOptions {
+    pub fn new(
+        source: impl Into<PathBuf>,
+        destination: impl Into<PathBuf>,
+        chunk_size: u64,
+    ) -> RechunkOptions {
+        RechunkOptions {
+            source: source.into(),
+            destination: destination.into(),
+            chunk_size,
+            min_chunk_size: DEFAULT_MIN_CHUNK_SIZE,
+            hash: None,
+            compression: None,
+            compression_level: None,
+            temp_dir: None,
+            accept_modified: false,
+        }
+    }
+}
+
+// What `rechunk` would do, from `plan_rechunk`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RechunkPlan {
+    pub destination: PathBuf,
+    pub total_size: u64,
+    pub chunks: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<HashAlgorithm>,
+    pub compression: Compression,
+    // The new chunks and info.json
+    pub changes: Vec<FileChange>,
+}
+
+// Split the set in `options.source` again into `options.destination`. `progress` hears
+// about the new chunks; the source's verification beforehand isn't reported. A
+// destination left unfinished by an error or cancellation is removed as after a split.
+pub fn rechunk(
+    options: &RechunkOptions,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<SplitReport> {
+    let (source, destination) = (options.source.as_path(), options.destination.as_path());
+    let archive = is_archive(source);
+    let _source_lock = check(options, cancel)?;
+
+    let (created, _lock) = prepare_destination(destination, "rechunk", lock::DEFAULT_MAX_AGE)?;
+    let result = match archive {
+        true => ArchiveStore::open(source, options.accept_modified)
+            .and_then(|store| cut(store, options, progress, cancel)),
+        false => LocalDirStore::open(source, options.accept_modified)
+            .and_then(|store| cut(store, options, progress, cancel)),
+    };
+    let report = match result {
+        Ok(report) => report,
+        Err(e) => {
+            info!("rechunk failed; removing the chunks written so far");
+            remove_partial(destination, created, false, None);
+            return Err(e);
+        }
+    };
+    info!(
+        "rechunked {} into {} chunks in {}",
+        source.display(),
+        report.chunks.len(),
+        destination.display()
+    );
+    progress(ProgressEvent::Completed {
+        report: Report::Split(report.clone()),
+    });
+    Ok(report)
+}
+
+// What `rechunk` would write, once the source verifies and the destination is found
+// free, without writing anything.
+pub fn plan_rechunk(options: &RechunkOptions, cancel: &CancelToken) -> Result<RechunkPlan> {
+    let (source, destination) = (options.source.as_path(), options.destination.as_path());
+    let _source_lock = check(options, cancel)?;
+    check_destination(destination)?;
+    if destination.exists() {
+        check_empty(destination)?;
+    }
+    let (old, total) = match is_archive(source) {
+        true => layout(ArchiveStore::open(source, options.accept_modified)?)?,
+        false => layout(LocalDirStore::open(source, options.accept_modified)?)?,
+    };
+    let (hash, compression, level) = settings(old.as_ref(), options)?;
+    let count = chunk_count(total, options.chunk_size)?;
+    let store = LocalDirStore::new(destination)
+        .compressed(compression, level)
+        .counted(count);
+    let mut changes = Vec::with_capacity(count + 1);
+    for index in 0..count {
+        let start = index as u64 * options.chunk_size;
+        let len = options.chunk_size.min(total - start);
+        let path = destination.join(store.new_chunk_name(index));
+        // Compressed, a chunk takes what it comes to
+        changes.push(FileChange::write(
+            path,
+            Some(len).filter(|_| compression.is_none()),
+        ));
+    }
+    changes.push(FileChange::write(destination.join(MANIFEST_NAME), None));
+    Ok(RechunkPlan {
+        destination: destination.to_path_buf(),
+        total_size: total,
+        chunks: count,
+        hash,
+        compression,
+        changes,
+    })
+}
+
+// Check `options` and verify the source, locked unless it is an archive; the lock is
+// returned, to be held for as long as the source is read.
+fn check(options: &RechunkOptions, cancel: &CancelToken) -> Result<Option<DirLock>> {
+    let source = options.source.as_path();
+    check_chunk_size(options.chunk_size, options.min_chunk_size)?;
+    if is_s3_url(source) || is_sftp_url(source) {
+        return Err(SplitterError::InvalidOption {
+            field: "source",
+            reason: "must be a local directory of chunks or an archive of them",
+        });
+    }
+    info!("verifying {} before rechunking it", source.display());
+    let archive = is_archive(source);
+    let lock = match archive {
+        true => None,
+        false => Some(lock::acquire(source, "rechunk")?),
+    };
+    let verified = match archive {
+        true => verify(source, &[], options.accept_modified, &mut |_| {}, cancel)?,
+        false => verify_locked(source, &[], options.accept_modified, &mut |_| {}, cancel)?,
+    };
+    check_source(source, &verified)?;
+    Ok(lock)
+}
+
+// The source's manifest, if it has one, and the size of what it holds.
+fn layout<S: ChunkStore>(store: S) -> Result<(Option<Manifest>, u64)>
+where
+    S::Reader: Seek,
+{
+    let old = store.read_info()?;
+    Ok((old, ChunkedReader::new(store)?.len()))
+}
+
+// The hash, compression and level for the new chunks, from `options` or else the
+// source's manifest `old`, once it is known the source can be rechunked.
+fn settings(
+    old: Option<&Manifest>,
+    options: &RechunkOptions,
+) -> Result<(Option<HashAlgorithm>, Compression, u32)> {
+    if old.is_some_and(|manifest| manifest.span.is_some()) {
+        return Err(SplitterError::InvalidOption {
+            field: "source",
+            reason: "is one volume of a set split across several; reconstruct it first",
+        });
+    }
+    let hash = options
+        .hash
+        .or_else(|| old.and_then(|manifest| manifest.hash));
+    let compression = options
+        .compression
+        .or_else(|| old.map(|manifest| manifest.compression))
+        .unwrap_or(Compression::None);
+    let level = options
+        .compression_level
+        .unwrap_or_else(|| compression.default_level());
+    if !compression.is_none() && !compression.levels().contains(&level) {
+        return Err(SplitterError::InvalidOption {
+            field: "compression_level",
+            reason: "is not one the codec supports",
+        });
+    }
+    Ok((hash, compression.at_level(level), level))
+}
+
+// Fail with what `verify` found wrong with the source, if anything.
+fn check_source(source: &Path, report: &VerifyReport) -> Result<()> {
+    if report.is_ok() {
+        return Ok(());
+    }
+    let health = &report.health;
+    if health.chunks == 0 {
+        return Err(SplitterError::NoChunks {
+            path: source.to_path_buf(),
+        });
+    }
+    if !health.missing.is_empty() {
+        return Err(SplitterError::MissingChunks {
+            indices: health.missing.clone(),
+        });
+    }
+    let mut problems: Vec<String> = report
+        .mismatched
+        .iter()
+        .map(|name| format!("{} is missing or damaged", name))
+        .collect();
+    for (count, what) in [
+        (health.uneven.len(), "chunks are unevenly sized"),
+        (
+            health.unexpected.len(),
+            "files named like chunks aren't in info.json",
+        ),
+        (
+            report.checksum_missing.len(),
+            "files listed in a checksum file are missing",
+        ),
+    ] {
+        if count > 0 {
+            problems.push(format!("{} {}", count, what));
+        }
+    }
+    let message = format!(
+        "does not verify ({}); repair or heal it first",
+        problems.join(", ")
+    );
+    Err(io::Error::new(io::ErrorKind::InvalidData, message)).at(source)
+}
+
+// Read the set in `store` through and split it into the destination.
+fn cut<S: ChunkStore + Send>(
+    store: S,
+    options: &RechunkOptions,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<SplitReport>
+where
+    S::Reader: Seek + Send,
+{
+    let source = options.source.as_path();
+    let old = store.read_info()?;
+    let (hash, compression, level) = settings(old.as_ref(), options)?;
+
+    let reader = ChunkedReader::new(store)?;
+    let total = reader.len();
+    let destination = options.destination.as_path();
+    let mut output = LocalDirStore::new(destination)
+        .staged(options.temp_dir.clone())
+        .compressed(compression, level)
+        .counted(chunk_count(total, options.chunk_size)?);
+    let mut input = BufReader::with_capacity(DEFAULT_BUFFER_SIZE, reader);
+    let chunks = split_into(
+        &mut input,
+        source,
+        &mut output,
+        options.chunk_size,
+        hash,
+        progress,
+        cancel,
+    )?;
+
+    let manifest = match old {
+        Some(old) => Manifest {
+            version: MANIFEST_VERSION,
+            chunk_size: Some(options.chunk_size),
+            hash,
+            compression,
+            random_names: false,
+            compat: None,
+            shards: None,
+            created: None,
+            parity: None,
+            span: None,
+            chunks,
+            ..old
+        },
+        None => stream_manifest(
+            default_output_name(source, options.accept_modified)?,
+            options.chunk_size,
+            hash,
+            compression,
+            chunks,
+        ),
+    };
+    output.write_info(&manifest)?;
+    let mut stored_size = 0;
+    for entry in &manifest.chunks {
+        let path = destination.join(&entry.name);
+        stored_size += std::fs::metadata(&path).at(&path)?.len();
+    }
+    Ok(SplitReport {
+        destination: destination.to_path_buf(),
+        total_size: total,
+        compression,
+        stored_size,
+        deduplicated_size: 0,
+        parity: None,
+        mirror: None,
+        par2: None,
+        span: None,
+        input_changed: false,
+        adapted_buffers: None,
+        chunks: manifest.chunks,
+    })
+}
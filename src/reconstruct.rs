@@ -0,0 +1,1776 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "async")]
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::archive::{ArchiveStore, is_archive};
+use crate::cancel::CancelToken;
+use crate::crypt::ChunkKey;
+#[cfg(feature = "async")]
+use crate::engine::{Engine, SharedStore};
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::{Counting, ProgressEvent, Report};
+use crate::heal::same_split;
+use crate::hook::{ChunkHook, Hooks, Phase};
+use crate::lock::{self, LOCK_NAME};
+use crate::manifest::{ChunkEntry, Compression, MANIFEST_NAME, Manifest, is_plain_name};
+#[cfg(feature = "mmap")]
+use crate::mmap;
+use crate::modes::{self, MAX_MODE};
+use crate::owner;
+use crate::parity;
+use crate::pipeline::{DEFAULT_BUFFER_SIZE, Io, copy_overlapped};
+use crate::retry::{RetryPolicy, Retrying, retried};
+use crate::s3::{S3Options, S3Store, is_s3_url};
+use crate::scratch::{self, Staged};
+#[cfg(feature = "sftp")]
+use crate::sftp::{SftpOptions, SftpStore};
+use crate::split::{default_buffer_size, default_lock_max_age};
+use crate::store::{ChunkReader, ChunkStore, is_shard_dir, reconstruct_from_with, temp_name};
+use crate::sums::sums_name;
+use crate::symlinks::SymlinkPolicy;
+use crate::transfer::TRANSFER_STATE_NAME;
+use crate::unicode::{self, Normalization};
+use crate::xattrs;
+use crate::{
+    TRASH_DIR, case_insensitive, chunk_index, default_output_name, fastcopy, first_time,
+    is_set_file, is_sftp_url, is_stream, list_directory, missing_below, same_name,
+};
+
+// What to reconstruct and how. `output` is a file name inside `directory`, by default
+// the name recorded when the file was split. `directory` may also be a zip or tar of
+// the chunks, with the output going next to it, or an `s3://bucket/prefix` they were
+// uploaded to or an `sftp://host/path` they were written to, with the output going
+// into the current directory. Where the output would go can't be written to, as on
+// read-only media, it goes in `fallback_dir` instead.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReconstructOptions {
+    pub directory: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    pub threads: usize,
+    #[serde(default)]
+    pub mmap: bool,
+    #[serde(default)]
+    pub sparse: bool,
+    // The service an `s3://` directory is on
+    #[serde(default)]
+    pub s3: S3Options,
+    // How an `sftp://` directory is reached
+    #[cfg(feature = "sftp")]
+    #[serde(default)]
+    pub sftp: SftpOptions,
+    // Run for every chunk the directory's `info.json` lists, to fetch it, before any is
+    // read. A local directory only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_chunk_cmd: Option<ChunkHook>,
+    // The other directories of a set split across several, to find chunks in that
+    // `directory` doesn't have; see `Span`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes: Vec<PathBuf>,
+    // Give the output back to the owner info.json records by number, even where this
+    // system has the names for others; see `owner`
+    #[serde(default)]
+    pub numeric_owner: bool,
+    // The form the name the set recorded is put in, when no `output` is given
+    #[serde(default)]
+    pub normalize: Normalization,
+    // Permissions for the reconstructed file, in place of what the umask leaves or an
+    // existing file had; set whether or not info.json records anything else about it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_mode: Option<u32>,
+    // Where the output goes instead when it would go in a directory that can't be
+    // written to, as with chunks on a mounted ISO or a write-protected SD card; the
+    // current directory when not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback_dir: Option<PathBuf>,
+    // How opening and reading a chunk is tried again when it fails in a way that can
+    // pass, as on a flaky network mount; not at all by default
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    // The size of the buffers data is copied with, or with `adaptive_buffers` the
+    // largest tried; see `pipeline`
+    #[serde(default = "default_buffer_size")]
+    pub buffer_size: usize,
+    #[serde(default)]
+    pub adaptive_buffers: bool,
+    // A cap on what the copy buffers take across all threads, which `threads` is
+    // expected to have been fitted to; see `pipeline::fit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_memory: Option<u64>,
+    // Keep what is copied out of the page cache; see `cache`
+    #[serde(default)]
+    pub direct_io: bool,
+    // How old a lock left on the directory has to be for the error to call it stale
+    #[serde(default = "default_lock_max_age")]
+    pub lock_max_age: Duration,
+    // Whether links among the chunks are followed when looking for them
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+    // Where the output is written until complete, see `scratch`; beside it when not
+    // given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temp_dir: Option<PathBuf>,
+    // Go on with an info.json changed after it was sealed, with a warning, rather than
+    // fail with `MetadataModified`
+    #[serde(default)]
+    pub accept_modified: bool,
+    // The key the chunks of an encrypted set open with, derived from its passphrase and
+    // what info.json records; see `crypt`
+    #[serde(skip)]
+    pub key: Option<ChunkKey>,
+}
+
+impl ReconstructOptions {
+    // Single-threaded reconstruction under the recorded name, with the space reserved.
+    pub fn new(directory: impl Into<PathBuf>) -> ReconstructOptions {
+        ReconstructOptions {
+            directory: directory.into(),
+            output: None,
+            threads: 1,
+            mmap: false,
+            sparse: false,
+            s3: S3Options::default(),
+            #[cfg(feature = "sftp")]
+            sftp: SftpOptions::default(),
+            pre_chunk_cmd: None,
+            volumes: Vec::new(),
+            numeric_owner: false,
+            normalize: Normalization::Keep,
+            file_mode: None,
+            fallback_dir: None,
+            retry: RetryPolicy::default(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            adaptive_buffers: false,
+            max_memory: None,
+            direct_io: false,
+            lock_max_age: lock::DEFAULT_MAX_AGE,
+            symlinks: SymlinkPolicy::Resolve,
+            temp_dir: None,
+            accept_modified: false,
+            key: None,
+        }
+    }
+
+    // How the reconstruction copies, with a trial of its own when its buffers adapt.
+    pub(crate) fn io(&self) -> Io {
+        Io::new(self.buffer_size, self.adaptive_buffers, self.direct_io)
+    }
+}
+
+// How `assemble` copies the chunks into the output: the parts of `ReconstructOptions`
+// that are about that, which callers without any make up themselves.
+#[derive(Debug)]
+pub(crate) struct Copying {
+    pub threads: usize,
+    pub mmap: bool,
+    pub sparse: bool,
+    pub retry: RetryPolicy,
+    pub io: Io,
+    pub temp_dir: Option<PathBuf>,
+    pub accept_modified: bool,
+    pub key: Option<ChunkKey>,
+}
+
+impl Copying {
+    // On `threads` threads with buffered I/O, the space reserved and nothing retried.
+    pub(crate) fn new(threads: usize) -> Copying {
+        Copying {
+            threads,
+            mmap: false,
+            sparse: false,
+            retry: RetryPolicy::default(),
+            io: Io::default(),
+            temp_dir: None,
+            accept_modified: false,
+            key: None,
+        }
+    }
+
+    fn of(options: &ReconstructOptions) -> Copying {
+        Copying {
+            threads: options.threads,
+            mmap: options.mmap,
+            sparse: options.sparse,
+            retry: options.retry.clone(),
+            io: options.io(),
+            temp_dir: options.temp_dir.clone(),
+            accept_modified: options.accept_modified,
+            key: options.key.clone(),
+        }
+    }
+}
+
+// What a reconstruction is going to do; see `plan_reconstruct`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReconstructPlan {
+    pub output: PathBuf,
+    pub chunks: usize,
+    pub total_size: u64,
+    // The chunks are checked against the hashes info.json records: as they are read
+    // out of an archive or a server, or all of them first for a set with parity
+    pub verifies: bool,
+    // Something is at `output` already, which the reconstruction replaces
+    pub overwrites: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReconstructReport {
+    pub output: PathBuf,
+    pub chunks: usize,
+    pub total_size: u64,
+    // Chunks that were missing or damaged and were rebuilt from the parity instead
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recovered: Vec<String>,
+    // With `adaptive_buffers`: the buffer size settled on, and the one it was faster than
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adapted_buffers: Option<(usize, usize)>,
+}
+
+// Concatenate the chunks in `options.directory`, and in `options.volumes` for a set
+// split across several directories, once `pre_chunk_cmd` has run for each. See
+// `reconstruct_chunks`, and for sets with parity `reconstruct_with_parity`, or in an
+// archive, S3 or over SFTP `reconstruct_store`.
+pub fn reconstruct(
+    options: &ReconstructOptions,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<ReconstructReport> {
+    check_options(options)?;
+    let directory = options.directory.as_path();
+    let _lock = match is_s3_url(directory) || is_sftp_url(directory) || is_archive(directory) {
+        true => None,
+        false => Some(lock::acquire_with_max_age(
+            directory,
+            "reconstruct",
+            options.lock_max_age,
+        )?),
+    };
+    if let Some(hook) = &options.pre_chunk_cmd {
+        fetch_chunks(hook, directory, options.accept_modified, cancel)?;
+    }
+    if is_s3_url(&options.directory) {
+        let store = S3Store::open(
+            &options.directory,
+            &options.s3,
+            options.accept_modified,
+            cancel,
+        )?;
+        let output_path = remote_output(options, store.read_info()?)?;
+        return reconstruct_remote(
+            store,
+            (options.s3.connections, options.s3.retries),
+            options,
+            &output_path,
+            progress,
+            cancel,
+        );
+    }
+    if is_sftp_url(&options.directory) {
+        #[cfg(feature = "sftp")]
+        {
+            let store = SftpStore::open(
+                &options.directory,
+                &options.sftp,
+                options.accept_modified,
+                cancel,
+            )?;
+            let output_path = remote_output(options, store.read_info()?)?;
+            return reconstruct_remote(
+                store,
+                (options.sftp.connections, options.sftp.retries),
+                options,
+                &output_path,
+                progress,
+                cancel,
+            );
+        }
+        #[cfg(not(feature = "sftp"))]
+        return Err(no_sftp());
+    }
+    if is_archive(&options.directory) {
+        let archive = ArchiveStore::open(&options.directory, options.accept_modified)?;
+        let output_path = archive_output(options)?;
+        return reconstruct_store(&archive, options, &output_path, progress, cancel);
+    }
+    let Local {
+        output_path,
+        manifest,
+        chunk_files,
+    } = plan_local(options)?;
+    if let Some(manifest) = manifest
+        .as_ref()
+        .filter(|manifest| manifest.parity.is_some())
+    {
+        return reconstruct_with_parity(options, manifest, &output_path, progress, cancel);
+    }
+    // Where a file comes up once for every chunk stored in it, which would look like two
+    // chunks of the same number
+    if !manifest.as_ref().is_some_and(Manifest::shares_files) {
+        check_sequence(&chunk_files)?;
+    }
+    let report = assemble(
+        &chunk_files,
+        &output_path,
+        &Copying::of(options),
+        progress,
+        cancel,
+    )?;
+    progress(ProgressEvent::Completed {
+        report: Report::Reconstruct(report.clone()),
+    });
+    restore_metadata(options, manifest.as_ref(), &output_path)?;
+    Ok(report)
+}
+
+// What `reconstruct` is going to do with `options`, worked out the way it works it out
+// but with nothing written, fetched or locked, for a front end to show first. Chunks
+// that a pre-chunk command would fetch count as missing, as it isn't run.
+pub fn plan_reconstruct(
+    options: &ReconstructOptions,
+    cancel: &CancelToken,
+) -> Result<ReconstructPlan> {
+    check_options(options)?;
+    if is_s3_url(&options.directory) {
+        let store = S3Store::open(
+            &options.directory,
+            &options.s3,
+            options.accept_modified,
+            cancel,
+        )?;
+        let output_path = remote_output(options, store.read_info()?)?;
+        return plan_store(&store, options, output_path);
+    }
+    if is_sftp_url(&options.directory) {
+        #[cfg(feature = "sftp")]
+        {
+            let store = SftpStore::open(
+                &options.directory,
+                &options.sftp,
+                options.accept_modified,
+                cancel,
+            )?;
+            let output_path = remote_output(options, store.read_info()?)?;
+            return plan_store(&store, options, output_path);
+        }
+        #[cfg(not(feature = "sftp"))]
+        return Err(no_sftp());
+    }
+    if is_archive(&options.directory) {
+        let archive = ArchiveStore::open(&options.directory, options.accept_modified)?;
+        return plan_store(&archive, options, archive_output(options)?);
+    }
+    let Local {
+        output_path,
+        manifest,
+        chunk_files,
+    } = plan_local(options)?;
+    let (chunks, total_size) = match manifest
+        .as_ref()
+        .filter(|manifest| manifest.parity.is_some())
+    {
+        Some(manifest) => (
+            manifest.chunks.len(),
+            manifest.chunks.iter().map(|entry| entry.size).sum(),
+        ),
+        None => {
+            let key = options.key.as_ref();
+            let sources = sources(&chunk_files, options.accept_modified, key)?;
+            (
+                chunk_files.len(),
+                sources.iter().map(|source| source.size).sum(),
+            )
+        }
+    };
+    Ok(ReconstructPlan {
+        overwrites: overwrites(&output_path),
+        output: output_path,
+        chunks,
+        total_size,
+        verifies: manifest.is_some_and(|manifest| manifest.parity.is_some()),
+    })
+}
+
+// The rest of what `reconstruct` checks before writing anything, for a dry run: for a
+// local set with parity, every chunk against its hash, and whether the parity can
+// rebuild those lost. Returns the names of those it would rebuild; nothing to check for
+// any other set, whose chunks are read as they are copied.
+pub fn check_recovery(options: &ReconstructOptions, cancel: &CancelToken) -> Result<Vec<String>> {
+    let directory = options.directory.as_path();
+    if is_s3_url(directory) || is_sftp_url(directory) || is_archive(directory) {
+        return Ok(Vec::new());
+    }
+    let Some(manifest) = plan_local(options)?
+        .manifest
+        .filter(|manifest| manifest.parity.is_some())
+    else {
+        return Ok(Vec::new());
+    };
+    info!("checking the chunks of {} first", directory.display());
+    let damaged = parity::damaged(directory, &manifest, cancel)?;
+    if !damaged.is_empty() {
+        parity::plan(directory, &manifest, &damaged, cancel)?;
+    }
+    Ok(manifest
+        .indexed()
+        .filter(|(index, _)| damaged.contains(index))
+        .map(|(_, entry)| entry.name.clone())
+        .collect())
+}
+
+fn plan_store<S: ChunkStore>(
+    store: &S,
+    options: &ReconstructOptions,
+    output_path: PathBuf,
+) -> Result<ReconstructPlan> {
+    let manifest = store.read_info().ok().flatten();
+    let present: BTreeSet<usize> = store.list_chunks()?.into_iter().collect();
+    check_present(&options.directory, manifest.as_ref(), &present)?;
+    let total_size = match &manifest {
+        Some(manifest) => manifest.chunks.iter().map(|entry| entry.size).sum(),
+        None => {
+            let mut total = 0;
+            for &index in &present {
+                total += store.chunk_len(index)?;
+            }
+            total
+        }
+    };
+    Ok(ReconstructPlan {
+        overwrites: overwrites(&output_path),
+        output: output_path,
+        chunks: present.len(),
+        total_size,
+        verifies: manifest.is_some_and(|manifest| manifest.hash.is_some()),
+    })
+}
+
+// Whether writing `output_path` replaces something already there. A pipe is written
+// to, not replaced.
+fn overwrites(output_path: &Path) -> bool {
+    !is_stream(output_path) && fs::symlink_metadata(output_path).is_ok()
+}
+
+// What no reconstruction of `options` can go ahead with, however far it got.
+fn check_options(options: &ReconstructOptions) -> Result<()> {
+    if options.file_mode.is_some_and(|mode| mode > MAX_MODE) {
+        return Err(SplitterError::InvalidOption {
+            field: "file_mode",
+            reason: "must be an octal mode of at most 7777",
+        });
+    }
+    if options.buffer_size == 0 {
+        return Err(SplitterError::InvalidOption {
+            field: "buffer_size",
+            reason: "must be at least 1 byte",
+        });
+    }
+    let directory = options.directory.as_path();
+    let local = !(is_s3_url(directory) || is_sftp_url(directory) || is_archive(directory));
+    if let Some(hook) = &options.pre_chunk_cmd {
+        hook.validate("pre_chunk_cmd")?;
+        if !local {
+            return Err(SplitterError::InvalidOption {
+                field: "pre_chunk_cmd",
+                reason: "needs a local directory to fetch the chunk files into",
+            });
+        }
+    }
+    if !options.volumes.is_empty() && !local {
+        return Err(SplitterError::InvalidOption {
+            field: "volumes",
+            reason: "go with a local directory of chunks",
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "sftp"))]
+fn no_sftp() -> SplitterError {
+    SplitterError::InvalidOption {
+        field: "directory",
+        reason: "sftp:// needs a build with the sftp feature",
+    }
+}
+
+// Where the chunks of an archive are put back together: next to it.
+fn archive_output(options: &ReconstructOptions) -> Result<PathBuf> {
+    let parent = options.directory.parent().unwrap_or(Path::new("."));
+    let output_path = output_in(parent, options, || {
+        default_output_name(&options.directory, options.accept_modified)
+    })?;
+    let output_path = clear_of_archive(&options.directory, output_path)?;
+    writable_output(options, output_path)
+}
+
+// A reconstruction from a local directory, with its chunk files found and checked.
+struct Local {
+    output_path: PathBuf,
+    manifest: Option<Manifest>,
+    // In order; none for a set with parity, which `reconstruct_with_parity` reads by
+    // its manifest once it has checked the chunks
+    chunk_files: Vec<PathBuf>,
+}
+
+fn plan_local(options: &ReconstructOptions) -> Result<Local> {
+    // Metadata sealed under the key is opened with it first, for the name it holds
+    let manifest = Manifest::load(&options.directory, options.accept_modified)
+        .ok()
+        .flatten();
+    let manifest = match manifest {
+        Some(manifest) if manifest.concealed() => Some(reveal(manifest, options)?),
+        _ => None,
+    };
+    let output_path = output_in(&options.directory, options, || match &manifest {
+        Some(manifest) => manifest.output_name(),
+        None => default_output_name(&options.directory, options.accept_modified),
+    })?;
+    let output_path = clear_of_set(&options.directory, output_path, options.output.is_some())?;
+    let output_path = writable_output(options, output_path)?;
+    let manifest = match manifest {
+        Some(manifest) => Some(manifest),
+        None => Manifest::load(&options.directory, options.accept_modified)
+            .ok()
+            .flatten(),
+    };
+    let spanned = manifest
+        .as_ref()
+        .and_then(|manifest| manifest.span.as_ref());
+    if !options.volumes.is_empty() && spanned.is_none() {
+        return Err(SplitterError::InvalidOption {
+            field: "volumes",
+            reason: "are for a set split across several directories, which this isn't",
+        });
+    }
+    let chunk_files = match &manifest {
+        Some(manifest) if manifest.parity.is_some() => Vec::new(),
+        Some(manifest) if manifest.span.is_some() => spanned_files(options, manifest)?,
+        Some(manifest)
+            if manifest.random_names || manifest.shards.is_some() || manifest.shares_files() =>
+        {
+            listed_files(&options.directory, manifest)?
+        }
+        _ => {
+            let listing = list_directory(
+                &options.directory,
+                options.symlinks,
+                options.accept_modified,
+            )?;
+            for link in &listing.skipped_links {
+                warn!(
+                    "skipping {}, a symbolic link; follow symbolic links to read it",
+                    link.display()
+                );
+            }
+            let files = listing.chunk_files;
+            let present = files
+                .iter()
+                .filter_map(|path| chunk_index(&path.file_name()?.to_string_lossy()))
+                .filter_map(|index| usize::try_from(index).ok())
+                .collect();
+            check_present(&options.directory, manifest.as_ref(), &present)?;
+            files
+        }
+    };
+    Ok(Local {
+        output_path,
+        manifest,
+        chunk_files,
+    })
+}
+
+// A manifest whose metadata is concealed, opened with the key in `options`.
+fn reveal(manifest: Manifest, options: &ReconstructOptions) -> Result<Manifest> {
+    let Some(key) = &options.key else {
+        return Err(SplitterError::Encrypted {
+            path: options.directory.clone(),
+        });
+    };
+    manifest.reveal(key, &options.directory.join(MANIFEST_NAME))
+}
+
+// Give the reconstructed file the owner and extended attributes the manifest recorded,
+// as far as they go, and the mode asked for, which has to. A pipe takes none of them.
+// The owner goes first, as changing it can clear some of the attributes, such as
+// Linux's file capabilities, and the setuid and setgid bits of the mode.
+fn restore_metadata(
+    options: &ReconstructOptions,
+    manifest: Option<&Manifest>,
+    output_path: &Path,
+) -> Result<()> {
+    if is_stream(output_path) {
+        return Ok(());
+    }
+    if let Some(manifest) = manifest {
+        if let Some(recorded) = &manifest.owner {
+            owner::restore(output_path, recorded, options.numeric_owner);
+        }
+        if !manifest.xattrs.is_empty() {
+            xattrs::restore(output_path, &manifest.xattrs);
+        }
+    }
+    modes::set(output_path, options.file_mode)
+}
+
+// Run the pre-chunk hook for every chunk the manifest in `directory` lists, which is
+// the only way of knowing what to fetch, and wait for them all.
+fn fetch_chunks(
+    hook: &ChunkHook,
+    directory: &Path,
+    accept_modified: bool,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let Some(manifest) = Manifest::load(directory, accept_modified)? else {
+        return Err(SplitterError::InvalidOption {
+            field: "pre_chunk_cmd",
+            reason: "needs the directory's info.json to know which chunks to fetch",
+        });
+    };
+    info!(
+        "running the pre-chunk command for the {} chunks of {}",
+        manifest.chunks.len(),
+        directory.display()
+    );
+    let hooks = Hooks::start(hook, Phase::PreChunk, manifest.chunks.len(), None, cancel);
+    for (index, entry) in manifest.indexed() {
+        hooks.run(index, directory.join(entry.file()));
+    }
+    hooks.finish(Ok(()))
+}
+
+// Where a set fetched from elsewhere goes: into the current directory, under the name
+// given or else the one its manifest recorded.
+fn remote_output(options: &ReconstructOptions, manifest: Option<Manifest>) -> Result<PathBuf> {
+    output_in(Path::new(""), options, || match manifest {
+        Some(manifest) => manifest.output_name(),
+        None => Err(SplitterError::InvalidOption {
+            field: "output",
+            reason: "must be given for chunks without an info.json",
+        }),
+    })
+}
+
+// `output_path`, or the same name in `fallback_dir` when it would go in a directory
+// that can't be written to, such as that of chunks on read-only media, rather than
+// failing once the first chunk has been read. A path given in full is left alone.
+fn writable_output(options: &ReconstructOptions, output_path: PathBuf) -> Result<PathBuf> {
+    let given = options
+        .output
+        .as_ref()
+        .is_some_and(|output| Path::new(output).is_absolute());
+    let parent = match output_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if given || is_stream(&output_path) || writable(parent) {
+        return Ok(output_path);
+    }
+    let fallback = options.fallback_dir.as_deref().unwrap_or(Path::new(""));
+    let redirected = output_in(fallback, options, || {
+        Ok(output_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned())
+    })?;
+    warn!(
+        "{} can't be written to; writing {} instead",
+        parent.display(),
+        std::path::absolute(&redirected)
+            .unwrap_or_else(|_| redirected.clone())
+            .display()
+    );
+    Ok(redirected)
+}
+
+// Whether files can be made in `directory`: not on a read-only file system, nor where
+// permissions keep us out. One that can't be looked at is taken to be, for what is
+// done in it to say what is wrong.
+fn writable(directory: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        let Ok(path) = CString::new(directory.as_os_str().as_bytes()) else {
+            return true;
+        };
+        // SAFETY: `path` is NUL-terminated.
+        if unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0 {
+            return true;
+        }
+        let error = io::Error::last_os_error();
+        !matches!(
+            error.kind(),
+            io::ErrorKind::ReadOnlyFilesystem | io::ErrorKind::PermissionDenied
+        )
+    }
+    // Elsewhere the only way to know is to try
+    #[cfg(not(unix))]
+    {
+        let probe = directory.join(format!(".fsr-probe-{}", std::process::id()));
+        match File::options().write(true).create_new(true).open(&probe) {
+            Ok(file) => {
+                drop(file);
+                let _ = fs::remove_file(&probe);
+                true
+            }
+            // ERROR_WRITE_PROTECT, from a write-protected card or disc
+            Err(e) if cfg!(windows) && e.raw_os_error() == Some(19) => false,
+            Err(e) => !matches!(
+                e.kind(),
+                io::ErrorKind::ReadOnlyFilesystem | io::ErrorKind::PermissionDenied
+            ),
+        }
+    }
+}
+
+// Where the output goes in `directory`: under `options.output`, or else the name
+// `recorded` gives, in the form `options.normalize` asks for. A file already there
+// whose name differs from that only in its normalization, as one from macOS can, is
+// the one written over, rather than a second one beside it that looks the same.
+fn output_in(
+    directory: &Path,
+    options: &ReconstructOptions,
+    recorded: impl FnOnce() -> Result<String>,
+) -> Result<PathBuf> {
+    let name = match &options.output {
+        Some(name) => name.clone(),
+        None => options.normalize.apply(&recorded()?),
+    };
+    // A recorded name comes from the set, which may have come from anywhere, so it has
+    // to stay in `directory`; one given is the caller's to choose
+    if options.output.is_none() && !is_plain_name(&name) {
+        return Err(SplitterError::UnsafeName {
+            path: options.directory.join(MANIFEST_NAME),
+            name,
+        });
+    }
+    // Nothing else is the same as an ASCII name
+    if !name.is_ascii() {
+        let listed = match directory.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => directory,
+        };
+        for entry in fs::read_dir(listed).into_iter().flatten().flatten() {
+            let existing = entry.file_name();
+            if let Some(existing) = existing.to_str()
+                && existing != name
+                && unicode::equivalent(existing, &name)
+            {
+                info!(
+                    "writing over {}, the same name as {} in another normalization form",
+                    existing, name
+                );
+                return Ok(directory.join(existing));
+            }
+        }
+    }
+    Ok(directory.join(name))
+}
+
+// `output_path` in the set's `directory`, unless writing there would ruin the set for
+// any later verify or repair: over one of its own files, or under a name that would pass
+// for one, such as an original that was called info.json. Its lock and trash count
+// too, as a file in their place would leave the set locked for good or its trash lost.
+// A name the set recorded then
+// goes in the directory above instead; one given in `output` is an error. Where the file
+// system ignores case `Info.json` is info.json too, so names are compared the way it
+// would compare them.
+fn clear_of_set(directory: &Path, output_path: PathBuf, given: bool) -> Result<PathBuf> {
+    let name = match output_path.file_name().and_then(|name| name.to_str()) {
+        Some(name) if output_path.parent() == Some(directory) => name,
+        _ => return Ok(output_path),
+    };
+    let ignore_case = case_insensitive(directory);
+    let set_name = |name: &str| {
+        is_set_file(name)
+            || sums_name(name).is_some()
+            || [LOCK_NAME, TRASH_DIR, TRANSFER_STATE_NAME].contains(&name)
+    };
+    let taken = set_name(name)
+        || ignore_case && set_name(&name.to_lowercase())
+        || fs::read_dir(directory)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .any(|entry| {
+                let existing = entry.file_name();
+                existing.to_str().is_some_and(|existing| {
+                    set_name(existing) && same_name(existing, name, ignore_case)
+                })
+            });
+    if !taken {
+        return Ok(output_path);
+    }
+    if given {
+        return Err(SplitterError::InvalidOption {
+            field: "output",
+            reason: "is the name of one of the set's own files, which it would write over",
+        });
+    }
+    let above = fs::canonicalize(directory).at(directory)?;
+    let Some(above) = above.parent() else {
+        return Err(SplitterError::InvalidOption {
+            field: "output",
+            reason: "must be given, as the name recorded is one of the set's own files",
+        });
+    };
+    let moved = above.join(name);
+    // Planning a reconstruction first comes here as well
+    if first_time(&moved) {
+        warn!(
+            "{} is the name of one of the set's own files; writing it to {} instead",
+            name,
+            moved.display()
+        );
+    }
+    Ok(moved)
+}
+
+// `output_path` next to `archive`, as long as it isn't the archive itself.
+fn clear_of_archive(archive: &Path, output_path: PathBuf) -> Result<PathBuf> {
+    let parent = archive.parent().unwrap_or(Path::new("."));
+    let ignore_case = case_insensitive(parent);
+    let name = |path: &Path| path.file_name()?.to_str().map(str::to_string);
+    if let (Some(archive_name), Some(output_name)) = (name(archive), name(&output_path))
+        && same_name(&archive_name, &output_name, ignore_case)
+    {
+        return Err(SplitterError::InvalidOption {
+            field: "output",
+            reason: "is the archive being read",
+        });
+    }
+    Ok(output_path)
+}
+
+// The chunks of an archive, or of a set in S3 or on an SFTP server, are read straight
+// out of it one after another, as `reconstruct_from` does for any store, checking the
+// hashes its manifest has; nothing is extracted or downloaded first. Chunks the
+// manifest lists must all be there. As with a directory, the output is written to a
+// temporary file, see `scratch`, that only replaces `output_path` once it is complete,
+// unless that is a pipe.
+fn reconstruct_store<S: ChunkStore>(
+    store: &S,
+    options: &ReconstructOptions,
+    output_path: &Path,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<ReconstructReport> {
+    if options.mmap || options.threads > 1 {
+        debug!("chunks are read out of an archive or a server on one thread, with buffered I/O");
+    }
+    let io = options.io();
+    let copy = |output: &mut File, count: &mut dyn FnMut(ProgressEvent)| {
+        reconstruct_from_with(&io, store, output, count, cancel)
+    };
+    reconstruct_store_with(store, options, output_path, &io, copy, progress, cancel)
+}
+
+// `reconstruct_store` from a store on a server through the async engine, with
+// `in_flight` chunks coming down at once, each tried `retries` more times. A pipe takes
+// the chunks in order, so is still written one chunk after another.
+#[cfg(feature = "async")]
+fn reconstruct_remote<S: SharedStore>(
+    store: S,
+    (in_flight, retries): (usize, u32),
+    options: &ReconstructOptions,
+    output_path: &Path,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<ReconstructReport> {
+    if is_stream(output_path) {
+        return reconstruct_store(&store, options, output_path, progress, cancel);
+    }
+    debug!("downloading up to {} chunks at once", in_flight);
+    let engine = Engine::new(in_flight, retries, cancel).at(&options.directory)?;
+    let store = Arc::new(store);
+    let copy = |output: &mut File, count: &mut dyn FnMut(ProgressEvent)| {
+        engine.reconstruct(&store, output, output_path, count)
+    };
+    reconstruct_store_with(
+        &*store,
+        options,
+        output_path,
+        &Io::default(),
+        copy,
+        progress,
+        cancel,
+    )
+}
+
+#[cfg(not(feature = "async"))]
+fn reconstruct_remote<S: ChunkStore>(
+    store: S,
+    _: (usize, u32),
+    options: &ReconstructOptions,
+    output_path: &Path,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<ReconstructReport> {
+    reconstruct_store(&store, options, output_path, progress, cancel)
+}
+
+// `reconstruct_store` with the chunks copied into the output by `copy`, which returns
+// how many bytes that was; `io` says which buffers it settled on.
+fn reconstruct_store_with<S: ChunkStore>(
+    store: &S,
+    options: &ReconstructOptions,
+    output_path: &Path,
+    io: &Io,
+    copy: impl FnOnce(&mut File, &mut dyn FnMut(ProgressEvent)) -> Result<u64>,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<ReconstructReport> {
+    cancel.check()?;
+    let manifest = store.read_info().ok().flatten();
+    if manifest
+        .as_ref()
+        .is_some_and(|manifest| manifest.encryption.is_some())
+    {
+        return Err(SplitterError::InvalidOption {
+            field: "directory",
+            reason: "holds encrypted chunks, which are only read back from a local directory",
+        });
+    }
+    let present = store.list_chunks()?.into_iter().collect();
+    check_present(&options.directory, manifest.as_ref(), &present)?;
+    info!(
+        "reconstructing {} from {}",
+        output_path.display(),
+        options.directory.display()
+    );
+    let streamed = is_stream(output_path);
+    let (mut output, staged) = match streamed {
+        true => (open_stream(output_path)?, None),
+        false => {
+            let (file, staged) = Staged::create(output_path, options.temp_dir.as_deref())?;
+            (file, Some(staged))
+        }
+    };
+    let mut chunks = 0;
+    let mut count = |event: ProgressEvent| {
+        if let ProgressEvent::ChunkFinished { .. } = event {
+            chunks += 1;
+        }
+        progress(event);
+    };
+    let copied = copy(&mut output, &mut count).and_then(|copied| {
+        match streamed {
+            true => output.flush().at(output_path)?,
+            false => output.sync_all().at(output_path)?,
+        }
+        Ok(copied)
+    });
+    // Dropping an unfinished temporary file removes it
+    let total_size = copied?;
+    drop(output);
+    if let Some(staged) = staged {
+        staged.persist(output_path)?;
+    }
+    info!(
+        "reconstructed {} ({} bytes)",
+        output_path.display(),
+        total_size
+    );
+    restore_metadata(options, manifest.as_ref(), output_path)?;
+    let report = ReconstructReport {
+        output: output_path.to_path_buf(),
+        chunks,
+        total_size,
+        recovered: Vec::new(),
+        adapted_buffers: io.chosen(),
+    };
+    progress(ProgressEvent::Completed {
+        report: Report::Reconstruct(report.clone()),
+    });
+    Ok(report)
+}
+
+// A set with parity is checked chunk by chunk first, which reads it all once more, so
+// that missing or damaged chunks can be rebuilt from the parity rather than failing the
+// reconstruction. Rebuilt chunks are kept in hidden files next to the others until the
+// reconstruction is done; the set itself is left as it was. When a stripe lost more
+// chunks than its parity covers, those chunks are reported missing.
+fn reconstruct_with_parity(
+    options: &ReconstructOptions,
+    manifest: &Manifest,
+    output_path: &Path,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<ReconstructReport> {
+    let directory = options.directory.as_path();
+    info!("checking the chunks of {} first", directory.display());
+    let damaged = parity::damaged(directory, manifest, cancel)?;
+    let temp_path = |entry: &ChunkEntry| directory.join(temp_name(&entry.name, "rebuilt"));
+    if !damaged.is_empty() {
+        let plans = parity::plan(directory, manifest, &damaged, cancel)?;
+        parity::rebuild(directory, manifest, &plans, &temp_path, cancel)?;
+    }
+    let mut chunk_files = Vec::with_capacity(manifest.chunks.len());
+    let mut recovered = Vec::new();
+    for (index, entry) in manifest.indexed() {
+        if damaged.contains(&index) {
+            warn!(
+                "{} is missing or damaged; rebuilt it from the parity",
+                entry.name
+            );
+            recovered.push(entry.name.clone());
+            chunk_files.push(temp_path(entry));
+        } else {
+            chunk_files.push(directory.join(entry.file()));
+        }
+    }
+    let result = assemble(
+        &chunk_files,
+        output_path,
+        &Copying::of(options),
+        progress,
+        cancel,
+    );
+    for (index, entry) in manifest.indexed() {
+        if damaged.contains(&index) {
+            let _ = fs::remove_file(temp_path(entry));
+        }
+    }
+    let report = ReconstructReport {
+        recovered,
+        ..result?
+    };
+    restore_metadata(options, Some(manifest), output_path)?;
+    progress(ProgressEvent::Completed {
+        report: Report::Reconstruct(report.clone()),
+    });
+    Ok(report)
+}
+
+// The chunk files of a set split with random names, which only its manifest can put in
+// order. None of them may be missing.
+fn listed_files(directory: &Path, manifest: &Manifest) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::with_capacity(manifest.chunks.len());
+    let mut missing = Vec::new();
+    for (index, entry) in manifest.indexed() {
+        let path = directory.join(entry.file());
+        if !path.is_file() {
+            missing.push(index as u64);
+        }
+        files.push(path);
+    }
+    if missing.is_empty() {
+        Ok(files)
+    } else {
+        Err(SplitterError::MissingChunks { indices: missing })
+    }
+}
+
+// The chunk files of a set split across several directories, each found in whichever of
+// `options.directory` and `options.volumes` has it, all of which must hold volumes of
+// the same split. None of them may be missing; the volumes those were on are logged,
+// since that is what there is to go and find.
+fn spanned_files(options: &ReconstructOptions, manifest: &Manifest) -> Result<Vec<PathBuf>> {
+    let mut directories = vec![options.directory.as_path()];
+    for volume in &options.volumes {
+        let same = Manifest::load(volume, options.accept_modified)?.is_some_and(|other| {
+            other.span.as_ref().map(|span| &span.volumes)
+                == manifest.span.as_ref().map(|span| &span.volumes)
+                && same_split(manifest, &other)
+        });
+        if !same {
+            return Err(SplitterError::ManifestMismatch {
+                path: volume.join(MANIFEST_NAME),
+            });
+        }
+        directories.push(volume);
+    }
+    let mut files = Vec::with_capacity(manifest.chunks.len());
+    let mut missing = Vec::new();
+    for (index, entry) in manifest.indexed() {
+        let found = directories
+            .iter()
+            .map(|directory| directory.join(entry.file()))
+            .find(|path| path.is_file());
+        match found {
+            Some(path) => files.push(path),
+            None => missing.push(index as u64),
+        }
+    }
+    if missing.is_empty() {
+        return Ok(files);
+    }
+    if let Some(span) = &manifest.span {
+        for (number, volume) in span.volumes.iter().enumerate() {
+            let lacking = missing
+                .iter()
+                .filter(|&&index| volume.chunks().contains(&(index as usize)))
+                .count();
+            if lacking > 0 {
+                warn!(
+                    "{} of the {} chunks of volume {} of {} are in none of the directories given",
+                    lacking,
+                    volume.count,
+                    number + 1,
+                    span.volumes.len()
+                );
+            }
+        }
+    }
+    Err(SplitterError::MissingChunks { indices: missing })
+}
+
+// A chunk file, where its bytes go in the output, how they were compressed and the key
+// they open with if encrypted.
+struct Source<'a> {
+    path: &'a Path,
+    compression: Compression,
+    key: Option<&'a ChunkKey>,
+    offset: u64,
+    size: u64,
+}
+
+impl Source<'_> {
+    // Whether the file holds anything but the bytes that go in the output.
+    fn encoded(&self) -> bool {
+        !self.compression.is_none() || self.key.is_some()
+    }
+
+    fn open(&self, copying: &Copying) -> Result<ChunkReader> {
+        let (path, retry) = (self.path, &copying.retry);
+        ChunkReader::open_keyed(
+            path,
+            self.compression,
+            self.key,
+            retry,
+            copying.io.buffer_size(),
+        )
+        .doing("opening chunk", path)
+    }
+}
+
+// Lay the chunks end to end. How a chunk is compressed, and so what it holds, is what
+// the manifest next to it records for it; only chunks it doesn't list go by their file
+// names, and their size by decoding them. That manifest is only gone by, changed after
+// it was sealed, with `accept_modified`. Where it says the chunks are encrypted, `key`
+// is needed to open them.
+fn sources<'a>(
+    chunk_files: &'a [PathBuf],
+    accept_modified: bool,
+    key: Option<&'a ChunkKey>,
+) -> Result<Vec<Source<'a>>> {
+    let mut directory = chunk_files
+        .first()
+        .and_then(|path| path.parent())
+        .unwrap_or(Path::new("."));
+    // The chunks of a sharded set are a level below its manifest
+    if let Some(above) = directory.parent()
+        && directory
+            .file_name()
+            .is_some_and(|name| is_shard_dir(&name.to_string_lossy()))
+        && above.join(MANIFEST_NAME).is_file()
+    {
+        directory = above;
+    }
+    let manifest = Manifest::load(directory, accept_modified).unwrap_or_else(|e| {
+        warn!("{}; going by the chunk names", e);
+        None
+    });
+    let encrypted = manifest
+        .as_ref()
+        .is_some_and(|manifest| manifest.encryption.is_some());
+    if let Some(encryption) = manifest
+        .as_ref()
+        .and_then(|manifest| manifest.encryption.as_ref())
+    {
+        match key {
+            Some(key) => key.check(encryption, directory)?,
+            None => {
+                return Err(SplitterError::Encrypted {
+                    path: directory.to_path_buf(),
+                });
+            }
+        }
+    }
+    let key = key.filter(|_| encrypted);
+    let manifest = match (manifest, key) {
+        (Some(manifest), Some(key)) => Some(manifest.reveal(key, &directory.join(MANIFEST_NAME))?),
+        (manifest, _) => manifest,
+    };
+    let mut sources = Vec::with_capacity(chunk_files.len());
+    let mut total = 0;
+    for chunk_path in chunk_files {
+        // As the manifest names it, with its shard subdirectory
+        let name = chunk_path.strip_prefix(directory).ok().map(|relative| {
+            let parts: Vec<_> = relative.iter().map(|part| part.to_string_lossy()).collect();
+            parts.join("/")
+        });
+        let name = name.as_deref();
+        let index = name.and_then(chunk_index);
+        let recorded = manifest.as_ref().and_then(|manifest| {
+            let entry = match manifest.random_names {
+                true => manifest
+                    .chunks
+                    .iter()
+                    .find(|entry| Some(entry.name.as_str()) == name),
+                false => manifest
+                    .chunks
+                    .iter()
+                    .find(|entry| index.is_some() && chunk_index(&entry.name) == index),
+            }?;
+            Some((manifest.compression_of(entry), entry.size))
+        });
+        let compression = match recorded {
+            Some((compression, _)) => compression,
+            None => chunk_path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .and_then(Compression::from_extension)
+                .unwrap_or_default(),
+        };
+        let size = match recorded {
+            _ if compression.is_none() && key.is_none() => {
+                fs::metadata(chunk_path).at(chunk_path)?.len()
+            }
+            Some((_, size)) => size,
+            None => {
+                let retry = RetryPolicy::default();
+                let mut reader = ChunkReader::open_keyed(
+                    chunk_path,
+                    compression,
+                    key,
+                    &retry,
+                    DEFAULT_BUFFER_SIZE,
+                )
+                .doing("opening chunk", chunk_path)?;
+                io::copy(&mut reader, &mut io::sink()).at(chunk_path)?
+            }
+        };
+        debug!(
+            "{} goes at offset {} ({} bytes)",
+            chunk_path.display(),
+            total,
+            size
+        );
+        sources.push(Source {
+            path: chunk_path,
+            compression,
+            key,
+            offset: total,
+            size,
+        });
+        total += size;
+    }
+    Ok(sources)
+}
+
+// Every chunk `manifest` lists must be among those `present` in `directory`. None at all
+// is only an empty file where the manifest says so, as one written by a split lists
+// every chunk and so none for an empty input; without that, it is more likely the wrong
+// directory, and an empty output would pass for the file.
+fn check_present(
+    directory: &Path,
+    manifest: Option<&Manifest>,
+    present: &BTreeSet<usize>,
+) -> Result<()> {
+    let missing: Vec<u64> = manifest
+        .iter()
+        .flat_map(|manifest| manifest.indexed())
+        .filter(|(index, _)| !present.contains(index))
+        .map(|(index, _)| index as u64)
+        .collect();
+    if !missing.is_empty() {
+        return Err(SplitterError::MissingChunks { indices: missing });
+    }
+    let empty = manifest.is_some_and(|manifest| manifest.version > 0 && manifest.chunks.is_empty());
+    if present.is_empty() && !empty {
+        return Err(SplitterError::NoChunks {
+            path: directory.to_path_buf(),
+        });
+    }
+    Ok(())
+}
+
+// A gap in chunk000..chunkN would silently shift everything after it, and two files of
+// the same number, however each is padded, would both be copied in.
+fn check_sequence(chunk_files: &[PathBuf]) -> Result<()> {
+    let mut present: BTreeMap<u64, &PathBuf> = BTreeMap::new();
+    for path in chunk_files {
+        let Some(index) = path
+            .file_name()
+            .and_then(|name| chunk_index(&name.to_string_lossy()))
+        else {
+            continue;
+        };
+        if let Some(first) = present.insert(index, path) {
+            return Err(SplitterError::DuplicateChunk {
+                index,
+                first: first.clone(),
+                second: path.clone(),
+            });
+        }
+    }
+    let missing = missing_below(present.keys().copied())?;
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(SplitterError::MissingChunks { indices: missing })
+    }
+}
+
+// Concatenate `chunk_files` into `output_path`. The output is first grown to its
+// final size (reserving the space unless `sparse`), and with more than one thread
+// chunks are copied to their offsets concurrently. Chunks that have gone missing
+// since they were listed are an error, as are gaps in their numbering. `progress`
+// hears about every chunk and buffer. On failure or once `cancel` is set, nothing is
+// left behind at `output_path`. Into a named pipe, the chunks are instead written one
+// after another on one thread, and what was written before a failure has been read
+// already.
+pub fn reconstruct_chunks(
+    chunk_files: &[PathBuf],
+    output_path: &Path,
+    threads: usize,
+    mmap: bool,
+    sparse: bool,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<ReconstructReport> {
+    check_sequence(chunk_files)?;
+    let copying = Copying {
+        mmap,
+        sparse,
+        ..Copying::new(threads)
+    };
+    let report = assemble(chunk_files, output_path, &copying, progress, cancel)?;
+    progress(ProgressEvent::Completed {
+        report: Report::Reconstruct(report.clone()),
+    });
+    Ok(report)
+}
+
+// `reconstruct_chunks` for chunks already known to be complete, short of reporting that
+// it is done.
+pub(crate) fn assemble(
+    chunk_files: &[PathBuf],
+    output_path: &Path,
+    copying: &Copying,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<ReconstructReport> {
+    info!(
+        "reconstructing {} from {} chunks",
+        output_path.display(),
+        chunk_files.len()
+    );
+    let mut total_size = 0;
+    let mut count = |event: ProgressEvent| {
+        if let ProgressEvent::BytesCopied { delta } = event {
+            total_size += delta;
+        }
+        progress(event);
+    };
+    concatenate(chunk_files, output_path, copying, &mut count, cancel)?;
+    info!(
+        "reconstructed {} ({} bytes)",
+        output_path.display(),
+        total_size
+    );
+    Ok(ReconstructReport {
+        output: output_path.to_path_buf(),
+        chunks: chunk_files.len(),
+        total_size,
+        recovered: Vec::new(),
+        adapted_buffers: copying.io.chosen(),
+    })
+}
+
+fn concatenate(
+    chunk_files: &[PathBuf],
+    output_path: &Path,
+    copying: &Copying,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<()> {
+    let &Copying {
+        threads,
+        mmap,
+        sparse,
+        ..
+    } = copying;
+    let sources = sources(chunk_files, copying.accept_modified, copying.key.as_ref())?;
+    if is_stream(output_path) {
+        if mmap || threads > 1 {
+            debug!("a pipe is written front to back on one thread, with buffered I/O");
+        }
+        return write_stream(&sources, output_path, copying, progress, cancel);
+    }
+    let compressed = sources.iter().any(Source::encoded);
+    if mmap && compressed {
+        warn!("compressed and encrypted chunks are read with buffered I/O, not a memory map");
+    }
+    #[cfg(feature = "mmap")]
+    if mmap && !compressed {
+        let temp_dir = copying.temp_dir.as_deref();
+        if mmap::reconstruct(
+            chunk_files,
+            output_path,
+            temp_dir,
+            threads,
+            progress,
+            cancel,
+        )?
+        .is_some()
+        {
+            return Ok(());
+        }
+        warn!(
+            "cannot map {} into memory; using buffered I/O instead",
+            output_path.display()
+        );
+    }
+    #[cfg(not(feature = "mmap"))]
+    let _ = mmap;
+
+    let total = sources.last().map_or(0, |last| last.offset + last.size);
+    if threads > 1 && sources.len() > 1 {
+        debug!("copying chunks with {} threads", threads);
+        return reconstruct_parallel(&sources, total, output_path, copying, progress, cancel);
+    }
+
+    // Concatenate all chunks into a temporary file, which goes again if any of them fails
+    let (output_file, staged) = Staged::create(output_path, copying.temp_dir.as_deref())?;
+    let mut copy_all = || {
+        if !sparse {
+            preallocate(&output_file, total).at(output_path)?;
+        }
+        for (index, source) in sources.iter().enumerate() {
+            cancel.check()?;
+            let size = source.size;
+            progress(ProgressEvent::ChunkStarted { index, size });
+            let mut copied = |delta| progress(ProgressEvent::BytesCopied { delta });
+            copy_chunk_at(source, &output_file, copying, &mut copied, cancel)?;
+            progress(ProgressEvent::ChunkFinished { index, hash: None });
+        }
+        Ok(())
+    };
+    let result = copy_all();
+    drop(output_file);
+    result?;
+    staged.persist(output_path)
+}
+
+// `concatenate` into a pipe or device, which can't be grown, written at an offset or
+// removed: the chunks go in order, decoded as they are read, with nothing to clean up
+// on failure.
+fn write_stream(
+    sources: &[Source],
+    output_path: &Path,
+    copying: &Copying,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<()> {
+    info!(
+        "{} is a pipe; writing the chunks into it in order",
+        output_path.display()
+    );
+    let mut output = open_stream(output_path)?;
+    for (index, source) in sources.iter().enumerate() {
+        cancel.check()?;
+        let (path, size) = (source.path, source.size);
+        progress(ProgressEvent::ChunkStarted { index, size });
+        let mut reader = source.open(copying)?;
+        let mut writer = Counting {
+            inner: &mut output,
+            copied: &mut |delta| progress(ProgressEvent::BytesCopied { delta }),
+            cancel,
+        };
+        let copied = copy_overlapped(
+            &copying.io,
+            &mut (&mut reader).take(size),
+            &mut writer,
+            None,
+        )
+        .at(path)?;
+        let actual = copied + io::copy(&mut reader, &mut io::sink()).at(path)?;
+        if actual != size {
+            return Err(match !source.encoded() {
+                true => SplitterError::ChangedSize {
+                    path: path.to_path_buf(),
+                },
+                false => SplitterError::DecodedSize {
+                    path: path.to_path_buf(),
+                    expected: size,
+                    actual,
+                },
+            });
+        }
+        progress(ProgressEvent::ChunkFinished { index, hash: None });
+    }
+    output.flush().at(output_path)
+}
+
+// A pipe to write the output into, opened as it is, which waits for a reader. Files
+// are written to a temporary file instead, see `scratch`.
+fn open_stream(output_path: &Path) -> Result<File> {
+    File::options()
+        .write(true)
+        .open(output_path)
+        .doing("creating", output_path)
+}
+
+// Workers take the next unclaimed chunk and write it in place. The result goes to a
+// temporary file, see `scratch`, that only replaces `output_path` once every chunk has
+// been copied.
+fn reconstruct_parallel(
+    sources: &[Source],
+    total: u64,
+    output_path: &Path,
+    copying: &Copying,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<()> {
+    let (output_file, temp_path) = scratch::create(output_path, copying.temp_dir.as_deref())?;
+    let sized = if copying.sparse {
+        output_file.set_len(total)
+    } else {
+        preallocate(&output_file, total)
+    };
+    let result = sized
+        .at(&temp_path)
+        .and_then(|_| copy_chunks_at(sources, &output_file, copying, progress, cancel));
+    drop(output_file);
+    match result {
+        Ok(()) => scratch::persist(&temp_path, output_path),
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(e)
+        }
+    }
+}
+
+// Grow `file` to `len` bytes with the space actually reserved, so a full disk shows up
+// before any copying rather than near the end. On Linux that takes fallocate; on
+// Windows, extending a file already allocates it. Elsewhere this only sets the length.
+fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+        // The 64 variant on glibc, whose off_t is 32 bits on 32-bit targets
+        #[cfg(not(target_env = "gnu"))]
+        use libc::{fallocate, off_t};
+        #[cfg(target_env = "gnu")]
+        use libc::{fallocate64 as fallocate, off64_t as off_t};
+        // SAFETY: plain system call on a descriptor we own.
+        let result =
+            unsafe { fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, len as off_t) };
+        if result != 0 {
+            let e = io::Error::last_os_error();
+            // Unsupported by the file system is fine; out of space is the point
+            if e.raw_os_error() == Some(libc::ENOSPC) {
+                return Err(e);
+            }
+            debug!(
+                "cannot reserve space for the output ({}); only setting its length",
+                e
+            );
+        }
+    }
+    file.set_len(len)
+}
+
+fn copy_chunks_at(
+    sources: &[Source],
+    output_file: &File,
+    copying: &Copying,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<()> {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel::<Result<ProgressEvent>>();
+
+    thread::scope(|scope| {
+        for _ in 0..copying.threads.min(sources.len()) {
+            let sender = sender.clone();
+            let (next, failed) = (&next, &failed);
+            scope.spawn(move || {
+                while !failed.load(Ordering::Relaxed) && !cancel.is_cancelled() {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(source) = sources.get(index) else {
+                        return;
+                    };
+                    let size = source.size;
+                    let _ = sender.send(Ok(ProgressEvent::ChunkStarted { index, size }));
+                    let mut copied = |delta| {
+                        let _ = sender.send(Ok(ProgressEvent::BytesCopied { delta }));
+                    };
+                    let result = copy_chunk_at(source, output_file, copying, &mut copied, cancel);
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    let finished = ProgressEvent::ChunkFinished { index, hash: None };
+                    let _ = sender.send(result.map(|_| finished));
+                }
+            });
+        }
+        drop(sender);
+
+        let mut error = None;
+        for result in receiver {
+            match result {
+                Ok(event) => progress(event),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        // Workers stop claiming chunks once cancelled, which leaves gaps
+        error.map_or_else(|| cancel.check(), Err)
+    })
+}
+
+// Copy one chunk to its offset, insisting it still has the size it had when the
+// offsets were worked out. Failures are reported against the chunk, which is the
+// part the caller can do something about. `copied` hears about each buffer.
+fn copy_chunk_at(
+    source: &Source,
+    output_file: &File,
+    copying: &Copying,
+    copied: &mut dyn FnMut(u64),
+    cancel: &CancelToken,
+) -> Result<u64> {
+    if source.encoded() {
+        return decode_chunk(source, output_file, copying, copied, cancel);
+    }
+    let chunk_file = retried(&copying.retry, "opening", source.path, || {
+        File::open(source.path)
+    })
+    .doing("opening chunk", source.path)?;
+    copy_chunk_range(source, chunk_file, output_file, copying, copied, cancel)
+        .at(source.path)?
+        .ok_or_else(|| SplitterError::ChangedSize {
+            path: source.path.to_path_buf(),
+        })
+}
+
+// None when `chunk_file` turned out shorter or longer than its size.
+fn copy_chunk_range(
+    source: &Source,
+    mut chunk_file: File,
+    output_file: &File,
+    copying: &Copying,
+    progress: &mut dyn FnMut(u64),
+    cancel: &CancelToken,
+) -> io::Result<Option<u64>> {
+    let &Source { offset, size, .. } = source;
+    let mut copied = fastcopy::copy_range(&chunk_file, 0, output_file, offset, size);
+    progress(copied);
+    if copied == size {
+        copying.io.release(&chunk_file, 0, size, false)?;
+        copying.io.release(output_file, offset, size, true)?;
+        return Ok(Some(copied));
+    }
+    chunk_file.seek(SeekFrom::Start(copied))?;
+    let mut writer = Counting {
+        inner: OffsetWriter {
+            file: output_file,
+            offset: offset + copied,
+        },
+        copied: progress,
+        cancel,
+    };
+    copied += copy_overlapped(
+        &copying.io,
+        &mut Retrying::new(&chunk_file, source.path, &copying.retry).take(size - copied),
+        &mut writer,
+        None,
+    )?;
+    let grew = chunk_file.read(&mut [0u8])? != 0;
+    copying.io.release(&chunk_file, 0, copied, false)?;
+    copying.io.release(output_file, offset, copied, true)?;
+    Ok((copied == size && !grew).then_some(copied))
+}
+
+// As `copy_chunk_range`, for a chunk that has to be decoded on the way. Decoding one
+// that is too long carries on to the end, so the error can say by how much.
+fn decode_chunk(
+    source: &Source,
+    output_file: &File,
+    copying: &Copying,
+    progress: &mut dyn FnMut(u64),
+    cancel: &CancelToken,
+) -> Result<u64> {
+    let path = source.path;
+    let mut reader = source.open(copying)?;
+    let mut writer = Counting {
+        inner: OffsetWriter {
+            file: output_file,
+            offset: source.offset,
+        },
+        copied: progress,
+        cancel,
+    };
+    let copied = copy_overlapped(
+        &copying.io,
+        &mut (&mut reader).take(source.size),
+        &mut writer,
+        None,
+    )
+    .at(path)?;
+    copying
+        .io
+        .release(output_file, source.offset, copied, true)
+        .at(path)?;
+    let actual = copied + io::copy(&mut reader, &mut io::sink()).at(path)?;
+    if actual != source.size {
+        return Err(SplitterError::DecodedSize {
+            path: path.to_path_buf(),
+            expected: source.size,
+            actual,
+        });
+    }
+    Ok(copied)
+}
+
+// Writes to a fixed position of a file shared with other writers.
+pub(crate) struct OffsetWriter<'a> {
+    pub file: &'a File,
+    pub offset: u64,
+}
+
+impl Write for OffsetWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write_all_at(self.file, buf, self.offset)?;
+        self.offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, data: &[u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(data, offset)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut data: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !data.is_empty() {
+        let written = file.seek_write(data, offset)?;
+        if written == 0 {
+            return Err(io::Error::from(io::ErrorKind::WriteZero));
+        }
+        data = &data[written..];
+        offset += written as u64;
+    }
+    Ok(())
+}
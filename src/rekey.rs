@@ -0,0 +1,313 @@
+// Encrypting a set's chunks again under another key, as after a passphrase got out or
+// to move a set from a passphrase to age recipients, without joining the file back
+// together. Only the sealing is done again: a chunk is opened with the old key and its
+// bytes, still compressed if they were, sealed under the new one, so the sizes and
+// hashes of what the chunks hold don't change, only those of their files.
+//
+// Every chunk is first opened through with the old key, which reads every byte of the
+// set, so that a wrong key or a damaged chunk is found before anything is written;
+// `dry_run` stops there. Then each chunk is written again to a temporary file beside
+// it, flushed to disk and renamed over it, and info.json last of all in the same way,
+// with the new key's fingerprint, or recipients, and KDF salt. A chunk is thus always
+// whole under one key or the other. Once the rewriting has started it isn't cancelled,
+// as stopping part way would leave chunks under both keys, which info.json can only
+// describe one of; a crash there leaves the set needing the old key for some chunks and
+// the new one for the rest.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::crypt::{ChunkKey, Encryption, SealedReader, SealedWriter};
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::{ProgressEvent, Report};
+use crate::lock;
+use crate::manifest::{ChunkEntry, ChunkHasher, HashAlgorithm, MANIFEST_NAME, Manifest};
+use crate::scratch::Staged;
+use crate::{is_archive, is_s3_url, is_sftp_url};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RekeyOptions {
+    // A local directory of encrypted chunks
+    pub directory: PathBuf,
+    // How the new key is got again, recorded in info.json along with what the key
+    // itself records of itself; see `ChunkKey::record`
+    pub encryption: Encryption,
+    // Only check that the old key opens every chunk
+    #[serde(default)]
+    pub dry_run: bool,
+    // Go on with an info.json that was changed after it was sealed
+    #[serde(default)]
+    pub accept_modified: bool,
+}
+
+impl RekeyOptions {
+    pub fn new(directory: impl Into<PathBuf>, encryption: Encryption) -> RekeyOptions {
+        RekeyOptions {
+            directory: directory.into(),
+            encryption,
+            dry_run: false,
+            accept_modified: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RekeyReport {
+    pub directory: PathBuf,
+    pub chunks: usize,
+    // The size of the chunk files read with the old key
+    pub stored_size: u64,
+    // Of the files written under the new key, which is 0 for a dry run
+    pub rewritten_size: u64,
+    pub dry_run: bool,
+    // How the set records its key now
+    pub encryption: Encryption,
+}
+
+// Encrypt the chunks in `options.directory`, now opened with `old`, under `new`. Both
+// passes over the chunks are reported to `progress`, the check first, then for other
+// than a dry run the rewrite, each chunk by the bytes of its file.
+pub fn rekey(
+    options: &RekeyOptions,
+    old: &ChunkKey,
+    new: &ChunkKey,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<RekeyReport> {
+    let directory = options.directory.as_path();
+    if is_s3_url(directory) || is_sftp_url(directory) || is_archive(directory) {
+        return Err(SplitterError::InvalidOption {
+            field: "directory",
+            reason: "must be a local directory of chunks to encrypt them again",
+        });
+    }
+    if let Some(unsupported) = options.encryption.unsupported() {
+        return Err(unsupported);
+    }
+    if new.cipher() != options.encryption.cipher {
+        return Err(SplitterError::InvalidOption {
+            field: "encryption",
+            reason: "is for another cipher than the new key",
+        });
+    }
+    let _lock = lock::acquire(directory, "rekey")?;
+    let path = directory.join(MANIFEST_NAME);
+    let Some(manifest) = Manifest::load(directory, options.accept_modified)? else {
+        return Err(SplitterError::NoChunks {
+            path: directory.to_path_buf(),
+        });
+    };
+    let Some(encryption) = manifest.encryption.clone() else {
+        return Err(SplitterError::InvalidOption {
+            field: "directory",
+            reason: "holds a set that isn't encrypted, so has no key to change",
+        });
+    };
+    old.check(&encryption, directory)?;
+    let concealed = manifest.concealed();
+    let mut manifest = manifest.reveal(old, &path)?;
+
+    info!(
+        "checking that the key opens every chunk in {}",
+        directory.display()
+    );
+    let mut stored_size = 0;
+    for (index, entry) in files(&manifest) {
+        cancel.check()?;
+        stored_size += check(directory, (index, entry), old, progress, cancel)?;
+    }
+    let mut report = RekeyReport {
+        directory: directory.to_path_buf(),
+        chunks: manifest.chunks.len(),
+        stored_size,
+        rewritten_size: 0,
+        dry_run: options.dry_run,
+        encryption,
+    };
+    if options.dry_run {
+        progress(ProgressEvent::Completed {
+            report: Report::Rekey(report.clone()),
+        });
+        return Ok(report);
+    }
+
+    info!("encrypting the chunks in {} again", directory.display());
+    let mut rewritten = Vec::new();
+    for (index, entry) in files(&manifest) {
+        let (size, stored_hash) = rewrite(directory, (index, entry), old, new, progress)?;
+        report.rewritten_size += size;
+        rewritten.push((index, stored_hash));
+    }
+    for (index, stored_hash) in rewritten {
+        manifest.chunks[index].stored_hash = Some(stored_hash);
+    }
+    let encryption = new.record(options.encryption.clone());
+    manifest.encryption = Some(encryption.clone());
+    let manifest = match concealed {
+        true => manifest.conceal(new, directory)?,
+        false => manifest,
+    };
+    sync_directories(directory, &manifest)?;
+    manifest.replace(directory)?;
+    info!(
+        "encrypted the {} chunks in {} again",
+        report.chunks,
+        directory.display()
+    );
+    report.encryption = encryption;
+    progress(ProgressEvent::Completed {
+        report: Report::Rekey(report.clone()),
+    });
+    Ok(report)
+}
+
+// The chunks with files of their own, and where they are in the manifest.
+fn files(manifest: &Manifest) -> impl Iterator<Item = (usize, &ChunkEntry)> {
+    manifest
+        .chunks
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.same_as.is_none())
+}
+
+// Open chunk `index` through with `key`, checking its file against the hash info.json
+// has of it. Returns the size of the file.
+fn check(
+    directory: &Path,
+    (index, entry): (usize, &ChunkEntry),
+    key: &ChunkKey,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<u64> {
+    let path = directory.join(&entry.name);
+    let size = fs::metadata(&path).at(&path)?.len();
+    progress(ProgressEvent::ChunkStarted { index, size });
+    let mut file = Hashed::new(File::open(&path).at(&path)?, progress, Some(cancel));
+    SealedReader::new(&mut file, Some(key))
+        .and_then(|mut reader| io::copy(&mut reader, &mut io::sink()))
+        .at(&path)?;
+    let hash = file.hasher.finish();
+    if entry
+        .stored_hash
+        .as_ref()
+        .is_some_and(|stored| *stored != hash)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "opens with the key but isn't the file info.json records; verify the set",
+        ))
+        .at(&path);
+    }
+    progress(ProgressEvent::ChunkFinished { index, hash: None });
+    Ok(size)
+}
+
+// Write chunk `index` again under `new` beside itself and put that in its place.
+// Returns the size of the new file and its SHA-256, for `stored_hash`.
+fn rewrite(
+    directory: &Path,
+    (index, entry): (usize, &ChunkEntry),
+    old: &ChunkKey,
+    new: &ChunkKey,
+    progress: &mut dyn FnMut(ProgressEvent),
+) -> Result<(u64, String)> {
+    let path = directory.join(&entry.name);
+    let size = fs::metadata(&path).at(&path)?.len();
+    progress(ProgressEvent::ChunkStarted { index, size });
+    let (temp, staged) = Staged::create(&path, None)?;
+    // From here on there is no stopping part way
+    let mut file = Hashed::new(File::open(&path).at(&path)?, progress, None);
+    let mut reader = SealedReader::new(&mut file, Some(old)).at(&path)?;
+    let mut writer = SealedWriter::new(Hashed::writing(temp), Some(new)).at(&path)?;
+    io::copy(&mut reader, &mut writer).at(&path)?;
+    let written = writer.finish().at(&path)?;
+    written.inner.sync_all().doing("writing", &path)?;
+    let (size, hash) = (written.len, written.hasher.finish());
+    drop(written.inner);
+    staged.persist(&path)?;
+    progress(ProgressEvent::ChunkFinished { index, hash: None });
+    Ok((size, hash))
+}
+
+// Flush the renames of the chunks to disk before info.json says they are under the new
+// key: the directory, and any shard subdirectories the chunks are in.
+fn sync_directories(directory: &Path, manifest: &Manifest) -> Result<()> {
+    // Only Unix lets a directory be opened to flush it
+    #[cfg(unix)]
+    for shard in std::iter::once("").chain(manifest.shard_dirs()) {
+        let path = directory.join(shard);
+        File::open(&path)
+            .and_then(|directory| directory.sync_all())
+            .at(&path)?;
+    }
+    #[cfg(not(unix))]
+    let _ = (directory, manifest);
+    Ok(())
+}
+
+// A chunk file as it is read or written, hashed with SHA-256 on the way. A file read
+// is reported to `copied`, and stops once `cancel` is set if there is one.
+struct Hashed<'a, T> {
+    inner: T,
+    hasher: ChunkHasher,
+    len: u64,
+    copied: Option<&'a mut dyn FnMut(ProgressEvent)>,
+    cancel: Option<&'a CancelToken>,
+}
+
+impl<'a, T> Hashed<'a, T> {
+    fn new(
+        inner: T,
+        progress: &'a mut dyn FnMut(ProgressEvent),
+        cancel: Option<&'a CancelToken>,
+    ) -> Hashed<'a, T> {
+        Hashed {
+            copied: Some(progress),
+            cancel,
+            ..Hashed::writing(inner)
+        }
+    }
+
+    fn writing(inner: T) -> Hashed<'a, T> {
+        Hashed {
+            inner,
+            hasher: HashAlgorithm::Sha256.hasher(),
+            len: 0,
+            copied: None,
+            cancel: None,
+        }
+    }
+}
+
+impl<T: Read> Read for Hashed<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(cancel) = self.cancel {
+            cancel.check_io()?;
+        }
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.len += read as u64;
+        if let Some(copied) = &mut self.copied {
+            copied(ProgressEvent::BytesCopied { delta: read as u64 });
+        }
+        Ok(read)
+    }
+}
+
+impl<T: Write> Write for Hashed<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
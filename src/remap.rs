@@ -0,0 +1,277 @@
+// Finding the chunks of a set under names other than those info.json lists, as after
+// they were all renamed in bulk, `chunk007` to `part007`, and writing the names they
+// have now into info.json so that the set reads again. Only the part of a name before
+// its number may have changed: a chunk is looked for as a file in its own directory
+// with the same number, padding and extension after another prefix. Every chunk
+// missing has to be there under the one other prefix, as the only files with it, and
+// be the size info.json records, or, for a compressed one, whose size on disk says
+// nothing, hash to what it records; when there are hashes, every one is checked.
+// Anything short of that is said as it is, rather than settled by guessing: two
+// prefixes that could both be the chunks, only some chunks renamed, some of them
+// missing under the one prefix there is, more files with it than chunks missing, or
+// one that doesn't match.
+//
+// The names go into info.json as a set with random names has them, in order, so that
+// nothing goes by the numbers in them any more. Sets named for another tool, and those
+// split across several directories, are left alone.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::error::{PathContext, Result, SplitterError};
+use crate::manifest::{Compression, MANIFEST_NAME, Manifest, hash_compressed, hash_file};
+use crate::{is_set_file, lock};
+
+// Files by the directory they are in, within the set's, and what follows their prefix
+type Files<'a> = BTreeMap<(&'a str, String), String>;
+
+// The chunks of a set found under other names, to be recorded with `apply_remap`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Remap {
+    pub directory: PathBuf,
+    // What the names in info.json start with, and what the files' do
+    pub from: String,
+    pub to: String,
+    // Each chunk's name in info.json and the name of the file found for it, in order
+    pub renamed: Vec<(String, String)>,
+    // Whether the files were checked against hashes, or only by their sizes
+    pub hashed: bool,
+}
+
+// The chunks info.json in `directory` lists but the directory doesn't have, found under
+// another prefix. None when nothing is missing, or nothing missing is there under
+// another name; `RemapDoubt` when what is there can't be told to be the chunks. An
+// info.json changed after it was sealed is refused unless `accept_modified`.
+pub fn find_remap(
+    directory: &Path,
+    accept_modified: bool,
+    cancel: &CancelToken,
+) -> Result<Option<Remap>> {
+    let Some(manifest) = Manifest::load(directory, accept_modified)? else {
+        return Ok(None);
+    };
+    if manifest.compat.is_some() || manifest.span.is_some() {
+        return Ok(None);
+    }
+    let absent: Vec<usize> = manifest
+        .chunks
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.same_as.is_none() && !directory.join(&entry.name).exists())
+        .map(|(position, _)| position)
+        .collect();
+    if absent.is_empty() {
+        return Ok(None);
+    }
+    // Each chunk missing by where it would be and what follows its prefix
+    let mut wanted = BTreeMap::new();
+    let mut from = BTreeSet::new();
+    for &position in &absent {
+        let name = &manifest.chunks[position].name;
+        let (subdirectory, file) = name.rsplit_once('/').unwrap_or(("", name));
+        // A random name has no number to find it by
+        let Some((prefix, rest)) = split_number(file) else {
+            return Ok(None);
+        };
+        from.insert(prefix);
+        wanted.insert((subdirectory, rest), position);
+    }
+    let listed: BTreeSet<&str> = manifest.chunks.iter().map(|entry| entry.file()).collect();
+    // The files of each other prefix, by where they are and what follows it
+    let mut prefixes: BTreeMap<String, Files> = BTreeMap::new();
+    let subdirectories: BTreeSet<&str> = wanted
+        .keys()
+        .map(|(subdirectory, _)| *subdirectory)
+        .collect();
+    for subdirectory in subdirectories {
+        let path = directory.join(subdirectory);
+        for entry in fs::read_dir(&path).at(&path)? {
+            let entry = entry.at(&path)?;
+            let Ok(file) = entry.file_name().into_string() else {
+                continue;
+            };
+            let name = match subdirectory {
+                "" => file.clone(),
+                subdirectory => format!("{}/{}", subdirectory, file),
+            };
+            if !entry.path().is_file() || listed.contains(name.as_str()) || is_set_file(&file) {
+                continue;
+            }
+            if let Some((prefix, rest)) = split_number(&file)
+                && !from.contains(prefix)
+            {
+                prefixes
+                    .entry(prefix.to_string())
+                    .or_default()
+                    .insert((subdirectory, rest.to_string()), name);
+            }
+        }
+    }
+    let from = from.into_iter().next().unwrap_or_default().to_string();
+    let candidates: Vec<(&String, &Files)> = prefixes
+        .iter()
+        .filter(|(_, files)| {
+            files
+                .keys()
+                .any(|(subdirectory, rest)| wanted.contains_key(&(*subdirectory, rest.as_str())))
+        })
+        .collect();
+    let doubt = |reason: String| SplitterError::RemapDoubt {
+        path: directory.to_path_buf(),
+        reason,
+    };
+    let (to, files) = match candidates.as_slice() {
+        [] => return Ok(None),
+        [only] => *only,
+        several => {
+            let names: Vec<String> = several
+                .iter()
+                .map(|(prefix, _)| format!("{}…", prefix))
+                .collect();
+            return Err(doubt(format!(
+                "files named {} could each be the {} chunks missing",
+                names.join(" and "),
+                absent.len()
+            )));
+        }
+    };
+    // Some renamed and the rest not is no rename in bulk, and two namings in one set
+    // would leave the numbers in them going two ways
+    let own = manifest
+        .chunks
+        .iter()
+        .filter(|entry| entry.same_as.is_none())
+        .count();
+    if own > absent.len() {
+        return Err(doubt(format!(
+            "the other {} chunks are there as {}…, and only the {} missing have files named \
+             {}…; if those are them, name them all the same way",
+            own - absent.len(),
+            from,
+            absent.len(),
+            to
+        )));
+    }
+    let mut renamed = Vec::with_capacity(absent.len());
+    for ((subdirectory, rest), &position) in &wanted {
+        let Some(file) = files.get(&(*subdirectory, rest.to_string())) else {
+            let found = wanted
+                .keys()
+                .filter(|(subdirectory, rest)| {
+                    files.contains_key(&(*subdirectory, rest.to_string()))
+                })
+                .count();
+            return Err(doubt(format!(
+                "only {} of the {} chunks missing are there as {}…, such as no {}",
+                found,
+                absent.len(),
+                to,
+                manifest.chunks[position].name.replacen(&from, to, 1)
+            )));
+        };
+        renamed.push((position, file.clone()));
+    }
+    if files.len() > absent.len() {
+        return Err(doubt(format!(
+            "there are {} files named {}… for the {} chunks missing",
+            files.len(),
+            to,
+            absent.len()
+        )));
+    }
+    renamed.sort();
+    let hashed = manifest.hash.is_some();
+    for (position, file) in &renamed {
+        let entry = &manifest.chunks[*position];
+        let path = directory.join(file);
+        let compression = manifest.compression_of(entry);
+        if compression == Compression::None {
+            let size = fs::metadata(&path).at(&path)?.len();
+            if size != entry.size {
+                return Err(doubt(format!(
+                    "{} is {} bytes, where info.json has {} for {}",
+                    file, size, entry.size, entry.name
+                )));
+            }
+        }
+        if let (Some(algorithm), Some(expected)) = (manifest.hash, &entry.hash) {
+            let hash = match compression {
+                Compression::None => hash_file(&path, algorithm, &mut |_| {}, cancel)?,
+                compression => hash_compressed(&path, compression, algorithm, &mut |_| {}, cancel)?,
+            };
+            if !hash.eq_ignore_ascii_case(expected) {
+                return Err(doubt(format!(
+                    "{} doesn't have the hash info.json has for {}",
+                    file, entry.name
+                )));
+            }
+        }
+    }
+    Ok(Some(Remap {
+        directory: directory.to_path_buf(),
+        from,
+        to: to.clone(),
+        renamed: renamed
+            .into_iter()
+            .map(|(position, file)| (manifest.chunks[position].name.clone(), file))
+            .collect(),
+        hashed,
+    }))
+}
+
+// Write the names `remap` found into the info.json of its set, sealing it again; one
+// changed after it was sealed is only taken with `accept_modified`, as by `find_remap`.
+pub fn apply_remap(remap: &Remap, accept_modified: bool) -> Result<()> {
+    let directory = &remap.directory;
+    let _lock = lock::acquire(directory, "remap")?;
+    let Some(mut manifest) = Manifest::load(directory, accept_modified)? else {
+        let message = format!("there is no {} to record the names in", MANIFEST_NAME);
+        return Err(io::Error::new(io::ErrorKind::NotFound, message)).at(directory);
+    };
+    let names: BTreeMap<&str, &str> = remap
+        .renamed
+        .iter()
+        .map(|(old, new)| (old.as_str(), new.as_str()))
+        .collect();
+    for entry in &mut manifest.chunks {
+        if let Some(&new) = names.get(entry.name.as_str()) {
+            entry.name = new.to_string();
+        }
+        if let Some(same_as) = &mut entry.same_as
+            && let Some(&new) = names.get(same_as.as_str())
+        {
+            *same_as = new.to_string();
+        }
+    }
+    manifest.random_names = true;
+    info!(
+        "recording {} chunks of {} under their names starting {} rather than {}",
+        remap.renamed.len(),
+        directory.display(),
+        remap.to,
+        remap.from
+    );
+    manifest.save(directory)
+}
+
+// A chunk's file name as what comes before its number and the rest, the number and
+// any extension: `chunk007.gz` as `chunk` and `007.gz`. None for a name without one.
+fn split_number(name: &str) -> Option<(&str, &str)> {
+    let stem = match name.split_once('.') {
+        Some((stem, extension)) if Compression::from_extension(extension).is_some() => stem,
+        Some(_) => return None,
+        None => name,
+    };
+    let digits = stem.len() - stem.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 {
+        return None;
+    }
+    let start = stem.len() - digits;
+    Some((&name[..start], &name[start..]))
+}
@@ -0,0 +1,302 @@
+// Fixing a chunk set in place from its parity, so the directory is whole again for
+// whatever copies it next, rather than only reconstructing the file around the damage.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::changes::FileChange;
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::{ProgressEvent, Report};
+use crate::hash_chunk;
+use crate::lock;
+use crate::manifest::{ChunkEntry, Compression, MANIFEST_NAME, Manifest};
+use crate::par2;
+use crate::parity;
+use crate::pipeline::{Io, copy_overlapped};
+use crate::store::{LocalDirStore, restore_shard_dir, temp_name};
+
+// Outcome of `repair`: what was rebuilt, or with `dry_run` what would have been.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RepairReport {
+    pub directory: PathBuf,
+    pub dry_run: bool,
+    // Chunks that were missing or damaged, rebuilt from the parity
+    pub rebuilt: Vec<String>,
+    // Parity files that were missing or damaged, computed again from the chunks
+    pub parity: Vec<String>,
+    // Files that were missing or damaged, rebuilt from the directory's `.par2` files
+    #[serde(default)]
+    pub par2: Vec<String>,
+    // With `dry_run`, the files that would be written, with their sizes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<FileChange>,
+}
+
+impl RepairReport {
+    pub fn is_empty(&self) -> bool {
+        self.rebuilt.is_empty() && self.parity.is_empty() && self.par2.is_empty()
+    }
+}
+
+// Rebuild the missing and damaged chunks of `directory` from its parity, then the
+// damaged parity files from the chunks. Each file is written under a temporary name,
+// checked against its recorded hash and only then renamed over the old one, stored the
+// way the manifest says it is. When a stripe has lost more than its parity covers,
+// nothing is touched and its chunks are reported missing. `.par2` files in the
+// directory are used first, when they can rebuild everything they find lost; what they
+// cover (the chunks and the manifest, when this tool wrote them) is then whole before
+// the parity is looked at. With `dry_run` the damage is only looked for, and what would
+// be written listed in `changes`. `progress` hears about every chunk rebuilt; `cancel`
+// stops between buffers, leaving the chunks already repaired in place. A manifest
+// changed after it was sealed is refused unless `accept_modified`.
+pub fn repair(
+    directory: &Path,
+    dry_run: bool,
+    accept_modified: bool,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<RepairReport> {
+    let _lock = lock::acquire(directory, "repair")?;
+    let mut report = RepairReport {
+        directory: directory.to_path_buf(),
+        dry_run,
+        rebuilt: Vec::new(),
+        parity: Vec::new(),
+        par2: Vec::new(),
+        changes: Vec::new(),
+    };
+    let par2 = match par2::load(directory)? {
+        Some(set) => {
+            info!("checking {} against its PAR2 files", directory.display());
+            let damage = par2::check(&set, directory, cancel)?;
+            Some((set, damage))
+        }
+        None => None,
+    };
+    // Files the PAR2 data can't rebuild are left for the parity as if it wasn't there
+    if let Some((set, damage)) = &par2
+        && !damage.files.is_empty()
+    {
+        if !set.can_repair(damage) {
+            info!("the PAR2 files have too few recovery slices for the damage found");
+        } else if dry_run {
+            report.par2 = set.damaged_names(damage);
+        } else if let Some(repaired) = par2::repair(set, directory, damage, cancel)? {
+            for name in &repaired {
+                info!(
+                    "{} was missing or damaged; rebuilt it from the PAR2 files",
+                    name
+                );
+            }
+            report.par2 = repaired;
+        }
+    }
+
+    // A manifest the PAR2 data is about to rebuild can't be read yet, and neither can a
+    // dry run tell what would be left after that
+    let manifest = match report.par2.iter().any(|name| name == MANIFEST_NAME) && dry_run {
+        true => None,
+        false => Manifest::load(directory, accept_modified)?,
+    };
+    let Some(mut manifest) = manifest else {
+        if par2.is_some() {
+            progress(ProgressEvent::Completed {
+                report: Report::Repair(report.clone()),
+            });
+            return Ok(report);
+        }
+        let path = directory.join(MANIFEST_NAME);
+        return Err(io::Error::from(io::ErrorKind::NotFound)).at(&path);
+    };
+    // What the PAR2 data would rebuild, sized as the manifest has them when it can
+    if dry_run {
+        for name in &report.par2 {
+            let size = manifest
+                .chunks
+                .iter()
+                .find(|entry| entry.file() == name.as_str())
+                .filter(|entry| manifest.compression_of(entry).is_none())
+                .map(|entry| entry.size);
+            report
+                .changes
+                .push(FileChange::write(directory.join(name), size));
+        }
+    }
+    info!("checking the chunks of {}", directory.display());
+    let mut damaged = parity::damaged(directory, &manifest, cancel)?;
+    if dry_run {
+        damaged.retain(|&index| {
+            let entry = manifest.indexed().find(|&(i, _)| i == index);
+            entry.is_none_or(|(_, entry)| !report.par2.contains(&entry.name))
+        });
+    }
+    let plans = match damaged.is_empty() {
+        true => Vec::new(),
+        false => parity::plan(directory, &manifest, &damaged, cancel)?,
+    };
+    let mut numbers = Vec::new();
+    if let Some(info) = &manifest.parity {
+        for (number, entry) in info.files.iter().enumerate() {
+            if !parity::is_intact(directory, &manifest, entry, &mut |_| {}, cancel)? {
+                numbers.push(number);
+            }
+        }
+    }
+    let targets: Vec<(usize, ChunkEntry)> = manifest
+        .indexed()
+        .filter(|(index, _)| damaged.contains(index))
+        .map(|(index, entry)| (index, entry.clone()))
+        .collect();
+    if dry_run {
+        for (_, entry) in &targets {
+            // Stored compressed, it takes what compressing it again comes to
+            let size = Some(entry.size).filter(|_| manifest.compression_of(entry).is_none());
+            report
+                .changes
+                .push(FileChange::write(directory.join(entry.file()), size));
+        }
+        report.rebuilt = targets.into_iter().map(|(_, entry)| entry.name).collect();
+        if let Some(info) = &manifest.parity {
+            report.parity = numbers
+                .iter()
+                .map(|&n| info.files[n].name.clone())
+                .collect();
+            for name in &report.parity {
+                let path = directory.join(name);
+                report
+                    .changes
+                    .push(FileChange::write(path, Some(info.size)));
+            }
+        }
+        progress(ProgressEvent::Completed {
+            report: Report::Repair(report.clone()),
+        });
+        return Ok(report);
+    }
+
+    let raw_path = |entry: &ChunkEntry| directory.join(temp_name(&entry.name, "rebuilt"));
+    for (_, entry) in &targets {
+        restore_shard_dir(directory, &entry.name)?;
+    }
+    let result = parity::rebuild(directory, &manifest, &plans, &raw_path, cancel);
+    let mut hashes = Vec::with_capacity(targets.len());
+    let result = result.and_then(|()| {
+        for (index, entry) in &targets {
+            progress(ProgressEvent::ChunkStarted {
+                index: *index,
+                size: entry.size,
+            });
+            let raw = raw_path(entry);
+            let hash = replace_chunk(
+                directory,
+                &manifest,
+                (*index, entry),
+                &raw,
+                progress,
+                cancel,
+            )?;
+            info!(
+                "{} was missing or damaged; rebuilt it from the parity",
+                entry.name
+            );
+            report.rebuilt.push(entry.name.clone());
+            hashes.push(hash.clone());
+            progress(ProgressEvent::ChunkFinished {
+                index: *index,
+                hash,
+            });
+        }
+        Ok(())
+    });
+    for (_, entry) in &targets {
+        let _ = fs::remove_file(raw_path(entry));
+    }
+    result?;
+
+    // A rebuilt chunk that a hashed set had no hash for has one now
+    let mut changed = false;
+    for ((_, target), hash) in targets.iter().zip(hashes) {
+        if let Some(entry) = manifest.chunks.iter_mut().find(|e| e.name == target.name)
+            && entry.hash.is_none()
+            && hash.is_some()
+        {
+            entry.hash = hash;
+            changed = true;
+        }
+    }
+    let hashes = parity::rewrite(directory, &manifest, &numbers, cancel)?;
+    if let Some(info) = &mut manifest.parity {
+        for (&number, hash) in numbers.iter().zip(hashes) {
+            let entry = &mut info.files[number];
+            info!("{} was missing or damaged; computed it again", entry.name);
+            report.parity.push(entry.name.clone());
+            changed |= entry.hash != hash;
+            entry.hash = hash;
+        }
+    }
+    if changed {
+        manifest.save(directory)?;
+    }
+    progress(ProgressEvent::Completed {
+        report: Report::Repair(report.clone()),
+    });
+    Ok(report)
+}
+
+// Store the rebuilt chunk `entry`, whose original bytes are in `raw`, under its own
+// name: compressed again first if that is how the set keeps it, then hashed again as it
+// will be read back. Returns that hash.
+fn replace_chunk(
+    directory: &Path,
+    manifest: &Manifest,
+    (index, entry): (usize, &ChunkEntry),
+    raw: &Path,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Option<String>> {
+    let compression = manifest.compression_of(entry);
+    let temp = match compression {
+        Compression::None => raw.to_path_buf(),
+        compression => {
+            let name = temp_name(&entry.name, "repair");
+            let temp = directory.join(&name);
+            let store = LocalDirStore::new(directory)
+                .compressed(compression, compression.default_level())
+                .counted(manifest.chunks.len());
+            let encoded = store
+                .create_named(index, &name, compression)
+                .and_then(|mut writer| {
+                    let mut file = File::open(raw).at(raw)?;
+                    copy_overlapped(&Io::default(), &mut file, &mut writer, None).at(&temp)?;
+                    store.finish(writer).map(|_| ())
+                });
+            if let Err(e) = encoded {
+                let _ = fs::remove_file(&temp);
+                return Err(e);
+            }
+            temp
+        }
+    };
+    let hash = match manifest.hash {
+        Some(algorithm) => {
+            let mut copied = |delta| progress(ProgressEvent::BytesCopied { delta });
+            hash_chunk(&temp, compression, algorithm, &mut copied, cancel)?
+        }
+        None => None,
+    };
+    if entry.hash.is_some() && hash != entry.hash {
+        let _ = fs::remove_file(&temp);
+        warn!("{} does not read back as recorded once rebuilt", entry.name);
+        return Err(SplitterError::MissingChunks {
+            indices: vec![index as u64],
+        });
+    }
+    let path = directory.join(entry.file());
+    fs::rename(&temp, &path).at(&path)?;
+    Ok(hash)
+}
@@ -0,0 +1,253 @@
+// Retrying chunk files on a file system that fails now and then, such as a network
+// mount returning EIO under load, rather than losing hours of splitting to one bad
+// moment: creating and writing the chunks of a split, and opening and reading them in
+// a reconstruction. A call that fails is made again after a pause, `delay` at first and
+// twice as long each time up to MAX_BACKOFF, as long as the error is one that can pass:
+// interrupted, timed out or would block, or one of `also`. A missing file, refused
+// access and everything else is given up on at once. A read or write that fails has
+// moved nothing, so the one made again carries on from the same place. Each split and
+// reconstruction has its own policy, `SplitOptions::retry` and `ReconstructOptions::retry`,
+// which the CLI sets from `--retries`, `--retry-delay` and `--retry-on`.
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_DELAY: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// Errors that aren't retried unless asked for, as on a local disk they mean the disk
+// is failing rather than having a bad moment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Transient {
+    // An I/O error from the device or the server behind a mount (EIO)
+    Eio,
+    // A file handle a network file system server no longer recognizes (ESTALE)
+    Stale,
+    // A file or device in use by something else (EBUSY)
+    Busy,
+    // The connection to the server reset, dropped or unreachable
+    Network,
+}
+
+impl Transient {
+    fn matches(self, error: &io::Error) -> bool {
+        use io::ErrorKind::*;
+        let code = error.raw_os_error();
+        match self {
+            #[cfg(unix)]
+            Transient::Eio => code == Some(libc::EIO),
+            // ERROR_IO_DEVICE
+            #[cfg(windows)]
+            Transient::Eio => code == Some(1117),
+            #[cfg(not(any(unix, windows)))]
+            Transient::Eio => false,
+            Transient::Stale => error.kind() == StaleNetworkFileHandle,
+            Transient::Busy => error.kind() == ResourceBusy,
+            Transient::Network => {
+                // ERROR_UNEXP_NET_ERR and ERROR_NETNAME_DELETED
+                let windows = cfg!(windows) && matches!(code, Some(59 | 64));
+                windows
+                    || matches!(
+                        error.kind(),
+                        ConnectionReset
+                            | ConnectionAborted
+                            | NotConnected
+                            | BrokenPipe
+                            | HostUnreachable
+                            | NetworkUnreachable
+                            | NetworkDown
+                    )
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    // Further attempts after the first; 0 for none
+    pub retries: u32,
+    // The pause before the first of them
+    pub delay: Duration,
+    // Errors retried besides interrupted, timed out and would block
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub also: Vec<Transient>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        NONE
+    }
+}
+
+const NONE: RetryPolicy = RetryPolicy {
+    retries: 0,
+    delay: DEFAULT_DELAY,
+    also: Vec::new(),
+};
+
+fn retryable(policy: &RetryPolicy, error: &io::Error) -> bool {
+    use io::ErrorKind::*;
+    matches!(error.kind(), Interrupted | TimedOut | WouldBlock)
+        || policy.also.iter().any(|transient| transient.matches(error))
+}
+
+// Run `op`, `what` being done to `path` such as "writing", until it succeeds, fails in
+// a way that won't pass, or has no attempts left under `policy`. Each retry is logged; the error that
+// is given up on says how many attempts were made, when there was more than one.
+pub(crate) fn retried<T>(
+    policy: &RetryPolicy,
+    what: &str,
+    path: &Path,
+    mut op: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut error = match op() {
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+    let mut delay = policy.delay;
+    let mut attempt = 1;
+    loop {
+        if attempt > policy.retries || !retryable(policy, &error) {
+            return Err(match attempt {
+                1 => error,
+                _ => {
+                    let message = format!("{} (gave up after {} attempts)", error, attempt);
+                    io::Error::new(error.kind(), message)
+                }
+            });
+        }
+        attempt += 1;
+        warn!(
+            "{}: {} failed: {}; trying again in {:?} (attempt {} of {})",
+            path.display(),
+            what,
+            error,
+            delay,
+            attempt,
+            policy.retries + 1
+        );
+        thread::sleep(delay);
+        delay = (delay * 2).min(MAX_BACKOFF);
+        match op() {
+            Ok(value) => return Ok(value),
+            Err(e) => error = e,
+        }
+    }
+}
+
+// A chunk file whose reads, writes and flushes are `retried` under `policy`.
+pub(crate) struct Retrying<T> {
+    pub inner: T,
+    path: PathBuf,
+    policy: RetryPolicy,
+}
+
+impl<T> Retrying<T> {
+    pub fn new(inner: T, path: &Path, policy: &RetryPolicy) -> Retrying<T> {
+        Retrying {
+            inner,
+            path: path.to_path_buf(),
+            policy: policy.clone(),
+        }
+    }
+}
+
+impl<T: Read> Read for Retrying<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let inner = &mut self.inner;
+        retried(&self.policy, "reading", &self.path, || inner.read(buf))
+    }
+}
+
+impl<T: Write> Write for Retrying<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let inner = &mut self.inner;
+        retried(&self.policy, "writing", &self.path, || inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let inner = &mut self.inner;
+        retried(&self.policy, "flushing", &self.path, || inner.flush())
+    }
+}
+
+impl<T: Seek> Seek for Retrying<T> {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fails its first `failures` writes as timed out, then takes everything.
+    struct Flaky {
+        failures: u32,
+        written: Vec<u8>,
+    }
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn retries(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            retries,
+            delay: Duration::ZERO,
+            also: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn each_destination_goes_by_its_own_policy() {
+        let flaky = || Flaky {
+            failures: 2,
+            written: Vec::new(),
+        };
+        let (patient, impatient) = thread::scope(|scope| {
+            let patient = scope.spawn(|| {
+                let mut file = Retrying::new(flaky(), Path::new("a/chunk_0000"), &retries(3));
+                file.write_all(b"chunk").map(|_| file.inner.written)
+            });
+            let impatient = scope.spawn(|| {
+                let mut file = Retrying::new(flaky(), Path::new("b/chunk_0000"), &retries(1));
+                file.write_all(b"chunk").map(|_| file.inner.written)
+            });
+            (patient.join().unwrap(), impatient.join().unwrap())
+        });
+        assert_eq!(patient.unwrap(), b"chunk");
+        let error = impatient.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(error.to_string().contains("gave up after 2 attempts"));
+    }
+
+    #[test]
+    fn errors_that_wont_pass_are_not_retried() {
+        let mut calls = 0;
+        let result: io::Result<()> = retried(&retries(5), "opening", Path::new("x"), || {
+            calls += 1;
+            Err(io::ErrorKind::NotFound.into())
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,1622 @@
+// Chunk sets kept as objects in S3, or in any service that speaks its API (MinIO,
+// Ceph, Backblaze B2, …): `s3://bucket/prefix` holds `prefix/chunk000`, … and
+// `prefix/info.json`. Requests are signed with AWS Signature Version 4 and addressed
+// path-style, `/bucket/key`, over https, which needs a build with the tls feature. Plain
+// http is only taken for an endpoint on this machine or the local network, such as a
+// MinIO beside it: only signatures would cross the wire, never the secret key, but the
+// chunks would go as they are. Credentials are looked for where the AWS tools look:
+// AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY (with AWS_SESSION_TOKEN), then the
+// profile's keys in ~/.aws/credentials or ~/.aws/config.
+//
+// A split uploads a few chunks at once while the next is read: each in one PUT, or as a
+// multipart upload in parts of `PART_SIZE` when it is larger than that. `info.json`
+// goes last, once every chunk is in place, so a prefix with one holds a whole set. A
+// split that fails takes down what it uploaded, and any multipart upload it left
+// unfinished, which would otherwise go on being billed for. One that never got to, say
+// because the machine went down, leaves chunks without an `info.json`; splitting to
+// the same prefix again writes over them, aborting unfinished multipart uploads of
+// chunks first, and skips the chunks sent whole that are already there as they would
+// be written.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use log::{debug, info, warn};
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::armor::{ArmorDecoder, ArmorEncoder};
+use crate::cancel::CancelToken;
+use crate::chunk_index;
+#[cfg(feature = "async")]
+use crate::engine::SharedStore;
+use crate::error::{PathContext, Result, SplitterError};
+use crate::fetch::retryable;
+use crate::gzip::{GzDecoder, GzEncoder};
+use crate::http::{self, Response, Url, encode};
+use crate::manifest::{Compression, MANIFEST_NAME, Manifest};
+use crate::pipeline::DEFAULT_BUFFER_SIZE;
+use crate::store::{ChunkStore, chunk_name};
+use crate::zst::{ZstDecoder, ZstEncoder};
+
+pub const S3_SCHEME: &str = "s3://";
+
+// Chunks up to this size go up in one PUT, larger ones in parts of this size, or more
+// for chunks that would take more than `MAX_PARTS`
+const PART_SIZE: u64 = 8 << 20;
+const MAX_PARTS: u64 = 10_000;
+// Longest wait between two attempts at a request
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// Most read of an error response or a page of a listing
+const MAX_TEXT: u64 = 16 << 20;
+const DEFAULT_REGION: &str = "us-east-1";
+
+// Where the service is and how to talk to it. Unset fields are looked for where the AWS
+// tools look for them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct S3Options {
+    // An https:// URL, or http:// for a local service [default: AWS_ENDPOINT_URL_S3,
+    // AWS_ENDPOINT_URL or the profile's endpoint_url, else AWS's own endpoint for the
+    // region]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    // [default: AWS_REGION, AWS_DEFAULT_REGION or the profile's, else us-east-1]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    // The profile in ~/.aws/credentials and ~/.aws/config, which is then used even with
+    // keys in the environment [default: AWS_PROFILE, else default]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    // Requests in flight at once
+    pub connections: usize,
+    // Further attempts at a request that failed in a way another may get past
+    pub retries: u32,
+}
+
+impl Default for S3Options {
+    // Four connections and three retries, everything else from the environment.
+    fn default() -> S3Options {
+        S3Options {
+            endpoint: None,
+            region: None,
+            profile: None,
+            connections: 4,
+            retries: 3,
+        }
+    }
+}
+
+// Whether `path` is an `s3://bucket/prefix` URL rather than a local path.
+pub fn is_s3_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with(S3_SCHEME))
+}
+
+#[derive(Clone)]
+struct Credentials {
+    access_key: String,
+    secret_key: String,
+    token: Option<String>,
+}
+
+// Without the secrets, which would otherwise end up in logs
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("access_key", &self.access_key)
+            .finish_non_exhaustive()
+    }
+}
+
+// One object a listing turned up.
+#[derive(Clone, Debug)]
+struct Object {
+    size: u64,
+    etag: String,
+}
+
+// Signs and sends the requests for one bucket, retrying those that fail in a way that
+// may pass.
+#[derive(Debug)]
+struct Client {
+    endpoint: Url,
+    region: String,
+    credentials: Credentials,
+    bucket: String,
+    retries: u32,
+    cancel: CancelToken,
+}
+
+impl Client {
+    fn new(bucket: &str, options: &S3Options, cancel: &CancelToken) -> Result<Client> {
+        let profile = options
+            .profile
+            .clone()
+            .or_else(|| env::var("AWS_PROFILE").ok())
+            .unwrap_or_else(|| "default".to_string());
+        let config = Profile::load(&profile);
+        let region = options
+            .region
+            .clone()
+            .or_else(|| env::var("AWS_REGION").ok())
+            .or_else(|| env::var("AWS_DEFAULT_REGION").ok())
+            .or_else(|| config.get("region"))
+            .unwrap_or_else(|| DEFAULT_REGION.to_string());
+        let endpoint = options
+            .endpoint
+            .clone()
+            .or_else(|| env::var("AWS_ENDPOINT_URL_S3").ok())
+            .or_else(|| env::var("AWS_ENDPOINT_URL").ok())
+            .or_else(|| config.get("endpoint_url"));
+        let endpoint = endpoint.unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+        let endpoint = Url::parse(&endpoint).map_err(|e| {
+            let reason = match e.kind() {
+                io::ErrorKind::Unsupported => "is https, which needs a build with the tls feature",
+                _ => "must be an https:// or http:// URL",
+            };
+            SplitterError::InvalidOption {
+                field: "endpoint",
+                reason,
+            }
+        })?;
+        if !endpoint.is_secure() && !endpoint.is_local() {
+            return Err(SplitterError::InvalidOption {
+                field: "endpoint",
+                reason: "may only be http:// on this machine or the local network; use https",
+            });
+        }
+        let from_environment = match (
+            env::var("AWS_ACCESS_KEY_ID"),
+            env::var("AWS_SECRET_ACCESS_KEY"),
+        ) {
+            (Ok(access_key), Ok(secret_key)) if options.profile.is_none() => Some(Credentials {
+                access_key,
+                secret_key,
+                token: env::var("AWS_SESSION_TOKEN").ok(),
+            }),
+            _ => None,
+        };
+        let credentials = match from_environment {
+            Some(credentials) => {
+                debug!("using the credentials in the environment");
+                credentials
+            }
+            None => match (
+                config.get("aws_access_key_id"),
+                config.get("aws_secret_access_key"),
+            ) {
+                (Some(access_key), Some(secret_key)) => {
+                    debug!("using the credentials of the profile {}", profile);
+                    Credentials {
+                        access_key,
+                        secret_key,
+                        token: config.get("aws_session_token"),
+                    }
+                }
+                _ => {
+                    return Err(SplitterError::InvalidOption {
+                        field: "credentials",
+                        reason: "none found; set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, or give them a profile in ~/.aws/credentials",
+                    });
+                }
+            },
+        };
+        Ok(Client {
+            endpoint,
+            region,
+            credentials,
+            bucket: bucket.to_string(),
+            retries: options.retries,
+            cancel: cancel.clone(),
+        })
+    }
+
+    // `method` on `key`, or the bucket itself when that is empty, with `query` and
+    // `headers`. Anything but success comes back as an error, after as many attempts
+    // as the client makes at those that may pass.
+    fn call(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> io::Result<Response> {
+        let payload = hex(&Sha256::digest(body));
+        let mut backoff = Duration::from_secs(1);
+        let mut attempt = 0;
+        loop {
+            let failure = match self.send(method, key, query, headers, body, &payload) {
+                Ok(response) => return Ok(response),
+                Err(e) if retryable(&e) && attempt < self.retries => e,
+                Err(e) => return Err(e),
+            };
+            attempt += 1;
+            warn!(
+                "{} {}: {}; trying again in {} s ({} of {})",
+                method,
+                self.describe(key),
+                failure,
+                backoff.as_secs(),
+                attempt,
+                self.retries
+            );
+            pause(backoff, &self.cancel)?;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    fn send(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        body: &[u8],
+        payload: &str,
+    ) -> io::Result<Response> {
+        let mut path = format!(
+            "{}/{}",
+            self.endpoint.path().trim_end_matches('/'),
+            encode(&self.bucket)
+        );
+        if !key.is_empty() {
+            path.push('/');
+            path.push_str(&encode_key(key));
+        }
+        let mut query: Vec<String> = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", encode(name), encode(value)))
+            .collect();
+        query.sort();
+        let query = query.join("&");
+        let (date, time) = amz_date(SystemTime::now());
+
+        let mut signed: Vec<(String, String)> = headers
+            .iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+        signed.push(("host".to_string(), self.endpoint.authority()));
+        signed.push(("x-amz-content-sha256".to_string(), payload.to_string()));
+        signed.push(("x-amz-date".to_string(), time.clone()));
+        if let Some(token) = &self.credentials.token {
+            signed.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+        signed.sort();
+        let request = CanonicalRequest {
+            method,
+            path: &path,
+            query: &query,
+            headers: &signed,
+            payload,
+        };
+        let (_, signature) = request.sign(&time, &self.region, "s3", &self.credentials.secret_key);
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.credentials.access_key,
+            scope(&date, &self.region, "s3"),
+            request.signed_headers(),
+            signature
+        );
+
+        // Host goes out with every request anyway
+        let mut sent: Vec<(String, String)> = signed
+            .into_iter()
+            .filter(|(name, _)| name != "host")
+            .collect();
+        sent.push(("Authorization".to_string(), authorization));
+        if !query.is_empty() {
+            path = format!("{}?{}", path, query);
+        }
+        let mut response = http::send(method, &self.endpoint.with_path(path), &sent, body)?;
+        if (200..300).contains(&response.status) {
+            return Ok(response);
+        }
+        Err(service_error(&mut response))
+    }
+
+    fn describe(&self, key: &str) -> String {
+        format!("{}{}/{}", S3_SCHEME, self.bucket, key)
+    }
+
+    // A response's whole body as text, such as a listing.
+    fn call_text(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        body: &[u8],
+    ) -> io::Result<String> {
+        let mut response = self.call(method, key, query, &[], body)?;
+        let mut text = Vec::new();
+        (&mut response).take(MAX_TEXT).read_to_end(&mut text)?;
+        let text = String::from_utf8_lossy(&text).into_owned();
+        // A multipart upload can fail after the status has gone out as a success
+        if let Some(code) = element(&text, "Error").and_then(|error| element(error, "Code")) {
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                format!("the service failed: {}", code),
+            ));
+        }
+        Ok(text)
+    }
+
+    // The objects whose keys start with `prefix`, by key.
+    fn list(&self, prefix: &str) -> io::Result<BTreeMap<String, Object>> {
+        let mut objects = BTreeMap::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+            if let Some(token) = &token {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let text = self.call_text("GET", "", &query, &[])?;
+            for contents in elements(&text, "Contents") {
+                let (Some(key), Some(size)) = (element(contents, "Key"), element(contents, "Size"))
+                else {
+                    continue;
+                };
+                let object = Object {
+                    size: size.trim().parse().unwrap_or(0),
+                    etag: unescape(element(contents, "ETag").unwrap_or_default()),
+                };
+                objects.insert(unescape(key), object);
+            }
+            match element(&text, "NextContinuationToken") {
+                Some(next) if element(&text, "IsTruncated") == Some("true") => {
+                    token = Some(unescape(next));
+                }
+                _ => return Ok(objects),
+            }
+        }
+    }
+
+    // Multipart uploads under `prefix` that were started and never completed or
+    // aborted, as keys and upload ids.
+    fn list_uploads(&self, prefix: &str) -> io::Result<Vec<(String, String)>> {
+        let mut uploads = Vec::new();
+        let mut markers: Option<(String, String)> = None;
+        loop {
+            let mut query = vec![("uploads", ""), ("prefix", prefix)];
+            if let Some((key, id)) = &markers {
+                query.push(("key-marker", key.as_str()));
+                query.push(("upload-id-marker", id.as_str()));
+            }
+            let text = self.call_text("GET", "", &query, &[])?;
+            for upload in elements(&text, "Upload") {
+                if let (Some(key), Some(id)) = (element(upload, "Key"), element(upload, "UploadId"))
+                {
+                    uploads.push((unescape(key), unescape(id)));
+                }
+            }
+            match (
+                element(&text, "NextKeyMarker"),
+                element(&text, "NextUploadIdMarker"),
+            ) {
+                (Some(key), Some(id)) if element(&text, "IsTruncated") == Some("true") => {
+                    markers = Some((unescape(key), unescape(id)));
+                }
+                _ => return Ok(uploads),
+            }
+        }
+    }
+
+    fn put(&self, key: &str, body: &[u8], content_type: &str) -> io::Result<()> {
+        self.call("PUT", key, &[], &[("Content-Type", content_type)], body)?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        self.call("DELETE", key, &[], &[], &[])?;
+        Ok(())
+    }
+
+    // `key` from byte `from` on, which if it isn't 0 must be the same object as `etag`.
+    fn get(&self, key: &str, from: u64, etag: Option<&str>) -> io::Result<Response> {
+        let range = format!("bytes={}-", from);
+        let mut headers = Vec::new();
+        if from > 0 {
+            headers.push(("Range", range.as_str()));
+            headers.extend(etag.map(|etag| ("If-Match", etag)));
+        }
+        let response = self.call("GET", key, &[], &headers, &[])?;
+        if from > 0 && (response.status != 206 || response.range_start() != Some(from)) {
+            return Err(io::Error::other("the service sent the wrong part"));
+        }
+        Ok(response)
+    }
+
+    fn start_upload(&self, key: &str) -> io::Result<String> {
+        let text = self.call_text("POST", key, &[("uploads", "")], &[])?;
+        match element(&text, "UploadId") {
+            Some(id) => Ok(unescape(id)),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no upload id in the service's answer",
+            )),
+        }
+    }
+
+    // Upload part `number` (from 1) of the upload `id`, returning its ETag.
+    fn upload_part(&self, key: &str, id: &str, number: u32, body: &[u8]) -> io::Result<String> {
+        let number = number.to_string();
+        let query = [("partNumber", number.as_str()), ("uploadId", id)];
+        let response = self.call("PUT", key, &query, &[], body)?;
+        match response.header("ETag") {
+            Some(etag) => Ok(etag.to_string()),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no ETag for an uploaded part",
+            )),
+        }
+    }
+
+    fn complete_upload(&self, key: &str, id: &str, etags: &[String]) -> io::Result<()> {
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for (number, etag) in etags.iter().enumerate() {
+            body.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                number + 1,
+                escape(etag)
+            ));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+        self.call_text("POST", key, &[("uploadId", id)], body.as_bytes())?;
+        Ok(())
+    }
+
+    fn abort_upload(&self, key: &str, id: &str) -> io::Result<()> {
+        match self.call("DELETE", key, &[("uploadId", id)], &[], &[]) {
+            // Already gone, which is what was wanted
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+}
+
+// The keys a profile gives, from ~/.aws/credentials and then ~/.aws/config, or the
+// files AWS_SHARED_CREDENTIALS_FILE and AWS_CONFIG_FILE name.
+struct Profile {
+    values: BTreeMap<String, String>,
+}
+
+impl Profile {
+    fn load(name: &str) -> Profile {
+        let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"));
+        let file = |variable: &str, default: &str| {
+            env::var_os(variable).map(PathBuf::from).or_else(|| {
+                home.as_ref()
+                    .map(|home| Path::new(home).join(".aws").join(default))
+            })
+        };
+        let mut values = BTreeMap::new();
+        // The config file calls every profile but the default `profile NAME`
+        let sections = [
+            (
+                file("AWS_SHARED_CREDENTIALS_FILE", "credentials"),
+                name.to_string(),
+            ),
+            (
+                file("AWS_CONFIG_FILE", "config"),
+                match name {
+                    "default" => name.to_string(),
+                    _ => format!("profile {}", name),
+                },
+            ),
+        ];
+        for (path, section) in sections {
+            let Some(text) = path.and_then(|path| fs::read_to_string(path).ok()) else {
+                continue;
+            };
+            for (key, value) in ini_section(&text, &section) {
+                values.entry(key).or_insert(value);
+            }
+        }
+        Profile { values }
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        self.values.get(key).cloned()
+    }
+}
+
+// The `key = value` lines of `[section]`, leaving out those indented under another key,
+// as the config file's `s3 =` settings are.
+fn ini_section(text: &str, section: &str) -> Vec<(String, String)> {
+    let mut values = Vec::new();
+    let mut inside = false;
+    for line in text.lines() {
+        let nested = line.starts_with([' ', '\t']);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            inside = name.trim() == section;
+        } else if inside
+            && !nested
+            && let Some((key, value)) = line.split_once('=')
+        {
+            values.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    values
+}
+
+// `s3://bucket/prefix` taken apart, with the prefix lacking slashes at either end.
+fn parse_url(url: &Path) -> Result<(String, String)> {
+    let invalid = SplitterError::InvalidOption {
+        field: "destination",
+        reason: "must be s3://BUCKET or s3://BUCKET/PREFIX",
+    };
+    let Some(rest) = url.to_str().and_then(|url| url.strip_prefix(S3_SCHEME)) else {
+        return Err(invalid);
+    };
+    let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+    if bucket.is_empty() || prefix.contains("//") {
+        return Err(invalid);
+    }
+    Ok((bucket.to_string(), prefix.trim_matches('/').to_string()))
+}
+
+// A chunk set under one prefix of a bucket, written with `create` or read with `open`.
+// Chunks are written compressed when the store is; reading goes by the set's manifest
+// when it has one, so sets split elsewhere and copied in, with random names or names
+// for another tool, read back as well.
+#[derive(Debug)]
+pub struct S3Store {
+    client: Arc<Client>,
+    // For error messages: s3://bucket/prefix
+    url: PathBuf,
+    prefix: String,
+    compression: Compression,
+    level: u32,
+    count: Option<usize>,
+    // Objects directly under the prefix, by name, as they were when the store opened
+    objects: BTreeMap<String, Object>,
+    manifest: Option<Manifest>,
+    // Chunks to read, by index, and the names of their objects
+    chunks: BTreeMap<usize, String>,
+    // While writing
+    pool: Option<Pool>,
+    part_size: usize,
+    // Objects uploaded, and multipart uploads started and not yet finished, by key
+    written: Arc<Mutex<BTreeSet<String>>>,
+    uploads: Arc<Mutex<BTreeMap<String, String>>>,
+}
+
+impl S3Store {
+    // The set at `url`, to read from; a manifest changed after it was sealed is refused
+    // unless `accept_modified`.
+    pub fn open(
+        url: impl Into<PathBuf>,
+        options: &S3Options,
+        accept_modified: bool,
+        cancel: &CancelToken,
+    ) -> Result<S3Store> {
+        let mut store = S3Store::connect(url.into(), options, cancel)?;
+        let url = store.url.clone();
+        let objects = store.client.list(&store.key("")).at(&url)?;
+        store.objects = store.children(objects);
+        if store.objects.contains_key(MANIFEST_NAME) {
+            let path = store.object_path(MANIFEST_NAME);
+            let mut text = Vec::new();
+            let mut response = store
+                .client
+                .get(&store.key(MANIFEST_NAME), 0, None)
+                .at(&path)?;
+            response.read_to_end(&mut text).at(&path)?;
+            let manifest = Manifest::parse(&text, &path, accept_modified)?;
+            store.compression = manifest.compression;
+            store.manifest = Some(manifest);
+        }
+        match &store.manifest {
+            Some(manifest) if !manifest.chunks.is_empty() => {
+                for (index, entry) in manifest.indexed() {
+                    if store.objects.contains_key(&entry.name) {
+                        store.chunks.insert(index, entry.name.clone());
+                    }
+                }
+            }
+            _ => {
+                for name in store.objects.keys() {
+                    if let Some(index) = chunk_index(name)
+                        && let Ok(index) = usize::try_from(index)
+                    {
+                        store.chunks.insert(index, name.clone());
+                    }
+                }
+            }
+        }
+        Ok(store)
+    }
+
+    // A new set at `url`, for chunks of at most `chunk_size` bytes. There mustn't be
+    // anything under the prefix but chunks a split left there without an `info.json`,
+    // which are written over.
+    pub fn create(
+        url: impl Into<PathBuf>,
+        options: &S3Options,
+        chunk_size: u64,
+        cancel: &CancelToken,
+    ) -> Result<S3Store> {
+        let mut store = S3Store::connect(url.into(), options, cancel)?;
+        let url = store.url.clone();
+        let listed = store.client.list(&store.key("")).at(&url)?;
+        let nested = listed.len();
+        store.objects = store.children(listed);
+        let foreign = store
+            .objects
+            .keys()
+            .any(|name| name == MANIFEST_NAME || chunk_index(name).is_none());
+        if foreign || store.objects.len() != nested {
+            return Err(SplitterError::DestinationNotEmpty { path: url });
+        }
+        if !store.objects.is_empty() {
+            info!(
+                "{} holds chunks of a split that never finished; writing over them",
+                url.display()
+            );
+        }
+        for (key, id) in store.client.list_uploads(&store.key("")).at(&url)? {
+            let name = key.strip_prefix(&store.key("")).unwrap_or(&key);
+            if chunk_index(name).is_some() {
+                info!(
+                    "aborting an unfinished upload of {} from an earlier split",
+                    name
+                );
+                store
+                    .client
+                    .abort_upload(&key, &id)
+                    .at(&store.object_path(name))?;
+            }
+        }
+        let twice = chunk_size.saturating_mul(2);
+        store.part_size =
+            usize::try_from(PART_SIZE.max(twice.div_ceil(MAX_PARTS))).unwrap_or(usize::MAX);
+        store.pool = Some(Pool::start(options.connections.max(1)));
+        Ok(store)
+    }
+
+    fn connect(url: PathBuf, options: &S3Options, cancel: &CancelToken) -> Result<S3Store> {
+        let (bucket, prefix) = parse_url(&url)?;
+        let client = Client::new(&bucket, options, cancel)?;
+        Ok(S3Store {
+            client: Arc::new(client),
+            url: PathBuf::from(format!("{}{}/{}", S3_SCHEME, bucket, prefix).trim_end_matches('/')),
+            prefix,
+            compression: Compression::None,
+            level: 0,
+            count: None,
+            objects: BTreeMap::new(),
+            manifest: None,
+            chunks: BTreeMap::new(),
+            pool: None,
+            part_size: PART_SIZE as usize,
+            written: Arc::default(),
+            uploads: Arc::default(),
+        })
+    }
+
+    // Write chunks compressed with `compression` at `level`.
+    pub fn compressed(mut self, compression: Compression, level: u32) -> S3Store {
+        self.compression = compression;
+        self.level = level;
+        self
+    }
+
+    // A set of `count` chunks, which armored chunks say in their header.
+    pub(crate) fn counted(mut self, count: usize) -> S3Store {
+        self.count = Some(count);
+        self
+    }
+
+    pub fn url(&self) -> &Path {
+        &self.url
+    }
+
+    // Bytes uploaded so far, as stored.
+    pub fn stored_size(&self) -> u64 {
+        self.pool
+            .as_ref()
+            .map_or(0, |pool| pool.shared.stored.load(Ordering::Relaxed))
+    }
+
+    fn key(&self, name: &str) -> String {
+        match self.prefix.is_empty() {
+            true => name.to_string(),
+            false => format!("{}/{}", self.prefix, name),
+        }
+    }
+
+    // With a slash whatever the platform's separator, as the URL has it.
+    fn object_path(&self, name: &str) -> PathBuf {
+        PathBuf::from(format!("{}/{}", self.url.display(), name))
+    }
+
+    // Of a listing of the prefix, the objects directly under it, by name.
+    fn children(&self, listed: BTreeMap<String, Object>) -> BTreeMap<String, Object> {
+        let start = self.key("");
+        listed
+            .into_iter()
+            .filter_map(|(key, object)| {
+                let name = key.strip_prefix(&start)?;
+                (!name.is_empty() && !name.contains('/')).then(|| (name.to_string(), object))
+            })
+            .collect()
+    }
+
+    pub fn chunk_compression(&self, index: usize) -> Compression {
+        let entry = self.manifest.as_ref().and_then(|manifest| {
+            let name = self.chunks.get(&index)?;
+            manifest.chunks.iter().find(|entry| &entry.name == name)
+        });
+        entry
+            .and_then(|entry| entry.compression)
+            .unwrap_or(self.compression)
+    }
+
+    // Stop uploading after a failed split: what is still queued is dropped, unfinished
+    // multipart uploads are aborted, and unless `keep_partial` the chunks uploaded are
+    // deleted again. Best effort, as the failure is what the caller reports.
+    pub(crate) fn abort(&mut self, keep_partial: bool) {
+        if let Some(pool) = self.pool.take() {
+            pool.stop();
+        }
+        let uploads = std::mem::take(&mut *self.uploads.lock().unwrap());
+        for (key, id) in uploads {
+            if let Err(e) = self.client.abort_upload(&key, &id) {
+                warn!(
+                    "cannot abort the upload of {}: {}; the service's lifecycle rules will have to",
+                    self.client.describe(&key),
+                    e
+                );
+            }
+        }
+        if keep_partial {
+            return;
+        }
+        let written = std::mem::take(&mut *self.written.lock().unwrap());
+        for key in written {
+            if let Err(e) = self.client.delete(&key) {
+                debug!("cannot delete {}: {}", self.client.describe(&key), e);
+            }
+        }
+    }
+
+    // Wait for every chunk to be uploaded.
+    fn drain(&mut self) -> Result<()> {
+        match &mut self.pool {
+            Some(pool) => pool.drain(&self.url),
+            None => Ok(()),
+        }
+    }
+
+    // Chunk `index` to write, whose parts go to the pool as they fill.
+    fn writer(&self, index: usize) -> Result<ObjectWriter> {
+        let name = chunk_name(index, self.compression);
+        let path = self.object_path(&name);
+        let pool = self.pool(&path)?;
+        let Some(jobs) = pool.sender.clone() else {
+            return Err(read_only()).at(&path);
+        };
+        let upload = Upload {
+            client: Arc::clone(&self.client),
+            key: self.key(&name),
+            path: path.clone(),
+            buffer: Vec::with_capacity(self.part_size),
+            part_size: self.part_size,
+            multipart: None,
+            parts: mpsc::channel(),
+            jobs,
+            shared: Arc::clone(&pool.shared),
+            uploads: Arc::clone(&self.uploads),
+        };
+        let encoder = match self.compression {
+            Compression::None => Encoder::Plain(upload),
+            Compression::Gzip => Encoder::Gzip(Box::new(GzEncoder::new(upload, self.level))),
+            Compression::Armor => {
+                let encoder = ArmorEncoder::new(upload, index, self.count).at(&path)?;
+                Encoder::Armor(Box::new(encoder))
+            }
+            Compression::Zstd { level, long } => {
+                let encoder = ZstEncoder::new(upload, level, long).at(&path)?;
+                Encoder::Zstd(Box::new(encoder))
+            }
+        };
+        Ok(ObjectWriter { encoder })
+    }
+
+    // A chunk in one part goes to the pool whole, unless the object is already there
+    // with the same contents, or with `wait` is put from this thread; the last part of a
+    // multipart upload goes with the others, which are then waited for so the upload
+    // can be completed.
+    fn finish(&self, writer: ObjectWriter, wait: bool) -> Result<()> {
+        let mut upload = match writer.encoder {
+            Encoder::Plain(upload) => Ok(upload),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Armor(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+        .at(&self.url)?;
+        let path = upload.path.clone();
+        let pool = self.pool(&path)?;
+        let Some((id, _)) = upload.multipart.clone() else {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if let Some(object) = self.objects.get(name.as_ref())
+                && object.size == upload.buffer.len() as u64
+                && object.etag.trim_matches('"')
+                    == hex(&<Md5 as md5::Digest>::digest(&upload.buffer))
+            {
+                debug!("{} is already there", path.display());
+                pool.shared.stored.fetch_add(object.size, Ordering::Relaxed);
+                self.written.lock().unwrap().insert(upload.key);
+                return Ok(());
+            }
+            if wait {
+                self.client
+                    .put(&upload.key, &upload.buffer, "application/octet-stream")
+                    .at(&path)?;
+                pool.shared
+                    .stored
+                    .fetch_add(upload.buffer.len() as u64, Ordering::Relaxed);
+                self.written.lock().unwrap().insert(upload.key);
+                return Ok(());
+            }
+            let (client, written) = (Arc::clone(&self.client), Arc::clone(&self.written));
+            let shared = Arc::clone(&pool.shared);
+            let Upload { key, buffer, .. } = upload;
+            let job_path = path.clone();
+            return pool.submit(
+                Box::new(move || {
+                    client
+                        .put(&key, &buffer, "application/octet-stream")
+                        .at(&job_path)?;
+                    shared
+                        .stored
+                        .fetch_add(buffer.len() as u64, Ordering::Relaxed);
+                    written.lock().unwrap().insert(key);
+                    Ok(())
+                }),
+                &path,
+            );
+        };
+        if !upload.buffer.is_empty() {
+            upload.send_part().at(&path)?;
+        }
+        let Some((_, sent)) = upload.multipart else {
+            unreachable!("a multipart upload was started");
+        };
+        drop(upload.parts.0);
+        let mut etags = BTreeMap::new();
+        for (number, result) in upload.parts.1.iter().take(sent as usize) {
+            etags.insert(number, result.at(&path)?);
+        }
+        // Fewer results than parts means the pool dropped some; it says why
+        if etags.len() != sent as usize {
+            pool.check(&path)?;
+            return Err(io::Error::other("a part was never uploaded")).at(&path);
+        }
+        let etags: Vec<String> = etags.into_values().collect();
+        self.client
+            .complete_upload(&upload.key, &id, &etags)
+            .at(&path)?;
+        self.uploads.lock().unwrap().remove(&upload.key);
+        self.written.lock().unwrap().insert(upload.key);
+        debug!("completed {} in {} parts", path.display(), sent);
+        Ok(())
+    }
+
+    fn pool(&self, path: &Path) -> Result<&Pool> {
+        let pool = self.pool.as_ref().ok_or_else(read_only).at(path)?;
+        pool.check(path)?;
+        Ok(pool)
+    }
+}
+
+impl Drop for S3Store {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.stop();
+        }
+    }
+}
+
+// The threads uploading chunks and parts, which the split hands them to through a
+// queue no longer than there are threads, so it can only get so far ahead.
+#[derive(Debug)]
+struct Pool {
+    sender: Option<SyncSender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    shared: Arc<Shared>,
+}
+
+type Job = Box<dyn FnOnce() -> Result<()> + Send>;
+// A part's number and its ETag, or why it didn't go up
+type Part = (u32, io::Result<String>);
+
+#[derive(Debug, Default)]
+struct Shared {
+    // The first upload that failed, which fails the split
+    failure: Mutex<Option<SplitterError>>,
+    stopped: AtomicBool,
+    stored: AtomicU64,
+}
+
+impl Pool {
+    fn start(threads: usize) -> Pool {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(threads);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let shared = Arc::new(Shared::default());
+        let workers = (0..threads)
+            .map(|_| {
+                let (receiver, shared) = (Arc::clone(&receiver), Arc::clone(&shared));
+                thread::spawn(move || {
+                    loop {
+                        // Let go of the queue before the upload, for the others
+                        let job = receiver.lock().unwrap().recv();
+                        let Ok(job) = job else {
+                            return;
+                        };
+                        if shared.stopped.load(Ordering::Relaxed) {
+                            continue;
+                        }
+                        if let Err(e) = job() {
+                            shared.stopped.store(true, Ordering::Relaxed);
+                            shared.failure.lock().unwrap().get_or_insert(e);
+                        }
+                    }
+                })
+            })
+            .collect();
+        Pool {
+            sender: Some(sender),
+            workers,
+            shared,
+        }
+    }
+
+    fn submit(&self, job: Job, path: &Path) -> Result<()> {
+        if let Some(sender) = &self.sender {
+            // Only fails once the workers are gone, which `check` then reports
+            let _ = sender.send(job);
+        }
+        self.check(path)
+    }
+
+    // The first failure, the first time after it happened, and later that there was one,
+    // against `path`.
+    fn check(&self, path: &Path) -> Result<()> {
+        if let Some(e) = self.shared.failure.lock().unwrap().take() {
+            return Err(e);
+        }
+        match self.shared.stopped.load(Ordering::Relaxed) {
+            true => Err(io::Error::other("an earlier upload failed")).at(path),
+            false => Ok(()),
+        }
+    }
+
+    fn drain(&mut self, path: &Path) -> Result<()> {
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        self.check(path)
+    }
+
+    fn stop(mut self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+        let _ = self.drain(Path::new(""));
+    }
+}
+
+// A chunk being written to an `S3Store`, compressed on the way when the store is.
+pub struct ObjectWriter {
+    encoder: Encoder,
+}
+
+enum Encoder {
+    Plain(Upload),
+    Gzip(Box<GzEncoder<Upload>>),
+    Armor(Box<ArmorEncoder<Upload>>),
+    Zstd(Box<ZstEncoder<Upload>>),
+}
+
+// What a chunk's writer uploads: the data gathered until it is a part, then, once
+// there is more than one part to it, the parts of a multipart upload as they fill.
+struct Upload {
+    client: Arc<Client>,
+    key: String,
+    path: PathBuf,
+    buffer: Vec<u8>,
+    part_size: usize,
+    // The upload's id and the parts handed to the pool so far, whose results come back
+    // on the channel
+    multipart: Option<(String, u32)>,
+    parts: (Sender<Part>, Receiver<Part>),
+    jobs: SyncSender<Job>,
+    shared: Arc<Shared>,
+    uploads: Arc<Mutex<BTreeMap<String, String>>>,
+}
+
+impl Upload {
+    fn send_part(&mut self) -> io::Result<()> {
+        let id = match &mut self.multipart {
+            Some((id, _)) => id.clone(),
+            None => {
+                let id = self.client.start_upload(&self.key)?;
+                debug!("started a multipart upload of {}", self.path.display());
+                let mut uploads = self.uploads.lock().unwrap();
+                uploads.insert(self.key.clone(), id.clone());
+                self.multipart = Some((id.clone(), 0));
+                id
+            }
+        };
+        let Some((_, sent)) = &mut self.multipart else {
+            unreachable!("the upload was just started");
+        };
+        *sent += 1;
+        let number = *sent;
+        let body = std::mem::replace(&mut self.buffer, Vec::with_capacity(self.part_size));
+        let (client, key, results) = (
+            Arc::clone(&self.client),
+            self.key.clone(),
+            self.parts.0.clone(),
+        );
+        let shared = Arc::clone(&self.shared);
+        let job: Job = Box::new(move || {
+            let result = client.upload_part(&key, &id, number, &body);
+            if result.is_ok() {
+                shared
+                    .stored
+                    .fetch_add(body.len() as u64, Ordering::Relaxed);
+            }
+            let _ = results.send((number, result));
+            Ok(())
+        });
+        self.jobs
+            .send(job)
+            .map_err(|_| io::Error::other("the uploads have stopped"))
+    }
+}
+
+impl Write for Upload {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.shared.stopped.load(Ordering::Relaxed) {
+            return Err(io::Error::other("an earlier upload failed"));
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // A full part is only sent once more follows, so that a chunk of exactly one
+        // part still goes up in a single PUT
+        if self.buffer.len() == self.part_size {
+            self.send_part()?;
+        }
+        let taken = buf.len().min(self.part_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..taken]);
+        Ok(taken)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Write for ObjectWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.encoder {
+            Encoder::Plain(upload) => upload.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Armor(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.encoder {
+            Encoder::Plain(upload) => upload.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Armor(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+// A chunk read back from an `S3Store`: the object as it downloads, continued from
+// where it broke off when the connection drops, as often as the client retries.
+struct Download {
+    client: Arc<Client>,
+    key: String,
+    etag: Option<String>,
+    position: u64,
+    response: Option<Response>,
+    failures: u32,
+}
+
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let response = match &mut self.response {
+                Some(response) => response,
+                None => self.response.insert(self.client.get(
+                    &self.key,
+                    self.position,
+                    self.etag.as_deref(),
+                )?),
+            };
+            let failure = match response.read(buf) {
+                Ok(read) => {
+                    self.position += read as u64;
+                    return Ok(read);
+                }
+                Err(e) if retryable(&e) && self.failures < self.client.retries => e,
+                Err(e) => return Err(e),
+            };
+            self.failures += 1;
+            self.response = None;
+            warn!(
+                "{}: {}; continuing from byte {}",
+                self.client.describe(&self.key),
+                failure,
+                self.position
+            );
+            pause(Duration::from_secs(1), &self.client.cancel)?;
+        }
+    }
+}
+
+impl ChunkStore for S3Store {
+    type Writer = ObjectWriter;
+    type Reader = Box<dyn Read + Send>;
+
+    fn create_chunk(&mut self, index: usize) -> Result<ObjectWriter> {
+        self.writer(index)
+    }
+
+    fn finish_chunk(&mut self, _index: usize, writer: ObjectWriter) -> Result<()> {
+        self.finish(writer, false)
+    }
+
+    fn open_chunk(&self, index: usize) -> Result<Self::Reader> {
+        let Some(name) = self.chunks.get(&index) else {
+            return Err(io::Error::from(io::ErrorKind::NotFound)).at(&self.chunk_path(index));
+        };
+        let download = Download {
+            client: Arc::clone(&self.client),
+            key: self.key(name),
+            etag: self.objects.get(name).map(|object| object.etag.clone()),
+            position: 0,
+            response: None,
+            failures: 0,
+        };
+        let capacity = DEFAULT_BUFFER_SIZE.min(1 << 20);
+        Ok(match self.chunk_compression(index) {
+            Compression::None => Box::new(download),
+            Compression::Gzip => {
+                Box::new(GzDecoder::new(BufReader::with_capacity(capacity, download)))
+            }
+            Compression::Armor => Box::new(ArmorDecoder::new(BufReader::with_capacity(
+                capacity, download,
+            ))),
+            Compression::Zstd { .. } => Box::new(
+                ZstDecoder::new(BufReader::with_capacity(capacity, download))
+                    .at(&self.chunk_path(index))?,
+            ),
+        })
+    }
+
+    // From the manifest, which knows the size of compressed chunks, or else the listing.
+    fn chunk_len(&self, index: usize) -> Result<u64> {
+        let path = self.chunk_path(index);
+        let Some(name) = self.chunks.get(&index) else {
+            return Err(io::Error::from(io::ErrorKind::NotFound)).at(&path);
+        };
+        let listed = self
+            .manifest
+            .as_ref()
+            .and_then(|manifest| manifest.chunks.iter().find(|entry| &entry.name == name));
+        match (listed, self.objects.get(name)) {
+            (Some(entry), _) => Ok(entry.size),
+            (None, Some(object)) if self.chunk_compression(index).is_none() => Ok(object.size),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "a compressed chunk without a manifest to give its size",
+            ))
+            .at(&path),
+        }
+    }
+
+    fn remove_chunk(&mut self, index: usize) -> Result<()> {
+        let path = self.chunk_path(index);
+        let name = chunk_name(index, self.compression);
+        let name = self.chunks.remove(&index).unwrap_or(name);
+        self.client.delete(&self.key(&name)).at(&path)?;
+        self.objects.remove(&name);
+        Ok(())
+    }
+
+    fn list_chunks(&self) -> Result<Vec<usize>> {
+        Ok(self.chunks.keys().copied().collect())
+    }
+
+    fn read_info(&self) -> Result<Option<Manifest>> {
+        Ok(self.manifest.clone())
+    }
+
+    // Once every chunk is up, which this waits for, so that the manifest only appears
+    // next to a whole set. Chunks left from an unfinished split that this one didn't
+    // write over are deleted then.
+    fn write_info(&mut self, manifest: &Manifest) -> Result<()> {
+        self.drain()?;
+        let path = self.object_path(MANIFEST_NAME);
+        let data = manifest.to_json().map_err(io::Error::other).at(&path)?;
+        self.client
+            .put(
+                &self.key(MANIFEST_NAME),
+                data.as_bytes(),
+                "application/json",
+            )
+            .at(&path)?;
+        let names: BTreeSet<&str> = manifest
+            .chunks
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        for name in self
+            .objects
+            .keys()
+            .filter(|name| !names.contains(name.as_str()))
+        {
+            debug!("deleting {}, left from an earlier split", name);
+            self.client
+                .delete(&self.key(name))
+                .at(&self.object_path(name))?;
+        }
+        self.manifest = Some(manifest.clone());
+        Ok(())
+    }
+
+    fn chunk_path(&self, index: usize) -> PathBuf {
+        match self.chunks.get(&index) {
+            Some(name) => self.object_path(name),
+            None => self.object_path(&chunk_name(index, self.compression)),
+        }
+    }
+
+    fn compression(&self) -> Compression {
+        self.compression
+    }
+}
+
+#[cfg(feature = "async")]
+impl SharedStore for S3Store {
+    fn create_shared(&self, index: usize) -> Result<ObjectWriter> {
+        self.writer(index)
+    }
+
+    fn finish_shared(&self, _index: usize, writer: ObjectWriter) -> Result<()> {
+        self.finish(writer, true)
+    }
+}
+
+// Wait `pause`, a little at a time so Ctrl+C isn't held up.
+pub(crate) fn pause(pause: Duration, cancel: &CancelToken) -> io::Result<()> {
+    let until = Instant::now() + pause;
+    while Instant::now() < until {
+        cancel.check_io()?;
+        thread::sleep(Duration::from_millis(100));
+    }
+    cancel.check_io()
+}
+
+// The error an unsuccessful response stands for, with the service's own code and
+// message from its body when it sent them. Statuses worth trying again come back as
+// `Interrupted`, as in `fetch`.
+fn service_error(response: &mut Response) -> io::Error {
+    let mut text = Vec::new();
+    let _ = response.take(MAX_TEXT).read_to_end(&mut text);
+    let text = String::from_utf8_lossy(&text);
+    let code = element(&text, "Code");
+    let kind = match (response.status, code) {
+        (_, Some("RequestTimeout" | "SlowDown" | "InternalError")) => io::ErrorKind::Interrupted,
+        (401 | 403, _) => io::ErrorKind::PermissionDenied,
+        (404, _) => io::ErrorKind::NotFound,
+        (408 | 429 | 500..=599, _) => io::ErrorKind::Interrupted,
+        _ => io::ErrorKind::Other,
+    };
+    let message = match (code, element(&text, "Message")) {
+        (Some(code), Some(message)) => format!("{}: {}", code, unescape(message)),
+        (Some(code), None) => code.to_string(),
+        _ => "the service refused".to_string(),
+    };
+    io::Error::new(kind, format!("{} ({})", message, response.status))
+}
+
+// The text inside the first `<name>` element of `xml`, for the little of S3's answers
+// that is needed; none of it nests an element in one of the same name.
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    elements(xml, name).next()
+}
+
+fn elements<'a>(xml: &'a str, name: &str) -> impl Iterator<Item = &'a str> {
+    let (open, close) = (format!("<{}>", name), format!("</{}>", name));
+    let mut rest = xml;
+    std::iter::from_fn(move || {
+        let start = rest.find(&open)? + open.len();
+        let end = start + rest[start..].find(&close)?;
+        let inner = &rest[start..end];
+        rest = &rest[end + close.len()..];
+        Some(inner)
+    })
+}
+
+fn unescape(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find('&') {
+        plain.push_str(&rest[..at]);
+        rest = &rest[at..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                plain.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                plain.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    plain.push_str(rest);
+    plain
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// A key percent-encoded as a path, keeping its slashes.
+fn encode_key(key: &str) -> String {
+    let segments: Vec<String> = key.split('/').map(encode).collect();
+    segments.join("/")
+}
+
+// What Signature Version 4 signs of a request: its `headers` lowercased, trimmed and
+// sorted by name, and `payload`, the hex SHA-256 of its body or UNSIGNED-PAYLOAD.
+struct CanonicalRequest<'a> {
+    method: &'a str,
+    path: &'a str,
+    query: &'a str,
+    headers: &'a [(String, String)],
+    payload: &'a str,
+}
+
+impl CanonicalRequest<'_> {
+    fn signed_headers(&self) -> String {
+        let names: Vec<&str> = self.headers.iter().map(|(name, _)| name.as_str()).collect();
+        names.join(";")
+    }
+
+    fn text(&self) -> String {
+        let headers: String = self
+            .headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect();
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            self.method,
+            self.path,
+            self.query,
+            headers,
+            self.signed_headers(),
+            self.payload
+        )
+    }
+
+    // The string to sign at `time`, YYYYMMDDTHHMMSSZ, for `service` in `region`, and
+    // its signature under `secret_key`.
+    fn sign(&self, time: &str, region: &str, service: &str, secret_key: &str) -> (String, String) {
+        let date = &time[..8];
+        let to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            time,
+            scope(date, region, service),
+            hex(&Sha256::digest(self.text().as_bytes()))
+        );
+        let secret = format!("AWS4{}", secret_key);
+        let mut signing_key = hmac(secret.as_bytes(), date.as_bytes());
+        for part in [region, service, "aws4_request"] {
+            signing_key = hmac(&signing_key, part.as_bytes());
+        }
+        let signature = hex(&hmac(&signing_key, to_sign.as_bytes()));
+        (to_sign, signature)
+    }
+}
+
+fn scope(date: &str, region: &str, service: &str) -> String {
+    format!("{}/{}/{}/aws4_request", date, region, service)
+}
+
+// The date and time of `now` in UTC, as YYYYMMDD and YYYYMMDDTHHMMSSZ.
+fn amz_date(now: SystemTime) -> (String, String) {
+    let seconds = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (days, time) = (seconds / 86_400, seconds % 86_400);
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let time = format!(
+        "{}T{:02}{:02}{:02}Z",
+        date,
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60
+    );
+    (date, time)
+}
+
+// HMAC-SHA256, which Signature Version 4 chains to derive its key, and which signs
+// exported manifests.
+pub(crate) fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes a key of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn read_only() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the store was opened for reading",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 4231's second case, and its sixth, with a key longer than a block.
+    #[test]
+    fn hmac_gives_the_rfcs_values() {
+        assert_eq!(
+            hex(&hmac(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&hmac(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    // Cases of AWS's Signature Version 4 test suite, signed as of 20150830T123600Z in
+    // us-east-1 for "service" with its example key; each request is sent with Host and
+    // X-Amz-Date, and no body. The -query case is the suite's request without a query
+    // string, and -query-order-key-case the one with two parameters given out of order.
+    #[test]
+    fn requests_sign_as_in_the_aws_test_suite() {
+        const EMPTY: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        let cases = [
+            (
+                "get-vanilla",
+                "GET",
+                "",
+                "bb579772317eb040ac9ed261061d46c1f17a8133879d6129b6e1c25292927e63",
+                "5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31",
+            ),
+            (
+                "get-vanilla-query",
+                "GET",
+                "",
+                "bb579772317eb040ac9ed261061d46c1f17a8133879d6129b6e1c25292927e63",
+                "5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31",
+            ),
+            (
+                "get-vanilla-query-order-key-case",
+                "GET",
+                "Param2=value2&Param1=value1",
+                "816cd5b414d056048ba4f7c5386d6e0533120fb1fcfa93762cf0fc39e2cf19e0",
+                "b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500",
+            ),
+            (
+                "post-header-key-case",
+                "POST",
+                "",
+                "553f88c9e4d10fc9e109e2aeb65f030801b70c2f6468faca261d401ae622fc87",
+                "5da7c1a2acd57cee7505fc6676e4e544621c30862966e37dddb68e92efbe5d6b",
+            ),
+        ];
+        for (name, method, query, hashed, expected) in cases {
+            // The headers and query as `send` puts them, from their names as given
+            let mut headers: Vec<(String, String)> = [
+                ("Host", "example.amazonaws.com"),
+                ("X-Amz-Date", "20150830T123600Z"),
+            ]
+            .iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+            headers.sort();
+            let mut query: Vec<&str> = query.split('&').filter(|p| !p.is_empty()).collect();
+            query.sort();
+            let query = query.join("&");
+            let request = CanonicalRequest {
+                method,
+                path: "/",
+                query: &query,
+                headers: &headers,
+                payload: EMPTY,
+            };
+            let canonical = format!(
+                "{}\n/\n{}\nhost:example.amazonaws.com\nx-amz-date:20150830T123600Z\n\n\
+                 host;x-amz-date\n{}",
+                method, query, EMPTY
+            );
+            assert_eq!(request.text(), canonical, "{}", name);
+            let (to_sign, signature) = request.sign(
+                "20150830T123600Z",
+                "us-east-1",
+                "service",
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            );
+            assert_eq!(
+                to_sign,
+                format!(
+                    "AWS4-HMAC-SHA256\n20150830T123600Z\n\
+                     20150830/us-east-1/service/aws4_request\n{}",
+                    hashed
+                ),
+                "{}",
+                name
+            );
+            assert_eq!(signature, expected, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+// Verifying only some of a set's chunks, for a quick check of a set too large to hash
+// through in the time there is. Which chunks are checked is drawn at random from those
+// there are hashes or checksums for, by a generator started from `seed`, so the same
+// seed checks the same chunks again for an audit to be repeated. However the sample is
+// limited, at least one chunk is checked. Which chunks are missing or misnamed is
+// found as by `verify`, from the directory listing; the parity, PAR2 and checksum
+// files, which would take reading everything they cover, are not looked at, nor is what
+// could be rebuilt from them.
+//
+// The report is a `VerifyReport` with `sample` saying what was checked, so nothing
+// reading one mistakes it for the outcome of a full verify.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::archive::is_archive;
+use crate::cancel::CancelToken;
+use crate::chunkset::ChunkSet;
+use crate::error::{Result, SplitterError};
+use crate::event::{ProgressEvent, Report};
+use crate::{VerifyReport, check_chunk, checkable, chunk_health, is_s3_url, is_sftp_url, lock};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SampleOptions {
+    // Of the chunks that can be checked, from above 0 to 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fraction: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<usize>,
+    // No chunk is started after this long, and without a fraction or count to stop at,
+    // chunks are checked until then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_time: Option<Duration>,
+    pub seed: u64,
+    // Go on with an info.json changed after it was sealed, with a warning
+    #[serde(default)]
+    pub accept_modified: bool,
+}
+
+// What `verify_sample` checked.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Sampled {
+    pub seed: u64,
+    // Chunks there were hashes or checksums for, which the sample was drawn from
+    pub checkable: usize,
+    // Indices of the chunks checked, in order
+    pub checked: Vec<usize>,
+    // What they hold, decoded
+    pub bytes: u64,
+    // `max_time` ran out before all the chunks the sample was to have were checked
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub timed_out: bool,
+}
+
+// Check a sample of the chunks in the local directory `directory`, as `options` says.
+pub fn verify_sample(
+    directory: &Path,
+    options: &SampleOptions,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<VerifyReport> {
+    if is_archive(directory) || is_s3_url(directory) || is_sftp_url(directory) {
+        return Err(SplitterError::InvalidOption {
+            field: "directory",
+            reason: "must be a local directory to verify a sample of",
+        });
+    }
+    if options
+        .fraction
+        .is_some_and(|fraction| !(fraction > 0.0 && fraction <= 1.0))
+    {
+        return Err(SplitterError::InvalidOption {
+            field: "fraction",
+            reason: "must be above 0 and at most 1",
+        });
+    }
+    if options.chunks == Some(0) {
+        return Err(SplitterError::InvalidOption {
+            field: "chunks",
+            reason: "must be at least 1",
+        });
+    }
+    let _lock = lock::acquire(directory, "verify")?;
+    let started = Instant::now();
+    let mut report = VerifyReport {
+        health: chunk_health(directory, options.accept_modified)?,
+        hashed: false,
+        mismatched: Vec::new(),
+        par2: false,
+        recoverability: None,
+        checksum_files: Vec::new(),
+        checksum_missing: Vec::new(),
+        unchecksummed: Vec::new(),
+        sample: None,
+    };
+    let set = match ChunkSet::open(directory, options.accept_modified) {
+        Ok(set) => Some(set),
+        Err(SplitterError::MissingChunks { .. }) => None,
+        Err(e) => return Err(e),
+    };
+    let algorithm = set.as_ref().and_then(ChunkSet::hash_algorithm);
+    report.hashed = algorithm.is_some();
+    let span = set
+        .as_ref()
+        .and_then(ChunkSet::manifest)
+        .and_then(|manifest| manifest.span.clone());
+    // Missing ones are in `health` already
+    let mut candidates: Vec<_> = set
+        .iter()
+        .flatten()
+        .filter(|chunk| chunk.present && checkable(chunk, algorithm))
+        .filter(|chunk| {
+            span.as_ref()
+                .is_none_or(|span| span.here().contains(&chunk.index))
+        })
+        .collect();
+    let total = candidates.len();
+    let wanted = match (options.chunks, options.fraction) {
+        (Some(chunks), _) => chunks,
+        (None, Some(fraction)) => (total as f64 * fraction).ceil() as usize,
+        (None, None) => total,
+    }
+    .clamp(1, total.max(1));
+
+    // A Fisher-Yates shuffle as far as the sample goes
+    let mut random = SplitMix64(options.seed);
+    for i in 0..wanted.min(total) {
+        let j = i + (random.next() % (total - i) as u64) as usize;
+        candidates.swap(i, j);
+    }
+    candidates.truncate(wanted);
+
+    let mut checked = Vec::new();
+    let mut bytes = 0;
+    let mut timed_out = false;
+    for chunk in candidates {
+        if !checked.is_empty()
+            && options
+                .max_time
+                .is_some_and(|limit| started.elapsed() >= limit)
+        {
+            timed_out = checked.len() < wanted;
+            break;
+        }
+        check_chunk(directory, chunk, algorithm, &mut report, progress, cancel)?;
+        checked.push(chunk.index);
+        bytes += chunk.len;
+    }
+    checked.sort_unstable();
+    info!(
+        "verified {} of {} chunks of {}: {} mismatched",
+        checked.len(),
+        total,
+        directory.display(),
+        report.mismatched.len()
+    );
+    report.sample = Some(Box::new(Sampled {
+        seed: options.seed,
+        checkable: total,
+        checked,
+        bytes,
+        timed_out,
+    }));
+    progress(ProgressEvent::Completed {
+        report: Report::Verify(report.clone()),
+    });
+    Ok(report)
+}
+
+// A small generator that gives the same numbers for the same seed everywhere, which
+// std's randomly keyed hasher doesn't.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
@@ -0,0 +1,139 @@
+// Where a reconstruction writes the output until it is complete, to put it in place in
+// one go: a hidden file beside the output by default, so that putting it in place is a
+// rename on the one file system, or in the directory `--temp-dir` names, as for a drive
+// with room where the output's has none to spare for two copies, as the options of what
+// writes it say.
+//
+// The file's name is the output's with random hex digits, as in `.disk.img.3f9a….part`,
+// and it is only ever created new, so that in a directory shared with others nothing can
+// be made to stand where it will be. A temporary file on another file system than the
+// output can't be renamed into place; it is copied beside the output first, to a hidden
+// file named the same way, and renamed from there, with a warning, as that writes the
+// whole file a second time. Either file is removed again when anything fails, as on
+// being cancelled. Names starting with a dot and ending in `.part` are no chunk's, so a
+// temporary file in a chunk directory is never taken for one.
+//
+// Packing writes the tar this way, and rechunking each new chunk, as `Staged` files that
+// are removed if they are dropped unfinished, so the temporary directory only needs room
+// for one chunk at a time.
+
+use std::fs::{self, File};
+use std::hash::{BuildHasher, RandomState};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+
+use crate::error::{PathContext, Result};
+
+// A new, empty temporary file, open for reading and writing, for what is to become
+// `output`, and where it is: in `temp_dir`, or beside `output` when None.
+pub(crate) fn create(output: &Path, temp_dir: Option<&Path>) -> Result<(File, PathBuf)> {
+    let beside = temp_dir.is_none();
+    let (file, path) = create_in(output, temp_dir, beside)?;
+    debug!("writing to {} until complete", path.display());
+    Ok((file, path))
+}
+
+// Put the complete temporary file at `temp` in place as `output`, removing it whatever
+// happens.
+pub(crate) fn persist(temp: &Path, output: &Path) -> Result<()> {
+    let error = match fs::rename(temp, output) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => e,
+        Err(e) => {
+            let _ = fs::remove_file(temp);
+            return Err(e).at(output);
+        }
+    };
+    warn!(
+        "{} is on another file system than {} ({}), so the file is copied across before it \
+         is put in place",
+        temp.display(),
+        output.display(),
+        error
+    );
+    let copied = create_in(output, None, true).and_then(|(file, across)| {
+        let put = fs::copy(temp, &across)
+            .and_then(|_| file.sync_all())
+            .doing("copying to", &across)
+            .and_then(|()| fs::rename(&across, output).at(output));
+        if put.is_err() {
+            let _ = fs::remove_file(&across);
+        }
+        put
+    });
+    let _ = fs::remove_file(temp);
+    copied
+}
+
+// A temporary file from `create` that is removed when dropped, unless it was put in place
+// with `persist` first.
+pub(crate) struct Staged(Option<PathBuf>);
+
+impl Staged {
+    // A new temporary file for `output`, as from `create`.
+    pub(crate) fn create(output: &Path, temp_dir: Option<&Path>) -> Result<(File, Staged)> {
+        let (file, path) = create(output, temp_dir)?;
+        Ok((file, Staged(Some(path))))
+    }
+
+    // `persist` the file as `output`.
+    pub(crate) fn persist(mut self, output: &Path) -> Result<()> {
+        match self.0.take() {
+            Some(temp) => persist(&temp, output),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        if let Some(temp) = self.0.take() {
+            let _ = fs::remove_file(temp);
+        }
+    }
+}
+
+// Whether `name` is that of a temporary file for `output`, or one that an older version
+// named `.OUTPUT.part` without the random digits.
+pub(crate) fn is_temp_of(name: &str, output: &str) -> bool {
+    let Some(rest) = name
+        .strip_prefix('.')
+        .and_then(|name| name.strip_prefix(output))
+        .and_then(|rest| rest.strip_suffix(".part"))
+    else {
+        return false;
+    };
+    rest.is_empty()
+        || rest.strip_prefix('.').is_some_and(|digits| {
+            digits.len() == 16 && digits.bytes().all(|b| b.is_ascii_hexdigit())
+        })
+}
+
+// A new file named for `output` in `directory`, or beside it with `beside`, trying
+// another name for as long as the one drawn is taken.
+fn create_in(output: &Path, directory: Option<&Path>, beside: bool) -> Result<(File, PathBuf)> {
+    let file_name = output.file_name().unwrap_or_default().to_string_lossy();
+    loop {
+        let name = format!(
+            ".{}.{:016x}.part",
+            file_name,
+            RandomState::new().hash_one(0u8)
+        );
+        let path = match (directory, beside) {
+            (Some(directory), false) => directory.join(name),
+            _ => output.with_file_name(name),
+        };
+        match File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => return Ok((file, path)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).doing("creating", &path),
+        }
+    }
+}
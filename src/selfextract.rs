@@ -0,0 +1,319 @@
+// A chunk set as one script that writes the original file back, for someone with no
+// tooling at all: a POSIX shell script, or a .cmd for Windows. The script holds the
+// file itself, decoded from the chunks, as base64 in lines of 64 characters, which
+// makes it about a third larger than the file. Run, it writes the file into the current
+// directory, never over one already there, and checks it against the SHA-256 of what
+// went in.
+//
+// The shell script gets the file out with `tail`, `sed` and `base64` (-d, or -D on
+// older macOS), or `openssl base64` when there is no `base64`, and checks it as JOIN.sh
+// does. The .cmd has certutil decode the text between its BEGIN and END lines, which
+// cmd itself jumps over, and check the result with `certutil -hashfile`.
+//
+// The script is written front to back as the chunks are read, so nothing holds more
+// than a buffer of the file at once. The SHA-256 is only known at the end, so it comes
+// after the base64: the shell script reads it from its last line, and the .cmd has it
+// in the commands it jumps to. The chunks aren't checked against their hashes on the
+// way: verify the set first for that.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::chunkset::ChunkSet;
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::{ProgressEvent, Report};
+use crate::join::{batch_quote, batch_safe, shell_check, shell_quote};
+use crate::manifest::HashAlgorithm;
+use crate::pack::PackReport;
+use crate::store::ChunkReader;
+use crate::{armor, default_output_name};
+
+// The largest file put in a script unless told otherwise; past it, a tar of the chunks
+// is the better way to send it
+pub const DEFAULT_SELF_EXTRACTING_MAX: u64 = 1 << 30;
+
+// Bytes per line of base64, which make 64 characters, as `openssl base64 -d` wants
+const LINE_BYTES: usize = 48;
+// Read from a chunk at a time, a whole number of lines
+const READ_LEN: usize = LINE_BYTES * 1024;
+
+// Where certutil finds what to decode
+const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const END: &str = "-----END CERTIFICATE-----";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Script {
+    // For sh, with LF line endings
+    Shell,
+    // For cmd on Windows, with CRLF line endings
+    Cmd,
+}
+
+impl Script {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Script::Shell => "sh",
+            Script::Cmd => "cmd",
+        }
+    }
+
+    fn line_end(self) -> &'static str {
+        match self {
+            Script::Shell => "\n",
+            Script::Cmd => "\r\n",
+        }
+    }
+}
+
+// Write the file the set in `directory` holds as a new script at `path`, which mustn't
+// exist yet, refusing a file larger than `max_size`. A script left unfinished by an
+// error or cancellation is removed. An info.json changed after it was sealed is refused
+// unless `accept_modified`.
+pub fn self_extracting(
+    directory: &Path,
+    path: &Path,
+    script: Script,
+    max_size: u64,
+    accept_modified: bool,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<PackReport> {
+    let file = File::create_new(path).at(path)?;
+    let mut output = io::BufWriter::new(file);
+    let written = self_extracting_into(
+        directory,
+        &mut output,
+        path,
+        script,
+        max_size,
+        accept_modified,
+        progress,
+        cancel,
+    )
+    .and_then(|report| {
+        let file = output.into_inner().map_err(|e| e.into_error()).at(path)?;
+        file.sync_all().at(path)?;
+        Ok(report)
+    });
+    match &written {
+        Err(_) => {
+            let _ = fs::remove_file(path);
+        }
+        #[cfg(unix)]
+        Ok(_) if script == Script::Shell => {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755)).at(path)?;
+        }
+        Ok(_) => {}
+    }
+    written
+}
+
+// As `self_extracting`, writing the script to `output`, e.g. standard output; `name` is
+// what errors writing it and the report call it.
+#[allow(clippy::too_many_arguments)]
+pub fn self_extracting_into(
+    directory: &Path,
+    output: &mut dyn Write,
+    name: &Path,
+    script: Script,
+    max_size: u64,
+    accept_modified: bool,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<PackReport> {
+    let set = ChunkSet::open(directory, accept_modified)?;
+    let missing: Vec<u64> = set
+        .iter()
+        .filter(|chunk| !chunk.present)
+        .map(|chunk| chunk.index as u64)
+        .collect();
+    if !missing.is_empty() {
+        return Err(SplitterError::MissingChunks { indices: missing });
+    }
+    let size = set.total_size();
+    if size > max_size {
+        return Err(SplitterError::InvalidOption {
+            field: "max_size",
+            reason: "is less than the file, which is better sent as a tar of the chunks than \
+                     in a script a third larger than it",
+        });
+    }
+    let file_name = match set.original_filename() {
+        Some(file_name) => file_name.to_string(),
+        None => default_output_name(directory, accept_modified)?,
+    };
+    if script == Script::Cmd && !batch_safe(&file_name) {
+        return Err(SplitterError::InvalidOption {
+            field: "windows",
+            reason: "can't be used for a file whose name has a \" or control character, \
+                     which Windows doesn't allow",
+        });
+    }
+    info!(
+        "writing {} ({} bytes) from {} as a self-extracting script to {}",
+        file_name,
+        size,
+        directory.display(),
+        name.display()
+    );
+
+    let end = script.line_end();
+    let header = match script {
+        Script::Shell => shell_header(&file_name),
+        Script::Cmd => cmd_header(&file_name),
+    };
+    output.write_all(header.as_bytes()).at(name)?;
+    let mut hasher = HashAlgorithm::Sha256.hasher();
+    let mut buffer = vec![0; READ_LEN];
+    let mut pending = Vec::with_capacity(READ_LEN + LINE_BYTES);
+    let mut text = Vec::new();
+    for chunk in set.iter() {
+        progress(ProgressEvent::ChunkStarted {
+            index: chunk.index,
+            size: chunk.len,
+        });
+        let mut reader = ChunkReader::open(&chunk.path, chunk.compression)
+            .doing("opening chunk", &chunk.path)?;
+        let mut read = 0;
+        loop {
+            cancel.check()?;
+            let len = reader.read(&mut buffer).at(&chunk.path)?;
+            if len == 0 {
+                break;
+            }
+            read += len as u64;
+            hasher.update(&buffer[..len]);
+            pending.extend_from_slice(&buffer[..len]);
+            let whole = pending.len() - pending.len() % LINE_BYTES;
+            text.clear();
+            for line in pending[..whole].chunks(LINE_BYTES) {
+                armor::encode(line, &mut text);
+                text.extend_from_slice(end.as_bytes());
+            }
+            output.write_all(&text).at(name)?;
+            pending.drain(..whole);
+            progress(ProgressEvent::BytesCopied { delta: len as u64 });
+        }
+        if read != chunk.len {
+            return Err(SplitterError::DecodedSize {
+                path: chunk.path.clone(),
+                expected: chunk.len,
+                actual: read,
+            });
+        }
+        progress(ProgressEvent::ChunkFinished {
+            index: chunk.index,
+            hash: None,
+        });
+    }
+    text.clear();
+    if !pending.is_empty() {
+        armor::encode(&pending, &mut text);
+        text.extend_from_slice(end.as_bytes());
+    }
+    output.write_all(&text).at(name)?;
+    let sha256 = hasher.finish();
+    let footer = match script {
+        Script::Shell => format!("{}\n", sha256),
+        Script::Cmd => cmd_footer(&file_name, &sha256),
+    };
+    output.write_all(footer.as_bytes()).at(name)?;
+    output.flush().at(name)?;
+
+    let report = PackReport {
+        directory: directory.to_path_buf(),
+        archive: name.to_path_buf(),
+        files: vec![file_name],
+        size,
+    };
+    progress(ProgressEvent::Completed {
+        report: Report::Pack(report.clone()),
+    });
+    Ok(report)
+}
+
+// What goes before the base64; after it is only the SHA-256, on a line of its own.
+fn shell_header(file_name: &str) -> String {
+    let mut script = String::from("#!/bin/sh\n");
+    script += "# Writes the file it holds into the current directory and checks it; run it\n";
+    script += "# with sh. It needs tail, sed and base64 or openssl, nothing else.\n";
+    script += "set -e\n";
+    script += &format!("output={}\n", shell_quote(file_name));
+    script += "if [ -e \"$output\" ]; then\n";
+    script += "    echo \"$output is already there; not writing over it\" >&2\n";
+    script += "    exit 1\n";
+    script += "fi\n";
+    script += "if printf 'QQ==\\n' | base64 -d >/dev/null 2>&1; then\n";
+    script += "    decode() { base64 -d; }\n";
+    script += "elif printf 'QQ==\\n' | base64 -D >/dev/null 2>&1; then\n";
+    script += "    decode() { base64 -D; }\n";
+    script += "elif command -v openssl >/dev/null 2>&1; then\n";
+    script += "    decode() { openssl base64 -d; }\n";
+    script += "else\n";
+    script += "    echo \"there is neither base64 nor openssl to decode the file with\" >&2\n";
+    script += "    exit 1\n";
+    script += "fi\n";
+    script += "expected=$(tail -n 1 \"$0\")\n";
+    script += "echo \"writing $output\"\n";
+    // The line the base64 starts on, put in once the header is complete; no file name
+    // holds a NUL to be mistaken for it
+    script += "if ! tail -n +\0 \"$0\" | sed '$d' | decode > \"$output\"; then\n";
+    script += "    rm -f \"$output\"\n";
+    script += "    echo \"the file could not be decoded: this script is damaged\" >&2\n";
+    script += "    exit 1\n";
+    script += "fi\n";
+    script += &shell_check("this script is damaged");
+    script += "exit 0\n";
+    script += "# What follows is the file, as base64, then its SHA-256\n";
+    let start = script.lines().count() + 1;
+    script.replacen('\0', &start.to_string(), 1)
+}
+
+fn cmd_header(file_name: &str) -> String {
+    let output = batch_quote(file_name);
+    let lines = [
+        "@echo off".to_string(),
+        // So that names outside ASCII read as the UTF-8 they are written in
+        "chcp 65001 >nul".to_string(),
+        "rem Writes the file it holds into the current directory and checks it.".to_string(),
+        "setlocal DisableDelayedExpansion".to_string(),
+        format!(
+            "if exist {0} (echo {0} is already there; not writing over it& pause& exit /b 1)",
+            output
+        ),
+        format!("echo writing {}", output),
+        format!(
+            "certutil -decode \"%~f0\" {0} >nul || (echo the file could not be decoded: this \
+             script is damaged& del {0} 2>nul& pause& exit /b 1)",
+            output
+        ),
+        "goto check".to_string(),
+        BEGIN.to_string(),
+    ];
+    lines.join("\r\n") + "\r\n"
+}
+
+// certutil stops at END; cmd goes on at the label.
+fn cmd_footer(file_name: &str, sha256: &str) -> String {
+    let output = batch_quote(file_name);
+    let lines = [
+        END.to_string(),
+        ":check".to_string(),
+        format!(
+            "certutil -hashfile {0} SHA256 | findstr /i /x {1} >nul || (echo the SHA-256 of \
+             {0} does not match: this script is damaged& pause& exit /b 1)",
+            output, sha256
+        ),
+        "echo done; the SHA-256 matches".to_string(),
+        "pause".to_string(),
+        "exit /b 0".to_string(),
+    ];
+    lines.join("\r\n") + "\r\n"
+}
@@ -0,0 +1,218 @@
+// `self-test`: a check of this build on a given file system, which unit tests run on
+// tmpfs say nothing about. A pseudo-random file from a fixed seed, so the same data
+// every time, is split with each of a few combinations of options and reconstructed,
+// and the result compared with the original byte for byte. Everything is written in a
+// scratch directory, removed afterwards whatever happened. `stress` does the same over
+// and over with options drawn at random.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Instant;
+
+use reconstruct_large_file::manifest::{Compression, HashAlgorithm};
+use reconstruct_large_file::{
+    CancelToken, DEFAULT_CHUNK_SIZE, ReconstructOptions, SplitOptions, SplitterError, reconstruct,
+    split_file,
+};
+
+use crate::bench::generate;
+use crate::{format_size, free_space};
+
+pub struct SelfTestOptions {
+    pub directory: PathBuf,
+    pub size: u64,
+    pub seed: u64,
+    pub threads: usize,
+}
+
+// One combination of options tried.
+struct Case {
+    name: &'static str,
+    chunk_size: u64,
+    threads: Option<usize>,
+    hash: Option<HashAlgorithm>,
+    compression: Compression,
+}
+
+const CASES: &[Case] = &[
+    Case {
+        name: "default options",
+        chunk_size: DEFAULT_CHUNK_SIZE,
+        threads: None,
+        hash: None,
+        compression: Compression::None,
+    },
+    Case {
+        name: "64 KiB chunks",
+        chunk_size: 64 << 10,
+        threads: None,
+        hash: None,
+        compression: Compression::None,
+    },
+    Case {
+        name: "SHA-256 hashes",
+        chunk_size: DEFAULT_CHUNK_SIZE,
+        threads: None,
+        hash: Some(HashAlgorithm::Sha256),
+        compression: Compression::None,
+    },
+    Case {
+        name: "gzip compression",
+        chunk_size: DEFAULT_CHUNK_SIZE,
+        threads: None,
+        hash: None,
+        compression: Compression::Gzip,
+    },
+    Case {
+        name: "one thread",
+        chunk_size: 1 << 20,
+        threads: Some(1),
+        hash: None,
+        compression: Compression::None,
+    },
+];
+
+// Run every case, printing how each went. Returns whether they all passed; an error
+// is for the test itself failing, such as the scratch directory not being writable.
+pub fn run(options: &SelfTestOptions, cancel: &CancelToken) -> io::Result<bool> {
+    // The source, a set of chunks and its reconstruction exist at the same time
+    let needed = options.size.saturating_mul(3);
+    if let Some(available) = free_space(&options.directory)
+        && available < needed
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::StorageFull,
+            format!(
+                "the self-test needs {} free but only {} is available",
+                format_size(needed),
+                format_size(available)
+            ),
+        ));
+    }
+    let scratch = options
+        .directory
+        .join(format!(".file_splitter-self-test-{}", process::id()));
+    fs::create_dir(&scratch)?;
+    let result = run_cases(options, &scratch, cancel);
+    let _ = fs::remove_dir_all(&scratch);
+    result
+}
+
+fn run_cases(options: &SelfTestOptions, scratch: &Path, cancel: &CancelToken) -> io::Result<bool> {
+    let source = scratch.join("source");
+    println!(
+        "Generating {} of test data (seed {}) in {}",
+        format_size(options.size),
+        options.seed,
+        options.directory.display()
+    );
+    generate(&source, options.size, false, options.seed)?;
+    let mut failed = 0;
+    for case in CASES {
+        let chunks = scratch.join("chunks");
+        let started = Instant::now();
+        let outcome = run_case(options, case, &source, &chunks, cancel);
+        if cancel.is_cancelled() {
+            return Err(SplitterError::Cancelled.into());
+        }
+        let elapsed = started.elapsed().as_secs_f64();
+        match outcome {
+            Ok(()) => println!("PASS  {:<20} {:>8.2} s", case.name, elapsed),
+            Err(e) => {
+                failed += 1;
+                println!("FAIL  {:<20} {}", case.name, e);
+            }
+        }
+        if chunks.exists() {
+            fs::remove_dir_all(&chunks)?;
+        }
+    }
+    match failed {
+        0 => println!("All {} combinations passed.", CASES.len()),
+        _ => println!("{} of {} combinations failed.", failed, CASES.len()),
+    }
+    Ok(failed == 0)
+}
+
+fn run_case(
+    options: &SelfTestOptions,
+    case: &Case,
+    source: &Path,
+    chunks: &Path,
+    cancel: &CancelToken,
+) -> io::Result<()> {
+    let threads = case.threads.unwrap_or(options.threads);
+    let split = SplitOptions::builder(source, chunks)
+        .chunk_size(case.chunk_size)
+        .threads(threads)
+        .hash(case.hash)
+        .compression(case.compression)
+        // Random data doesn't compress, and would otherwise be stored as it is
+        .min_ratio(0.0)
+        .build()?;
+    split_file(&split, &mut |_| {}, cancel)?;
+    let rebuild = ReconstructOptions {
+        output: Some("reconstructed".to_string()),
+        threads,
+        ..ReconstructOptions::new(chunks)
+    };
+    reconstruct(&rebuild, &mut |_| {}, cancel)?;
+    compare(source, &chunks.join("reconstructed"))
+}
+
+// Fails if the files are of different lengths, or at the first byte where they differ.
+pub(crate) fn compare(expected: &Path, actual: &Path) -> io::Result<()> {
+    let (want, got) = (fs::metadata(expected)?.len(), fs::metadata(actual)?.len());
+    if want != got {
+        return Err(mismatch(format!(
+            "the reconstruction is {} bytes where the original is {}",
+            got, want
+        )));
+    }
+    let mut expected = BufReader::new(File::open(expected)?);
+    let mut actual = BufReader::new(File::open(actual)?);
+    let (mut left, mut right) = (vec![0; 64 << 10], vec![0; 64 << 10]);
+    let mut offset = 0u64;
+    loop {
+        let len = read_full(&mut expected, &mut left)?;
+        let got = read_full(&mut actual, &mut right)?;
+        let same = len.min(got);
+        if let Some(at) = left[..same]
+            .iter()
+            .zip(&right[..same])
+            .position(|(a, b)| a != b)
+        {
+            return Err(mismatch(format!(
+                "the reconstruction differs from the original at byte {}",
+                offset + at as u64
+            )));
+        }
+        if len != got {
+            return Err(mismatch(
+                "the reconstruction changed length while it was compared".to_string(),
+            ));
+        }
+        if len == 0 {
+            return Ok(());
+        }
+        offset += len as u64;
+    }
+}
+
+// As much of `buffer` as the reader fills before it ends.
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+fn mismatch(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,733 @@
+// `serve`: a small read-only HTTP server, so a chunk set can be fetched from another
+// machine without setting up a share. It serves info.json, the chunks, and the parity
+// and PAR2 files next to them, by name, plus an HTML index for browsers; nothing else
+// in the directory can be asked for. Sets without an info.json get one made up from
+// their chunks, so a client always has a manifest to go by.
+//
+// Each connection gets a thread and one request, answered with `Connection: close`.
+// Files get an ETag, from the recorded hash where there is one, and single byte ranges
+// are honoured so an interrupted download can pick up where it stopped. Ctrl+C stops
+// accepting connections, cuts off the ones still open and waits for their threads.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use reconstruct_large_file::size::format_size;
+use reconstruct_large_file::{
+    CancelToken, ChunkEntry, ChunkSet, Compression, MANIFEST_NAME, MANIFEST_VERSION, Manifest,
+    default_output_name,
+};
+
+// Connections served at once; more are turned away with 503 rather than queued
+const MAX_CONNECTIONS: usize = 64;
+// Longest request line and headers taken
+const MAX_HEAD: u64 = 16 * 1024;
+// How long a client may take to send its request, or to take the next piece of a
+// response, before it is dropped
+const TIMEOUT: Duration = Duration::from_secs(30);
+// How often the accept loop looks for Ctrl+C
+const POLL: Duration = Duration::from_millis(100);
+const PIECE: usize = 256 * 1024;
+
+pub struct ServeOptions {
+    pub directory: PathBuf,
+    pub address: IpAddr,
+    pub port: u16,
+}
+
+// A file that can be asked for, by the name in its URL.
+struct Served {
+    path: PathBuf,
+    content_type: &'static str,
+    // From the recorded hash; files without one get an ETag from their size and age
+    etag: Option<String>,
+}
+
+struct Server {
+    files: BTreeMap<String, Served>,
+    // For sets without an info.json of their own, or with one that lists no chunks
+    manifest: Option<String>,
+    index: String,
+    // Every open connection, so Ctrl+C can cut them off
+    connections: Mutex<HashMap<u64, TcpStream>>,
+    next_connection: AtomicU64,
+    active: AtomicUsize,
+}
+
+// Serve `set`, which is in `options.directory`, until `cancel` is set.
+pub fn run(options: &ServeOptions, set: &ChunkSet, cancel: &CancelToken) -> io::Result<()> {
+    let server = Server::new(&options.directory, set)?;
+    let listener = TcpListener::bind((options.address, options.port))?;
+    listener.set_nonblocking(true)?;
+    let address = listener.local_addr()?;
+    println!(
+        "Serving {} ({} chunks, {}) at http://{}/",
+        options.directory.display(),
+        set.len(),
+        format_size(set.total_size()),
+        address
+    );
+    if address.ip().is_unspecified() {
+        println!("That is every address of this machine; use --address to pick one.");
+    }
+    println!("Press Ctrl+C to stop.");
+    thread::scope(|scope| {
+        while !cancel.is_cancelled() {
+            let (stream, peer) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(POLL);
+                    continue;
+                }
+                // A client that gave up before it was accepted, or a shortage of file
+                // handles, which passes once connections close
+                Err(e) => {
+                    eprintln!("Cannot accept a connection: {}", e);
+                    thread::sleep(POLL);
+                    continue;
+                }
+            };
+            if server.active.load(Ordering::Relaxed) >= MAX_CONNECTIONS {
+                let _ = busy(stream, peer);
+                continue;
+            }
+            server.active.fetch_add(1, Ordering::Relaxed);
+            let server = &server;
+            scope.spawn(move || {
+                if let Err(e) = server.connection(stream, peer, cancel) {
+                    log::debug!("connection from {} failed: {}", peer, e);
+                }
+                server.active.fetch_sub(1, Ordering::Relaxed);
+            });
+        }
+        for stream in server.connections.lock().unwrap().values() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    });
+    println!("Stopped.");
+    Ok(())
+}
+
+impl Server {
+    fn new(directory: &Path, set: &ChunkSet) -> io::Result<Server> {
+        let mut files = BTreeMap::new();
+        let etag = |compression: Compression, hash: &Option<String>| {
+            // The hash is of the chunk's original bytes, which a compressed file only
+            // stands for, so it can only make a weak ETag for it
+            hash.as_ref().map(|hash| match compression.is_none() {
+                true => format!("\"{}\"", hash),
+                false => format!("W/\"{}\"", hash),
+            })
+        };
+        for chunk in set {
+            // With its shard subdirectory, if it is in one, as the manifest names it
+            let relative = chunk.path.strip_prefix(directory).unwrap_or(&chunk.path);
+            let parts: Vec<_> = relative.iter().map(|part| part.to_string_lossy()).collect();
+            let served = Served {
+                path: chunk.path.clone(),
+                content_type: content_type(&chunk.path),
+                etag: etag(chunk.compression, &chunk.hash),
+            };
+            files.insert(parts.join("/"), served);
+        }
+        let listed = set.manifest().is_some_and(|m| !m.chunks.is_empty());
+        if listed {
+            files.insert(
+                MANIFEST_NAME.to_string(),
+                Served {
+                    path: directory.join(MANIFEST_NAME),
+                    content_type: "application/json",
+                    etag: None,
+                },
+            );
+        }
+        // Parity hashes are of the files as stored
+        let parity = set.manifest().and_then(|m| m.parity.as_ref());
+        for entry in parity.iter().flat_map(|info| &info.files) {
+            let served = Served {
+                path: directory.join(&entry.name),
+                content_type: "application/octet-stream",
+                etag: etag(Compression::None, &entry.hash),
+            };
+            files.insert(entry.name.clone(), served);
+        }
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("par2"))
+                && path.is_file()
+            {
+                let name = path.file_name().unwrap_or_default();
+                let name = name.to_string_lossy().into_owned();
+                let served = Served {
+                    path,
+                    content_type: "application/octet-stream",
+                    etag: None,
+                };
+                files.entry(name).or_insert(served);
+            }
+        }
+
+        let manifest = match listed {
+            true => None,
+            false => Some(
+                serde_json::to_string_pretty(&listing(directory, set)).map_err(io::Error::other)?,
+            ),
+        };
+        let index = index_page(directory, set, &files, manifest.is_some());
+        Ok(Server {
+            files,
+            manifest,
+            index,
+            connections: Mutex::new(HashMap::new()),
+            next_connection: AtomicU64::new(0),
+            active: AtomicUsize::new(0),
+        })
+    }
+
+    fn connection(
+        &self,
+        stream: TcpStream,
+        peer: SocketAddr,
+        cancel: &CancelToken,
+    ) -> io::Result<()> {
+        // Accepted connections can inherit the listener's non-blocking mode
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let id = self.next_connection.fetch_add(1, Ordering::Relaxed);
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(id, stream.try_clone()?);
+        let result = self.exchange(stream, peer, cancel);
+        self.connections.lock().unwrap().remove(&id);
+        result
+    }
+
+    fn exchange(
+        &self,
+        mut stream: TcpStream,
+        peer: SocketAddr,
+        cancel: &CancelToken,
+    ) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let (request, response) = match read_request(&mut reader) {
+            Ok(Some(request)) => {
+                let response = self.respond(&request);
+                (request, response)
+            }
+            // Closed without asking anything
+            Ok(None) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                let request = Request {
+                    method: "-".to_string(),
+                    target: "-".to_string(),
+                    headers: Vec::new(),
+                };
+                (request, Response::text(400, "Bad request\n"))
+            }
+            Err(e) => return Err(e),
+        };
+        let status = response.status;
+        let result = response.send(&mut stream, request.method == "HEAD", cancel);
+        let sent = match &result {
+            Ok(sent) => sent.to_string(),
+            Err(_) => "-".to_string(),
+        };
+        println!(
+            "{} \"{} {}\" {} {}",
+            peer.ip(),
+            request.method,
+            request.target,
+            status,
+            sent
+        );
+        result.map(|_| ())
+    }
+
+    fn respond(&self, request: &Request) -> Response {
+        if request.method != "GET" && request.method != "HEAD" {
+            let mut response = Response::text(405, "Only GET and HEAD are served\n");
+            response.headers.push(("Allow", "GET, HEAD".to_string()));
+            return response;
+        }
+        let Some(name) = decode_path(&request.target) else {
+            return Response::text(400, "Bad request\n");
+        };
+        match name.as_str() {
+            "" | "index.html" => {
+                return Response::body(200, "text/html; charset=utf-8", self.index.clone());
+            }
+            MANIFEST_NAME if self.manifest.is_some() => {
+                let manifest = self.manifest.clone().unwrap_or_default();
+                return Response::body(200, "application/json", manifest);
+            }
+            _ => {}
+        }
+        let Some(served) = self.files.get(&name) else {
+            return Response::text(404, "Not found\n");
+        };
+        match file_response(served, request) {
+            Ok(response) => response,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Response::text(404, "Not found\n"),
+            Err(e) => {
+                eprintln!("Cannot read {}: {}", served.path.display(), e);
+                Response::text(500, "The file cannot be read\n")
+            }
+        }
+    }
+}
+
+// A manifest for a set that hasn't one listing its chunks, from what was found of them.
+fn listing(directory: &Path, set: &ChunkSet) -> Manifest {
+    let original_filename = match set.manifest() {
+        Some(manifest) => manifest.original_filename.clone(),
+        None => default_output_name(directory, crate::globals().accept_modified)
+            .unwrap_or_else(|_| "reconstructed".to_string()),
+    };
+    let chunks = set
+        .iter()
+        .map(|chunk| ChunkEntry {
+            name: chunk
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            size: chunk.len,
+            hash: None,
+            stored_hash: chunk.stored_hash.clone(),
+            compression: (chunk.compression != set.compression()).then_some(chunk.compression),
+            same_as: None,
+            shared: None,
+            mtime: None,
+        })
+        .collect();
+    Manifest {
+        version: MANIFEST_VERSION,
+        original_filename,
+        name_form: set.manifest().and_then(|m| m.name_form),
+        link_name: set.manifest().and_then(|m| m.link_name.clone()),
+        range: set.manifest().and_then(|m| m.range),
+        xattrs: set.manifest().map(|m| m.xattrs.clone()).unwrap_or_default(),
+        owner: set.manifest().and_then(|m| m.owner.clone()),
+        created: None,
+        chunk_size: set.manifest().and_then(|m| m.chunk_size),
+        hash: None,
+        compression: set.compression(),
+        encryption: set.manifest().and_then(|m| m.encryption.clone()),
+        metadata: set.manifest().and_then(|m| m.metadata.clone()),
+        random_names: false,
+        compat: set.manifest().and_then(|m| m.compat),
+        shards: None,
+        parity: None,
+        span: None,
+        chunks,
+    }
+}
+
+fn index_page(
+    directory: &Path,
+    set: &ChunkSet,
+    files: &BTreeMap<String, Served>,
+    made_up: bool,
+) -> String {
+    let title = set
+        .original_filename()
+        .map_or_else(|| directory.display().to_string(), |name| name.to_string());
+    let mut page = String::new();
+    let _ = write!(
+        page,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head>\n\
+         <body>\n<h1>{0}</h1>\n<p>{1} chunks, {2} in all. ",
+        escape(&title),
+        set.len(),
+        format_size(set.total_size())
+    );
+    page.push_str("Download them into one directory to reconstruct it.</p>\n<ul>\n");
+    let mut link = |name: &str, note: String| {
+        let _ = writeln!(
+            page,
+            "<li><a href=\"{0}\">{1}</a>{2}</li>",
+            encode_path(name),
+            escape(name),
+            note
+        );
+    };
+    if made_up || files.contains_key(MANIFEST_NAME) {
+        link(MANIFEST_NAME, String::new());
+    }
+    for chunk in set {
+        let name = chunk.path.file_name().unwrap_or_default().to_string_lossy();
+        link(&name, format!(" ({})", format_size(chunk.len)));
+    }
+    for name in files.keys() {
+        let chunk = set
+            .iter()
+            .any(|chunk| chunk.path.file_name() == Some(name.as_ref()));
+        if !chunk && name != MANIFEST_NAME {
+            link(name, String::new());
+        }
+    }
+    page.push_str("</ul>\n</body></html>\n");
+    page
+}
+
+struct Request {
+    method: String,
+    target: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// The request line and headers; None when the client closed without sending any.
+fn read_request(reader: &mut impl BufRead) -> io::Result<Option<Request>> {
+    let mut head = reader.take(MAX_HEAD);
+    let mut lines = Vec::new();
+    loop {
+        let mut line = Vec::new();
+        if head.read_until(b'\n', &mut line)? == 0 {
+            if lines.is_empty() && line.is_empty() {
+                return Ok(None);
+            }
+            return Err(invalid("the request ends before its headers do"));
+        }
+        let line = String::from_utf8(line).map_err(|_| invalid("a header isn't text"))?;
+        let line = line.trim_end_matches(['\r', '\n']).to_string();
+        if line.is_empty() {
+            if lines.is_empty() {
+                // Blank lines before the request line are allowed
+                continue;
+            }
+            break;
+        }
+        lines.push(line);
+    }
+    let mut words = lines[0].split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (words.next(), words.next(), words.next())
+    else {
+        return Err(invalid("malformed request line"));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(invalid("not an HTTP/1 request"));
+    }
+    let mut headers = Vec::new();
+    for line in &lines[1..] {
+        let Some((key, value)) = line.split_once(':') else {
+            return Err(invalid("malformed header"));
+        };
+        headers.push((key.trim().to_string(), value.trim().to_string()));
+    }
+    Ok(Some(Request {
+        method: method.to_string(),
+        target: target.to_string(),
+        headers,
+    }))
+}
+
+// The file name a request target asks for: its path without the leading slash or any
+// query, percent-decoded. None for one that can't be a file name of ours.
+fn decode_path(target: &str) -> Option<String> {
+    let path = target.split(['?', '#']).next()?.strip_prefix('/')?;
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&b, after)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(after.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &after[2..];
+        } else {
+            bytes.push(b);
+            rest = after;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+// `name` for use in a link: everything but unreserved characters percent-encoded.
+fn encode_path(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for &b in name.as_bytes() {
+        match b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            true => encoded.push(char::from(b)),
+            false => {
+                let _ = write!(encoded, "%{:02X}", b);
+            }
+        }
+    }
+    encoded
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("gz") => "application/gzip",
+        Some("txt") => "text/plain; charset=us-ascii",
+        _ => "application/octet-stream",
+    }
+}
+
+enum Body {
+    Empty,
+    Bytes(Vec<u8>),
+    // `len` bytes of the file from `start`
+    File { file: File, start: u64, len: u64 },
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Body,
+}
+
+impl Response {
+    fn body(status: u16, content_type: &'static str, body: String) -> Response {
+        Response {
+            status,
+            headers: vec![("Content-Type", content_type.to_string())],
+            body: Body::Bytes(body.into_bytes()),
+        }
+    }
+
+    fn text(status: u16, text: &str) -> Response {
+        Response::body(status, "text/plain; charset=utf-8", text.to_string())
+    }
+
+    fn len(&self) -> u64 {
+        match &self.body {
+            Body::Empty => 0,
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File { len, .. } => *len,
+        }
+    }
+
+    // Write the response, leaving out the body for HEAD; how many body bytes went out.
+    fn send(
+        self,
+        stream: &mut TcpStream,
+        head_only: bool,
+        cancel: &CancelToken,
+    ) -> io::Result<u64> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+        let _ = write!(
+            head,
+            "Server: reconstruct_large_file/{}\r\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        for (name, value) in &self.headers {
+            let _ = write!(head, "{}: {}\r\n", name, value);
+        }
+        if self.status != 304 {
+            let _ = write!(head, "Content-Length: {}\r\n", self.len());
+        }
+        head.push_str("Connection: close\r\n\r\n");
+        stream.write_all(head.as_bytes())?;
+        if head_only {
+            return Ok(0);
+        }
+        let sent = match self.body {
+            Body::Empty => 0,
+            Body::Bytes(bytes) => {
+                stream.write_all(&bytes)?;
+                bytes.len() as u64
+            }
+            Body::File {
+                mut file,
+                start,
+                len,
+            } => {
+                file.seek(SeekFrom::Start(start))?;
+                let mut file = file.take(len);
+                let mut buffer = vec![0; PIECE];
+                let mut sent = 0;
+                while sent < len {
+                    if cancel.is_cancelled() {
+                        return Err(io::Error::from(io::ErrorKind::Interrupted));
+                    }
+                    let read = file.read(&mut buffer)?;
+                    if read == 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "the file got shorter while being sent",
+                        ));
+                    }
+                    stream.write_all(&buffer[..read])?;
+                    sent += read as u64;
+                }
+                sent
+            }
+        };
+        stream.flush()?;
+        Ok(sent)
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        416 => "Range Not Satisfiable",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+// The file, or the part of it the Range header asks for, unless the client's copy
+// is current.
+fn file_response(served: &Served, request: &Request) -> io::Result<Response> {
+    let file = File::open(&served.path)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return Err(io::Error::from(io::ErrorKind::NotFound));
+    }
+    let len = metadata.len();
+    let etag = served.etag.clone().unwrap_or_else(|| {
+        let modified = metadata.modified().ok();
+        let age = modified.and_then(|m| m.duration_since(UNIX_EPOCH).ok());
+        format!("W/\"{:x}-{:x}\"", len, age.map_or(0, |age| age.as_secs()))
+    });
+    let mut headers = vec![
+        ("ETag", etag.clone()),
+        ("Accept-Ranges", "bytes".to_string()),
+    ];
+    if let Some(tags) = request.header("If-None-Match")
+        && tags
+            .split(',')
+            .any(|tag| tag.trim() == "*" || weak_match(tag, &etag))
+    {
+        return Ok(Response {
+            status: 304,
+            headers,
+            body: Body::Empty,
+        });
+    }
+    // A range is only for the copy the client already has part of, which a weak ETag
+    // can't vouch for
+    let current = request
+        .header("If-Range")
+        .is_none_or(|tag| !etag.starts_with("W/") && tag.trim() == etag);
+    let wanted = match request.header("Range") {
+        Some(range) if current => byte_range(range, len),
+        _ => Wanted::Whole,
+    };
+    headers.push(("Content-Type", served.content_type.to_string()));
+    let (status, start, part) = match wanted {
+        Wanted::Whole => (200, 0, len),
+        Wanted::Part(start, part) => {
+            let end = start + part - 1;
+            headers.push(("Content-Range", format!("bytes {}-{}/{}", start, end, len)));
+            (206, start, part)
+        }
+        Wanted::Unsatisfiable => {
+            headers.push(("Content-Range", format!("bytes */{}", len)));
+            return Ok(Response {
+                status: 416,
+                headers,
+                body: Body::Empty,
+            });
+        }
+    };
+    Ok(Response {
+        status,
+        headers,
+        body: Body::File {
+            file,
+            start,
+            len: part,
+        },
+    })
+}
+
+fn weak_match(tag: &str, etag: &str) -> bool {
+    let strip = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    strip(tag) == strip(etag)
+}
+
+enum Wanted {
+    Whole,
+    // Start and length
+    Part(u64, u64),
+    Unsatisfiable,
+}
+
+// What a `Range: bytes=…` header asks for of a file of `len` bytes. A header that
+// can't be parsed is ignored, as HTTP has it, and so are several ranges at once,
+// which are answered with the whole file.
+fn byte_range(header: &str, len: u64) -> Wanted {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return Wanted::Whole;
+    };
+    let Some((first, last)) = spec.split_once('-') else {
+        return Wanted::Whole;
+    };
+    if spec.contains(',') {
+        return Wanted::Whole;
+    }
+    let (first, last) = (first.trim(), last.trim());
+    if first.is_empty() {
+        // The last so many bytes
+        return match last.parse::<u64>() {
+            Ok(0) => Wanted::Unsatisfiable,
+            Ok(_) if len == 0 => Wanted::Unsatisfiable,
+            Ok(suffix) => Wanted::Part(len - suffix.min(len), suffix.min(len)),
+            Err(_) => Wanted::Whole,
+        };
+    }
+    let Ok(first) = first.parse::<u64>() else {
+        return Wanted::Whole;
+    };
+    let end = match last {
+        "" => len,
+        last => match last.parse::<u64>() {
+            Ok(last) if last >= first => last.saturating_add(1).min(len),
+            _ => return Wanted::Whole,
+        },
+    };
+    match first < len {
+        true => Wanted::Part(first, end - first),
+        false => Wanted::Unsatisfiable,
+    }
+}
+
+// Turned away when `MAX_CONNECTIONS` are already being served.
+fn busy(mut stream: TcpStream, peer: SocketAddr) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut response = Response::text(503, "Too many connections; try again shortly\n");
+    response.headers.push(("Retry-After", "5".to_string()));
+    response.send(&mut stream, false, &CancelToken::new())?;
+    println!("{} \"-\" 503 -", peer.ip());
+    Ok(())
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
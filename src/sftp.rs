@@ -0,0 +1,1256 @@
+// Chunk sets on a server reached over SSH: `sftp://[user@]host[:port]/path` is a
+// directory there holding `chunk000`, … and `info.json`, as a local one would. The
+// connection is made with libssh2, through the ssh2 crate, and logs in as ssh does by
+// default: with the keys the agent holds, then ~/.ssh/id_ed25519, id_ecdsa and id_rsa,
+// and with a key's passphrase or a password only when there is a terminal to ask on.
+// ~/.ssh/config isn't read, so the user and port go in the URL, the user being the
+// local one otherwise. The server's key must be the one ~/.ssh/known_hosts has for it,
+// unless `insecure_skip_hostkey` says otherwise; nothing is added there. libssh2 keeps
+// the reads and writes of 32 KiB that make up what it is handed at once in flight, so
+// the round trip doesn't set the pace, and it is handed `window` of them.
+//
+// The path after the host is absolute, and one under `/~/` is in the login directory,
+// as curl has them. When the connection drops partway through a chunk, the chunk
+// carries on over a new one from where the server got to, as often as `retries`
+// allows; writes are held until the server acknowledges them for this. As with a local
+// directory, the destination must be empty or not exist yet on the server, and
+// `info.json` goes last. A split that fails removes what it wrote, and the directories
+// it made, unless told to keep them.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use ssh2::{
+    CheckResult, ErrorCode, File, FileStat, KeyboardInteractivePrompt, KnownHostFileKind,
+    MethodType, OpenFlags, OpenType, Prompt, Sftp,
+};
+
+use crate::armor::{ArmorDecoder, ArmorEncoder};
+use crate::cancel::CancelToken;
+#[cfg(feature = "async")]
+use crate::engine::SharedStore;
+use crate::error::{PathContext, Result, SplitterError};
+use crate::gzip::{GzDecoder, GzEncoder};
+use crate::manifest::{Compression, MANIFEST_NAME, Manifest};
+use crate::pipeline::DEFAULT_BUFFER_SIZE;
+use crate::s3::pause;
+use crate::store::{ChunkStore, chunk_name};
+use crate::zst::{ZstDecoder, ZstEncoder};
+use crate::{SFTP_SCHEME, chunk_index};
+
+// Most data in one read or write, which every server has to take
+const BLOCK: usize = 32 * 1024;
+const PORT: u16 = 22;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+// A server that answers nothing for this long, in milliseconds, is given up on, so
+// that the chunk can carry on over another connection
+const TIMEOUT: u32 = 60_000;
+// Longest wait before connecting again
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// The kinds of key the server is asked to prove itself with, in the order OpenSSH
+// asks, so that the one known_hosts has from a first connection with ssh is offered
+const HOST_KEYS: &str = "ssh-ed25519,ecdsa-sha2-nistp256,ecdsa-sha2-nistp384,\
+                         ecdsa-sha2-nistp521,rsa-sha2-512,rsa-sha2-256,ssh-rsa";
+// Keys in ~/.ssh tried after the agent's, as ssh tries them
+const KEYS: &[&str] = &["id_ed25519", "id_ecdsa", "id_rsa"];
+const PASSWORD_TRIES: usize = 3;
+const MODE: i32 = 0o644;
+const DIRECTORY_MODE: i32 = 0o755;
+
+// libssh2's failures of the connection itself, which a new one may get past
+const DROPPED: &[i32] = &[
+    -1,  // LIBSSH2_ERROR_SOCKET_NONE
+    -2,  // LIBSSH2_ERROR_BANNER_RECV
+    -3,  // LIBSSH2_ERROR_BANNER_SEND
+    -7,  // LIBSSH2_ERROR_SOCKET_SEND
+    -9,  // LIBSSH2_ERROR_TIMEOUT
+    -13, // LIBSSH2_ERROR_SOCKET_DISCONNECT
+    -26, // LIBSSH2_ERROR_CHANNEL_CLOSED
+    -30, // LIBSSH2_ERROR_SOCKET_TIMEOUT
+    -43, // LIBSSH2_ERROR_SOCKET_RECV
+];
+// A key file that can't be read without a passphrase, among other things
+const ERROR_FILE: i32 = -16;
+
+// SFTP status codes
+const FX_EOF: i32 = 1;
+const FX_NO_SUCH_FILE: i32 = 2;
+const FX_PERMISSION_DENIED: i32 = 3;
+const FX_NO_CONNECTION: i32 = 6;
+const FX_CONNECTION_LOST: i32 = 7;
+const FX_OP_UNSUPPORTED: i32 = 8;
+const FX_NO_SUCH_PATH: i32 = 10;
+const FX_FILE_ALREADY_EXISTS: i32 = 11;
+
+// How to reach the server, on top of what ~/.ssh has for it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SftpOptions {
+    // Reads or writes of up to 32 KiB in flight at once
+    pub window: usize,
+    // Chunks transferred at once by the async engine, over the one connection
+    #[serde(default = "default_connections")]
+    pub connections: usize,
+    // Further connections to carry a chunk on over when the one it was going over drops
+    pub retries: u32,
+    // Connect to a server whose key isn't in known_hosts, or differs from the one there.
+    // Anyone between here and there can then read and change the chunks
+    #[serde(default)]
+    pub insecure_skip_hostkey: bool,
+    // Asks for a password, or a key's passphrase, with the prompt given; none when there
+    // is no one to ask
+    #[serde(skip)]
+    pub ask: Option<fn(&str) -> io::Result<String>>,
+}
+
+impl Default for SftpOptions {
+    // 64 requests in flight, as OpenSSH's sftp has, four chunks and three retries.
+    fn default() -> SftpOptions {
+        SftpOptions {
+            window: 64,
+            connections: default_connections(),
+            retries: 3,
+            insecure_skip_hostkey: false,
+            ask: None,
+        }
+    }
+}
+
+fn default_connections() -> usize {
+    4
+}
+
+// Where an `sftp://` URL points.
+#[derive(Clone, Debug)]
+struct Target {
+    user: Option<String>,
+    host: String,
+    port: Option<u16>,
+    // As the server takes it: absolute, or relative to the login directory
+    directory: String,
+}
+
+fn parse_url(url: &Path) -> Result<Target> {
+    let invalid = SplitterError::InvalidOption {
+        field: "destination",
+        reason: "must be sftp://[USER@]HOST[:PORT]/PATH",
+    };
+    let Some(rest) = url.to_str().and_then(|url| url.strip_prefix(SFTP_SCHEME)) else {
+        return Err(invalid);
+    };
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let (user, address) = match authority.rsplit_once('@') {
+        Some((user, address)) => (Some(user), address),
+        None => (None, authority),
+    };
+    // An IPv6 address is in brackets, so its colons aren't taken for the port's
+    let (host, port) = match address.strip_prefix('[') {
+        Some(bracketed) => match bracketed.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, after)) => match after.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None => return Err(invalid),
+            },
+            None => return Err(invalid),
+        },
+        None => match address.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (address, None),
+        },
+    };
+    let port = match port.map(str::parse::<u16>) {
+        Some(Ok(port)) => Some(port),
+        Some(Err(_)) => return Err(invalid),
+        None => None,
+    };
+    if host.is_empty() || user.is_some_and(str::is_empty) {
+        return Err(invalid);
+    }
+    let home = path.is_empty() || path == "/~" || path.starts_with("/~/");
+    let directory = match home {
+        true => path.get(3..).unwrap_or(""),
+        false => path,
+    };
+    let directory = directory.trim_end_matches('/');
+    let directory = match (directory.is_empty(), home) {
+        (false, _) => directory,
+        (true, true) => ".",
+        (true, false) => "/",
+    };
+    Ok(Target {
+        user: user.map(str::to_string),
+        host: host.to_string(),
+        port,
+        directory: directory.to_string(),
+    })
+}
+
+// An SSH session and the SFTP channel over it. Dropping it ends both.
+struct Connection {
+    // Which of a session's connections this is; a file is only open on the one it was
+    // opened on
+    number: u64,
+    sftp: Sftp,
+}
+
+impl Connection {
+    fn start(target: &Target, options: &SftpOptions, number: u64) -> io::Result<Connection> {
+        let user = match &target.user {
+            Some(user) => user.clone(),
+            None => env::var("USER")
+                .or_else(|_| env::var("USERNAME"))
+                .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "no user to log in as; give one as sftp://USER@HOST/PATH",
+                    )
+                })?,
+        };
+        let host = target.host.as_str();
+        let port = target.port.unwrap_or(PORT);
+        let addresses = (host, port).to_socket_addrs().map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("cannot resolve {}: {}", host, e),
+            )
+        })?;
+        let mut stream = Err(lost(&format!("{} has no address", host)));
+        for address in addresses {
+            stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)
+                .map_err(|e| lost(&format!("cannot connect to {}: {}", host, e)));
+            if stream.is_ok() {
+                break;
+            }
+        }
+        debug!("connecting to {} port {} as {}", host, port, user);
+        let mut session = ssh2::Session::new().map_err(error)?;
+        session.set_tcp_stream(stream?);
+        session.set_timeout(TIMEOUT);
+        session
+            .method_pref(MethodType::HostKey, HOST_KEYS)
+            .map_err(error)?;
+        session
+            .handshake()
+            .map_err(|e| lost(&format!("SSH with {} failed: {}", host, e.message())))?;
+        check_host_key(&session, host, port, options)?;
+        authenticate(&session, &user, host, options)?;
+        let sftp = session.sftp().map_err(error)?;
+        debug!("connected to {}", host);
+        Ok(Connection { number, sftp })
+    }
+
+    // What a failed read or write of a file stands for, which libssh2 doesn't say: the
+    // failure while the connection still answers, a lost connection otherwise.
+    fn failed(&self, failure: io::Error) -> io::Error {
+        match self.sftp.stat(Path::new(".")) {
+            Ok(_) => failure,
+            Err(_) => lost(&failure.to_string()),
+        }
+    }
+
+    fn open(&self, path: &str, flags: OpenFlags) -> io::Result<File> {
+        self.sftp
+            .open_mode(Path::new(path), flags, MODE, OpenType::File)
+            .map_err(error)
+    }
+
+    fn stat(&self, path: &str) -> io::Result<FileStat> {
+        self.sftp.stat(Path::new(path)).map_err(error)
+    }
+
+    fn remove(&self, path: &str) -> io::Result<()> {
+        self.sftp.unlink(Path::new(path)).map_err(error)
+    }
+
+    fn mkdir(&self, path: &str) -> io::Result<()> {
+        self.sftp
+            .mkdir(Path::new(path), DIRECTORY_MODE)
+            .map_err(error)
+    }
+
+    fn rmdir(&self, path: &str) -> io::Result<()> {
+        self.sftp.rmdir(Path::new(path)).map_err(error)
+    }
+
+    // What is in directory `path`, but for `.` and `..`.
+    fn list(&self, path: &str) -> io::Result<Vec<(String, FileStat)>> {
+        let entries = self.sftp.readdir(Path::new(path)).map_err(error)?;
+        let names = entries.into_iter().filter_map(|(path, stat)| {
+            let name = path.file_name()?.to_string_lossy().into_owned();
+            Some((name, stat))
+        });
+        Ok(names.collect())
+    }
+
+    // Make directory `path` and those on the way to it that aren't there yet, returning
+    // the ones made, outermost first.
+    fn create_dir_all(&self, path: &str) -> io::Result<Vec<String>> {
+        let mut made = Vec::new();
+        match self.stat(path) {
+            Ok(stat) if stat.is_dir() => return Ok(made),
+            Ok(_) => return Err(not_a_directory(path)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let ends = path
+            .match_indices('/')
+            .map(|(at, _)| at)
+            .filter(|&at| at > 0)
+            .chain([path.len()]);
+        for end in ends {
+            let on_the_way = &path[..end];
+            match self.stat(on_the_way) {
+                Ok(stat) if stat.is_dir() => continue,
+                Ok(_) => return Err(not_a_directory(on_the_way)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            self.mkdir(on_the_way)?;
+            made.push(on_the_way.to_string());
+        }
+        Ok(made)
+    }
+}
+
+// Ok if the server's key is the one known_hosts has for it, or the check is skipped.
+fn check_host_key(
+    session: &ssh2::Session,
+    host: &str,
+    port: u16,
+    options: &SftpOptions,
+) -> io::Result<()> {
+    if options.insecure_skip_hostkey {
+        debug!("not checking the key of {}", host);
+        return Ok(());
+    }
+    let Some((key, _)) = session.host_key() else {
+        return Err(lost(&format!("{} sent no key", host)));
+    };
+    let mut known = session.known_hosts().map_err(error)?;
+    // A line at a time, as libssh2 gives up on a whole file for a line it can't read,
+    // such as one for a certificate authority
+    if let Some(file) = ssh_dir().map(|dir| dir.join("known_hosts"))
+        && let Ok(text) = fs::read_to_string(&file)
+    {
+        for line in text.lines() {
+            let _ = known.read_str(line, KnownHostFileKind::OpenSSH);
+        }
+    }
+    let problem = match known.check_port(host, port, key) {
+        CheckResult::Match => return Ok(()),
+        CheckResult::Mismatch => "differs from the one known_hosts has for it",
+        CheckResult::NotFound | CheckResult::Failure => "isn't in known_hosts",
+    };
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!(
+            "the key of {} {} (connect once with ssh to check it and add it there)",
+            host, problem
+        ),
+    ))
+}
+
+// Log in as `user` with the agent's keys, then those in ~/.ssh, then a password if
+// there is someone to ask for one.
+fn authenticate(
+    session: &ssh2::Session,
+    user: &str,
+    host: &str,
+    options: &SftpOptions,
+) -> io::Result<()> {
+    let methods = session.auth_methods(user).map_err(error)?.to_string();
+    // A server that wants nothing has let us in by now
+    if session.authenticated() {
+        return Ok(());
+    }
+    if methods.contains("publickey") {
+        if session.userauth_agent(user).is_ok() {
+            return Ok(());
+        }
+        let files = ssh_dir()
+            .into_iter()
+            .flat_map(|dir| KEYS.iter().map(move |key| dir.join(key)));
+        for file in files.filter(|file| file.is_file()) {
+            let tried = session.userauth_pubkey_file(user, None, &file, None);
+            let passphrase = match (tried, options.ask) {
+                (Ok(()), _) => return Ok(()),
+                (Err(e), Some(ask)) if e.code() == ErrorCode::Session(ERROR_FILE) => {
+                    ask(&format!("Passphrase for {}", file.display()))?
+                }
+                (Err(e), _) => {
+                    debug!("{} not taken: {}", file.display(), e.message());
+                    continue;
+                }
+            };
+            if session
+                .userauth_pubkey_file(user, None, &file, Some(&passphrase))
+                .is_ok()
+            {
+                return Ok(());
+            }
+        }
+    }
+    let password = methods.contains("password");
+    // Where a server asks for the password through PAM
+    let interactive = methods.contains("keyboard-interactive");
+    if let Some(ask) = options.ask
+        && (password || interactive)
+    {
+        let prompt = format!("Password for {}@{}", user, host);
+        for _ in 0..PASSWORD_TRIES {
+            let result = match password {
+                true => session.userauth_password(user, &ask(&prompt)?),
+                false => {
+                    let mut asked = Asked { ask, failure: None };
+                    let result = session.userauth_keyboard_interactive(user, &mut asked);
+                    if let Some(failure) = asked.failure {
+                        return Err(failure);
+                    }
+                    result
+                }
+            };
+            if result.is_ok() {
+                return Ok(());
+            }
+        }
+    }
+    let unasked = match options.ask {
+        Some(_) => "",
+        None => ", and there is no terminal to ask for a password on",
+    };
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!(
+            "{}@{} took none of the keys in the agent or ~/.ssh{} (it takes {})",
+            user, host, unasked, methods
+        ),
+    ))
+}
+
+// The server's own questions, as a password prompt is.
+struct Asked {
+    ask: fn(&str) -> io::Result<String>,
+    failure: Option<io::Error>,
+}
+
+impl KeyboardInteractivePrompt for Asked {
+    fn prompt(&mut self, _: &str, _: &str, prompts: &[Prompt<'_>]) -> Vec<String> {
+        let mut answers = Vec::new();
+        for prompt in prompts {
+            if self.failure.is_some() {
+                break;
+            }
+            match (self.ask)(prompt.text.trim_end().trim_end_matches(':')) {
+                Ok(answer) => answers.push(answer),
+                Err(e) => self.failure = Some(e),
+            }
+        }
+        answers
+    }
+}
+
+fn ssh_dir() -> Option<PathBuf> {
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    Some(Path::new(&home).join(".ssh"))
+}
+
+// The connection a store works over, made again when it drops.
+struct Session {
+    target: Target,
+    options: SftpOptions,
+    connection: Option<Connection>,
+    connections: u64,
+}
+
+impl Session {
+    fn connection(&mut self) -> io::Result<&mut Connection> {
+        let connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => {
+                self.connections += 1;
+                Connection::start(&self.target, &self.options, self.connections)?
+            }
+        };
+        Ok(self.connection.insert(connection))
+    }
+
+    // `operation` over the connection, which is done with if it drops meanwhile.
+    fn run<T>(
+        &mut self,
+        operation: impl FnOnce(&mut Connection) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let result = operation(self.connection()?);
+        if let Err(e) = &result
+            && is_lost(e)
+        {
+            self.connection = None;
+        }
+        result
+    }
+}
+
+// Tries at something, a chunk say, over connections that keep dropping.
+struct Attempts {
+    retries: u32,
+    failures: u32,
+    cancel: CancelToken,
+}
+
+impl Attempts {
+    // Ok once it is time to try again after `failure`, if that is worth doing and there
+    // are tries left; the failure otherwise. Pauses grow from 1 s to `MAX_BACKOFF`.
+    fn recover(&mut self, failure: io::Error, path: &Path) -> io::Result<()> {
+        if !is_lost(&failure) || self.failures >= self.retries {
+            return Err(failure);
+        }
+        self.failures += 1;
+        let backoff = Duration::from_secs(1 << (self.failures - 1).min(5)).min(MAX_BACKOFF);
+        warn!(
+            "{}: {}; connecting again in {} s ({} of {})",
+            path.display(),
+            failure,
+            backoff.as_secs(),
+            self.failures,
+            self.retries
+        );
+        pause(backoff, &self.cancel)
+    }
+}
+
+// A chunk set in a directory on the server, written with `create` or read with `open`.
+// Chunks are written compressed when the store is; reading goes by the set's manifest
+// when it has one, as with `S3Store`.
+pub struct SftpStore {
+    session: Arc<Mutex<Session>>,
+    // For error messages: sftp://host/path
+    url: PathBuf,
+    directory: String,
+    options: SftpOptions,
+    cancel: CancelToken,
+    compression: Compression,
+    level: u32,
+    count: Option<usize>,
+    // Files in the directory, by name, with their sizes, as they were when it opened
+    files: BTreeMap<String, u64>,
+    manifest: Option<Manifest>,
+    // Chunks to read, by index, and the names of their files
+    chunks: BTreeMap<usize, String>,
+    // While writing: files written and directories made, to take down after a failure
+    writing: bool,
+    written: Mutex<Vec<String>>,
+    made: Vec<String>,
+    stored: AtomicU64,
+}
+
+impl SftpStore {
+    // The set at `url`, to read from; a manifest changed after it was sealed is refused
+    // unless `accept_modified`.
+    pub fn open(
+        url: impl Into<PathBuf>,
+        options: &SftpOptions,
+        accept_modified: bool,
+        cancel: &CancelToken,
+    ) -> Result<SftpStore> {
+        let mut store = SftpStore::connect(url.into(), options, cancel)?;
+        let (url, directory) = (store.url.clone(), store.directory.clone());
+        let stat = store.retried(&url, |connection| connection.stat(&directory));
+        if !stat.at(&url)?.is_dir() {
+            return Err(not_a_directory(&directory)).at(&url);
+        }
+        let entries = store.retried(&url, |connection| connection.list(&directory));
+        store.files = entries
+            .at(&url)?
+            .into_iter()
+            .filter(|(_, stat)| !stat.is_dir())
+            .map(|(name, stat)| (name, stat.size.unwrap_or(0)))
+            .collect();
+        if store.files.contains_key(MANIFEST_NAME) {
+            let path = store.file_path(MANIFEST_NAME);
+            let mut text = Vec::new();
+            store
+                .download(MANIFEST_NAME)
+                .read_to_end(&mut text)
+                .at(&path)?;
+            let manifest = Manifest::parse(&text, &path, accept_modified)?;
+            store.compression = manifest.compression;
+            store.manifest = Some(manifest);
+        }
+        match &store.manifest {
+            Some(manifest) if !manifest.chunks.is_empty() => {
+                for (index, entry) in manifest.indexed() {
+                    if store.files.contains_key(&entry.name) {
+                        store.chunks.insert(index, entry.name.clone());
+                    }
+                }
+            }
+            _ => {
+                for name in store.files.keys() {
+                    if let Some(index) = chunk_index(name)
+                        && let Ok(index) = usize::try_from(index)
+                    {
+                        store.chunks.insert(index, name.clone());
+                    }
+                }
+            }
+        }
+        Ok(store)
+    }
+
+    // A new set at `url`, which is made if it isn't there and must be empty if it is.
+    pub fn create(
+        url: impl Into<PathBuf>,
+        options: &SftpOptions,
+        cancel: &CancelToken,
+    ) -> Result<SftpStore> {
+        let mut store = SftpStore::connect(url.into(), options, cancel)?;
+        let (url, directory) = (store.url.clone(), store.directory.clone());
+        let made = store.retried(&url, |connection| connection.create_dir_all(&directory));
+        store.made = made.at(&url)?;
+        if store.made.is_empty() {
+            let entries = store.retried(&url, |connection| connection.list(&directory));
+            if !entries.at(&url)?.is_empty() {
+                return Err(SplitterError::DestinationNotEmpty { path: url });
+            }
+        }
+        store.writing = true;
+        Ok(store)
+    }
+
+    fn connect(url: PathBuf, options: &SftpOptions, cancel: &CancelToken) -> Result<SftpStore> {
+        let target = parse_url(&url)?;
+        let url = match url.to_str() {
+            Some(text) if text.ends_with('/') => PathBuf::from(text.trim_end_matches('/')),
+            _ => url,
+        };
+        let directory = target.directory.clone();
+        let session = Session {
+            target,
+            options: options.clone(),
+            connection: None,
+            connections: 0,
+        };
+        Ok(SftpStore {
+            session: Arc::new(Mutex::new(session)),
+            url,
+            directory,
+            options: options.clone(),
+            cancel: cancel.clone(),
+            compression: Compression::None,
+            level: 0,
+            count: None,
+            files: BTreeMap::new(),
+            manifest: None,
+            chunks: BTreeMap::new(),
+            writing: false,
+            written: Mutex::default(),
+            made: Vec::new(),
+            stored: AtomicU64::new(0),
+        })
+    }
+
+    // Write chunks compressed with `compression` at `level`.
+    pub fn compressed(mut self, compression: Compression, level: u32) -> SftpStore {
+        self.compression = compression;
+        self.level = level;
+        self
+    }
+
+    // A set of `count` chunks, which armored chunks say in their header.
+    pub(crate) fn counted(mut self, count: usize) -> SftpStore {
+        self.count = Some(count);
+        self
+    }
+
+    pub fn url(&self) -> &Path {
+        &self.url
+    }
+
+    // Bytes written so far, as stored.
+    pub fn stored_size(&self) -> u64 {
+        self.stored.load(Ordering::Relaxed)
+    }
+
+    // As the server takes it.
+    fn remote(&self, name: &str) -> String {
+        match self.directory.as_str() {
+            "." => name.to_string(),
+            "/" => format!("/{}", name),
+            directory => format!("{}/{}", directory, name),
+        }
+    }
+
+    // With a slash whatever the platform's separator, as the URL has it.
+    fn file_path(&self, name: &str) -> PathBuf {
+        PathBuf::from(format!("{}/{}", self.url.display(), name))
+    }
+
+    fn attempts(&self) -> Attempts {
+        Attempts {
+            retries: self.options.retries,
+            failures: 0,
+            cancel: self.cancel.clone(),
+        }
+    }
+
+    // `operation`, again over a new connection when the one it went over drops.
+    fn retried<T>(
+        &self,
+        path: &Path,
+        mut operation: impl FnMut(&mut Connection) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut attempts = self.attempts();
+        loop {
+            let result = self.session.lock().unwrap().run(&mut operation);
+            match result {
+                Ok(value) => return Ok(value),
+                Err(e) => attempts.recover(e, path)?,
+            }
+        }
+    }
+
+    // What libssh2 is handed at once, which it keeps in flight as `window` requests.
+    fn window(&self) -> usize {
+        self.options.window.max(1) * BLOCK
+    }
+
+    fn upload(&self, name: &str) -> Upload {
+        Upload {
+            session: Arc::clone(&self.session),
+            remote: self.remote(name),
+            path: self.file_path(name),
+            pending: Vec::with_capacity(self.window()),
+            at: 0,
+            offset: 0,
+            file: None,
+            generation: 0,
+            truncate: true,
+            window: self.window(),
+            attempts: self.attempts(),
+        }
+    }
+
+    fn download(&self, name: &str) -> Download {
+        Download {
+            session: Arc::clone(&self.session),
+            remote: self.remote(name),
+            path: self.file_path(name),
+            size: self.files.get(name).copied().unwrap_or(0),
+            file: None,
+            generation: 0,
+            received: 0,
+            data: Vec::new(),
+            at: 0,
+            window: self.window(),
+            attempts: self.attempts(),
+        }
+    }
+
+    pub fn chunk_compression(&self, index: usize) -> Compression {
+        let entry = self.manifest.as_ref().and_then(|manifest| {
+            let name = self.chunks.get(&index)?;
+            manifest.chunks.iter().find(|entry| &entry.name == name)
+        });
+        entry
+            .and_then(|entry| entry.compression)
+            .unwrap_or(self.compression)
+    }
+
+    // Take down what a failed split wrote, and the directories it made, unless
+    // `keep_partial`. Best effort, as the failure is what the caller reports; a
+    // connection that Ctrl+C took with it is made again for this.
+    pub(crate) fn abort(&mut self, keep_partial: bool) {
+        if keep_partial {
+            return;
+        }
+        let mut session = self.session.lock().unwrap();
+        for name in mem::take(self.written.get_mut().unwrap()) {
+            let remote = self.remote(&name);
+            if let Err(e) = session.run(|connection| connection.remove(&remote)) {
+                debug!("cannot remove {}: {}", self.file_path(&name).display(), e);
+            }
+        }
+        for directory in mem::take(&mut self.made).into_iter().rev() {
+            if let Err(e) = session.run(|connection| connection.rmdir(&directory)) {
+                debug!("cannot remove {}: {}", directory, e);
+            }
+        }
+    }
+
+    // The file is opened here, so a directory that can't be written to says so before
+    // anything is read for the chunk.
+    fn writer(&self, index: usize) -> Result<SftpWriter> {
+        let name = chunk_name(index, self.compression);
+        let path = self.file_path(&name);
+        if !self.writing {
+            return Err(read_only()).at(&path);
+        }
+        let mut upload = self.upload(&name);
+        upload.pump(false).at(&path)?;
+        let mut written = self.written.lock().unwrap();
+        if !written.contains(&name) {
+            written.push(name);
+        }
+        drop(written);
+        let encoder = match self.compression {
+            Compression::None => Encoder::Plain(upload),
+            Compression::Gzip => Encoder::Gzip(Box::new(GzEncoder::new(upload, self.level))),
+            Compression::Armor => {
+                let encoder = ArmorEncoder::new(upload, index, self.count).at(&path)?;
+                Encoder::Armor(Box::new(encoder))
+            }
+            Compression::Zstd { level, long } => {
+                let encoder = ZstEncoder::new(upload, level, long).at(&path)?;
+                Encoder::Zstd(Box::new(encoder))
+            }
+        };
+        Ok(SftpWriter { encoder })
+    }
+
+    // Once the server has every byte of the chunk.
+    fn finish(&self, index: usize, writer: SftpWriter) -> Result<()> {
+        let path = self.chunk_path(index);
+        let upload = match writer.encoder {
+            Encoder::Plain(upload) => Ok(upload),
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Armor(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+        .at(&path)?;
+        let stored = upload.finish().at(&path)?;
+        self.stored.fetch_add(stored, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+pub struct SftpWriter {
+    encoder: Encoder,
+}
+
+enum Encoder {
+    Plain(Upload),
+    Gzip(Box<GzEncoder<Upload>>),
+    Armor(Box<ArmorEncoder<Upload>>),
+    Zstd(Box<ZstEncoder<Upload>>),
+}
+
+impl Write for SftpWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.encoder {
+            Encoder::Plain(upload) => upload.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Armor(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.encoder {
+            Encoder::Plain(upload) => upload.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Armor(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+// A file as it is written: what it is handed goes to libssh2 `window` bytes at a time,
+// and is kept until the server acknowledges it so it can go again over a new
+// connection when the one it went over drops.
+struct Upload {
+    session: Arc<Mutex<Session>>,
+    remote: String,
+    path: PathBuf,
+    // Bytes handed on and not yet acknowledged from `at`, which is at `offset` in the
+    // file
+    pending: Vec<u8>,
+    at: usize,
+    offset: u64,
+    // Open on connection number `generation`
+    file: Option<File>,
+    generation: u64,
+    // Until the file has been opened once, after which opening it again mustn't lose
+    // what the server has of it
+    truncate: bool,
+    window: usize,
+    attempts: Attempts,
+}
+
+impl Upload {
+    // Send what is pending, opening the file first if need be, and wait until the server
+    // has it. Then when `close` the file is checked and closed.
+    fn pump(&mut self, close: bool) -> io::Result<()> {
+        loop {
+            let session = Arc::clone(&self.session);
+            let result = session
+                .lock()
+                .unwrap()
+                .run(|connection| self.step(connection, close));
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) => self.attempts.recover(e, &self.path)?,
+            }
+        }
+    }
+
+    fn step(&mut self, connection: &mut Connection, close: bool) -> io::Result<()> {
+        if self.generation != connection.number {
+            self.generation = connection.number;
+            self.file = None;
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let flags = match self.truncate {
+                    true => OpenFlags::WRITE | OpenFlags::TRUNCATE,
+                    false => OpenFlags::WRITE | OpenFlags::CREATE,
+                };
+                let mut file = connection.open(&self.remote, flags)?;
+                self.truncate = false;
+                file.seek(SeekFrom::Start(self.offset))?;
+                self.file.insert(file)
+            }
+        };
+        // libssh2 says how much the server has acknowledged, and must be handed the rest
+        // again after it
+        while self.at < self.pending.len() {
+            let written = match file.write(&self.pending[self.at..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => written,
+                Err(e) => return Err(connection.failed(e)),
+            };
+            self.at += written;
+            self.offset += written as u64;
+        }
+        self.pending.clear();
+        self.at = 0;
+        if close {
+            let size = file.stat().map_err(error)?.size;
+            if let Some(size) = size
+                && size != self.offset
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "the server has {} bytes of it where {} were written",
+                        size, self.offset
+                    ),
+                ));
+            }
+            file.close().map_err(error)?;
+            self.file = None;
+        }
+        Ok(())
+    }
+
+    // Send the rest and wait for all of it, then close the file; returns its length.
+    fn finish(mut self) -> io::Result<u64> {
+        self.pump(true)?;
+        Ok(self.offset)
+    }
+}
+
+impl Write for Upload {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let taken = buf.len().min(self.window - self.pending.len());
+        self.pending.extend_from_slice(&buf[..taken]);
+        if self.pending.len() == self.window {
+            self.pump(false)?;
+        }
+        Ok(taken)
+    }
+
+    // What is pending goes once there is a window of it, and `finish` sends the rest.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// A file read back from an `SftpStore`, `window` bytes at a time, and carried on over a
+// new connection from where it got to when the one it was read over drops, as often as
+// the store retries.
+struct Download {
+    session: Arc<Mutex<Session>>,
+    remote: String,
+    path: PathBuf,
+    // As listed when the store opened
+    size: u64,
+    file: Option<File>,
+    generation: u64,
+    // Where the data received ends
+    received: u64,
+    data: Vec<u8>,
+    at: usize,
+    window: usize,
+    attempts: Attempts,
+}
+
+impl Download {
+    fn fill(&mut self) -> io::Result<()> {
+        loop {
+            let session = Arc::clone(&self.session);
+            let result = session
+                .lock()
+                .unwrap()
+                .run(|connection| self.step(connection));
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) => self.attempts.recover(e, &self.path)?,
+            }
+        }
+    }
+
+    fn step(&mut self, connection: &mut Connection) -> io::Result<()> {
+        if self.generation != connection.number {
+            self.generation = connection.number;
+            self.file = None;
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let mut file = connection.open(&self.remote, OpenFlags::READ)?;
+                file.seek(SeekFrom::Start(self.received))?;
+                self.file.insert(file)
+            }
+        };
+        let len = (self.size - self.received).min(self.window as u64) as usize;
+        self.data.resize(len, 0);
+        self.at = 0;
+        let read = match file.read(&mut self.data) {
+            Ok(read) => read,
+            Err(e) => {
+                self.data.clear();
+                return Err(connection.failed(e));
+            }
+        };
+        self.data.truncate(read);
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "ends at byte {} where the server listed {}",
+                    self.received, self.size
+                ),
+            ));
+        }
+        self.received += read as u64;
+        Ok(())
+    }
+}
+
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.at < self.data.len() {
+                let n = buf.len().min(self.data.len() - self.at);
+                buf[..n].copy_from_slice(&self.data[self.at..self.at + n]);
+                self.at += n;
+                return Ok(n);
+            }
+            if buf.is_empty() || self.received >= self.size {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+    }
+}
+
+impl ChunkStore for SftpStore {
+    type Writer = SftpWriter;
+    type Reader = Box<dyn Read + Send>;
+
+    fn create_chunk(&mut self, index: usize) -> Result<SftpWriter> {
+        self.writer(index)
+    }
+
+    fn finish_chunk(&mut self, index: usize, writer: SftpWriter) -> Result<()> {
+        self.finish(index, writer)
+    }
+
+    fn open_chunk(&self, index: usize) -> Result<Self::Reader> {
+        let Some(name) = self.chunks.get(&index) else {
+            return Err(io::Error::from(io::ErrorKind::NotFound)).at(&self.chunk_path(index));
+        };
+        let download = self.download(name);
+        let capacity = DEFAULT_BUFFER_SIZE.min(1 << 20);
+        Ok(match self.chunk_compression(index) {
+            Compression::None => Box::new(download),
+            Compression::Gzip => {
+                Box::new(GzDecoder::new(BufReader::with_capacity(capacity, download)))
+            }
+            Compression::Armor => Box::new(ArmorDecoder::new(BufReader::with_capacity(
+                capacity, download,
+            ))),
+            Compression::Zstd { .. } => Box::new(
+                ZstDecoder::new(BufReader::with_capacity(capacity, download))
+                    .at(&self.chunk_path(index))?,
+            ),
+        })
+    }
+
+    // From the manifest, which knows the size of compressed chunks, or else the listing.
+    fn chunk_len(&self, index: usize) -> Result<u64> {
+        let path = self.chunk_path(index);
+        let Some(name) = self.chunks.get(&index) else {
+            return Err(io::Error::from(io::ErrorKind::NotFound)).at(&path);
+        };
+        let listed = self
+            .manifest
+            .as_ref()
+            .and_then(|manifest| manifest.chunks.iter().find(|entry| &entry.name == name));
+        match (listed, self.files.get(name)) {
+            (Some(entry), _) => Ok(entry.size),
+            (None, Some(&size)) if self.chunk_compression(index).is_none() => Ok(size),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "a compressed chunk without a manifest to give its size",
+            ))
+            .at(&path),
+        }
+    }
+
+    fn remove_chunk(&mut self, index: usize) -> Result<()> {
+        let path = self.chunk_path(index);
+        let name = chunk_name(index, self.compression);
+        let name = self.chunks.remove(&index).unwrap_or(name);
+        let remote = self.remote(&name);
+        self.retried(&path, |connection| connection.remove(&remote))
+            .at(&path)?;
+        self.files.remove(&name);
+        Ok(())
+    }
+
+    fn list_chunks(&self) -> Result<Vec<usize>> {
+        Ok(self.chunks.keys().copied().collect())
+    }
+
+    fn read_info(&self) -> Result<Option<Manifest>> {
+        Ok(self.manifest.clone())
+    }
+
+    fn write_info(&mut self, manifest: &Manifest) -> Result<()> {
+        let path = self.file_path(MANIFEST_NAME);
+        if !self.writing {
+            return Err(read_only()).at(&path);
+        }
+        let data = manifest.to_json().map_err(io::Error::other).at(&path)?;
+        let mut upload = self.upload(MANIFEST_NAME);
+        self.written.lock().unwrap().push(MANIFEST_NAME.to_string());
+        upload
+            .write_all(data.as_bytes())
+            .and_then(|()| upload.finish())
+            .at(&path)?;
+        self.manifest = Some(manifest.clone());
+        Ok(())
+    }
+
+    fn chunk_path(&self, index: usize) -> PathBuf {
+        match self.chunks.get(&index) {
+            Some(name) => self.file_path(name),
+            None => self.file_path(&chunk_name(index, self.compression)),
+        }
+    }
+
+    fn compression(&self) -> Compression {
+        self.compression
+    }
+}
+
+#[cfg(feature = "async")]
+impl SharedStore for SftpStore {
+    fn create_shared(&self, index: usize) -> Result<SftpWriter> {
+        self.writer(index)
+    }
+
+    fn finish_shared(&self, index: usize, writer: SftpWriter) -> Result<()> {
+        self.finish(index, writer)
+    }
+}
+
+// A connection lost, or never made for a reason that may pass, which a new one may get
+// past.
+fn lost(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, message.to_string())
+}
+
+fn is_lost(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::ConnectionAborted
+}
+
+// What a failure libssh2 reports stands for here.
+fn error(failure: ssh2::Error) -> io::Error {
+    match failure.code() {
+        ErrorCode::Session(code) if DROPPED.contains(&code) => lost(failure.message()),
+        ErrorCode::Session(_) => io::Error::other(failure.message().to_string()),
+        ErrorCode::SFTP(code) => {
+            let kind = match code {
+                FX_EOF => io::ErrorKind::UnexpectedEof,
+                FX_NO_SUCH_FILE | FX_NO_SUCH_PATH => io::ErrorKind::NotFound,
+                FX_PERMISSION_DENIED => io::ErrorKind::PermissionDenied,
+                FX_NO_CONNECTION | FX_CONNECTION_LOST => io::ErrorKind::ConnectionAborted,
+                FX_OP_UNSUPPORTED => io::ErrorKind::Unsupported,
+                FX_FILE_ALREADY_EXISTS => io::ErrorKind::AlreadyExists,
+                _ => io::ErrorKind::Other,
+            };
+            io::Error::new(kind, format!("the server says: {}", failure.message()))
+        }
+    }
+}
+
+fn not_a_directory(path: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotADirectory,
+        format!("{} on the server is not a directory", path),
+    )
+}
+
+fn read_only() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the store was opened for reading",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dropped_connections_are_retried_and_the_servers_refusals_are_not() {
+        let refused = error(ssh2::Error::from_errno(ErrorCode::SFTP(
+            FX_PERMISSION_DENIED,
+        )));
+        assert_eq!(refused.kind(), io::ErrorKind::PermissionDenied);
+        assert!(!is_lost(&refused));
+        let missing = error(ssh2::Error::from_errno(ErrorCode::SFTP(FX_NO_SUCH_PATH)));
+        assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+        for code in DROPPED {
+            assert!(is_lost(&error(ssh2::Error::from_errno(
+                ErrorCode::Session(*code)
+            ))));
+        }
+        assert!(is_lost(&error(ssh2::Error::from_errno(ErrorCode::SFTP(
+            FX_CONNECTION_LOST
+        )))));
+    }
+
+    #[test]
+    fn urls_give_the_user_host_port_and_directory() {
+        let target = parse_url(Path::new("sftp://me@[::1]:2222/~/sets/")).unwrap();
+        assert_eq!(target.user.as_deref(), Some("me"));
+        assert_eq!((target.host.as_str(), target.port), ("::1", Some(2222)));
+        assert_eq!(target.directory, "sets");
+        let target = parse_url(Path::new("sftp://host")).unwrap();
+        assert_eq!((target.user, target.directory.as_str()), (None, "."));
+        assert!(parse_url(Path::new("sftp://@host/x")).is_err());
+        assert!(parse_url(Path::new("sftp://host:ssh/x")).is_err());
+    }
+}
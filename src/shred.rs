@@ -0,0 +1,39 @@
+// Removing a file after overwriting it once with zeros, for `split --shred`: what a
+// split removes again when it fails or is interrupted is the original's contents in the
+// clear, and plain unlinking leaves every byte of it on the disk until something else
+// is written there. One pass of zeros is all this does. It doesn't reach the old
+// contents on an SSD or flash card, which write somewhere else and map the block over,
+// nor on a copy-on-write file system such as Btrfs, ZFS or APFS, nor in a snapshot, a
+// backup or the journal of the file system; it only stops the blocks being read back
+// as they were on a plain file system on a spinning disk.
+//
+// Only a regular file is overwritten. A symbolic link is removed as it would have been,
+// with whatever it points to left alone.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+// How much is written at a time
+const ZEROS_LEN: usize = 1 << 20;
+
+// Remove the file at `path`, overwriting it with zeros first when `shred` is set.
+pub(crate) fn remove_file(path: &Path, shred: bool) -> io::Result<()> {
+    if shred && fs::symlink_metadata(path)?.is_file() {
+        overwrite(path)?;
+    }
+    fs::remove_file(path)
+}
+
+fn overwrite(path: &Path) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    let mut left = file.metadata()?.len();
+    let zeros = vec![0u8; ZEROS_LEN];
+    while left > 0 {
+        let len = left.min(ZEROS_LEN as u64) as usize;
+        file.write_all(&zeros[..len])?;
+        left -= len as u64;
+    }
+    // On the disk before the name goes, or the zeros may never be written at all
+    file.sync_all()
+}
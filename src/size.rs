@@ -0,0 +1,197 @@
+// Byte counts as people write and read them, the same everywhere sizes are shown or
+// entered. Sizes are always shown in binary units with one decimal, as in "1.4 GiB".
+// When entered, a single letter or a unit ending in "iB" is binary, as in "500K" or
+// "1.5MiB", and "KB", "MB" and so on are decimal, so "2 GB" is two billion bytes.
+
+const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+
+pub fn format_size(bytes: u64) -> String {
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    // Carried up by what rounds to 1024 as well, so 1048575 bytes is "1.0 MiB" rather
+    // than "1024.0 KiB"
+    while unit < UNITS.len() - 1 && (value * 10.0).round() >= 1024.0 * 10.0 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} {}", bytes, UNITS[0]),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+// Parse a size such as `500`, `500K`, `1.5MiB` or `2 GB`, rounded to a whole byte, a
+// half up. The arithmetic is on integers, so a byte count past 2^53, which a float
+// would round, comes out exact.
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    if number.is_empty() {
+        return Err(format!(
+            "invalid size \"{}\": expected a number such as 500, 500K, 1.5MiB or 2GB",
+            input
+        ));
+    }
+    let not_a_number = || format!("invalid size \"{}\": \"{}\" is not a number", input, number);
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if fraction.contains('.') || (whole.is_empty() && fraction.is_empty()) {
+        return Err(not_a_number());
+    }
+    let whole: u64 = match whole {
+        "" => 0,
+        whole => whole.parse().map_err(|_| too_large(input))?,
+    };
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "p" | "pib" => 1 << 50,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        "pb" => 1_000_000_000_000_000,
+        _ => {
+            return Err(format!(
+                "unknown unit in size \"{}\": use K, M, G, T or P (KiB, MiB, ...) for \
+                 powers of 1024, or KB, MB, GB, TB or PB for powers of 1000",
+                input
+            ));
+        }
+    };
+    // Digits past the 18th are under a thousandth of a byte even in PiB
+    let digits = &fraction[..fraction.len().min(18)];
+    let scale = 10u128.pow(digits.len() as u32);
+    let fraction = match digits {
+        "" => 0,
+        digits => digits.parse::<u128>().map_err(|_| not_a_number())?,
+    };
+    let part = (fraction * u128::from(multiplier) * 2 + scale) / (scale * 2);
+    u64::try_from(u128::from(whole) * u128::from(multiplier) + part).map_err(|_| too_large(input))
+}
+
+fn too_large(input: &str) -> String {
+    format!("size \"{}\" is too large", input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_bytes_below_a_kib_exactly() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1), "1 B");
+        assert_eq!(format_size(1023), "1023 B");
+    }
+
+    #[test]
+    fn formats_binary_units_with_one_decimal() {
+        assert_eq!(format_size(1024), "1.0 KiB");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(10 * 1024 + 512), "10.5 KiB");
+        assert_eq!(format_size(1 << 20), "1.0 MiB");
+        assert_eq!(format_size(1_503_238_554), "1.4 GiB");
+        assert_eq!(format_size(1 << 40), "1.0 TiB");
+        assert_eq!(format_size(1 << 50), "1.0 PiB");
+        // Never decimal units: a billion bytes is under a GiB
+        assert_eq!(format_size(1_000_000_000), "953.7 MiB");
+    }
+
+    #[test]
+    fn formats_rounding_to_the_next_unit_in_it() {
+        // 1023.95 KiB, 1048524.8 bytes, would round to 1024.0 KiB
+        assert_eq!(format_size(1_048_524), "1023.9 KiB");
+        assert_eq!(format_size(1_048_525), "1.0 MiB");
+        assert_eq!(format_size(1_048_575), "1.0 MiB");
+        assert_eq!(format_size((1 << 30) - 1), "1.0 GiB");
+    }
+
+    #[test]
+    fn formats_the_largest_size_in_pib() {
+        assert_eq!(format_size(u64::MAX), "16384.0 PiB");
+    }
+
+    #[test]
+    fn parses_plain_byte_counts() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("500"), Ok(500));
+        assert_eq!(parse_size(" 500 "), Ok(500));
+        assert_eq!(parse_size("500B"), Ok(500));
+        assert_eq!(parse_size("500 b"), Ok(500));
+    }
+
+    #[test]
+    fn parses_binary_units() {
+        assert_eq!(parse_size("500K"), Ok(512_000));
+        assert_eq!(parse_size("500KiB"), Ok(512_000));
+        assert_eq!(parse_size("1.5MiB"), Ok(1_572_864));
+        assert_eq!(parse_size("1.5m"), Ok(1_572_864));
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+        assert_eq!(parse_size("3 TiB"), Ok(3 << 40));
+        assert_eq!(parse_size("1p"), Ok(1 << 50));
+    }
+
+    #[test]
+    fn parses_decimal_units() {
+        assert_eq!(parse_size("1KB"), Ok(1_000));
+        assert_eq!(parse_size("2 GB"), Ok(2_000_000_000));
+        assert_eq!(parse_size("2gb"), Ok(2_000_000_000));
+        assert_eq!(parse_size("1.5MB"), Ok(1_500_000));
+        assert_eq!(parse_size("4TB"), Ok(4_000_000_000_000));
+        assert_eq!(parse_size("1PB"), Ok(1_000_000_000_000_000));
+        // The same number in the two kinds of unit
+        assert_ne!(parse_size("1GB"), parse_size("1GiB"));
+    }
+
+    #[test]
+    fn rounds_fractions_to_the_nearest_byte_half_up() {
+        assert_eq!(parse_size("0.4"), Ok(0));
+        assert_eq!(parse_size("0.5"), Ok(1));
+        assert_eq!(parse_size(".5K"), Ok(512));
+        assert_eq!(parse_size("1.K"), Ok(1024));
+        // 0.001 KiB is 1.024 bytes, 0.0005 KiB 0.512
+        assert_eq!(parse_size("0.001K"), Ok(1));
+        assert_eq!(parse_size("0.0005K"), Ok(1));
+        assert_eq!(parse_size("0.0004K"), Ok(0));
+        assert_eq!(parse_size("0.0005KB"), Ok(1));
+        assert_eq!(parse_size("1.0000000000000000001K"), Ok(1024));
+    }
+
+    #[test]
+    fn parses_integers_past_two_to_the_53_exactly() {
+        assert_eq!(parse_size("9007199254740993"), Ok(9_007_199_254_740_993));
+        assert_eq!(parse_size("18446744073709551615"), Ok(u64::MAX));
+        assert_eq!(parse_size("16383P"), Ok(16_383 << 50));
+        assert_eq!(parse_size("8192.5P"), Ok((8_192 << 50) + (1 << 49)));
+    }
+
+    #[test]
+    fn refuses_sizes_past_u64() {
+        assert!(parse_size("18446744073709551616").is_err());
+        assert!(parse_size("16384P").is_err());
+        assert!(parse_size("99999999999999999999999").is_err());
+    }
+
+    #[test]
+    fn refuses_garbage() {
+        for input in [
+            "", " ", "K", "abc", ".", "1.2.3", "5X", "5 KX", "-1", "1e3", "0x10",
+        ] {
+            assert!(parse_size(input).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn round_trips_whole_units() {
+        for bytes in [1024, 1 << 20, 3 << 30, 5 << 40] {
+            let shown = format_size(bytes).replace(' ', "");
+            assert_eq!(parse_size(&shown), Ok(bytes), "{}", shown);
+        }
+    }
+}
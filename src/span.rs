@@ -0,0 +1,315 @@
+// Splits spread over several directories, usually on removable drives of different
+// sizes, each taking as much of the set as it has room for: a volume. The room is what
+// the file system says is free, less `Span::margin` and an allowance for info.json and
+// for files taking up whole clusters. Every volume gets the chunks in turn, in order,
+// and a copy of info.json that says which of them went where; see `SpanInfo`. Which
+// chunks fit depends on the room there was, so such a split is only reproducible onto
+// drives with the same room.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{PathContext, Result, SplitterError};
+use crate::manifest::Compression;
+use crate::split::SplitOptions;
+
+// What `Span::margin` is unless set otherwise.
+pub const DEFAULT_SPAN_MARGIN: u64 = 16 << 20;
+
+// Allowed for every chunk file on top of its bytes: cluster slack, as exFAT on a large
+// drive rounds files up to 128 KiB, and the directory entry
+const FILE_SLACK: u64 = 128 << 10;
+// Allowed for info.json: what it takes, with a hash, per chunk, and the rest of it
+const ENTRY_SIZE: u64 = 160;
+const MANIFEST_BASE: u64 = 4 << 10;
+
+// The volumes a split fills after `SplitOptions::destination`, which is the first.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Span {
+    pub volumes: Vec<PathBuf>,
+    // Bytes left free on every volume
+    #[serde(default = "default_margin")]
+    pub margin: u64,
+    // One chunk per volume, as large as it has room for, rather than chunks of
+    // `chunk_size`; needs uncompressed chunks, whose size on disk is known ahead
+    #[serde(default)]
+    pub fit: bool,
+}
+
+fn default_margin() -> u64 {
+    DEFAULT_SPAN_MARGIN
+}
+
+impl Span {
+    pub fn new(volumes: Vec<PathBuf>) -> Span {
+        Span {
+            volumes,
+            margin: DEFAULT_SPAN_MARGIN,
+            fit: false,
+        }
+    }
+}
+
+// Where the chunks of a spanned split go, from `plan_span`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SpanPlan {
+    // Every destination in order, those with no room or not needed included
+    pub volumes: Vec<PlannedVolume>,
+    pub total_size: u64,
+    // Bytes none of the destinations have room for; 0 when the plan holds the input
+    pub unplaced: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlannedVolume {
+    pub directory: PathBuf,
+    // What the file system said was free there
+    pub free: u64,
+    // The chunks it gets: `count` of them from `first` on, `size` original bytes in all
+    pub first: usize,
+    pub count: usize,
+    pub size: u64,
+}
+
+impl SpanPlan {
+    // The volumes that get any chunks, which are the ones written.
+    pub fn used(&self) -> impl Iterator<Item = &PlannedVolume> {
+        self.volumes.iter().filter(|volume| volume.count > 0)
+    }
+}
+
+// Which chunks each destination of `options.span` has room for, looking at the free
+// space of each now. A destination on the same file system as one before it only has
+// room for what that one leaves. The input must be a file.
+pub fn plan_span(options: &SplitOptions) -> Result<SpanPlan> {
+    let Some(span) = &options.span else {
+        return Err(SplitterError::InvalidOption {
+            field: "span",
+            reason: "is needed to plan a split across several directories",
+        });
+    };
+    let input = options.input.as_path();
+    let total = fs::metadata(input).at(input)?.len();
+    let chunk_size = options.chunk_size;
+    let chunks = match span.fit {
+        true => span.volumes.len() as u64 + 1,
+        false => total.div_ceil(chunk_size),
+    };
+    // Every volume's info.json lists all of them
+    let reserve = MANIFEST_BASE + FILE_SLACK + chunks * ENTRY_SIZE;
+
+    let mut volumes = Vec::new();
+    // Bytes already planned onto each file system
+    let mut planned: Vec<(String, u64)> = Vec::new();
+    let (mut offset, mut index) = (0, 0);
+    for directory in std::iter::once(&options.destination).chain(&span.volumes) {
+        let existing = existing_ancestor(directory);
+        let Some(free) = free_space(existing) else {
+            return Err(io::Error::other("cannot tell how much room is left there")).at(directory);
+        };
+        let device = device(existing);
+        let taken = planned
+            .iter()
+            .filter(|(other, _)| device.as_ref() == Some(other))
+            .map(|(_, bytes)| bytes)
+            .sum::<u64>();
+        let room = free.saturating_sub(taken + span.margin + reserve);
+        let (mut count, mut size) = (0, 0);
+        if span.fit {
+            size = room.saturating_sub(FILE_SLACK).min(total - offset);
+            count = usize::from(size > 0);
+        } else {
+            let mut left = room;
+            while offset + size < total {
+                let len = chunk_size.min(total - offset - size);
+                let needed = stored_bound(len, options.compression) + FILE_SLACK;
+                if needed > left {
+                    break;
+                }
+                left -= needed;
+                size += len;
+                count += 1;
+            }
+        }
+        debug!(
+            "{}: {} bytes free, room for {} chunks ({} bytes)",
+            directory.display(),
+            free,
+            count,
+            size
+        );
+        if let Some(device) = device {
+            let used = size + count as u64 * FILE_SLACK + reserve;
+            planned.push((device, used));
+        }
+        volumes.push(PlannedVolume {
+            directory: directory.clone(),
+            free,
+            first: index,
+            count,
+            size,
+        });
+        offset += size;
+        index += count;
+    }
+    Ok(SpanPlan {
+        volumes,
+        total_size: total,
+        unplaced: total - offset,
+    })
+}
+
+// The most a chunk of `len` bytes takes stored with `compression`: gzip adds a little
+// to what doesn't compress, zstd a little less, and armor a third and a line break every
+// 57 bytes.
+fn stored_bound(len: u64, compression: Compression) -> u64 {
+    match compression {
+        Compression::None => len,
+        Compression::Gzip => len + len / 1000 + 1024,
+        Compression::Armor => len.div_ceil(57) * 78 + 1024,
+        // ZSTD_compressBound, and room for the frame's header and checksum
+        Compression::Zstd { .. } => len + len / 256 + 1024,
+    }
+}
+
+// `path`, or the nearest directory above it that exists, for a destination that has yet
+// to be made.
+fn existing_ancestor(path: &Path) -> &Path {
+    let mut existing = path;
+    while !existing.exists() {
+        match existing.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => existing = parent,
+            _ => return Path::new("."),
+        }
+    }
+    existing
+}
+
+// Whether `a` and `b` are on the same file system, either judged by the nearest
+// directory above it that exists when it has yet to be made. None when the OS won't
+// say.
+pub fn same_file_system(a: &Path, b: &Path) -> Option<bool> {
+    Some(device(existing_ancestor(a))? == device(existing_ancestor(b))?)
+}
+
+// What tells apart the file systems of two paths: the device on Unix, the drive or
+// share on Windows.
+fn device(path: &Path) -> Option<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        fs::metadata(path)
+            .ok()
+            .map(|metadata| metadata.dev().to_string())
+    }
+    #[cfg(windows)]
+    {
+        use std::path::Component;
+        let absolute = std::path::absolute(path).ok()?;
+        match absolute.components().next() {
+            Some(Component::Prefix(prefix)) => Some(
+                crate::longpath::display_path(Path::new(prefix.as_os_str()))
+                    .to_string_lossy()
+                    .to_ascii_uppercase(),
+            ),
+            _ => None,
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+// Bytes available to us on the file system holding `path`, when the OS will say.
+pub fn free_space(path: &Path) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+        let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+        // SAFETY: `path` is NUL-terminated and `stat` is only read after success.
+        if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        let stat = unsafe { stat.assume_init() };
+        // The field types differ between platforms
+        #[allow(clippy::unnecessary_cast)]
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+        // Unlike std's file functions, this doesn't add the long path prefix itself
+        let path = crate::longpath::extended_path(path);
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let mut available = 0;
+        // SAFETY: `wide` is NUL-terminated; the other outputs may be null.
+        let ok = unsafe {
+            GetDiskFreeSpaceExW(
+                wide.as_ptr(),
+                &mut available,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        };
+        (ok != 0).then_some(available)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+// Files that can still be made on the file system holding `path`, when it has a fixed
+// number of them and the OS will say: Unix file systems run out of inodes, which a split
+// into many small chunks can use up before the space. None on Windows, whose file
+// systems have no such limit, as well as where it can't be told.
+pub(crate) fn free_files(path: &Path) -> Option<u64> {
+    #[cfg(unix)]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+        let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+        // SAFETY: `path` is NUL-terminated and `stat` is only read after success.
+        if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        let stat = unsafe { stat.assume_init() };
+        // Those that make files as they are needed, such as btrfs, say they have none
+        #[allow(clippy::unnecessary_cast)]
+        (stat.f_files > 0).then_some(stat.f_favail as u64)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_bounds_of_chunks_past_4_gib() {
+        const GIB: u64 = 1 << 30;
+        let len = 5 * GIB;
+        assert_eq!(stored_bound(len, Compression::None), len);
+        assert_eq!(stored_bound(len, Compression::Gzip), len + 5_368_709 + 1024);
+        assert_eq!(
+            stored_bound(len, Compression::Armor),
+            len.div_ceil(57) * 78 + 1024
+        );
+        assert!(stored_bound(len, Compression::Armor) > len / 3 * 4);
+    }
+}
@@ -0,0 +1,3304 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::cache::Released;
+use crate::cancel::CancelToken;
+use crate::chunkset::containing_set;
+use crate::compat::Compat;
+use crate::crypt::{ChunkKey, Encryption};
+#[cfg(feature = "async")]
+use crate::engine::{Engine, SharedStore};
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::{Counting, ProgressEvent, Report};
+use crate::hook::{ChunkHook, Hooks, Phase};
+use crate::join;
+use crate::lock::{self, DirLock, LOCK_NAME};
+use crate::manifest::{
+    ChunkEntry, ChunkHasher, Compression, HashAlgorithm, InputRange, MANIFEST_NAME,
+    MANIFEST_VERSION, MAX_PARITY_SHARDS, Manifest, Parity, ParityInfo, SpanInfo, VolumeEntry,
+    hash_file,
+};
+#[cfg(feature = "mmap")]
+use crate::mmap;
+use crate::modes::{self, MAX_MODE};
+use crate::owner;
+use crate::par2::{self, Par2Report};
+use crate::parity;
+use crate::pipeline::{self, DEFAULT_BUFFER_SIZE, Io, copy_overlapped};
+use crate::retry::{RetryPolicy, Retrying, retried};
+use crate::s3::{S3Options, S3Store, is_s3_url};
+#[cfg(feature = "sftp")]
+use crate::sftp::{SftpOptions, SftpStore};
+use crate::shred;
+use crate::size::format_size;
+use crate::span::{PlannedVolume, Span, SpanPlan, free_files, plan_span};
+use crate::store::{
+    ChunkStore, LocalDirStore, chunk_name, is_shard_dir, log_written, prefixed_chunk_name,
+    random_chunk_name, shard_dir, split_into_with,
+};
+use crate::symlinks::{self, SymlinkPolicy};
+use crate::timestamps;
+use crate::transfer::TRANSFER_STATE_NAME;
+use crate::unicode::Normalization;
+use crate::xattrs;
+use crate::zip::ZipStore;
+use crate::{
+    DEFAULT_CHUNK_SIZE, DEFAULT_MIN_RATIO, fastcopy, is_set_file, is_sftp_url, is_stream,
+    prefixed_index,
+};
+
+// How much of each chunk is trial-compressed to decide whether to compress it.
+const SAMPLE_SIZE: u64 = 64 << 10;
+
+// Chunks a directory is planned to hold before `ShardDirs` has a say. Directories of
+// tens of thousands of files are slow to list on most file systems, and FAT32 holds at
+// most 65,534.
+pub const DEFAULT_MAX_DIR_FILES: usize = 10_000;
+
+// Chunks smaller than this are refused unless `SplitOptions::min_chunk_size` is lowered:
+// a size like 16 is far more often 16MiB without its unit than meant, and would leave a
+// file of every 16 bytes
+pub const DEFAULT_MIN_CHUNK_SIZE: u64 = 4 << 10;
+
+// What to split and where to. The chunks go into `destination`, which must be empty
+// or not exist yet, or with `Container::Zip` into an archive there, which mustn't. An
+// `s3://bucket/prefix` destination uploads them instead, see `S3Store`, and an
+// `sftp://host/path` one writes them to a directory on that server, see `SftpStore`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SplitOptions {
+    pub input: PathBuf,
+    pub destination: PathBuf,
+    pub chunk_size: u64,
+    // The smallest `chunk_size` taken; see `check_chunk_size`
+    #[serde(default = "default_min_chunk_size")]
+    pub min_chunk_size: u64,
+    // Cut the input into this many chunks instead, of the one size that takes, the last
+    // holding what is left: fewer when the input is too small for that many. `build`
+    // works out `chunk_size` from it, and so does `split_file`; see `part_size`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parts: Option<u64>,
+    pub threads: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<HashAlgorithm>,
+    // Keep one file for chunks with the same bytes, as the runs of zeros in a disk image
+    // have: each chunk is read through before it is written, and one with the size and
+    // hash of an earlier one is never written, its entry pointed at the earlier one's
+    // (`same_as`) instead. Needs `hash`, and a file for its input. Versions of this tool
+    // from before it can't read such sets
+    #[serde(default)]
+    pub dedup: bool,
+    #[serde(default)]
+    pub mmap: bool,
+    // Leave the chunks written so far in place when the split fails or is cancelled,
+    // instead of removing them
+    #[serde(default)]
+    pub keep_partial: bool,
+    // Overwrite what is removed again when the split fails or is cancelled with zeros
+    // first
+    #[serde(default)]
+    pub shred: bool,
+    // Flush every file written, and the directory holding them, to disk before the split
+    // returns, so that a crash or power cut right after can't lose any of them. A zip is
+    // always flushed
+    #[serde(default)]
+    pub fsync: bool,
+    // Split a file that is a piece of a chunk set, such as one of its chunks, rather
+    // than fail with `NestedSplit`; see `containing_set`
+    #[serde(default)]
+    pub allow_nested: bool,
+    // Chunks are cut from the original, then compressed; `compression_level` is within
+    // the codec's `levels`
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    pub compression: Compression,
+    #[serde(default)]
+    pub compression_level: u32,
+    // Chunks whose start compresses by less than this (original over compressed size)
+    // are stored uncompressed; 0 compresses everything
+    #[serde(default)]
+    pub min_ratio: f64,
+    // Name chunks at random so their order is only in the manifest; such splits are not
+    // reproducible
+    #[serde(default)]
+    pub random_names: bool,
+    // Name the chunks `PREFIX000`, `PREFIX001` and so on rather than `chunk000`, which
+    // only info.json then finds them by; see `check_prefix`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    // Chunks a compressed split on several threads may hold in memory at once, read
+    // ahead of the workers compressing them; 0 for twice the thread count
+    #[serde(default)]
+    pub in_flight: usize,
+    // Redundancy to compute once the chunks are written, which reads them all back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parity: Option<Parity>,
+    // A second directory that gets a complete copy of the set, written from the same
+    // buffers as `destination` so the input is only read once. Like `destination` it
+    // must be empty or not exist yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<PathBuf>,
+    #[serde(default)]
+    pub mirror_failure: MirrorFailure,
+    // PAR2 recovery data to write over the chunks and the manifest, as a percentage of
+    // their size, for repair with any PAR2 client. It protects against the same losses
+    // as `parity`, so one or the other is usually enough
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub par2: Option<u32>,
+    // Name chunks so that another tool, or `cat`, can join them; see `Compat`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compat: Option<Compat>,
+    // Don't write `info.json`, for sets whose chunk names say everything; there is then
+    // nowhere to record hashes, parity or random names
+    #[serde(default)]
+    pub no_manifest: bool,
+    // Also write `JOIN.sh` and `JOIN.bat`, which put the file back together without this
+    // tool
+    #[serde(default)]
+    pub join_scripts: bool,
+    #[serde(default)]
+    pub container: Container,
+    // The service an `s3://` destination is on
+    #[serde(default)]
+    pub s3: S3Options,
+    // How an `sftp://` destination is reached
+    #[cfg(feature = "sftp")]
+    #[serde(default)]
+    pub sftp: SftpOptions,
+    // Run for every chunk once it is written, while the split goes on, which waits for
+    // them all before writing `info.json`. A directory destination only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_chunk_cmd: Option<ChunkHook>,
+    // Fill `destination` and then each of these directories with as much of the set as
+    // it has room for, numbered chunks compressed or not and info.json; see `Span`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<Span>,
+    // Fail the split when the input changes while it is read, rather than warn and say
+    // so in the report
+    #[serde(default)]
+    pub strict: bool,
+    // Record the input's extended attributes in info.json, for reconstruction to put
+    // back; see `xattrs`
+    #[serde(default)]
+    pub xattrs: bool,
+    // Record in info.json when the split started and when it wrote each chunk, for
+    // `check_timestamps`; the directory is then no longer the same from one split to the
+    // next
+    #[serde(default)]
+    pub record_times: bool,
+    // Permissions for the chunks, info.json and the rest of the files written, and for
+    // the directories they go in, in place of what the umask leaves; see `modes`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_mode: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir_mode: Option<u32>,
+    // Split only the input from `input_offset` on, `input_length` bytes of it or else up
+    // to its end, for a set that joins into that extract of it. Such a range is read
+    // front to back on one thread, with buffered I/O
+    #[serde(default)]
+    pub input_offset: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_length: Option<u64>,
+    // What a split into a directory does when it will have more than `max_dir_files`
+    // chunks
+    #[serde(default)]
+    pub shard_dirs: ShardDirs,
+    #[serde(default = "default_max_dir_files")]
+    pub max_dir_files: usize,
+    // How creating and writing a chunk is tried again when it fails in a way that can
+    // pass, as on a flaky network mount; not at all by default
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    // The size of the buffers data is copied with, or with `adaptive_buffers` the
+    // largest tried; see `pipeline`
+    #[serde(default = "default_buffer_size")]
+    pub buffer_size: usize,
+    #[serde(default)]
+    pub adaptive_buffers: bool,
+    // A cap on what the copy buffers and the chunks read ahead take across all threads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_memory: Option<u64>,
+    // Keep what is copied out of the page cache; see `cache`
+    #[serde(default)]
+    pub direct_io: bool,
+    // How old a lock left on the destination has to be for the error to call it stale
+    #[serde(default = "default_lock_max_age")]
+    pub lock_max_age: Duration,
+    // Whether an input that is a symbolic link is split, and as what
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+    // Encrypt each chunk with `key`, which `encryption` says how to derive again from the
+    // passphrase; see `crypt`. Needs the `encrypt` feature
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<Encryption>,
+    #[serde(skip)]
+    pub key: Option<ChunkKey>,
+    // Seal the file's name, size, hashes and the rest of info.json under `key` too,
+    // leaving what verify and getting the key again take; see `Manifest::conceal`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypt_metadata: bool,
+}
+
+fn default_max_dir_files() -> usize {
+    DEFAULT_MAX_DIR_FILES
+}
+
+fn default_min_chunk_size() -> u64 {
+    DEFAULT_MIN_CHUNK_SIZE
+}
+
+pub(crate) fn default_buffer_size() -> usize {
+    DEFAULT_BUFFER_SIZE
+}
+
+pub(crate) fn default_lock_max_age() -> Duration {
+    lock::DEFAULT_MAX_AGE
+}
+
+// That chunks of `chunk_size` are neither empty nor smaller than `minimum`, for a split
+// or rechunk; as small as one byte is let through with a minimum of 0 or 1.
+pub fn check_chunk_size(chunk_size: u64, minimum: u64) -> Result<()> {
+    if chunk_size == 0 {
+        return Err(SplitterError::InvalidOption {
+            field: "chunk_size",
+            reason: "must be greater than zero",
+        });
+    }
+    if chunk_size < minimum {
+        return Err(SplitterError::ChunkTooSmall {
+            chunk_size,
+            minimum,
+        });
+    }
+    Ok(())
+}
+
+// That chunks can be named `prefix` and their number: a letter, then up to 31 more
+// ASCII letters, digits, `-` or `_`, not ending in a digit, which would run into the
+// number, and not `parity`, which parity files are named with.
+fn check_prefix(prefix: &str) -> Result<()> {
+    let invalid = |reason| {
+        Err(SplitterError::InvalidOption {
+            field: "prefix",
+            reason,
+        })
+    };
+    if !prefix.starts_with(|c: char| c.is_ascii_alphabetic()) || prefix.len() > 32 {
+        return invalid("must start with a letter and be at most 32 characters");
+    }
+    if !prefix
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    {
+        return invalid("may only hold ASCII letters, digits, - and _");
+    }
+    if prefix.ends_with(|c: char| c.is_ascii_digit()) {
+        return invalid("must not end in a digit, which would run into the chunk numbers");
+    }
+    if prefix == "parity" {
+        return invalid("is what parity files are named with");
+    }
+    Ok(())
+}
+
+// What a split writes the chunks into.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Container {
+    // A chunk file each, side by side
+    #[default]
+    Directory,
+    // Entries of one zip archive, for transfers that take a single file; see `ZipStore`
+    Zip,
+}
+
+// What a split into a directory does when planning finds it will hold more chunks than
+// `SplitOptions::max_dir_files`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum ShardDirs {
+    // Say so, and put them all in the one directory anyway
+    #[default]
+    Warn,
+    // Put them `max_dir_files` to a subdirectory, `00/`, `01/` and so on, recorded in
+    // the manifest, which is then the only way to find them; versions of this tool from
+    // before it can't read such sets
+    Shard,
+    // Fail the split before anything is written
+    Refuse,
+}
+
+// What a split does when writing to its mirror fails.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum MirrorFailure {
+    // Carry on without the mirror, and say so in the report
+    #[default]
+    Warn,
+    // Fail the split, as for a failure in the destination
+    Abort,
+}
+
+impl SplitOptions {
+    // Single-threaded and unhashed, in chunks of DEFAULT_CHUNK_SIZE.
+    pub fn new(input: impl Into<PathBuf>, destination: impl Into<PathBuf>) -> SplitOptions {
+        SplitOptions {
+            input: input.into(),
+            destination: destination.into(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            min_chunk_size: DEFAULT_MIN_CHUNK_SIZE,
+            parts: None,
+            threads: 1,
+            hash: None,
+            dedup: false,
+            mmap: false,
+            keep_partial: false,
+            shred: false,
+            fsync: false,
+            allow_nested: false,
+            compression: Compression::None,
+            compression_level: 0,
+            min_ratio: DEFAULT_MIN_RATIO,
+            random_names: false,
+            prefix: None,
+            in_flight: 0,
+            parity: None,
+            mirror: None,
+            mirror_failure: MirrorFailure::Warn,
+            par2: None,
+            compat: None,
+            no_manifest: false,
+            join_scripts: false,
+            container: Container::Directory,
+            s3: S3Options::default(),
+            #[cfg(feature = "sftp")]
+            sftp: SftpOptions::default(),
+            post_chunk_cmd: None,
+            span: None,
+            strict: false,
+            xattrs: false,
+            record_times: false,
+            file_mode: None,
+            dir_mode: None,
+            input_offset: 0,
+            input_length: None,
+            shard_dirs: ShardDirs::Warn,
+            max_dir_files: DEFAULT_MAX_DIR_FILES,
+            retry: RetryPolicy::default(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            adaptive_buffers: false,
+            max_memory: None,
+            direct_io: false,
+            lock_max_age: lock::DEFAULT_MAX_AGE,
+            symlinks: SymlinkPolicy::Resolve,
+            encryption: None,
+            key: None,
+            encrypt_metadata: false,
+        }
+    }
+
+    // The part of the input `input_offset` and `input_length` ask for, or None for all
+    // of it. It has to be within the input as it is now.
+    pub fn input_range(&self) -> Result<Option<InputRange>> {
+        if self.input_offset == 0 && self.input_length.is_none() {
+            return Ok(None);
+        }
+        let size = fs::metadata(&self.input).at(&self.input)?.len();
+        if self.input_offset >= size {
+            return Err(SplitterError::InvalidOption {
+                field: "input_offset",
+                reason: "is at or past the end of the input",
+            });
+        }
+        let length = self.input_length.unwrap_or(size - self.input_offset);
+        if length > size - self.input_offset {
+            return Err(SplitterError::InvalidOption {
+                field: "input_length",
+                reason: "reaches past the end of the input",
+            });
+        }
+        Ok(Some(InputRange {
+            offset: self.input_offset,
+            length,
+        }))
+    }
+
+    // How much of the input is split: its range, or the whole of it, or None for a pipe
+    // or device, whose size isn't known until it ends.
+    pub fn input_len(&self) -> Result<Option<u64>> {
+        check_readable(&self.input, self.symlinks)?;
+        if let Some(range) = self.input_range()? {
+            return Ok(Some(range.length));
+        }
+        if is_stream(&self.input) {
+            return Ok(None);
+        }
+        Ok(Some(fs::metadata(&self.input).at(&self.input)?.len()))
+    }
+
+    // How the split copies, with a trial of its own when its buffers adapt.
+    pub(crate) fn io(&self) -> Io {
+        Io::new(self.buffer_size, self.adaptive_buffers, self.direct_io)
+    }
+
+    // The chunk size that cuts the input into `parts` chunks, as `parts` asks for.
+    pub fn part_size(&self, parts: u64) -> Result<u64> {
+        if parts == 0 {
+            return Err(SplitterError::InvalidOption {
+                field: "parts",
+                reason: "must be at least 1",
+            });
+        }
+        match self.input_len()? {
+            Some(len) => Ok(len.div_ceil(parts).max(1)),
+            None => Err(SplitterError::InvalidOption {
+                field: "parts",
+                reason: "needs an input whose size is known, not a pipe",
+            }),
+        }
+    }
+
+    // These options with `chunk_size` worked out from `parts`, if given.
+    fn with_parts(&self) -> Result<Cow<'_, SplitOptions>> {
+        let Some(parts) = self.parts else {
+            return Ok(Cow::Borrowed(self));
+        };
+        Ok(Cow::Owned(SplitOptions {
+            chunk_size: self.part_size(parts)?,
+            ..self.clone()
+        }))
+    }
+
+    // The name chunk `index` gets with `compression`, unless random or for another tool.
+    fn numbered_name(&self, index: usize, compression: Compression) -> String {
+        match &self.prefix {
+            Some(prefix) => prefixed_chunk_name(prefix, index, compression),
+            None => chunk_name(index, compression),
+        }
+    }
+
+    // Starts from the defaults of `new`; `build` checks the result.
+    pub fn builder(
+        input: impl Into<PathBuf>,
+        destination: impl Into<PathBuf>,
+    ) -> SplitOptionsBuilder {
+        SplitOptionsBuilder {
+            options: SplitOptions::new(input, destination),
+            chunk_size_set: false,
+        }
+    }
+
+    // The checks `build` makes, for options put together by hand or deserialized.
+    // `split_file` makes them too.
+    pub fn validate(&self) -> Result<()> {
+        if self.input.file_name().is_none() {
+            return Err(SplitterError::NotAFile {
+                path: self.input.clone(),
+            });
+        }
+        check_chunk_size(self.chunk_size, self.min_chunk_size)?;
+        if self.threads == 0 {
+            return Err(SplitterError::InvalidOption {
+                field: "threads",
+                reason: "must be at least 1",
+            });
+        }
+        if self.parts == Some(0) {
+            return Err(SplitterError::InvalidOption {
+                field: "parts",
+                reason: "must be at least 1",
+            });
+        }
+        if let Some(prefix) = &self.prefix {
+            check_prefix(prefix)?;
+            if self.random_names || self.compat.is_some() || self.no_manifest {
+                return Err(SplitterError::InvalidOption {
+                    field: "prefix",
+                    reason: "names chunks that info.json then lists, so not random ones, ones for other tools or a set without it",
+                });
+            }
+        }
+        if self.buffer_size == 0 {
+            return Err(SplitterError::InvalidOption {
+                field: "buffer_size",
+                reason: "must be at least 1 byte",
+            });
+        }
+        if !self.compression.is_none()
+            && !self.compression.levels().contains(&self.compression_level)
+        {
+            return Err(SplitterError::InvalidOption {
+                field: "compression_level",
+                reason: "is not one the codec supports",
+            });
+        }
+        if let Some(parity) = self.parity
+            && !(1..=MAX_PARITY_SHARDS).contains(&parity.shards())
+        {
+            return Err(SplitterError::InvalidOption {
+                field: "parity",
+                reason: "must have from 1 to 128 parity files per stripe",
+            });
+        }
+        if let Some(percent) = self.par2
+            && !(1..=100).contains(&percent)
+        {
+            return Err(SplitterError::InvalidOption {
+                field: "par2",
+                reason: "must be from 1 to 100 percent",
+            });
+        }
+        if self.compat.is_some() && !self.compression.is_none() {
+            return Err(SplitterError::InvalidOption {
+                field: "compat",
+                reason: "needs uncompressed chunks for other tools to join",
+            });
+        }
+        if self.compat.is_some() && self.random_names {
+            return Err(SplitterError::InvalidOption {
+                field: "compat",
+                reason: "cannot be combined with random names",
+            });
+        }
+        if self.no_manifest && (self.hash.is_some() || self.parity.is_some() || self.random_names) {
+            return Err(SplitterError::InvalidOption {
+                field: "no_manifest",
+                reason: "leaves nowhere to record hashes, parity or random names",
+            });
+        }
+        if self.encryption.is_some() != self.key.is_some() {
+            return Err(SplitterError::InvalidOption {
+                field: "encryption",
+                reason: "needs both how the key is derived and the key itself",
+            });
+        }
+        if self.encrypt_metadata && self.encryption.is_none() {
+            return Err(SplitterError::InvalidOption {
+                field: "encrypt_metadata",
+                reason: "needs the chunks encrypted, under whose key it is sealed",
+            });
+        }
+        if let Some(encryption) = &self.encryption {
+            if let Some(unsupported) = encryption.unsupported() {
+                return Err(unsupported);
+            }
+            if self.no_manifest
+                || self.compat.is_some()
+                || self.join_scripts
+                || self.compression == Compression::Armor
+            {
+                return Err(SplitterError::InvalidOption {
+                    field: "encryption",
+                    reason: "needs info.json to read the chunks back, which nothing else can join",
+                });
+            }
+            if self.parity.is_some() || self.par2.is_some() || self.dedup {
+                return Err(SplitterError::InvalidOption {
+                    field: "encryption",
+                    reason: "cannot be combined with parity, PAR2 or dedup",
+                });
+            }
+            if self.container == Container::Zip
+                || self.span.is_some()
+                || is_s3_url(&self.destination)
+                || is_sftp_url(&self.destination)
+            {
+                return Err(SplitterError::InvalidOption {
+                    field: "encryption",
+                    reason: "only applies to a split into a single local directory",
+                });
+            }
+        }
+        if self.xattrs && (self.no_manifest || !xattrs::SUPPORTED) {
+            return Err(SplitterError::InvalidOption {
+                field: "xattrs",
+                reason: "are only kept in info.json, on Linux and macOS",
+            });
+        }
+        let elsewhere = self.span.is_some()
+            || self.container == Container::Zip
+            || is_s3_url(&self.destination)
+            || is_sftp_url(&self.destination);
+        if self.shred && (is_s3_url(&self.destination) || is_sftp_url(&self.destination)) {
+            return Err(SplitterError::InvalidOption {
+                field: "shred",
+                reason: "can only overwrite chunks on a local disk, not uploaded ones",
+            });
+        }
+        if self.record_times && (self.no_manifest || elsewhere) {
+            return Err(SplitterError::InvalidOption {
+                field: "record_times",
+                reason: "are only recorded in the info.json of a single local directory",
+            });
+        }
+        if self.dedup && self.hash.is_none() {
+            return Err(SplitterError::InvalidOption {
+                field: "dedup",
+                reason: "needs chunk hashes to tell identical chunks apart",
+            });
+        }
+        if self.dedup && (self.compat.is_some() || self.compression == Compression::Armor) {
+            return Err(SplitterError::InvalidOption {
+                field: "dedup",
+                reason: "needs a file for every chunk with names for other tools or armored chunks",
+            });
+        }
+        // Parity counts every chunk as a file of its own, so one file stored for several
+        // would be that many chunks lost with it
+        if self.dedup && (self.parity.is_some() || self.par2.is_some()) {
+            return Err(SplitterError::InvalidOption {
+                field: "dedup",
+                reason: "cannot be combined with parity or PAR2, which take every chunk for a file of its own",
+            });
+        }
+        if self.dedup && self.post_chunk_cmd.is_some() {
+            return Err(SplitterError::InvalidOption {
+                field: "dedup",
+                reason: "leaves chunks unwritten that the chunk command would be run on",
+            });
+        }
+        if self.dedup && is_stream(&self.input) {
+            return Err(SplitterError::InvalidOption {
+                field: "input",
+                reason: "is a pipe, and dedup reads each chunk before writing it, to know whether to",
+            });
+        }
+        if self.join_scripts && !self.compression.is_none() {
+            return Err(SplitterError::InvalidOption {
+                field: "join_scripts",
+                reason: "need uncompressed chunks to join",
+            });
+        }
+        if (self.compat.is_some() || self.join_scripts) && is_stream(&self.input) {
+            return Err(SplitterError::InvalidOption {
+                field: "input",
+                reason: "is a pipe, and names for other tools and join scripts need the whole input first",
+            });
+        }
+        let ranged = self.input_offset > 0 || self.input_length.is_some();
+        if ranged && is_stream(&self.input) {
+            return Err(SplitterError::InvalidOption {
+                field: "input",
+                reason: "is a pipe, which can only be split from its start to its end",
+            });
+        }
+        if self.input_length == Some(0) {
+            return Err(SplitterError::InvalidOption {
+                field: "input_length",
+                reason: "must be greater than zero",
+            });
+        }
+        if ranged && self.span.is_some() {
+            return Err(SplitterError::InvalidOption {
+                field: "span",
+                reason: "is planned for the whole input, not a range of it",
+            });
+        }
+        let name = symlinks::resolved_name(&self.input);
+        if self.join_scripts && !join::batch_safe(&name.to_string_lossy()) {
+            return Err(SplitterError::InvalidOption {
+                field: "join_scripts",
+                reason: "cannot name a file with quotes or control characters on Windows",
+            });
+        }
+        // Nothing but numbered chunks, compressed or not, and info.json
+        let compressed_only = self.parity.is_none()
+            && self.par2.is_none()
+            && self.mirror.is_none()
+            && !self.random_names
+            && self.prefix.is_none()
+            && self.compat.is_none()
+            && !self.no_manifest
+            && !self.join_scripts
+            && !self.dedup;
+        let extras = !self.compression.is_none()
+            || self.parity.is_some()
+            || self.par2.is_some()
+            || self.mirror.is_some()
+            || self.random_names
+            || self.prefix.is_some()
+            || self.compat.is_some()
+            || self.no_manifest
+            || self.join_scripts
+            || self.dedup;
+        if self.container == Container::Zip && extras {
+            return Err(SplitterError::InvalidOption {
+                field: "container",
+                reason: "a zip holds uncompressed, numbered chunks and info.json, and nothing else",
+            });
+        }
+        let uploaded = is_s3_url(&self.destination);
+        if uploaded && (self.container == Container::Zip || !compressed_only) {
+            return Err(SplitterError::InvalidOption {
+                field: "destination",
+                reason: "an S3 prefix holds numbered chunks, compressed or not, and info.json, and nothing else",
+            });
+        }
+        if uploaded && self.s3.connections == 0 {
+            return Err(SplitterError::InvalidOption {
+                field: "s3",
+                reason: "needs at least 1 connection",
+            });
+        }
+        let remote = is_sftp_url(&self.destination);
+        if remote && !cfg!(feature = "sftp") {
+            return Err(SplitterError::InvalidOption {
+                field: "destination",
+                reason: "sftp:// needs a build with the sftp feature",
+            });
+        }
+        if remote && (self.container == Container::Zip || !compressed_only) {
+            return Err(SplitterError::InvalidOption {
+                field: "destination",
+                reason: "an SFTP destination holds numbered chunks, compressed or not, and info.json, and nothing else",
+            });
+        }
+        if self.max_dir_files == 0 {
+            return Err(SplitterError::InvalidOption {
+                field: "max_dir_files",
+                reason: "must be at least 1",
+            });
+        }
+        if self.shard_dirs == ShardDirs::Shard
+            && (self.compat.is_some() || self.no_manifest || self.join_scripts)
+        {
+            return Err(SplitterError::InvalidOption {
+                field: "shard_dirs",
+                reason: "puts chunks where only info.json finds them, not other tools or join scripts",
+            });
+        }
+        for (field, mode) in [("file_mode", self.file_mode), ("dir_mode", self.dir_mode)] {
+            if mode.is_some_and(|mode| mode > MAX_MODE) {
+                return Err(SplitterError::InvalidOption {
+                    field,
+                    reason: "must be an octal mode of at most 7777",
+                });
+            }
+            if mode.is_some() && (uploaded || remote) {
+                return Err(SplitterError::InvalidOption {
+                    field,
+                    reason: "only applies to a local destination",
+                });
+            }
+        }
+        if self.fsync && (uploaded || remote) {
+            return Err(SplitterError::InvalidOption {
+                field: "fsync",
+                reason: "only applies to a local destination",
+            });
+        }
+        #[cfg(feature = "sftp")]
+        if remote && self.sftp.window == 0 {
+            return Err(SplitterError::InvalidOption {
+                field: "sftp",
+                reason: "needs a window of at least 1",
+            });
+        }
+        if let Some(span) = &self.span {
+            if self.container == Container::Zip || uploaded || remote || !compressed_only {
+                return Err(SplitterError::InvalidOption {
+                    field: "span",
+                    reason: "a split across several directories writes numbered chunks, compressed or not, and info.json, and nothing else",
+                });
+            }
+            if self.post_chunk_cmd.is_some() {
+                return Err(SplitterError::InvalidOption {
+                    field: "post_chunk_cmd",
+                    reason: "isn't run for a split across several directories",
+                });
+            }
+            if span.fit && !self.compression.is_none() {
+                return Err(SplitterError::InvalidOption {
+                    field: "span",
+                    reason: "can only fit a chunk to each volume uncompressed, as only then is its size on disk known ahead",
+                });
+            }
+            if is_stream(&self.input) {
+                return Err(SplitterError::InvalidOption {
+                    field: "input",
+                    reason: "is a pipe, and a split across several directories needs its size to plan",
+                });
+            }
+            let mut directories = vec![&self.destination];
+            for volume in &span.volumes {
+                if directories.contains(&volume) {
+                    return Err(SplitterError::InvalidOption {
+                        field: "span",
+                        reason: "names the same directory twice",
+                    });
+                }
+                directories.push(volume);
+            }
+        }
+        if let Some(hook) = &self.post_chunk_cmd {
+            hook.validate("post_chunk_cmd")?;
+            if self.container == Container::Zip || uploaded || remote {
+                return Err(SplitterError::InvalidOption {
+                    field: "post_chunk_cmd",
+                    reason: "needs chunk files in a local directory",
+                });
+            }
+            if self.random_names {
+                return Err(SplitterError::InvalidOption {
+                    field: "post_chunk_cmd",
+                    reason: "cannot be given random names, which aren't known until info.json is written",
+                });
+            }
+        }
+        if self.mirror.as_ref() == Some(&self.destination) {
+            return Err(SplitterError::InvalidOption {
+                field: "mirror",
+                reason: "must be a different directory from the destination",
+            });
+        }
+        if !(self.min_ratio.is_finite() && self.min_ratio >= 0.0) {
+            return Err(SplitterError::InvalidOption {
+                field: "min_ratio",
+                reason: "must be a number of at least 0",
+            });
+        }
+        Ok(())
+    }
+}
+
+// Sets up `SplitOptions` one field at a time, so both front ends arrive at the same
+// checked options however many there are.
+#[derive(Clone, Debug)]
+pub struct SplitOptionsBuilder {
+    options: SplitOptions,
+    // `parts` goes instead of a chunk size, not with one
+    chunk_size_set: bool,
+}
+
+impl SplitOptionsBuilder {
+    pub fn chunk_size(mut self, chunk_size: u64) -> SplitOptionsBuilder {
+        self.options.chunk_size = chunk_size;
+        self.chunk_size_set = true;
+        self
+    }
+
+    pub fn parts(mut self, parts: Option<u64>) -> SplitOptionsBuilder {
+        self.options.parts = parts;
+        self
+    }
+
+    pub fn min_chunk_size(mut self, min_chunk_size: u64) -> SplitOptionsBuilder {
+        self.options.min_chunk_size = min_chunk_size;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> SplitOptionsBuilder {
+        self.options.threads = threads;
+        self
+    }
+
+    pub fn hash(mut self, hash: Option<HashAlgorithm>) -> SplitOptionsBuilder {
+        self.options.hash = hash;
+        self
+    }
+
+    pub fn dedup(mut self, dedup: bool) -> SplitOptionsBuilder {
+        self.options.dedup = dedup;
+        self
+    }
+
+    pub fn mmap(mut self, mmap: bool) -> SplitOptionsBuilder {
+        self.options.mmap = mmap;
+        self
+    }
+
+    pub fn keep_partial(mut self, keep_partial: bool) -> SplitOptionsBuilder {
+        self.options.keep_partial = keep_partial;
+        self
+    }
+
+    pub fn shred(mut self, shred: bool) -> SplitOptionsBuilder {
+        self.options.shred = shred;
+        self
+    }
+
+    pub fn fsync(mut self, fsync: bool) -> SplitOptionsBuilder {
+        self.options.fsync = fsync;
+        self
+    }
+
+    pub fn allow_nested(mut self, allow_nested: bool) -> SplitOptionsBuilder {
+        self.options.allow_nested = allow_nested;
+        self
+    }
+
+    // At the codec's default level unless `compression_level` says otherwise.
+    pub fn compression(mut self, compression: Compression) -> SplitOptionsBuilder {
+        self.options.compression = compression;
+        self.options.compression_level = compression.default_level();
+        self
+    }
+
+    pub fn compression_level(mut self, level: u32) -> SplitOptionsBuilder {
+        self.options.compression_level = level;
+        self.options.compression = self.options.compression.at_level(level);
+        self
+    }
+
+    pub fn min_ratio(mut self, min_ratio: f64) -> SplitOptionsBuilder {
+        self.options.min_ratio = min_ratio;
+        self
+    }
+
+    pub fn random_names(mut self, random_names: bool) -> SplitOptionsBuilder {
+        self.options.random_names = random_names;
+        self
+    }
+
+    pub fn prefix(mut self, prefix: Option<String>) -> SplitOptionsBuilder {
+        self.options.prefix = prefix;
+        self
+    }
+
+    pub fn in_flight(mut self, in_flight: usize) -> SplitOptionsBuilder {
+        self.options.in_flight = in_flight;
+        self
+    }
+
+    pub fn parity(mut self, parity: Option<Parity>) -> SplitOptionsBuilder {
+        self.options.parity = parity;
+        self
+    }
+
+    pub fn mirror(mut self, mirror: Option<PathBuf>) -> SplitOptionsBuilder {
+        self.options.mirror = mirror;
+        self
+    }
+
+    pub fn mirror_failure(mut self, mirror_failure: MirrorFailure) -> SplitOptionsBuilder {
+        self.options.mirror_failure = mirror_failure;
+        self
+    }
+
+    pub fn par2(mut self, par2: Option<u32>) -> SplitOptionsBuilder {
+        self.options.par2 = par2;
+        self
+    }
+
+    pub fn compat(mut self, compat: Option<Compat>) -> SplitOptionsBuilder {
+        self.options.compat = compat;
+        self
+    }
+
+    pub fn no_manifest(mut self, no_manifest: bool) -> SplitOptionsBuilder {
+        self.options.no_manifest = no_manifest;
+        self
+    }
+
+    pub fn join_scripts(mut self, join_scripts: bool) -> SplitOptionsBuilder {
+        self.options.join_scripts = join_scripts;
+        self
+    }
+
+    pub fn container(mut self, container: Container) -> SplitOptionsBuilder {
+        self.options.container = container;
+        self
+    }
+
+    pub fn s3(mut self, s3: S3Options) -> SplitOptionsBuilder {
+        self.options.s3 = s3;
+        self
+    }
+
+    #[cfg(feature = "sftp")]
+    pub fn sftp(mut self, sftp: SftpOptions) -> SplitOptionsBuilder {
+        self.options.sftp = sftp;
+        self
+    }
+
+    pub fn post_chunk_cmd(mut self, hook: Option<ChunkHook>) -> SplitOptionsBuilder {
+        self.options.post_chunk_cmd = hook;
+        self
+    }
+
+    pub fn span(mut self, span: Option<Span>) -> SplitOptionsBuilder {
+        self.options.span = span;
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> SplitOptionsBuilder {
+        self.options.strict = strict;
+        self
+    }
+
+    pub fn xattrs(mut self, xattrs: bool) -> SplitOptionsBuilder {
+        self.options.xattrs = xattrs;
+        self
+    }
+
+    pub fn record_times(mut self, record_times: bool) -> SplitOptionsBuilder {
+        self.options.record_times = record_times;
+        self
+    }
+
+    pub fn file_mode(mut self, mode: Option<u32>) -> SplitOptionsBuilder {
+        self.options.file_mode = mode;
+        self
+    }
+
+    pub fn dir_mode(mut self, mode: Option<u32>) -> SplitOptionsBuilder {
+        self.options.dir_mode = mode;
+        self
+    }
+
+    pub fn input_offset(mut self, offset: u64) -> SplitOptionsBuilder {
+        self.options.input_offset = offset;
+        self
+    }
+
+    pub fn input_length(mut self, length: Option<u64>) -> SplitOptionsBuilder {
+        self.options.input_length = length;
+        self
+    }
+
+    pub fn shard_dirs(mut self, shard_dirs: ShardDirs) -> SplitOptionsBuilder {
+        self.options.shard_dirs = shard_dirs;
+        self
+    }
+
+    pub fn max_dir_files(mut self, max_dir_files: usize) -> SplitOptionsBuilder {
+        self.options.max_dir_files = max_dir_files;
+        self
+    }
+
+    // `key` is what `encryption` derives from the passphrase, or the key file's, whose
+    // fingerprint it gets, or the age recipients, which it lists.
+    pub fn encryption(
+        mut self,
+        encryption: Option<Encryption>,
+        key: Option<ChunkKey>,
+    ) -> SplitOptionsBuilder {
+        self.options.encryption = match (encryption, &key) {
+            (Some(encryption), Some(key)) => Some(key.record(encryption)),
+            (encryption, _) => encryption,
+        };
+        self.options.key = key;
+        self
+    }
+
+    pub fn encrypt_metadata(mut self, encrypt: bool) -> SplitOptionsBuilder {
+        self.options.encrypt_metadata = encrypt;
+        self
+    }
+
+    pub fn retry(mut self, policy: RetryPolicy) -> SplitOptionsBuilder {
+        self.options.retry = policy;
+        self
+    }
+
+    pub fn buffer_size(mut self, buffer_size: usize) -> SplitOptionsBuilder {
+        self.options.buffer_size = buffer_size;
+        self
+    }
+
+    pub fn adaptive_buffers(mut self, adaptive: bool) -> SplitOptionsBuilder {
+        self.options.adaptive_buffers = adaptive;
+        self
+    }
+
+    pub fn max_memory(mut self, max_memory: Option<u64>) -> SplitOptionsBuilder {
+        self.options.max_memory = max_memory;
+        self
+    }
+
+    pub fn direct_io(mut self, direct_io: bool) -> SplitOptionsBuilder {
+        self.options.direct_io = direct_io;
+        self
+    }
+
+    pub fn lock_max_age(mut self, max_age: Duration) -> SplitOptionsBuilder {
+        self.options.lock_max_age = max_age;
+        self
+    }
+
+    pub fn symlinks(mut self, policy: SymlinkPolicy) -> SplitOptionsBuilder {
+        self.options.symlinks = policy;
+        self
+    }
+
+    pub fn build(self) -> Result<SplitOptions> {
+        if self.options.parts.is_some() && self.chunk_size_set {
+            return Err(SplitterError::InvalidOption {
+                field: "parts",
+                reason: "goes instead of a chunk size, not with one",
+            });
+        }
+        let options = self.options.with_parts()?.into_owned();
+        options.validate()?;
+        Ok(options)
+    }
+}
+
+// What was written: the same chunk list that went into the manifest.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SplitReport {
+    pub destination: PathBuf,
+    pub total_size: u64,
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    pub compression: Compression,
+    // What the chunk files take up on disk, less than `total_size` when compressed
+    #[serde(default)]
+    pub stored_size: u64,
+    // With `SplitOptions::dedup`: what the files of the chunks stored in another's would
+    // have taken up
+    #[serde(default)]
+    pub deduplicated_size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parity: Option<Box<ParityInfo>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<MirrorReport>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub par2: Option<Par2Report>,
+    // With `SplitOptions::span`: the volumes written, the first being `destination`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<Box<SpanPlan>>,
+    // The input's size or modification time was different by the end, so the chunks
+    // hold what was read of it at each point, not any one version of it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub input_changed: bool,
+    // With `adaptive_buffers`: the buffer size settled on, and the one it was faster than
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adapted_buffers: Option<(usize, usize)>,
+    pub chunks: Vec<ChunkEntry>,
+}
+
+// How the copy in `SplitOptions::mirror` came out.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MirrorReport {
+    pub directory: PathBuf,
+    // Why writing the mirror was given up on, after which what it got was removed
+    // again (or kept, with `keep_partial`); None when it is a complete copy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure: Option<String>,
+}
+
+// Fails unless `path` is something a split can read: a file, or something read front
+// to back such as a named pipe, but not a directory or a socket, with
+// `symlinks::check_input` under `policy` for a link.
+fn check_readable(path: &Path, policy: SymlinkPolicy) -> Result<()> {
+    symlinks::check_input(path, policy)?;
+    let kind = fs::metadata(path).at(path)?.file_type();
+    #[cfg(unix)]
+    let socket = std::os::unix::fs::FileTypeExt::is_socket(&kind);
+    #[cfg(not(unix))]
+    let socket = false;
+    match kind.is_dir() || socket {
+        true => Err(SplitterError::NotAFile {
+            path: path.to_path_buf(),
+        }),
+        false => Ok(()),
+    }
+}
+
+// Split `options.input` into `chunk_size` pieces inside `options.destination`,
+// hashing each chunk when `hash` is given. With more than one thread, chunks are
+// written by a pool of workers. `progress` hears about every chunk and buffer. When
+// the split fails or `cancel` is set, the chunks written so far are removed again
+// (along with the destination, if this call created it) unless `keep_partial` is set.
+// Chunk names and contents depend only on the input and `chunk_size`, not on which
+// worker wrote what (see `Manifest`). `post_chunk_cmd` runs for each chunk as it is
+// finished; see `ChunkHook`.
+pub fn split_file(
+    options: &SplitOptions,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<SplitReport> {
+    let options = &*options.with_parts()?;
+    let (input_path, savedir) = (options.input.as_path(), options.destination.as_path());
+    options.validate()?;
+    check_readable(input_path, options.symlinks)?;
+    options.input_range()?;
+    if !options.allow_nested
+        && let Some(set) = containing_set(input_path)
+    {
+        return Err(SplitterError::NestedSplit {
+            path: input_path.to_path_buf(),
+            set,
+        });
+    }
+    if is_s3_url(savedir) {
+        return split_to_s3(options, progress, cancel);
+    }
+    #[cfg(feature = "sftp")]
+    if is_sftp_url(savedir) {
+        return split_to_sftp(options, progress, cancel);
+    }
+    if options.container == Container::Zip {
+        return split_to_zip(options, progress, cancel);
+    }
+    if let Some(span) = &options.span {
+        return split_spanned(options, span, progress, cancel);
+    }
+
+    info!(
+        "splitting {} into {} in chunks of {} bytes",
+        input_path.display(),
+        savedir.display(),
+        options.chunk_size
+    );
+
+    let max_age = options.lock_max_age;
+    let (created, _lock) = prepare_destination(savedir, "split", max_age)?;
+    let (mirror, _mirror_lock) = match &options.mirror {
+        Some(mirror) => {
+            let (mirror_created, lock) = prepare_destination(mirror, "split", max_age)
+                .inspect_err(|_| {
+                    remove_partial(savedir, created, options.shred, options.prefix.as_deref());
+                })?;
+            (Some((mirror.as_path(), mirror_created)), Some(lock))
+        }
+        None => (None, None),
+    };
+    let remove_all = || {
+        remove_partial(savedir, created, options.shred, options.prefix.as_deref());
+        if let Some((mirror, mirror_created)) = mirror {
+            remove_partial(
+                mirror,
+                mirror_created,
+                options.shred,
+                options.prefix.as_deref(),
+            );
+        }
+    };
+    let prepared = modes::prepare(savedir, options.dir_mode).and_then(|()| match mirror {
+        Some((mirror, _)) => modes::prepare(mirror, options.dir_mode),
+        None => Ok(()),
+    });
+    prepared.inspect_err(|_| remove_all())?;
+    // Told apart by what they are rather than by name, as `validate` couldn't
+    if let Some((mirror, _)) = mirror
+        && fs::canonicalize(mirror).ok() == fs::canonicalize(savedir).ok()
+    {
+        remove_all();
+        return Err(SplitterError::InvalidOption {
+            field: "mirror",
+            reason: "must be a different directory from the destination",
+        });
+    }
+
+    // The manifest goes last, once every chunk is known to be complete
+    let count = input_size(options)
+        .and_then(|total| {
+            total
+                .map(|total| chunk_count(total, options.chunk_size))
+                .transpose()
+        })
+        .inspect_err(|_| remove_all())?;
+    let mirror_dir = mirror.map(|(mirror, _)| mirror);
+    let shards = plan_shards(options, count, mirror_dir).inspect_err(|_| remove_all())?;
+    let mut store = LocalDirStore::new(savedir)
+        .compressed(options.compression, options.compression_level)
+        .retrying(options.retry.clone())
+        .with_io(Arc::new(options.io()))
+        .with_key(options.key.clone())
+        .concealing(options.encrypt_metadata);
+    if let Some(count) = count {
+        store = store.counted(count);
+    }
+    if let Some(per_directory) = shards {
+        store = store.sharded(per_directory);
+    }
+    if let Some(mirror) = mirror_dir {
+        info!("mirroring the chunks to {}", mirror.display());
+        let fatal = options.mirror_failure == MirrorFailure::Abort;
+        store = store.mirrored(mirror, fatal);
+    }
+    let before = InputState::of(input_path);
+    let started = SystemTime::now();
+    let written = match &options.post_chunk_cmd {
+        Some(hook) => {
+            // Unknown for a pipe, until it ends
+            let count = count.unwrap_or(0);
+            let transfers = hook.track_transfers.then_some(savedir);
+            let hooks = Hooks::start(hook, Phase::PostChunk, count, transfers, cancel);
+            let mut hooked = |event: ProgressEvent| {
+                if let ProgressEvent::ChunkFinished { index, .. } = &event {
+                    hooks.run(*index, written_path(options, *index, count, shards));
+                }
+                progress(event);
+            };
+            let written = write_chunks(options, &mut store, &mut hooked, hooks.token());
+            hooks.finish(written)
+        }
+        None => write_chunks(options, &mut store, progress, cancel),
+    };
+    let result = written.and_then(|chunks| {
+        let input_changed = check_input(options, before)?;
+        let deduplicated_size = deduplicated_size(&store, &chunks)?;
+        let mut manifest = input_manifest(options, chunks)?;
+        manifest.shards = shards;
+        if options.record_times {
+            timestamps::record(savedir, &mut manifest, started);
+        }
+        if let Some(scheme) = options.parity {
+            let info = parity::write(savedir, &manifest, scheme, cancel)?;
+            for entry in &info.files {
+                store.mirror_copy(&entry.name)?;
+            }
+            manifest.parity = Some(info);
+        }
+        if !options.no_manifest {
+            store.write_info(&manifest)?;
+        }
+        if options.join_scripts {
+            // For the scripts to check the joined file by, so of the whole input, or of
+            // the range of it that was split
+            let sha256 = match options.input_range()? {
+                Some(_) => {
+                    let mut hasher = HashAlgorithm::Sha256.hasher();
+                    let mut sink = Counting {
+                        inner: io::sink(),
+                        copied: &mut |_| {},
+                        cancel,
+                    };
+                    let io = store.io();
+                    copy_overlapped(
+                        io,
+                        &mut open_input(options, io)?,
+                        &mut sink,
+                        Some(&mut hasher),
+                    )
+                    .at(input_path)?;
+                    hasher.finish()
+                }
+                None => hash_file(input_path, HashAlgorithm::Sha256, &mut |_| {}, cancel)?,
+            };
+            join::write(savedir, &manifest, &sha256)?;
+            for name in join::SCRIPT_NAMES {
+                store.mirror_copy(name)?;
+            }
+        }
+        let par2 = match options.par2 {
+            Some(percent) => {
+                let mut names: Vec<String> = manifest
+                    .chunks
+                    .iter()
+                    .filter(|e| e.same_as.is_none())
+                    .map(|e| e.name.clone())
+                    .collect();
+                if !options.no_manifest {
+                    names.push(MANIFEST_NAME.to_string());
+                }
+                // Kept out of the `FILE.*` that joins the chunks
+                let base = match options.compat {
+                    Some(_) => format!("{}-recovery", manifest.original_filename),
+                    None => manifest.original_filename.clone(),
+                };
+                let report = par2::write(savedir, &base, &names, percent, cancel)?;
+                for name in &report.files {
+                    store.mirror_copy(name)?;
+                }
+                Some(report)
+            }
+            None => None,
+        };
+        modes::finish(savedir, options.file_mode, options.dir_mode)?;
+        if let Some((mirror, _)) = mirror {
+            modes::finish(mirror, options.file_mode, options.dir_mode)?;
+        }
+        if options.fsync {
+            sync_set(savedir, options.prefix.as_deref())?;
+            if let Some((mirror, _)) = mirror {
+                sync_set(mirror, options.prefix.as_deref())?;
+            }
+        }
+        Ok((manifest, par2, input_changed, deduplicated_size))
+    });
+    let (manifest, par2, input_changed, deduplicated_size) = match result {
+        Ok(written) => written,
+        Err(e) => {
+            if options.keep_partial {
+                info!("split failed; keeping the chunks written so far");
+            } else {
+                info!("split failed; removing the chunks written so far");
+                remove_all();
+            }
+            return Err(e);
+        }
+    };
+    let mirror = mirror.map(|(directory, mirror_created)| {
+        let failure = store.mirror_failure();
+        if failure.is_some() && !options.keep_partial {
+            info!("removing the incomplete mirror in {}", directory.display());
+            remove_partial(
+                directory,
+                mirror_created,
+                options.shred,
+                options.prefix.as_deref(),
+            );
+        }
+        MirrorReport {
+            directory: directory.to_path_buf(),
+            failure,
+        }
+    });
+    info!(
+        "split {} into {} chunks",
+        input_path.display(),
+        manifest.chunks.len()
+    );
+    let mut stored_size = 0;
+    for entry in manifest.chunks.iter().filter(|e| e.same_as.is_none()) {
+        let chunk_path = savedir.join(&entry.name);
+        stored_size += fs::metadata(&chunk_path).at(&chunk_path)?.len();
+    }
+    let report = SplitReport {
+        destination: savedir.to_path_buf(),
+        total_size: manifest.chunks.iter().map(|chunk| chunk.size).sum(),
+        compression: manifest.compression,
+        stored_size,
+        deduplicated_size,
+        parity: manifest.parity.map(Box::new),
+        mirror,
+        par2,
+        span: None,
+        input_changed,
+        adapted_buffers: store.io().chosen(),
+        chunks: manifest.chunks,
+    };
+    progress(ProgressEvent::Completed {
+        report: Report::Split(report.clone()),
+    });
+    Ok(report)
+}
+
+// `split_file` into an archive. Its entries go in one after another, so there is one
+// thread writing and none of the faster paths for chunk files; the input is still read
+// ahead of it. When the split fails the archive is removed, or kept as far as it got
+// with `keep_partial`, though without its central directory no unzip can read that.
+fn split_to_zip(
+    options: &SplitOptions,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<SplitReport> {
+    let (input_path, archive_path) = (options.input.as_path(), options.destination.as_path());
+    info!(
+        "splitting {} into the archive {} in chunks of {} bytes",
+        input_path.display(),
+        archive_path.display(),
+        options.chunk_size
+    );
+    if options.mmap || options.threads > 1 {
+        debug!("an archive is written on one thread, with buffered I/O");
+    }
+    let io = options.io();
+    let mut store = ZipStore::create(archive_path, options.chunk_size)?;
+    let result = split_sequentially(options, &io, &mut store, progress, cancel).and_then(
+        |(manifest, input_changed)| {
+            let stored_size = store.finish()?;
+            modes::set(archive_path, options.file_mode)?;
+            Ok((manifest, input_changed, stored_size))
+        },
+    );
+    let (manifest, input_changed, stored_size) = match result {
+        Ok(written) => written,
+        Err(e) => {
+            if options.keep_partial {
+                info!("split failed; keeping the archive as far as it got");
+            } else {
+                info!("split failed; removing the archive");
+                let _ = shred::remove_file(archive_path, options.shred);
+            }
+            return Err(e);
+        }
+    };
+    info!(
+        "split {} into {} chunks",
+        input_path.display(),
+        manifest.chunks.len()
+    );
+    let report = SplitReport {
+        destination: archive_path.to_path_buf(),
+        total_size: manifest.chunks.iter().map(|chunk| chunk.size).sum(),
+        compression: Compression::None,
+        stored_size,
+        deduplicated_size: 0,
+        parity: None,
+        mirror: None,
+        par2: None,
+        span: None,
+        input_changed,
+        adapted_buffers: io.chosen(),
+        chunks: manifest.chunks,
+    };
+    progress(ProgressEvent::Completed {
+        report: Report::Split(report.clone()),
+    });
+    Ok(report)
+}
+
+// `split_file` into objects under an S3 prefix, from one thread reading the input while
+// the store uploads what it has read on several connections. When the split fails what
+// was uploaded is deleted again, or kept with `keep_partial`; multipart uploads left
+// unfinished are aborted either way. Chunks are all compressed or none are: the store
+// gets them as a stream, with no chance to decide chunk by chunk.
+fn split_to_s3(
+    options: &SplitOptions,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<SplitReport> {
+    let (input_path, url) = (options.input.as_path(), options.destination.as_path());
+    info!(
+        "splitting {} into {} in chunks of {} bytes",
+        input_path.display(),
+        url.display(),
+        options.chunk_size
+    );
+    if options.mmap || options.threads > 1 {
+        debug!("an upload is read on one thread, with buffered I/O");
+    }
+    let count = match input_size(options)? {
+        Some(total) => Some(chunk_count(total, options.chunk_size)?),
+        None => None,
+    };
+    let io = options.io();
+    let mut store = S3Store::create(url, &options.s3, options.chunk_size, cancel)?
+        .compressed(options.compression, options.compression_level);
+    if let Some(count) = count {
+        store = store.counted(count);
+    }
+    let limits = (options.s3.connections, options.s3.retries);
+    let (mut store, written) = split_remote(options, &io, store, limits, progress, cancel);
+    let (manifest, input_changed) = match written {
+        Ok(written) => written,
+        Err(e) => {
+            match options.keep_partial {
+                true => info!("split failed; keeping the chunks uploaded so far"),
+                false => info!("split failed; deleting the chunks uploaded"),
+            }
+            store.abort(options.keep_partial);
+            return Err(e);
+        }
+    };
+    info!(
+        "split {} into {} chunks",
+        input_path.display(),
+        manifest.chunks.len()
+    );
+    let report = SplitReport {
+        destination: url.to_path_buf(),
+        total_size: manifest.chunks.iter().map(|chunk| chunk.size).sum(),
+        compression: options.compression,
+        stored_size: store.stored_size(),
+        deduplicated_size: 0,
+        parity: None,
+        mirror: None,
+        par2: None,
+        span: None,
+        input_changed,
+        adapted_buffers: io.chosen(),
+        chunks: manifest.chunks,
+    };
+    progress(ProgressEvent::Completed {
+        report: Report::Split(report.clone()),
+    });
+    Ok(report)
+}
+
+// `split_file` into a directory on a server over SFTP, from one thread, with the writes
+// pipelined over one connection. When the split fails what was written is removed
+// again, as are the directories it made, unless `keep_partial`. As with S3, chunks are
+// all compressed or none are.
+#[cfg(feature = "sftp")]
+fn split_to_sftp(
+    options: &SplitOptions,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<SplitReport> {
+    let (input_path, url) = (options.input.as_path(), options.destination.as_path());
+    info!(
+        "splitting {} into {} in chunks of {} bytes",
+        input_path.display(),
+        url.display(),
+        options.chunk_size
+    );
+    if options.mmap || options.threads > 1 {
+        debug!("an SFTP destination is written from one thread, with buffered I/O");
+    }
+    let count = match input_size(options)? {
+        Some(total) => Some(chunk_count(total, options.chunk_size)?),
+        None => None,
+    };
+    let io = options.io();
+    let mut store = SftpStore::create(url, &options.sftp, cancel)?
+        .compressed(options.compression, options.compression_level);
+    if let Some(count) = count {
+        store = store.counted(count);
+    }
+    let limits = (options.sftp.connections, options.sftp.retries);
+    let (mut store, written) = split_remote(options, &io, store, limits, progress, cancel);
+    let (manifest, input_changed) = match written {
+        Ok(written) => written,
+        Err(e) => {
+            match options.keep_partial {
+                true => info!("split failed; keeping the chunks written so far"),
+                false => info!("split failed; removing the chunks written"),
+            }
+            store.abort(options.keep_partial);
+            return Err(e);
+        }
+    };
+    info!(
+        "split {} into {} chunks",
+        input_path.display(),
+        manifest.chunks.len()
+    );
+    let report = SplitReport {
+        destination: url.to_path_buf(),
+        total_size: manifest.chunks.iter().map(|chunk| chunk.size).sum(),
+        compression: options.compression,
+        stored_size: store.stored_size(),
+        deduplicated_size: 0,
+        parity: None,
+        mirror: None,
+        par2: None,
+        span: None,
+        input_changed,
+        adapted_buffers: io.chosen(),
+        chunks: manifest.chunks,
+    };
+    progress(ProgressEvent::Completed {
+        report: Report::Split(report.clone()),
+    });
+    Ok(report)
+}
+
+// `split_file` across the volumes `plan_span` finds room on, filled in turn from one
+// read of the input. Chunks keep the numbers they have in the whole set, so gathering
+// the volumes into one directory makes an ordinary set, and every volume gets the same
+// info.json listing all of them but for the volume it says it is. Destinations with
+// no room are passed over. When the split fails, what it wrote is removed from every
+// volume unless `keep_partial`.
+fn split_spanned(
+    options: &SplitOptions,
+    span: &Span,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<SplitReport> {
+    let input_path = options.input.as_path();
+    let before = InputState::of(input_path);
+    let plan = plan_span(options)?;
+    if plan.unplaced > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::StorageFull,
+            format!(
+                "the destinations have room for {} of the {} to split; add another, or leave a smaller margin",
+                format_size(plan.total_size - plan.unplaced),
+                format_size(plan.total_size)
+            ),
+        ))
+        .at(&options.destination);
+    }
+    // Those after the last chunk aren't needed at all
+    let chunks: usize = plan.volumes.iter().map(|volume| volume.count).sum();
+    for volume in &plan.volumes {
+        if volume.count == 0 && volume.first < chunks {
+            warn!(
+                "{} has no room for a chunk; passing it over",
+                volume.directory.display()
+            );
+        }
+    }
+    let mut used: Vec<&PlannedVolume> = plan.used().collect();
+    if used.is_empty() {
+        // An empty input still gets its info.json
+        used.extend(plan.volumes.first());
+    }
+    info!(
+        "splitting {} across {} volumes in chunks of {} bytes",
+        input_path.display(),
+        used.len(),
+        options.chunk_size
+    );
+
+    let mut prepared = Vec::new();
+    let remove_all = |prepared: &[(&Path, bool)]| {
+        for &(directory, created) in prepared {
+            remove_partial(directory, created, options.shred, options.prefix.as_deref());
+        }
+    };
+    let mut canonical = Vec::new();
+    let mut locks = Vec::new();
+    for volume in &used {
+        let directory = volume.directory.as_path();
+        let (created, lock) = prepare_destination(directory, "split", options.lock_max_age)
+            .inspect_err(|_| remove_all(&prepared))?;
+        prepared.push((directory, created));
+        locks.push(lock);
+        modes::prepare(directory, options.dir_mode).inspect_err(|_| remove_all(&prepared))?;
+        // Told apart by what they are rather than by name, as `validate` couldn't
+        let path = fs::canonicalize(directory).at(directory)?;
+        if canonical.contains(&path) {
+            remove_all(&prepared);
+            return Err(SplitterError::InvalidOption {
+                field: "span",
+                reason: "names the same directory twice",
+            });
+        }
+        canonical.push(path);
+    }
+
+    let io = Arc::new(options.io());
+    let written = write_volumes(options, &io, span, &used, progress, cancel);
+    let result = written.and_then(|chunks| {
+        let input_changed = check_input(options, before)?;
+        let volumes: Vec<VolumeEntry> = used
+            .iter()
+            .map(|volume| VolumeEntry {
+                first: volume.first,
+                count: volume.count,
+            })
+            .collect();
+        let mut manifest = Manifest {
+            chunk_size: (!span.fit).then_some(options.chunk_size),
+            random_names: false,
+            compat: None,
+            shards: None,
+            ..input_manifest(options, chunks)?
+        };
+        for (number, volume) in used.iter().enumerate() {
+            manifest.span = Some(SpanInfo {
+                volume: number,
+                volumes: volumes.clone(),
+            });
+            manifest.save(&volume.directory)?;
+        }
+        for volume in &used {
+            modes::finish(&volume.directory, options.file_mode, options.dir_mode)?;
+            if options.fsync {
+                sync_set(&volume.directory, None)?;
+            }
+        }
+        Ok((manifest, input_changed))
+    });
+    let (manifest, input_changed) = match result {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            if options.keep_partial {
+                info!("split failed; keeping the chunks written so far");
+            } else {
+                info!("split failed; removing the chunks written so far");
+                remove_all(&prepared);
+            }
+            return Err(e);
+        }
+    };
+    info!(
+        "split {} into {} chunks on {} volumes",
+        input_path.display(),
+        manifest.chunks.len(),
+        used.len()
+    );
+    let mut stored_size = 0;
+    for volume in &used {
+        for entry in &manifest.chunks[volume.first..volume.first + volume.count] {
+            let chunk_path = volume.directory.join(&entry.name);
+            stored_size += fs::metadata(&chunk_path).at(&chunk_path)?.len();
+        }
+    }
+    let report = SplitReport {
+        destination: options.destination.clone(),
+        total_size: plan.total_size,
+        compression: manifest.compression,
+        stored_size,
+        deduplicated_size: 0,
+        parity: None,
+        mirror: None,
+        par2: None,
+        span: Some(Box::new(SpanPlan {
+            volumes: used.into_iter().cloned().collect(),
+            ..plan
+        })),
+        input_changed,
+        adapted_buffers: io.chosen(),
+        chunks: manifest.chunks,
+    };
+    progress(ProgressEvent::Completed {
+        report: Report::Split(report.clone()),
+    });
+    Ok(report)
+}
+
+// The chunks of every volume in `volumes`, written into its directory in turn from one
+// read of the input, which must still be as large as it was when they were planned.
+fn write_volumes(
+    options: &SplitOptions,
+    io: &Arc<Io>,
+    span: &Span,
+    volumes: &[&PlannedVolume],
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Vec<ChunkEntry>> {
+    let input_path = options.input.as_path();
+    let file = File::open(input_path).doing("opening", input_path)?;
+    let file = io.released(file, 0);
+    let mut input = BufReader::with_capacity(io.buffer_size(), file);
+    let count = volumes.iter().map(|volume| volume.count).sum();
+    let mut chunks = Vec::with_capacity(count);
+    for volume in volumes {
+        debug!(
+            "chunks {} to {} go into {}",
+            volume.first,
+            volume.first + volume.count,
+            volume.directory.display()
+        );
+        let store = LocalDirStore::new(&volume.directory)
+            .compressed(options.compression, options.compression_level)
+            .counted(count)
+            .retrying(options.retry.clone())
+            .with_io(Arc::clone(io));
+        let mut left = volume.size;
+        for index in volume.first..volume.first + volume.count {
+            cancel.check()?;
+            let size = match span.fit {
+                true => left,
+                false => options.chunk_size.min(left),
+            };
+            progress(ProgressEvent::ChunkStarted { index, size });
+            let mut sample = Vec::new();
+            let mut compression = store.compression();
+            if compression.shrinks() && options.min_ratio > 0.0 {
+                (&mut input)
+                    .take(size.min(SAMPLE_SIZE))
+                    .read_to_end(&mut sample)
+                    .at(input_path)?;
+                compression = chunk_compression(options, &store, index, &sample)?;
+            }
+            let rest = size - sample.len() as u64;
+            let mut chunk = io::Cursor::new(sample).chain((&mut input).take(rest));
+            let mut copied = |delta| progress(ProgressEvent::BytesCopied { delta });
+            let entry = store_chunk(
+                options,
+                &store,
+                index,
+                compression,
+                &mut chunk,
+                &mut copied,
+                cancel,
+            )?;
+            if entry.size != size {
+                return Err(SplitterError::ChangedSize {
+                    path: input_path.to_path_buf(),
+                });
+            }
+            progress(ProgressEvent::ChunkFinished {
+                index,
+                hash: entry.hash.clone(),
+            });
+            left -= size;
+            chunks.push(entry);
+        }
+    }
+    Ok(chunks)
+}
+
+// The chunks written into `store` one after another, with the input read ahead, and
+// then the manifest, which is returned with whether the input changed meanwhile.
+fn split_sequentially<S: ChunkStore>(
+    options: &SplitOptions,
+    io: &Io,
+    store: &mut S,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<(Manifest, bool)> {
+    let input_path = options.input.as_path();
+    let before = InputState::of(input_path);
+    let input_file = open_input(options, io)?;
+    let mut input_file = BufReader::with_capacity(io.buffer_size(), input_file);
+    let (chunk_size, hash) = (options.chunk_size, options.hash);
+    let chunks = split_into_with(
+        io,
+        &mut input_file,
+        input_path,
+        store,
+        chunk_size,
+        hash,
+        progress,
+        cancel,
+    )?;
+    let manifest = Manifest {
+        compression: store.compression(),
+        random_names: false,
+        compat: None,
+        shards: None,
+        ..input_manifest(options, chunks)?
+    };
+    let input_changed = check_input(options, before)?;
+    store.write_info(&manifest)?;
+    Ok((manifest, input_changed))
+}
+
+// `split_sequentially` into a store on a server through the async engine, with
+// `in_flight` chunks going up at once, each tried `retries` more times, reading every
+// chunk from its own offset of the input; a pipe is still read front to back. The store
+// is handed back, to be aborted if the split failed.
+#[cfg(feature = "async")]
+fn split_remote<S: SharedStore>(
+    options: &SplitOptions,
+    io: &Io,
+    mut store: S,
+    (in_flight, retries): (usize, u32),
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> (S, Result<(Manifest, bool)>) {
+    if is_stream(&options.input) {
+        let written = split_sequentially(options, io, &mut store, progress, cancel);
+        return (store, written);
+    }
+    debug!("uploading up to {} chunks at once", in_flight);
+    let store = Arc::new(store);
+    let written = Engine::new(in_flight, retries, cancel)
+        .at(&options.destination)
+        .and_then(|engine| split_async(options, &engine, &store, progress));
+    let Some(mut store) = Arc::into_inner(store) else {
+        unreachable!("the engine is done with the store once it returns");
+    };
+    let written = written.and_then(|(manifest, input_changed)| {
+        store.write_info(&manifest)?;
+        Ok((manifest, input_changed))
+    });
+    (store, written)
+}
+
+#[cfg(not(feature = "async"))]
+fn split_remote<S: ChunkStore>(
+    options: &SplitOptions,
+    io: &Io,
+    mut store: S,
+    _: (usize, u32),
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> (S, Result<(Manifest, bool)>) {
+    let written = split_sequentially(options, io, &mut store, progress, cancel);
+    (store, written)
+}
+
+// `split_sequentially` with the chunks written by `engine`, short of info.json, which
+// only goes once the store is the caller's alone again.
+#[cfg(feature = "async")]
+fn split_async<S: SharedStore>(
+    options: &SplitOptions,
+    engine: &Engine,
+    store: &Arc<S>,
+    progress: &mut dyn FnMut(ProgressEvent),
+) -> Result<(Manifest, bool)> {
+    let input_path = options.input.as_path();
+    let before = InputState::of(input_path);
+    let range = match options.input_range()? {
+        Some(range) => (range.offset, range.length),
+        None => (0, fs::metadata(input_path).at(input_path)?.len()),
+    };
+    let (chunk_size, hash) = (options.chunk_size, options.hash);
+    let chunks = engine.split(store, input_path, range, chunk_size, hash, progress)?;
+    let manifest = Manifest {
+        compression: store.compression(),
+        random_names: false,
+        compat: None,
+        shards: None,
+        ..input_manifest(options, chunks)?
+    };
+    let input_changed = check_input(options, before)?;
+    Ok((manifest, input_changed))
+}
+
+// Split file into chunks. Without hashing or compression nothing needs to see the
+// data, so the kernel can copy it when it knows how.
+fn write_chunks(
+    options: &SplitOptions,
+    store: &mut LocalDirStore,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Vec<ChunkEntry>> {
+    let input_path = options.input.as_path();
+    let compressed = !options.compression.is_none();
+    // Codecs are decided chunk by chunk, names other than `chunk000` given out and
+    // chunks encrypted only by the split workers
+    let per_chunk = compressed
+        || options.random_names
+        || options.prefix.is_some()
+        || options.compat.is_some()
+        || options.key.is_some();
+    // Only chunks written through the store's writers get to the mirror too
+    let mirrored = options.mirror.is_some();
+    if options.dedup {
+        debug!("writing only the chunks not already stored, on one thread");
+        return split_deduplicated(options, store, progress, cancel);
+    }
+    if is_stream(input_path) {
+        if options.mmap || options.threads > 1 {
+            debug!("a pipe is read front to back on one thread, with buffered I/O");
+        }
+        return split_stream(options, store, progress, cancel);
+    }
+    // The other ways in all start from the beginning of the file
+    if options.input_range()?.is_some() {
+        if options.mmap || options.threads > 1 {
+            debug!("a range of the input is read front to back on one thread, with buffered I/O");
+        }
+        return split_stream(options, store, progress, cancel);
+    }
+    if options.mmap && (per_chunk || mirrored) {
+        warn!(
+            "compressed, mirrored or specially named chunks are written with buffered I/O, not a memory map"
+        );
+    } else if options.mmap {
+        if let Some(chunks) = split_mapped(options, store, progress, cancel)? {
+            return Ok(chunks);
+        }
+        warn!(
+            "cannot map {} into memory; using buffered I/O instead",
+            input_path.display()
+        );
+    }
+    if options.hash.is_none() && !per_chunk && !mirrored && fastcopy::SUPPORTED {
+        debug!("copying chunks in the kernel");
+        let input_file = File::open(input_path).doing("opening", input_path)?;
+        split_in_kernel(input_file, options, store, progress, cancel)
+    } else if options.threads > 1 && compressed && chunks_in_memory(options).is_none_or(|n| n >= 2)
+    {
+        debug!("compressing chunks with {} threads", options.threads);
+        split_pipelined(options, store, progress, cancel)
+    } else if options.threads > 1 || per_chunk {
+        if options.threads > 1 && compressed {
+            debug!("chunks don't fit in --max-memory twice over; streaming each instead");
+        }
+        debug!("writing chunks with {} threads", options.threads);
+        split_parallel(options, store, progress, cancel)
+    } else {
+        debug!("copying chunks through the read-ahead pipeline");
+        let io = Arc::clone(store.io());
+        let input_file = io.released(File::open(input_path).doing("opening", input_path)?, 0);
+        let mut input_file = BufReader::with_capacity(io.buffer_size(), input_file);
+        let (chunk_size, hash) = (options.chunk_size, options.hash);
+        split_into_with(
+            &io,
+            &mut input_file,
+            input_path,
+            store,
+            chunk_size,
+            hash,
+            progress,
+            cancel,
+        )
+    }
+}
+
+// For `SplitOptions::dedup`: the chunks one after another, each read through to hash it
+// before anything is written, and only written when no earlier chunk has its size and
+// hash. One that has is left unwritten, with its entry pointed at the earlier one's
+// (`same_as`), which is stored the same way, being the same bytes.
+fn split_deduplicated(
+    options: &SplitOptions,
+    store: &LocalDirStore,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Vec<ChunkEntry>> {
+    let input_path = options.input.as_path();
+    let algorithm = options
+        .hash
+        .expect("dedup needs a hash, checked by validate");
+    let base = options.input_range()?.map_or(0, |range| range.offset);
+    let total = options.input_len()?.unwrap_or(0);
+    let count = chunk_count(total, options.chunk_size)?;
+    let mut first: HashMap<(u64, String), usize> = HashMap::new();
+    let mut chunks: Vec<ChunkEntry> = Vec::with_capacity(count);
+    for index in 0..count {
+        cancel.check()?;
+        let start = index as u64 * options.chunk_size;
+        let (offset, len) = (base + start, options.chunk_size.min(total - start));
+        progress(ProgressEvent::ChunkStarted { index, size: len });
+        let mut input_file = File::open(input_path).doing("opening", input_path)?;
+        input_file.seek(SeekFrom::Start(offset)).at(input_path)?;
+        let mut hasher = algorithm.hasher();
+        let read = copy_overlapped(
+            store.io(),
+            &mut (&mut input_file).take(len),
+            &mut io::sink(),
+            Some(&mut hasher),
+        )
+        .at(input_path)?;
+        if read != len {
+            return Err(SplitterError::ChangedSize {
+                path: input_path.to_path_buf(),
+            });
+        }
+        let hash = hasher.finish();
+        let entry = match first.get(&(len, hash.clone())) {
+            Some(&original) => {
+                let stored: &ChunkEntry = &chunks[original];
+                let compression = stored.compression.unwrap_or(store.compression());
+                let name = entry_name(options, store, index, compression)?;
+                debug!("{} is the same as {}; not writing it", name, stored.name);
+                progress(ProgressEvent::BytesCopied { delta: len });
+                let entry = ChunkEntry {
+                    name,
+                    size: len,
+                    hash: Some(hash.clone()),
+                    stored_hash: stored.stored_hash.clone(),
+                    compression: stored.compression,
+                    same_as: Some(stored.name.clone()),
+                    shared: None,
+                    mtime: None,
+                };
+                *chunks[original].shared.get_or_insert(0) += 1;
+                entry
+            }
+            None => {
+                let mut copied = |delta| progress(ProgressEvent::BytesCopied { delta });
+                let entry =
+                    write_chunk_from(options, store, offset, len, index, &mut copied, cancel)?;
+                // Read twice, it has to have been the same both times
+                if entry.hash.as_ref() != Some(&hash) {
+                    return Err(SplitterError::InputChanged {
+                        path: input_path.to_path_buf(),
+                    });
+                }
+                first.insert((len, hash.clone()), index);
+                entry
+            }
+        };
+        progress(ProgressEvent::ChunkFinished {
+            index,
+            hash: Some(hash),
+        });
+        chunks.push(entry);
+    }
+    Ok(chunks)
+}
+
+// What the files of the chunks stored in another's would have taken up: as much as
+// the one each is stored in.
+fn deduplicated_size(store: &LocalDirStore, chunks: &[ChunkEntry]) -> Result<u64> {
+    let mut size = 0;
+    for same_as in chunks.iter().filter_map(|entry| entry.same_as.as_ref()) {
+        let path = store.directory().join(same_as);
+        size += fs::metadata(&path).at(&path)?.len();
+    }
+    Ok(size)
+}
+
+// `SplitOptions::input_len`, saying so when it isn't known.
+fn input_size(options: &SplitOptions) -> Result<Option<u64>> {
+    let size = options.input_len()?;
+    if size.is_none() {
+        info!(
+            "{} is a pipe; reading it as it arrives, size unknown",
+            options.input.display()
+        );
+    }
+    Ok(size)
+}
+
+// The input from where the split starts to where it ends, releasing it from the cache
+// as it is read with `direct_io`: all of it, or the range `input_offset` and
+// `input_length` ask for.
+fn open_input(options: &SplitOptions, io: &Io) -> Result<io::Take<Released>> {
+    let input_path = options.input.as_path();
+    let mut file = File::open(input_path).doing("opening", input_path)?;
+    let (offset, length) = match options.input_range()? {
+        Some(range) => (range.offset, range.length),
+        None => (0, u64::MAX),
+    };
+    if offset > 0 {
+        file.seek(SeekFrom::Start(offset)).at(input_path)?;
+    }
+    Ok(io.released(file, offset).take(length))
+}
+
+// `write_chunks` for input that can only be read front to back, such as a named pipe:
+// each chunk is stored as it arrives, as `split_parallel` stores them, so compressed
+// chunks that don't shrink are still stored raw and random names still work. The last
+// chunk is the one the input ends in.
+fn split_stream(
+    options: &SplitOptions,
+    store: &mut LocalDirStore,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Vec<ChunkEntry>> {
+    let input_path = options.input.as_path();
+    let file: Box<dyn Read + Send> = match is_stream(input_path) {
+        true => Box::new(File::open(input_path).doing("opening", input_path)?),
+        false => Box::new(open_input(options, store.io())?),
+    };
+    stream_chunks(options, store, file, progress, cancel)
+}
+
+// `split_stream` of what `file` reads; errors name it as the input.
+fn stream_chunks(
+    options: &SplitOptions,
+    store: &mut LocalDirStore,
+    file: impl Read + Send,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Vec<ChunkEntry>> {
+    let input_path = options.input.as_path();
+    let mut input = BufReader::with_capacity(store.io().buffer_size(), file);
+    let mut chunks = Vec::new();
+    while !input.fill_buf().at(input_path)?.is_empty() {
+        cancel.check()?;
+        let index = chunks.len();
+        progress(ProgressEvent::ChunkStarted {
+            index,
+            size: options.chunk_size,
+        });
+        let mut sample = Vec::new();
+        let mut compression = store.compression();
+        if compression.shrinks() && options.min_ratio > 0.0 {
+            (&mut input)
+                .take(options.chunk_size.min(SAMPLE_SIZE))
+                .read_to_end(&mut sample)
+                .at(input_path)?;
+            compression = chunk_compression(options, store, index, &sample)?;
+        }
+        // Read until the chunk is full or the input ends, however little a pipe hands
+        // over at a time, so every chunk but the last is `chunk_size`
+        let rest = options.chunk_size - sample.len() as u64;
+        let mut chunk = io::Cursor::new(sample).chain((&mut input).take(rest));
+        let mut copied = |delta| progress(ProgressEvent::BytesCopied { delta });
+        let entry = store_chunk(
+            options,
+            store,
+            index,
+            compression,
+            &mut chunk,
+            &mut copied,
+            cancel,
+        )?;
+        progress(ProgressEvent::ChunkFinished {
+            index,
+            hash: entry.hash.clone(),
+        });
+        chunks.push(entry);
+    }
+    Ok(chunks)
+}
+
+// Where chunk `index` of a split into a directory was written, for a hook that only
+// hears its index. Chunks of a compressed set that were stored raw lack the set's
+// extension; random names aren't known until the manifest is written, so `validate`
+// keeps hooks away from them.
+fn written_path(
+    options: &SplitOptions,
+    index: usize,
+    count: usize,
+    shards: Option<usize>,
+) -> PathBuf {
+    let mut directory = options.destination.clone();
+    if let Some(compat) = options.compat {
+        let base = symlinks::resolved_name(&options.input);
+        return directory.join(compat.chunk_name(&base.to_string_lossy(), index, count));
+    }
+    if let Some(per_directory) = shards {
+        directory.push(shard_dir(index, per_directory));
+    }
+    let path = directory.join(options.numbered_name(index, options.compression));
+    if options.compression.shrinks() && !path.exists() {
+        return directory.join(options.numbered_name(index, Compression::None));
+    }
+    path
+}
+
+// How many chunks go in each shard subdirectory of a split into a directory, if it is
+// to be sharded, from what `shard_dirs` says to do about `count` chunks being more than
+// `max_dir_files`; the subdirectories are made here, in `mirror` too. Also that the file
+// system has room for that many more files, as far as it says. Chunks of a pipe can't
+// be counted ahead, so all go in the one directory.
+fn plan_shards(
+    options: &SplitOptions,
+    count: Option<usize>,
+    mirror: Option<&Path>,
+) -> Result<Option<usize>> {
+    let directory = options.destination.as_path();
+    let Some(count) = count else {
+        if options.shard_dirs == ShardDirs::Shard {
+            warn!(
+                "{} is a pipe, whose chunks can't be counted ahead; they all go in {}",
+                options.input.display(),
+                directory.display()
+            );
+        }
+        return Ok(None);
+    };
+    let limit = options.max_dir_files;
+    let sharded = count > limit && options.shard_dirs == ShardDirs::Shard;
+    let shard_count = match sharded {
+        true => count.div_ceil(limit),
+        false => 0,
+    };
+    // The chunks, their subdirectories and info.json, leaving parity and the like aside
+    let needed = (count + shard_count + 1) as u64;
+    for target in std::iter::once(directory).chain(mirror) {
+        if let Some(free) = free_files(target)
+            && free < needed
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                format!(
+                    "the file system has room for {} more files, and the split needs {}; split into larger chunks",
+                    free, needed
+                ),
+            ))
+            .at(target);
+        }
+    }
+    if count <= limit {
+        return Ok(None);
+    }
+    match options.shard_dirs {
+        ShardDirs::Warn => {
+            warn!(
+                "{} chunks will go in {}, more than the {} a directory is planned to hold, \
+                 which many file systems grow slow with; shard them into subdirectories, or \
+                 split into larger chunks",
+                count,
+                directory.display(),
+                limit
+            );
+            Ok(None)
+        }
+        ShardDirs::Refuse => Err(SplitterError::TooManyFiles {
+            path: directory.to_path_buf(),
+            count,
+            limit,
+        }),
+        ShardDirs::Shard => {
+            info!(
+                "putting the {} chunks {} to a subdirectory, in {} of them",
+                count, limit, shard_count
+            );
+            for target in std::iter::once(directory).chain(mirror) {
+                for shard in 0..shard_count {
+                    let path = target.join(shard_dir(shard * limit, limit));
+                    fs::create_dir(&path).doing("creating", &path)?;
+                    modes::prepare(&path, options.dir_mode)?;
+                }
+            }
+            Ok(Some(limit))
+        }
+    }
+}
+
+// Create `directory` if it doesn't exist, and make sure there is nothing in it. True
+// when it was created here. A lock already on it is stale past `max_age`.
+pub(crate) fn prepare_destination(
+    directory: &Path,
+    operation: &str,
+    max_age: Duration,
+) -> Result<(bool, DirLock)> {
+    check_destination(directory)?;
+    let created = !directory.exists();
+    if created {
+        debug!("creating {}", directory.display());
+        fs::create_dir_all(directory).doing("creating", directory)?;
+    }
+    // Before looking inside, so that a split into it already under way is named
+    let lock = lock::acquire_with_max_age(directory, operation, max_age).inspect_err(|_| {
+        if created {
+            let _ = fs::remove_dir(directory);
+        }
+    })?;
+    check_empty(directory)?;
+    Ok((created, lock))
+}
+
+// That `directory`, which exists, has nothing in it but perhaps the lock.
+pub(crate) fn check_empty(directory: &Path) -> Result<()> {
+    for entry in fs::read_dir(directory).at(directory)? {
+        if entry.at(directory)?.file_name() != LOCK_NAME {
+            return Err(SplitterError::DestinationNotEmpty {
+                path: directory.to_path_buf(),
+            });
+        }
+    }
+    Ok(())
+}
+
+// That `directory` is one or can be made one: neither it nor any directory above it is a
+// file, which creating it would only report as "File exists" or "Not a directory".
+pub fn check_destination(directory: &Path) -> Result<()> {
+    match directory.ancestors().find(|path| path.exists()) {
+        Some(existing) if !existing.is_dir() => Err(SplitterError::NotADirectory {
+            path: existing.to_path_buf(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+// What the input looked like when a split started, to tell at the end whether it changed
+// while it was read, as a log still being written to does.
+#[derive(Clone, Copy, PartialEq)]
+struct InputState {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl InputState {
+    // None for a stream, which is read as it comes, or an input that can't be looked at
+    fn of(path: &Path) -> Option<InputState> {
+        if is_stream(path) {
+            return None;
+        }
+        let metadata = fs::metadata(path).ok()?;
+        Some(InputState {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+// The manifest of a split of the input into `chunks` as `options` say, without parity
+// or volumes. It names the input, as `symlinks` resolves it, and has its owner and,
+// when `xattrs` asks for them, its extended attributes.
+fn input_manifest(options: &SplitOptions, chunks: Vec<ChunkEntry>) -> Result<Manifest> {
+    let input_path = options.input.as_path();
+    let name = symlinks::resolved_name(input_path)
+        .to_string_lossy()
+        .into_owned();
+    Ok(Manifest {
+        version: MANIFEST_VERSION,
+        name_form: Normalization::of(&name),
+        original_filename: name,
+        link_name: symlinks::link_name(input_path),
+        range: options.input_range()?,
+        chunk_size: Some(options.chunk_size),
+        hash: options.hash,
+        compression: options.compression,
+        encryption: options.encryption.clone(),
+        metadata: None,
+        random_names: options.random_names || options.prefix.is_some(),
+        compat: options.compat,
+        shards: None,
+        parity: None,
+        span: None,
+        xattrs: match options.xattrs {
+            true => xattrs::read(input_path)?,
+            false => BTreeMap::new(),
+        },
+        owner: owner::of(input_path),
+        created: None,
+        chunks,
+    })
+}
+
+// Whether the input is no longer as `before` found it, asked once the chunks are written
+// and before the manifest is. Every chunk's size is what was read of it, so the set is
+// consistent, but it is no one version of the input. With `strict` that fails the split,
+// which then cleans up as for any failure; otherwise the report says so, for the front
+// end to warn about.
+fn check_input(options: &SplitOptions, before: Option<InputState>) -> Result<bool> {
+    let Some(before) = before else {
+        return Ok(false);
+    };
+    let input_path = options.input.as_path();
+    if InputState::of(input_path) == Some(before) {
+        return Ok(false);
+    }
+    if options.strict {
+        return Err(SplitterError::InputChanged {
+            path: input_path.to_path_buf(),
+        });
+    }
+    debug!("{} changed while it was being split", input_path.display());
+    Ok(true)
+}
+
+// The destination was empty before the split started, so every chunk in it is ours.
+// Cleanup is best effort: the error that got us here is the one worth reporting.
+// Chunks of a compressed set that were stored raw lack the set's extension, and random
+// names aren't recorded until the manifest is written, so this goes by the names on
+// disk rather than the store's. So does shard subdirectories, which go once empty.
+// With `shred`, each file is overwritten with zeros before it goes. Chunks named with a
+// `prefix` of their own go too.
+pub(crate) fn remove_partial(directory: &Path, created: bool, shred: bool, prefix: Option<&str>) {
+    for entry in fs::read_dir(directory).into_iter().flatten().flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let prefixed = prefix.is_some_and(|prefix| prefixed_index(&name, prefix).is_some());
+        // The transfer states of chunks that are going too
+        if is_set_file(&name) || prefixed || name == TRANSFER_STATE_NAME {
+            let _ = shred::remove_file(&entry.path(), shred);
+        } else if is_shard_dir(&name) && entry.path().is_dir() {
+            remove_partial(&entry.path(), true, shred, prefix);
+        }
+    }
+    if created {
+        lock::release_own(directory);
+        // Only succeeds when nothing else was put there in the meantime
+        let _ = fs::remove_dir(directory);
+    }
+}
+
+// For `fsync`: flushes the set's files in `directory` to disk, those in shard
+// subdirectories too, then the directories, so that their names are on disk as well.
+fn sync_set(directory: &Path, prefix: Option<&str>) -> Result<()> {
+    for entry in fs::read_dir(directory).at(directory)? {
+        let path = entry.at(directory)?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let prefixed = prefix.is_some_and(|prefix| prefixed_index(&name, prefix).is_some());
+        if is_shard_dir(&name) && path.is_dir() {
+            sync_set(&path, prefix)?;
+        } else if is_set_file(&name) || prefixed {
+            File::open(&path)
+                .and_then(|file| file.sync_all())
+                .at(&path)?;
+        }
+    }
+    // Only Unix lets a directory be opened to flush it
+    #[cfg(unix)]
+    File::open(directory)
+        .and_then(|directory| directory.sync_all())
+        .at(directory)?;
+    Ok(())
+}
+
+#[cfg(feature = "mmap")]
+fn split_mapped(
+    options: &SplitOptions,
+    store: &LocalDirStore,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Option<Vec<ChunkEntry>>> {
+    mmap::split(
+        &options.input,
+        store,
+        options.chunk_size,
+        options.hash,
+        progress,
+        cancel,
+    )
+}
+
+// Without the feature there is nothing to map with; buffered I/O it is.
+#[cfg(not(feature = "mmap"))]
+fn split_mapped(
+    _options: &SplitOptions,
+    _store: &LocalDirStore,
+    _progress: &mut dyn FnMut(ProgressEvent),
+    _cancel: &CancelToken,
+) -> Result<Option<Vec<ChunkEntry>>> {
+    Ok(None)
+}
+
+// Chunks are copied with `fastcopy::copy_range`, finishing with a normal copy
+// whenever that stops short.
+fn split_in_kernel(
+    mut input_file: File,
+    options: &SplitOptions,
+    store: &LocalDirStore,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Vec<ChunkEntry>> {
+    let input_path = options.input.as_path();
+    let chunk_size = options.chunk_size;
+    let total = input_file.metadata().at(input_path)?.len();
+    let mut chunks = Vec::new();
+    let mut offset = 0;
+    while offset < total {
+        cancel.check()?;
+        let index = chunks.len();
+        let name = store.sharded_name(index, &chunk_name(index, Compression::None));
+        let chunk_path = store.chunk_path(index);
+        let len = chunk_size.min(total - offset);
+        progress(ProgressEvent::ChunkStarted { index, size: len });
+        let mut chunk_file = retried(&options.retry, "creating", &chunk_path, || {
+            File::create(&chunk_path)
+        })
+        .doing("creating chunk", &chunk_path)?;
+        let mut copied = fastcopy::copy_range(&input_file, offset, &chunk_file, 0, len);
+        progress(ProgressEvent::BytesCopied { delta: copied });
+        if copied < len {
+            debug!(
+                "kernel copy into {} stopped after {} of {} bytes; copying the rest",
+                chunk_path.display(),
+                copied,
+                len
+            );
+            input_file
+                .seek(SeekFrom::Start(offset + copied))
+                .at(input_path)?;
+            chunk_file.seek(SeekFrom::Start(copied)).at(&chunk_path)?;
+            copied += io::copy(
+                &mut (&mut input_file).take(len - copied),
+                &mut Counting {
+                    inner: Retrying::new(&chunk_file, &chunk_path, &options.retry),
+                    copied: &mut |delta| progress(ProgressEvent::BytesCopied { delta }),
+                    cancel,
+                },
+            )
+            .at(&chunk_path)?;
+        }
+        if copied == 0 {
+            // The file got shorter since we looked at its size
+            drop(chunk_file);
+            fs::remove_file(&chunk_path).at(&chunk_path)?;
+            break;
+        }
+        store
+            .io()
+            .release(&input_file, offset, copied, false)
+            .at(input_path)?;
+        store
+            .io()
+            .release(&chunk_file, 0, copied, true)
+            .at(&chunk_path)?;
+        log_written(&chunk_path, copied, None);
+        progress(ProgressEvent::ChunkFinished { index, hash: None });
+        offset += copied;
+        chunks.push(ChunkEntry {
+            name,
+            size: copied,
+            hash: None,
+            stored_hash: None,
+            compression: None,
+            same_as: None,
+            shared: None,
+            mtime: None,
+        });
+    }
+    Ok(chunks)
+}
+
+// Workers claim chunk indices in turn and copy their range of the input into the
+// chunk file, each through its own handle on the input. Memory use is the copy
+// buffers of each worker, however large the chunks are. Entries are collected by
+// index so the manifest lists them in order; the first failure stops the workers
+// from claiming more and is returned once they have wound down, as is cancellation.
+fn split_parallel(
+    options: &SplitOptions,
+    store: &LocalDirStore,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Vec<ChunkEntry>> {
+    let input_path = options.input.as_path();
+    let (chunk_size, threads) = (options.chunk_size, options.threads);
+    let total = fs::metadata(input_path).at(input_path)?.len();
+    let count = chunk_count(total, chunk_size)?;
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel::<Result<Update>>();
+
+    thread::scope(|scope| {
+        for _ in 0..threads.min(count) {
+            let sender = sender.clone();
+            let (next, failed) = (&next, &failed);
+            scope.spawn(move || {
+                while !failed.load(Ordering::Relaxed) && !cancel.is_cancelled() {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= count {
+                        return;
+                    }
+                    let offset = index as u64 * chunk_size;
+                    let len = chunk_size.min(total - offset);
+                    let started = ProgressEvent::ChunkStarted { index, size: len };
+                    let _ = sender.send(Ok(Update::Event(started)));
+                    let mut copied = |delta| {
+                        let event = ProgressEvent::BytesCopied { delta };
+                        let _ = sender.send(Ok(Update::Event(event)));
+                    };
+                    let result =
+                        write_chunk_from(options, store, offset, len, index, &mut copied, cancel);
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    let _ = sender.send(result.map(|entry| Update::Written(index, entry)));
+                }
+            });
+        }
+        drop(sender);
+        collect_chunks(receiver, count, progress, cancel)
+    })
+}
+
+// For compressed splits on more than one thread, where the codec rather than the disk
+// sets the pace. One thread reads the input front to back, a whole chunk at a time,
+// and the workers compress and write whichever chunk is next. Chunks are read into a
+// fixed set of `in_flight` buffers that the workers hand back, so memory use is
+// `in_flight` times the chunk size, and reading waits when all of them are taken.
+// With `max_memory` there are no more of them than fit next to the workers' own
+// buffers. Failure and cancellation work as in `split_parallel`.
+fn split_pipelined(
+    options: &SplitOptions,
+    store: &LocalDirStore,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Vec<ChunkEntry>> {
+    let input_path = options.input.as_path();
+    let (chunk_size, threads) = (options.chunk_size, options.threads);
+    let total = fs::metadata(input_path).at(input_path)?.len();
+    let count = chunk_count(total, chunk_size)?;
+    let in_flight = match options.in_flight {
+        0 => threads * 2,
+        in_flight => in_flight,
+    };
+    let in_flight = chunks_in_memory(options).map_or(in_flight, |room| in_flight.min(room));
+    debug!("reading ahead up to {} chunks", in_flight);
+    let failed = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel::<Result<Update>>();
+    let (free_sender, free_receiver) = mpsc::channel::<Vec<u8>>();
+    for _ in 0..in_flight.min(count) {
+        let _ = free_sender.send(Vec::new());
+    }
+    let (full_sender, full_receiver) = mpsc::sync_channel::<(usize, Vec<u8>)>(in_flight);
+    let full_receiver = Mutex::new(full_receiver);
+
+    thread::scope(|scope| {
+        let reader_sender = sender.clone();
+        let failed = &failed;
+        scope.spawn(move || {
+            let read_all = || -> Result<()> {
+                let mut input_file = File::open(input_path).doing("opening", input_path)?;
+                for index in 0..count {
+                    if failed.load(Ordering::Relaxed) || cancel.is_cancelled() {
+                        break;
+                    }
+                    let Ok(mut buffer) = free_receiver.recv() else {
+                        break;
+                    };
+                    let offset = index as u64 * chunk_size;
+                    let len = chunk_size.min(total - offset);
+                    buffer.clear();
+                    (&mut input_file)
+                        .take(len)
+                        .read_to_end(&mut buffer)
+                        .at(input_path)?;
+                    store
+                        .io()
+                        .release(&input_file, offset, len, false)
+                        .at(input_path)?;
+                    if buffer.len() as u64 != len {
+                        return Err(SplitterError::ChangedSize {
+                            path: input_path.to_path_buf(),
+                        });
+                    }
+                    if full_sender.send((index, buffer)).is_err() {
+                        break;
+                    }
+                }
+                Ok(())
+            };
+            if let Err(e) = read_all() {
+                failed.store(true, Ordering::Relaxed);
+                let _ = reader_sender.send(Err(e));
+            }
+        });
+        for _ in 0..threads.min(count) {
+            let (sender, free_sender) = (sender.clone(), free_sender.clone());
+            let full_receiver = &full_receiver;
+            scope.spawn(move || {
+                loop {
+                    // Not in a `while let`, which would hold the lock for the whole body
+                    let next = full_receiver.lock().unwrap().recv();
+                    let Ok((index, buffer)) = next else {
+                        return;
+                    };
+                    // Chunks read after a failure are only handed back, so the reader
+                    // never waits on a buffer that won't come
+                    if failed.load(Ordering::Relaxed) || cancel.is_cancelled() {
+                        let _ = free_sender.send(buffer);
+                        continue;
+                    }
+                    let size = buffer.len() as u64;
+                    let started = ProgressEvent::ChunkStarted { index, size };
+                    let _ = sender.send(Ok(Update::Event(started)));
+                    let mut copied = |delta| {
+                        let event = ProgressEvent::BytesCopied { delta };
+                        let _ = sender.send(Ok(Update::Event(event)));
+                    };
+                    let result =
+                        chunk_compression(options, store, index, &buffer).and_then(|compression| {
+                            let mut input = buffer.as_slice();
+                            store_chunk(
+                                options,
+                                store,
+                                index,
+                                compression,
+                                &mut input,
+                                &mut copied,
+                                cancel,
+                            )
+                        });
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    let _ = free_sender.send(buffer);
+                    let _ = sender.send(result.map(|entry| Update::Written(index, entry)));
+                }
+            });
+        }
+        drop((sender, free_sender));
+        collect_chunks(receiver, count, progress, cancel)
+    })
+}
+
+// Whole chunks that fit in `max_memory` next to the buffers of the threads writing
+// them; None without a cap.
+fn chunks_in_memory(options: &SplitOptions) -> Option<usize> {
+    let buffers = pipeline::memory(options.threads, options.buffer_size);
+    let room = options.max_memory?.saturating_sub(buffers);
+    Some(usize::try_from(room / options.chunk_size).unwrap_or(usize::MAX))
+}
+
+pub(crate) fn chunk_count(total: u64, chunk_size: u64) -> Result<usize> {
+    let count = total.div_ceil(chunk_size);
+    usize::try_from(count).map_err(|_| SplitterError::TooManyChunks { count })
+}
+
+// What split workers send back to the thread that owns the progress callback.
+enum Update {
+    Event(ProgressEvent),
+    Written(usize, ChunkEntry),
+}
+
+// Pass the workers' events on to `progress` until they have all hung up, and put the
+// entries in order. The first error comes back once the workers are done.
+fn collect_chunks(
+    receiver: mpsc::Receiver<Result<Update>>,
+    count: usize,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Vec<ChunkEntry>> {
+    let mut chunks: Vec<Option<ChunkEntry>> = Vec::new();
+    chunks.resize_with(count, || None);
+    let mut error = None;
+    for result in receiver {
+        match result {
+            Ok(Update::Event(event)) => progress(event),
+            Ok(Update::Written(index, entry)) => {
+                progress(ProgressEvent::ChunkFinished {
+                    index,
+                    hash: entry.hash.clone(),
+                });
+                chunks[index] = Some(entry);
+            }
+            Err(e) => {
+                error.get_or_insert(e);
+            }
+        }
+    }
+    match error {
+        Some(e) => Err(e),
+        None => {
+            // Workers stop claiming chunks once cancelled, which leaves gaps
+            cancel.check()?;
+            Ok(chunks.into_iter().flatten().collect())
+        }
+    }
+}
+
+// Copy `len` bytes at `offset` of the input into chunk `index`, hashing on the way
+// and telling `copied` about each buffer.
+fn write_chunk_from(
+    options: &SplitOptions,
+    store: &LocalDirStore,
+    offset: u64,
+    len: u64,
+    index: usize,
+    copied: &mut dyn FnMut(u64),
+    cancel: &CancelToken,
+) -> Result<ChunkEntry> {
+    let input_path = options.input.as_path();
+    let mut input_file = File::open(input_path).doing("opening", input_path)?;
+    input_file.seek(SeekFrom::Start(offset)).at(input_path)?;
+    let mut compression = store.compression();
+    if compression.shrinks() && options.min_ratio > 0.0 {
+        let mut sample = Vec::new();
+        (&mut input_file)
+            .take(len.min(SAMPLE_SIZE))
+            .read_to_end(&mut sample)
+            .at(input_path)?;
+        input_file.seek(SeekFrom::Start(offset)).at(input_path)?;
+        compression = chunk_compression(options, store, index, &sample)?;
+    }
+    let mut input = (&mut input_file).take(len);
+    let entry = store_chunk(
+        options,
+        store,
+        index,
+        compression,
+        &mut input,
+        copied,
+        cancel,
+    )?;
+    store
+        .io()
+        .release(&input_file, offset, entry.size, false)
+        .at(input_path)?;
+    if entry.size != len {
+        return Err(SplitterError::ChangedSize {
+            path: input_path.to_path_buf(),
+        });
+    }
+    Ok(entry)
+}
+
+// How to store the chunk that starts with `data`: with the store's codec, unless that
+// compresses and its first `SAMPLE_SIZE` bytes compress by less than `min_ratio`.
+fn chunk_compression(
+    options: &SplitOptions,
+    store: &LocalDirStore,
+    index: usize,
+    data: &[u8],
+) -> Result<Compression> {
+    let compression = store.compression();
+    if !compression.shrinks() || options.min_ratio <= 0.0 {
+        return Ok(compression);
+    }
+    let sample = &data[..data.len().min(SAMPLE_SIZE as usize)];
+    let ratio = store
+        .compression_ratio(sample)
+        .at(options.input.as_path())?;
+    if ratio < options.min_ratio {
+        debug!("chunk {} compresses {:.2}:1; storing it raw", index, ratio);
+        return Ok(Compression::None);
+    }
+    Ok(compression)
+}
+
+// The name chunk `index`, stored with `compression`, has in info.json, with its shard
+// directory if it has one.
+fn entry_name(
+    options: &SplitOptions,
+    store: &LocalDirStore,
+    index: usize,
+    compression: Compression,
+) -> Result<String> {
+    let name = match (options.random_names, options.compat) {
+        (true, _) => random_chunk_name(compression),
+        (false, Some(compat)) => {
+            let input_path = options.input.as_path();
+            let total = options.input_len()?.unwrap_or(0);
+            let count = chunk_count(total, options.chunk_size)?;
+            let base = symlinks::resolved_name(input_path);
+            compat.chunk_name(&base.to_string_lossy(), index, count)
+        }
+        (false, None) => options.numbered_name(index, compression),
+    };
+    Ok(store.sharded_name(index, &name))
+}
+
+// Write everything `input` yields into a new chunk `index` stored with `compression`,
+// hashing on the way and telling `copied` about each buffer.
+fn store_chunk(
+    options: &SplitOptions,
+    store: &LocalDirStore,
+    index: usize,
+    compression: Compression,
+    input: &mut (impl Read + Send),
+    copied: &mut dyn FnMut(u64),
+    cancel: &CancelToken,
+) -> Result<ChunkEntry> {
+    let name = entry_name(options, store, index, compression)?;
+    let chunk_path = store.directory().join(&name);
+    let mut writer = store.create_named(index, &name, compression)?;
+    let mut hasher = options.hash.map(HashAlgorithm::hasher);
+    let size = copy_overlapped(
+        store.io(),
+        input,
+        &mut Counting {
+            inner: &mut writer,
+            copied,
+            cancel,
+        },
+        hasher.as_mut(),
+    )
+    .at(&chunk_path)?;
+    let stored_hash = store.finish(writer)?;
+    let hash = hasher.map(ChunkHasher::finish);
+    log_written(&chunk_path, size, hash.as_deref());
+    Ok(ChunkEntry {
+        name,
+        size,
+        hash,
+        stored_hash,
+        compression: (compression != store.compression()).then_some(compression),
+        same_as: None,
+        shared: None,
+        mtime: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_counts_of_files_past_4_gib() {
+        const GIB: u64 = 1 << 30;
+        assert_eq!(chunk_count(5 * GIB, GIB).unwrap(), 5);
+        assert_eq!(chunk_count(5 * GIB + 1, GIB).unwrap(), 6);
+        assert_eq!(chunk_count(u64::MAX, u64::MAX).unwrap(), 1);
+        assert_eq!(chunk_count(u64::MAX, 1 << 40).unwrap(), 1 << 24);
+        assert_eq!(chunk_count(0, GIB).unwrap(), 0);
+    }
+
+    #[test]
+    fn chunk_counts_around_2_to_the_32() {
+        let last_u32 = u64::from(u32::MAX);
+        assert_eq!(chunk_count(last_u32, 1).unwrap() as u64, last_u32);
+        assert_eq!(chunk_count(last_u32 * 4096, 4096).unwrap() as u64, last_u32);
+        // One chunk more than a u32 counts, which only a 64-bit usize holds
+        let past = chunk_count((last_u32 + 1) * 4096 + 1, 4096);
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(past.unwrap() as u64, last_u32 + 2);
+        #[cfg(target_pointer_width = "32")]
+        assert!(matches!(
+            past,
+            Err(SplitterError::TooManyChunks { count }) if count == last_u32 + 2
+        ));
+    }
+
+    // Hands over at most 7 bytes a read, as a pipe may however much is asked for.
+    struct Trickle(io::Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            let len = buffer.len().min(7);
+            self.0.read(&mut buffer[..len])
+        }
+    }
+
+    #[test]
+    fn short_reads_still_fill_every_chunk() {
+        let dir = tempfile::tempdir().unwrap();
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 31 % 251) as u8).collect();
+        let options = SplitOptions::builder(dir.path().join("input"), dir.path().join("chunks"))
+            .chunk_size(64)
+            .min_chunk_size(0)
+            .build()
+            .unwrap();
+        fs::create_dir(&options.destination).unwrap();
+        let mut store = LocalDirStore::new(&options.destination);
+        let input = Trickle(io::Cursor::new(data.clone()));
+        let chunks = stream_chunks(
+            &options,
+            &mut store,
+            input,
+            &mut |_| {},
+            &CancelToken::new(),
+        )
+        .unwrap();
+
+        let sizes: Vec<u64> = chunks.iter().map(|chunk| chunk.size).collect();
+        let mut expected = vec![64; 15];
+        expected.push(1000 - 15 * 64);
+        assert_eq!(sizes, expected);
+        let mut joined = Vec::new();
+        for chunk in &chunks {
+            let path = options.destination.join(&chunk.name);
+            assert_eq!(
+                fs::metadata(&path).unwrap().len(),
+                chunk.size,
+                "{}",
+                chunk.name
+            );
+            joined.extend(fs::read(path).unwrap());
+        }
+        assert_eq!(joined, data);
+    }
+
+    #[test]
+    fn splits_at_once_go_by_their_own_buffer_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i * 7 % 253) as u8).collect();
+        fs::write(&input, &data).unwrap();
+        thread::scope(|scope| {
+            for (name, buffer_size, direct_io) in [("small", 4096, true), ("large", 1 << 20, false)]
+            {
+                let (input, data) = (&input, &data);
+                let destination = dir.path().join(name);
+                scope.spawn(move || {
+                    let options = SplitOptions::builder(input, &destination)
+                        .chunk_size(50_000)
+                        .min_chunk_size(0)
+                        .buffer_size(buffer_size)
+                        .direct_io(direct_io)
+                        .build()
+                        .unwrap();
+                    let report = split_file(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+                    assert_eq!(report.adapted_buffers, None);
+                    let mut joined = Vec::new();
+                    for chunk in &report.chunks {
+                        joined.extend(fs::read(destination.join(&chunk.name)).unwrap());
+                    }
+                    assert_eq!(&joined, data);
+                });
+            }
+        });
+
+        let zero = SplitOptions::builder(&input, dir.path().join("none"))
+            .buffer_size(0)
+            .build();
+        assert!(matches!(
+            zero,
+            Err(SplitterError::InvalidOption {
+                field: "buffer_size",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn parts_go_instead_of_a_chunk_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        fs::write(&input, vec![7u8; 1000]).unwrap();
+        let builder = || SplitOptions::builder(&input, dir.path().join("chunks")).min_chunk_size(0);
+        for (parts, chunk_size) in [(4, 250), (3, 334), (1, 1000), (5000, 1)] {
+            let options = builder().parts(Some(parts)).build().unwrap();
+            assert_eq!(options.chunk_size, chunk_size, "{} parts", parts);
+        }
+        for wrong in [
+            builder().parts(Some(4)).chunk_size(100).build(),
+            builder().parts(Some(0)).build(),
+        ] {
+            assert!(matches!(
+                wrong,
+                Err(SplitterError::InvalidOption { field: "parts", .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn prefixes_that_name_chunks_safely() {
+        for prefix in ["part", "vol_", "backup-2024-x", "A"] {
+            assert!(check_prefix(prefix).is_ok(), "{}", prefix);
+        }
+        let long = "p".repeat(33);
+        for prefix in [
+            "", "1part", "part1", "part.", "a b", "../x", "parity", "dé", &long,
+        ] {
+            assert!(
+                matches!(
+                    check_prefix(prefix),
+                    Err(SplitterError::InvalidOption {
+                        field: "prefix",
+                        ..
+                    })
+                ),
+                "{}",
+                prefix
+            );
+        }
+    }
+
+    #[test]
+    fn prefixed_chunks_join_by_their_listed_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input");
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 13 % 241) as u8).collect();
+        fs::write(&input, &data).unwrap();
+        let destination = dir.path().join("chunks");
+        let options = SplitOptions::builder(&input, &destination)
+            .parts(Some(3))
+            .min_chunk_size(0)
+            .prefix(Some("part_".to_string()))
+            .fsync(true)
+            .build()
+            .unwrap();
+        let report = split_file(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+        let names: Vec<&str> = report.chunks.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["part_000", "part_001", "part_002"]);
+        assert!(
+            Manifest::load(&destination, false)
+                .unwrap()
+                .unwrap()
+                .random_names
+        );
+
+        let options = crate::reconstruct::ReconstructOptions {
+            output: Some("joined".to_string()),
+            ..crate::reconstruct::ReconstructOptions::new(&destination)
+        };
+        crate::reconstruct::reconstruct(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+        assert_eq!(fs::read(destination.join("joined")).unwrap(), data);
+    }
+
+    #[test]
+    fn chunk_sizes_at_the_limits_are_taken_or_refused_by_build() {
+        let build = |chunk_size, minimum: Option<u64>| {
+            let builder = SplitOptions::builder("input", "chunks").chunk_size(chunk_size);
+            match minimum {
+                Some(minimum) => builder.min_chunk_size(minimum),
+                None => builder,
+            }
+            .build()
+            .map(|_| ())
+        };
+        for minimum in [None, Some(0), Some(1)] {
+            assert!(matches!(
+                build(0, minimum),
+                Err(SplitterError::InvalidOption {
+                    field: "chunk_size",
+                    ..
+                })
+            ));
+        }
+        assert!(matches!(
+            build(1, None),
+            Err(SplitterError::ChunkTooSmall {
+                chunk_size: 1,
+                minimum: DEFAULT_MIN_CHUNK_SIZE
+            })
+        ));
+        build(1, Some(0)).unwrap();
+        build(1, Some(1)).unwrap();
+        assert!(matches!(
+            build(DEFAULT_MIN_CHUNK_SIZE - 1, None),
+            Err(SplitterError::ChunkTooSmall { .. })
+        ));
+        build(DEFAULT_MIN_CHUNK_SIZE, None).unwrap();
+        assert!(matches!(
+            build(4095, Some(4096)),
+            Err(SplitterError::ChunkTooSmall {
+                chunk_size: 4095,
+                minimum: 4096
+            })
+        ));
+    }
+
+    #[test]
+    fn files_of_0_1_a_multiple_and_one_past_it_split_into_whole_chunks() {
+        const CHUNK: u64 = DEFAULT_MIN_CHUNK_SIZE;
+        let dir = tempfile::tempdir().unwrap();
+        for (len, sizes) in [
+            (0, vec![]),
+            (1, vec![1]),
+            (3 * CHUNK, vec![CHUNK; 3]),
+            (3 * CHUNK + 1, vec![CHUNK, CHUNK, CHUNK, 1]),
+        ] {
+            let input = dir.path().join(format!("input{}", len));
+            let data: Vec<u8> = (0..len).map(|i| (i * 31 % 251) as u8).collect();
+            fs::write(&input, &data).unwrap();
+            let destination = dir.path().join(format!("chunks{}", len));
+            let options = SplitOptions::builder(&input, &destination)
+                .chunk_size(CHUNK)
+                .build()
+                .unwrap();
+            let report = split_file(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+            let found: Vec<u64> = report.chunks.iter().map(|chunk| chunk.size).collect();
+            assert_eq!(found, sizes, "{} bytes", len);
+            let mut joined = Vec::new();
+            for chunk in &report.chunks {
+                joined.extend(fs::read(destination.join(&chunk.name)).unwrap());
+            }
+            assert_eq!(joined, data, "{} bytes", len);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// Figures for every chunk set under a directory, for `stats`: how large each set's file
+// is, what its chunks take on disk, how many there are and how they are hashed and
+// stored, with the totals. The tree is walked without following symbolic links, and a
+// set's own subdirectories aren't looked in. A directory that looks like a set but
+// can't be read as one, as when chunks are missing or its info.json is damaged, is
+// listed apart with why, as are pieces another tool split a file into; neither stops
+// the walk.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cancel::CancelToken;
+use crate::chunkset::ChunkSet;
+use crate::error::{PathContext, Result};
+use crate::import::detect_foreign;
+use crate::manifest::{Compression, HashAlgorithm, MANIFEST_NAME};
+use crate::{chunk_health, default_output_name};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SetStats {
+    pub directory: PathBuf,
+    // What the set reconstructs into
+    pub original_filename: String,
+    pub original_size: u64,
+    // What the chunk files take, each file counted once however many chunks it holds
+    pub stored_size: u64,
+    // Everything in the directory, info.json, parity and PAR2 files included
+    pub disk_size: u64,
+    pub chunks: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<HashAlgorithm>,
+    pub compression: Compression,
+}
+
+impl SetStats {
+    // Of what the chunks take to what they hold, as in 0.42 for chunks compressed to
+    // under half; None for an empty file.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.original_size > 0).then(|| self.stored_size as f64 / self.original_size as f64)
+    }
+}
+
+// A directory that isn't a set that can be read, and why.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SkippedSet {
+    pub directory: PathBuf,
+    pub reason: String,
+}
+
+// Outcome of `stats`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatsReport {
+    pub root: PathBuf,
+    // In the order they were found, which is by name, depth first
+    pub sets: Vec<SetStats>,
+    // Named like chunks or with an info.json, but not readable as a set
+    pub broken: Vec<SkippedSet>,
+    // Pieces another tool split a file into, which `import` could make a set of
+    pub foreign: Vec<SkippedSet>,
+}
+
+impl StatsReport {
+    pub fn original_size(&self) -> u64 {
+        self.sets.iter().map(|set| set.original_size).sum()
+    }
+
+    pub fn stored_size(&self) -> u64 {
+        self.sets.iter().map(|set| set.stored_size).sum()
+    }
+
+    pub fn disk_size(&self) -> u64 {
+        self.sets.iter().map(|set| set.disk_size).sum()
+    }
+
+    pub fn chunks(&self) -> usize {
+        self.sets.iter().map(|set| set.chunks).sum()
+    }
+}
+
+// Walk the tree under `root` and take the figures of every chunk set in it. A set whose
+// info.json was changed after it was sealed is broken unless `accept_modified`.
+pub fn stats(root: &Path, accept_modified: bool, cancel: &CancelToken) -> Result<StatsReport> {
+    if !fs::metadata(root).at(root)?.is_dir() {
+        let not_a_directory = io::Error::new(io::ErrorKind::NotADirectory, "not a directory");
+        return Err(not_a_directory).at(root);
+    }
+    let mut report = StatsReport {
+        root: root.to_path_buf(),
+        sets: Vec::new(),
+        broken: Vec::new(),
+        foreign: Vec::new(),
+    };
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(directory) = pending.pop() {
+        cancel.check()?;
+        let skipped = |reason: String| SkippedSet {
+            directory: directory.clone(),
+            reason,
+        };
+        let looks_like_set = directory.join(MANIFEST_NAME).exists()
+            || chunk_health(&directory, accept_modified).is_ok_and(|health| health.chunks > 0);
+        if looks_like_set {
+            match set_stats(&directory, accept_modified) {
+                Ok(set) => report.sets.push(set),
+                Err(e) => report.broken.push(skipped(e.to_string())),
+            }
+            continue;
+        }
+        if let Ok(found) = detect_foreign(&directory)
+            && let Some(set) = found.first()
+        {
+            let name = set.original_filename.as_deref().unwrap_or(&set.prefix);
+            let reason = format!("{} pieces of {}", set.pieces.len(), name);
+            report.foreign.push(skipped(reason));
+            continue;
+        }
+        let entries = match fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                report.broken.push(skipped(e.to_string()));
+                continue;
+            }
+        };
+        let mut subdirectories: Vec<PathBuf> = entries
+            .flatten()
+            .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+            .map(|entry| entry.path())
+            .collect();
+        // Popped from the end, so the first by name goes first
+        subdirectories.sort_by(|a, b| b.cmp(a));
+        pending.extend(subdirectories);
+    }
+    Ok(report)
+}
+
+fn set_stats(directory: &Path, accept_modified: bool) -> Result<SetStats> {
+    let set = ChunkSet::open(directory, accept_modified)?;
+    let files: BTreeSet<&Path> = set.iter().map(|chunk| chunk.path.as_path()).collect();
+    let mut stored_size = 0;
+    for path in files {
+        stored_size += fs::metadata(path).at(path)?.len();
+    }
+    Ok(SetStats {
+        directory: directory.to_path_buf(),
+        original_filename: default_output_name(directory, accept_modified)?,
+        original_size: set.total_size(),
+        stored_size,
+        disk_size: disk_size(directory)?,
+        chunks: set.len(),
+        hash: set.hash_algorithm(),
+        compression: set.compression(),
+    })
+}
+
+// Of every file under `directory`, its shard subdirectories included.
+fn disk_size(directory: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(directory).at(directory)? {
+        let entry = entry.at(directory)?;
+        let path = entry.path();
+        let kind = entry.file_type().at(&path)?;
+        if kind.is_dir() {
+            size += disk_size(&path)?;
+        } else if kind.is_file() {
+            size += entry.metadata().at(&path)?.len();
+        }
+    }
+    Ok(size)
+}
@@ -0,0 +1,1316 @@
+// Where chunks and their manifest are kept. Everything that knows how a chunk set is
+// laid out (chunk names, where `info.json` goes) lives here, so `split_into` and
+// `reconstruct_from` work the same against a local directory, memory, or anything
+// else that can hand out a writer and a reader per chunk. `split_file` and
+// `reconstruct` keep their faster paths (kernel copies, parallel writes at offsets,
+// memory maps) for local directories, which need real files to work with. Stores
+// that compress do so in their writers and readers, so to everything above them a
+// chunk is always its original bytes.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::hash::{BuildHasher, RandomState};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use log::{debug, warn};
+
+use crate::armor::{ArmorDecoder, ArmorEncoder};
+use crate::cancel::CancelToken;
+use crate::crypt::{ChunkKey, SealedReader, SealedWriter};
+use crate::error::{PathContext, Result, SplitterError};
+use crate::event::{Counting, ProgressEvent};
+use crate::gzip::{GzDecoder, GzEncoder};
+use crate::manifest::{
+    ChunkEntry, ChunkHasher, Compression, HashAlgorithm, MANIFEST_NAME, MANIFEST_VERSION, Manifest,
+};
+use crate::pipeline::{DEFAULT_BUFFER_SIZE, Io, copy_overlapped};
+use crate::retry::{RetryPolicy, Retrying, retried};
+use crate::scratch::Staged;
+use crate::zst::{ZstDecoder, ZstEncoder};
+use crate::{chunk_index, compat, missing_below};
+
+pub trait ChunkStore {
+    type Writer: Write;
+    type Reader: Read + Send;
+
+    // Start chunk `index` afresh, replacing whatever was there.
+    fn create_chunk(&mut self, index: usize) -> Result<Self::Writer>;
+
+    // Called with the writer once chunk `index` is complete.
+    fn finish_chunk(&mut self, index: usize, writer: Self::Writer) -> Result<()> {
+        let _ = index;
+        drop(writer);
+        Ok(())
+    }
+
+    fn open_chunk(&self, index: usize) -> Result<Self::Reader>;
+
+    fn chunk_len(&self, index: usize) -> Result<u64>;
+
+    fn remove_chunk(&mut self, index: usize) -> Result<()>;
+
+    // Indices of the chunks present, in order.
+    fn list_chunks(&self) -> Result<Vec<usize>>;
+
+    // None when the store has no manifest, as with sets split by older versions.
+    fn read_info(&self) -> Result<Option<Manifest>>;
+
+    fn write_info(&mut self, manifest: &Manifest) -> Result<()>;
+
+    // Where chunk `index` lives, for error messages.
+    fn chunk_path(&self, index: usize) -> PathBuf {
+        PathBuf::from(chunk_name(index, self.compression()))
+    }
+
+    // What the manifest calls a new chunk `index`.
+    fn new_chunk_name(&self, index: usize) -> String {
+        chunk_name(index, self.compression())
+    }
+
+    // How new chunks are stored, for the manifest written alongside them.
+    fn compression(&self) -> Compression {
+        Compression::None
+    }
+}
+
+// The name of chunk `index` of a set split into numbered chunks.
+pub fn chunk_name(index: usize, compression: Compression) -> String {
+    prefixed_chunk_name("chunk", index, compression)
+}
+
+// `chunk_name` starting with `prefix` rather than `chunk`; see `SplitOptions::prefix`.
+pub(crate) fn prefixed_chunk_name(prefix: &str, index: usize, compression: Compression) -> String {
+    match compression.extension() {
+        Some(extension) => format!("{}{:03}.{}", prefix, index, extension),
+        None => format!("{}{:03}", prefix, index),
+    }
+}
+
+// A name that says nothing about where the chunk goes: 32 hex digits, plus the
+// codec's extension. It only has to be unguessable enough not to give the order
+// away, which std's randomly keyed hasher manages without another dependency.
+pub(crate) fn random_chunk_name(compression: Compression) -> String {
+    let state = RandomState::new();
+    let name = format!("{:016x}{:016x}", state.hash_one(0u8), state.hash_one(1u8));
+    match compression.extension() {
+        Some(extension) => format!("{}.{}", name, extension),
+        None => name,
+    }
+}
+
+// Whether `name` looks like one `random_chunk_name` made.
+pub(crate) fn is_random_name(name: &str) -> bool {
+    let (stem, extension) = name.split_once('.').unwrap_or((name, ""));
+    stem.len() == 32
+        && stem.bytes().all(|b| b.is_ascii_hexdigit())
+        && (extension.is_empty() || Compression::from_extension(extension).is_some())
+}
+
+// Whether `name` is one of the subdirectories a split sharded with
+// `ShardDirs::Shard` puts its chunks in: `00`, `01`, … and on past `99` if need be.
+pub(crate) fn is_shard_dir(name: &str) -> bool {
+    name.len() >= 2 && name.bytes().all(|b| b.is_ascii_digit())
+}
+
+// The shard subdirectory chunk `index` goes in with `per_directory` chunks to each.
+pub(crate) fn shard_dir(index: usize, per_directory: usize) -> String {
+    format!("{:02}", index / per_directory.max(1))
+}
+
+// `name`, as the manifest lists it, without the shard subdirectory it may be in.
+pub(crate) fn unsharded(name: &str) -> &str {
+    match name.split_once('/') {
+        Some((directory, file)) if is_shard_dir(directory) => file,
+        _ => name,
+    }
+}
+
+// A hidden file next to the chunk the manifest calls `name`, in its shard subdirectory
+// if it has one, as in `.chunk007.repair` or `03/.chunk307.repair`.
+pub(crate) fn temp_name(name: &str, suffix: &str) -> String {
+    match name.split_once('/') {
+        Some((directory, file)) if is_shard_dir(directory) => {
+            format!("{}/.{}.{}", directory, file, suffix)
+        }
+        _ => format!(".{}.{}", name, suffix),
+    }
+}
+
+// Make the shard subdirectory the file the manifest calls `name` goes in, if it has one
+// and it has gone missing along with every chunk in it, before putting it back.
+pub(crate) fn restore_shard_dir(directory: &Path, name: &str) -> Result<()> {
+    match name.split_once('/') {
+        Some((shard, _)) if is_shard_dir(shard) => {
+            let path = directory.join(shard);
+            fs::create_dir_all(&path).doing("creating", &path)
+        }
+        _ => Ok(()),
+    }
+}
+
+// The debug line for every chunk written, whichever way it was.
+pub(crate) fn log_written(path: &Path, size: u64, hash: Option<&str>) {
+    match hash {
+        Some(hash) => debug!("wrote {} ({} bytes, hash {})", path.display(), size, hash),
+        None => debug!("wrote {} ({} bytes)", path.display(), size),
+    }
+}
+
+// A directory of `chunk000`, `chunk001`, … files next to an `info.json`, as written
+// by every version so far, or `chunk000.gz`, … when compressed. Sets split with
+// random names are only readable through their manifest; those named for another tool
+// to join (see `Compat`) are readable with or without one.
+#[derive(Clone, Debug)]
+pub struct LocalDirStore {
+    directory: PathBuf,
+    compression: Compression,
+    level: u32,
+    // Chunks the manifest lists as stored differently from `compression`
+    overrides: BTreeMap<usize, Compression>,
+    // Every chunk's file name, when they are random or named for another tool
+    names: BTreeMap<usize, String>,
+    // How many chunks the set has, when known, for the header of armored ones
+    count: Option<usize>,
+    // A second directory every chunk and the manifest are written to as well
+    mirror: Option<Arc<Mirror>>,
+    // With this many chunks to a subdirectory, new chunks go into `00/`, `01/`, …
+    shards: Option<usize>,
+    // New chunks are written to temporary files, see `scratch`, in `temp_dir` or beside
+    // them, and only put in place once complete
+    staged: bool,
+    temp_dir: Option<PathBuf>,
+    // How chunk files that fail in a way that can pass are tried again; see `retry`
+    retry: RetryPolicy,
+    // The buffers chunks are copied with, shared by the stores of one split
+    io: Arc<Io>,
+    // Read a manifest changed after it was sealed rather than fail
+    accept_modified: bool,
+    // The key chunks are encrypted with, see `crypt`, and whether they are, which for a
+    // set opened without its key keeps any of them from being read or written
+    key: Option<ChunkKey>,
+    encrypted: bool,
+    // Write the manifest with its metadata sealed under `key`; see `Manifest::conceal`
+    conceal: bool,
+}
+
+// The copy a split writes into a second directory as it goes. A failure there either
+// fails the split or, unless `fatal`, is recorded and stops the copy while the split
+// carries on without it.
+#[derive(Debug)]
+struct Mirror {
+    directory: PathBuf,
+    fatal: bool,
+    failure: Mutex<Option<String>>,
+}
+
+impl LocalDirStore {
+    // A store for uncompressed chunks, such as a new set.
+    pub fn new(directory: impl Into<PathBuf>) -> LocalDirStore {
+        LocalDirStore {
+            directory: directory.into(),
+            compression: Compression::None,
+            level: 0,
+            overrides: BTreeMap::new(),
+            names: BTreeMap::new(),
+            count: None,
+            mirror: None,
+            shards: None,
+            staged: false,
+            temp_dir: None,
+            retry: RetryPolicy::default(),
+            io: Arc::default(),
+            accept_modified: false,
+            key: None,
+            encrypted: false,
+            conceal: false,
+        }
+    }
+
+    // Write each new chunk to a temporary file first, as a rechunk does, in `temp_dir`
+    // or else beside it.
+    pub fn staged(mut self, temp_dir: Option<PathBuf>) -> LocalDirStore {
+        self.staged = true;
+        self.temp_dir = temp_dir;
+        self
+    }
+
+    // Create, write and open chunks again as `policy` says when that fails in a way
+    // that can pass, rather than not at all.
+    pub fn retrying(mut self, policy: RetryPolicy) -> LocalDirStore {
+        self.retry = policy;
+        self
+    }
+
+    // Copy chunks with the buffers of `io`, and with its `--direct-io`.
+    pub(crate) fn with_io(mut self, io: Arc<Io>) -> LocalDirStore {
+        self.io = io;
+        self
+    }
+
+    pub(crate) fn io(&self) -> &Arc<Io> {
+        &self.io
+    }
+
+    // Read a manifest that was changed after it was sealed, with a warning, rather than
+    // fail with `MetadataModified`.
+    pub fn accepting_modified(mut self, accept: bool) -> LocalDirStore {
+        self.accept_modified = accept;
+        self
+    }
+
+    // Encrypt new chunks with `key`, and decrypt a set's with it; see `crypt`.
+    pub fn with_key(mut self, key: Option<ChunkKey>) -> LocalDirStore {
+        self.encrypted |= key.is_some();
+        self.key = key;
+        self
+    }
+
+    // Write the manifest with everything but what finding and checking the chunks takes
+    // sealed under the key.
+    pub fn concealing(mut self, conceal: bool) -> LocalDirStore {
+        self.conceal = conceal;
+        self
+    }
+
+    // Fails with `Encrypted` when the set's chunks are, and the store has no key for them.
+    fn check_key(&self) -> Result<()> {
+        match self.encrypted && self.key.is_none() {
+            true => Err(SplitterError::Encrypted {
+                path: self.directory.clone(),
+            }),
+            false => Ok(()),
+        }
+    }
+
+    // The store for an existing set, reading each chunk the way its manifest says it
+    // was written, which is refused for having been changed unless `accept_modified`.
+    pub fn open(directory: impl Into<PathBuf>, accept_modified: bool) -> Result<LocalDirStore> {
+        let store = LocalDirStore::new(directory).accepting_modified(accept_modified);
+        let Some(manifest) = store.read_info()? else {
+            let mut store = store;
+            for entry in fs::read_dir(&store.directory).at(&store.directory)? {
+                let name = entry.at(&store.directory)?.file_name();
+                let name = name.to_string_lossy();
+                if let Some((_, _, index)) = compat::parse(&name)
+                    && let Ok(index) = usize::try_from(index)
+                {
+                    store.names.insert(index, name.into_owned());
+                }
+            }
+            return Ok(store);
+        };
+        let compression = manifest.compression;
+        let mut store = store.compressed(compression, compression.default_level());
+        store.encrypted = manifest.encryption.is_some();
+        if !manifest.chunks.is_empty() {
+            store.count = Some(manifest.chunks.len());
+        }
+        // Chunks sharing a file are found by the name of the one that has it
+        let shared = manifest.shares_files();
+        for (index, entry) in manifest.indexed() {
+            if let Some(overridden) = entry.compression
+                && overridden != compression
+            {
+                store.overrides.insert(index, overridden);
+            }
+            if manifest.random_names
+                || manifest.compat.is_some()
+                || manifest.shards.is_some()
+                || shared
+            {
+                store.names.insert(index, entry.file().to_string());
+            }
+        }
+        Ok(store)
+    }
+
+    // Write chunks compressed with `compression` at `level`.
+    pub fn compressed(mut self, compression: Compression, level: u32) -> LocalDirStore {
+        self.compression = compression.at_level(level);
+        self.level = level;
+        self
+    }
+
+    // A set of `count` chunks, which armored chunks say in their header.
+    pub(crate) fn counted(mut self, count: usize) -> LocalDirStore {
+        self.count = Some(count);
+        self
+    }
+
+    // Put new chunks `per_directory` to a subdirectory, which must exist already.
+    pub(crate) fn sharded(mut self, per_directory: usize) -> LocalDirStore {
+        self.shards = Some(per_directory.max(1));
+        self
+    }
+
+    // `name` within the subdirectory new chunk `index` goes in, if the store has them.
+    pub(crate) fn sharded_name(&self, index: usize, name: &str) -> String {
+        match self.shards {
+            Some(per_directory) => format!("{}/{}", shard_dir(index, per_directory), name),
+            None => name.to_string(),
+        }
+    }
+
+    // Write everything into `directory` as well. With `fatal`, a failure there fails
+    // whatever was writing; otherwise the mirror is given up on, see `mirror_failure`.
+    pub(crate) fn mirrored(mut self, directory: impl Into<PathBuf>, fatal: bool) -> LocalDirStore {
+        self.mirror = Some(Arc::new(Mirror {
+            directory: directory.into(),
+            fatal,
+            failure: Mutex::new(None),
+        }));
+        self
+    }
+
+    // Why the mirror stopped being written to, if it did.
+    pub(crate) fn mirror_failure(&self) -> Option<String> {
+        let mirror = self.mirror.as_ref()?;
+        mirror.failure.lock().unwrap().clone()
+    }
+
+    // Copy the finished file `name` into the mirror, as for parity files that are
+    // written outside the store.
+    pub(crate) fn mirror_copy(&self, name: &str) -> Result<()> {
+        let Some(path) = self.mirror_path(name) else {
+            return Ok(());
+        };
+        let result = fs::copy(self.directory.join(name), &path).map(|_| ());
+        self.mirror_result(&path, result).map(|_| ())
+    }
+
+    // Where `name` goes in the mirror, while it is still being written to.
+    fn mirror_path(&self, name: &str) -> Option<PathBuf> {
+        let mirror = self.mirror.as_ref()?;
+        if mirror.failure.lock().unwrap().is_some() {
+            return None;
+        }
+        Some(mirror.directory.join(name))
+    }
+
+    // The error when the mirror failing at `path` is fatal; otherwise None, recording
+    // the first failure and warning about it.
+    fn mirror_result<T>(&self, path: &Path, result: io::Result<T>) -> Result<Option<T>> {
+        let (Some(mirror), Err(source)) = (&self.mirror, &result) else {
+            return Ok(result.ok());
+        };
+        if mirror.fatal {
+            return result.map(Some).at(path);
+        }
+        let mut failure = mirror.failure.lock().unwrap();
+        if failure.is_none() {
+            warn!(
+                "cannot write {}: {}; carrying on without the mirror",
+                path.display(),
+                source
+            );
+            *failure = Some(format!("{}: {}", path.display(), source));
+        }
+        Ok(None)
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn manifest_path(&self) -> PathBuf {
+        self.directory.join(MANIFEST_NAME)
+    }
+
+    // How chunk `index` is stored, which for a set opened from its manifest may differ
+    // from chunk to chunk.
+    pub fn chunk_compression(&self, index: usize) -> Compression {
+        self.overrides
+            .get(&index)
+            .copied()
+            .unwrap_or(self.compression)
+    }
+
+    // `create_chunk` and `finish_chunk` for workers sharing the store.
+    pub(crate) fn create(&self, index: usize) -> Result<ChunkWriter> {
+        self.create_named(index, &self.new_chunk_name(index), self.compression)
+    }
+
+    // A new file called `name` for chunk `index`, stored with `compression` rather than
+    // the store's own codec if need be. The manifest then has to record both for it.
+    pub(crate) fn create_named(
+        &self,
+        index: usize,
+        name: &str,
+        compression: Compression,
+    ) -> Result<ChunkWriter> {
+        self.check_key()?;
+        let path = self.directory.join(name);
+        let (file, staged) = match self.staged {
+            true => {
+                let (file, staged) = Staged::create(&path, self.temp_dir.as_deref())?;
+                (file, Some(staged))
+            }
+            false => {
+                let file = retried(&self.retry, "creating", &path, || File::create(&path))
+                    .doing("creating chunk", &path)?;
+                (file, None)
+            }
+        };
+        let file = Retrying::new(file, &path, &self.retry);
+        let mut mirror = None;
+        if let Some(mirror_path) = self.mirror_path(name) {
+            let created = File::create(&mirror_path);
+            mirror = self
+                .mirror_result(&mirror_path, created)?
+                .map(|file| (mirror_path, file));
+        }
+        let file = Tee {
+            file,
+            mirror,
+            failure: None,
+            hasher: self.key.is_some().then(|| HashAlgorithm::Sha256.hasher()),
+        };
+        let file = SealedWriter::new(file, self.key.as_ref()).at(&path)?;
+        let encoder = match compression {
+            Compression::None => Encoder::Plain(Box::new(file)),
+            Compression::Gzip => Encoder::Gzip(Box::new(GzEncoder::new(file, self.level))),
+            Compression::Armor => {
+                let encoder = ArmorEncoder::new(file, index, self.count).at(&path)?;
+                Encoder::Armor(Box::new(encoder))
+            }
+            Compression::Zstd { level, long } => {
+                let encoder = ZstEncoder::new(file, level, long).at(&path)?;
+                Encoder::Zstd(Box::new(encoder))
+            }
+        };
+        Ok(ChunkWriter {
+            path,
+            encoder,
+            staged,
+        })
+    }
+
+    // With `--direct-io` the chunk's pages are flushed and dropped from the cache.
+    // Returns the SHA-256 of the file as written when the store encrypts, for its
+    // manifest entry's `stored_hash`.
+    pub(crate) fn finish(&self, writer: ChunkWriter) -> Result<Option<String>> {
+        let path = writer.path;
+        let sealed = match writer.encoder {
+            Encoder::Plain(sealed) => *sealed,
+            Encoder::Gzip(encoder) => encoder.finish().at(&path)?,
+            Encoder::Armor(encoder) => encoder.finish().at(&path)?,
+            Encoder::Zstd(encoder) => encoder.finish().at(&path)?,
+        };
+        let mut tee = sealed.finish().at(&path)?;
+        let stored_hash = tee.hasher.take().map(ChunkHasher::finish);
+        self.io.release(&tee.file.inner, 0, 0, true).at(&path)?;
+        if let Some(staged) = writer.staged {
+            drop(tee.file);
+            staged.persist(&path)?;
+        }
+        if let Some((mirror_path, source)) = tee.failure {
+            self.mirror_result::<()>(&mirror_path, Err(source))?;
+        } else if let Some((mirror_path, file)) = tee.mirror {
+            let released = self.io.release(&file, 0, 0, true);
+            self.mirror_result(&mirror_path, released)?;
+        }
+        Ok(stored_hash)
+    }
+
+    // How much smaller the store's codec makes `sample`, as its size over the
+    // compressed size; 1 for a store that doesn't compress.
+    pub(crate) fn compression_ratio(&self, sample: &[u8]) -> io::Result<f64> {
+        let compressed = match self.compression {
+            Compression::None | Compression::Armor => return Ok(1.0),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), self.level);
+                encoder.write_all(sample)?;
+                encoder.finish()?.len()
+            }
+            Compression::Zstd { level, long } => {
+                let mut encoder = ZstEncoder::new(Vec::new(), level, long)?;
+                encoder.write_all(sample)?;
+                encoder.finish()?.len()
+            }
+        };
+        Ok(sample.len() as f64 / compressed.max(1) as f64)
+    }
+}
+
+// A chunk being written to a `LocalDirStore`, compressed on the way when the store is.
+pub struct ChunkWriter {
+    path: PathBuf,
+    encoder: Encoder,
+    // Where it is written until it is finished, for a staged store
+    staged: Option<Staged>,
+}
+
+// Compression goes before encryption, which is all the file and the mirror see.
+enum Encoder {
+    Plain(Box<SealedWriter<Tee>>),
+    Gzip(Box<GzEncoder<SealedWriter<Tee>>>),
+    Armor(Box<ArmorEncoder<SealedWriter<Tee>>>),
+    Zstd(Box<ZstEncoder<SealedWriter<Tee>>>),
+}
+
+// The chunk file, and its copy in the mirror until writing that fails. The failure is
+// only dealt with once the chunk is finished, so the error names the mirror's file.
+// What is written is hashed as it goes when the chunk is encrypted.
+struct Tee {
+    file: Retrying<File>,
+    mirror: Option<(PathBuf, File)>,
+    failure: Option<(PathBuf, io::Error)>,
+    hasher: Option<ChunkHasher>,
+}
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..written]);
+        }
+        if let Some((path, file)) = &mut self.mirror
+            && let Err(e) = file.write_all(&buf[..written])
+        {
+            self.failure = Some((path.clone(), e));
+            self.mirror = None;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.encoder {
+            Encoder::Plain(file) => file.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Armor(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.encoder {
+            Encoder::Plain(file) => file.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Armor(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+// A chunk of a `LocalDirStore` read back as its original bytes. Seeking within a
+// compressed chunk decodes up to the new position, from the start when it is behind
+// the current one.
+pub struct ChunkReader {
+    path: PathBuf,
+    compression: Compression,
+    key: Option<ChunkKey>,
+    retry: RetryPolicy,
+    buffer_size: usize,
+    decoder: Decoder,
+    position: u64,
+}
+
+// An encrypted chunk is decrypted before it is decompressed.
+enum Decoder {
+    Plain(Retrying<File>),
+    Sealed(Box<SealedReader<Retrying<File>>>),
+    Gzip(Box<GzDecoder<BufReader<SealedReader<Retrying<File>>>>>),
+    Armor(Box<ArmorDecoder<BufReader<SealedReader<Retrying<File>>>>>),
+    Zstd(Box<ZstDecoder<BufReader<SealedReader<Retrying<File>>>>>),
+}
+
+impl ChunkReader {
+    pub(crate) fn open(path: &Path, compression: Compression) -> io::Result<ChunkReader> {
+        ChunkReader::open_with(
+            path,
+            compression,
+            &RetryPolicy::default(),
+            DEFAULT_BUFFER_SIZE,
+        )
+    }
+
+    // `open`, with the file opened and read again as `retry` says when that fails in a
+    // way that can pass, and a compressed one read up to `buffer_size` at a time.
+    pub(crate) fn open_with(
+        path: &Path,
+        compression: Compression,
+        retry: &RetryPolicy,
+        buffer_size: usize,
+    ) -> io::Result<ChunkReader> {
+        ChunkReader::open_keyed(path, compression, None, retry, buffer_size)
+    }
+
+    // `open_with` for a chunk of an encrypted set, decrypted with `key`.
+    pub(crate) fn open_keyed(
+        path: &Path,
+        compression: Compression,
+        key: Option<&ChunkKey>,
+        retry: &RetryPolicy,
+        buffer_size: usize,
+    ) -> io::Result<ChunkReader> {
+        let capacity = buffer_size.min(1 << 20);
+        let file = retried(retry, "opening", path, || File::open(path))?;
+        let file = Retrying::new(file, path, retry);
+        let decoder = match (compression, key) {
+            (Compression::None, None) => Decoder::Plain(file),
+            (Compression::None, Some(key)) => {
+                Decoder::Sealed(Box::new(SealedReader::new(file, Some(key))?))
+            }
+            (compression, key) => {
+                Decoder::open(SealedReader::new(file, key)?, compression, capacity)?
+            }
+        };
+        Ok(ChunkReader {
+            path: path.to_path_buf(),
+            compression,
+            key: key.cloned(),
+            retry: retry.clone(),
+            buffer_size,
+            decoder,
+            position: 0,
+        })
+    }
+
+    fn skip(&mut self, len: u64) -> io::Result<u64> {
+        io::copy(&mut Read::take(&mut *self, len), &mut io::sink())
+    }
+}
+
+impl Decoder {
+    // The decoder for a compressed chunk.
+    fn open(
+        file: SealedReader<Retrying<File>>,
+        compression: Compression,
+        capacity: usize,
+    ) -> io::Result<Decoder> {
+        Ok(match compression {
+            Compression::None => unreachable!("uncompressed chunks have no decoder"),
+            Compression::Gzip => {
+                let file = BufReader::with_capacity(capacity, file);
+                Decoder::Gzip(Box::new(GzDecoder::new(file)))
+            }
+            Compression::Armor => {
+                let file = BufReader::with_capacity(capacity, file);
+                Decoder::Armor(Box::new(ArmorDecoder::new(file)))
+            }
+            Compression::Zstd { .. } => {
+                let file = BufReader::with_capacity(capacity, file);
+                Decoder::Zstd(Box::new(ZstDecoder::new(file)?))
+            }
+        })
+    }
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = match &mut self.decoder {
+            Decoder::Plain(file) => file.read(buf)?,
+            Decoder::Sealed(reader) => reader.read(buf)?,
+            Decoder::Gzip(decoder) => decoder.read(buf)?,
+            Decoder::Armor(decoder) => decoder.read(buf)?,
+            Decoder::Zstd(decoder) => decoder.read(buf)?,
+        };
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for ChunkReader {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        if let Decoder::Plain(file) = &mut self.decoder {
+            self.position = file.seek(to)?;
+            return Ok(self.position);
+        }
+        let target = match to {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+            // The end is only known once it has been decoded
+            SeekFrom::End(delta) => {
+                self.skip(u64::MAX)?;
+                self.position.checked_add_signed(delta)
+            }
+        };
+        let Some(target) = target else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative or overflowing position",
+            ));
+        };
+        if target < self.position {
+            let (path, key, retry) = (&self.path, self.key.as_ref(), &self.retry);
+            *self = ChunkReader::open_keyed(path, self.compression, key, retry, self.buffer_size)?;
+        }
+        // Past the end, as with a file, reads there return nothing
+        self.skip(target - self.position)?;
+        self.position = target;
+        Ok(target)
+    }
+}
+
+impl ChunkStore for LocalDirStore {
+    type Writer = ChunkWriter;
+    type Reader = ChunkReader;
+
+    fn create_chunk(&mut self, index: usize) -> Result<ChunkWriter> {
+        self.create(index)
+    }
+
+    fn finish_chunk(&mut self, _index: usize, writer: ChunkWriter) -> Result<()> {
+        self.finish(writer).map(|_| ())
+    }
+
+    fn open_chunk(&self, index: usize) -> Result<ChunkReader> {
+        self.check_key()?;
+        let path = self.chunk_path(index);
+        let compression = self.chunk_compression(index);
+        let (key, retry) = (self.key.as_ref(), &self.retry);
+        ChunkReader::open_keyed(&path, compression, key, retry, self.io.buffer_size())
+            .doing("opening chunk", &path)
+    }
+
+    // Compressed and encrypted chunks have to be decoded to tell; callers with a
+    // manifest use the sizes it recorded instead.
+    fn chunk_len(&self, index: usize) -> Result<u64> {
+        let path = self.chunk_path(index);
+        if self.chunk_compression(index).is_none() && !self.encrypted {
+            return Ok(fs::metadata(&path).at(&path)?.len());
+        }
+        let mut reader = self.open_chunk(index)?;
+        io::copy(&mut reader, &mut io::sink()).at(&path)
+    }
+
+    // A file other chunks are stored in as well stays until the last of them goes.
+    fn remove_chunk(&mut self, index: usize) -> Result<()> {
+        let path = self.chunk_path(index);
+        if let Some(name) = self.names.remove(&index)
+            && self.names.values().any(|other| *other == name)
+        {
+            return Ok(());
+        }
+        fs::remove_file(&path).at(&path)
+    }
+
+    fn list_chunks(&self) -> Result<Vec<usize>> {
+        if !self.names.is_empty() {
+            let present = self
+                .names
+                .iter()
+                .filter(|(_, name)| self.directory.join(name).exists());
+            return Ok(present.map(|(&index, _)| index).collect());
+        }
+        let mut indices = Vec::new();
+        for entry in fs::read_dir(&self.directory).at(&self.directory)? {
+            let entry = entry.at(&self.directory)?;
+            if let Some(index) = chunk_index(&entry.file_name().to_string_lossy())
+                && let Ok(index) = usize::try_from(index)
+            {
+                indices.push(index);
+            }
+        }
+        indices.sort_unstable();
+        Ok(indices)
+    }
+
+    fn read_info(&self) -> Result<Option<Manifest>> {
+        Manifest::load(&self.directory, self.accept_modified)
+    }
+
+    fn write_info(&mut self, manifest: &Manifest) -> Result<()> {
+        let concealed = match (&self.key, self.conceal) {
+            (Some(key), true) => Some(manifest.conceal(key, &self.directory)?),
+            _ => None,
+        };
+        let manifest = concealed.as_ref().unwrap_or(manifest);
+        manifest.save(&self.directory)?;
+        if let Some(path) = self.mirror_path(MANIFEST_NAME) {
+            let saved = manifest
+                .to_json()
+                .map_err(io::Error::other)
+                .and_then(|data| fs::write(&path, data));
+            self.mirror_result(&path, saved)?;
+        }
+        Ok(())
+    }
+
+    fn chunk_path(&self, index: usize) -> PathBuf {
+        match self.names.get(&index) {
+            Some(name) => self.directory.join(name),
+            None => self
+                .directory
+                .join(self.sharded_name(index, &chunk_name(index, self.chunk_compression(index)))),
+        }
+    }
+
+    fn new_chunk_name(&self, index: usize) -> String {
+        self.sharded_name(index, &chunk_name(index, self.compression))
+    }
+
+    fn compression(&self) -> Compression {
+        self.compression
+    }
+}
+
+// Keeps everything in memory, for exercising the split and reconstruct logic without
+// a file system. Clones share their contents.
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryStore {
+    chunks: Arc<Mutex<BTreeMap<usize, Vec<u8>>>>,
+    info: Arc<Mutex<Option<Manifest>>>,
+}
+
+impl InMemoryStore {
+    pub fn new() -> InMemoryStore {
+        InMemoryStore::default()
+    }
+
+    pub fn chunk(&self, index: usize) -> Option<Vec<u8>> {
+        self.chunks.lock().unwrap().get(&index).cloned()
+    }
+}
+
+// Appends to its chunk in the store as it is written.
+pub struct MemoryChunk {
+    chunks: Arc<Mutex<BTreeMap<usize, Vec<u8>>>>,
+    index: usize,
+}
+
+impl Write for MemoryChunk {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut chunks = self.chunks.lock().unwrap();
+        chunks.entry(self.index).or_default().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ChunkStore for InMemoryStore {
+    type Writer = MemoryChunk;
+    type Reader = io::Cursor<Vec<u8>>;
+
+    fn create_chunk(&mut self, index: usize) -> Result<MemoryChunk> {
+        self.chunks.lock().unwrap().insert(index, Vec::new());
+        Ok(MemoryChunk {
+            chunks: Arc::clone(&self.chunks),
+            index,
+        })
+    }
+
+    fn open_chunk(&self, index: usize) -> Result<io::Cursor<Vec<u8>>> {
+        match self.chunk(index) {
+            Some(data) => Ok(io::Cursor::new(data)),
+            None => Err(io::Error::from(io::ErrorKind::NotFound)).at(&self.chunk_path(index)),
+        }
+    }
+
+    fn chunk_len(&self, index: usize) -> Result<u64> {
+        let chunks = self.chunks.lock().unwrap();
+        match chunks.get(&index) {
+            Some(data) => Ok(data.len() as u64),
+            None => Err(io::Error::from(io::ErrorKind::NotFound)).at(&self.chunk_path(index)),
+        }
+    }
+
+    fn remove_chunk(&mut self, index: usize) -> Result<()> {
+        self.chunks.lock().unwrap().remove(&index);
+        Ok(())
+    }
+
+    fn list_chunks(&self) -> Result<Vec<usize>> {
+        Ok(self.chunks.lock().unwrap().keys().copied().collect())
+    }
+
+    fn read_info(&self) -> Result<Option<Manifest>> {
+        Ok(self.info.lock().unwrap().clone())
+    }
+
+    fn write_info(&mut self, manifest: &Manifest) -> Result<()> {
+        *self.info.lock().unwrap() = Some(manifest.clone());
+        Ok(())
+    }
+}
+
+// Cut `input` into `chunk_size` pieces in `store`, hashing each one when `hash` is
+// given, and return the entries for its manifest. `input_path` names the input in
+// error messages. The manifest itself is left to the caller.
+pub fn split_into<S: ChunkStore>(
+    input: &mut (impl BufRead + Send),
+    input_path: &Path,
+    store: &mut S,
+    chunk_size: u64,
+    hash: Option<HashAlgorithm>,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Vec<ChunkEntry>> {
+    let io = Io::default();
+    split_into_with(
+        &io, input, input_path, store, chunk_size, hash, progress, cancel,
+    )
+}
+
+// `split_into`, copying with the buffers of `io`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn split_into_with<S: ChunkStore>(
+    io: &Io,
+    input: &mut (impl BufRead + Send),
+    input_path: &Path,
+    store: &mut S,
+    chunk_size: u64,
+    hash: Option<HashAlgorithm>,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Vec<ChunkEntry>> {
+    let mut chunks = Vec::new();
+
+    // A single read may return fewer bytes than asked for, so each chunk is copied
+    // until it is full or the input runs out. Checking for end of input first avoids
+    // leaving an empty chunk behind.
+    while !input.fill_buf().at(input_path)?.is_empty() {
+        cancel.check()?;
+        let index = chunks.len();
+        let chunk_path = store.chunk_path(index);
+        progress(ProgressEvent::ChunkStarted {
+            index,
+            size: chunk_size,
+        });
+        let mut writer = store.create_chunk(index)?;
+        let mut hasher = hash.map(HashAlgorithm::hasher);
+        let copied = copy_overlapped(
+            io,
+            &mut (&mut *input).take(chunk_size),
+            &mut Counting {
+                inner: &mut writer,
+                copied: &mut |delta| progress(ProgressEvent::BytesCopied { delta }),
+                cancel,
+            },
+            hasher.as_mut(),
+        )
+        .at(&chunk_path)?;
+        store.finish_chunk(index, writer)?;
+        chunks.push(written_entry(store, index, copied, hasher, progress));
+    }
+    Ok(chunks)
+}
+
+// What the manifest has of chunk `index` of `store`, now `size` bytes were written to
+// it through `hasher`, which is reported finished.
+pub(crate) fn written_entry<S: ChunkStore>(
+    store: &S,
+    index: usize,
+    size: u64,
+    hasher: Option<ChunkHasher>,
+    progress: &mut dyn FnMut(ProgressEvent),
+) -> ChunkEntry {
+    let entry = ChunkEntry {
+        name: store.new_chunk_name(index),
+        size,
+        hash: hasher.map(ChunkHasher::finish),
+        stored_hash: None,
+        compression: None,
+        same_as: None,
+        shared: None,
+        mtime: None,
+    };
+    log_written(&store.chunk_path(index), size, entry.hash.as_deref());
+    progress(ProgressEvent::ChunkFinished {
+        index,
+        hash: entry.hash.clone(),
+    });
+    entry
+}
+
+// `split_into` from any reader, such as a socket or a request body, followed by the
+// manifest of a file named `original_filename`, which is returned. `reconstruct_from`
+// goes the other way, into any writer. As with `split_into`, chunks written before a
+// failure or a cancellation are left for the caller to remove.
+pub fn split_reader<S: ChunkStore>(
+    input: impl Read + Send,
+    original_filename: &str,
+    store: &mut S,
+    chunk_size: u64,
+    hash: Option<HashAlgorithm>,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<Manifest> {
+    if chunk_size == 0 {
+        return Err(SplitterError::InvalidOption {
+            field: "chunk_size",
+            reason: "must be greater than zero",
+        });
+    }
+    let mut input = BufReader::with_capacity(DEFAULT_BUFFER_SIZE, input);
+    let input_path = Path::new(original_filename);
+    let chunks = split_into(
+        &mut input, input_path, store, chunk_size, hash, progress, cancel,
+    )?;
+    let compression = store.compression();
+    let manifest = stream_manifest(original_filename, chunk_size, hash, compression, chunks);
+    store.write_info(&manifest)?;
+    Ok(manifest)
+}
+
+// The manifest of a set split from a stream, of which nothing is known but the name it
+// is saved under: numbered chunks and no metadata to restore.
+pub(crate) fn stream_manifest(
+    original_filename: impl Into<String>,
+    chunk_size: u64,
+    hash: Option<HashAlgorithm>,
+    compression: Compression,
+    chunks: Vec<ChunkEntry>,
+) -> Manifest {
+    Manifest {
+        version: MANIFEST_VERSION,
+        original_filename: original_filename.into(),
+        name_form: None,
+        link_name: None,
+        range: None,
+        xattrs: BTreeMap::new(),
+        owner: None,
+        created: None,
+        chunk_size: Some(chunk_size),
+        hash,
+        compression,
+        encryption: None,
+        metadata: None,
+        random_names: false,
+        compat: None,
+        shards: None,
+        parity: None,
+        span: None,
+        chunks,
+    }
+}
+
+// Write the chunks in `store` to `output` in order and return how many bytes that
+// was. Gaps in the numbering are an error, as are chunks that change size while
+// being copied, and those that don't match the hash the store's manifest has for
+// them. Failures are reported against the chunk.
+pub fn reconstruct_from<S: ChunkStore>(
+    store: &S,
+    output: &mut impl Write,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<u64> {
+    reconstruct_from_with(&Io::default(), store, output, progress, cancel)
+}
+
+// `reconstruct_from`, copying with the buffers of `io`.
+pub(crate) fn reconstruct_from_with<S: ChunkStore>(
+    io: &Io,
+    store: &S,
+    output: &mut impl Write,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> Result<u64> {
+    let indices = store.list_chunks()?;
+    check_numbering(&indices)?;
+    let manifest = store.read_info().ok().flatten();
+    let (algorithm, hashes) = recorded_hashes(manifest.as_ref());
+
+    let mut total = 0;
+    for index in indices {
+        cancel.check()?;
+        let chunk_path = store.chunk_path(index);
+        let size = store.chunk_len(index)?;
+        progress(ProgressEvent::ChunkStarted { index, size });
+        debug!("copying {} ({} bytes)", chunk_path.display(), size);
+        let mut reader = store.open_chunk(index)?;
+        let expected = hashes.get(&index).map(String::as_str);
+        let mut hasher = algorithm
+            .filter(|_| expected.is_some())
+            .map(HashAlgorithm::hasher);
+        let copied = copy_overlapped(
+            io,
+            &mut reader,
+            &mut Counting {
+                inner: &mut *output,
+                copied: &mut |delta| progress(ProgressEvent::BytesCopied { delta }),
+                cancel,
+            },
+            hasher.as_mut(),
+        )
+        .at(&chunk_path)?;
+        let hash = hasher.map(ChunkHasher::finish);
+        check_read_back(&chunk_path, (copied, size), hash.as_deref(), expected)?;
+        progress(ProgressEvent::ChunkFinished { index, hash });
+        total += copied;
+    }
+    output.flush().at(&store.chunk_path(0))?;
+    Ok(total)
+}
+
+// The chunks of a set read back, `indices`, have to run from 0 without a gap.
+pub(crate) fn check_numbering(indices: &[usize]) -> Result<()> {
+    let missing = missing_below(indices.iter().map(|&index| index as u64))?;
+    if !missing.is_empty() {
+        return Err(SplitterError::MissingChunks { indices: missing });
+    }
+    Ok(())
+}
+
+// What chunks read back are checked against: the algorithm `manifest` hashes with, and
+// the hash it has for each chunk, by index.
+pub(crate) fn recorded_hashes(
+    manifest: Option<&Manifest>,
+) -> (Option<HashAlgorithm>, BTreeMap<usize, String>) {
+    let algorithm = manifest.and_then(|manifest| manifest.hash);
+    let hashes = manifest
+        .iter()
+        .flat_map(|manifest| manifest.indexed())
+        .filter_map(|(index, entry)| Some((index, entry.hash.clone()?)))
+        .collect();
+    (algorithm, hashes)
+}
+
+// A chunk read back has to come to the size the store has for it, `(copied, size)`,
+// and `hash` has to be the one the manifest has, `expected`, when there are both.
+pub(crate) fn check_read_back(
+    chunk_path: &Path,
+    (copied, size): (u64, u64),
+    hash: Option<&str>,
+    expected: Option<&str>,
+) -> Result<()> {
+    if copied != size {
+        return Err(SplitterError::ChangedSize {
+            path: chunk_path.to_path_buf(),
+        });
+    }
+    if let (Some(hash), Some(expected)) = (hash, expected)
+        && !hash.eq_ignore_ascii_case(expected)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "does not match its hash in info.json",
+        ))
+        .at(chunk_path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len as u64)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect()
+    }
+
+    // `data` split from a Cursor into a fresh store, hashed.
+    fn split(data: &[u8], chunk_size: u64) -> (InMemoryStore, Manifest) {
+        let mut store = InMemoryStore::new();
+        let manifest = split_reader(
+            Cursor::new(data),
+            "data.bin",
+            &mut store,
+            chunk_size,
+            Some(HashAlgorithm::Sha256),
+            &mut |_| {},
+            &CancelToken::new(),
+        )
+        .unwrap();
+        (store, manifest)
+    }
+
+    fn join(store: &InMemoryStore) -> Result<Vec<u8>> {
+        let mut output = Cursor::new(Vec::new());
+        reconstruct_from(store, &mut output, &mut |_| {}, &CancelToken::new())?;
+        Ok(output.into_inner())
+    }
+
+    #[test]
+    fn a_cursor_splits_into_memory_and_joins_into_another() {
+        let (store, manifest) = split(&data(1000), 300);
+        assert_eq!(manifest.original_filename, "data.bin");
+        let sizes: Vec<u64> = manifest.chunks.iter().map(|chunk| chunk.size).collect();
+        assert_eq!(sizes, [300, 300, 300, 100]);
+        assert!(manifest.chunks.iter().all(|chunk| chunk.hash.is_some()));
+        let info = store.read_info().unwrap().unwrap();
+        assert_eq!(info.to_json().unwrap(), manifest.to_json().unwrap());
+        assert_eq!(store.chunk(3), Some(data(1000)[900..].to_vec()));
+
+        let mut events = Vec::new();
+        let mut output = Cursor::new(Vec::new());
+        let copied = reconstruct_from(
+            &store,
+            &mut output,
+            &mut |event| events.push(event),
+            &CancelToken::new(),
+        )
+        .unwrap();
+        assert_eq!(copied, 1000);
+        assert_eq!(output.into_inner(), data(1000));
+        let finished = events
+            .iter()
+            .filter(|event| matches!(event, ProgressEvent::ChunkFinished { hash: Some(_), .. }))
+            .count();
+        assert_eq!(finished, 4);
+    }
+
+    #[test]
+    fn an_empty_cursor_is_a_set_of_no_chunks() {
+        let (store, manifest) = split(&[], 300);
+        assert!(manifest.chunks.is_empty());
+        assert_eq!(join(&store).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn chunks_that_changed_or_went_missing_fail_the_join() {
+        let (mut store, _) = split(&data(1000), 300);
+        store.create_chunk(1).unwrap().write_all(&[0; 300]).unwrap();
+        let result = join(&store);
+        assert!(
+            matches!(&result, Err(SplitterError::Io { path, source, .. })
+                if *path == store.chunk_path(1) && source.kind() == io::ErrorKind::InvalidData),
+            "{:?}",
+            result
+        );
+
+        store.remove_chunk(1).unwrap();
+        let result = join(&store);
+        assert!(
+            matches!(&result, Err(SplitterError::MissingChunks { indices }) if *indices == [1]),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn a_cancelled_split_or_join_stops() {
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let mut store = InMemoryStore::new();
+        let result = split_reader(
+            Cursor::new(data(1000)),
+            "data.bin",
+            &mut store,
+            300,
+            None,
+            &mut |_| {},
+            &cancel,
+        );
+        assert!(
+            matches!(result, Err(SplitterError::Cancelled)),
+            "{:?}",
+            result
+        );
+
+        let (store, _) = split(&data(1000), 300);
+        let mut output = Cursor::new(Vec::new());
+        let result = reconstruct_from(&store, &mut output, &mut |_| {}, &cancel);
+        assert!(
+            matches!(result, Err(SplitterError::Cancelled)),
+            "{:?}",
+            result
+        );
+        assert!(output.into_inner().is_empty());
+    }
+}
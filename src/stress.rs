@@ -0,0 +1,333 @@
+// `stress`: the self-test over and over, for leaving running against a disk suspected
+// of losing data. Every iteration draws a file and a combination of options from its
+// own seed, then splits the file, verifies the chunks, reconstructs the file and
+// compares it with the original. The sizes drawn favour the edges: an empty file, one
+// of exactly a chunk, a byte either side of that or of a multiple of it, as well as
+// any size up to --max-size, which can be several GiB where the disk has room for it.
+//
+// Iteration N of a run from seed S draws from seed S + N alone, so a failure is
+// repeated exactly with --seed S + N --iterations 1 and the same --max-size, which is
+// what is printed with it. What a failed iteration wrote is left where it is, with
+// the options it was split with in case.json, and the run goes on with the next one;
+// what passed is removed.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use reconstruct_large_file::manifest::{Compression, HashAlgorithm, Parity};
+use reconstruct_large_file::{
+    CancelToken, ReconstructOptions, ShardDirs, SplitOptions, SplitterError, reconstruct,
+    split_file, verify,
+};
+
+use crate::bench::generate;
+use crate::selftest::compare;
+use crate::{format_size, free_space};
+
+pub struct StressOptions {
+    pub directory: PathBuf,
+    pub iterations: u64,
+    pub seed: u64,
+    pub max_size: u64,
+    pub threads: usize,
+}
+
+// Chunks one iteration writes at most, so that a small chunk size drawn for a large
+// file doesn't spend the run on creating files
+const MAX_CHUNKS: u64 = 4096;
+const MIN_CHUNK_SIZE: u64 = 1 << 10;
+const MAX_CHUNK_SIZE: u64 = 16 << 20;
+
+// What one iteration does, all drawn from its seed.
+struct Case {
+    seed: u64,
+    size: u64,
+    zeros: bool,
+    split: SplitOptions,
+}
+
+// Run the iterations, printing how each went. Returns whether they all passed; an
+// error is for the run itself failing, such as the directory not being writable.
+pub fn run(options: &StressOptions, cancel: &CancelToken) -> io::Result<bool> {
+    fs::create_dir_all(&options.directory)?;
+    println!(
+        "Running {} iterations from seed {} in {}, with files of up to {}",
+        options.iterations,
+        options.seed,
+        options.directory.display(),
+        format_size(options.max_size)
+    );
+    let mut failed = 0;
+    for iteration in 0..options.iterations {
+        let seed = options.seed.wrapping_add(iteration);
+        let scratch = options.directory.join(format!("stress-{}", seed));
+        if scratch.exists() {
+            fs::remove_dir_all(&scratch)?;
+        }
+        fs::create_dir(&scratch)?;
+        let case = draw(options, seed, &scratch);
+        // The source, its chunks and the reconstruction exist at the same time
+        let needed = case.size.saturating_mul(3);
+        if let Some(available) = free_space(&scratch)
+            && available < needed
+        {
+            println!(
+                "SKIP  seed {:<20} {:>10}  needs {} free, and {} is",
+                seed,
+                format_size(case.size),
+                format_size(needed),
+                format_size(available)
+            );
+            fs::remove_dir(&scratch)?;
+            continue;
+        }
+        let started = Instant::now();
+        let outcome = run_case(&case, cancel);
+        if cancel.is_cancelled() {
+            let _ = fs::remove_dir_all(&scratch);
+            return Err(SplitterError::Cancelled.into());
+        }
+        let elapsed = started.elapsed().as_secs_f64();
+        match outcome {
+            Ok(()) => {
+                println!(
+                    "PASS  seed {:<20} {:>10} {:>8.2} s  {}",
+                    seed,
+                    format_size(case.size),
+                    elapsed,
+                    describe(&case)
+                );
+                fs::remove_dir_all(&scratch)?;
+            }
+            Err(e) => {
+                failed += 1;
+                println!(
+                    "FAIL  seed {:<20} {:>10} {:>8.2} s  {}",
+                    seed,
+                    format_size(case.size),
+                    elapsed,
+                    describe(&case)
+                );
+                println!("      {}", e);
+                keep(&case, &scratch);
+                println!(
+                    "      left in {}; repeat it with --seed {} --iterations 1 --max-size {}",
+                    scratch.display(),
+                    seed,
+                    options.max_size
+                );
+            }
+        }
+    }
+    match failed {
+        0 => println!("All {} iterations passed.", options.iterations),
+        _ => println!("{} of {} iterations failed.", failed, options.iterations),
+    }
+    Ok(failed == 0)
+}
+
+fn run_case(case: &Case, cancel: &CancelToken) -> io::Result<()> {
+    generate(&case.split.input, case.size, case.zeros, case.seed).map_err(during("generating"))?;
+    split_file(&case.split, &mut |_| {}, cancel).map_err(during("splitting"))?;
+    let chunks = &case.split.destination;
+    // An empty file is split into no chunks at all, which verify calls unhealthy
+    if case.size > 0 {
+        let report =
+            verify(chunks, &[], false, &mut |_| {}, cancel).map_err(during("verifying"))?;
+        if !report.is_ok() {
+            let health = &report.health;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "verify found {} chunks, missing {:?}, uneven {:?}, unexpected {:?}, \
+                     not matching {:?}",
+                    health.chunks,
+                    health.missing,
+                    health.uneven,
+                    health.unexpected,
+                    report.mismatched
+                ),
+            ));
+        }
+    }
+    let rebuild = ReconstructOptions {
+        output: Some("reconstructed".to_string()),
+        threads: case.split.threads,
+        ..ReconstructOptions::new(chunks)
+    };
+    reconstruct(&rebuild, &mut |_| {}, cancel).map_err(during("reconstructing"))?;
+    compare(&case.split.input, &chunks.join("reconstructed")).map_err(during("comparing"))
+}
+
+// An error of the step named `step`, saying so, unless it is the run being cancelled.
+fn during<E: Into<io::Error>>(step: &'static str) -> impl Fn(E) -> io::Error {
+    move |e| {
+        let e = e.into();
+        match e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<SplitterError>())
+        {
+            Some(SplitterError::Cancelled) => e,
+            _ => io::Error::new(e.kind(), format!("{}: {}", step, e)),
+        }
+    }
+}
+
+// Draw the file and options of the iteration with `seed`.
+fn draw(options: &StressOptions, seed: u64, scratch: &Path) -> Case {
+    let mut random = SplitMix64(seed);
+    // Log-uniform, so small chunks come up as often as large ones
+    let chunk_size = log_uniform(&mut random, MIN_CHUNK_SIZE, MAX_CHUNK_SIZE);
+    let max_size = options.max_size;
+    let size = match random.below(8) {
+        0 => 0,
+        1 => chunk_size,
+        2 => chunk_size - 1,
+        3 => chunk_size + 1,
+        4 => {
+            let multiple = chunk_size * (2 + random.below(8));
+            multiple - 1 + random.below(3)
+        }
+        _ => log_uniform(&mut random, 1, max_size.max(1)),
+    }
+    .min(max_size);
+    // Few enough chunks, by writing larger ones where there would be too many
+    let chunk_size = chunk_size.max(size.div_ceil(MAX_CHUNKS));
+    let hash = (random.below(2) == 1).then_some(HashAlgorithm::Sha256);
+    let compression = match random.below(5) {
+        0 => Compression::Gzip,
+        1 => Compression::Armor,
+        2 if cfg!(feature = "zstd") => Compression::Zstd {
+            level: 1 + random.below(5) as u32,
+            long: random.below(2) == 1,
+        },
+        _ => Compression::None,
+    };
+    let dedup = hash.is_some() && compression != Compression::Armor && random.below(4) == 0;
+    let parity = match random.below(6) {
+        // Parity takes every chunk for a file of its own, which dedup's aren't
+        _ if dedup => None,
+        0 => Some(Parity::Xor),
+        1 => Some(Parity::ReedSolomon {
+            shards: 1 + random.below(3) as usize,
+        }),
+        _ => None,
+    };
+    let threads = 1 + random.below(options.threads as u64) as usize;
+    let mut builder = SplitOptions::builder(scratch.join("source"), scratch.join("chunks"));
+    if random.below(4) == 0 {
+        builder = builder
+            .shard_dirs(ShardDirs::Shard)
+            .max_dir_files(2 + random.below(30) as usize);
+    }
+    let split = builder
+        .chunk_size(chunk_size)
+        .min_chunk_size(0)
+        .threads(threads)
+        .hash(hash)
+        .dedup(dedup)
+        .compression(compression)
+        // Compressed however little it shrinks, so the codec is always exercised
+        .min_ratio(0.0)
+        .parity(parity)
+        .build()
+        // Only ever drawn from combinations that go together
+        .expect("the options drawn for a stress iteration are valid");
+    Case {
+        seed,
+        size,
+        // Data that compresses, and that deduplicates down to one chunk
+        zeros: random.below(5) == 0,
+        split,
+    }
+}
+
+// The options of `case` in a few words.
+fn describe(case: &Case) -> String {
+    let split = &case.split;
+    let mut words = vec![
+        format!("chunks of {}", format_size(split.chunk_size)),
+        format!(
+            "{} thread{}",
+            split.threads,
+            if split.threads == 1 { "" } else { "s" }
+        ),
+    ];
+    if case.zeros {
+        words.push("zeros".to_string());
+    }
+    if split.hash.is_some() {
+        words.push("sha256".to_string());
+    }
+    if split.dedup {
+        words.push("dedup".to_string());
+    }
+    match split.compression {
+        Compression::None => {}
+        Compression::Gzip => words.push("gzip".to_string()),
+        Compression::Armor => words.push("armor".to_string()),
+        Compression::Zstd { level, long } => {
+            words.push(format!("zstd:{}{}", level, if long { " long" } else { "" }))
+        }
+    }
+    match split.parity {
+        Some(Parity::Xor) => words.push("xor parity".to_string()),
+        Some(Parity::ReedSolomon { shards }) => words.push(format!("{} rs parity", shards)),
+        None => {}
+    }
+    if split.shard_dirs == ShardDirs::Shard {
+        words.push(format!("sharded by {}", split.max_dir_files));
+    }
+    words.join(", ")
+}
+
+// Write what the failed `case` was beside what it left, for whoever looks into it.
+fn keep(case: &Case, scratch: &Path) {
+    let record = serde_json::json!({
+        "seed": case.seed,
+        "size": case.size,
+        "zeros": case.zeros,
+        "split": case.split,
+    });
+    let path = scratch.join("case.json");
+    let written = serde_json::to_string_pretty(&record)
+        .map_err(io::Error::other)
+        .and_then(|text| fs::write(&path, text + "\n"));
+    if let Err(e) = written {
+        eprintln!("Warning: could not write {}: {}", path.display(), e);
+    }
+}
+
+// Between `low` and `high`, both included, evenly over the powers of two between them.
+fn log_uniform(random: &mut SplitMix64, low: u64, high: u64) -> u64 {
+    if high <= low {
+        return low;
+    }
+    let (low_bits, high_bits) = (low.ilog2(), high.ilog2());
+    let bits = low_bits + random.below((high_bits - low_bits + 1) as u64) as u32;
+    let start = (1u64 << bits).max(low);
+    let end = 1u64
+        .checked_shl(bits + 1)
+        .map_or(high, |end| (end - 1).min(high));
+    start + random.below(end - start + 1)
+}
+
+// The same numbers for the same seed everywhere, as sample.rs has it.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Below `bound`, which must not be 0.
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
@@ -0,0 +1,152 @@
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STATUS: AtomicBool = AtomicBool::new(false);
+// The width of the terminal when it was last asked, for when asking fails
+static WIDTH: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Copy)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Blue,
+}
+
+impl Color {
+    fn code(self) -> &'static str {
+        match self {
+            Color::Red => "91",
+            Color::Green => "92",
+            Color::Yellow => "93",
+            Color::Blue => "94",
+        }
+    }
+}
+
+// Decide once at startup whether output gets colored. Colors are off when stdout is
+// not a terminal, when NO_COLOR is set (https://no-color.org) or when asked to by flag.
+pub fn init(no_color: bool) {
+    let wanted = !no_color
+        && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && io::stdout().is_terminal();
+    ENABLED.store(wanted && enable_ansi_support(), Ordering::Relaxed);
+    let status = io::stdout().is_terminal() && !dumb_terminal() && width().is_some();
+    STATUS.store(status, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn paint(text: &str, color: Color) -> String {
+    if ENABLED.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m", color.code(), text)
+    } else {
+        text.to_string()
+    }
+}
+
+// Reverse video, used to highlight the selected menu row.
+pub fn inverse(text: &str) -> String {
+    if ENABLED.load(Ordering::Relaxed) {
+        format!("\x1b[7m{}\x1b[0m", text)
+    } else {
+        text.to_string()
+    }
+}
+
+// Whether TERM says the terminal understands no control sequences, as in an Emacs shell
+// buffer, where neither a carriage return nor an escape does what it would elsewhere.
+pub fn dumb_terminal() -> bool {
+    env::var_os("TERM").is_some_and(|term| term == "dumb")
+}
+
+// Whether progress is shown as one status line redrawn in place. It is when stdout is a
+// terminal of a width that can be found out and not a dumb one; otherwise progress comes
+// as plain lines now and then, which read the same in a log, a pipe or a dumb terminal.
+pub fn status_line() -> bool {
+    STATUS.load(Ordering::Relaxed)
+}
+
+// Redraw the status line as `line`, fitted to the terminal as wide as it is now, so that
+// a resize is followed from the next redraw on. `done` puts `line` there for good and
+// moves off it, in full however long it is, only padded to cover the status line.
+pub fn draw_status(line: &str, done: bool) {
+    let width = width().unwrap_or(WIDTH.load(Ordering::Relaxed));
+    match done {
+        true => println!("\r{:<1$}", line, width.saturating_sub(1)),
+        false => {
+            print!("\r{}", fit_status(line, width));
+            let _ = io::stdout().flush();
+        }
+    }
+}
+
+// `line` as drawn over the one before it on a terminal `width` columns wide: cut to one
+// column less than that, since a line reaching the last column wraps on some terminals
+// and each redraw then scrolls, and padded to it so that nothing of a longer line before
+// is left showing. Every character is taken for a column, which the progress lines, all
+// ASCII, are.
+pub fn fit_status(line: &str, width: usize) -> String {
+    let columns = width.saturating_sub(1).max(1);
+    let mut fitted: String = line.chars().take(columns).collect();
+    let len = fitted.chars().count();
+    fitted.extend(std::iter::repeat_n(' ', columns - len));
+    fitted
+}
+
+// The width of the terminal on stdout, asked anew every time.
+fn width() -> Option<usize> {
+    let columns = crossterm::terminal::size()
+        .ok()
+        .map(|(columns, _)| columns as usize)
+        .filter(|&columns| columns > 0)?;
+    WIDTH.store(columns, Ordering::Relaxed);
+    Some(columns)
+}
+
+#[cfg(windows)]
+fn enable_ansi_support() -> bool {
+    use windows_sys::Win32::System::Console::{
+        ENABLE_VIRTUAL_TERMINAL_PROCESSING, GetConsoleMode, GetStdHandle, STD_OUTPUT_HANDLE,
+        SetConsoleMode,
+    };
+
+    // SAFETY: plain console API calls on our own stdout handle.
+    unsafe {
+        let handle = GetStdHandle(STD_OUTPUT_HANDLE);
+        let mut mode = 0;
+        if GetConsoleMode(handle, &mut mode) == 0 {
+            return false;
+        }
+        mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+            || SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+    }
+}
+
+#[cfg(not(windows))]
+fn enable_ansi_support() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_lines_are_cut_and_padded_to_one_column_less_than_the_width() {
+        let line = "Splitting: 3 of 10 chunks, 1.2 GiB/s";
+        assert_eq!(line.len(), 36);
+        assert_eq!(fit_status(line, 80), format!("{:<79}", line));
+        assert_eq!(fit_status(line, 37), line);
+        assert_eq!(fit_status(line, 36), &line[..35]);
+        assert_eq!(fit_status(line, 10), "Splitting");
+        // Narrower than anything fits still draws a column, so the line moves on
+        assert_eq!(fit_status(line, 1), "S");
+        assert_eq!(fit_status(line, 0), "S");
+        assert_eq!(fit_status("", 5), "    ");
+    }
+}
@@ -0,0 +1,332 @@
+// Checksum files other tools leave next to the pieces they split, often in place of an
+// info.json: what md5sum, sha1sum, sha256sum and sha512sum print, `HASH  NAME`, or
+// `HASH *NAME` for a file read in binary mode (the same thing here), and the BSD form
+// `SHA256 (NAME) = HASH` that BSD md5 and `sha256sum --tag` print. Either may start with
+// a `\`, meaning backslashes and newlines in the name are escaped. Files are found by
+// their names, such as `MD5SUMS`, `SHA256SUMS`, `checksums.md5` or `file.iso.sha1`. A
+// line's algorithm is the one it names, else the one the file's name does, else the one
+// whose hashes are as long as its. Entries are matched to files in the directory by
+// name, dropping a leading `./` or any directory in front, as sums are often taken one
+// level up.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use log::{debug, warn};
+// md-5 and sha1 are of digest 0.11, sha2 of the 0.10 that hmac is built on, so each
+// brings its own Digest
+use md5::{Digest as _, Md5};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::cancel::CancelToken;
+use crate::error::{PathContext, Result};
+use crate::event::Counting;
+use crate::pipeline::{Io, copy_overlapped};
+
+// Anything larger is no checksum file of a chunk set
+const MAX_SUMS_SIZE: u64 = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SumAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl SumAlgorithm {
+    const ALL: [SumAlgorithm; 4] = [
+        SumAlgorithm::Md5,
+        SumAlgorithm::Sha1,
+        SumAlgorithm::Sha256,
+        SumAlgorithm::Sha512,
+    ];
+
+    // As in `md5sum`, and in lowercase the tag of the BSD form.
+    fn name(self) -> &'static str {
+        match self {
+            SumAlgorithm::Md5 => "md5",
+            SumAlgorithm::Sha1 => "sha1",
+            SumAlgorithm::Sha256 => "sha256",
+            SumAlgorithm::Sha512 => "sha512",
+        }
+    }
+
+    // Hex digits in a hash.
+    fn len(self) -> usize {
+        match self {
+            SumAlgorithm::Md5 => 32,
+            SumAlgorithm::Sha1 => 40,
+            SumAlgorithm::Sha256 => 64,
+            SumAlgorithm::Sha512 => 128,
+        }
+    }
+
+    // Its place in `ALL`, weakest first.
+    fn strength(self) -> usize {
+        SumAlgorithm::ALL
+            .iter()
+            .position(|&algorithm| algorithm == self)
+            .unwrap_or(0)
+    }
+
+    fn from_tag(tag: &str) -> Option<SumAlgorithm> {
+        let tag = tag.to_ascii_lowercase().replace('-', "");
+        SumAlgorithm::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.name() == tag)
+    }
+}
+
+// One line of a checksum file.
+#[derive(Clone, Debug)]
+pub(crate) struct SumEntry {
+    pub algorithm: SumAlgorithm,
+    // Lowercase hex
+    pub hash: String,
+    // The checksum file it came from
+    pub source: String,
+}
+
+// The entries of every checksum file in a directory, by the name of the file each is
+// for there.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Sums {
+    pub files: Vec<String>,
+    pub entries: BTreeMap<String, SumEntry>,
+}
+
+// Whether `name` is that of a checksum file, and the algorithm it names if any.
+pub(crate) fn sums_name(name: &str) -> Option<Option<SumAlgorithm>> {
+    let lower = name.to_ascii_lowercase();
+    let stem = lower.strip_suffix(".txt").unwrap_or(&lower);
+    if matches!(stem, "checksums" | "checksum" | "sums") {
+        return Some(None);
+    }
+    SumAlgorithm::ALL.into_iter().find_map(|algorithm| {
+        let tag = algorithm.name();
+        let named = stem == format!("{}sums", tag)
+            || stem == format!("{}sum", tag)
+            || stem.ends_with(&format!(".{}", tag))
+            || stem.ends_with(&format!(".{}sum", tag))
+            || stem.ends_with(&format!(".{}sums", tag));
+        named.then_some(Some(algorithm))
+    })
+}
+
+// The checksum files in `directory`, read; None when there are none. Lines that aren't
+// checksums are passed over with a warning, as sums files pick up other notes too.
+pub(crate) fn load(directory: &Path) -> Result<Option<Sums>> {
+    let mut sums = Sums::default();
+    let mut names = Vec::new();
+    for entry in fs::read_dir(directory).at(directory)? {
+        let entry = entry.at(directory)?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if let Some(algorithm) = sums_name(&name)
+            && entry.file_type().at(&entry.path())?.is_file()
+            && entry.metadata().at(&entry.path())?.len() <= MAX_SUMS_SIZE
+        {
+            names.push((name, algorithm));
+        }
+    }
+    names.sort_by(|a, b| a.0.cmp(&b.0));
+    for (name, named) in names {
+        let path = directory.join(&name);
+        let data = fs::read(&path).at(&path)?;
+        let text = String::from_utf8_lossy(&data);
+        let mut read = 0;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((algorithm, file, hash)) = parse_line(line, named) else {
+                warn!("{}:{}: not a checksum line", path.display(), number + 1);
+                continue;
+            };
+            let Some(file) = local_name(&file, directory) else {
+                debug!("{}: {} is not in the directory", path.display(), file);
+                continue;
+            };
+            read += 1;
+            let entry = SumEntry {
+                algorithm,
+                hash,
+                source: name.clone(),
+            };
+            // A file listed twice is checked by the stronger algorithm, or against the
+            // first entry when both use the same one
+            if let Some(earlier) = sums.entries.get(&file) {
+                if earlier.algorithm == entry.algorithm && earlier.hash != entry.hash {
+                    warn!(
+                        "{} has different checksums in {} and {}; checking the first",
+                        file, earlier.source, entry.source
+                    );
+                }
+                if earlier.algorithm.strength() >= entry.algorithm.strength() {
+                    continue;
+                }
+            }
+            sums.entries.insert(file, entry);
+        }
+        debug!("{}: {} checksums", path.display(), read);
+        sums.files.push(name);
+    }
+    Ok((!sums.files.is_empty()).then_some(sums))
+}
+
+// Whether the file at `path` has the hash `entry` gives it. `copied` hears about each
+// buffer read; `cancel` stops it between buffers.
+pub(crate) fn check(
+    path: &Path,
+    entry: &SumEntry,
+    copied: &mut dyn FnMut(u64),
+    cancel: &CancelToken,
+) -> Result<bool> {
+    let mut file = fs::File::open(path).at(path)?;
+    let mut sink = Counting {
+        inner: Hashing(SumHasher::new(entry.algorithm)),
+        copied,
+        cancel,
+    };
+    copy_overlapped(&Io::default(), &mut file, &mut sink, None).at(path)?;
+    let hash = sink.inner.0.finish();
+    if hash != entry.hash {
+        debug!(
+            "{}: {} {} where {} has {}",
+            path.display(),
+            entry.algorithm.name(),
+            hash,
+            entry.source,
+            entry.hash
+        );
+    }
+    Ok(hash == entry.hash)
+}
+
+// The algorithm, file name and lowercase hash of a checksum line.
+fn parse_line(line: &str, named: Option<SumAlgorithm>) -> Option<(SumAlgorithm, String, String)> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+    let bsd = bsd_line(line)
+        .and_then(|(tag, file, hash)| Some((SumAlgorithm::from_tag(tag)?, file, hash)));
+    let (tag, file, hash) = match bsd {
+        Some((tag, file, hash)) => (Some(tag), file, hash),
+        None => {
+            let (hash, rest) = line.split_once(' ')?;
+            let file = rest.strip_prefix([' ', '*'])?;
+            (None, file, hash)
+        }
+    };
+    if file.is_empty() || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let algorithm = match tag.or(named) {
+        Some(algorithm) => algorithm,
+        None => SumAlgorithm::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.len() == hash.len())?,
+    };
+    if hash.len() != algorithm.len() {
+        return None;
+    }
+    let file = match escaped {
+        true => unescape(file)?,
+        false => file.to_string(),
+    };
+    Some((algorithm, file, hash.to_ascii_lowercase()))
+}
+
+// `TAG (FILE) = HASH`, split up.
+fn bsd_line(line: &str) -> Option<(&str, &str, &str)> {
+    let (tag, rest) = line.split_once(" (")?;
+    let (file, hash) = rest.rsplit_once(") = ")?;
+    Some((tag, file, hash))
+}
+
+fn unescape(name: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            'n' => unescaped.push('\n'),
+            'r' => unescaped.push('\r'),
+            _ => return None,
+        }
+    }
+    Some(unescaped)
+}
+
+// The name in `directory` of the file an entry is for: as listed if it is there, else
+// its last component, for sums taken from the directory above.
+fn local_name(listed: &str, directory: &Path) -> Option<String> {
+    let mut name = listed;
+    while let Some(rest) = name.strip_prefix("./") {
+        name = rest;
+    }
+    if !name.contains(['/', '\\']) {
+        return Some(name.to_string());
+    }
+    let last = name.rsplit(['/', '\\']).next()?;
+    (!last.is_empty() && directory.join(last).is_file()).then(|| last.to_string())
+}
+
+enum SumHasher {
+    Md5(Md5),
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl SumHasher {
+    fn new(algorithm: SumAlgorithm) -> SumHasher {
+        match algorithm {
+            SumAlgorithm::Md5 => SumHasher::Md5(Md5::new()),
+            SumAlgorithm::Sha1 => SumHasher::Sha1(Sha1::new()),
+            SumAlgorithm::Sha256 => SumHasher::Sha256(Sha256::new()),
+            SumAlgorithm::Sha512 => SumHasher::Sha512(Sha512::new()),
+        }
+    }
+
+    fn finish(self) -> String {
+        let digest = match self {
+            SumHasher::Md5(hasher) => hasher.finalize().to_vec(),
+            SumHasher::Sha1(hasher) => hasher.finalize().to_vec(),
+            SumHasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            SumHasher::Sha512(hasher) => hasher.finalize().to_vec(),
+        };
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+// Feeds whatever is written to it to the hasher, so the pipeline's writer thread does
+// the hashing for algorithms `ChunkHasher` doesn't have.
+struct Hashing(SumHasher);
+
+impl Write for Hashing {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            SumHasher::Md5(hasher) => hasher.update(buf),
+            SumHasher::Sha1(hasher) => hasher.update(buf),
+            SumHasher::Sha256(hasher) => hasher.update(buf),
+            SumHasher::Sha512(hasher) => hasher.update(buf),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
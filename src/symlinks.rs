@@ -0,0 +1,103 @@
+// What is done with symbolic links: a file to split that is one, and ones named like
+// chunks in a directory being scanned for them. Links in a directory shared with others
+// could point anywhere, such as at a file of someone else's, so by default only the
+// file asked to be split is followed; `--follow-symlinks` follows both, and
+// `--no-follow-symlinks` neither. Each split and reconstruction goes by its own
+// `symlinks` option.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{PathContext, Result, SplitterError};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkPolicy {
+    // An input that is a link is split as the file it points to, and the set named for
+    // that; links among chunks are skipped
+    #[default]
+    Resolve,
+    Follow,
+    // An input that is a link is refused, and links among chunks are skipped
+    NoFollow,
+}
+
+// Whether the chunk at `path`, found by scanning a directory, is taken under `policy`,
+// `kind` being what the directory entry itself is. A link followed to nothing is an
+// error.
+pub(crate) fn takes_chunk(path: &Path, kind: fs::FileType, policy: SymlinkPolicy) -> Result<bool> {
+    if !kind.is_symlink() {
+        return Ok(true);
+    }
+    if policy != SymlinkPolicy::Follow {
+        return Ok(false);
+    }
+    match fs::metadata(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(SplitterError::BrokenSymlink {
+            path: path.to_path_buf(),
+            target: fs::read_link(path).unwrap_or_default(),
+        }),
+        _ => Ok(true),
+    }
+}
+
+// Check a file about to be split, when it is a symbolic link: one whose target doesn't
+// exist, or any at all under a `policy` of `NoFollow`, is an error. The target the link
+// names is what the error gives, as the link says it.
+pub(crate) fn check_input(path: &Path, policy: SymlinkPolicy) -> Result<()> {
+    let Ok(target) = fs::read_link(path) else {
+        return Ok(());
+    };
+    if let Err(e) = fs::metadata(path) {
+        return match e.kind() {
+            io::ErrorKind::NotFound => Err(SplitterError::BrokenSymlink {
+                path: path.to_path_buf(),
+                target,
+            }),
+            _ => Err(e).at(path),
+        };
+    }
+    if policy == SymlinkPolicy::NoFollow {
+        return Err(SplitterError::Symlink {
+            path: path.to_path_buf(),
+            target,
+        });
+    }
+    info!(
+        "{} is a symbolic link to {}; splitting that",
+        path.display(),
+        target.display()
+    );
+    Ok(())
+}
+
+// The name of the file `path` leads to, following any symbolic links on the way, which
+// is the name a split of it is recorded under.
+pub(crate) fn resolved_name(path: &Path) -> OsString {
+    let own = path.file_name().unwrap_or_default().to_os_string();
+    if !links_to_file(path) {
+        return own;
+    }
+    fs::canonicalize(path)
+        .ok()
+        .and_then(|resolved| resolved.file_name().map(|name| name.to_os_string()))
+        .unwrap_or(own)
+}
+
+// The name of `path` itself when it is a symbolic link to a file, recorded along with
+// the name of the file.
+pub(crate) fn link_name(path: &Path) -> Option<String> {
+    let name = path.file_name()?;
+    links_to_file(path).then(|| name.to_string_lossy().into_owned())
+}
+
+// Links to pipes and devices, such as /dev/stdin, keep their own name, that of what
+// they lead to saying nothing.
+fn links_to_file(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_symlink()) && path.is_file()
+}
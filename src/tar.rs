@@ -0,0 +1,248 @@
+// Reading the index of a tar archive, for `ArchiveStore`: the 512-byte headers of the
+// POSIX ustar format and of GNU tar, with GNU long names, pax extended headers for
+// long paths and large sizes, and the base-256 sizes GNU writes for files past 8 GiB.
+// Headers are read one after another, seeking past each file's data, so even a large
+// archive is listed without reading it through. Only plain files are listed; links,
+// directories and whatever else a tar can hold are passed over. Headers are also
+// written here, for `pack`, in the same format.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::archive::{Member, Method, invalid};
+
+const BLOCK: u64 = 512;
+// Longest GNU long name or pax header taken; anything longer is no tar of ours
+const MAX_EXTENDED: u64 = 1 << 20;
+// Largest size the octal field holds
+const MAX_OCTAL: u64 = 0o777_7777_7777;
+// Two empty blocks end an archive
+pub(crate) const END: [u8; 2 * BLOCK as usize] = [0; 2 * BLOCK as usize];
+
+// Whether `start`, the first block of a file, is a tar header.
+pub(crate) fn is_tar(start: &[u8]) -> bool {
+    start.len() >= BLOCK as usize && checksum_matches(&start[..BLOCK as usize])
+}
+
+// Every plain file in the archive, by name, in the order they are stored.
+pub(crate) fn read_members(file: &mut File) -> io::Result<Vec<(String, Member)>> {
+    let mut listed = Vec::new();
+    let mut offset = 0;
+    // What a GNU long name or pax header said about the header after it
+    let mut long_name = None;
+    let mut pax_path = None;
+    let mut pax_size = None;
+    loop {
+        let mut header = [0; BLOCK as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        match file.read_exact(&mut header) {
+            Ok(()) => {}
+            // Archives cut short of their end blocks are read as far as they go
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        if !checksum_matches(&header) {
+            return Err(invalid("a tar header is damaged"));
+        }
+        let data = offset + BLOCK;
+        let mut len =
+            number(&header[124..136]).ok_or_else(|| invalid("a tar header is damaged"))?;
+        match header[156] {
+            b'L' => long_name = Some(read_text(file, data, len)?),
+            b'x' => {
+                for (key, value) in pax_records(&read_text(file, data, len)?) {
+                    match key {
+                        "path" => pax_path = Some(value.to_string()),
+                        "size" => pax_size = value.parse().ok(),
+                        _ => {}
+                    }
+                }
+            }
+            // Global pax headers and GNU long link targets say nothing this needs
+            b'g' | b'K' => {}
+            b'0' | 0 | b'7' => {
+                len = pax_size.take().unwrap_or(len);
+                let name = pax_path.take().or(long_name.take());
+                let name = name.unwrap_or_else(|| header_name(&header));
+                listed.push((
+                    name,
+                    Member {
+                        offset: data,
+                        size: len,
+                        stored_size: len,
+                        crc: None,
+                        method: Method::Stored,
+                    },
+                ));
+            }
+            _ => {
+                long_name = None;
+                pax_path = None;
+                pax_size = None;
+            }
+        }
+        offset = data + len.div_ceil(BLOCK) * BLOCK;
+    }
+    Ok(listed)
+}
+
+// The header for a plain file `name` holding `size` bytes, the same whatever the file's
+// own metadata: mode 0644, owner and group 0, dated 1970. A name too long for the header,
+// or a size too large for its octal field, also goes in a pax header in front, with the
+// size written in base 256 as well for tars that read those instead.
+pub(crate) fn file_header(name: &str, size: u64) -> Vec<u8> {
+    let mut records = String::new();
+    if name.len() > 100 {
+        records.push_str(&pax_record("path", name));
+    }
+    if size > MAX_OCTAL {
+        records.push_str(&pax_record("size", &size.to_string()));
+    }
+    let mut header = Vec::new();
+    if !records.is_empty() {
+        header.extend(ustar_header("././@PaxHeader", records.len() as u64, b'x'));
+        header.extend(records.as_bytes());
+        header.resize(header.len() + padding(records.len() as u64), 0);
+    }
+    let mut short = name.len().min(100);
+    while !name.is_char_boundary(short) {
+        short -= 1;
+    }
+    header.extend(ustar_header(&name[..short], size, b'0'));
+    header
+}
+
+// Zeros after `size` bytes of data to fill its last block.
+pub(crate) fn padding(size: u64) -> usize {
+    (size.next_multiple_of(BLOCK) - size) as usize
+}
+
+fn ustar_header(name: &str, size: u64, kind: u8) -> [u8; BLOCK as usize] {
+    let mut header = [0; BLOCK as usize];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    if size > MAX_OCTAL {
+        header[124] = 0x80;
+        header[128..136].copy_from_slice(&size.to_be_bytes());
+    } else {
+        header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+    }
+    header[136..148].copy_from_slice(b"00000000000\0");
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[329..337].copy_from_slice(b"0000000\0");
+    header[337..345].copy_from_slice(b"0000000\0");
+    header[148..156].fill(b' ');
+    let sum: u64 = header.iter().map(|&b| u64::from(b)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    header
+}
+
+// A `LENGTH KEY=VALUE\n` record, whose length counts its own digits.
+fn pax_record(key: &str, value: &str) -> String {
+    let body = key.len() + value.len() + 3;
+    let mut length = body + body.to_string().len();
+    if length.to_string().len() > body.to_string().len() {
+        length += 1;
+    }
+    format!("{} {}={}\n", length, key, value)
+}
+
+// The checksum is the sum of the header's bytes with its own field taken as spaces,
+// unsigned as POSIX has it or signed as some old tars wrote it.
+fn checksum_matches(header: &[u8]) -> bool {
+    let Some(recorded) = number(&header[148..156]) else {
+        return false;
+    };
+    let field = 148..156;
+    let byte = |(at, &b): (usize, &u8)| match field.contains(&at) {
+        true => b' ',
+        false => b,
+    };
+    let unsigned: u64 = header
+        .iter()
+        .enumerate()
+        .map(|entry| u64::from(byte(entry)))
+        .sum();
+    let signed: i64 = header
+        .iter()
+        .enumerate()
+        .map(|entry| i64::from(byte(entry) as i8))
+        .sum();
+    recorded == unsigned || Ok(recorded) == u64::try_from(signed)
+}
+
+// An octal field, padded with spaces or NULs, or from GNU tar a base-256 one, marked by
+// its high bit, for values octal can't hold.
+fn number(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+        if field[0] != 0x80 {
+            // Negative, or too large for 64 bits
+            return None;
+        }
+        let mut value: u64 = 0;
+        for &b in &field[1..] {
+            value = value.checked_mul(256)? | u64::from(b);
+        }
+        return Some(value);
+    }
+    let text = std::str::from_utf8(field).ok()?;
+    let text = text.trim_matches(|c| c == ' ' || c == '\0');
+    match text.is_empty() {
+        true => Some(0),
+        false => u64::from_str_radix(text, 8).ok(),
+    }
+}
+
+// The name in the header itself: with POSIX ustar, the prefix field goes before it.
+fn header_name(header: &[u8]) -> String {
+    let name = text_field(&header[..100]);
+    let prefix = text_field(&header[345..500]);
+    match &header[257..263] == b"ustar\0" && !prefix.is_empty() {
+        true => format!("{}/{}", prefix, name),
+        false => name,
+    }
+}
+
+fn text_field(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn read_text(file: &mut File, offset: u64, len: u64) -> io::Result<String> {
+    if len > MAX_EXTENDED {
+        return Err(invalid("a tar header is damaged"));
+    }
+    let mut data = vec![0; len as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut data)?;
+    Ok(text_field(&data))
+}
+
+// The `LENGTH KEY=VALUE\n` records of a pax header, where LENGTH counts the whole
+// record.
+fn pax_records(mut data: &str) -> Vec<(&str, &str)> {
+    let mut records = Vec::new();
+    while let Some((length, _)) = data.split_once(' ') {
+        let Some(record) = length
+            .parse::<usize>()
+            .ok()
+            .filter(|&total| total > length.len() + 1)
+            .and_then(|total| data.get(..total))
+        else {
+            break;
+        };
+        let body = record[length.len() + 1..].trim_end_matches('\n');
+        if let Some((key, value)) = body.split_once('=') {
+            records.push((key, value));
+        }
+        data = &data[record.len()..];
+    }
+    records
+}
@@ -0,0 +1,258 @@
+// Destination names built from the file being split, for `split --dest`, the interactive
+// split's "Save chunks to" and `watch --template`: a path such as
+// /archive/{year}/{month}/{name}.{ext}.split with variables in braces, put in before
+// anything looks at whether the destination is free. A template is parsed when the
+// arguments are, so a variable misspelt fails then, not after a long wait for the first
+// file; `{{` and `}}` stand for braces themselves. A path with no braces is taken as it
+// is, whatever it holds.
+//
+// The date is the time of the split, in UTC like every other time this tool prints. The
+// short hash is of the file's first MiB only, so it costs the same however large the
+// file, and is only read when the template has one; a pipe, which can't be read twice,
+// has neither a hash nor a size.
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::builder::TypedValueParser;
+use clap::error::ErrorKind;
+use reconstruct_large_file::is_stream;
+use reconstruct_large_file::manifest::HashAlgorithm;
+
+use crate::logging::civil_date;
+
+// How much of the start of the file `{hash}` is of
+const HASHED_LEN: u64 = 1 << 20;
+
+// The variables, in the order `--help` and the errors list them
+const VARIABLES: &[&str] = &[
+    "filename", "name", "stem", "ext", "parent", "year", "month", "day", "hour", "minute", "date",
+    "size", "hash",
+];
+
+// Upper bounds of the `{size}` buckets, each ten times the last within a unit
+const SIZE_UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB", "PiB"];
+
+#[derive(Clone, Debug)]
+enum Part {
+    Text(String),
+    Variable(&'static str),
+}
+
+#[derive(Clone, Debug)]
+pub struct Template {
+    // As given, which a template without variables stands for as it is
+    path: PathBuf,
+    // Empty when there are no variables
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(text: &OsStr) -> Result<Template, String> {
+        let path = PathBuf::from(text);
+        let Some(text) = text.to_str() else {
+            // Not UTF-8, which no variable is written in, so nothing to put in
+            return Ok(Template {
+                path,
+                parts: Vec::new(),
+            });
+        };
+        if !text.contains(['{', '}']) {
+            return Ok(Template {
+                path,
+                parts: Vec::new(),
+            });
+        }
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            if let Some(after) = rest.strip_prefix("{{") {
+                literal.push('{');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("}}") {
+                literal.push('}');
+                rest = after;
+            } else if c == '}' {
+                return Err("a } with no { before it; write }} for a brace itself".to_string());
+            } else if c == '{' {
+                let Some((name, after)) = rest[1..].split_once('}') else {
+                    return Err("a { with no } closing it; write {{ for a brace itself".to_string());
+                };
+                let Some(&variable) = VARIABLES.iter().find(|known| **known == name) else {
+                    return Err(format!(
+                        "{{{}}} isn't a variable; the variables are {}",
+                        name,
+                        names()
+                    ));
+                };
+                if !literal.is_empty() {
+                    parts.push(Part::Text(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Variable(variable));
+                rest = after;
+            } else {
+                literal.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        // Only braces written twice, which still have to come out single
+        if !parts.iter().any(|part| matches!(part, Part::Variable(_))) {
+            let path = PathBuf::from(parts.iter().map(text_of).collect::<String>());
+            return Ok(Template {
+                path,
+                parts: Vec::new(),
+            });
+        }
+        Ok(Template { path, parts })
+    }
+
+    // Whether there is anything to put in.
+    pub fn has_variables(&self) -> bool {
+        !self.parts.is_empty()
+    }
+
+    pub fn is_absolute(&self) -> bool {
+        self.path.is_absolute()
+    }
+
+    // The path for splitting the file at `input`.
+    pub fn expand(&self, input: &Path) -> io::Result<PathBuf> {
+        if !self.has_variables() {
+            return Ok(self.path.clone());
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let (year, month, day) = civil_date(now / 86_400);
+        let (hour, minute) = (now % 86_400 / 3_600, now % 3_600 / 60);
+        let filename = input
+            .file_name()
+            .map_or("output".into(), |name| name.to_string_lossy());
+        let (name, ext) = match filename.rsplit_once('.') {
+            Some((name, ext)) if !name.is_empty() => (name, ext),
+            _ => (&*filename, ""),
+        };
+        let stem = match filename.split_once('.') {
+            Some((stem, _)) if !stem.is_empty() => stem,
+            _ => name,
+        };
+        let mut expanded = String::new();
+        for part in &self.parts {
+            let value = match part {
+                Part::Text(text) => text.clone(),
+                Part::Variable("filename") => filename.to_string(),
+                Part::Variable("name") => name.to_string(),
+                Part::Variable("stem") => stem.to_string(),
+                Part::Variable("ext") => ext.to_string(),
+                Part::Variable("parent") => parent_name(input),
+                Part::Variable("year") => format!("{:04}", year),
+                Part::Variable("month") => format!("{:02}", month),
+                Part::Variable("day") => format!("{:02}", day),
+                Part::Variable("hour") => format!("{:02}", hour),
+                Part::Variable("minute") => format!("{:02}", minute),
+                Part::Variable("date") => format!("{:04}-{:02}-{:02}", year, month, day),
+                Part::Variable("size") => size_bucket(readable(input, "size")?.metadata()?.len()),
+                Part::Variable("hash") => short_hash(readable(input, "hash")?)?,
+                Part::Variable(other) => unreachable!("unknown template variable {}", other),
+            };
+            expanded.push_str(&value);
+        }
+        Ok(PathBuf::from(expanded))
+    }
+}
+
+// For clap, so that a template that can't be parsed is an error in the arguments
+// rather than a failure once they are taken.
+#[derive(Clone)]
+pub struct TemplateParser;
+
+impl TypedValueParser for TemplateParser {
+    type Value = Template;
+
+    fn parse_ref(
+        &self,
+        command: &clap::Command,
+        arg: Option<&clap::Arg>,
+        value: &OsStr,
+    ) -> Result<Template, clap::Error> {
+        Template::parse(value).map_err(|reason| {
+            let arg = arg.map_or("--dest".to_string(), ToString::to_string);
+            let message = format!(
+                "invalid value '{}' for '{}': {}\n",
+                value.to_string_lossy(),
+                arg,
+                reason
+            );
+            clap::Error::raw(ErrorKind::ValueValidation, message).with_cmd(command)
+        })
+    }
+}
+
+fn text_of(part: &Part) -> &str {
+    match part {
+        Part::Text(text) => text,
+        Part::Variable(name) => name,
+    }
+}
+
+fn names() -> String {
+    let names: Vec<String> = VARIABLES
+        .iter()
+        .map(|name| format!("{{{}}}", name))
+        .collect();
+    names.join(", ")
+}
+
+fn parent_name(input: &Path) -> String {
+    let absolute = std::path::absolute(input).unwrap_or_else(|_| input.to_path_buf());
+    absolute
+        .parent()
+        .and_then(Path::file_name)
+        .map_or("root".to_string(), |name| {
+            name.to_string_lossy().into_owned()
+        })
+}
+
+// The file at `input`, opened to be looked at for `variable`, which a pipe can't be.
+fn readable(input: &Path, variable: &str) -> io::Result<File> {
+    if is_stream(input) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{{{}}} can't be worked out for a pipe, which is only read once",
+                variable
+            ),
+        ));
+    }
+    File::open(input)
+}
+
+fn size_bucket(size: u64) -> String {
+    let mut bound = 1u64 << 10;
+    for unit in SIZE_UNITS {
+        for times in [1, 10, 100] {
+            if size < bound.saturating_mul(times) {
+                return format!("{}{}", times, unit);
+            }
+        }
+        bound = bound.saturating_mul(1 << 10);
+    }
+    format!("over100{}", SIZE_UNITS[SIZE_UNITS.len() - 1])
+}
+
+fn short_hash(file: File) -> io::Result<String> {
+    let mut start = Vec::new();
+    file.take(HASHED_LEN).read_to_end(&mut start)?;
+    let mut hasher = HashAlgorithm::Sha256.hasher();
+    hasher.update(&start);
+    let digest = hasher.finish();
+    Ok(digest[..8].to_string())
+}
@@ -0,0 +1,192 @@
+// What the chunks' modification times say about what happened to a set, for
+// `verify --timestamps` and `doctor --timestamps`. A split with `record_times` notes in
+// info.json when it started and when it last wrote each chunk. A chunk modified since
+// was most likely rewritten by something, and one older than the split itself can't be
+// one it wrote, as a chunk left from another set isn't. A copy that didn't keep the
+// times gives every chunk a new one, and then, as without recorded times, only a chunk
+// modified long after most of the others stands out.
+//
+// None of this proves anything, as copying tools set times as they please, so what is
+// found is a hint, reported apart from what `verify` finds and never failing anything.
+// Times are compared to within a tolerance, for filesystems that keep them coarsely, as
+// FAT does to two seconds; one that keeps none worth comparing is simply not asked.
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::archive::is_archive;
+use crate::error::{Result, SplitterError};
+use crate::lock::describe_age;
+use crate::manifest::Manifest;
+use crate::store::{ChunkStore, LocalDirStore};
+use crate::{is_s3_url, is_sftp_url};
+
+pub const DEFAULT_TIMESTAMP_TOLERANCE: Duration = Duration::from_secs(2);
+
+// How much later than most of the others a chunk has to have been modified to stand out
+// when there are no recorded times to go by
+const OUTLIER_GAP: u64 = 60 * 60;
+
+// What is odd about a chunk's time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampKind {
+    // Modified after the split wrote it
+    Later,
+    // Modified before the split wrote it, though not before the split started
+    Earlier,
+    // Modified before the split started
+    BeforeSplit,
+    // Modified long after most of the other chunks
+    Outlier,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimestampHint {
+    pub name: String,
+    pub kind: TimestampKind,
+    // By how much, in seconds
+    pub seconds: u64,
+}
+
+impl TimestampHint {
+    // As in "chunk117 was modified 3 days after the split wrote it".
+    pub fn describe(&self) -> String {
+        let by = describe_age(Duration::from_secs(self.seconds));
+        let what = match self.kind {
+            TimestampKind::Later => "after the split wrote it",
+            TimestampKind::Earlier => "before the split wrote it",
+            TimestampKind::BeforeSplit => "before the split started",
+            TimestampKind::Outlier => "after most of the other chunks",
+        };
+        format!("{} was modified {} {}", self.name, by, what)
+    }
+}
+
+// Outcome of `check_timestamps`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimestampReport {
+    // Chunks whose times were looked at
+    pub checked: usize,
+    // Whether the split recorded times to compare them with
+    pub recorded: bool,
+    // Whether most chunks still have the times recorded for them, as after a copy that
+    // keeps times; when not, only outliers and chunks older than the split are looked for
+    pub kept: bool,
+    pub hints: Vec<TimestampHint>,
+}
+
+// Compare the modification times of the chunks in `directory` with those the split
+// recorded, and with each other, taking times within `tolerance` of each other to be
+// the same. Chunks that are missing are left to `verify` to report. A manifest changed
+// after it was sealed fails it unless `accept_modified`.
+pub fn check_timestamps(
+    directory: &Path,
+    tolerance: Duration,
+    accept_modified: bool,
+) -> Result<TimestampReport> {
+    if is_archive(directory) || is_s3_url(directory) || is_sftp_url(directory) {
+        return Err(SplitterError::InvalidOption {
+            field: "directory",
+            reason: "must be a local directory for its chunks' times to be checked",
+        });
+    }
+    let store = LocalDirStore::open(directory, accept_modified)?;
+    let manifest = store.read_info()?;
+    // Each chunk file by name, with the time recorded for it and the one it has
+    let mut times: Vec<(String, Option<u64>, u64)> = Vec::new();
+    match manifest
+        .as_ref()
+        .filter(|manifest| !manifest.chunks.is_empty())
+    {
+        Some(manifest) => {
+            for entry in manifest.chunks.iter().filter(|e| e.same_as.is_none()) {
+                if let Some(modified) = modified(&directory.join(&entry.name)) {
+                    times.push((entry.name.clone(), entry.mtime, modified));
+                }
+            }
+        }
+        None => {
+            for index in store.list_chunks()? {
+                let path = store.chunk_path(index);
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                if let Some(modified) = modified(&path) {
+                    times.push((name.into_owned(), None, modified));
+                }
+            }
+        }
+    }
+    let created = manifest.as_ref().and_then(|manifest| manifest.created);
+    let tolerance = tolerance.as_secs_f64().ceil() as u64;
+
+    let recorded: Vec<(u64, u64)> = times
+        .iter()
+        .filter_map(|&(_, recorded, modified)| Some((recorded?, modified)))
+        .collect();
+    let unchanged = recorded
+        .iter()
+        .filter(|&&(recorded, modified)| recorded.abs_diff(modified) <= tolerance)
+        .count();
+    let kept = !recorded.is_empty() && unchanged * 2 > recorded.len();
+    let mut sorted: Vec<u64> = times.iter().map(|&(_, _, modified)| modified).collect();
+    sorted.sort_unstable();
+    let median = sorted.get(sorted.len() / 2).copied();
+
+    let mut hints = Vec::new();
+    for (name, recorded, modified) in &times {
+        let (recorded, modified) = (*recorded, *modified);
+        let hint = |kind, seconds| TimestampHint {
+            name: name.clone(),
+            kind,
+            seconds,
+        };
+        if let Some(created) = created
+            && modified + tolerance < created
+        {
+            hints.push(hint(TimestampKind::BeforeSplit, created - modified));
+        } else if let Some(recorded) = recorded
+            && kept
+        {
+            if modified > recorded + tolerance {
+                hints.push(hint(TimestampKind::Later, modified - recorded));
+            } else if modified + tolerance < recorded {
+                hints.push(hint(TimestampKind::Earlier, recorded - modified));
+            }
+        } else if let Some(median) = median
+            && !kept
+            && modified > median + OUTLIER_GAP + tolerance
+        {
+            hints.push(hint(TimestampKind::Outlier, modified - median));
+        }
+    }
+    Ok(TimestampReport {
+        checked: times.len(),
+        recorded: !recorded.is_empty(),
+        kept,
+        hints,
+    })
+}
+
+// Note in `manifest` when the split started and when each chunk in `directory` was last
+// written, for `check_timestamps` to compare with later.
+pub(crate) fn record(directory: &Path, manifest: &mut Manifest, started: SystemTime) {
+    manifest.created = Some(seconds(started));
+    for entry in manifest.chunks.iter_mut().filter(|e| e.same_as.is_none()) {
+        entry.mtime = modified(&directory.join(&entry.name));
+    }
+}
+
+fn modified(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(seconds(modified))
+}
+
+// Since the Unix epoch; none for a time before it.
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,232 @@
+// Where each chunk of a set stands in a transfer done by something else, such as a
+// script uploading the chunks one by one: pending, uploaded or failed, and since when.
+// The states are kept beside the chunks in transfer_state.json, never in info.json,
+// which describes the set and is sealed, and which a transfer has no business changing.
+// A chunk the file doesn't mention is pending, so a set with no file yet has all its
+// chunks still to go.
+//
+// Chunks are recorded by their index, which a split's post-chunk hook knows before
+// there is an info.json to name them by. Whoever records a state takes an exclusive
+// lock on the file for the whole of reading and rewriting it, so uploaders running
+// side by side, and a split's hooks on their threads, don't lose each other's marks;
+// readers take a shared one. The file is rewritten in place rather than replaced, as a
+// lock is on the file and a replacement would be a file no one else waits on.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::archive::is_archive;
+use crate::chunkset::ChunkSet;
+use crate::error::{PathContext, Result, SplitterError};
+
+pub const TRANSFER_STATE_NAME: &str = "transfer_state.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "snake_case")]
+pub enum TransferState {
+    // Still to go, or to go again
+    Pending,
+    Uploaded,
+    Failed,
+}
+
+impl TransferState {
+    pub fn name(self) -> &'static str {
+        match self {
+            TransferState::Pending => "pending",
+            TransferState::Uploaded => "uploaded",
+            TransferState::Failed => "failed",
+        }
+    }
+}
+
+// What transfer_state.json records of one chunk.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ChunkTransfer {
+    pub state: TransferState,
+    // When it was marked, in seconds since 1970
+    pub changed: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct StateFile {
+    // By chunk index
+    chunks: BTreeMap<usize, ChunkTransfer>,
+}
+
+// One chunk of a set and where it stands.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChunkStatus {
+    pub index: usize,
+    pub path: PathBuf,
+    pub state: TransferState,
+    // None for a chunk never marked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changed: Option<u64>,
+}
+
+// Where the chunks of a set stand, with counts of each state.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransferStatus {
+    pub directory: PathBuf,
+    pub pending: usize,
+    pub uploaded: usize,
+    pub failed: usize,
+    pub chunks: Vec<ChunkStatus>,
+}
+
+impl TransferStatus {
+    // The chunks in `state`, in order.
+    pub fn in_state(&self, state: TransferState) -> impl Iterator<Item = &ChunkStatus> {
+        self.chunks.iter().filter(move |chunk| chunk.state == state)
+    }
+}
+
+// Mark the chunks of the set in `directory` that `chunks` names, each by its index or
+// its file name, as in `state`; the indices marked come back. An info.json changed after
+// it was sealed is refused unless `accept_modified`.
+pub fn mark(
+    directory: &Path,
+    chunks: &[String],
+    state: TransferState,
+    accept_modified: bool,
+) -> Result<Vec<usize>> {
+    let set = open(directory, accept_modified)?;
+    let indices = chunks
+        .iter()
+        .map(|chunk| resolve(&set, directory, chunk))
+        .collect::<Result<Vec<usize>>>()?;
+    mark_indices(directory, &indices, state)?;
+    Ok(indices)
+}
+
+// Mark the chunks at `indices` without looking at the set, which a split still writing
+// it has no info.json for yet.
+pub(crate) fn mark_indices(
+    directory: &Path,
+    indices: &[usize],
+    state: TransferState,
+) -> Result<()> {
+    let path = directory.join(TRANSFER_STATE_NAME);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .at(&path)?;
+    file.lock().doing("locking", &path)?;
+    let mut states = read(&mut file, &path)?;
+    let changed = now();
+    for &index in indices {
+        states
+            .chunks
+            .insert(index, ChunkTransfer { state, changed });
+    }
+    let mut data = serde_json::to_vec_pretty(&states)
+        .map_err(io::Error::other)
+        .at(&path)?;
+    data.push(b'\n');
+    file.set_len(0).doing("writing", &path)?;
+    file.rewind().doing("writing", &path)?;
+    file.write_all(&data).doing("writing", &path)?;
+    file.sync_all().doing("writing", &path)
+}
+
+// Where every chunk of the set in `directory` stands, as far as an info.json changed
+// after it was sealed is gone by, which is only with `accept_modified`.
+pub fn transfer_status(directory: &Path, accept_modified: bool) -> Result<TransferStatus> {
+    let set = open(directory, accept_modified)?;
+    let path = directory.join(TRANSFER_STATE_NAME);
+    let states = match File::open(&path) {
+        Ok(mut file) => {
+            file.lock_shared().doing("locking", &path)?;
+            read(&mut file, &path)?
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => StateFile::default(),
+        Err(e) => return Err(e).at(&path),
+    };
+    let chunks: Vec<ChunkStatus> = set
+        .iter()
+        .map(|chunk| {
+            let recorded = states.chunks.get(&chunk.index);
+            ChunkStatus {
+                index: chunk.index,
+                path: chunk.path.clone(),
+                state: recorded.map_or(TransferState::Pending, |recorded| recorded.state),
+                changed: recorded.map(|recorded| recorded.changed),
+            }
+        })
+        .collect();
+    let count = |state| chunks.iter().filter(|chunk| chunk.state == state).count();
+    Ok(TransferStatus {
+        directory: directory.to_path_buf(),
+        pending: count(TransferState::Pending),
+        uploaded: count(TransferState::Uploaded),
+        failed: count(TransferState::Failed),
+        chunks,
+    })
+}
+
+// The set whose states are kept in `directory`, which must be a directory for there to
+// be somewhere to keep them.
+fn open(directory: &Path, accept_modified: bool) -> Result<ChunkSet> {
+    if is_archive(directory) {
+        return Err(SplitterError::InvalidOption {
+            field: "directory",
+            reason: "is an archive, which has nowhere to keep transfer states; extract it first",
+        });
+    }
+    ChunkSet::open(directory, accept_modified)
+}
+
+// The states in `file`, of which an empty one, just made, has none.
+fn read(file: &mut File, path: &Path) -> Result<StateFile> {
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).at(path)?;
+    if data.iter().all(u8::is_ascii_whitespace) {
+        return Ok(StateFile::default());
+    }
+    serde_json::from_slice(&data).map_err(|source| SplitterError::MetadataCorrupt {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+// The index of the chunk `given` names: its index, its file name, its name in the
+// directory, as in `00/chunk007` for a sharded set, or its path.
+fn resolve(set: &ChunkSet, directory: &Path, given: &str) -> Result<usize> {
+    let by_index = given
+        .parse::<usize>()
+        .ok()
+        .filter(|&index| index < set.len());
+    let found = by_index.or_else(|| {
+        set.iter()
+            .find(|chunk| {
+                chunk.path.file_name().is_some_and(|name| name == given)
+                    || chunk
+                        .path
+                        .strip_prefix(directory)
+                        .is_ok_and(|name| name == Path::new(given))
+                    || chunk.path == Path::new(given)
+            })
+            .map(|chunk| chunk.index)
+    });
+    found.ok_or(SplitterError::InvalidOption {
+        field: "chunk",
+        reason: "names no chunk of the set, by index or file name",
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
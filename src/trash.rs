@@ -0,0 +1,570 @@
+// Keeping what a reconstruction replaces: a file already at the output is moved to the
+// trash before the reconstruction writes there, rather than written over, and the move
+// is recorded in the journal for `undo-last` to put back. On a desktop that is the
+// desktop's own trash, through the trash crate, so the file is where its owner would
+// look for it. Without one, as on a server reached over ssh, or when the desktop's
+// trash won't take the file, it goes into .fsr-trash/ beside it instead: within its own
+// directory, so on the same file system and a rename however large the file. Should the
+// reconstruction fail, the file goes straight back. Only a regular file is moved: a
+// reconstruction into a symbolic link writes through it, as it always has, and a pipe
+// is written to, not replaced.
+//
+// `undo-last` goes by the journal: the latest file moved to the trash and not yet put
+// back. Whatever has since taken its place, as the reconstructed file has, is moved to
+// the trash in turn, so that can be undone as well. The journal records a move to the
+// desktop's trash as `trash:///` and the file's name; putting that back goes by the
+// trash's own record of where its files came from, which the trash crate can read on
+// Windows and freedesktop systems but not on macOS, where the Finder puts it back. The
+// desktop's trash is never emptied here; .fsr-trash/ is, by `empty-trash`, wholly or
+// only of what has been there longer than some age.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reconstruct_large_file::{
+    CancelToken, ChangeKind, FileChange, ProgressEvent, ReconstructOptions, ReconstructPlan,
+    ReconstructReport, SplitterError, TRASH_DIR, plan_reconstruct, reconstruct,
+};
+
+use crate::journal;
+use crate::logging::parse_utc_time;
+use crate::watch::unused;
+
+// What a file moved to the desktop's trash is recorded as having gone to, its name after
+const DESKTOP_TRASH: &str = "trash:///";
+
+// A file moved to the trash, and where from.
+pub struct Trashed {
+    pub original: PathBuf,
+    // In .fsr-trash/, or `DESKTOP_TRASH` and the file's name in the desktop's trash
+    pub trashed: PathBuf,
+}
+
+impl Trashed {
+    fn on_desktop(&self) -> bool {
+        on_desktop(&self.trashed)
+    }
+
+    // Where the file is now, to tell someone.
+    pub fn location(&self) -> String {
+        match self.on_desktop() {
+            true => "the trash".to_string(),
+            false => self.trashed.display().to_string(),
+        }
+    }
+}
+
+// `reconstruct`, with the file at the output moved to the trash first when `trash` is
+// set. The move is journaled once the reconstruction has succeeded, and is otherwise
+// undone; the second half is what was moved, if anything.
+pub fn reconstruct_keeping(
+    options: &ReconstructOptions,
+    trash: bool,
+    progress: &mut dyn FnMut(ProgressEvent),
+    cancel: &CancelToken,
+) -> (Result<ReconstructReport, SplitterError>, Option<Trashed>) {
+    // Left for the reconstruction itself to fail over when it can't be planned
+    let output = match trash {
+        true => plan_reconstruct(options, cancel)
+            .ok()
+            .filter(|plan| plan.overwrites)
+            .map(|plan| plan.output),
+        false => None,
+    };
+    let moved = match output.filter(|output| is_file(output)) {
+        Some(output) => match discard(&output) {
+            Ok(moved) => Some(moved),
+            Err(e) => return (Err(e), None),
+        },
+        None => None,
+    };
+    let result = reconstruct(options, progress, cancel);
+    match (&result, moved) {
+        (Ok(_), Some(moved)) => {
+            journal::trash(&moved.original, &moved.trashed);
+            (result, Some(moved))
+        }
+        (Err(_), Some(moved)) => {
+            if fs::symlink_metadata(&moved.original).is_err()
+                && let Err(e) = put_back(&moved.trashed, &moved.original)
+            {
+                eprintln!(
+                    "Warning: could not put {} back from {}: {}",
+                    moved.original.display(),
+                    moved.trashed.display(),
+                    e
+                );
+            }
+            (result, None)
+        }
+        (_, None) => (result, None),
+    }
+}
+
+// What `reconstruct_keeping` would do at the output `plan` has, for a dry run.
+pub fn planned_changes(
+    plan: &ReconstructPlan,
+    trash: bool,
+) -> Result<Vec<FileChange>, SplitterError> {
+    let size = Some(plan.total_size);
+    if !(trash && plan.overwrites && is_file(&plan.output)) {
+        return Ok(vec![FileChange::write(plan.output.clone(), size)]);
+    }
+    let moved = trash_path(&plan.output)?;
+    let created = FileChange {
+        kind: ChangeKind::Create,
+        path: moved.original.clone(),
+        size,
+        to: None,
+    };
+    Ok(vec![
+        FileChange::moved(moved.original, moved.trashed),
+        created,
+    ])
+}
+
+// Move `path` to the trash: the desktop's, if there is one that takes it, or else the
+// one beside it, under its own name or that with a number added when the trash has one
+// already.
+pub fn discard(path: &Path) -> Result<Trashed, SplitterError> {
+    let failed = |source: io::Error| SplitterError::Io {
+        path: path.to_path_buf(),
+        source,
+        action: Some("moving to the trash"),
+    };
+    let moved = trash_path(path)?;
+    if moved.on_desktop() {
+        let before = desktop::names(&moved.original);
+        match ::trash::delete(&moved.original) {
+            // The trash renames a file when it has one by that name already
+            Ok(()) => match desktop::added(&moved.original, &before) {
+                Some(name) => return Ok(desktop_path(&moved.original, &name)),
+                None => return Ok(moved),
+            },
+            Err(e) => eprintln!(
+                "Warning: the trash won't take {}, so it goes into {}/ beside it: {}",
+                path.display(),
+                TRASH_DIR,
+                e
+            ),
+        }
+    }
+    let moved = beside(&moved.original).map_err(failed)?;
+    if let Some(directory) = moved.trashed.parent() {
+        fs::create_dir_all(directory).map_err(failed)?;
+    }
+    fs::rename(path, &moved.trashed).map_err(failed)?;
+    Ok(moved)
+}
+
+// Where `discard` would move `path` to, made absolute.
+fn trash_path(path: &Path) -> Result<Trashed, SplitterError> {
+    let failed = |source: io::Error| SplitterError::Io {
+        path: path.to_path_buf(),
+        source,
+        action: Some("moving to the trash"),
+    };
+    let name = path.file_name().ok_or_else(|| {
+        failed(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not a file name",
+        ))
+    })?;
+    // Resolved, so the journal has the paths the same whichever way they were reached
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty());
+    let parent = fs::canonicalize(parent.unwrap_or(Path::new("."))).map_err(failed)?;
+    let original = parent.join(name);
+    match has_desktop() {
+        true => Ok(desktop_path(&original, &name.to_string_lossy())),
+        false => beside(&original).map_err(failed),
+    }
+}
+
+// Where `original`, an absolute path, goes in the trash beside it.
+fn beside(original: &Path) -> io::Result<Trashed> {
+    let (Some(parent), Some(name)) = (original.parent(), original.file_name()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not a file name",
+        ));
+    };
+    let mut trashed = parent.join(TRASH_DIR).join(name);
+    if fs::symlink_metadata(&trashed).is_ok() {
+        trashed = unused(&trashed);
+    }
+    Ok(Trashed {
+        original: original.to_path_buf(),
+        trashed,
+    })
+}
+
+// Whether there is a desktop whose trash someone would look in: always on Windows and
+// macOS, and elsewhere within an X11 or Wayland session.
+fn has_desktop() -> bool {
+    cfg!(any(windows, target_os = "macos"))
+        || ["DISPLAY", "WAYLAND_DISPLAY"]
+            .iter()
+            .any(|name| env::var_os(name).is_some_and(|value| !value.is_empty()))
+}
+
+fn desktop_path(original: &Path, name: &str) -> Trashed {
+    Trashed {
+        original: original.to_path_buf(),
+        trashed: PathBuf::from(format!("{}{}", DESKTOP_TRASH, name)),
+    }
+}
+
+// Whether `trashed` is where a file moved to the desktop's trash is recorded as going.
+pub fn on_desktop(trashed: &Path) -> bool {
+    trashed
+        .to_str()
+        .is_some_and(|trashed| trashed.starts_with(DESKTOP_TRASH))
+}
+
+// Put the file moved from `original` to `trashed` back.
+fn put_back(trashed: &Path, original: &Path) -> io::Result<()> {
+    match on_desktop(trashed) {
+        true => desktop::restore(desktop::find(original, trashed)?),
+        false => fs::rename(trashed, original),
+    }
+}
+
+// The desktop's trash, as far as the trash crate can search it. A file in it is known
+// by its name there, which the trash makes unique: that of its entry in a freedesktop
+// trash's info/, or of the file in the Recycle Bin.
+#[cfg(any(
+    windows,
+    all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "ios"),
+        not(target_os = "android")
+    )
+))]
+mod desktop {
+    use std::io;
+    use std::path::Path;
+
+    use trash::TrashItem;
+    use trash::os_limited::{list, restore_all};
+
+    use super::DESKTOP_TRASH;
+
+    fn name(item: &TrashItem) -> String {
+        let id = Path::new(&item.id);
+        id.file_stem()
+            .unwrap_or(id.as_os_str())
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    // The names of the files in the trash that came from `original`.
+    pub fn names(original: &Path) -> Vec<String> {
+        let items = list().unwrap_or_default();
+        items
+            .iter()
+            .filter(|item| item.original_path() == original)
+            .map(name)
+            .collect()
+    }
+
+    // The name of the file from `original` that is in the trash now but wasn't among
+    // `before`.
+    pub fn added(original: &Path, before: &[String]) -> Option<String> {
+        names(original)
+            .into_iter()
+            .find(|name| !before.contains(name))
+    }
+
+    // The file moved from `original` to `trashed` in the trash.
+    pub fn find(original: &Path, trashed: &Path) -> io::Result<TrashItem> {
+        let wanted = trashed.to_string_lossy();
+        let wanted = wanted.strip_prefix(DESKTOP_TRASH).unwrap_or(&wanted);
+        let items = list().map_err(io::Error::other)?;
+        items
+            .into_iter()
+            .find(|item| item.original_path() == original && name(item) == wanted)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "{}, moved to the trash from {}, is no longer there",
+                        wanted,
+                        original.display()
+                    ),
+                )
+            })
+    }
+
+    pub fn restore(item: TrashItem) -> io::Result<()> {
+        restore_all([item]).map_err(io::Error::other)
+    }
+}
+
+// macOS's Trash can only be put into
+#[cfg(not(any(
+    windows,
+    all(
+        unix,
+        not(target_os = "macos"),
+        not(target_os = "ios"),
+        not(target_os = "android")
+    )
+)))]
+mod desktop {
+    use std::io;
+    use std::path::Path;
+
+    pub enum TrashItem {}
+
+    pub fn names(_original: &Path) -> Vec<String> {
+        Vec::new()
+    }
+
+    pub fn added(_original: &Path, _before: &[String]) -> Option<String> {
+        None
+    }
+
+    pub fn find(original: &Path, _trashed: &Path) -> io::Result<TrashItem> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "{} is in the Trash; put it back from there in the Finder",
+                original.display()
+            ),
+        ))
+    }
+
+    pub fn restore(item: TrashItem) -> io::Result<()> {
+        match item {}
+    }
+}
+
+// What `undo_last` did: the file put back, and what was in its way, now in the trash.
+pub struct Undone {
+    pub restored: Trashed,
+    pub displaced: Option<Trashed>,
+}
+
+// Put back the latest file the journal has moved to the trash that hasn't been put
+// back yet.
+pub fn undo_last() -> io::Result<Undone> {
+    let records = journal::read()?;
+    let mut restored = Vec::new();
+    let mut last = None;
+    for record in records.iter().rev() {
+        match record.operation.as_str() {
+            "restore" => restored.push(record.source.as_str()),
+            "trash" => {
+                if let Some(trashed) = &record.destination
+                    && !restored.contains(&trashed.as_str())
+                {
+                    last = Some((PathBuf::from(&record.source), PathBuf::from(trashed)));
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let Some((original, trashed)) = last else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "the journal has no file moved to the trash that hasn't been put back",
+        ));
+    };
+    // Found before anything is moved out of its way
+    let on_desktop = on_desktop(&trashed);
+    let item = match on_desktop {
+        true => Some(desktop::find(&original, &trashed)?),
+        false => None,
+    };
+    if !on_desktop && fs::symlink_metadata(&trashed).is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "{}, moved there from {}, is no longer in the trash",
+                trashed.display(),
+                original.display()
+            ),
+        ));
+    }
+    let displaced = match fs::symlink_metadata(&original) {
+        Ok(_) => {
+            let displaced = discard(&original).map_err(io::Error::from)?;
+            journal::trash(&displaced.original, &displaced.trashed);
+            Some(displaced)
+        }
+        Err(_) => None,
+    };
+    if let Some(parent) = original.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match item {
+        Some(item) => desktop::restore(item)?,
+        None => fs::rename(&trashed, &original)?,
+    }
+    journal::restore(&trashed, &original);
+    Ok(Undone {
+        restored: Trashed { original, trashed },
+        displaced,
+    })
+}
+
+// What `empty` removed from a .fsr-trash/, or with a dry run would have: each file and
+// its size, and how many it kept for having been moved there too recently.
+pub struct Emptied {
+    pub trash: PathBuf,
+    pub removed: Vec<(PathBuf, u64)>,
+    pub kept: usize,
+}
+
+// Remove what is in the .fsr-trash/ in `directory`, or in `directory` itself when it is
+// one: everything, or with `older_than` only what was moved there longer ago than that.
+// When a file was moved goes by the journal, or, for one it has no record of, by when
+// its entry last changed, which the move did; where that can't be told, as on Windows,
+// such a file is kept. The trash directory goes too once nothing is left in it; a
+// directory without one has nothing to remove.
+pub fn empty(directory: &Path, older_than: Option<Duration>, dry_run: bool) -> io::Result<Emptied> {
+    // Resolved, as the journal has the paths
+    let directory = fs::canonicalize(directory)?;
+    let trash = match directory.file_name() == Some(OsStr::new(TRASH_DIR)) {
+        true => directory,
+        false => directory.join(TRASH_DIR),
+    };
+    let mut emptied = Emptied {
+        trash: trash.clone(),
+        removed: Vec::new(),
+        kept: 0,
+    };
+    let entries = match fs::read_dir(&trash) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(emptied),
+        Err(e) => return Err(e),
+    };
+    let moved = match older_than {
+        Some(_) => moved_times(),
+        None => BTreeMap::new(),
+    };
+    let mut entries = entries.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    let now = SystemTime::now();
+    for entry in entries {
+        let path = entry.path();
+        let metadata = fs::symlink_metadata(&path)?;
+        if let Some(older_than) = older_than {
+            let since = moved
+                .get(path.to_string_lossy().as_ref())
+                .copied()
+                .or_else(|| changed(&metadata));
+            let old = since
+                .and_then(|since| now.duration_since(since).ok())
+                .is_some_and(|age| age >= older_than);
+            if !old {
+                emptied.kept += 1;
+                continue;
+            }
+        }
+        let size = match metadata.is_dir() {
+            true => walkdir::WalkDir::new(&path)
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.metadata().ok())
+                .filter(|metadata| metadata.is_file())
+                .map(|metadata| metadata.len())
+                .sum(),
+            false => metadata.len(),
+        };
+        if !dry_run {
+            match metadata.is_dir() {
+                true => fs::remove_dir_all(&path)?,
+                false => fs::remove_file(&path)?,
+            }
+        }
+        emptied.removed.push((path, size));
+    }
+    if !dry_run && emptied.kept == 0 {
+        // Something moved there meanwhile stays, and the directory with it
+        let _ = fs::remove_dir(&trash);
+    }
+    Ok(emptied)
+}
+
+// When each file the journal has moved into a trash was moved, by where it went.
+fn moved_times() -> BTreeMap<String, SystemTime> {
+    let records = journal::read().unwrap_or_default();
+    records
+        .into_iter()
+        .filter(|record| record.operation == "trash")
+        .filter_map(|record| {
+            let seconds = parse_utc_time(&record.timestamp)?;
+            Some((
+                record.destination?,
+                UNIX_EPOCH + Duration::from_secs(seconds),
+            ))
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn changed(metadata: &fs::Metadata) -> Option<SystemTime> {
+    use std::os::unix::fs::MetadataExt;
+
+    let seconds = u64::try_from(metadata.ctime()).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+#[cfg(not(unix))]
+fn changed(_metadata: &fs::Metadata) -> Option<SystemTime> {
+    None
+}
+
+fn is_file(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Elsewhere a file the journal has no record of is never old enough
+    #[cfg(unix)]
+    #[test]
+    fn the_trash_beside_a_file_is_emptied_of_what_is_old_enough() {
+        let temp = tempfile::tempdir().unwrap();
+        let trash = temp.path().join(TRASH_DIR);
+        fs::create_dir_all(trash.join("b")).unwrap();
+        fs::write(trash.join("a.bin"), "replaced").unwrap();
+        fs::write(trash.join("b").join("c.bin"), "nested").unwrap();
+        let names = |emptied: &Emptied| -> Vec<PathBuf> {
+            let removed = emptied.removed.iter();
+            removed
+                .map(|(path, _)| path.strip_prefix(&emptied.trash).unwrap().to_path_buf())
+                .collect()
+        };
+
+        // Just moved there, by when their entries changed
+        let emptied = empty(temp.path(), Some(Duration::from_secs(3600)), false).unwrap();
+        assert!(emptied.removed.is_empty());
+        assert_eq!(emptied.kept, 2);
+
+        let emptied = empty(temp.path(), Some(Duration::ZERO), true).unwrap();
+        assert_eq!(names(&emptied), [Path::new("a.bin"), Path::new("b")]);
+        assert_eq!(emptied.removed[0].1 + emptied.removed[1].1, 14);
+        assert!(trash.join("a.bin").exists());
+
+        // The trash itself can be named, and goes once empty
+        let emptied = empty(&trash, None, false).unwrap();
+        assert_eq!(emptied.removed.len(), 2);
+        assert!(!trash.exists());
+        let emptied = empty(temp.path(), None, false).unwrap();
+        assert!(emptied.removed.is_empty());
+        assert!(empty(&temp.path().join("missing"), None, false).is_err());
+    }
+}
@@ -0,0 +1,516 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use reconstruct_large_file::size::format_size;
+use reconstruct_large_file::{
+    CancelToken, ChunkHealth, DEFAULT_CHUNK_SIZE, ProgressEvent, ReconstructOptions, SplitOptions,
+    chunk_health, default_output_name, parent_dir, split_file,
+};
+
+use crate::progress::Timing;
+use crate::{CONCEALED, concealed};
+use crate::{globals, natural_cmp, reconstruct_options, split_options, style, suffixed};
+use crate::{journal, logging, trash};
+
+// Full-screen alternative to the prompt-based menus. ratatui's init installs a panic
+// hook that restores the terminal, and every frame is laid out against the current
+// size so resizes (including mid-operation) just redraw.
+pub fn run(directory: PathBuf) -> io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    // Log lines on stderr would scribble over the screen; the log file still gets them
+    logging::set_console(false);
+    let result = App::new(directory).run(&mut terminal);
+    ratatui::restore();
+    logging::set_console(true);
+    result
+}
+
+struct Entry {
+    name: String,
+    path: PathBuf,
+    is_dir: bool,
+}
+
+// An operation running on a worker thread so the screen keeps refreshing.
+struct Job {
+    label: String,
+    done: Arc<AtomicU64>,
+    total: u64,
+    cancel: CancelToken,
+    handle: JoinHandle<Result<String, String>>,
+}
+
+enum Pending {
+    Split { input: PathBuf, savedir: PathBuf },
+    Reconstruct { directory: PathBuf, name: String },
+}
+
+struct App {
+    directory: PathBuf,
+    entries: Vec<Entry>,
+    state: ListState,
+    details: Option<(PathBuf, Vec<String>)>,
+    pending: Option<Pending>,
+    job: Option<Job>,
+    message: String,
+    quit: bool,
+}
+
+impl App {
+    fn new(directory: PathBuf) -> Self {
+        let mut app = App {
+            directory,
+            entries: Vec::new(),
+            state: ListState::default(),
+            details: None,
+            pending: None,
+            job: None,
+            message: String::new(),
+            quit: false,
+        };
+        app.refresh();
+        app
+    }
+
+    fn refresh(&mut self) {
+        self.entries.clear();
+        if let Some(parent) = parent_dir(&self.directory) {
+            self.entries.push(Entry {
+                name: "..".to_string(),
+                path: parent,
+                is_dir: true,
+            });
+        }
+        match fs::read_dir(&self.directory) {
+            Ok(read_dir) => {
+                let mut found: Vec<Entry> = read_dir
+                    .flatten()
+                    .map(|entry| {
+                        let path = entry.path();
+                        Entry {
+                            name: entry.file_name().to_string_lossy().into_owned(),
+                            is_dir: path.is_dir(),
+                            path,
+                        }
+                    })
+                    .collect();
+                found.sort_by(|a, b| {
+                    b.is_dir
+                        .cmp(&a.is_dir)
+                        .then_with(|| natural_cmp(&a.name, &b.name))
+                });
+                self.entries.extend(found);
+            }
+            Err(e) => {
+                self.message = format!("cannot open {}: {}", self.directory.display(), e);
+            }
+        }
+        self.state.select((!self.entries.is_empty()).then_some(0));
+        self.details = None;
+    }
+
+    fn selected(&self) -> Option<&Entry> {
+        self.state.selected().and_then(|i| self.entries.get(i))
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        while !self.quit {
+            self.finish_job();
+            self.update_details();
+            terminal.draw(|frame| self.draw(frame))?;
+            if event::poll(Duration::from_millis(100))?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+            {
+                self.handle_key(key.code);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_key(&mut self, code: KeyCode) {
+        if let Some(job) = &self.job {
+            if code == KeyCode::Esc {
+                job.cancel.cancel();
+            } else {
+                self.message = "An operation is in progress, please wait.".to_string();
+            }
+            return;
+        }
+        if let Some(pending) = self.pending.take() {
+            match code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => self.start(pending),
+                _ => self.message = "Cancelled.".to_string(),
+            }
+            return;
+        }
+
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Up | KeyCode::Char('k') => self.state.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.state.select_next(),
+            KeyCode::Home => self.state.select_first(),
+            KeyCode::End => self.state.select_last(),
+            KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => {
+                if let Some(entry) = self.selected()
+                    && entry.is_dir
+                {
+                    self.directory = entry.path.clone();
+                    self.refresh();
+                }
+            }
+            KeyCode::Left | KeyCode::Backspace | KeyCode::Char('h') => {
+                if let Some(parent) = parent_dir(&self.directory) {
+                    self.directory = parent;
+                    self.refresh();
+                }
+            }
+            KeyCode::Char('s') => self.ask_split(),
+            KeyCode::Char('r') => self.ask_reconstruct(),
+            KeyCode::Char('v') => self.verify(),
+            _ => {}
+        }
+    }
+
+    // The chunk directory an action applies to: the selected directory if it holds
+    // chunks, otherwise the directory being browsed.
+    fn target_directory(&self) -> PathBuf {
+        match self.selected() {
+            Some(entry)
+                if entry.is_dir
+                    && entry.name != ".."
+                    && chunk_health(&entry.path, globals().accept_modified)
+                        .is_ok_and(|h| h.chunks > 0) =>
+            {
+                entry.path.clone()
+            }
+            _ => self.directory.clone(),
+        }
+    }
+
+    fn ask_split(&mut self) {
+        let Some(entry) = self.selected().filter(|e| !e.is_dir) else {
+            self.message = "Select a file to split.".to_string();
+            return;
+        };
+        let input = entry.path.clone();
+        let savedir = self
+            .directory
+            .join(suffixed(entry.path.file_name(), "output", ".chunks"));
+        self.message = format!(
+            "Split {} into {} ({} chunks)? [y/N]",
+            entry.name,
+            savedir.display(),
+            format_size(DEFAULT_CHUNK_SIZE)
+        );
+        self.pending = Some(Pending::Split { input, savedir });
+    }
+
+    fn ask_reconstruct(&mut self) {
+        let directory = self.target_directory();
+        if concealed(&directory) {
+            self.message = format!(
+                "{}: {}; reconstruct it from the menus or with the reconstruct command, \
+                 which ask for it",
+                directory.display(),
+                CONCEALED
+            );
+            return;
+        }
+        match default_output_name(&directory, globals().accept_modified) {
+            Ok(name) => {
+                self.message = format!(
+                    "Reconstruct {} into {}? [y/N]",
+                    directory.display(),
+                    directory.join(&name).display()
+                );
+                self.pending = Some(Pending::Reconstruct { directory, name });
+            }
+            Err(e) => self.message = format!("Error during reconstruction: {}", e),
+        }
+    }
+
+    fn verify(&mut self) {
+        let directory = self.target_directory();
+        self.message = match chunk_health(&directory, globals().accept_modified) {
+            Ok(health) if health.is_healthy() => format!(
+                "{}: {} chunks, {}, no problems found.",
+                directory.display(),
+                health.chunks,
+                format_size(health.total_size)
+            ),
+            Ok(health) if health.chunks == 0 => {
+                format!("{}: no chunk files found.", directory.display())
+            }
+            Ok(health) if !health.unexpected.is_empty() => format!(
+                "{}: files not in info.json {:?}.",
+                directory.display(),
+                health.unexpected
+            ),
+            Ok(health) => format!(
+                "{}: missing chunks {:?}, unevenly sized chunks {:?}.",
+                directory.display(),
+                health.missing,
+                health.uneven
+            ),
+            Err(e) => format!("cannot open {}: {}", directory.display(), e),
+        };
+        self.details = None;
+    }
+
+    fn start(&mut self, pending: Pending) {
+        let cancel = CancelToken::new();
+        let token = cancel.clone();
+        let done = Arc::new(AtomicU64::new(0));
+        let counter = Arc::clone(&done);
+        let count = move |event: &ProgressEvent| {
+            if let ProgressEvent::BytesCopied { delta } = *event {
+                counter.fetch_add(delta, Ordering::Relaxed);
+            }
+        };
+
+        let (label, total, handle) = match pending {
+            Pending::Split { input, savedir } => {
+                let total = fs::metadata(&input).map(|m| m.len()).unwrap_or(0);
+                let label = format!("Splitting {}", input.display());
+                let handle = thread::spawn(move || {
+                    let mut timing = Timing::start();
+                    let mut progress = |event: ProgressEvent| {
+                        count(&event);
+                        timing.record(&event);
+                    };
+                    split_options(SplitOptions::builder(&input, &savedir), None)
+                        .build()
+                        .and_then(|options| {
+                            let started = journal::start();
+                            let result = split_file(&options, &mut progress, &token);
+                            journal::split(started, &options, &result);
+                            result
+                        })
+                        .map(|_| format!("Split into {}. {}", savedir.display(), timing.summary()))
+                        .map_err(|e| format!("Error during splitting: {}", e))
+                });
+                (label, total, handle)
+            }
+            Pending::Reconstruct { directory, name } => {
+                let total = chunk_health(&directory, globals().accept_modified)
+                    .map(|h| h.total_size)
+                    .unwrap_or(0);
+                let label = format!("Reconstructing {}", name);
+                let handle = thread::spawn(move || {
+                    let mut timing = Timing::start();
+                    let mut progress = |event: ProgressEvent| {
+                        count(&event);
+                        timing.record(&event);
+                    };
+                    let options = ReconstructOptions {
+                        output: Some(name.clone()),
+                        ..reconstruct_options(&directory, None)
+                    };
+                    let started = journal::start();
+                    let (result, _) =
+                        trash::reconstruct_keeping(&options, true, &mut progress, &token);
+                    journal::reconstruct(started, &options, &result);
+                    result
+                        .map(|_| {
+                            format!(
+                                "Reconstructed file saved as \"{}\". {}",
+                                name,
+                                timing.summary()
+                            )
+                        })
+                        .map_err(|e| format!("Error during reconstruction: {}", e))
+                });
+                (label, total, handle)
+            }
+        };
+        self.message = String::new();
+        self.job = Some(Job {
+            label,
+            done,
+            total,
+            cancel,
+            handle,
+        });
+    }
+
+    fn finish_job(&mut self) {
+        if !self
+            .job
+            .as_ref()
+            .is_some_and(|job| job.handle.is_finished())
+        {
+            return;
+        }
+        let job = self.job.take().unwrap();
+        self.message = match job.handle.join() {
+            Ok(Ok(message)) | Ok(Err(message)) => message,
+            Err(_) => "The operation panicked.".to_string(),
+        };
+        let selected = self.state.selected();
+        self.refresh();
+        self.state.select(selected);
+    }
+
+    fn update_details(&mut self) {
+        let Some(entry) = self.selected() else {
+            self.details = None;
+            return;
+        };
+        if self
+            .details
+            .as_ref()
+            .is_some_and(|(path, _)| *path == entry.path)
+        {
+            return;
+        }
+        let lines = describe(entry);
+        self.details = Some((entry.path.clone(), lines));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)])
+                .areas(main);
+
+        let items: Vec<ListItem> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                if entry.is_dir {
+                    ListItem::new(format!("{}/", entry.name)).style(colored(Color::Blue))
+                } else {
+                    ListItem::new(entry.name.clone())
+                }
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::bordered().title(self.directory.display().to_string()))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+            .highlight_symbol("> ");
+        frame.render_stateful_widget(list, left, &mut self.state);
+
+        let details: Vec<Line> = self
+            .details
+            .iter()
+            .flat_map(|(_, lines)| lines.iter().map(|l| Line::from(l.as_str())))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(details)
+                .block(Block::bordered().title("Details"))
+                .wrap(Wrap { trim: false }),
+            right,
+        );
+
+        match &self.job {
+            Some(job) => {
+                let done = job.done.load(Ordering::Relaxed);
+                let ratio = if job.total == 0 {
+                    0.0
+                } else {
+                    (done as f64 / job.total as f64).min(1.0)
+                };
+                let gauge = Gauge::default()
+                    .block(Block::bordered().title(job.label.as_str()).title_bottom(
+                        if job.cancel.is_cancelled() {
+                            "Cancelling…"
+                        } else {
+                            "Esc to cancel"
+                        },
+                    ))
+                    .gauge_style(colored(Color::Green))
+                    .ratio(ratio)
+                    .label(format!(
+                        "{} / {}",
+                        format_size(done),
+                        format_size(job.total)
+                    ));
+                frame.render_widget(gauge, status);
+            }
+            None => {
+                let text = if self.message.is_empty() {
+                    "↑/↓ move  Enter open  ← parent  s split  r reconstruct  v verify  q quit"
+                } else {
+                    self.message.as_str()
+                };
+                frame.render_widget(Paragraph::new(text).block(Block::bordered()), status);
+            }
+        }
+    }
+}
+
+fn colored(color: Color) -> Style {
+    if style::enabled() {
+        Style::default().fg(color)
+    } else {
+        Style::default()
+    }
+}
+
+fn describe(entry: &Entry) -> Vec<String> {
+    if !entry.is_dir {
+        return match fs::metadata(&entry.path) {
+            Ok(metadata) => {
+                let size = metadata.len();
+                vec![
+                    format!("File: {}", entry.name),
+                    format!("Size: {}", format_size(size)),
+                    format!(
+                        "Splits into {} chunks of {}",
+                        size.div_ceil(DEFAULT_CHUNK_SIZE),
+                        format_size(DEFAULT_CHUNK_SIZE)
+                    ),
+                ]
+            }
+            Err(e) => vec![format!("cannot read {}: {}", entry.path.display(), e)],
+        };
+    }
+
+    let mut lines = vec![format!("Directory: {}", entry.name)];
+    match chunk_health(&entry.path, globals().accept_modified) {
+        Ok(health) if health.chunks == 0 => lines.push("No chunk files.".to_string()),
+        // Sealed under the key, the size is only that of the chunks as stored
+        Ok(health) if concealed(&entry.path) => {
+            lines.push(CONCEALED.to_string());
+            lines.push(format!("Chunks: {}", health.chunks));
+            lines.push(format!("Stored size: {}", format_size(health.total_size)));
+            lines.push(health_summary(&health));
+        }
+        Ok(health) => {
+            if let Ok(name) = default_output_name(&entry.path, globals().accept_modified) {
+                lines.push(format!("Original file: {}", name));
+            }
+            lines.push(format!("Chunks: {}", health.chunks));
+            lines.push(format!("Total size: {}", format_size(health.total_size)));
+            lines.push(health_summary(&health));
+        }
+        Err(e) => lines.push(format!("cannot open {}: {}", entry.path.display(), e)),
+    }
+    lines
+}
+
+fn health_summary(health: &ChunkHealth) -> String {
+    if !health.missing.is_empty() {
+        format!("Health: missing chunks {:?}", health.missing)
+    } else if !health.uneven.is_empty() {
+        format!("Health: unevenly sized chunks {:?}", health.uneven)
+    } else if !health.unexpected.is_empty() {
+        format!("Health: files not in info.json {:?}", health.unexpected)
+    } else {
+        "Health: OK".to_string()
+    }
+}
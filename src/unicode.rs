@@ -0,0 +1,97 @@
+// Unicode normalization of file names. macOS keeps names decomposed, "é" as "e" and a
+// combining accent (NFD), where most else has them composed (NFC), so the same name
+// can be two different strings depending on where it was typed or last stored. Both
+// forms come from the unicode-normalization crate.
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{UnicodeNormalization, is_nfc, is_nfd};
+
+// A normalization form to put a name in, or none.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Normalization {
+    // As it is
+    #[default]
+    Keep,
+    Nfc,
+    Nfd,
+}
+
+impl Normalization {
+    pub fn apply(self, name: &str) -> String {
+        match self {
+            Normalization::Keep => name.to_string(),
+            Normalization::Nfc => nfc(name),
+            Normalization::Nfd => nfd(name),
+        }
+    }
+
+    // The form `name` is in, when it is in one and not the other; ASCII names are in
+    // both.
+    pub fn of(name: &str) -> Option<Normalization> {
+        match (is_nfc(name), is_nfd(name)) {
+            (true, false) => Some(Normalization::Nfc),
+            (false, true) => Some(Normalization::Nfd),
+            _ => None,
+        }
+    }
+}
+
+// Whether two names are the same but for their normalization.
+pub fn equivalent(a: &str, b: &str) -> bool {
+    a == b || nfd(a) == nfd(b)
+}
+
+pub fn nfd(text: &str) -> String {
+    text.nfd().collect()
+}
+
+pub fn nfc(text: &str) -> String {
+    text.nfc().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Names in both forms: accents, one character that composes from two accents in
+    // either order, Hangul, and a singleton with no composed form of its own
+    const NAMES: [(&str, &str); 5] = [
+        ("caf\u{e9}.txt", "cafe\u{301}.txt"),
+        ("\u{1ec7}t", "e\u{323}\u{302}t"),
+        (
+            "\u{d55c}\u{ae00}",
+            "\u{1112}\u{1161}\u{11ab}\u{1100}\u{1173}\u{11af}",
+        ),
+        ("\u{c5}ngstr\u{f6}m", "A\u{30a}ngstro\u{308}m"),
+        ("plain.bin", "plain.bin"),
+    ];
+
+    #[test]
+    fn names_go_back_and_forth_between_forms() {
+        for (composed, decomposed) in NAMES {
+            assert_eq!(nfc(decomposed), composed);
+            assert_eq!(nfd(composed), decomposed);
+            assert_eq!(nfc(&nfd(composed)), composed);
+            assert_eq!(nfd(&nfc(decomposed)), decomposed);
+            assert!(equivalent(composed, decomposed));
+        }
+        // The angstrom sign and combining marks out of canonical order come back in
+        // one form or the other, not as they were
+        assert_eq!(nfc("\u{212b}"), "\u{c5}");
+        assert_eq!(nfd("e\u{302}\u{323}"), "e\u{323}\u{302}");
+        assert!(!equivalent("cafe.txt", "caf\u{e9}.txt"));
+    }
+
+    #[test]
+    fn the_form_a_name_is_in() {
+        let (composed, decomposed) = NAMES[0];
+        assert_eq!(Normalization::of(composed), Some(Normalization::Nfc));
+        assert_eq!(Normalization::of(decomposed), Some(Normalization::Nfd));
+        assert_eq!(Normalization::of("plain.bin"), None);
+        assert_eq!(Normalization::Keep.apply(decomposed), decomposed);
+        assert_eq!(Normalization::Nfc.apply(decomposed), composed);
+        assert_eq!(Normalization::Nfd.apply(composed), decomposed);
+    }
+}
@@ -0,0 +1,640 @@
+// `watch`: splits every file that turns up in an inbox directory, each into a directory
+// of its own under a destination root, for exports that are dropped in one place and
+// picked up from another. The system reports changes in the inbox through the `notify`
+// crate; where it can't, or with `poll` for a network share whose changes made on other
+// machines it never hears of, the inbox is looked through every `interval` instead. A
+// file is only taken once its size and modification time have stayed the same for the
+// quiet period, so one still being copied in is left until it is complete. Names that
+// editors and downloads use for files not yet finished are never taken, nor is anything
+// in subdirectories unless `recursive` is set.
+//
+// A file whose split fails is left where it is, and not tried again until it changes.
+// With `done`, each file split is moved into `done/` in the inbox. A file whose directory
+// of chunks is already taken, as by a file of the same name split earlier, is dealt with
+// as `on_collision` says: by default, without `done` it counts as split, so the watch can
+// be stopped and started again, and with it the chunks go to a directory numbered apart.
+// Each directory is named for the file, or as a template says. The directory each file
+// went to is in the line saying it was split. Every step is logged to stdout, failures
+// to stderr, each line with the time; Ctrl+C stops the watch, cancelling a split under
+// way.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime};
+
+use clap::ValueEnum;
+use notify::{Event, EventKind, PollWatcher, RecursiveMode, Watcher as _};
+use reconstruct_large_file::manifest::{Compression, HashAlgorithm};
+use reconstruct_large_file::{CancelToken, SplitOptions, split_file};
+
+use crate::logging::timestamp;
+use crate::progress::Timing;
+use crate::prompt::list_prompt;
+use crate::template::Template;
+use crate::{format_size, print_input_changed};
+
+// Where files split are moved to with `done`, inside the inbox
+pub const DONE_DIR: &str = "done";
+// How often the wait for changes checks for Ctrl+C and for files gone quiet
+const POLL: Duration = Duration::from_millis(100);
+// Endings of names given to files still being written: partial downloads, editor swap
+// and backup files
+const TEMP_SUFFIXES: &[&str] = &[
+    "~",
+    ".tmp",
+    ".temp",
+    ".part",
+    ".partial",
+    ".crdownload",
+    ".download",
+    ".swp",
+    ".swx",
+];
+
+// What is done with a file whose directory of chunks is already taken.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Collision {
+    // Ask on the terminal, each time
+    Ask,
+    // Number the directory apart, the first number free: export.csv.2.chunks
+    Rename,
+    // Tell it apart by a hash of the file's path, the same every time that file is
+    // split: export.csv.5d41402a.chunks
+    Hash,
+    // Take the file to be split already, and leave it be
+    Skip,
+    // Stop the watch
+    Fail,
+}
+
+pub struct WatchOptions {
+    pub inbox: PathBuf,
+    pub dest_root: PathBuf,
+    // Naming each file's directory of chunks in place of <file name>.chunks
+    pub template: Option<Template>,
+    pub chunk_size: u64,
+    pub min_chunk_size: u64,
+    pub threads: usize,
+    pub hash: Option<HashAlgorithm>,
+    pub compress: Option<(Compression, u32)>,
+    // How long a file has to stay unchanged before it is split
+    pub quiet: Duration,
+    // Look through the inbox every `interval` rather than be told of changes
+    pub poll: bool,
+    pub interval: Duration,
+    pub done: bool,
+    pub recursive: bool,
+    pub on_collision: Collision,
+}
+
+// What a file looked like, which it has to keep looking like to be taken.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Signature {
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+struct Watcher<'a> {
+    options: &'a WatchOptions,
+    // The inbox, `done/` in it and the destination root as they resolve, so the last
+    // two are left out of the inbox when they are in it
+    inbox: PathBuf,
+    done: PathBuf,
+    dest_root: PathBuf,
+    // Files seen, with when they were last seen to change
+    pending: HashMap<PathBuf, (Signature, Instant)>,
+    // Files split or failed, left alone until they change
+    settled: HashMap<PathBuf, Signature>,
+}
+
+pub fn run(options: &WatchOptions, token: &CancelToken) -> io::Result<()> {
+    fs::create_dir_all(&options.dest_root)?;
+    let inbox = fs::canonicalize(&options.inbox)?;
+    let dest_root = fs::canonicalize(&options.dest_root)?;
+    let (sender, events) = mpsc::channel();
+    let _watching = watch(&inbox, options, sender)?;
+    let mut watcher = Watcher {
+        options,
+        done: inbox.join(DONE_DIR),
+        inbox,
+        dest_root,
+        pending: HashMap::new(),
+        settled: HashMap::new(),
+    };
+    log(&format!(
+        "Watching {} for files to split into {}; Ctrl+C stops.",
+        options.inbox.display(),
+        options.dest_root.display()
+    ));
+    // What was there before the watch started is taken as just changed
+    watcher.rescan();
+    while !token.is_cancelled() {
+        match events.recv_timeout(POLL) {
+            Ok(Ok(event)) => watcher.changed(&event),
+            Ok(Err(e)) => {
+                log_error(&format!("Watching {}: {}", options.inbox.display(), e));
+                watcher.rescan();
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(io::Error::other("the watch on the inbox stopped"));
+            }
+        }
+        watcher.take_quiet(token)?;
+    }
+    log("Stopped watching.");
+    Ok(())
+}
+
+// Start reporting changes in `inbox` to `sender`, as the system tells of them or, with
+// `poll` or where it can't, as looking through the inbox every interval finds them.
+// They are reported for as long as what is returned is kept.
+fn watch(
+    inbox: &Path,
+    options: &WatchOptions,
+    sender: mpsc::Sender<notify::Result<Event>>,
+) -> io::Result<Box<dyn notify::Watcher>> {
+    let mode = match options.recursive {
+        true => RecursiveMode::Recursive,
+        false => RecursiveMode::NonRecursive,
+    };
+    if !options.poll {
+        let told = notify::recommended_watcher(sender.clone()).and_then(|mut watcher| {
+            watcher.watch(inbox, mode)?;
+            Ok(watcher)
+        });
+        match told {
+            Ok(watcher) => return Ok(Box::new(watcher)),
+            Err(e) => log_error(&format!(
+                "Cannot be told of changes in {} ({}); looking through it every {} s instead.",
+                options.inbox.display(),
+                e,
+                options.interval.as_secs()
+            )),
+        }
+    }
+    let config = notify::Config::default().with_poll_interval(options.interval);
+    let mut watcher = PollWatcher::new(sender, config).map_err(io::Error::other)?;
+    watcher.watch(inbox, mode).map_err(io::Error::other)?;
+    Ok(Box::new(watcher))
+}
+
+impl Watcher<'_> {
+    // Note what `event` says changed, to be split once it has been quiet for long enough.
+    // A new directory, as one moved in whole, has its files noted as well.
+    fn changed(&mut self, event: &Event) {
+        if event.need_rescan() {
+            self.rescan();
+            return;
+        }
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+        for path in &event.paths {
+            if !self.watched(path) {
+                continue;
+            }
+            if path.is_dir() {
+                if !self.options.recursive {
+                    continue;
+                }
+                let mut found = Vec::new();
+                self.scan(path, &mut found);
+                for (path, signature) in found {
+                    self.note(path, Some(signature));
+                }
+            } else {
+                self.note(path.clone(), signature_of(path).filter(|_| path.is_file()));
+            }
+        }
+    }
+
+    // Whether a change at `path` is one to look at: not of a file still being written,
+    // nor in `done/` or the destination root, nor in a subdirectory without `recursive`.
+    fn watched(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.inbox) else {
+            return false;
+        };
+        if relative.as_os_str().is_empty()
+            || path.starts_with(&self.done)
+            || path.starts_with(&self.dest_root)
+            || path.file_name().is_some_and(is_temporary)
+        {
+            return false;
+        }
+        self.options.recursive || relative.components().count() == 1
+    }
+
+    // The file at `path` is now as `signature` has it, or gone.
+    fn note(&mut self, path: PathBuf, signature: Option<Signature>) {
+        let Some(signature) = signature else {
+            self.pending.remove(&path);
+            self.settled.remove(&path);
+            return;
+        };
+        if self.settled.get(&path) == Some(&signature) {
+            return;
+        }
+        self.settled.remove(&path);
+        match self.pending.get(&path) {
+            Some(&(seen, _)) if seen == signature => {}
+            _ => {
+                self.pending.insert(path, (signature, Instant::now()));
+            }
+        }
+    }
+
+    // Look through the whole inbox, as at the start and when changes may have been
+    // missed.
+    fn rescan(&mut self) {
+        let mut found = Vec::new();
+        self.scan(&self.inbox, &mut found);
+        let current: HashMap<PathBuf, Signature> = found.into_iter().collect();
+        self.pending.retain(|path, _| current.contains_key(path));
+        self.settled.retain(|path, _| current.contains_key(path));
+        for (path, signature) in current {
+            self.note(path, Some(signature));
+        }
+    }
+
+    // Split the files that have been quiet for long enough, as they are found to be
+    // still. An error stops the watch.
+    fn take_quiet(&mut self, token: &CancelToken) -> io::Result<()> {
+        let now = Instant::now();
+        let mut quiet: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, since))| now.duration_since(*since) >= self.options.quiet)
+            .map(|(path, _)| path.clone())
+            .collect();
+        quiet.sort();
+        for path in quiet {
+            if token.is_cancelled() {
+                return Ok(());
+            }
+            let Some((signature, _)) = self.pending.remove(&path) else {
+                continue;
+            };
+            // A change may have gone unreported, as on a share
+            match signature_of(&path) {
+                Some(current) if current == signature => {}
+                current => {
+                    self.note(path, current);
+                    continue;
+                }
+            }
+            self.take(&path, token)?;
+            // Whatever came of it, the file is left alone until it changes again, as
+            // it is after the split when it isn't moved
+            if let Some(signature) = signature_of(&path) {
+                self.settled.insert(path, signature);
+            }
+        }
+        Ok(())
+    }
+
+    // Files in `directory` that could be split, and those in its subdirectories with
+    // `recursive`. A directory that can't be read is warned about and passed over.
+    fn scan(&self, directory: &Path, found: &mut Vec<(PathBuf, Signature)>) {
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log_error(&format!("Cannot read {}: {}", directory.display(), e));
+                return;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(kind) = entry.file_type() else {
+                continue;
+            };
+            if is_temporary(&entry.file_name()) {
+                continue;
+            }
+            if kind.is_dir() {
+                if self.options.recursive && path != self.done && path != self.dest_root {
+                    self.scan(&path, found);
+                }
+            } else if kind.is_file()
+                && let Some(signature) = signature_of(&path)
+            {
+                found.push((path, signature));
+            }
+        }
+    }
+
+    // Split the file at `path`, then move it into `done/` if that was asked for. Only a
+    // collision that is to stop the watch is an error.
+    fn take(&self, path: &Path, token: &CancelToken) -> io::Result<()> {
+        let relative = path.strip_prefix(&self.inbox).unwrap_or(path);
+        let mut savedir = match &self.options.template {
+            Some(template) => match template.expand(path) {
+                Ok(name) => match relative.parent() {
+                    Some(parent) => self.dest_root.join(parent).join(name),
+                    None => self.dest_root.join(name),
+                },
+                Err(e) => {
+                    log_error(&format!(
+                        "Cannot name {}'s chunks: {}",
+                        relative.display(),
+                        e
+                    ));
+                    return Ok(());
+                }
+            },
+            None => {
+                let mut name = relative.as_os_str().to_os_string();
+                name.push(".chunks");
+                self.dest_root.join(&name)
+            }
+        };
+        if savedir.exists() {
+            match self.resolve(path, relative, &savedir)? {
+                Some(free) => savedir = free,
+                None => return Ok(()),
+            }
+        }
+        let size = fs::metadata(path).map_or(0, |m| m.len());
+        log(&format!(
+            "Splitting {} ({}) into {}",
+            relative.display(),
+            format_size(size),
+            savedir.display()
+        ));
+        if let Some(parent) = savedir.parent()
+            && let Err(e) = fs::create_dir_all(parent)
+        {
+            log_error(&format!("Cannot create {}: {}", parent.display(), e));
+            return Ok(());
+        }
+        let options = SplitOptions::builder(path, &savedir)
+            .chunk_size(self.options.chunk_size)
+            .min_chunk_size(self.options.min_chunk_size)
+            .threads(self.options.threads)
+            .hash(self.options.hash);
+        let options = match self.options.compress {
+            Some((compression, level)) => options.compression(compression).compression_level(level),
+            None => options,
+        };
+        let options = match options.build() {
+            Ok(options) => options,
+            Err(e) => {
+                log_error(&format!("Cannot split {}: {}", relative.display(), e));
+                return Ok(());
+            }
+        };
+        let mut timing = Timing::start();
+        match split_file(&options, &mut |event| timing.record(&event), token) {
+            Ok(report) => {
+                log(&format!(
+                    "Split {} into {}: {}",
+                    relative.display(),
+                    savedir.display(),
+                    timing.summary()
+                ));
+                if report.input_changed {
+                    print_input_changed();
+                }
+            }
+            Err(e) => {
+                log_error(&format!("Splitting {} failed: {}", relative.display(), e));
+                return Ok(());
+            }
+        }
+        if self.options.done {
+            self.move_done(path, relative);
+        }
+        Ok(())
+    }
+
+    // Where the file at `path` goes now that `savedir` is taken: None to leave it be.
+    fn resolve(&self, path: &Path, relative: &Path, savedir: &Path) -> io::Result<Option<PathBuf>> {
+        let renamed = unused(savedir);
+        let hashed = hashed(path, savedir);
+        let collision = match self.options.on_collision {
+            Collision::Ask => ask(relative, savedir, &renamed, &hashed)?,
+            collision => collision,
+        };
+        match collision {
+            Collision::Rename => Ok(Some(renamed)),
+            Collision::Hash if !hashed.exists() => Ok(Some(hashed)),
+            Collision::Hash => {
+                log(&format!(
+                    "{} was already split into {}; remove that to split it again.",
+                    relative.display(),
+                    hashed.display()
+                ));
+                Ok(None)
+            }
+            Collision::Skip | Collision::Ask => {
+                log(&format!(
+                    "{} was already split into {}; remove that to split it again.",
+                    relative.display(),
+                    savedir.display()
+                ));
+                Ok(None)
+            }
+            Collision::Fail => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "{} is already taken, so {} can't be split into it",
+                    savedir.display(),
+                    relative.display()
+                ),
+            )),
+        }
+    }
+
+    fn move_done(&self, path: &Path, relative: &Path) {
+        let mut target = self.done.join(relative);
+        if target.exists() {
+            target = unused(&target);
+        }
+        let moved = match target.parent() {
+            Some(parent) => fs::create_dir_all(parent).and_then(|()| fs::rename(path, &target)),
+            None => fs::rename(path, &target),
+        };
+        match moved {
+            Ok(()) => log(&format!(
+                "Moved {} to {}",
+                relative.display(),
+                target.display()
+            )),
+            Err(e) => log_error(&format!(
+                "Split {} but could not move it to {}: {}",
+                relative.display(),
+                target.display(),
+                e
+            )),
+        }
+    }
+}
+
+// What to do about `savedir` being taken, as the user says: `Fail` for giving up on the
+// watch altogether.
+fn ask(relative: &Path, savedir: &Path, renamed: &Path, hashed: &Path) -> io::Result<Collision> {
+    let name = |path: &Path| {
+        path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    };
+    let rename = format!("Split it into {}", name(renamed));
+    let hash = format!("Split it into {}, named for its path", name(hashed));
+    let mut options = BTreeMap::new();
+    options.insert(rename.clone(), "action");
+    if !hashed.exists() {
+        options.insert(hash.clone(), "action");
+    }
+    options.insert("Skip it".to_string(), "action");
+    options.insert("Stop watching".to_string(), "back");
+    let question = format!(
+        "{} is already taken; what of {}?",
+        savedir.display(),
+        relative.display()
+    );
+    let choice = list_prompt(&question, &options)?;
+    Ok(match choice.as_str() {
+        choice if choice == rename => Collision::Rename,
+        choice if choice == hash => Collision::Hash,
+        "Skip it" => Collision::Skip,
+        _ => Collision::Fail,
+    })
+}
+
+// `savedir` with a hash of the file at `path` put before its extension, of its path
+// rather than what it holds, so it is known before the file is read:
+// export.csv.chunks to export.csv.5d41402a.chunks.
+fn hashed(path: &Path, savedir: &Path) -> PathBuf {
+    let absolute = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut hasher = HashAlgorithm::Sha256.hasher();
+    hasher.update(absolute.as_os_str().as_encoded_bytes());
+    let digest = hasher.finish();
+    let mut name = OsString::from(savedir.file_stem().unwrap_or_default());
+    name.push(format!(".{}", &digest[..8]));
+    if let Some(extension) = savedir.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    savedir.with_file_name(name)
+}
+
+fn signature_of(path: &Path) -> Option<Signature> {
+    let metadata = fs::symlink_metadata(path).ok()?;
+    Some(Signature {
+        size: metadata.len(),
+        modified: metadata.modified().ok(),
+    })
+}
+
+// Hidden files, which is how many programs name a file they are still writing, editor
+// autosaves and lock files (#name#, ~$name), and partial downloads.
+fn is_temporary(name: &OsStr) -> bool {
+    let name = name.to_string_lossy();
+    name.starts_with('.')
+        || name.starts_with("~$")
+        || (name.starts_with('#') && name.ends_with('#'))
+        || TEMP_SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+}
+
+// `path` with a number put before its extension, the first that isn't taken:
+// report.pdf.chunks to report.pdf.2.chunks and so on.
+pub fn unused(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default();
+    let extension = path.extension();
+    (2..)
+        .map(|n| {
+            let mut name = OsString::from(stem);
+            name.push(format!(".{}", n));
+            if let Some(extension) = extension {
+                name.push(".");
+                name.push(extension);
+            }
+            path.with_file_name(name)
+        })
+        .find(|candidate| !candidate.exists())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+fn log(message: &str) {
+    println!("{} {}", timestamp(), message);
+}
+
+fn log_error(message: &str) {
+    eprintln!("{} {}", timestamp(), message);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn options(inbox: &Path, dest_root: &Path, poll: bool) -> WatchOptions {
+        WatchOptions {
+            inbox: inbox.to_path_buf(),
+            dest_root: dest_root.to_path_buf(),
+            template: None,
+            chunk_size: 4096,
+            min_chunk_size: 0,
+            threads: 1,
+            hash: None,
+            compress: None,
+            quiet: Duration::from_millis(300),
+            poll,
+            interval: Duration::from_millis(200),
+            done: true,
+            recursive: false,
+            on_collision: Collision::Rename,
+        }
+    }
+
+    // Watch `inbox` while `drop` puts files in it, until `dest_root` has chunks for the
+    // file named `expected`.
+    fn watch_for(dir: &Path, poll: bool, expected: &str, drop: impl FnOnce(&Path)) {
+        let (inbox, dest_root) = (dir.join("inbox"), dir.join("out"));
+        fs::create_dir(&inbox).unwrap();
+        let options = options(&inbox, &dest_root, poll);
+        let token = CancelToken::new();
+        let chunks = dest_root.join(format!("{}.chunks", expected));
+        thread::scope(|scope| {
+            let watching = scope.spawn(|| run(&options, &token));
+            thread::sleep(Duration::from_millis(300));
+            drop(&inbox);
+            let started = Instant::now();
+            while !chunks.join("info.json").exists() && started.elapsed() < Duration::from_secs(10)
+            {
+                thread::sleep(POLL);
+            }
+            token.cancel();
+            watching.join().unwrap().unwrap();
+        });
+        assert!(
+            chunks.join("info.json").exists(),
+            "{} was not split",
+            expected
+        );
+        assert!(inbox.join(DONE_DIR).join(expected).exists());
+        for ignored in ["export.csv.part", "sub"] {
+            assert!(!dest_root.join(format!("{}.chunks", ignored)).exists());
+        }
+        assert!(inbox.join("export.csv.part").exists());
+    }
+
+    #[test]
+    fn a_file_dropped_in_is_split_once_quiet_and_temporaries_are_left() {
+        for poll in [false, true] {
+            let dir = tempfile::tempdir().unwrap();
+            watch_for(dir.path(), poll, "export.csv", |inbox| {
+                fs::write(inbox.join("export.csv.part"), b"not yet").unwrap();
+                fs::create_dir(inbox.join("sub")).unwrap();
+                fs::write(inbox.join("sub/nested.csv"), b"not recursive").unwrap();
+                fs::write(inbox.join("export.csv"), vec![7; 10_000]).unwrap();
+            });
+        }
+    }
+}
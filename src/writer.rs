@@ -0,0 +1,159 @@
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::path::PathBuf;
+
+use crate::error::{PathContext, Result, SplitterError};
+use crate::manifest::{ChunkEntry, ChunkHasher, HashAlgorithm, Manifest};
+use crate::store::{ChunkStore, LocalDirStore, log_written, stream_manifest};
+
+// Splits whatever is written to it, rolling over to a new chunk every `chunk_size`
+// bytes, for input whose length isn't known up front. `finish` completes the last
+// chunk and writes the manifest. Dropped without finishing, the chunks written so far
+// are removed again, so an interrupted stream doesn't leave behind what looks like a
+// complete set.
+pub struct ChunkedWriter<S: ChunkStore> {
+    store: S,
+    original_filename: String,
+    chunk_size: u64,
+    hash: Option<HashAlgorithm>,
+    // The chunk being written, its hasher and how much it holds so far
+    current: Option<(S::Writer, Option<ChunkHasher>, u64)>,
+    chunks: Vec<ChunkEntry>,
+    finished: bool,
+}
+
+impl ChunkedWriter<LocalDirStore> {
+    // Write into `directory`, which must be empty or not exist yet.
+    pub fn create(
+        directory: impl Into<PathBuf>,
+        original_filename: impl Into<String>,
+        chunk_size: u64,
+        hash: Option<HashAlgorithm>,
+    ) -> Result<ChunkedWriter<LocalDirStore>> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory).at(&directory)?;
+        if fs::read_dir(&directory).at(&directory)?.next().is_some() {
+            return Err(SplitterError::DestinationNotEmpty { path: directory });
+        }
+        ChunkedWriter::new(
+            LocalDirStore::new(directory),
+            original_filename,
+            chunk_size,
+            hash,
+        )
+    }
+}
+
+impl<S: ChunkStore> ChunkedWriter<S> {
+    pub fn new(
+        store: S,
+        original_filename: impl Into<String>,
+        chunk_size: u64,
+        hash: Option<HashAlgorithm>,
+    ) -> Result<ChunkedWriter<S>> {
+        if chunk_size == 0 {
+            return Err(SplitterError::InvalidOption {
+                field: "chunk_size",
+                reason: "must be greater than zero",
+            });
+        }
+        Ok(ChunkedWriter {
+            store,
+            original_filename: original_filename.into(),
+            chunk_size,
+            hash,
+            current: None,
+            chunks: Vec::new(),
+            finished: false,
+        })
+    }
+
+    // Bytes written so far.
+    pub fn written(&self) -> u64 {
+        let done: u64 = self.chunks.iter().map(|chunk| chunk.size).sum();
+        done + self.current.as_ref().map_or(0, |(_, _, len)| *len)
+    }
+
+    // Complete the last chunk and write the manifest, which is also returned.
+    pub fn finish(mut self) -> Result<Manifest> {
+        self.close_chunk()?;
+        let manifest = stream_manifest(
+            mem::take(&mut self.original_filename),
+            self.chunk_size,
+            self.hash,
+            self.store.compression(),
+            mem::take(&mut self.chunks),
+        );
+        self.store.write_info(&manifest)?;
+        self.finished = true;
+        Ok(manifest)
+    }
+
+    fn close_chunk(&mut self) -> Result<()> {
+        let Some((mut writer, hasher, size)) = self.current.take() else {
+            return Ok(());
+        };
+        let index = self.chunks.len();
+        let chunk_path = self.store.chunk_path(index);
+        writer.flush().at(&chunk_path)?;
+        self.store.finish_chunk(index, writer)?;
+        let hash = hasher.map(ChunkHasher::finish);
+        log_written(&chunk_path, size, hash.as_deref());
+        self.chunks.push(ChunkEntry {
+            name: self.store.new_chunk_name(index),
+            size,
+            hash,
+            stored_hash: None,
+            compression: None,
+            same_as: None,
+            shared: None,
+            mtime: None,
+        });
+        Ok(())
+    }
+}
+
+impl<S: ChunkStore> Write for ChunkedWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.current.is_none() {
+            let writer = self.store.create_chunk(self.chunks.len())?;
+            let hasher = self.hash.map(HashAlgorithm::hasher);
+            self.current = Some((writer, hasher, 0));
+        }
+        let (writer, hasher, size) = self.current.as_mut().unwrap();
+        let room = (self.chunk_size - *size).min(buf.len() as u64) as usize;
+        let written = writer.write(&buf[..room])?;
+        if let Some(hasher) = hasher {
+            hasher.update(&buf[..written]);
+        }
+        *size += written as u64;
+        if *size == self.chunk_size {
+            self.close_chunk()?;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some((writer, ..)) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<S: ChunkStore> Drop for ChunkedWriter<S> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let started = self.chunks.len() + usize::from(self.current.is_some());
+        drop(self.current.take());
+        for index in 0..started {
+            let _ = self.store.remove_chunk(index);
+        }
+    }
+}
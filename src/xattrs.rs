@@ -0,0 +1,116 @@
+// Extended attributes of a file split with `SplitOptions::xattrs`, kept in its
+// info.json and put back on the file reconstructed from it: Finder tags and the like on
+// macOS, user.* and security.* ones on Linux, read and written through the xattr crate,
+// following a symbolic link as splitting does. Values are kept as base64. Windows has
+// no such attributes to read.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use log::{debug, warn};
+
+use crate::armor;
+use crate::error::{PathContext, Result};
+
+// Values larger than this are left out, with a warning, rather than swell info.json;
+// Linux takes no more than this, where macOS keeps whole resource forks as attributes
+pub const MAX_XATTR_SIZE: usize = 64 << 10;
+
+pub(crate) const SUPPORTED: bool = xattr::SUPPORTED_PLATFORM;
+
+// The attributes of `path` by name. A file system without them has none to give.
+pub(crate) fn read(path: &Path) -> Result<BTreeMap<String, String>> {
+    let mut attributes = BTreeMap::new();
+    let names = match xattr::list_deref(path) {
+        Ok(names) => names,
+        Err(e) if unsupported(&e) => {
+            debug!(
+                "{} has no extended attributes to read: {}",
+                path.display(),
+                e
+            );
+            return Ok(attributes);
+        }
+        Err(e) => return Err(e).doing("listing the extended attributes of", path),
+    };
+    for name in names {
+        let Some(shown) = name.to_str() else {
+            warn!(
+                "skipping an extended attribute of {} whose name isn't UTF-8",
+                path.display()
+            );
+            continue;
+        };
+        match xattr::get_deref(path, &name) {
+            Ok(Some(value)) if value.len() <= MAX_XATTR_SIZE => {
+                let mut text = Vec::with_capacity(value.len().div_ceil(3) * 4);
+                armor::encode(&value, &mut text);
+                let text = String::from_utf8_lossy(&text).into_owned();
+                attributes.insert(shown.to_string(), text);
+            }
+            // Removed since it was listed
+            Ok(None) => {}
+            Ok(Some(_)) => warn!(
+                "skipping extended attribute {} of {}, which is larger than the {} bytes kept",
+                shown,
+                path.display(),
+                MAX_XATTR_SIZE
+            ),
+            Err(e) => warn!(
+                "skipping extended attribute {} of {}: {}",
+                shown,
+                path.display(),
+                e
+            ),
+        }
+    }
+    debug!(
+        "read {} extended attributes of {}",
+        attributes.len(),
+        path.display()
+    );
+    Ok(attributes)
+}
+
+// Set `attributes` on `path` one at a time, as far as they go: one that can't be set,
+// such as a security.* one without the privilege for it, is warned about and the rest
+// are still set. Returns how many were.
+pub(crate) fn restore(path: &Path, attributes: &BTreeMap<String, String>) -> usize {
+    let mut restored = 0;
+    for (name, value) in attributes {
+        let Some(value) = armor::decode(value) else {
+            warn!(
+                "not restoring extended attribute {} of {}: info.json has no base64 value for it",
+                name,
+                path.display()
+            );
+            continue;
+        };
+        match xattr::set_deref(path, name, &value) {
+            Ok(()) => restored += 1,
+            Err(e) => warn!(
+                "could not restore extended attribute {} of {}: {}",
+                name,
+                path.display(),
+                e
+            ),
+        }
+    }
+    debug!(
+        "restored {} of {} extended attributes of {}",
+        restored,
+        attributes.len(),
+        path.display()
+    );
+    restored
+}
+
+fn unsupported(e: &io::Error) -> bool {
+    // The same number on Linux, but not on macOS
+    #[cfg(unix)]
+    if e.raw_os_error() == Some(libc::ENOTSUP) || e.raw_os_error() == Some(libc::EOPNOTSUPP) {
+        return true;
+    }
+    e.kind() == io::ErrorKind::Unsupported
+}
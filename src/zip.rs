@@ -0,0 +1,331 @@
+// A chunk set kept in one zip archive rather than a directory, for transfers that only
+// take a single file. The archive is written with the `zip` crate: entries are stored,
+// not deflated, one after another as the split produces them, with the ZIP64
+// extensions when chunks can pass 4 GiB, and all dated the same so that the same split
+// makes the same archive. Any unzip extracts what this writes. Reading zips, whoever
+// wrote them, goes through `ArchiveStore`, from the central directory the crate lists.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use ::zip::write::SimpleFileOptions;
+use ::zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter};
+
+use crate::archive::{EntryReader, Member, Method};
+use crate::chunk_index;
+use crate::error::{PathContext, Result};
+use crate::manifest::{Compression, MANIFEST_NAME, Manifest};
+use crate::store::{ChunkStore, chunk_name};
+
+// What `split` names the archive after the input, with `--container zip`.
+pub const ZIP_EXTENSION: &str = "fsrchunks.zip";
+
+// A chunk set being written into a zip archive: `chunk000`, `chunk001`, … and
+// `info.json` at the top of it, as `split --container zip` writes them, chunk by chunk,
+// until `finish` adds the central directory that makes it an archive. Its entries can
+// be read back from then on.
+#[derive(Debug)]
+pub struct ZipStore {
+    path: PathBuf,
+    // The entries by name once the archive is complete, and their sizes as written
+    entries: BTreeMap<String, Member>,
+    sizes: BTreeMap<String, u64>,
+    // Chunk indices by the name of their entry
+    chunks: BTreeMap<usize, String>,
+    // While writing, the archive, which a chunk's writer has while it is written
+    writing: Option<ZipWriter<File>>,
+    options: SimpleFileOptions,
+}
+
+impl ZipStore {
+    // Start a new archive at `path`, which mustn't exist yet, for chunks of at most
+    // `chunk_size` bytes.
+    pub fn create(path: impl Into<PathBuf>, chunk_size: u64) -> Result<ZipStore> {
+        let path = path.into();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .at(&path)?;
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .last_modified_time(DateTime::default())
+            .large_file(chunk_size >= u64::from(u32::MAX));
+        Ok(ZipStore {
+            path,
+            entries: BTreeMap::new(),
+            sizes: BTreeMap::new(),
+            chunks: BTreeMap::new(),
+            writing: Some(ZipWriter::new(file)),
+            options,
+        })
+    }
+
+    fn entry_path(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+
+    // Start the entry `name` at the end of the archive being written.
+    fn start_entry(&mut self, name: &str) -> Result<EntryWriter> {
+        let path = self.entry_path(name);
+        let Some(mut zip) = self.writing.take() else {
+            return Err(read_only()).at(&path);
+        };
+        zip.start_file(name, self.options)
+            .map_err(io::Error::from)
+            .at(&path)?;
+        Ok(EntryWriter {
+            zip,
+            name: name.to_string(),
+            size: 0,
+        })
+    }
+
+    // Take the entry `writer` wrote into the archive; the crate fills in its local
+    // header once the next one starts or the archive is finished.
+    fn finish_entry(&mut self, writer: EntryWriter) -> Result<()> {
+        if let Some(index) = chunk_index(&writer.name).and_then(|i| usize::try_from(i).ok()) {
+            self.chunks.insert(index, writer.name.clone());
+        }
+        self.sizes.insert(writer.name, writer.size);
+        self.writing = Some(writer.zip);
+        Ok(())
+    }
+
+    // Write the central directory after the entries, which completes the archive, and
+    // return its size.
+    pub fn finish(&mut self) -> Result<u64> {
+        let Some(zip) = self.writing.take() else {
+            return Err(read_only()).at(&self.path);
+        };
+        let mut file = zip.finish().map_err(io::Error::from).at(&self.path)?;
+        file.sync_all().at(&self.path)?;
+        self.entries = read_members(&mut file)
+            .at(&self.path)?
+            .into_iter()
+            .collect();
+        Ok(file.metadata().at(&self.path)?.len())
+    }
+
+    // Read back the entry `name`, as it was written.
+    fn open_entry(&self, name: &str) -> Result<EntryReader> {
+        let path = self.entry_path(name);
+        if self.writing.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the archive is still being written",
+            ))
+            .at(&path);
+        }
+        let Some(member) = self.entries.get(name) else {
+            return Err(io::Error::from(io::ErrorKind::NotFound)).at(&path);
+        };
+        EntryReader::open(&self.path, member, Compression::None).at(&path)
+    }
+}
+
+// Writes an entry's data into the archive through the crate, which keeps its CRC.
+pub struct EntryWriter {
+    zip: ZipWriter<File>,
+    name: String,
+    size: u64,
+}
+
+impl Write for EntryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.zip.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.zip.flush()
+    }
+}
+
+impl ChunkStore for ZipStore {
+    type Writer = EntryWriter;
+    type Reader = EntryReader;
+
+    // Chunks go in one after another, so there is only ever one being written.
+    fn create_chunk(&mut self, index: usize) -> Result<EntryWriter> {
+        self.start_entry(&chunk_name(index, self.compression()))
+    }
+
+    fn finish_chunk(&mut self, _index: usize, writer: EntryWriter) -> Result<()> {
+        self.finish_entry(writer)
+    }
+
+    fn open_chunk(&self, index: usize) -> Result<EntryReader> {
+        match self.chunks.get(&index) {
+            Some(name) => self.open_entry(name),
+            None => Err(io::Error::from(io::ErrorKind::NotFound)).at(&self.chunk_path(index)),
+        }
+    }
+
+    fn chunk_len(&self, index: usize) -> Result<u64> {
+        match self.chunks.get(&index) {
+            Some(name) => Ok(self.sizes[name]),
+            None => Err(io::Error::from(io::ErrorKind::NotFound)).at(&self.chunk_path(index)),
+        }
+    }
+
+    // Entries can't be taken out of an archive without rewriting it.
+    fn remove_chunk(&mut self, index: usize) -> Result<()> {
+        Err(read_only()).at(&self.chunk_path(index))
+    }
+
+    fn list_chunks(&self) -> Result<Vec<usize>> {
+        Ok(self.chunks.keys().copied().collect())
+    }
+
+    // Only ever the manifest this store wrote, so its seal holds.
+    fn read_info(&self) -> Result<Option<Manifest>> {
+        if !self.sizes.contains_key(MANIFEST_NAME) {
+            return Ok(None);
+        }
+        let path = self.entry_path(MANIFEST_NAME);
+        let mut data = String::new();
+        self.open_entry(MANIFEST_NAME)?
+            .read_to_string(&mut data)
+            .at(&path)?;
+        Manifest::parse(data.as_bytes(), &path, false).map(Some)
+    }
+
+    fn write_info(&mut self, manifest: &Manifest) -> Result<()> {
+        let path = self.entry_path(MANIFEST_NAME);
+        let data = manifest.to_json().map_err(io::Error::other).at(&path)?;
+        let mut writer = self.start_entry(MANIFEST_NAME)?;
+        writer.write_all(data.as_bytes()).at(&path)?;
+        self.finish_entry(writer)
+    }
+
+    fn chunk_path(&self, index: usize) -> PathBuf {
+        match self.chunks.get(&index) {
+            Some(name) => self.entry_path(name),
+            None => self.entry_path(&chunk_name(index, self.compression())),
+        }
+    }
+}
+
+fn read_only() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the archive is not being written",
+    )
+}
+
+// Every entry but directories, by name, in the order the central directory lists them,
+// with where its data starts past the local header.
+pub(crate) fn read_members(file: &mut File) -> io::Result<Vec<(String, Member)>> {
+    let mut archive = ZipArchive::new(file)?;
+    let mut members = Vec::with_capacity(archive.len());
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index)?;
+        if entry.is_dir() {
+            continue;
+        }
+        // The crate names only the methods it was built to decode, and none are here
+        #[allow(deprecated)]
+        let method = match entry.compression().to_u16() {
+            _ if entry.encrypted() => Method::Encrypted,
+            0 => Method::Stored,
+            8 => Method::Deflated,
+            method => Method::Unsupported(method),
+        };
+        let member = Member {
+            offset: entry.data_start(),
+            size: entry.size(),
+            stored_size: entry.compressed_size(),
+            crc: Some(entry.crc32()),
+            method,
+        };
+        // Zips from Windows may use backslashes
+        let name = String::from_utf8_lossy(entry.name_raw()).replace('\\', "/");
+        members.push((name, member));
+    }
+    Ok(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::archive::ArchiveStore;
+    use crate::split::{Container, SplitOptions, split_file};
+    use crate::{CancelToken, ReconstructOptions, reconstruct};
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn a_split_into_a_zip_is_stored_entries_that_join_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.bin");
+        let archive = dir.path().join("input.fsrchunks.zip");
+        let data = data(10_000);
+        fs::write(&input, &data).unwrap();
+        let options = SplitOptions::builder(&input, &archive)
+            .chunk_size(4096)
+            .min_chunk_size(0)
+            .container(Container::Zip)
+            .build()
+            .unwrap();
+        split_file(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+
+        let mut zip = ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        let names: Vec<&str> = zip.file_names().collect();
+        assert_eq!(names.len(), 4);
+        for name in ["chunk000", "chunk001", "chunk002", MANIFEST_NAME] {
+            assert!(names.contains(&name), "{} is missing", name);
+        }
+        for index in 0..zip.len() {
+            let entry = zip.by_index(index).unwrap();
+            assert_eq!(entry.compression(), CompressionMethod::Stored);
+            assert_eq!(entry.last_modified(), Some(DateTime::default()));
+        }
+
+        let options = ReconstructOptions {
+            output: Some("joined.bin".to_string()),
+            ..ReconstructOptions::new(&archive)
+        };
+        let report = reconstruct(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+        assert_eq!(fs::read(report.output).unwrap(), data);
+    }
+
+    #[test]
+    fn zip64_entries_read_back_from_the_store_and_as_an_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("set.zip");
+        let mut store = ZipStore::create(&path, u64::from(u32::MAX)).unwrap();
+        let chunks = [data(3000), data(5000), Vec::new()];
+        for (index, chunk) in chunks.iter().enumerate() {
+            let mut writer = store.create_chunk(index).unwrap();
+            writer.write_all(chunk).unwrap();
+            store.finish_chunk(index, writer).unwrap();
+        }
+        assert!(store.open_chunk(0).is_err());
+        let size = store.finish().unwrap();
+        assert_eq!(size, fs::metadata(&path).unwrap().len());
+
+        let archive = ArchiveStore::open(&path, false).unwrap();
+        for (index, chunk) in chunks.iter().enumerate() {
+            for read_back in [
+                Box::new(store.open_chunk(index).unwrap()) as Box<dyn Read>,
+                Box::new(archive.open_chunk(index).unwrap()),
+            ] {
+                let mut read = Vec::new();
+                Read::take(read_back, 1 << 20)
+                    .read_to_end(&mut read)
+                    .unwrap();
+                assert_eq!(&read, chunk);
+            }
+            assert_eq!(store.chunk_len(index).unwrap(), chunk.len() as u64);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+// Zstandard (RFC 8878), through the zstd crate, for `Compression::Zstd`: a streaming
+// encoder with levels 1 to 22, optionally with long-distance matching over a 128 MiB
+// window as `zstd --long` has, and a streaming decoder for any stream, several frames
+// one after the other included. Frames we write carry their content checksum, so a
+// damaged chunk is caught as it is read, and read as InvalidData as gzip's are.
+//
+// The zstd feature builds the C library in; without it, sets compressed with zstd are
+// still recognised, but reading or writing their chunks fails as unsupported.
+
+use std::io::{self, BufRead, Read, Write};
+
+// What `--long` asks for, 2^27 bytes, as with `zstd --long`
+#[cfg(feature = "zstd")]
+const LONG_WINDOW_LOG: u32 = 27;
+// The largest window the decoder accepts, so chunks written by `zstd --long=31` read too
+#[cfg(feature = "zstd")]
+const MAX_WINDOW_LOG: u32 = 31;
+
+// Compresses everything written to it into a zstd frame on `inner`. `finish` writes
+// the end of the frame; dropping the encoder without it leaves a truncated one.
+pub(crate) struct ZstEncoder<W: Write> {
+    #[cfg(feature = "zstd")]
+    inner: zstd::stream::write::Encoder<'static, W>,
+    #[cfg(not(feature = "zstd"))]
+    inner: W,
+}
+
+impl<W: Write> ZstEncoder<W> {
+    #[cfg(feature = "zstd")]
+    pub fn new(inner: W, level: u32, long: bool) -> io::Result<ZstEncoder<W>> {
+        let level = level.clamp(1, 22) as i32;
+        let mut inner = zstd::stream::write::Encoder::new(inner, level)?;
+        inner.include_checksum(true)?;
+        if long {
+            inner.long_distance_matching(true)?;
+            inner.window_log(LONG_WINDOW_LOG)?;
+        }
+        Ok(ZstEncoder { inner })
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    pub fn new(inner: W, level: u32, long: bool) -> io::Result<ZstEncoder<W>> {
+        let _ = (inner, level, long);
+        Err(unsupported())
+    }
+
+    // End the frame and hand back the writer.
+    pub fn finish(self) -> io::Result<W> {
+        #[cfg(feature = "zstd")]
+        return self.inner.finish();
+        #[cfg(not(feature = "zstd"))]
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ZstEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Decompresses zstd frames from `inner`, checking the content checksum of those that
+// have one.
+pub(crate) struct ZstDecoder<R: BufRead> {
+    #[cfg(feature = "zstd")]
+    inner: zstd::stream::read::Decoder<'static, R>,
+    #[cfg(not(feature = "zstd"))]
+    inner: R,
+}
+
+impl<R: BufRead> ZstDecoder<R> {
+    #[cfg(feature = "zstd")]
+    pub fn new(inner: R) -> io::Result<ZstDecoder<R>> {
+        let mut inner = zstd::stream::read::Decoder::with_buffer(inner)?;
+        inner.window_log_max(MAX_WINDOW_LOG)?;
+        Ok(ZstDecoder { inner })
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    pub fn new(inner: R) -> io::Result<ZstDecoder<R>> {
+        let _ = inner;
+        Err(unsupported())
+    }
+}
+
+impl<R: BufRead> Read for ZstDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // The library's own errors, a bad frame or checksum, come as Other, and a frame
+        // cut short as an early end
+        self.inner.read(buf).map_err(|e| match e.kind() {
+            io::ErrorKind::Other | io::ErrorKind::UnexpectedEof => io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("corrupt zstd data: {}", e),
+            ),
+            _ => e,
+        })
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "zstd compression needs a build with the zstd feature",
+    )
+}
+
+#[cfg(all(test, feature = "zstd"))]
+mod tests {
+    use super::*;
+
+    fn compress(data: &[u8], level: u32, long: bool) -> Vec<u8> {
+        let mut encoder = ZstEncoder::new(Vec::new(), level, long).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        ZstDecoder::new(data)?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    fn sample() -> Vec<u8> {
+        (0..20_000u32)
+            .flat_map(|i| format!("line {} of {}\n", i % 977, i / 3).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_with_and_without_long() {
+        let data = sample();
+        for (level, long) in [(1, false), (3, false), (19, false), (3, true), (22, true)] {
+            let compressed = compress(&data, level, long);
+            assert!(compressed.len() < data.len() / 4);
+            assert_eq!(decompress(&compressed).unwrap(), data, "{} {}", level, long);
+            assert_eq!(compressed, compress(&data, level, long));
+        }
+        assert_eq!(decompress(&compress(b"", 3, false)).unwrap(), b"");
+    }
+
+    #[test]
+    fn reads_every_frame_of_a_stream() {
+        let mut joined = compress(b"first, ", 1, false);
+        joined.extend(compress(b"then second", 9, true));
+        assert_eq!(decompress(&joined).unwrap(), b"first, then second");
+    }
+
+    #[test]
+    fn damage_reads_as_invalid_data() {
+        let compressed = compress(&sample(), 3, false);
+        let mut flipped = compressed.clone();
+        let middle = flipped.len() / 2;
+        flipped[middle] ^= 0x40;
+        let truncated = &compressed[..compressed.len() / 2];
+        for damaged in [&flipped[..], truncated, b"not zstd at all"] {
+            let error = decompress(damaged).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", error);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+// What a split and a reconstruction allocate under a `max_memory` cap, counted by a
+// global allocator of this test binary's own, so that the cap is seen to hold for
+// everything the library allocates and not only for the buffers it knows of.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use reconstruct_large_file::manifest::{Compression, HashAlgorithm};
+use reconstruct_large_file::{
+    CancelToken, ReconstructOptions, SplitOptions, pipeline, reconstruct, split_file,
+};
+
+// The system allocator, keeping count of the bytes allocated now and at most.
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pointer = unsafe { System.alloc(layout) };
+        if !pointer.is_null() {
+            let now = ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(now, Ordering::SeqCst);
+        }
+        pointer
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(pointer, layout) };
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+// The count is of the whole process, so the tests take turns.
+static ONE_AT_A_TIME: Mutex<()> = Mutex::new(());
+
+// What running `f` allocated at most beyond what was allocated before it.
+fn peak_of<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATED.load(Ordering::SeqCst);
+    PEAK.store(before, Ordering::SeqCst);
+    let result = f();
+    (result, PEAK.load(Ordering::SeqCst).saturating_sub(before))
+}
+
+// Bytes that differ from block to block without being held anywhere whole.
+struct Generated {
+    left: u64,
+    state: u64,
+}
+
+impl Read for Generated {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.left as usize);
+        for byte in &mut buf[..len] {
+            self.state = self
+                .state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1);
+            *byte = (self.state >> 56) as u8;
+        }
+        self.left -= len as u64;
+        Ok(len)
+    }
+}
+
+fn generate(path: &Path, len: u64) {
+    let mut file = File::create(path).unwrap();
+    io::copy(
+        &mut Generated {
+            left: len,
+            state: 1,
+        },
+        &mut file,
+    )
+    .unwrap();
+}
+
+fn same_files(a: &Path, b: &Path) -> bool {
+    let (mut a, mut b) = (File::open(a).unwrap(), File::open(b).unwrap());
+    let (mut left, mut right) = (vec![0; 64 << 10], vec![0; 64 << 10]);
+    loop {
+        let read = a.read(&mut left).unwrap();
+        b.read_exact(&mut right[..read]).unwrap();
+        if left[..read] != right[..read] {
+            return false;
+        }
+        if read == 0 {
+            return b.read(&mut right).unwrap() == 0;
+        }
+    }
+}
+
+const LEN: u64 = 24 << 20;
+const CHUNK_SIZE: u64 = 8 << 20;
+
+// Split LEN bytes into hashed chunks of CHUNK_SIZE, and join them back: with 4 threads
+// and buffers of `buffer_size` as wanted, fitted to `max_memory` as the CLI does for
+// --max-memory, unless that is None. Returns the most each took.
+fn split_and_join(
+    max_memory: Option<u64>,
+    buffer_size: usize,
+    compression: Compression,
+) -> (usize, usize) {
+    let _turn = ONE_AT_A_TIME.lock().unwrap_or_else(|e| e.into_inner());
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    let chunks = dir.path().join("chunks");
+    generate(&input, LEN);
+    let (threads, buffer_size) = pipeline::fit(4, buffer_size, max_memory);
+
+    let options = SplitOptions::builder(&input, &chunks)
+        .chunk_size(CHUNK_SIZE)
+        .threads(threads)
+        .buffer_size(buffer_size)
+        .max_memory(max_memory)
+        .hash(Some(HashAlgorithm::Sha256))
+        .compression(compression)
+        .min_chunk_size(0)
+        .build()
+        .unwrap();
+    let (split, split_peak) = peak_of(|| split_file(&options, &mut |_| {}, &CancelToken::new()));
+    split.unwrap();
+
+    let options = ReconstructOptions {
+        output: Some("joined.bin".to_string()),
+        threads,
+        buffer_size,
+        max_memory,
+        ..ReconstructOptions::new(&chunks)
+    };
+    let (report, join_peak) = peak_of(|| reconstruct(&options, &mut |_| {}, &CancelToken::new()));
+    assert!(same_files(&input, &report.unwrap().output));
+    (split_peak, join_peak)
+}
+
+fn within(max_memory: u64, buffer_size: usize, compression: Compression) {
+    let (split, join) = split_and_join(Some(max_memory), buffer_size, compression);
+    assert!(split as u64 <= max_memory, "the split took {} bytes", split);
+    assert!(join as u64 <= max_memory, "the join took {} bytes", join);
+}
+
+#[test]
+fn the_count_sees_the_buffers_without_a_cap() {
+    let (split, _) = split_and_join(None, pipeline::DEFAULT_BUFFER_SIZE, Compression::None);
+    // 4 threads of 4 MiB buffers
+    assert!(split > 12 << 20, "the split took {} bytes", split);
+}
+
+#[test]
+fn one_thread_with_smaller_buffers_stays_within_a_small_cap() {
+    within(1 << 20, pipeline::DEFAULT_BUFFER_SIZE, Compression::None);
+}
+
+#[test]
+fn compressed_chunks_larger_than_the_cap_are_streamed_within_it() {
+    within(1 << 20, pipeline::DEFAULT_BUFFER_SIZE, Compression::Gzip);
+}
+
+#[test]
+fn fewer_threads_stream_compressed_chunks_that_cannot_be_read_ahead() {
+    // Two threads of 1 MiB buffers fit, with no room left for a chunk read ahead
+    within(7 << 20, 1 << 20, Compression::Gzip);
+}
@@ -0,0 +1,3061 @@
+// Splitting files and joining them back through the library, in temporary directories.
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use reconstruct_large_file::manifest::{Compression, HashAlgorithm, Manifest, Parity};
+use reconstruct_large_file::{
+    CancelToken, ChunkedWriter, Compat, MANIFEST_NAME, Normalization, ProgressEvent,
+    RechunkOptions, ReconstructOptions, Script, ShardDirs, SplitOptions, SplitOptionsBuilder,
+    SplitterError, heal, pack, rechunk, reconstruct, repair, self_extracting, split_file, unpack,
+    verify,
+};
+#[cfg(any(feature = "encrypt", feature = "age"))]
+use reconstruct_large_file::{ChunkKey, Encryption};
+use reconstruct_large_file::{ChunkSet, apply_remap, containing_set, diagnose, find_remap};
+#[cfg(feature = "encrypt")]
+use reconstruct_large_file::{Cipher, Kdf, KdfAlgorithm, RekeyOptions, rekey};
+use reconstruct_large_file::{
+    Doubt, ExportedManifest, SampleOptions, detect_foreign, export_manifest, import, plan_heal,
+    plan_rechunk, stats, verify_exported, verify_sample,
+};
+
+// Options added to a split's builder
+type Options = fn(SplitOptionsBuilder) -> SplitOptionsBuilder;
+
+// Bytes that differ from chunk to chunk, so chunks joined out of order don't match.
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len as u64)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+        .collect()
+}
+
+fn split(input: &Path, destination: &Path, chunk_size: u64) {
+    split_with(SplitOptions::builder(input, destination).chunk_size(chunk_size));
+}
+
+fn split_with(builder: SplitOptionsBuilder) {
+    let options = builder.min_chunk_size(0).build().unwrap();
+    split_file(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+}
+
+// The command-line binary run with `args` and nothing to read, as a script runs it.
+fn cli<S: AsRef<OsStr>>(args: impl IntoIterator<Item = S>) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_reconstruct_large_file"))
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+// Every file under `directory` by its path in it, with what it holds.
+fn contents(directory: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    let mut files = BTreeMap::new();
+    let mut pending = vec![directory.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                let name = path.strip_prefix(directory).unwrap().to_path_buf();
+                files.insert(name, fs::read(&path).unwrap());
+            }
+        }
+    }
+    files
+}
+
+fn rebuild(directory: &Path, output: &str, threads: usize) -> Vec<u8> {
+    let options = ReconstructOptions {
+        output: Some(output.to_string()),
+        threads,
+        ..ReconstructOptions::new(directory)
+    };
+    let report = reconstruct(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+    fs::read(report.output).unwrap()
+}
+
+#[test]
+fn more_than_a_thousand_chunks_join_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    let chunks = dir.path().join("chunks");
+    // 1203 chunks, so the names go past chunk999 and the last is short
+    let data = pattern(1202 * 16 + 5);
+    fs::write(&input, &data).unwrap();
+    split(&input, &chunks, 16);
+    assert!(chunks.join("chunk1000").exists());
+    assert!(chunks.join("chunk1202").exists());
+
+    assert_eq!(rebuild(&chunks, "joined", 1), data);
+    assert_eq!(rebuild(&chunks, "joined-parallel", 4), data);
+
+    // Without info.json the order only comes from the numbers in the names
+    fs::remove_file(dir.path().join("chunks/joined")).unwrap();
+    fs::remove_file(dir.path().join("chunks/joined-parallel")).unwrap();
+    fs::remove_file(chunks.join(MANIFEST_NAME)).unwrap();
+    assert_eq!(rebuild(&chunks, "scanned", 1), data);
+}
+
+#[test]
+fn splits_are_reproducible() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    let mut data = pattern(300_000);
+    // Something that compresses, so gzip stores some chunks compressed
+    data.extend(std::iter::repeat_n(b'a', 200_000));
+    fs::write(&input, &data).unwrap();
+    let mut cases: Vec<(&str, Options)> = vec![
+        ("plain", |builder| builder),
+        ("hashed", |builder| {
+            builder
+                .hash(Some(HashAlgorithm::Sha256))
+                .parity(Some(Parity::ReedSolomon { shards: 2 }))
+        }),
+        ("gzip", |builder| {
+            builder
+                .hash(Some(HashAlgorithm::Sha256))
+                .compression(Compression::Gzip)
+        }),
+    ];
+    if cfg!(feature = "zstd") {
+        cases.push(("zstd", |builder| {
+            builder.compression(Compression::Zstd {
+                level: 19,
+                long: true,
+            })
+        }));
+    }
+    for (name, options) in cases {
+        // One thread and several, into directories at different depths
+        let first = dir.path().join(format!("{}-1", name));
+        let second = dir.path().join(format!("more/{}-4", name));
+        split_with(options(SplitOptions::builder(&input, &first).chunk_size(40_000)).threads(1));
+        split_with(options(SplitOptions::builder(&input, &second).chunk_size(40_000)).threads(4));
+        let (first, second) = (contents(&first), contents(&second));
+        assert!(first.contains_key(Path::new(MANIFEST_NAME)), "{}", name);
+        assert_eq!(
+            first.keys().collect::<Vec<_>>(),
+            second.keys().collect::<Vec<_>>(),
+            "{}",
+            name
+        );
+        for (path, bytes) in &first {
+            assert!(
+                second[path] == *bytes,
+                "{}: {} differs",
+                name,
+                path.display()
+            );
+        }
+    }
+}
+
+#[test]
+fn ranges_past_4_gib_split_from_where_they_start() {
+    use std::io::{Seek, SeekFrom, Write};
+
+    const GIB: u64 = 1 << 30;
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("sparse.img");
+    // A file of 6 GiB that takes no room but the bytes written just past 5 GiB
+    let mut file = fs::File::create(&input).unwrap();
+    file.set_len(6 * GIB).unwrap();
+    let data = pattern(300);
+    file.seek(SeekFrom::Start(5 * GIB + 100)).unwrap();
+    file.write_all(&data).unwrap();
+    drop(file);
+
+    let chunks = temp.path().join("chunks");
+    split_with(
+        SplitOptions::builder(&input, &chunks)
+            .chunk_size(128)
+            .input_offset(5 * GIB)
+            .input_length(Some(500)),
+    );
+    let mut expected = vec![0; 100];
+    expected.extend(&data);
+    expected.resize(500, 0);
+    assert_eq!(rebuild(&chunks, "range.img", 1), expected);
+}
+
+// The names in `directory` of the temporary files `scratch` writes, which starts them
+// with a dot and ends them in .part.
+fn temporaries(directory: &Path) -> Vec<String> {
+    fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with('.') && name.ends_with(".part"))
+        .collect()
+}
+
+// A progress callback that cancels `cancel` once the first chunk is done.
+fn cancel_after_first(cancel: &CancelToken) -> impl FnMut(ProgressEvent) + use<> {
+    let cancel = cancel.clone();
+    move |event| {
+        if let ProgressEvent::ChunkFinished { .. } = event {
+            cancel.cancel();
+        }
+    }
+}
+
+#[test]
+fn a_cancelled_reconstruction_on_one_thread_leaves_the_old_output() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(1000)).unwrap();
+    let chunks = temp.path().join("chunks");
+    split(&input, &chunks, 100);
+    let output = temp.path().join("output.bin");
+    fs::write(&output, b"the old output").unwrap();
+
+    let cancel = CancelToken::new();
+    let options = ReconstructOptions {
+        output: Some(output.to_string_lossy().into_owned()),
+        threads: 1,
+        ..ReconstructOptions::new(&chunks)
+    };
+    let result = reconstruct(&options, &mut cancel_after_first(&cancel), &cancel);
+    assert!(
+        matches!(result, Err(SplitterError::Cancelled)),
+        "{:?}",
+        result
+    );
+    assert_eq!(fs::read(&output).unwrap(), b"the old output");
+    assert_eq!(temporaries(temp.path()), Vec::<String>::new());
+
+    // Put in place in one go once complete
+    assert_eq!(rebuild(&chunks, output.to_str().unwrap(), 1), pattern(1000));
+    assert_eq!(temporaries(temp.path()), Vec::<String>::new());
+}
+
+#[test]
+fn a_cancelled_rechunk_leaves_no_temporary_chunk() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(1000)).unwrap();
+    let chunks = temp.path().join("chunks");
+    split(&input, &chunks, 100);
+    let destination = temp.path().join("rechunked");
+    fs::create_dir(&destination).unwrap();
+    let options = RechunkOptions {
+        min_chunk_size: 0,
+        ..RechunkOptions::new(&chunks, &destination, 300)
+    };
+
+    let cancel = CancelToken::new();
+    let result = rechunk(&options, &mut cancel_after_first(&cancel), &cancel);
+    assert!(
+        matches!(result, Err(SplitterError::Cancelled)),
+        "{:?}",
+        result
+    );
+    assert_eq!(contents(&destination), BTreeMap::new());
+
+    let report = rechunk(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert_eq!(report.chunks.len(), 4);
+    assert_eq!(temporaries(&destination), Vec::<String>::new());
+    assert_eq!(rebuild(&destination, "again.bin", 1), pattern(1000));
+}
+
+#[test]
+fn a_rechunked_set_joins_back_to_the_same_bytes() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let data = pattern(10_000);
+    fs::write(&input, &data).unwrap();
+    let cases: [(Options, u64, Option<Compression>); 5] = [
+        (|builder| builder, 3000, None),
+        (|builder| builder, 700, None),
+        (|builder| builder.compression(Compression::Gzip), 2500, None),
+        (
+            |builder| builder.compression(Compression::Gzip),
+            4096,
+            Some(Compression::None),
+        ),
+        (
+            |builder| builder.parity(Some(Parity::ReedSolomon { shards: 2 })),
+            3333,
+            None,
+        ),
+    ];
+    for (number, (options, size, compression)) in cases.into_iter().enumerate() {
+        let chunks = temp.path().join(format!("chunks{}", number));
+        split_with(options(
+            SplitOptions::builder(&input, &chunks)
+                .chunk_size(1000)
+                .hash(Some(HashAlgorithm::Sha256)),
+        ));
+        let source = Manifest::load(&chunks, false).unwrap().unwrap();
+        let destination = temp.path().join(format!("rechunked{}", number));
+        let options = RechunkOptions {
+            min_chunk_size: 0,
+            compression,
+            ..RechunkOptions::new(&chunks, &destination, size)
+        };
+        let report = rechunk(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+        assert_eq!(report.chunks.len(), data.len().div_ceil(size as usize));
+
+        let manifest = Manifest::load(&destination, false).unwrap().unwrap();
+        assert_eq!(manifest.chunk_size, Some(size));
+        assert_eq!(manifest.original_filename, source.original_filename);
+        assert_eq!(manifest.hash, Some(HashAlgorithm::Sha256));
+        assert_eq!(
+            manifest.compression,
+            compression.unwrap_or(source.compression)
+        );
+        // The parity was for the old chunks; the new set has none
+        assert!(manifest.parity.is_none());
+        assert!(
+            contents(&destination)
+                .keys()
+                .all(|name| !name.to_string_lossy().starts_with("parity"))
+        );
+        assert!(
+            verify(&destination, &[], false, &mut |_| {}, &CancelToken::new())
+                .unwrap()
+                .is_ok()
+        );
+        assert_eq!(rebuild(&destination, "joined.bin", 1), data, "{}", number);
+    }
+}
+
+#[test]
+fn pack_puts_the_tar_in_place_once_complete() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(1000)).unwrap();
+    let chunks = temp.path().join("chunks");
+    split(&input, &chunks, 100);
+    let archive = temp.path().join("set.tar");
+
+    let cancel = CancelToken::new();
+    let result = pack(
+        &chunks,
+        &archive,
+        None,
+        false,
+        &mut cancel_after_first(&cancel),
+        &cancel,
+    );
+    assert!(
+        matches!(result, Err(SplitterError::Cancelled)),
+        "{:?}",
+        result
+    );
+    assert!(!archive.exists());
+    assert_eq!(temporaries(temp.path()), Vec::<String>::new());
+
+    pack(
+        &chunks,
+        &archive,
+        None,
+        false,
+        &mut |_| {},
+        &CancelToken::new(),
+    )
+    .unwrap();
+    assert_eq!(temporaries(temp.path()), Vec::<String>::new());
+    assert_eq!(rebuild(&archive, "from-tar.bin", 1), pattern(1000));
+    // It is never written over
+    let again = pack(
+        &chunks,
+        &archive,
+        None,
+        false,
+        &mut |_| {},
+        &CancelToken::new(),
+    );
+    assert!(
+        matches!(&again, Err(SplitterError::Io { path, source, .. })
+            if *path == archive && source.kind() == io::ErrorKind::AlreadyExists),
+        "{:?}",
+        again
+    );
+}
+
+// A set packed into a tar and unpacked again is the set it was, file for file and byte
+// for byte, and the tar reconstructs as it is.
+#[test]
+fn a_packed_set_unpacks_to_the_same_files_and_joins_from_the_tar() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let data = pattern(9 * 4096 + 1000);
+    fs::write(&input, &data).unwrap();
+    let chunks = temp.path().join("chunks");
+    split_with(
+        SplitOptions::builder(&input, &chunks)
+            .chunk_size(4096)
+            .hash(Some(HashAlgorithm::Sha256))
+            .compression(Compression::Gzip)
+            .min_ratio(0.0)
+            .parity(Some(Parity::Xor)),
+    );
+    let archive = temp.path().join("set.tar");
+    let cancel = CancelToken::new();
+    let packed = pack(&chunks, &archive, None, false, &mut |_| {}, &cancel).unwrap();
+    let set = contents(&chunks);
+    assert_eq!(packed.files.len(), set.len());
+
+    let unpacked = temp.path().join("unpacked");
+    unpack(&archive, &unpacked, false, &mut |_| {}, &cancel).unwrap();
+    let again = contents(&unpacked);
+    assert_eq!(
+        again.keys().collect::<Vec<_>>(),
+        set.keys().collect::<Vec<_>>()
+    );
+    for (name, bytes) in &set {
+        assert!(again[name] == *bytes, "{} differs", name.display());
+    }
+    assert!(set.contains_key(Path::new(MANIFEST_NAME)));
+
+    assert_eq!(rebuild(&archive, "from-tar.bin", 2), data);
+    assert_eq!(rebuild(&unpacked, "joined.bin", 1), data);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_chunks_join_and_say_how_they_were_compressed() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let mut data = pattern(100_000);
+    data.extend(std::iter::repeat_n(b'z', 300_000));
+    fs::write(&input, &data).unwrap();
+    let chunks = temp.path().join("chunks");
+    split_with(
+        SplitOptions::builder(&input, &chunks)
+            .chunk_size(64 * 1024)
+            .compression(Compression::ZSTD)
+            .compression_level(7)
+            .min_ratio(0.0),
+    );
+    let names: Vec<_> = contents(&chunks).into_keys().collect();
+    assert_eq!(names.first().unwrap(), Path::new("chunk000.zst"));
+    let manifest = fs::read_to_string(chunks.join(MANIFEST_NAME)).unwrap();
+    assert!(
+        manifest.contains(r#""compression":{"zstd":{"level":7,"long":false}}"#),
+        "{}",
+        manifest
+    );
+    assert_eq!(rebuild(&chunks, "joined.bin", 1), data);
+    assert_eq!(rebuild(&chunks, "joined4.bin", 4), data);
+}
+
+#[test]
+fn parity_rebuilds_lost_chunks() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    // Ten chunks, the last one short
+    let data = pattern(9 * 4096 + 1000);
+    fs::write(&input, &data).unwrap();
+    let cases = [
+        (Parity::Xor, vec!["chunk006"]),
+        (Parity::ReedSolomon { shards: 1 }, vec!["chunk009"]),
+        (
+            Parity::ReedSolomon { shards: 3 },
+            vec!["chunk000", "chunk004", "chunk009"],
+        ),
+        (
+            Parity::ReedSolomon { shards: 3 },
+            vec!["chunk002", "parity001"],
+        ),
+    ];
+    for (number, (scheme, lost)) in cases.into_iter().enumerate() {
+        let chunks = temp.path().join(format!("chunks{}", number));
+        split_with(
+            SplitOptions::builder(&input, &chunks)
+                .chunk_size(4096)
+                .hash(Some(HashAlgorithm::Sha256))
+                .parity(Some(scheme)),
+        );
+        let before = contents(&chunks);
+        for name in &lost {
+            fs::remove_file(chunks.join(name)).unwrap();
+        }
+        // A damaged chunk too, where that still leaves enough parity
+        if lost.len() < scheme.shards() {
+            let path = chunks.join("chunk001");
+            let mut bytes = fs::read(&path).unwrap();
+            bytes[100] ^= 1;
+            fs::write(&path, bytes).unwrap();
+        }
+        let report = repair(&chunks, false, false, &mut |_| {}, &CancelToken::new())
+            .unwrap_or_else(|e| panic!("{:?}: {}", scheme, e));
+        assert!(!report.rebuilt.is_empty(), "{:?}", scheme);
+        assert_eq!(contents(&chunks), before, "{:?}", scheme);
+        assert_eq!(rebuild(&chunks, "joined.bin", 1), data, "{:?}", scheme);
+    }
+}
+
+#[test]
+fn xor_parity_mends_a_flipped_byte() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let data = pattern(5 * 4096 + 700);
+    fs::write(&input, &data).unwrap();
+    let chunks = temp.path().join("chunks");
+    split_with(
+        SplitOptions::builder(&input, &chunks)
+            .chunk_size(4096)
+            .hash(Some(HashAlgorithm::Sha256))
+            .parity(Some(Parity::Xor)),
+    );
+    let before = contents(&chunks);
+    let path = chunks.join("chunk003");
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[2000] ^= 0x40;
+    fs::write(&path, bytes).unwrap();
+
+    let report = repair(&chunks, false, false, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert_eq!(report.rebuilt, ["chunk003"]);
+    assert!(report.parity.is_empty());
+    assert_eq!(contents(&chunks), before);
+}
+
+#[test]
+fn repair_refuses_more_losses_than_the_parity_covers() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let data = pattern(9 * 4096 + 1000);
+    fs::write(&input, &data).unwrap();
+    let cases = [
+        (Parity::Xor, vec!["chunk002", "chunk007"]),
+        (
+            Parity::ReedSolomon { shards: 3 },
+            vec!["chunk000", "chunk003", "chunk004", "chunk009"],
+        ),
+        (
+            Parity::ReedSolomon { shards: 3 },
+            vec!["chunk005", "parity000", "parity001", "parity002"],
+        ),
+    ];
+    for (number, (scheme, lost)) in cases.into_iter().enumerate() {
+        let chunks = temp.path().join(format!("chunks{}", number));
+        split_with(
+            SplitOptions::builder(&input, &chunks)
+                .chunk_size(4096)
+                .hash(Some(HashAlgorithm::Sha256))
+                .parity(Some(scheme)),
+        );
+        for name in &lost {
+            fs::remove_file(chunks.join(name)).unwrap();
+        }
+        let before = contents(&chunks);
+        let error = repair(&chunks, false, false, &mut |_| {}, &CancelToken::new()).unwrap_err();
+        let lost: Vec<u64> = lost
+            .iter()
+            .filter_map(|name| name.strip_prefix("chunk"))
+            .map(|number| number.parse().unwrap())
+            .collect();
+        assert!(
+            matches!(&error, SplitterError::MissingChunks { indices } if *indices == lost),
+            "{:?}: {:?}",
+            scheme,
+            error
+        );
+        // Nothing half rebuilt is left behind
+        assert_eq!(contents(&chunks), before, "{:?}", scheme);
+    }
+}
+
+#[test]
+fn heal_takes_each_chunk_from_a_copy_that_has_it_intact() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let data = pattern(6 * 4096 + 300);
+    fs::write(&input, &data).unwrap();
+    let primary = temp.path().join("primary");
+    let mirror = temp.path().join("mirror");
+    let spare = temp.path().join("spare");
+    for directory in [&primary, &mirror, &spare] {
+        split_with(
+            SplitOptions::builder(&input, directory)
+                .chunk_size(4096)
+                .hash(Some(HashAlgorithm::Sha256)),
+        );
+    }
+    let before = contents(&primary);
+    let flip = |path: PathBuf| {
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[10] ^= 1;
+        fs::write(&path, bytes).unwrap();
+    };
+    // Each copy is damaged somewhere else; chunk004 is only intact in the spare
+    fs::remove_file(primary.join("chunk001")).unwrap();
+    flip(primary.join("chunk004"));
+    flip(mirror.join("chunk000"));
+    fs::remove_file(mirror.join("chunk004")).unwrap();
+
+    let sources = [mirror.clone(), spare.clone()];
+    let report = heal(&primary, &sources, false, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert!(report.is_ok(), "{:?}", report.damaged);
+    let healed: Vec<_> = report
+        .healed
+        .iter()
+        .map(|chunk| (chunk.name.as_str(), chunk.source.as_path()))
+        .collect();
+    assert_eq!(
+        healed,
+        [
+            ("chunk001", mirror.as_path()),
+            ("chunk004", spare.as_path())
+        ]
+    );
+    assert_eq!(contents(&primary), before);
+    assert_eq!(rebuild(&primary, "joined.bin", 1), data);
+
+    // A copy of some other file is refused before anything is touched
+    let other = temp.path().join("other.bin");
+    fs::write(&other, pattern(6 * 4096 + 301)).unwrap();
+    let stranger = temp.path().join("stranger");
+    split_with(
+        SplitOptions::builder(&other, &stranger)
+            .chunk_size(4096)
+            .hash(Some(HashAlgorithm::Sha256)),
+    );
+    fs::remove_file(primary.join("chunk002")).unwrap();
+    let error = heal(
+        &primary,
+        &[stranger],
+        false,
+        &mut |_| {},
+        &CancelToken::new(),
+    )
+    .unwrap_err();
+    assert!(
+        matches!(error, SplitterError::ManifestMismatch { .. }),
+        "{:?}",
+        error
+    );
+    assert!(!primary.join("chunk002").exists());
+}
+
+#[test]
+fn a_decomposed_name_comes_back_in_the_form_asked_for() {
+    let temp = tempfile::tempdir().unwrap();
+    let (composed, decomposed) = ("r\u{e9}sum\u{e9}.bin", "re\u{301}sume\u{301}.bin");
+    let input = temp.path().join(decomposed);
+    fs::write(&input, pattern(5000)).unwrap();
+    let chunks = temp.path().join("chunks");
+    split(&input, &chunks, 2048);
+    let manifest = fs::read_to_string(chunks.join(MANIFEST_NAME)).unwrap();
+    assert!(manifest.contains(r#""name_form":"nfd""#), "{}", manifest);
+
+    let join = |normalize| {
+        let options = ReconstructOptions {
+            normalize,
+            ..ReconstructOptions::new(&chunks)
+        };
+        reconstruct(&options, &mut |_| {}, &CancelToken::new())
+            .unwrap()
+            .output
+    };
+    let output = join(Normalization::Nfc);
+    assert_eq!(output.file_name().unwrap(), composed);
+    assert_eq!(fs::read(&output).unwrap(), pattern(5000));
+    // Joined again as recorded, it writes over that one rather than beside it
+    assert_eq!(join(Normalization::Keep), output);
+    assert_eq!(join(Normalization::Nfd), output);
+    let outputs: Vec<_> = contents(&chunks)
+        .into_keys()
+        .filter(|name| name.extension().is_some_and(|ext| ext == "bin"))
+        .collect();
+    assert_eq!(outputs, [Path::new(composed)]);
+}
+
+#[test]
+fn a_recorded_name_that_leads_elsewhere_is_never_written() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(1000)).unwrap();
+    let chunks = temp.path().join("sets/chunks");
+    split(&input, &chunks, 100);
+    let escape = temp.path().join("sets/escape.bin");
+    let absolute = temp.path().join("absolute.bin");
+    for name in ["../escape.bin".to_string(), absolute.display().to_string()] {
+        // As a set received from elsewhere would have it, without a seal to break
+        let path = chunks.join(MANIFEST_NAME);
+        let mut manifest: serde_json::Value =
+            serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        manifest["original_filename"] = name.clone().into();
+        manifest.as_object_mut().unwrap().remove("meta_checksum");
+        fs::write(&path, manifest.to_string()).unwrap();
+
+        let options = ReconstructOptions::new(&chunks);
+        let result = reconstruct(&options, &mut |_| {}, &CancelToken::new());
+        assert!(
+            matches!(&result, Err(SplitterError::UnsafeName { name: refused, .. }) if *refused == name),
+            "{:?}",
+            result
+        );
+        assert!(!escape.exists() && !absolute.exists());
+    }
+}
+
+#[test]
+fn a_set_named_like_its_lock_or_trash_is_put_back_outside_it() {
+    for name in [".fsr.lock", ".fsr-trash"] {
+        let temp = tempfile::tempdir().unwrap();
+        let input = temp.path().join("input.bin");
+        fs::write(&input, pattern(1000)).unwrap();
+        let chunks = temp.path().join("chunks");
+        split(&input, &chunks, 100);
+        let path = chunks.join(MANIFEST_NAME);
+        let mut manifest: serde_json::Value =
+            serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        manifest["original_filename"] = name.into();
+        manifest.as_object_mut().unwrap().remove("meta_checksum");
+        fs::write(&path, manifest.to_string()).unwrap();
+
+        let options = ReconstructOptions::new(&chunks);
+        let report = reconstruct(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+        assert_eq!(
+            report.output,
+            fs::canonicalize(temp.path()).unwrap().join(name)
+        );
+        assert!(!chunks.join(name).exists(), "{}", name);
+        // Which would otherwise leave the set locked for good
+        verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+    }
+}
+
+#[test]
+fn a_directory_is_not_split() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("folder");
+    fs::create_dir(&input).unwrap();
+    let options = SplitOptions::builder(&input, temp.path().join("chunks"))
+        .build()
+        .unwrap();
+    let result = split_file(&options, &mut |_| {}, &CancelToken::new());
+    assert!(
+        matches!(&result, Err(SplitterError::NotAFile { path }) if *path == input),
+        "{:?}",
+        result
+    );
+    assert!(matches!(
+        options.input_len(),
+        Err(SplitterError::NotAFile { .. })
+    ));
+    assert!(!temp.path().join("chunks").exists());
+}
+
+// A key from `passphrase` through an Argon2id far too cheap for anything but tests.
+#[cfg(feature = "encrypt")]
+fn cheap_key(passphrase: &str) -> (Encryption, ChunkKey) {
+    let encryption = Encryption {
+        cipher: Cipher::Aes256Gcm,
+        kdf: Some(Kdf {
+            algorithm: KdfAlgorithm::Argon2id,
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+            salt: "5a".repeat(16),
+        }),
+        fingerprint: None,
+        recipients: Vec::new(),
+    };
+    let key = ChunkKey::derive(passphrase, &encryption).unwrap();
+    (encryption, key)
+}
+
+#[cfg(feature = "encrypt")]
+#[test]
+fn encrypted_chunks_join_with_their_key_and_verify_without_it() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let data = pattern(3 * 4096 + 100);
+    fs::write(&input, &data).unwrap();
+    for (name, compression) in [("plain", Compression::None), ("gzip", Compression::Gzip)] {
+        let chunks = temp.path().join(name);
+        let (encryption, key) = cheap_key("correct horse");
+        split_with(
+            SplitOptions::builder(&input, &chunks)
+                .chunk_size(4096)
+                .hash(Some(HashAlgorithm::Sha256))
+                .compression(compression)
+                .min_ratio(0.0)
+                .encryption(Some(encryption), Some(key.clone())),
+        );
+        let first = fs::read(chunks.join(match name {
+            "plain" => "chunk000",
+            _ => "chunk000.gz",
+        }))
+        .unwrap();
+        assert!(!first.windows(32).any(|window| window == &data[..32]));
+
+        let report = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+        assert!(report.mismatched.is_empty(), "{:?}", report.mismatched);
+        assert!(report.health.missing.is_empty() && report.health.uneven.is_empty());
+
+        for threads in [1, 4] {
+            let options = ReconstructOptions {
+                output: Some(format!("{}{}.bin", name, threads)),
+                threads,
+                key: Some(key.clone()),
+                ..ReconstructOptions::new(&chunks)
+            };
+            let report = reconstruct(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+            assert_eq!(fs::read(report.output).unwrap(), data);
+        }
+        let without = reconstruct(
+            &ReconstructOptions::new(&chunks),
+            &mut |_| {},
+            &CancelToken::new(),
+        );
+        assert!(matches!(without, Err(SplitterError::Encrypted { .. })));
+
+        // A wrong passphrase is caught before anything is written
+        let options = ReconstructOptions {
+            output: Some(format!("{}-wrong.bin", name)),
+            key: Some(cheap_key("battery staple").1),
+            ..ReconstructOptions::new(&chunks)
+        };
+        let wrong = reconstruct(&options, &mut |_| {}, &CancelToken::new());
+        assert!(
+            matches!(wrong, Err(SplitterError::WrongKey { .. })),
+            "{:?}",
+            wrong
+        );
+        assert!(!chunks.join(format!("{}-wrong.bin", name)).exists());
+    }
+}
+
+#[cfg(feature = "encrypt")]
+#[test]
+fn a_changed_encrypted_chunk_fails_verify_without_the_key() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(3 * 4096)).unwrap();
+    let chunks = temp.path().join("chunks");
+    let (encryption, key) = cheap_key("correct horse");
+    split_with(
+        SplitOptions::builder(&input, &chunks)
+            .chunk_size(4096)
+            .encryption(Some(encryption), Some(key.clone())),
+    );
+    let path = chunks.join("chunk001");
+    let mut chunk = fs::read(&path).unwrap();
+    chunk[100] ^= 1;
+    fs::write(&path, &chunk).unwrap();
+    let report = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert_eq!(report.mismatched, ["chunk001"]);
+
+    let options = ReconstructOptions {
+        output: Some("joined.bin".to_string()),
+        key: Some(key),
+        ..ReconstructOptions::new(&chunks)
+    };
+    let joined = reconstruct(&options, &mut |_| {}, &CancelToken::new());
+    assert!(matches!(joined, Err(SplitterError::Undecryptable { path: failed }) if failed == path));
+    // Nothing of the chunks before it is left behind either
+    assert!(!chunks.join("joined.bin").exists());
+}
+
+// With --encrypt-metadata nothing in info.json gives the file away, and verify still
+// checks every chunk as stored.
+#[cfg(feature = "encrypt")]
+#[test]
+fn encrypted_metadata_hides_the_details_but_not_the_chunks() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("secret-plans.bin");
+    let data = pattern(3 * 4096 + 100);
+    fs::write(&input, &data).unwrap();
+    for (name, compression) in [("plain", Compression::None), ("gzip", Compression::Gzip)] {
+        let chunks = temp.path().join(name);
+        let (encryption, key) = cheap_key("correct horse");
+        split_with(
+            SplitOptions::builder(&input, &chunks)
+                .chunk_size(4096)
+                .hash(Some(HashAlgorithm::Sha256))
+                .compression(compression)
+                .min_ratio(0.0)
+                .encryption(Some(encryption), Some(key.clone()))
+                .encrypt_metadata(true),
+        );
+        let info = fs::read_to_string(chunks.join(MANIFEST_NAME)).unwrap();
+        assert!(!info.contains("secret-plans"), "{}", info);
+        assert!(!info.contains("\"hash\""), "{}", info);
+        assert!(!info.contains(&(data.len()).to_string()), "{}", info);
+        let manifest = Manifest::load(&chunks, false).unwrap().unwrap();
+        assert!(manifest.concealed());
+        assert_eq!(manifest.chunks.len(), 4);
+        assert!(matches!(
+            manifest.output_name(),
+            Err(SplitterError::Encrypted { .. })
+        ));
+
+        let report = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+        assert!(report.mismatched.is_empty(), "{:?}", report.mismatched);
+        assert!(report.health.missing.is_empty() && report.health.uneven.is_empty());
+
+        let without = reconstruct(
+            &ReconstructOptions::new(&chunks),
+            &mut |_| {},
+            &CancelToken::new(),
+        );
+        assert!(matches!(without, Err(SplitterError::Encrypted { .. })));
+        let wrong = ReconstructOptions {
+            key: Some(cheap_key("battery staple").1),
+            ..ReconstructOptions::new(&chunks)
+        };
+        let wrong = reconstruct(&wrong, &mut |_| {}, &CancelToken::new());
+        assert!(
+            matches!(wrong, Err(SplitterError::WrongKey { .. })),
+            "{:?}",
+            wrong
+        );
+
+        // The key opens the recorded name along with the chunks
+        let revealed = Manifest::load_revealed(&chunks, false, Some(&key))
+            .unwrap()
+            .unwrap();
+        assert_eq!(revealed.original_filename, "secret-plans.bin");
+        assert_eq!(revealed.hash, Some(HashAlgorithm::Sha256));
+        let options = ReconstructOptions {
+            key: Some(key),
+            ..ReconstructOptions::new(&chunks)
+        };
+        let report = reconstruct(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+        assert_eq!(report.output, chunks.join("secret-plans.bin"));
+        assert_eq!(fs::read(&report.output).unwrap(), data);
+        fs::remove_file(&report.output).unwrap();
+
+        // A changed chunk is still caught without the key
+        let path = chunks.join(match name {
+            "plain" => "chunk002",
+            _ => "chunk002.gz",
+        });
+        let mut chunk = fs::read(&path).unwrap();
+        chunk[30] ^= 1;
+        fs::write(&path, &chunk).unwrap();
+        let report = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+        assert_eq!(report.mismatched.len(), 1, "{:?}", report.mismatched);
+    }
+}
+
+// A set encrypted under a key file is laid out as one under a passphrase, and only its
+// info.json says which it needs.
+#[cfg(feature = "encrypt")]
+#[test]
+fn key_file_and_passphrase_sets_differ_only_in_how_the_key_is_had() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let data = pattern(2 * 4096 + 10);
+    fs::write(&input, &data).unwrap();
+    let key_path = temp.path().join("key.bin");
+    let key = ChunkKey::generate_file(&key_path).unwrap();
+    let by_file = temp.path().join("by-file");
+    split_with(
+        SplitOptions::builder(&input, &by_file)
+            .chunk_size(4096)
+            .encryption(Some(Encryption::with_key_file()), Some(key)),
+    );
+    let by_passphrase = temp.path().join("by-passphrase");
+    let (encryption, passphrase_key) = cheap_key("correct horse");
+    split_with(
+        SplitOptions::builder(&input, &by_passphrase)
+            .chunk_size(4096)
+            .encryption(Some(encryption), Some(passphrase_key)),
+    );
+    let sizes = |directory: &Path| -> Vec<(PathBuf, usize)> {
+        contents(directory)
+            .into_iter()
+            .filter(|(name, _)| name != Path::new(MANIFEST_NAME))
+            .map(|(name, bytes)| (name, bytes.len()))
+            .collect()
+    };
+    assert_eq!(sizes(&by_file), sizes(&by_passphrase));
+    for directory in [&by_file, &by_passphrase] {
+        let report = verify(directory, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+        assert!(report.mismatched.is_empty());
+    }
+
+    let options = ReconstructOptions {
+        output: Some("joined.bin".to_string()),
+        key: Some(ChunkKey::read_file(&key_path, false).unwrap()),
+        ..ReconstructOptions::new(&by_file)
+    };
+    let report = reconstruct(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert_eq!(fs::read(report.output).unwrap(), data);
+    // Another key is told apart by its fingerprint before any chunk is read
+    let options = ReconstructOptions {
+        key: Some(cheap_key("correct horse").1),
+        ..options
+    };
+    let wrong = reconstruct(&options, &mut |_| {}, &CancelToken::new());
+    assert!(matches!(wrong, Err(SplitterError::WrongKey { .. })));
+}
+
+// Rekeying leaves the chunks opening with the new key alone, and what they hold as it
+// was, the details sealed in info.json too when they were.
+#[cfg(feature = "encrypt")]
+#[test]
+fn rekeyed_sets_join_with_the_new_key_only() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let data = pattern(3 * 4096 + 100);
+    fs::write(&input, &data).unwrap();
+    for (name, compression, concealed) in [
+        ("plain", Compression::None, false),
+        ("gzip", Compression::Gzip, true),
+    ] {
+        let chunks = temp.path().join(name);
+        let (encryption, old) = cheap_key("correct horse");
+        split_with(
+            SplitOptions::builder(&input, &chunks)
+                .chunk_size(4096)
+                .hash(Some(HashAlgorithm::Sha256))
+                .compression(compression)
+                .min_ratio(0.0)
+                .encryption(Some(encryption.clone()), Some(old.clone()))
+                .encrypt_metadata(concealed),
+        );
+        let before = contents(&chunks);
+
+        // A dry run reads every chunk and writes nothing
+        let dry_run = RekeyOptions {
+            dry_run: true,
+            ..RekeyOptions::new(&chunks, encryption.clone())
+        };
+        let report = rekey(&dry_run, &old, &old, &mut |_| {}, &CancelToken::new()).unwrap();
+        assert_eq!(report.chunks, 4);
+        assert_eq!(report.rewritten_size, 0);
+        assert_eq!(contents(&chunks), before);
+        let wrong = cheap_key("battery staple").1;
+        let wrong = rekey(&dry_run, &wrong, &old, &mut |_| {}, &CancelToken::new());
+        assert!(matches!(wrong, Err(SplitterError::WrongKey { .. })));
+
+        let key_path = temp.path().join(format!("{}.key", name));
+        let new = ChunkKey::generate_file(&key_path).unwrap();
+        let options = RekeyOptions::new(&chunks, Encryption::with_key_file());
+        let mut finished = 0;
+        let mut count = |event: ProgressEvent| {
+            if let ProgressEvent::ChunkFinished { .. } = event {
+                finished += 1;
+            }
+        };
+        let report = rekey(&options, &old, &new, &mut count, &CancelToken::new()).unwrap();
+        assert_eq!(finished, 8);
+        assert_eq!(
+            report.encryption.fingerprint,
+            Some(new.fingerprint()),
+            "{:?}",
+            report.encryption
+        );
+        assert!(report.encryption.kdf.is_none());
+        let after = contents(&chunks);
+        assert_eq!(
+            after.keys().collect::<Vec<_>>(),
+            before.keys().collect::<Vec<_>>()
+        );
+        assert!(after.iter().all(|(name, bytes)| before[name] != *bytes));
+        let manifest = Manifest::load(&chunks, false).unwrap().unwrap();
+        assert_eq!(manifest.concealed(), concealed);
+
+        let report = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+        assert!(report.mismatched.is_empty(), "{:?}", report.mismatched);
+        let options = ReconstructOptions {
+            output: Some("joined.bin".to_string()),
+            key: Some(old.clone()),
+            ..ReconstructOptions::new(&chunks)
+        };
+        let joined = reconstruct(&options, &mut |_| {}, &CancelToken::new());
+        assert!(matches!(joined, Err(SplitterError::WrongKey { .. })));
+        let options = ReconstructOptions {
+            key: Some(ChunkKey::read_file(&key_path, false).unwrap()),
+            ..options
+        };
+        let report = reconstruct(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+        assert_eq!(fs::read(report.output).unwrap(), data);
+    }
+}
+
+// A chunk the old key doesn't open is found before any chunk is written again.
+#[cfg(feature = "encrypt")]
+#[test]
+fn rekey_writes_nothing_unless_every_chunk_opens() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(3 * 4096)).unwrap();
+    let chunks = temp.path().join("chunks");
+    let (encryption, old) = cheap_key("correct horse");
+    split_with(
+        SplitOptions::builder(&input, &chunks)
+            .chunk_size(4096)
+            .encryption(Some(encryption), Some(old.clone())),
+    );
+    let path = chunks.join("chunk002");
+    let mut chunk = fs::read(&path).unwrap();
+    chunk[100] ^= 1;
+    fs::write(&path, &chunk).unwrap();
+    let before = contents(&chunks);
+
+    let new = cheap_key("battery staple").1;
+    let options = RekeyOptions::new(&chunks, cheap_key("battery staple").0);
+    let rekeyed = rekey(&options, &old, &new, &mut |_| {}, &CancelToken::new());
+    assert!(
+        matches!(&rekeyed, Err(SplitterError::Undecryptable { path: failed }) if *failed == path),
+        "{:?}",
+        rekeyed
+    );
+    assert_eq!(contents(&chunks), before);
+}
+
+// An identity file as age-keygen writes it at `path`, and the recipient it decrypts for.
+#[cfg(feature = "age")]
+fn age_keygen(path: &Path) -> (age::x25519::Identity, String) {
+    use age::secrecy::ExposeSecret;
+    let identity = age::x25519::Identity::generate();
+    let public = identity.to_public().to_string();
+    let file = format!(
+        "# created: 2026-10-15T00:00:00Z\n# public key: {}\n{}\n",
+        public,
+        identity.to_string().expose_secret()
+    );
+    fs::write(path, file).unwrap();
+    (identity, public)
+}
+
+// Each chunk of a set split with --age-recipient is an age file of its own, so joining
+// the chunks needs nothing but age.
+#[cfg(feature = "age")]
+#[test]
+fn age_chunks_join_with_age_alone() {
+    use std::io::Read;
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let data = pattern(3 * 4096 + 100);
+    fs::write(&input, &data).unwrap();
+    let key_path = temp.path().join("key.txt");
+    let (identity, public) = age_keygen(&key_path);
+    let chunks = temp.path().join("chunks");
+    split_with(
+        SplitOptions::builder(&input, &chunks)
+            .chunk_size(4096)
+            .encryption(
+                Some(Encryption::with_age()),
+                Some(ChunkKey::age_recipients(&[public]).unwrap()),
+            ),
+    );
+    let mut joined = Vec::new();
+    for name in ["chunk000", "chunk001", "chunk002", "chunk003"] {
+        let file = fs::File::open(chunks.join(name)).unwrap();
+        age::Decryptor::new(file)
+            .unwrap()
+            .decrypt(std::iter::once(&identity as &dyn age::Identity))
+            .unwrap()
+            .read_to_end(&mut joined)
+            .unwrap();
+    }
+    assert_eq!(joined, data);
+
+    // And with the age tool itself, as the README has it, where it is installed
+    let installed = Command::new("age")
+        .arg("--version")
+        .output()
+        .is_ok_and(|output| output.status.success());
+    if !installed {
+        eprintln!("age isn't installed; not joining the chunks with it");
+        return;
+    }
+    let joined = Command::new("sh")
+        .arg("-c")
+        .arg("for f in chunk*; do age -d -i \"$1\" \"$f\"; done")
+        .arg("sh")
+        .arg(&key_path)
+        .current_dir(&chunks)
+        .output()
+        .unwrap();
+    assert!(joined.status.success(), "{:?}", joined);
+    assert_eq!(joined.stdout, data);
+}
+
+#[cfg(feature = "age")]
+#[test]
+fn age_sets_join_with_an_identity_and_verify_without_one() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let data = pattern(3 * 4096 + 100);
+    fs::write(&input, &data).unwrap();
+    let (_, public) = age_keygen(&temp.path().join("key.txt"));
+    let (_, other) = age_keygen(&temp.path().join("other.txt"));
+    for (name, compression) in [("plain", Compression::None), ("gzip", Compression::Gzip)] {
+        let chunks = temp.path().join(name);
+        // Any one of the recipients opens the set
+        let recipients = ChunkKey::age_recipients(&[other.clone(), public.clone()]).unwrap();
+        split_with(
+            SplitOptions::builder(&input, &chunks)
+                .chunk_size(4096)
+                .compression(compression)
+                .min_ratio(0.0)
+                .encryption(Some(Encryption::with_age()), Some(recipients)),
+        );
+        let report = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+        assert!(report.mismatched.is_empty(), "{:?}", report.mismatched);
+        assert!(report.health.missing.is_empty() && report.health.uneven.is_empty());
+
+        let options = ReconstructOptions {
+            output: Some("joined.bin".to_string()),
+            key: Some(ChunkKey::read_age_identity(&temp.path().join("key.txt")).unwrap()),
+            ..ReconstructOptions::new(&chunks)
+        };
+        let report = reconstruct(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+        assert_eq!(fs::read(report.output).unwrap(), data);
+    }
+
+    // An identity that isn't among the recipients is caught before anything is read
+    let chunks = temp.path().join("plain");
+    age_keygen(&temp.path().join("stranger.txt"));
+    let options = ReconstructOptions {
+        output: Some("wrong.bin".to_string()),
+        key: Some(ChunkKey::read_age_identity(&temp.path().join("stranger.txt")).unwrap()),
+        ..ReconstructOptions::new(&chunks)
+    };
+    let wrong = reconstruct(&options, &mut |_| {}, &CancelToken::new());
+    assert!(
+        matches!(wrong, Err(SplitterError::WrongKey { .. })),
+        "{:?}",
+        wrong
+    );
+    assert!(!chunks.join("wrong.bin").exists());
+
+    // A changed chunk fails verify, and fails to decrypt
+    let path = chunks.join("chunk001");
+    let mut chunk = fs::read(&path).unwrap();
+    let last = chunk.len() - 1;
+    chunk[last] ^= 1;
+    fs::write(&path, &chunk).unwrap();
+    let report = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert_eq!(report.mismatched, ["chunk001"]);
+    let options = ReconstructOptions {
+        output: Some("joined-again.bin".to_string()),
+        key: Some(ChunkKey::read_age_identity(&temp.path().join("key.txt")).unwrap()),
+        ..ReconstructOptions::new(&chunks)
+    };
+    let joined = reconstruct(&options, &mut |_| {}, &CancelToken::new());
+    assert!(matches!(joined, Err(SplitterError::Undecryptable { path: failed }) if failed == path));
+}
+
+#[cfg(unix)]
+#[test]
+fn extended_attributes_come_back_with_the_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("tagged.bin");
+    let chunks = dir.path().join("chunks");
+    fs::write(&input, pattern(10_000)).unwrap();
+    let value = [0, 1, 2, 255].repeat(100);
+    if let Err(e) = xattr::set(&input, "user.splitter.test", &value) {
+        // tmpfs before Linux 6.6 takes no user.* attributes, nor do some other file
+        // systems
+        eprintln!("skipping: the temporary directory takes no extended attributes: {e}");
+        return;
+    }
+    split_with(
+        SplitOptions::builder(&input, &chunks)
+            .chunk_size(4096)
+            .xattrs(true),
+    );
+    let info = fs::read_to_string(chunks.join(MANIFEST_NAME)).unwrap();
+    assert!(info.contains("user.splitter.test"));
+
+    let options = ReconstructOptions {
+        output: Some("joined.bin".to_string()),
+        ..ReconstructOptions::new(&chunks)
+    };
+    let output = reconstruct(&options, &mut |_| {}, &CancelToken::new())
+        .unwrap()
+        .output;
+    assert_eq!(fs::read(&output).unwrap(), pattern(10_000));
+    assert_eq!(
+        xattr::get(&output, "user.splitter.test").unwrap(),
+        Some(value)
+    );
+}
+
+#[test]
+fn a_directory_without_chunks_is_an_error_and_writes_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let options = ReconstructOptions {
+        output: Some("joined.bin".to_string()),
+        ..ReconstructOptions::new(dir.path())
+    };
+    let result = reconstruct(&options, &mut |_| {}, &CancelToken::new());
+    assert!(
+        matches!(&result, Err(SplitterError::NoChunks { path }) if path == dir.path()),
+        "{:?}",
+        result.map(|report| report.output)
+    );
+    assert!(!dir.path().join("joined.bin").exists());
+
+    // From the command line too, with a status saying so
+    let run = cli([OsStr::new("reconstruct"), dir.path().as_os_str()]);
+    assert_eq!(run.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&run.stderr).contains("no chunk files found"));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn an_edited_manifest_is_refused_until_resealed() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(5000)).unwrap();
+    let chunks = temp.path().join("chunks");
+    split(&input, &chunks, 2048);
+    let path = chunks.join(MANIFEST_NAME);
+    let manifest = fs::read_to_string(&path).unwrap();
+    let edited = manifest.replace(r#""input.bin""#, r#""renamed.bin""#);
+    assert_ne!(edited, manifest);
+    fs::write(&path, edited).unwrap();
+
+    for command in ["verify", "reconstruct"] {
+        let run = cli([OsStr::new(command), chunks.as_os_str()]);
+        assert_eq!(run.status.code(), Some(5), "{}", command);
+        let stderr = String::from_utf8_lossy(&run.stderr);
+        assert!(
+            stderr.contains("reseal it if the changes were meant"),
+            "{}",
+            stderr
+        );
+    }
+    assert!(!chunks.join("renamed.bin").exists());
+
+    let run = cli([OsStr::new("reseal"), chunks.as_os_str()]);
+    assert_eq!(run.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&run.stdout).starts_with("Resealed"));
+    for command in ["verify", "reconstruct"] {
+        let run = cli([OsStr::new(command), chunks.as_os_str()]);
+        assert_eq!(
+            run.status.code(),
+            Some(0),
+            "{}: {}",
+            command,
+            String::from_utf8_lossy(&run.stderr)
+        );
+    }
+    assert_eq!(fs::read(chunks.join("renamed.bin")).unwrap(), pattern(5000));
+}
+
+// With `true` for a process that has come and gone
+#[cfg(unix)]
+#[test]
+fn a_locked_directory_is_refused_until_a_stale_lock_is_broken() {
+    use reconstruct_large_file::lock::{self, Holder, LOCK_NAME};
+
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(5000)).unwrap();
+    let chunks = temp.path().join("chunks");
+    split(&input, &chunks, 2048);
+    let verify_with = |flags: &[&str]| {
+        let mut args = vec![OsStr::new("verify"), chunks.as_os_str()];
+        args.extend(flags.iter().map(OsStr::new));
+        cli(args)
+    };
+
+    // Held by this process, which is still running
+    let held = lock::acquire(&chunks, "test").unwrap();
+    let holder = lock::holder(&chunks).unwrap();
+    let error = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap_err();
+    assert!(
+        matches!(&error, SplitterError::Locked { path, .. } if *path == chunks),
+        "{:?}",
+        error
+    );
+    for flags in [&[][..], &["--steal-lock", "--yes"]] {
+        let run = verify_with(flags);
+        assert_eq!(run.status.code(), Some(3), "{:?}", flags);
+        let stderr = String::from_utf8_lossy(&run.stderr);
+        let holding = format!("test by process {}", std::process::id());
+        assert!(stderr.contains(&holding), "{:?}: {}", flags, stderr);
+    }
+    drop(held);
+    assert!(lock::holder(&chunks).is_none());
+
+    // Left behind by a process that has since exited
+    let mut gone = Command::new("true").spawn().unwrap();
+    gone.wait().unwrap();
+    let stale = Holder {
+        pid: gone.id(),
+        ..holder
+    };
+    let lock_path = chunks.join(LOCK_NAME);
+    fs::write(&lock_path, serde_json::to_vec(&stale).unwrap()).unwrap();
+    let reason = format!("but process {} is no longer running", gone.id());
+    let run = verify_with(&[]);
+    assert_eq!(run.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&run.stderr).contains(&reason));
+    assert!(lock_path.exists());
+
+    // Without a terminal to ask on, --steal-lock alone leaves it be
+    let run = verify_with(&["--steal-lock"]);
+    assert_eq!(run.status.code(), Some(3));
+    assert!(lock_path.exists());
+
+    let run = verify_with(&["--steal-lock", "--yes"]);
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert_eq!(run.status.code(), Some(0), "{}", stdout);
+    assert!(stdout.contains(&reason), "{}", stdout);
+    assert!(
+        stdout.contains("Break the lock? [y/N] y (--yes)"),
+        "{}",
+        stdout
+    );
+    assert!(!lock_path.exists());
+}
+
+#[test]
+fn an_exported_manifest_checks_a_set_it_was_signed_for() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(5000)).unwrap();
+    let chunks = temp.path().join("chunks");
+    split(&input, &chunks, 2048);
+    let exported = temp.path().join("set.fsrm");
+    let key = b"shared secret";
+    let report = export_manifest(
+        &chunks,
+        &exported,
+        Some(key),
+        false,
+        &mut |_| {},
+        &CancelToken::new(),
+    )
+    .unwrap();
+    // The split recorded no hashes, so every chunk was hashed for the export
+    assert_eq!((report.chunks, report.hashed, report.signed), (3, 3, true));
+    let manifest = ExportedManifest::load(&exported).unwrap();
+    assert_eq!(manifest.original_filename, "input.bin");
+    assert_eq!(manifest.size, 5000);
+
+    // The set's own info.json is neither needed nor read
+    fs::remove_file(chunks.join(MANIFEST_NAME)).unwrap();
+    let check = |key: Option<&[u8]>| {
+        verify_exported(&chunks, &exported, key, &mut |_| {}, &CancelToken::new())
+    };
+    assert!(check(Some(key)).unwrap().is_ok());
+    assert!(check(None).unwrap().is_ok());
+    assert!(check(Some(b"another secret")).is_err());
+
+    // Damage is found as by a verify
+    let path = chunks.join("chunk001");
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[7] ^= 1;
+    fs::write(&path, &bytes).unwrap();
+    fs::remove_file(chunks.join("chunk002")).unwrap();
+    let report = check(Some(key)).unwrap();
+    assert_eq!(report.mismatched, ["chunk001", "chunk002"]);
+    assert_eq!(report.health.missing, [2]);
+
+    // Changing the list, or the signature, breaks the signature
+    let text = fs::read_to_string(&exported).unwrap();
+    let value = manifest.signature.as_ref().unwrap().value.clone();
+    let flipped = match value.as_bytes()[0] {
+        b'0' => format!("1{}", &value[1..]),
+        _ => format!("0{}", &value[1..]),
+    };
+    let first = &manifest.chunks[0].hash;
+    for tampered in [
+        text.replace(&value, &flipped),
+        text.replace(&value, &value[..value.len() - 2]),
+        text.replace(first, &first.replace(&first[..4], "0000")),
+        text.replace("\"input.bin\"", "\"other.bin\""),
+    ] {
+        assert_ne!(tampered, text);
+        fs::write(&exported, tampered).unwrap();
+        assert!(check(Some(key)).is_err());
+    }
+}
+
+// Pieces of `data` another tool split it into, of `size` bytes each, under `names`.
+fn foreign_pieces(directory: &Path, data: &[u8], size: usize, names: &[&str]) {
+    fs::create_dir_all(directory).unwrap();
+    let pieces = data.chunks(size);
+    assert_eq!(pieces.len(), names.len());
+    for (name, piece) in names.iter().zip(pieces) {
+        fs::write(directory.join(name), piece).unwrap();
+    }
+}
+
+fn sha256(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[test]
+fn checksum_files_are_read_as_sha256sum_writes_them() {
+    let temp = tempfile::tempdir().unwrap();
+    let data = pattern(3500);
+    let pieces = temp.path().join("pieces");
+    let names = [
+        "data.bin.001",
+        "data.bin.002",
+        "data.bin.003",
+        "data.bin.004",
+    ];
+    foreign_pieces(&pieces, &data, 1000, &names);
+    // Plain, binary mode and BSD lines, one of them a level up, and the original too
+    let sums = format!(
+        "{}  data.bin.001\n{} *data.bin.002\nSHA256 (./data.bin.003) = {}\n{}  data.bin\n",
+        sha256(&data[..1000]),
+        sha256(&data[1000..2000]),
+        sha256(&data[2000..3000]),
+        sha256(&data),
+    );
+    fs::write(pieces.join("SHA256SUMS"), sums).unwrap();
+
+    let check = || verify(&pieces, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+    let report = check();
+    assert_eq!(report.checksum_files, ["SHA256SUMS"]);
+    assert_eq!(report.health.chunks, 4);
+    assert_eq!(report.health.total_size, 3500);
+    assert!(
+        report.mismatched.is_empty() && report.checksum_missing.is_empty(),
+        "{:?}",
+        report
+    );
+    assert_eq!(report.unchecksummed, ["data.bin.004"]);
+
+    // sha256sum itself agrees on what the lines it can read say, where it is installed
+    let sha256sum = |file: &str| {
+        Command::new("sha256sum")
+            .args(["-c", "--ignore-missing", file])
+            .current_dir(&pieces)
+            .output()
+    };
+    let plain: String = fs::read_to_string(pieces.join("SHA256SUMS"))
+        .unwrap()
+        .lines()
+        .filter(|line| !line.starts_with("SHA256 ("))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    fs::write(temp.path().join("plain.sha256"), &plain).unwrap();
+    let installed = Command::new("sha256sum")
+        .arg("--version")
+        .output()
+        .is_ok_and(|run| run.status.success());
+    if installed {
+        let run = sha256sum("../plain.sha256").unwrap();
+        assert!(run.status.success(), "{:?}", run);
+    }
+
+    let path = pieces.join("data.bin.002");
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[0] ^= 1;
+    fs::write(&path, bytes).unwrap();
+    fs::remove_file(pieces.join("data.bin.003")).unwrap();
+    let report = check();
+    assert_eq!(report.mismatched, ["data.bin.002"]);
+    assert_eq!(report.checksum_missing, ["data.bin.003"]);
+    assert!(!report.is_ok());
+    if installed {
+        assert!(!sha256sum("../plain.sha256").unwrap().status.success());
+    } else {
+        eprintln!("sha256sum isn't installed; not checking the sums with it");
+    }
+}
+
+#[test]
+fn pieces_another_tool_split_are_imported_as_a_chunk_set() {
+    let temp = tempfile::tempdir().unwrap();
+    let data = pattern(4500);
+    let cases: [(&str, &[&str], Option<&str>); 3] = [
+        (
+            "hjsplit",
+            &[
+                "movie.mkv.001",
+                "movie.mkv.002",
+                "movie.mkv.003",
+                "movie.mkv.004",
+                "movie.mkv.005",
+            ],
+            Some("movie.mkv"),
+        ),
+        ("split", &["xaa", "xab", "xac", "xad", "xae"], None),
+        (
+            "split-d",
+            &["part00", "part01", "part02", "part03", "part04"],
+            None,
+        ),
+    ];
+    for (tool, names, original) in cases {
+        let pieces = temp.path().join(tool);
+        foreign_pieces(&pieces, &data, 1000, names);
+        let found = detect_foreign(&pieces).unwrap();
+        assert_eq!(found.len(), 1, "{}", tool);
+        let set = &found[0];
+        assert_eq!(set.original_filename.as_deref(), original, "{}", tool);
+        let order: Vec<_> = set.pieces.iter().map(|piece| piece.name.as_str()).collect();
+        assert_eq!(order, names, "{}", tool);
+        assert!(set.doubts.is_empty(), "{}: {:?}", tool, set.doubts);
+
+        let report = import(
+            set,
+            original.unwrap_or("joined.bin"),
+            Some(HashAlgorithm::Sha256),
+            &mut |_| {},
+            &CancelToken::new(),
+        )
+        .unwrap();
+        assert_eq!((report.chunks, report.total_size), (5, 4500));
+        // Now a set of ours, which verify checks by its hashes
+        let manifest = Manifest::load(&pieces, false).unwrap().unwrap();
+        assert_eq!(manifest.chunk_size, Some(1000));
+        assert!(manifest.chunks.iter().all(|chunk| chunk.hash.is_some()));
+        assert!(
+            verify(&pieces, &[], false, &mut |_| {}, &CancelToken::new())
+                .unwrap()
+                .is_ok()
+        );
+        let output = rebuild(&pieces, "again.bin", 1);
+        assert_eq!(output, data, "{}", tool);
+    }
+
+    // A piece missing in the middle is pointed out rather than joined past
+    let pieces = temp.path().join("gap");
+    foreign_pieces(
+        &pieces,
+        &data,
+        1000,
+        &[
+            "big.iso.001",
+            "big.iso.002",
+            "big.iso.003",
+            "big.iso.004",
+            "big.iso.005",
+        ],
+    );
+    fs::remove_file(pieces.join("big.iso.003")).unwrap();
+    let found = detect_foreign(&pieces).unwrap();
+    assert!(
+        found[0].doubts.iter().any(
+            |doubt| matches!(doubt, Doubt::Gaps { count: 1, names } if names == &["big.iso.003"])
+        ),
+        "{:?}",
+        found[0].doubts
+    );
+}
+
+#[test]
+fn dry_runs_check_as_for_real_and_write_nothing() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(10_000)).unwrap();
+    let chunks = temp.path().join("chunks");
+    let mirror = temp.path().join("mirror");
+    for directory in [&chunks, &mirror] {
+        split_with(
+            SplitOptions::builder(&input, directory)
+                .chunk_size(1000)
+                .hash(Some(HashAlgorithm::Sha256))
+                .parity(Some(Parity::ReedSolomon { shards: 2 })),
+        );
+    }
+    fs::remove_file(chunks.join("chunk003")).unwrap();
+    let before = contents(temp.path());
+    let rechunked = temp.path().join("rechunked");
+    let dry_run = |args: &[&OsStr]| {
+        let run = cli(args.iter().copied().chain([OsStr::new("--dry-run")]));
+        assert_eq!(contents(temp.path()), before, "{:?}", args);
+        assert!(!rechunked.exists() && !chunks.join("input.bin").exists());
+        run
+    };
+    let s = OsStr::new;
+
+    let run = dry_run(&[s("repair"), chunks.as_os_str()]);
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert_eq!(run.status.code(), Some(0), "{}", stdout);
+    assert!(stdout.contains("chunk003"), "{}", stdout);
+    let run = dry_run(&[
+        s("heal"),
+        chunks.as_os_str(),
+        s("--from"),
+        mirror.as_os_str(),
+    ]);
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert_eq!(run.status.code(), Some(0), "{}", stdout);
+    assert!(stdout.contains("chunk003"), "{}", stdout);
+    let run = dry_run(&[s("reconstruct"), chunks.as_os_str()]);
+    assert_eq!(run.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&run.stdout).contains("input.bin"));
+    let run = dry_run(&[
+        s("rechunk"),
+        mirror.as_os_str(),
+        rechunked.as_os_str(),
+        s("-s"),
+        s("4KiB"),
+    ]);
+    assert_eq!(run.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&run.stdout).contains("chunk002"));
+    // What the real thing would fail on, its dry run fails on too: the source of a
+    // rechunk must verify
+    let run = dry_run(&[
+        s("rechunk"),
+        chunks.as_os_str(),
+        rechunked.as_os_str(),
+        s("-s"),
+        s("4KiB"),
+    ]);
+    assert_ne!(run.status.code(), Some(0));
+
+    // The library's plans say the same
+    let report = repair(&chunks, true, false, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert_eq!(report.rebuilt, ["chunk003"]);
+    let plan = plan_heal(
+        &chunks,
+        std::slice::from_ref(&mirror),
+        false,
+        &CancelToken::new(),
+    )
+    .unwrap();
+    assert_eq!(plan.chunks.len(), 1);
+    assert_eq!(plan.chunks[0].name, "chunk003");
+    let options = RechunkOptions::new(&mirror, &rechunked, 4096);
+    let plan = plan_rechunk(&options, &CancelToken::new()).unwrap();
+    assert_eq!((plan.chunks, plan.total_size), (3, 10_000));
+    assert_eq!(contents(temp.path()), before);
+    assert!(!rechunked.exists());
+}
+
+#[test]
+fn coverage_gives_the_ranges_present_and_the_gaps() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(9500)).unwrap();
+    let chunks = temp.path().join("chunks");
+    split(&input, &chunks, 1000);
+    for name in ["chunk002", "chunk003", "chunk007"] {
+        fs::remove_file(chunks.join(name)).unwrap();
+    }
+
+    let coverage = ChunkSet::open(&chunks, false).unwrap().coverage();
+    let ranges: Vec<_> = coverage
+        .ranges
+        .iter()
+        .map(|range| {
+            let chunks = (range.first_chunk, range.last_chunk);
+            (range.offset, range.end(), range.present, chunks)
+        })
+        .collect();
+    assert_eq!(
+        ranges,
+        [
+            (0, 2000, true, (0, 1)),
+            (2000, 4000, false, (2, 3)),
+            (4000, 7000, true, (4, 6)),
+            (7000, 8000, false, (7, 7)),
+            (8000, 9500, true, (8, 9)),
+        ]
+    );
+    assert_eq!((coverage.total_size, coverage.present_size()), (9500, 6500));
+    assert!(!coverage.is_complete());
+    let summary = "68.4% present, 2 gaps, largest gap 2.0 KiB";
+    assert_eq!(coverage.summary(), summary);
+
+    let run = cli([OsStr::new("coverage"), chunks.as_os_str()]);
+    assert_eq!(run.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&run.stdout).ends_with(&format!("{}\n", summary)));
+    let run = cli([
+        OsStr::new("coverage"),
+        chunks.as_os_str(),
+        OsStr::new("--json"),
+    ]);
+    let json: serde_json::Value = serde_json::from_slice(&run.stdout).unwrap();
+    assert_eq!(json["gaps"], 2);
+    assert_eq!(json["largest_gap"], 2000);
+    assert_eq!(json["present_size"], 6500);
+    assert_eq!(json["ranges"].as_array().unwrap().len(), 5);
+    assert_eq!(json["ranges"][1]["offset"], 2000);
+
+    // Complete, it is one range
+    let whole = temp.path().join("whole");
+    split(&input, &whole, 1000);
+    let coverage = ChunkSet::open(&whole, false).unwrap().coverage();
+    assert_eq!(coverage.ranges.len(), 1);
+    assert_eq!(coverage.summary(), "100% present, no gaps");
+}
+
+#[test]
+fn a_sample_drawn_with_a_seed_is_drawn_the_same_again() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(20 * 500)).unwrap();
+    let chunks = temp.path().join("chunks");
+    split_with(
+        SplitOptions::builder(&input, &chunks)
+            .chunk_size(500)
+            .hash(Some(HashAlgorithm::Sha256)),
+    );
+    let sample = |options: SampleOptions| {
+        let report = verify_sample(&chunks, &options, &mut |_| {}, &CancelToken::new()).unwrap();
+        let sampled = *report.sample.clone().unwrap();
+        (report, sampled)
+    };
+    let quarter = |seed| SampleOptions {
+        fraction: Some(0.25),
+        seed,
+        ..SampleOptions::default()
+    };
+
+    let (report, first) = sample(quarter(42));
+    assert!(report.is_ok());
+    assert_eq!((first.seed, first.checkable), (42, 20));
+    assert_eq!(first.checked.len(), 5);
+    assert!(first.checked.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(first.checked.iter().all(|&index| index < 20));
+    assert_eq!(first.bytes, 5 * 500);
+    assert_eq!(sample(quarter(42)).1.checked, first.checked);
+    let others: Vec<_> = (1..=5)
+        .map(|seed| sample(quarter(seed)).1.checked)
+        .collect();
+    assert!(others.iter().any(|checked| *checked != first.checked));
+
+    let (_, counted) = sample(SampleOptions {
+        chunks: Some(3),
+        seed: 42,
+        ..SampleOptions::default()
+    });
+    assert_eq!(counted.checked.len(), 3);
+    let (_, all) = sample(SampleOptions {
+        chunks: Some(100),
+        seed: 42,
+        ..SampleOptions::default()
+    });
+    assert_eq!(all.checked, (0..20).collect::<Vec<_>>());
+
+    // Damage in a chunk the sample checks is found
+    let damaged = format!("chunk{:03}", first.checked[0]);
+    let path = chunks.join(&damaged);
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[0] ^= 1;
+    fs::write(&path, bytes).unwrap();
+    let (report, again) = sample(quarter(42));
+    assert_eq!(again.checked, first.checked);
+    assert_eq!(report.mismatched, [damaged]);
+}
+
+#[test]
+fn stats_total_the_sets_under_a_directory_and_list_the_rest() {
+    let temp = tempfile::tempdir().unwrap();
+    let root = temp.path().join("archive");
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(5000)).unwrap();
+    split(&input, &root.join("a"), 2048);
+    split_with(
+        SplitOptions::builder(&input, root.join("b/nested"))
+            .chunk_size(1000)
+            .hash(Some(HashAlgorithm::Sha256))
+            .compression(Compression::Gzip),
+    );
+    split(&input, &root.join("c"), 1000);
+    fs::remove_file(root.join("c/chunk002")).unwrap();
+    // Named as `split` names them, which is nothing like a chunk of ours
+    foreign_pieces(
+        &root.join("d"),
+        &pattern(2500),
+        1000,
+        &["xaa", "xab", "xac"],
+    );
+    fs::create_dir_all(root.join("e/empty")).unwrap();
+
+    let report = stats(&root, false, &CancelToken::new()).unwrap();
+    let sets: Vec<_> = report
+        .sets
+        .iter()
+        .map(|set| {
+            (
+                set.directory.strip_prefix(&root).unwrap(),
+                set.chunks,
+                set.hash,
+            )
+        })
+        .collect();
+    assert_eq!(
+        sets,
+        [
+            (Path::new("a"), 3, None),
+            (Path::new("b/nested"), 5, Some(HashAlgorithm::Sha256)),
+        ]
+    );
+    assert!(
+        report
+            .sets
+            .iter()
+            .all(|set| set.original_filename == "input.bin")
+    );
+    assert_eq!(report.original_size(), 10_000);
+    assert_eq!(report.chunks(), 8);
+    let gzipped = &report.sets[1];
+    assert_eq!(gzipped.compression, Compression::Gzip);
+    assert!(gzipped.stored_size < 5000 && gzipped.ratio().unwrap() < 1.0);
+    assert_eq!(report.sets[0].stored_size, 5000);
+    assert_eq!(report.stored_size(), 5000 + gzipped.stored_size);
+    let info = fs::metadata(root.join("a").join(MANIFEST_NAME))
+        .unwrap()
+        .len();
+    assert_eq!(report.sets[0].disk_size, 5000 + info);
+
+    assert_eq!(report.broken.len(), 1);
+    assert_eq!(report.broken[0].directory, root.join("c"));
+    assert_eq!(report.foreign.len(), 1);
+    assert_eq!(report.foreign[0].directory, root.join("d"));
+    assert_eq!(report.foreign[0].reason, "3 pieces of x");
+}
+
+#[test]
+fn random_names_give_nothing_away_and_join_by_the_manifest() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let data = pattern(5 * 1000 + 10);
+    fs::write(&input, &data).unwrap();
+    let chunks = temp.path().join("chunks");
+    split_with(
+        SplitOptions::builder(&input, &chunks)
+            .chunk_size(1000)
+            .hash(Some(HashAlgorithm::Sha256))
+            .random_names(true),
+    );
+    let manifest = Manifest::load(&chunks, false).unwrap().unwrap();
+    assert!(manifest.random_names);
+    let listed: Vec<&str> = manifest
+        .chunks
+        .iter()
+        .map(|chunk| chunk.name.as_str())
+        .collect();
+    assert_eq!(listed.len(), 6);
+    let mut on_disk: Vec<String> = contents(&chunks)
+        .into_keys()
+        .map(|name| name.to_string_lossy().into_owned())
+        .filter(|name| name != MANIFEST_NAME)
+        .collect();
+    // Hex names that say nothing of the order, all different
+    assert!(
+        on_disk
+            .iter()
+            .all(|name| { name.len() == 32 && name.bytes().all(|byte| byte.is_ascii_hexdigit()) })
+    );
+    on_disk.sort();
+    let mut sorted = listed.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(on_disk, sorted);
+    assert_ne!(on_disk, listed);
+    assert_eq!(rebuild(&chunks, "joined.bin", 2), data);
+    fs::remove_file(chunks.join("joined.bin")).unwrap();
+
+    // What is missing and what is extra is still found
+    fs::remove_file(chunks.join(listed[2])).unwrap();
+    let stray = "0123456789abcdef0123456789abcdef";
+    fs::write(chunks.join(stray), pattern(1000)).unwrap();
+    let report = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert_eq!(report.health.missing, [2]);
+    assert_eq!(report.health.unexpected, [stray]);
+    assert!(!report.is_ok());
+
+    // A plain split keeps the plain names
+    let plain = temp.path().join("plain");
+    split(&input, &plain, 1000);
+    assert!(contents(&plain).contains_key(Path::new("chunk005")));
+}
+
+#[cfg(unix)]
+#[test]
+fn compat_names_join_with_cat_alone_and_without_the_manifest() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("disk.iso");
+    // Twelve chunks, for the tenth and later to sort after the ninth
+    let data = pattern(11 * 500 + 77);
+    fs::write(&input, &data).unwrap();
+    let cases = [
+        (Compat::Hjsplit, "disk.iso.001", "disk.iso.012"),
+        (Compat::Split, "disk.iso.partaa", "disk.iso.partal"),
+    ];
+    for (compat, first, last) in cases {
+        let chunks = temp.path().join(format!("{:?}", compat));
+        split_with(
+            SplitOptions::builder(&input, &chunks)
+                .chunk_size(500)
+                .compat(Some(compat)),
+        );
+        let names: Vec<String> = contents(&chunks)
+            .into_keys()
+            .map(|name| name.to_string_lossy().into_owned())
+            .filter(|name| name != MANIFEST_NAME)
+            .collect();
+        assert_eq!(names.len(), 12, "{:?}", compat);
+        assert_eq!((names[0].as_str(), names[11].as_str()), (first, last));
+
+        // As the recipient without this tool would put them back together
+        let joined = Command::new("sh")
+            .arg("-c")
+            .arg("cat disk.iso.*")
+            .current_dir(&chunks)
+            .output()
+            .unwrap();
+        assert!(joined.status.success(), "{:?}", joined);
+        assert!(joined.stdout == data, "{:?}", compat);
+
+        fs::remove_file(chunks.join(MANIFEST_NAME)).unwrap();
+        assert_eq!(rebuild(&chunks, "joined.bin", 1), data, "{:?}", compat);
+    }
+
+    // Or with no info.json written at all
+    let bare = temp.path().join("bare");
+    split_with(
+        SplitOptions::builder(&input, &bare)
+            .chunk_size(500)
+            .compat(Some(Compat::Hjsplit))
+            .no_manifest(true),
+    );
+    assert!(!bare.join(MANIFEST_NAME).exists());
+    assert_eq!(rebuild(&bare, "joined.bin", 1), data);
+}
+
+#[test]
+fn armored_chunks_survive_what_pasting_does_to_text() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let data = pattern(3 * 1000 + 123);
+    fs::write(&input, &data).unwrap();
+    let chunks = temp.path().join("chunks");
+    split_with(
+        SplitOptions::builder(&input, &chunks)
+            .chunk_size(1000)
+            .hash(Some(HashAlgorithm::Sha256))
+            .compression(Compression::Armor),
+    );
+    // The manifest counts the bytes of the file, not those of the text
+    let manifest = Manifest::load(&chunks, false).unwrap().unwrap();
+    let sizes: Vec<u64> = manifest.chunks.iter().map(|chunk| chunk.size).collect();
+    assert_eq!(sizes, [1000, 1000, 1000, 123]);
+    let text = fs::read_to_string(chunks.join("chunk001.txt")).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], "-----BEGIN CHUNK 2 OF 4-----");
+    assert!(
+        lines[1..lines.len() - 1]
+            .iter()
+            .all(|line| line.len() <= 76)
+    );
+    assert!(lines[lines.len() - 1].starts_with("-----END CHUNK 2 OF 4: 1000 BYTES, CRC32 "));
+
+    // Pasted somewhere that adds a greeting, CRLF and stray spaces
+    let pasted: String = text
+        .lines()
+        .map(|line| format!("  {} \r\n", line))
+        .collect();
+    fs::write(
+        chunks.join("chunk001.txt"),
+        format!(
+            "Here it is:\r\n\r\n{}\r\n-- \r\nSent from my phone\r\n",
+            pasted
+        ),
+    )
+    .unwrap();
+    assert_eq!(rebuild(&chunks, "joined.bin", 1), data);
+    fs::remove_file(chunks.join("joined.bin")).unwrap();
+
+    // A character changed in transit is caught by the CRC
+    let line = lines[5];
+    let other = if &line[10..11] == "A" { "B" } else { "A" };
+    let changed = format!("{}{}{}", &line[..10], other, &line[11..]);
+    fs::write(chunks.join("chunk001.txt"), text.replace(line, &changed)).unwrap();
+    let result = rebuild_result(&chunks);
+    assert!(
+        matches!(&result, Err(SplitterError::Io { path, source, .. })
+            if path.ends_with("chunk001.txt") && source.to_string() == "CRC mismatch"),
+        "{:?}",
+        result
+    );
+    assert!(!chunks.join("joined.bin").exists());
+}
+
+#[test]
+fn an_empty_file_comes_back_empty_as_its_manifest_says() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("empty.bin");
+    let chunks = dir.path().join("chunks");
+    fs::write(&input, b"").unwrap();
+    split(&input, &chunks, 4096);
+    assert_eq!(rebuild(&chunks, "joined.bin", 1), b"");
+
+    // Without the manifest, nothing says the file was empty
+    fs::remove_file(chunks.join(MANIFEST_NAME)).unwrap();
+    let options = ReconstructOptions {
+        output: Some("again.bin".to_string()),
+        ..ReconstructOptions::new(&chunks)
+    };
+    let result = reconstruct(&options, &mut |_| {}, &CancelToken::new());
+    assert!(matches!(result, Err(SplitterError::NoChunks { .. })));
+}
+
+fn split_result(builder: SplitOptionsBuilder) -> Result<(), SplitterError> {
+    let options = builder.min_chunk_size(0).build()?;
+    split_file(&options, &mut |_| {}, &CancelToken::new()).map(|_| ())
+}
+
+fn rebuild_result(directory: &Path) -> Result<(), SplitterError> {
+    let options = ReconstructOptions {
+        output: Some("joined.bin".to_string()),
+        ..ReconstructOptions::new(directory)
+    };
+    reconstruct(&options, &mut |_| {}, &CancelToken::new()).map(|_| ())
+}
+
+#[test]
+fn each_failure_comes_back_as_its_own_error() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(1000)).unwrap();
+
+    let missing = temp.path().join("missing.bin");
+    let result = split_result(SplitOptions::builder(&missing, temp.path().join("a")));
+    assert!(
+        matches!(&result, Err(SplitterError::Io { path, source, .. })
+            if *path == missing && source.kind() == io::ErrorKind::NotFound),
+        "{:?}",
+        result
+    );
+
+    let result = split_result(SplitOptions::builder(&input, temp.path().join("b")).threads(0));
+    assert!(
+        matches!(
+            result,
+            Err(SplitterError::InvalidOption {
+                field: "threads",
+                ..
+            })
+        ),
+        "{:?}",
+        result
+    );
+
+    let options = SplitOptions::builder(&input, temp.path().join("c"))
+        .chunk_size(100)
+        .build()
+        .map(|_| ());
+    assert!(
+        matches!(
+            options,
+            Err(SplitterError::ChunkTooSmall {
+                chunk_size: 100,
+                ..
+            })
+        ),
+        "{:?}",
+        options
+    );
+
+    let occupied = temp.path().join("occupied");
+    fs::create_dir(&occupied).unwrap();
+    fs::write(occupied.join("something"), "").unwrap();
+    let result = split_result(SplitOptions::builder(&input, &occupied).chunk_size(100));
+    assert!(
+        matches!(&result, Err(SplitterError::DestinationNotEmpty { path }) if *path == occupied),
+        "{:?}",
+        result
+    );
+
+    let file = temp.path().join("file");
+    fs::write(&file, "").unwrap();
+    let result = split_result(SplitOptions::builder(&input, &file).chunk_size(100));
+    assert!(
+        matches!(&result, Err(SplitterError::NotADirectory { path }) if *path == file),
+        "{:?}",
+        result
+    );
+
+    let chunks = temp.path().join("chunks");
+    split(&input, &chunks, 100);
+    let manifest = fs::read(chunks.join(MANIFEST_NAME)).unwrap();
+    // Reconstruction goes by the chunks alone then, but a check can't
+    fs::write(chunks.join(MANIFEST_NAME), "{ not json").unwrap();
+    let result = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).map(|_| ());
+    assert!(
+        matches!(&result, Err(SplitterError::MetadataCorrupt { path, .. })
+            if *path == chunks.join(MANIFEST_NAME)),
+        "{:?}",
+        result
+    );
+
+    fs::write(chunks.join(MANIFEST_NAME), manifest).unwrap();
+    fs::remove_file(chunks.join("chunk003")).unwrap();
+    fs::remove_file(chunks.join("chunk005")).unwrap();
+    let result = rebuild_result(&chunks);
+    assert!(
+        matches!(&result, Err(SplitterError::MissingChunks { indices }) if *indices == [3, 5]),
+        "{:?}",
+        result
+    );
+    assert!(!chunks.join("joined.bin").exists());
+}
+
+// Bytes that differ from block to block without being held anywhere whole, the same
+// ones each time for the same `state`.
+struct Generated {
+    left: u64,
+    state: u64,
+}
+
+impl Read for Generated {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.left as usize);
+        for byte in &mut buf[..len] {
+            self.state = self
+                .state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1);
+            *byte = (self.state >> 56) as u8;
+        }
+        self.left -= len as u64;
+        Ok(len)
+    }
+}
+
+#[test]
+fn hundreds_of_megabytes_stream_through_the_writer_and_back() {
+    const LEN: u64 = 200 << 20;
+    let temp = tempfile::tempdir().unwrap();
+    let chunks = temp.path().join("chunks");
+    let mut writer =
+        ChunkedWriter::create(&chunks, "stream.bin", 16 << 20, Some(HashAlgorithm::Sha256))
+            .unwrap();
+    let mut source = Generated {
+        left: LEN,
+        state: 7,
+    };
+    assert_eq!(io::copy(&mut source, &mut writer).unwrap(), LEN);
+    assert_eq!(writer.written(), LEN);
+    let manifest = writer.finish().unwrap();
+    // 12 full chunks and the 8 MiB left over
+    assert_eq!(manifest.chunks.len(), 13);
+    assert_eq!(manifest.chunks.last().unwrap().size, 8 << 20);
+    let report = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert!(report.mismatched.is_empty());
+
+    let options = ReconstructOptions::new(&chunks);
+    let joined = reconstruct(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert_eq!(joined.output, chunks.join("stream.bin"));
+    let mut joined = fs::File::open(joined.output).unwrap();
+    let mut expected = Generated {
+        left: LEN,
+        state: 7,
+    };
+    let (mut got, mut want) = (vec![0; 1 << 20], vec![0; 1 << 20]);
+    loop {
+        let read = expected.read(&mut want).unwrap();
+        joined.read_exact(&mut got[..read]).unwrap();
+        assert!(got[..read] == want[..read]);
+        if read == 0 {
+            assert_eq!(joined.read(&mut got).unwrap(), 0);
+            break;
+        }
+    }
+}
+
+#[test]
+fn a_writer_dropped_unfinished_takes_its_chunks_with_it() {
+    let temp = tempfile::tempdir().unwrap();
+    let chunks = temp.path().join("chunks");
+    let mut writer = ChunkedWriter::create(&chunks, "stream.bin", 100, None).unwrap();
+    writer.write_all(&pattern(250)).unwrap();
+    drop(writer);
+    assert_eq!(contents(&chunks), BTreeMap::new());
+}
+
+#[cfg(unix)]
+#[test]
+fn names_that_are_not_utf8_split_and_join_back() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path().join(OsStr::from_bytes(b"archive \xff"));
+    // Filesystems that only take UTF-8 names have nothing to test
+    if fs::create_dir(&dir).is_err() {
+        return;
+    }
+    let input = dir.join(OsStr::from_bytes(b"old\xe9.bin"));
+    fs::write(&input, pattern(1000)).unwrap();
+    let chunks = dir.join(OsStr::from_bytes(b"old\xe9.bin.chunks"));
+    split(&input, &chunks, 100);
+    assert_eq!(rebuild(&chunks, "joined.bin", 1), pattern(1000));
+
+    // Pieces another tool named after the file, found by their numbers alone
+    let pieces = dir.join(OsStr::from_bytes(b"pieces \xfe"));
+    fs::create_dir(&pieces).unwrap();
+    for (number, piece) in pattern(1000).chunks(300).enumerate() {
+        let mut name = b"old\xe9.bin.".to_vec();
+        name.extend_from_slice(format!("{:03}", number + 1).as_bytes());
+        fs::write(pieces.join(OsStr::from_bytes(&name)), piece).unwrap();
+    }
+    assert_eq!(rebuild(&pieces, "joined.bin", 1), pattern(1000));
+}
+
+// The rendered error, which is all the CLI shows, says what failed on which file.
+#[test]
+fn errors_name_the_file_they_happened_on() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(1000)).unwrap();
+    let chunks = temp.path().join("chunks");
+    split(&input, &chunks, 100);
+
+    let nowhere = temp.path().join("nowhere");
+    let options = ReconstructOptions {
+        output: Some(nowhere.join("joined.bin").display().to_string()),
+        ..ReconstructOptions::new(&chunks)
+    };
+    let message = reconstruct(&options, &mut |_| {}, &CancelToken::new())
+        .unwrap_err()
+        .to_string();
+    let creating = format!("creating {}", nowhere.display());
+    assert!(message.starts_with(&creating), "{}", message);
+
+    let stream = temp.path().join("stream");
+    let mut writer = ChunkedWriter::create(&stream, "stream.bin", 100, None).unwrap();
+    writer.write_all(&pattern(250)).unwrap();
+    fs::create_dir(stream.join(MANIFEST_NAME)).unwrap();
+    let message = writer.finish().unwrap_err().to_string();
+    let writing = format!("writing {}: ", stream.join(MANIFEST_NAME).display());
+    assert!(message.starts_with(&writing), "{}", message);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Permissions don't stop root
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let chunk = chunks.join("chunk002");
+        fs::set_permissions(&chunk, fs::Permissions::from_mode(0o000)).unwrap();
+        let options = ReconstructOptions {
+            output: Some("joined.bin".to_string()),
+            ..ReconstructOptions::new(&chunks)
+        };
+        let message = reconstruct(&options, &mut |_| {}, &CancelToken::new())
+            .unwrap_err()
+            .to_string();
+        let opening = format!("opening chunk {}: ", chunk.display());
+        assert!(message.starts_with(&opening), "{}", message);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn modes_asked_for_are_what_the_files_get() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(1000)).unwrap();
+    for (file_mode, dir_mode) in [(0o640, 0o750), (0o600, 0o700)] {
+        let chunks = temp.path().join(format!("chunks{:o}", file_mode));
+        split_with(
+            SplitOptions::builder(&input, &chunks)
+                .chunk_size(300)
+                .file_mode(Some(file_mode))
+                .dir_mode(Some(dir_mode)),
+        );
+        assert_eq!(mode(&chunks), dir_mode);
+        let files = fs::read_dir(&chunks).unwrap();
+        let modes: Vec<(PathBuf, u32)> = files
+            .map(|entry| entry.unwrap().path())
+            .map(|path| (path.clone(), mode(&path)))
+            .collect();
+        assert_eq!(modes.len(), 5);
+        for (path, found) in modes {
+            assert_eq!(found, file_mode, "{}", path.display());
+        }
+
+        // An output that is already there, with other permissions, gets the mode too
+        let output = chunks.join("joined.bin");
+        fs::write(&output, "old").unwrap();
+        fs::set_permissions(&output, fs::Permissions::from_mode(0o666)).unwrap();
+        let options = ReconstructOptions {
+            output: Some("joined.bin".to_string()),
+            file_mode: Some(file_mode),
+            ..ReconstructOptions::new(&chunks)
+        };
+        reconstruct(&options, &mut |_| {}, &CancelToken::new()).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), pattern(1000));
+        assert_eq!(mode(&output), file_mode);
+    }
+}
+
+#[test]
+fn a_sharded_set_joins_and_verifies_through_its_subdirectories() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(1000)).unwrap();
+    let chunks = temp.path().join("chunks");
+    let sharded = |chunks: &Path, shard_dirs| {
+        SplitOptions::builder(&input, chunks)
+            .chunk_size(100)
+            .hash(Some(HashAlgorithm::Sha256))
+            .max_dir_files(4)
+            .shard_dirs(shard_dirs)
+    };
+    split_with(sharded(&chunks, ShardDirs::Shard));
+    let mut names: Vec<PathBuf> = contents(&chunks).into_keys().collect();
+    names.sort();
+    let mut expected: Vec<PathBuf> = (0..10)
+        .map(|index| Path::new(&format!("{:02}", index / 4)).join(format!("chunk{:03}", index)))
+        .collect();
+    expected.push(PathBuf::from(MANIFEST_NAME));
+    expected.sort();
+    assert_eq!(names, expected);
+
+    assert_eq!(rebuild(&chunks, "joined", 1), pattern(1000));
+    assert_eq!(rebuild(&chunks, "joined-parallel", 4), pattern(1000));
+    let report = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert_eq!(report.health.chunks, 10);
+    assert!(report.hashed);
+    assert!(report.mismatched.is_empty(), "{:?}", report.mismatched);
+
+    fs::write(chunks.join("01/chunk005"), [0; 100]).unwrap();
+    let report = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert_eq!(report.mismatched, ["01/chunk005"]);
+
+    let refused = temp.path().join("refused");
+    let options = sharded(&refused, ShardDirs::Refuse)
+        .min_chunk_size(0)
+        .build()
+        .unwrap();
+    let result = split_file(&options, &mut |_| {}, &CancelToken::new());
+    assert!(
+        matches!(
+            &result,
+            Err(SplitterError::TooManyFiles {
+                count: 10,
+                limit: 4,
+                ..
+            })
+        ),
+        "{:?}",
+        result
+    );
+    assert!(!refused.join("chunk000").exists());
+}
+
+// A link to the first chunk, made as soon as it is written, keeps what the file held
+// once the split removes it again: zeros with `shred`, the original's bytes without.
+#[test]
+fn a_cancelled_split_with_shred_zeroes_its_chunks_before_removing_them() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(1000)).unwrap();
+    for shred in [true, false] {
+        let chunks = temp.path().join(format!("chunks-{}", shred));
+        let kept = temp.path().join(format!("kept-{}", shred));
+        let options = SplitOptions::builder(&input, &chunks)
+            .chunk_size(100)
+            .min_chunk_size(0)
+            .shred(shred)
+            .build()
+            .unwrap();
+        let cancel = CancelToken::new();
+        let mut progress = |event| {
+            if let ProgressEvent::ChunkFinished { index: 0, .. } = event {
+                fs::hard_link(chunks.join("chunk000"), &kept).unwrap();
+                cancel.cancel();
+            }
+        };
+        let result = split_file(&options, &mut progress, &cancel);
+        assert!(
+            matches!(result, Err(SplitterError::Cancelled)),
+            "{:?}",
+            result
+        );
+        assert!(!chunks.join("chunk000").exists());
+        let expected = match shred {
+            true => vec![0; 100],
+            false => pattern(100),
+        };
+        assert_eq!(fs::read(&kept).unwrap(), expected);
+    }
+}
+
+// The shell script pack --self-extracting writes, run with sh as whoever receives it
+// would, in a directory of its own.
+#[cfg(unix)]
+#[test]
+fn a_self_extracting_script_writes_the_file_back() {
+    let temp = tempfile::tempdir().unwrap();
+    // A quote in the name, which the script has to carry through sh intact
+    let input = temp.path().join("it's a file.bin");
+    let data = pattern(100_001);
+    fs::write(&input, &data).unwrap();
+    let chunks = temp.path().join("chunks");
+    split(&input, &chunks, 4096);
+    let script = temp.path().join("restore.sh");
+    self_extracting(
+        &chunks,
+        &script,
+        Script::Shell,
+        1 << 20,
+        false,
+        &mut |_| {},
+        &CancelToken::new(),
+    )
+    .unwrap();
+    let run = |directory: &Path, script: &Path| {
+        fs::create_dir_all(directory).unwrap();
+        Command::new("sh")
+            .arg(script)
+            .current_dir(directory)
+            .output()
+            .unwrap()
+    };
+
+    let out = temp.path().join("out");
+    let ran = run(&out, &script);
+    assert!(ran.status.success(), "{:?}", ran);
+    assert!(String::from_utf8_lossy(&ran.stdout).contains("the SHA-256 matches"));
+    assert_eq!(fs::read(out.join("it's a file.bin")).unwrap(), data);
+
+    // Never over a file that is there
+    fs::write(out.join("it's a file.bin"), "mine").unwrap();
+    let ran = run(&out, &script);
+    assert!(!ran.status.success());
+    assert_eq!(fs::read(out.join("it's a file.bin")).unwrap(), b"mine");
+
+    // One character of the base64 changed is caught by the SHA-256
+    let text = fs::read_to_string(&script).unwrap();
+    let mut lines: Vec<&str> = text.lines().collect();
+    let line = lines.len() - 100;
+    let changed = match &lines[line][..1] {
+        "A" => format!("B{}", &lines[line][1..]),
+        _ => format!("A{}", &lines[line][1..]),
+    };
+    lines[line] = &changed;
+    let damaged = temp.path().join("damaged.sh");
+    fs::write(&damaged, lines.join("\n") + "\n").unwrap();
+    let ran = run(&temp.path().join("damaged"), &damaged);
+    assert!(!ran.status.success());
+    assert!(
+        String::from_utf8_lossy(&ran.stderr).contains("damaged"),
+        "{:?}",
+        ran
+    );
+}
+
+// Pieces of a split, which a split refuses unless allowed, and files that only look
+// like them, which it takes as any other.
+#[test]
+fn pieces_of_a_split_are_told_from_look_alikes() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(1000)).unwrap();
+    let chunks = temp.path().join("chunks");
+    split(&input, &chunks, 300);
+    let sharded = temp.path().join("sharded");
+    split_with(
+        SplitOptions::builder(&input, &sharded)
+            .chunk_size(100)
+            .max_dir_files(4)
+            .shard_dirs(ShardDirs::Shard),
+    );
+
+    let genuine = [
+        (chunks.join("chunk002"), &chunks),
+        (chunks.join(MANIFEST_NAME), &chunks),
+        (sharded.join("01/chunk005"), &sharded),
+    ];
+    for (piece, set) in genuine {
+        assert_eq!(
+            containing_set(&piece).as_ref(),
+            Some(set),
+            "{}",
+            piece.display()
+        );
+        let again = temp.path().join("again");
+        match split_result(SplitOptions::builder(&piece, &again).chunk_size(100)) {
+            Err(SplitterError::NestedSplit { path, set: found }) => {
+                assert_eq!((&path, &found), (&piece, set));
+            }
+            other => panic!("{}: {:?}", piece.display(), other),
+        }
+        assert!(!again.exists());
+        split_with(
+            SplitOptions::builder(&piece, &again)
+                .chunk_size(100)
+                .allow_nested(true),
+        );
+        fs::remove_dir_all(&again).unwrap();
+    }
+
+    // A chunk name with no set about it, a file the set doesn't list beside it, and a
+    // shard-like directory with no set above it
+    let loose = temp.path().join("loose");
+    let shard = temp.path().join("plain/01");
+    fs::create_dir_all(&loose).unwrap();
+    fs::create_dir_all(&shard).unwrap();
+    let look_alikes = [
+        loose.join("chunk005"),
+        chunks.join("notes.txt"),
+        shard.join("chunk005"),
+    ];
+    for file in look_alikes {
+        fs::write(&file, pattern(500)).unwrap();
+        assert_eq!(containing_set(&file), None, "{}", file.display());
+        let again = temp.path().join("again");
+        split_result(SplitOptions::builder(&file, &again).chunk_size(100)).unwrap();
+        fs::remove_dir_all(&again).unwrap();
+        fs::remove_file(&file).unwrap();
+    }
+}
+
+// Rename the files under `directory` whose names start with `from` to start with `to`
+// instead, in whichever shard they are.
+fn rename_chunks(directory: &Path, from: &str, to: &str) {
+    let names = contents(directory).into_keys().filter(|name| {
+        let file = name.file_name().unwrap().to_str().unwrap();
+        file.starts_with(from)
+    });
+    for name in names {
+        let file = name.file_name().unwrap().to_str().unwrap();
+        let renamed = name.with_file_name(file.replacen(from, to, 1));
+        fs::rename(directory.join(&name), directory.join(renamed)).unwrap();
+    }
+}
+
+#[test]
+fn chunks_renamed_in_bulk_are_found_and_recorded() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let data = pattern(1000);
+    fs::write(&input, &data).unwrap();
+    let plain = temp.path().join("plain");
+    split_with(
+        SplitOptions::builder(&input, &plain)
+            .chunk_size(250)
+            .hash(Some(HashAlgorithm::Sha256)),
+    );
+    let sharded = temp.path().join("sharded");
+    split_with(
+        SplitOptions::builder(&input, &sharded)
+            .chunk_size(100)
+            .compression(Compression::Gzip)
+            .max_dir_files(4)
+            .shard_dirs(ShardDirs::Shard),
+    );
+    // Nothing renamed, nothing to find
+    assert!(
+        find_remap(&plain, false, &CancelToken::new())
+            .unwrap()
+            .is_none()
+    );
+
+    for (directory, chunks, hashed) in [(&plain, 4, true), (&sharded, 10, false)] {
+        rename_chunks(directory, "chunk", "part");
+        assert!(rebuild_result(directory).is_err());
+        let remap = find_remap(directory, false, &CancelToken::new())
+            .unwrap()
+            .unwrap();
+        assert_eq!((remap.from.as_str(), remap.to.as_str()), ("chunk", "part"));
+        assert_eq!(remap.renamed.len(), chunks);
+        assert_eq!(remap.hashed, hashed);
+        for (old, new) in &remap.renamed {
+            assert_eq!(new, &old.replacen("chunk", "part", 1));
+        }
+        apply_remap(&remap, false).unwrap();
+        assert!(
+            find_remap(directory, false, &CancelToken::new())
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(rebuild(directory, "joined", 1), data);
+        verify(directory, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+    }
+}
+
+// What is done to a renamed set to leave its chunks in doubt
+type Spoil = fn(&Path);
+
+#[test]
+fn renamed_chunks_that_can_not_be_told_apart_are_not_guessed() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    fs::write(&input, pattern(1000)).unwrap();
+    let chunks = temp.path().join("chunks");
+    split_with(
+        SplitOptions::builder(&input, &chunks)
+            .chunk_size(250)
+            .hash(Some(HashAlgorithm::Sha256)),
+    );
+    let unhashed = temp.path().join("unhashed");
+    split(&input, &unhashed, 250);
+
+    // Each a copy of one of the sets, renamed and then spoiled
+    let cases: [(&str, &Path, Spoil); 6] = [
+        ("could each be", &chunks, |set| {
+            for index in 0..4 {
+                let chunk = set.join(format!("part{:03}", index));
+                fs::copy(&chunk, set.join(format!("piece{:03}", index))).unwrap();
+            }
+        }),
+        ("the other 2 chunks are there as chunk", &chunks, |set| {
+            for index in 0..2 {
+                let chunk = format!("{:03}", index);
+                fs::rename(
+                    set.join(format!("part{}", chunk)),
+                    set.join(format!("chunk{}", chunk)),
+                )
+                .unwrap();
+            }
+        }),
+        (
+            "only 3 of the 4 chunks missing are there as part",
+            &chunks,
+            |set| {
+                fs::remove_file(set.join("part002")).unwrap();
+            },
+        ),
+        ("there are 5 files named part", &chunks, |set| {
+            fs::write(set.join("part004"), "extra").unwrap();
+        }),
+        ("is 249 bytes, where info.json has 250", &unhashed, |set| {
+            let chunk = fs::OpenOptions::new()
+                .write(true)
+                .open(set.join("part001"))
+                .unwrap();
+            chunk.set_len(249).unwrap();
+        }),
+        ("part001 doesn't have the hash", &chunks, |set| {
+            let mut bytes = fs::read(set.join("part001")).unwrap();
+            bytes[100] ^= 1;
+            fs::write(set.join("part001"), bytes).unwrap();
+        }),
+    ];
+    for (number, (reason, source, spoil)) in cases.into_iter().enumerate() {
+        let set = temp.path().join(format!("case{}", number));
+        fs::create_dir(&set).unwrap();
+        for (name, bytes) in contents(source) {
+            fs::write(set.join(name), bytes).unwrap();
+        }
+        rename_chunks(&set, "chunk", "part");
+        spoil(&set);
+        let manifest = fs::read(set.join(MANIFEST_NAME)).unwrap();
+        match find_remap(&set, false, &CancelToken::new()) {
+            Err(SplitterError::RemapDoubt {
+                path,
+                reason: found,
+            }) => {
+                assert_eq!(path, set);
+                assert!(found.contains(reason), "{}: {}", reason, found);
+            }
+            other => panic!("{}: {:?}", reason, other),
+        }
+        assert_eq!(fs::read(set.join(MANIFEST_NAME)).unwrap(), manifest);
+    }
+}
+
+// A stray file numbered in the billions beside a set without an info.json is taken for
+// one, promptly, rather than counted up to as billions of missing chunks.
+#[test]
+fn a_stray_numbered_far_past_the_chunks_is_refused_promptly() {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    let temp = tempfile::tempdir().unwrap();
+    let chunks = temp.path().join("chunks");
+    fs::create_dir(&chunks).unwrap();
+    fs::write(chunks.join("chunk000"), "first").unwrap();
+    fs::write(chunks.join("chunk001"), "second").unwrap();
+    fs::write(chunks.join("chunk99999999999"), "stray").unwrap();
+
+    let (sent, received) = mpsc::channel();
+    let directory = chunks.clone();
+    thread::spawn(move || {
+        let cancel = CancelToken::new();
+        let results = [
+            rebuild_result(&directory).err(),
+            verify(&directory, &[], false, &mut |_| {}, &cancel).err(),
+            diagnose(&directory, None, false, &mut |_| {}, &cancel).err(),
+            ChunkSet::open(&directory, false).err(),
+        ];
+        sent.send(results).unwrap();
+    });
+    let results = received.recv_timeout(Duration::from_secs(30)).unwrap();
+    for result in results {
+        match result {
+            Some(SplitterError::StrayChunk { index, chunks }) => {
+                assert_eq!((index, chunks), (99_999_999_999, 3));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+    assert!(!chunks.join("joined.bin").exists());
+
+    // Without it, the set joins again
+    fs::remove_file(chunks.join("chunk99999999999")).unwrap();
+    assert_eq!(rebuild(&chunks, "joined", 1), b"firstsecond");
+}
+
+// Chunks with the same bytes are written once, the rest never touching the disk, and
+// the set still joins and verifies; the file they share is every one of them lost.
+#[test]
+fn deduplicated_chunks_are_written_once_and_join_back() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let same = vec![0; 4096];
+    let data = [&same[..], &pattern(4096), &same, &same, &pattern(1000)].concat();
+    fs::write(&input, &data).unwrap();
+    let chunks = temp.path().join("chunks");
+    split_with(
+        SplitOptions::builder(&input, &chunks)
+            .chunk_size(4096)
+            .hash(Some(HashAlgorithm::Sha256))
+            .dedup(true),
+    );
+    let files: Vec<PathBuf> = contents(&chunks).into_keys().collect();
+    let stored = ["chunk000", "chunk001", "chunk004", MANIFEST_NAME];
+    assert_eq!(files, stored.map(PathBuf::from));
+    let manifest: Manifest =
+        serde_json::from_slice(&fs::read(chunks.join(MANIFEST_NAME)).unwrap()).unwrap();
+    let same_as: Vec<_> = manifest
+        .chunks
+        .iter()
+        .map(|entry| entry.same_as.as_deref())
+        .collect();
+    assert_eq!(
+        same_as,
+        [None, None, Some("chunk000"), Some("chunk000"), None]
+    );
+    assert_eq!(manifest.chunks[0].shared, Some(2));
+    assert_eq!(rebuild(&chunks, "joined.bin", 2), data);
+    fs::remove_file(chunks.join("joined.bin")).unwrap();
+    let report = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert!(report.mismatched.is_empty(), "{:?}", report.mismatched);
+
+    fs::remove_file(chunks.join("chunk000")).unwrap();
+    let report = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert_eq!(report.mismatched, ["chunk000"]);
+    assert_eq!(report.health.missing, [0, 2, 3]);
+    match rebuild_result(&chunks) {
+        Err(SplitterError::MissingChunks { indices }) => assert_eq!(indices, [0, 2, 3]),
+        other => panic!("{:?}", other),
+    }
+    // The last chunk is missed as much as those before it
+    fs::remove_file(chunks.join("chunk004")).unwrap();
+    let report = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert_eq!(report.mismatched, ["chunk000", "chunk004"]);
+    assert_eq!(report.health.missing, [0, 2, 3, 4]);
+
+    // Parity would count the shared file once for every chunk stored in it
+    for parity in [
+        |builder: SplitOptionsBuilder| builder.parity(Some(Parity::Xor)),
+        |builder: SplitOptionsBuilder| builder.par2(Some(10)),
+    ] {
+        let result = split_result(parity(
+            SplitOptions::builder(&input, temp.path().join("parity"))
+                .chunk_size(4096)
+                .hash(Some(HashAlgorithm::Sha256))
+                .dedup(true),
+        ));
+        assert!(
+            matches!(
+                result,
+                Err(SplitterError::InvalidOption { field: "dedup", .. })
+            ),
+            "{:?}",
+            result
+        );
+    }
+}
+
+// A set with nothing but its .par2 files to repair it from gets a lost chunk and a
+// damaged one back from them.
+#[test]
+fn par2_files_alone_rebuild_lost_and_damaged_chunks() {
+    let temp = tempfile::tempdir().unwrap();
+    let input = temp.path().join("input.bin");
+    let data = pattern(9 * 256 + 100);
+    fs::write(&input, &data).unwrap();
+    let chunks = temp.path().join("chunks");
+    split_with(
+        SplitOptions::builder(&input, &chunks)
+            .chunk_size(256)
+            .hash(Some(HashAlgorithm::Sha256))
+            .par2(Some(30)),
+    );
+    let before = contents(&chunks);
+    let extra: Vec<_> = before
+        .keys()
+        .map(|name| name.to_string_lossy())
+        .filter(|name| !name.starts_with("chunk") && name != MANIFEST_NAME)
+        .collect();
+    assert!(
+        extra.iter().all(|name| name.ends_with(".par2")),
+        "{:?}",
+        extra
+    );
+
+    fs::remove_file(chunks.join("chunk003")).unwrap();
+    let path = chunks.join("chunk007");
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[10] ^= 1;
+    fs::write(&path, bytes).unwrap();
+    let report = repair(&chunks, false, false, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert!(report.rebuilt.is_empty() && report.parity.is_empty());
+    let mut rebuilt = report.par2.clone();
+    rebuilt.sort();
+    assert_eq!(rebuilt, ["chunk003", "chunk007"]);
+    assert_eq!(contents(&chunks), before);
+    assert_eq!(rebuild(&chunks, "joined.bin", 1), data);
+}
+
+// Truncating a file while it's read through a map makes the pages gone fault with
+// SIGBUS; that has to end the split with an error, not the process.
+#[cfg(all(unix, feature = "mmap"))]
+#[test]
+fn an_input_truncated_while_mapped_is_refused_as_changed() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    // Hashed, the pages gone are read here and fault; plain, the kernel copies from
+    // them and the write fails
+    for (name, hash) in [("plain", None), ("hashed", Some(HashAlgorithm::Sha256))] {
+        let chunks = dir.path().join(name);
+        fs::write(&input, pattern(4 << 20)).unwrap();
+        let options = SplitOptions::builder(&input, &chunks)
+            .chunk_size(1 << 20)
+            .hash(hash)
+            .mmap(true)
+            .min_chunk_size(0)
+            .build()
+            .unwrap();
+        let mut progress = |event: ProgressEvent| {
+            if let ProgressEvent::ChunkFinished { index: 0, .. } = event {
+                fs::File::options()
+                    .write(true)
+                    .open(&input)
+                    .unwrap()
+                    .set_len(4096)
+                    .unwrap();
+            }
+        };
+        let result = split_file(&options, &mut progress, &CancelToken::new());
+        assert!(
+            matches!(&result, Err(SplitterError::ChangedSize { path }) if *path == input),
+            "{}: {:?}",
+            name,
+            result.map(|_| ())
+        );
+        assert!(!chunks.join(MANIFEST_NAME).exists());
+    }
+
+    // Mapping works on after
+    let whole = dir.path().join("whole");
+    let data = pattern(3 << 20);
+    fs::write(&input, &data).unwrap();
+    split_with(
+        SplitOptions::builder(&input, &whole)
+            .chunk_size(1 << 20)
+            .hash(Some(HashAlgorithm::Sha256))
+            .mmap(true),
+    );
+    assert_eq!(rebuild(&whole, "joined.bin", 1), data);
+}
+
+// Started from a directory that has since been removed, the menus and the terminal
+// interface have nowhere to browse from, which is an error to report, not a panic.
+#[cfg(unix)]
+#[test]
+fn a_removed_current_directory_is_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let gone = dir.path().join("gone");
+    fs::create_dir(&gone).unwrap();
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg("cd \"$1\" && rmdir \"$1\" && exec \"$2\" --tui")
+        .arg("sh")
+        .arg(&gone)
+        .arg(env!("CARGO_BIN_EXE_reconstruct_large_file"))
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    assert!(
+        stderr.contains("Cannot read the current directory"),
+        "{}",
+        stderr
+    );
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
+
+// One chunk reads as one chunk, in the split's summary and in what verify concludes.
+#[test]
+fn a_single_chunk_is_counted_in_the_singular() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    fs::write(&input, pattern(3000)).unwrap();
+    let one = dir.path().join("one");
+    let output = cli([
+        OsStr::new("split"),
+        input.as_os_str(),
+        OsStr::new("-d"),
+        one.as_os_str(),
+    ]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(" in 1 chunk took "), "{}", stdout);
+
+    let three = dir.path().join("three");
+    split(&input, &three, 1000);
+    fs::remove_file(three.join("chunk001")).unwrap();
+    let output = cli([OsStr::new("verify"), three.as_os_str()]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(4));
+    assert!(
+        stdout.contains("NOT recoverable: 1 chunk was lost with no redundancy to rebuild it from"),
+        "{}",
+        stdout
+    );
+}
+
+// Chunks gone from the end of a set are missing as much as those in the middle, in
+// what verify prints and in its JSON report alike.
+#[test]
+fn chunks_missing_from_the_end_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let input = dir.path().join("input.bin");
+    let chunks = dir.path().join("chunks");
+    fs::write(&input, pattern(5000)).unwrap();
+    split(&input, &chunks, 1000);
+    for name in ["chunk001", "chunk003", "chunk004"] {
+        fs::remove_file(chunks.join(name)).unwrap();
+    }
+    let report = verify(&chunks, &[], false, &mut |_| {}, &CancelToken::new()).unwrap();
+    assert_eq!(report.health.missing, [1, 3, 4]);
+
+    let output = cli([
+        OsStr::new("verify"),
+        chunks.as_os_str(),
+        OsStr::new("--progress"),
+        OsStr::new("json"),
+    ]);
+    assert_eq!(output.status.code(), Some(4));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Missing chunks: [1, 3, 4]"), "{}", stdout);
+    // The events go to standard error, the report to standard output
+    let completed = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .find(|event| event["event"] == "completed")
+        .unwrap();
+    assert_eq!(
+        completed["report"]["health"]["missing"],
+        serde_json::json!([1, 3, 4])
+    );
+}